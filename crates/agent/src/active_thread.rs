@@ -30,8 +30,10 @@ use gpui::{
     pulsating_between,
 };
 use language::{Buffer, Language, LanguageRegistry};
+use language_model::message_handler::PersistenceStatus;
 use language_model::{
-    LanguageModelRequestMessage, LanguageModelToolUseId, MessageContent, Role, StopReason,
+    LanguageModelRequestMessage, LanguageModelToolUseId, MessageContent, RequestSource, Role,
+    StopReason,
 };
 use markdown::parser::{CodeBlockKind, CodeBlockMetadata};
 use markdown::{
@@ -1459,6 +1461,16 @@ impl ActiveThread {
                             &configured_model.model,
                             cx,
                         ),
+                        toolchain: None,
+                        project: None,
+                        prompt_template: None,
+                        origin: None,
+                        buffer: None,
+                        git_branch: None,
+                        schedule_id: None,
+                        fan_out_group: None,
+                        message_id: None,
+                        source: Some(RequestSource::AgentPanel),
                     };
 
                     Some(configured_model.model.count_tokens(request, cx))
@@ -1833,6 +1845,9 @@ impl ActiveThread {
 
         let tool_uses = thread.tool_uses_for_message(message_id, cx);
         let has_tool_uses = !tool_uses.is_empty();
+        let persistence_indicator = thread
+            .message_persistence(message_id)
+            .map(|status| self.render_persistence_indicator(ix, message_id, status, cx));
         let is_generating = thread.is_generating();
         let is_generating_stale = thread.is_generation_stale().unwrap_or(false);
 
@@ -2037,12 +2052,16 @@ impl ActiveThread {
                 .child(
                     v_flex()
                         .id(("user-message", ix))
+                        .relative()
                         .bg(editor_bg_color)
                         .rounded_lg()
                         .shadow_md()
                         .border_1()
                         .border_color(colors.border)
                         .hover(|hover| hover.border_color(colors.text_accent.opacity(0.5)))
+                        .children(persistence_indicator.map(|indicator| {
+                            div().absolute().top_1().right_1().child(indicator)
+                        }))
                         .child(
                             v_flex()
                                 .p_2p5()
@@ -2144,8 +2163,13 @@ impl ActiveThread {
                 ),
             Role::Assistant => v_flex()
                 .id(("message-container", ix))
+                .relative()
                 .px(RESPONSE_PADDING_X)
                 .gap_2()
+                .children(
+                    persistence_indicator
+                        .map(|indicator| div().absolute().top_0().right_1().child(indicator)),
+                )
                 .children(message_content)
                 .when(has_tool_uses, |parent| {
                     parent.children(tool_uses.into_iter().map(|tool_use| {
@@ -3202,6 +3226,57 @@ impl ActiveThread {
         }).into_any_element()
     }
 
+    /// Whether the message is in the message store, with a button to store it again when that
+    /// failed.
+    fn render_persistence_indicator(
+        &self,
+        ix: usize,
+        message_id: MessageId,
+        status: PersistenceStatus,
+        cx: &Context<Self>,
+    ) -> AnyElement {
+        match status {
+            PersistenceStatus::Persisted => div()
+                .id(("message-persisted", ix))
+                .child(
+                    Icon::new(IconName::Check)
+                        .size(IconSize::XSmall)
+                        .color(Color::Ignored),
+                )
+                .tooltip(Tooltip::text("Stored"))
+                .into_any_element(),
+            PersistenceStatus::Pending => div()
+                .id(("message-persisting", ix))
+                .child(
+                    Icon::new(IconName::ArrowCircle)
+                        .size(IconSize::XSmall)
+                        .color(Color::Muted)
+                        .with_animation(
+                            ("message-persisting-animation", ix),
+                            Animation::new(Duration::from_secs(2)).repeat(),
+                            |icon, delta| icon.transform(Transformation::rotate(percentage(delta))),
+                        ),
+                )
+                .tooltip(Tooltip::text("Storing…"))
+                .into_any_element(),
+            PersistenceStatus::Failed(error) => {
+                IconButton::new(("retry-message-persistence", ix), IconName::Warning)
+                    .icon_size(IconSize::XSmall)
+                    .icon_color(Color::Error)
+                    .tooltip(Tooltip::text(format!(
+                        "Failed to store: {error}. Click to retry."
+                    )))
+                    .on_click(cx.listener(move |this, _, _window, cx| {
+                        cx.stop_propagation();
+                        this.thread.update(cx, |thread, cx| {
+                            thread.retry_persistence(message_id, cx);
+                        });
+                    }))
+                    .into_any_element()
+            }
+        }
+    }
+
     fn render_rules_item(&self, cx: &Context<Self>) -> AnyElement {
         let project_context = self.thread.read(cx).project_context();
         let project_context = project_context.borrow();
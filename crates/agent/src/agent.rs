@@ -4,22 +4,29 @@ mod agent_diff;
 mod agent_model_selector;
 mod agent_panel;
 mod buffer_codegen;
+mod commit_threads;
 mod context;
 mod context_picker;
 mod context_server_configuration;
 mod context_server_tool;
 mod context_store;
 mod context_strip;
+mod conversation_heat;
 mod debug;
+mod file_drift;
 mod history_store;
 mod inline_assistant;
 mod inline_prompt_editor;
+mod llm_store;
 mod message_editor;
+mod model_comparison;
 mod profile_selector;
+mod run_inbox;
 mod slash_command_settings;
 mod terminal_codegen;
 mod terminal_inline_assistant;
 mod thread;
+mod thread_bundle;
 mod thread_history;
 mod thread_store;
 mod tool_compatibility;
@@ -47,8 +54,10 @@ use crate::agent_configuration::{AddContextServerModal, ManageProfilesModal};
 pub use crate::agent_panel::{AgentPanel, ConcreteAssistantPanelDelegate};
 pub use crate::context::{ContextLoadResult, LoadedContext};
 pub use crate::inline_assistant::InlineAssistant;
+pub use crate::llm_store::StoreHealthIndicator;
 use crate::slash_command_settings::SlashCommandSettings;
 pub use crate::thread::{Message, MessageSegment, Thread, ThreadEvent};
+pub use crate::thread_bundle::{THREAD_BUNDLE_EXTENSION, ThreadBundle};
 pub use crate::thread_store::{SerializedThread, TextThreadStore, ThreadStore};
 pub use agent_diff::{AgentDiffPane, AgentDiffToolbar};
 pub use context_store::ContextStore;
@@ -91,6 +100,9 @@ actions!(
         ContinueThread,
         ContinueWithBurnMode,
         ToggleBurnMode,
+        ExportThreadBundle,
+        ExportEncryptedThreadBundle,
+        ImportThreadBundle,
     ]
 );
 
@@ -139,6 +151,11 @@ pub fn init(
     assistant_slash_command::init(cx);
     thread_store::init(cx);
     agent_panel::init(cx);
+    run_inbox::init(cx);
+    llm_store::init(cx);
+    model_comparison::init(cx);
+    conversation_heat::init(cx);
+    commit_threads::init(cx);
     context_server_configuration::init(language_registry, cx);
 
     register_slash_commands(cx);
@@ -201,6 +218,7 @@ fn update_active_language_model_from_settings(cx: &mut App) {
         .thread_summary_model
         .as_ref()
         .map(to_selected_model);
+    let judge = settings.judge_model.as_ref().map(to_selected_model);
     let inline_alternatives = settings
         .inline_alternatives
         .iter()
@@ -212,6 +230,7 @@ fn update_active_language_model_from_settings(cx: &mut App) {
         registry.select_inline_assistant_model(inline_assistant.as_ref(), cx);
         registry.select_commit_message_model(commit_message.as_ref(), cx);
         registry.select_thread_summary_model(thread_summary.as_ref(), cx);
+        registry.select_judge_model(judge.as_ref(), cx);
         registry.select_inline_alternative_models(inline_alternatives, cx);
     });
 }
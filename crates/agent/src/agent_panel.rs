@@ -24,10 +24,11 @@ use fs::Fs;
 use gpui::{
     Action, Animation, AnimationExt as _, AnyElement, App, AsyncWindowContext, ClipboardItem,
     Corner, DismissEvent, Entity, EventEmitter, ExternalPaths, FocusHandle, Focusable, FontWeight,
-    KeyContext, Pixels, Subscription, Task, UpdateGlobal, WeakEntity, linear_color_stop,
-    linear_gradient, prelude::*, pulsating_between,
+    KeyContext, PathPromptOptions, Pixels, Subscription, Task, UpdateGlobal, WeakEntity,
+    linear_color_stop, linear_gradient, prelude::*, pulsating_between,
 };
 use language::LanguageRegistry;
+use language_model::message_handler::store_read_only;
 use language_model::{
     LanguageModelProviderTosView, LanguageModelRegistry, RequestUsage, ZED_CLOUD_PROVIDER_ID,
 };
@@ -46,6 +47,7 @@ use ui::{
 };
 use util::{ResultExt as _, maybe};
 use workspace::dock::{DockPosition, Panel, PanelEvent};
+use workspace::notifications::DetachAndPromptErr as _;
 use workspace::{
     CollaboratorId, DraggedSelection, DraggedTab, ToggleZoom, ToolbarItemView, Workspace,
 };
@@ -60,15 +62,17 @@ use crate::agent_diff::AgentDiff;
 use crate::history_store::{HistoryStore, RecentEntry};
 use crate::message_editor::{MessageEditor, MessageEditorEvent};
 use crate::thread::{Thread, ThreadError, ThreadId, ThreadSummary, TokenUsageRatio};
+use crate::thread_bundle::{THREAD_BUNDLE_EXTENSION, ThreadBundle, ThreadBundleAttachment};
 use crate::thread_history::{HistoryEntryElement, ThreadHistory};
 use crate::thread_store::ThreadStore;
-use crate::ui::AgentOnboardingModal;
+use crate::ui::{AgentOnboardingModal, BundlePassphraseModal};
 use crate::{
     AddContextServer, AgentDiffPane, ContextStore, ContinueThread, ContinueWithBurnMode,
-    DeleteRecentlyOpenThread, ExpandMessageEditor, Follow, InlineAssistant, NewTextThread,
-    NewThread, OpenActiveThreadAsMarkdown, OpenAgentDiff, OpenHistory, ResetTrialEndUpsell,
-    ResetTrialUpsell, TextThreadStore, ThreadEvent, ToggleBurnMode, ToggleContextPicker,
-    ToggleNavigationMenu, ToggleOptionsMenu,
+    DeleteRecentlyOpenThread, ExpandMessageEditor, ExportEncryptedThreadBundle, ExportThreadBundle,
+    Follow, ImportThreadBundle, InlineAssistant, NewTextThread, NewThread,
+    OpenActiveThreadAsMarkdown, OpenAgentDiff, OpenHistory, ResetTrialEndUpsell, ResetTrialUpsell,
+    TextThreadStore, ThreadEvent, ToggleBurnMode, ToggleContextPicker, ToggleNavigationMenu,
+    ToggleOptionsMenu,
 };
 
 const AGENT_PANEL_KEY: &str = "agent_panel";
@@ -121,6 +125,24 @@ pub fn init(cx: &mut App) {
                         AgentDiffPane::deploy_in_workspace(thread, workspace, window, cx);
                     }
                 })
+                .register_action(|workspace, _: &ExportThreadBundle, window, cx| {
+                    if let Some(panel) = workspace.panel::<AgentPanel>(cx) {
+                        panel.update(cx, |panel, cx| {
+                            panel.export_thread_bundle(false, window, cx)
+                        });
+                    }
+                })
+                .register_action(|workspace, _: &ExportEncryptedThreadBundle, window, cx| {
+                    if let Some(panel) = workspace.panel::<AgentPanel>(cx) {
+                        panel.update(cx, |panel, cx| panel.export_thread_bundle(true, window, cx));
+                    }
+                })
+                .register_action(|workspace, _: &ImportThreadBundle, window, cx| {
+                    if let Some(panel) = workspace.panel::<AgentPanel>(cx) {
+                        workspace.focus_panel::<AgentPanel>(window, cx);
+                        panel.update(cx, |panel, cx| panel.import_thread_bundle(window, cx));
+                    }
+                })
                 .register_action(|workspace, _: &Follow, window, cx| {
                     workspace.follow(CollaboratorId::Agent, window, cx);
                 })
@@ -726,7 +748,9 @@ impl AgentPanel {
             inline_assist_context_store,
             previous_view: None,
             history_store: history_store.clone(),
-            history: cx.new(|cx| ThreadHistory::new(weak_self, history_store, window, cx)),
+            history: cx.new(|cx| {
+                ThreadHistory::new(weak_self, history_store, project.clone(), window, cx)
+            }),
             hovered_recent_history_item: None,
             assistant_dropdown_menu_handle: PopoverMenuHandle::default(),
             assistant_navigation_menu_handle: PopoverMenuHandle::default(),
@@ -1251,6 +1275,119 @@ impl AgentPanel {
             .detach_and_log_err(cx);
     }
 
+    /// Writes the active thread to a `.zedthread` bundle, asking for a passphrase to encrypt it
+    /// with first if `encrypt` is set.
+    pub(crate) fn export_thread_bundle(
+        &mut self,
+        encrypt: bool,
+        window: &mut Window,
+        cx: &mut Context<Self>,
+    ) {
+        let Some(workspace) = self.workspace.upgrade() else {
+            return;
+        };
+        let Some(thread) = self.active_thread() else {
+            return;
+        };
+
+        let passphrase = encrypt.then(|| {
+            workspace.update(cx, |workspace, cx| {
+                BundlePassphraseModal::prompt(workspace, "Encrypt Thread Bundle", window, cx)
+            })
+        });
+        let bundle = ThreadBundle::from_thread(&thread, cx);
+        let fs = self.fs.clone();
+        cx.spawn_in(window, async move |_, cx| {
+            let passphrase = match passphrase {
+                Some(passphrase) => match passphrase.await {
+                    Ok(passphrase) => Some(passphrase),
+                    Err(_) => return Ok(()),
+                },
+                None => None,
+            };
+            let bundle = bundle.await?;
+            let Some(path) = cx
+                .update(|_, cx| cx.prompt_for_new_path(paths::home_dir()))?
+                .await??
+            else {
+                return Ok(());
+            };
+            let path = if path.extension().is_none() {
+                path.with_extension(THREAD_BUNDLE_EXTENSION)
+            } else {
+                path
+            };
+            let contents = cx
+                .background_spawn(async move { bundle.encode(passphrase.as_deref()) })
+                .await?;
+            fs.atomic_write(path, contents).await
+        })
+        .detach_and_prompt_err("Failed to export thread", window, cx, |_, _, _| None);
+    }
+
+    /// Reads a `.zedthread` bundle into the thread history and opens it.
+    pub(crate) fn import_thread_bundle(&mut self, window: &mut Window, cx: &mut Context<Self>) {
+        let Some(workspace) = self.workspace.upgrade() else {
+            return;
+        };
+
+        let paths = cx.prompt_for_paths(PathPromptOptions {
+            files: true,
+            directories: false,
+            multiple: false,
+        });
+        let fs = self.fs.clone();
+        cx.spawn_in(window, async move |this, cx| {
+            let Some(path) = paths.await??.and_then(|paths| paths.into_iter().next()) else {
+                return Ok(());
+            };
+            let contents = fs.load(&path).await?;
+            let passphrase = if ThreadBundle::is_encrypted(&contents)? {
+                let passphrase = workspace.update_in(cx, |workspace, window, cx| {
+                    BundlePassphraseModal::prompt(workspace, "Decrypt Thread Bundle", window, cx)
+                })?;
+                match passphrase.await {
+                    Ok(passphrase) => Some(passphrase),
+                    Err(_) => return Ok(()),
+                }
+            } else {
+                None
+            };
+            let bundle = cx
+                .background_spawn(
+                    async move { ThreadBundle::decode(&contents, passphrase.as_deref()) },
+                )
+                .await?;
+
+            let serialized_thread = bundle.serialized_thread()?;
+            let thread_id = this
+                .update(cx, |this, cx| {
+                    this.thread_store
+                        .update(cx, |store, cx| store.import_thread(serialized_thread, cx))
+                })?
+                .await?;
+            let thread = this
+                .update_in(cx, |this, window, cx| {
+                    this.thread_store
+                        .update(cx, |store, cx| store.open_thread(&thread_id, window, cx))
+                })?
+                .await?;
+            this.update_in(cx, |this, window, cx| {
+                thread.update(cx, |thread, _| {
+                    for attachment in bundle.attachments {
+                        match attachment {
+                            ThreadBundleAttachment::Image { message_id, image } => {
+                                thread.attach_images(message_id, [image])
+                            }
+                        }
+                    }
+                });
+                this.open_thread(thread, window, cx);
+            })
+        })
+        .detach_and_prompt_err("Failed to import thread", window, cx, |_, _, _| None);
+    }
+
     fn handle_agent_configuration_event(
         &mut self,
         _entity: &Entity<AgentConfiguration>,
@@ -1775,7 +1912,9 @@ impl AgentPanel {
                                     from_thread_id: Some(thread_id.clone()),
                                 }),
                             )
+                            .action("Export Thread…", Box::new(ExportThreadBundle))
                         })
+                        .action("Import Thread…", Box::new(ImportThreadBundle))
                         .separator();
 
                     menu = menu
@@ -2718,6 +2857,18 @@ impl AgentPanel {
         Some(div().px_2().pb_2().child(banner).into_any_element())
     }
 
+    /// Tells the user that their conversations aren't stored while the store is only browsed.
+    fn render_read_only_store(&self, cx: &mut Context<Self>) -> Option<AnyElement> {
+        if !store_read_only(cx) {
+            return None;
+        }
+        let banner = Banner::new().severity(ui::Severity::Info).child(
+            Label::new("The message store is read-only, so this conversation isn't stored.")
+                .size(LabelSize::Small),
+        );
+        Some(div().px_2().pb_2().child(banner).into_any_element())
+    }
+
     fn render_last_error(&self, cx: &mut Context<Self>) -> Option<AnyElement> {
         let last_error = self.thread.read(cx).last_error()?;
 
@@ -3117,6 +3268,7 @@ impl Render for AgentPanel {
                     .relative()
                     .child(self.render_active_thread_or_empty_state(window, cx))
                     .children(self.render_tool_use_limit_reached(window, cx))
+                    .children(self.render_read_only_store(cx))
                     .child(h_flex().child(self.message_editor.clone()))
                     .children(self.render_last_error(cx))
                     .child(self.render_drag_target(cx)),
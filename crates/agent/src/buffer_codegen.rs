@@ -13,7 +13,8 @@ use gpui::{App, AppContext as _, Context, Entity, EventEmitter, Subscription, Ta
 use language::{Buffer, IndentKind, Point, TransactionId, line_diff};
 use language_model::{
     LanguageModel, LanguageModelRegistry, LanguageModelRequest, LanguageModelRequestMessage,
-    LanguageModelTextStream, Role, report_assistant_event,
+    LanguageModelTextStream, ProjectArea, RequestBuffer, RequestSource, Role,
+    report_assistant_event,
 };
 use multi_buffer::MultiBufferRow;
 use parking_lot::Mutex;
@@ -426,6 +427,13 @@ impl CodegenAlternative {
             anyhow::bail!("invalid transformation range");
         };
 
+        let request_buffer = RequestBuffer {
+            language: language_name.map(|language_name| language_name.to_string()),
+            area: buffer.file().map_or(ProjectArea::Other, |file| {
+                ProjectArea::for_path(file.path())
+            }),
+        };
+
         let prompt = self
             .builder
             .generate_inline_transformation_prompt(user_prompt, language_name, buffer, range)
@@ -473,6 +481,16 @@ impl CodegenAlternative {
                 stop: Vec::new(),
                 temperature,
                 messages: vec![request_message],
+                toolchain: None,
+                project: None,
+                prompt_template: None,
+                origin: None,
+                buffer: Some(request_buffer),
+                git_branch: None,
+                schedule_id: None,
+                fan_out_group: None,
+                message_id: None,
+                source: Some(RequestSource::InlineAssist),
             }
         }))
     }
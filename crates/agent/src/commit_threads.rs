@@ -0,0 +1,167 @@
+//! Records which agent thread each commit was made during, when a commit lands while the agent
+//! panel's thread is generating or was recently worked in, so that the thread can be opened
+//! from the commit's blame.
+
+use anyhow::Context as _;
+use chrono::{DateTime, Utc};
+use collections::HashMap;
+use gpui::{App, Context, SharedString, Task, Window};
+use language_model::message_handler::{
+    MessageHandlerRegistry, get_message_handler, save_commit_thread,
+};
+use project::git_store::{GitStoreEvent, Repository, RepositoryId};
+use workspace::notifications::{DetachAndPromptErr as _, NotificationId};
+use workspace::{Toast, Workspace};
+use zed_actions::agent::OpenCommitThread;
+
+use crate::AgentPanel;
+use crate::thread::ThreadId;
+
+/// How long after its last message a thread's session counts as ongoing.
+const SESSION_IDLE_MINUTES: i64 = 30;
+
+pub fn init(cx: &mut App) {
+    cx.observe_new(|workspace: &mut Workspace, _, cx| {
+        workspace.register_action(|workspace, action: &OpenCommitThread, window, cx| {
+            open_commit_thread(workspace, &action.sha, window, cx);
+        });
+        watch_commits(workspace, cx);
+    })
+    .detach();
+}
+
+/// Where a repository's head was when it was last updated.
+#[derive(Clone, Debug, PartialEq)]
+struct Head {
+    branch: Option<SharedString>,
+    sha: SharedString,
+    commit_timestamp: i64,
+}
+
+impl Head {
+    fn of(repository: &Repository) -> Option<Self> {
+        let commit = repository.head_commit.as_ref()?;
+        Some(Self {
+            branch: repository
+                .branch
+                .as_ref()
+                .map(|branch| branch.ref_name.clone()),
+            sha: commit.sha.clone(),
+            commit_timestamp: commit.commit_timestamp,
+        })
+    }
+
+    /// Whether the head moved from `previous` to a later commit on the same branch, as it does
+    /// when a commit is made, and not when another branch is checked out or the branch is reset.
+    fn follows(&self, previous: &Head) -> bool {
+        self.branch == previous.branch
+            && self.sha != previous.sha
+            && self.commit_timestamp >= previous.commit_timestamp
+    }
+}
+
+/// Whether a thread's session is ongoing: it is generating, or its last message is recent.
+fn in_session(generating: bool, updated_at: DateTime<Utc>, now: DateTime<Utc>) -> bool {
+    generating || now - updated_at <= chrono::Duration::minutes(SESSION_IDLE_MINUTES)
+}
+
+fn watch_commits(workspace: &mut Workspace, cx: &mut Context<Workspace>) {
+    let project = workspace.project().read(cx);
+    if !project.is_local() {
+        return;
+    }
+    let git_store = project.git_store().clone();
+    let mut heads = HashMap::<RepositoryId, Head>::default();
+    cx.subscribe(&git_store, move |workspace, git_store, event, cx| {
+        let GitStoreEvent::RepositoryUpdated(id, _, _) = event else {
+            return;
+        };
+        let Some(repository) = git_store.read(cx).repositories().get(id) else {
+            return;
+        };
+        let Some(head) = Head::of(repository.read(cx)) else {
+            return;
+        };
+        if !heads
+            .insert(*id, head.clone())
+            .is_some_and(|previous| head.follows(&previous))
+        {
+            return;
+        }
+        let Some(thread) = workspace
+            .panel::<AgentPanel>(cx)
+            .and_then(|panel| panel.read(cx).active_thread())
+        else {
+            return;
+        };
+        let thread = thread.read(cx);
+        let ongoing = in_session(thread.is_generating(), thread.updated_at(), Utc::now());
+        if thread.is_empty() || !ongoing {
+            return;
+        }
+        let thread_id = thread.id().to_string();
+        save_commit_thread(&head.sha, &thread_id, cx);
+    })
+    .detach();
+}
+
+/// Opens the thread the commit was made during in the agent panel.
+fn open_commit_thread(
+    workspace: &mut Workspace,
+    sha: &str,
+    window: &mut Window,
+    cx: &mut Context<Workspace>,
+) {
+    let handler = cx
+        .has_global::<MessageHandlerRegistry>()
+        .then(|| get_message_handler(cx))
+        .flatten();
+    let sha = sha.to_string();
+    cx.spawn_in(window, async move |workspace, cx| {
+        let handler = handler.context("messages aren't being stored")?;
+        let thread_id = handler.commit_thread(&sha).await?;
+        workspace
+            .update_in(cx, |workspace, window, cx| {
+                let Some(thread_id) = thread_id else {
+                    let message = format!(
+                        "No agent thread was recorded for commit {}",
+                        sha.get(..7).unwrap_or(&sha)
+                    );
+                    workspace.show_toast(
+                        Toast::new(NotificationId::unique::<OpenCommitThread>(), message)
+                            .autohide(),
+                        cx,
+                    );
+                    return Task::ready(Ok(()));
+                };
+                let Some(panel) = workspace.focus_panel::<AgentPanel>(window, cx) else {
+                    return Task::ready(Ok(()));
+                };
+                panel.update(cx, |panel, cx| {
+                    panel.open_thread_by_id(&ThreadId::from(thread_id.as_str()), window, cx)
+                })
+            })?
+            .await
+    })
+    .detach_and_prompt_err(
+        "Failed to open the commit's thread",
+        window,
+        cx,
+        |_, _, _| None,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_in_session() {
+        let now = Utc::now();
+        let minutes_ago = |minutes| now - chrono::Duration::minutes(minutes);
+
+        assert!(in_session(false, minutes_ago(5), now));
+        assert!(in_session(true, minutes_ago(120), now));
+        assert!(!in_session(false, minutes_ago(120), now));
+    }
+}
@@ -0,0 +1,231 @@
+//! Points out, in the gutter of their editors, the files that stored threads referenced heavily
+//! through their tool calls or context, linking to those threads so that earlier discussions of
+//! the code being read can be found.
+
+use std::sync::Arc;
+
+use agent_settings::AgentSettings;
+use anyhow::Result;
+use editor::display_map::{BlockPlacement, BlockProperties, BlockStyle};
+use editor::{Addon, Anchor, Editor};
+use gpui::{App, Entity, WeakEntity, Window};
+use language_model::message_handler::{MessageHandlerRegistry, get_message_handler};
+use settings::Settings as _;
+use ui::{Button, ButtonStyle, Label, LabelSize, prelude::*};
+use workspace::Workspace;
+
+use crate::AgentPanel;
+use crate::thread_store::SerializedThreadMetadata;
+
+/// How many of the threads that referenced a file are looked up, unless the threshold is higher.
+const MAX_REFERENCING_THREADS: usize = 20;
+
+/// How many of the threads that referenced a file are linked to above it.
+const MAX_LINKED_THREADS: usize = 5;
+
+/// Marks the editors whose files were looked up, and their gutter highlights.
+struct ConversationHeat;
+
+impl Addon for ConversationHeat {
+    fn to_any(&self) -> &dyn std::any::Any {
+        self
+    }
+}
+
+pub fn init(cx: &mut App) {
+    cx.observe_new(|_: &mut Workspace, window, cx| {
+        let Some(window) = window else {
+            return;
+        };
+        let workspace = cx.entity();
+        window
+            .subscribe(
+                &workspace,
+                cx,
+                |workspace, event: &workspace::Event, window, cx| {
+                    if let workspace::Event::ItemAdded { item } = event {
+                        if let Some(editor) = item.act_as::<Editor>(cx) {
+                            show_conversation_heat(workspace, editor, window, cx);
+                        }
+                    }
+                },
+            )
+            .detach();
+    })
+    .detach();
+}
+
+/// Marks the editor's file if enough stored threads referenced it, linking to those of them in
+/// the agent panel's history.
+fn show_conversation_heat(
+    workspace: Entity<Workspace>,
+    editor: Entity<Editor>,
+    window: &mut Window,
+    cx: &mut App,
+) {
+    let Some(threshold) = AgentSettings::get_global(cx).conversation_heat_threshold else {
+        return;
+    };
+    let handler = cx
+        .has_global::<MessageHandlerRegistry>()
+        .then(|| get_message_handler(cx))
+        .flatten();
+    let Some(handler) = handler else {
+        return;
+    };
+    if editor.read(cx).addon::<ConversationHeat>().is_some() {
+        return;
+    }
+    let Some(panel) = workspace.read(cx).panel::<AgentPanel>(cx) else {
+        return;
+    };
+    let thread_store = panel.read(cx).thread_store().clone();
+    // Stored like the paths tool calls take, starting with the worktree's root name.
+    let path = editor
+        .read(cx)
+        .buffer()
+        .read(cx)
+        .as_singleton()
+        .and_then(|buffer| Some(buffer.read(cx).file()?.full_path(cx)));
+    let Some(path) = path else {
+        return;
+    };
+    let path = path.to_string_lossy().into_owned();
+    // Items are added again when they move between panes.
+    editor.update(cx, |editor, _| editor.register_addon(ConversationHeat));
+    let limit = threshold.max(MAX_REFERENCING_THREADS);
+    let workspace = workspace.downgrade();
+    window
+        .spawn(cx, async move |cx| -> Result<()> {
+            let referencing = handler.threads_referencing_file(&path, limit).await?;
+            if referencing.is_empty() || referencing.len() < threshold {
+                return Ok(());
+            }
+            cx.update(|_, cx| {
+                let history = thread_store.read(cx).reverse_chronological_threads();
+                let threads = linked_threads(&referencing, history);
+                if threads.is_empty() {
+                    return;
+                }
+                let referenced_by = if referencing.len() == limit {
+                    format!("{limit}+")
+                } else {
+                    referencing.len().to_string()
+                };
+                editor.update(cx, |editor, cx| {
+                    insert_heat_block(editor, referenced_by, threads, workspace, cx)
+                });
+            })
+        })
+        .detach_and_log_err(cx);
+}
+
+/// The threads of the local history among those that referenced the file, most recently
+/// referencing first.
+fn linked_threads(
+    referencing: &[String],
+    history: Vec<SerializedThreadMetadata>,
+) -> Vec<SerializedThreadMetadata> {
+    let mut threads = history
+        .into_iter()
+        .filter_map(|thread| {
+            let ix = referencing
+                .iter()
+                .position(|thread_id| thread_id.as_str() == thread.id.to_string())?;
+            Some((ix, thread))
+        })
+        .collect::<Vec<_>>();
+    threads.sort_by_key(|(ix, _)| *ix);
+    threads
+        .into_iter()
+        .map(|(_, thread)| thread)
+        .take(MAX_LINKED_THREADS)
+        .collect()
+}
+
+fn insert_heat_block(
+    editor: &mut Editor,
+    referenced_by: String,
+    threads: Vec<SerializedThreadMetadata>,
+    workspace: WeakEntity<Workspace>,
+    cx: &mut Context<Editor>,
+) {
+    editor.highlight_gutter::<ConversationHeat>(
+        &[Anchor::min()..Anchor::max()],
+        |cx| cx.theme().status().info_background,
+        cx,
+    );
+    editor.insert_blocks(
+        [BlockProperties {
+            placement: BlockPlacement::Above(Anchor::min()),
+            height: Some(1),
+            style: BlockStyle::Sticky,
+            render: Arc::new(move |cx| {
+                h_flex()
+                    .id("conversation-heat")
+                    .block_mouse_except_scroll()
+                    .pl(cx.margins.gutter.full_width())
+                    .gap_1()
+                    .child(
+                        Label::new(format!("Referenced by {referenced_by} stored threads:"))
+                            .size(LabelSize::Small)
+                            .color(Color::Muted),
+                    )
+                    .children(threads.iter().enumerate().map(|(ix, thread)| {
+                        let thread_id = thread.id.clone();
+                        let workspace = workspace.clone();
+                        Button::new(("conversation-heat-thread", ix), thread.summary.clone())
+                            .style(ButtonStyle::Subtle)
+                            .label_size(LabelSize::Small)
+                            .on_click(move |_, window, cx| {
+                                workspace
+                                    .update(cx, |workspace, cx| {
+                                        let Some(panel) =
+                                            workspace.focus_panel::<AgentPanel>(window, cx)
+                                        else {
+                                            return;
+                                        };
+                                        panel.update(cx, |panel, cx| {
+                                            panel
+                                                .open_thread_by_id(&thread_id, window, cx)
+                                                .detach_and_log_err(cx)
+                                        });
+                                    })
+                                    .ok();
+                            })
+                    }))
+                    .into_any_element()
+            }),
+            priority: 0,
+            render_in_minimap: false,
+        }],
+        None,
+        cx,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::thread::ThreadId;
+    use chrono::Utc;
+
+    #[test]
+    fn test_linked_threads_in_reference_order() {
+        let thread = |id: &str| SerializedThreadMetadata {
+            id: ThreadId::from(id),
+            summary: id.to_string().into(),
+            updated_at: Utc::now(),
+            author: None,
+        };
+        let referencing = ["b", "deleted", "a", "c"].map(String::from);
+        let threads = linked_threads(&referencing, vec![thread("a"), thread("c"), thread("b")]);
+        assert_eq!(
+            threads
+                .iter()
+                .map(|thread| thread.id.to_string())
+                .collect::<Vec<_>>(),
+            ["b", "a", "c"]
+        );
+    }
+}
@@ -0,0 +1,72 @@
+//! Whether the files a thread's tool calls referenced have changed since, so that the history
+//! can point out the threads that may no longer reflect the project.
+
+use anyhow::Result;
+use collections::HashMap;
+use gpui::{App, AppContext as _, Entity, Task};
+use language_model::message_handler::{
+    FileChange, MessageHandlerRegistry, changed_files, content_hash, get_message_handler,
+};
+use project::Project;
+
+/// The [`content_hash`] of each of the project's files now, or `None` for the ones that don't
+/// exist. Paths in none of the project's worktrees are left out, as are all of them in remote
+/// projects, whose files aren't read here.
+pub(crate) fn hash_project_files(
+    project: &Entity<Project>,
+    paths: impl IntoIterator<Item = String>,
+    cx: &App,
+) -> Task<HashMap<String, Option<String>>> {
+    let project = project.read(cx);
+    if !project.is_local() {
+        return Task::ready(HashMap::default());
+    }
+    let fs = project.fs().clone();
+    let abs_paths = paths
+        .into_iter()
+        .filter_map(|path| {
+            let project_path = project.find_project_path(&path, cx)?;
+            let abs_path = project.absolute_path(&project_path, cx)?;
+            Some((path, abs_path))
+        })
+        .collect::<Vec<_>>();
+    cx.background_spawn(async move {
+        let mut hashes = HashMap::default();
+        for (path, abs_path) in abs_paths {
+            let hash = fs
+                .load_bytes(&abs_path)
+                .await
+                .ok()
+                .map(|content| content_hash(&content));
+            hashes.insert(path, hash);
+        }
+        hashes
+    })
+}
+
+/// The files the stored thread's tool calls referenced that have changed since the latest of
+/// them, or none if messages aren't stored.
+pub(crate) fn changed_thread_files(
+    project: Entity<Project>,
+    thread_id: String,
+    cx: &App,
+) -> Task<Result<Vec<FileChange>>> {
+    let handler = cx
+        .has_global::<MessageHandlerRegistry>()
+        .then(|| get_message_handler(cx))
+        .flatten();
+    let Some(handler) = handler else {
+        return Task::ready(Ok(Vec::new()));
+    };
+    cx.spawn(async move |cx| {
+        let snapshots = handler.file_snapshots(&thread_id).await?;
+        if snapshots.is_empty() {
+            return Ok(Vec::new());
+        }
+        let paths = snapshots.iter().map(|snapshot| snapshot.path.clone());
+        let hashes = cx
+            .update(|cx| hash_project_files(&project, paths, cx))?
+            .await;
+        Ok(changed_files(&snapshots, &hashes))
+    })
+}
@@ -0,0 +1,775 @@
+//! The message store's actions: listing the threads it holds, pinned and starred ones first,
+//! exporting the current thread as it was stored, linking it to the issue it is about, showing
+//! what is wrong with the store, linting its threads for prompt anti-patterns, and pausing what
+//! is stored. Whether conversations reach the store at all is shown in the status bar, and
+//! noted when they stop. Its usage is shown by
+//! `language_tools`.
+
+use anyhow::Context as _;
+use gpui::{
+    App, ClipboardItem, Context, DismissEvent, Entity, EventEmitter, FocusHandle, Focusable,
+    IntoElement, ParentElement, Render, Styled, Subscription, Task, Window,
+};
+use language_model::message_handler::{
+    AiMessageHandler, DegradedCause, HistoryCursor, MessageHandlerRegistry, StoreHealth,
+    StoredThread, ThreadFlag, ThreadLint, ThreadSort, config_diagnostics, get_message_handler,
+    persistence_paused, set_persistence_paused, store_health, store_read_only,
+};
+use std::sync::Arc;
+use ui::{
+    Banner, Button, ButtonLike, ButtonStyle, IconButton, Label, LabelSize, Tooltip, prelude::*,
+};
+use workspace::notifications::simple_message_notification::MessageNotification;
+use workspace::notifications::{
+    DetachAndPromptErr as _, NotificationId, dismiss_app_notification, show_app_notification,
+};
+use workspace::{ItemHandle, SplitDirection, StatusItemView, Toast, Workspace, item::Item};
+use zed_actions::llm_store::{
+    ExportCurrentThread, LinkCurrentThreadToIssue, LintStoredThreads, OpenHistory,
+    ResolveCurrentThread, ShowStoreDiagnostics, TogglePersistence,
+};
+
+use crate::{AgentPanel, Thread};
+
+/// How many stored threads are listed at a time.
+const HISTORY_PAGE_SIZE: usize = 50;
+
+pub fn init(cx: &mut App) {
+    cx.observe_new(|workspace: &mut Workspace, _, _| {
+        workspace
+            .register_action(|workspace, _: &OpenHistory, window, cx| {
+                open_history(workspace, window, cx);
+            })
+            .register_action(|workspace, _: &ExportCurrentThread, window, cx| {
+                export_current_thread(workspace, window, cx);
+            })
+            .register_action(|workspace, _: &LinkCurrentThreadToIssue, window, cx| {
+                link_current_thread_to_issue(workspace, window, cx);
+            })
+            .register_action(|workspace, _: &ResolveCurrentThread, window, cx| {
+                resolve_current_thread(workspace, window, cx);
+            })
+            .register_action(|workspace, _: &LintStoredThreads, window, cx| {
+                lint_stored_threads(workspace, window, cx);
+            })
+            .register_action(|workspace, _: &ShowStoreDiagnostics, window, cx| {
+                show_store_diagnostics(workspace, window, cx);
+            })
+            .register_action(|workspace, _: &TogglePersistence, _, cx| {
+                toggle_persistence(workspace, cx);
+            });
+    })
+    .detach();
+    observe_store_health(cx);
+}
+
+/// Notifies when conversations stop reaching the store, e.g. because it failed its smoke test at
+/// startup, and dismisses the notification once they reach it again. Configuration errors are
+/// already notified as the handler is initialized.
+fn observe_store_health(cx: &mut App) {
+    struct StoreHealthNotification;
+    let id = NotificationId::unique::<StoreHealthNotification>();
+    let mut health = None;
+    let mut update = move |cx: &mut App| {
+        let new_health = store_health(cx);
+        if health.as_ref() == Some(&new_health) {
+            return;
+        }
+        health = Some(new_health.clone());
+        match new_health {
+            StoreHealth::Degraded(degraded) if degraded.cause != DegradedCause::Configuration => {
+                let message = degraded.to_string();
+                show_app_notification(id.clone(), cx, move |cx| {
+                    cx.new(|cx| {
+                        MessageNotification::new(message.clone(), cx)
+                            .with_title("Persistence degraded")
+                            .primary_message("Show Diagnostics")
+                            .primary_icon(IconName::Warning)
+                            .primary_on_click(|window, cx| {
+                                window.dispatch_action(Box::new(ShowStoreDiagnostics), cx);
+                                cx.emit(DismissEvent);
+                            })
+                    })
+                });
+            }
+            StoreHealth::Healthy | StoreHealth::LocalOnly | StoreHealth::Off => {
+                dismiss_app_notification(&id, cx)
+            }
+            StoreHealth::Checking | StoreHealth::Degraded(_) => {}
+        }
+    };
+    update(cx);
+    cx.observe_global::<MessageHandlerRegistry>(update).detach();
+}
+
+/// Shows in the status bar that conversations don't reach the message store, while they don't.
+pub struct StoreHealthIndicator {
+    health: StoreHealth,
+    _observe_health: Subscription,
+}
+
+impl StoreHealthIndicator {
+    pub fn new(cx: &mut Context<Self>) -> Self {
+        let _observe_health = cx.observe_global::<MessageHandlerRegistry>(|this, cx| {
+            let health = store_health(cx);
+            if this.health != health {
+                this.health = health;
+                cx.notify();
+            }
+        });
+        Self {
+            health: store_health(cx),
+            _observe_health,
+        }
+    }
+}
+
+impl Render for StoreHealthIndicator {
+    fn render(&mut self, _window: &mut Window, _cx: &mut Context<Self>) -> impl IntoElement {
+        let container = h_flex();
+        let StoreHealth::Degraded(degraded) = &self.health else {
+            return container;
+        };
+        let tooltip = degraded.to_string();
+        container.child(
+            ButtonLike::new("store-health-indicator")
+                .child(
+                    h_flex()
+                        .gap_1()
+                        .child(
+                            Icon::new(IconName::Warning)
+                                .size(IconSize::Small)
+                                .color(Color::Warning),
+                        )
+                        .child(Label::new("Persistence degraded").size(LabelSize::Small)),
+                )
+                .tooltip(Tooltip::text(tooltip))
+                .on_click(|_, window, cx| {
+                    window.dispatch_action(Box::new(ShowStoreDiagnostics), cx);
+                }),
+        )
+    }
+}
+
+impl StatusItemView for StoreHealthIndicator {
+    fn set_active_pane_item(
+        &mut self,
+        _active_pane_item: Option<&dyn ItemHandle>,
+        _window: &mut Window,
+        _cx: &mut Context<Self>,
+    ) {
+    }
+}
+
+fn open_history(workspace: &mut Workspace, window: &mut Window, cx: &mut Context<Workspace>) {
+    if let Some(history) = workspace.active_item_as::<StoredThreadHistory>(cx) {
+        history.update(cx, |history, cx| history.reload(window, cx));
+        return;
+    }
+    let history = cx.new(|cx| StoredThreadHistory::new(window, cx));
+    workspace.split_item(SplitDirection::Right, Box::new(history), window, cx)
+}
+
+fn lint_stored_threads(
+    workspace: &mut Workspace,
+    window: &mut Window,
+    cx: &mut Context<Workspace>,
+) {
+    if let Some(report) = workspace.active_item_as::<ConversationLintReport>(cx) {
+        report.update(cx, |report, cx| report.lint(window, cx));
+        return;
+    }
+    let report = cx.new(|cx| ConversationLintReport::new(window, cx));
+    workspace.split_item(SplitDirection::Right, Box::new(report), window, cx)
+}
+
+/// Writes the agent panel's thread, as the message store holds it, to a JSON file, once the
+/// writes of its latest events are done. The store keeps each session of a thread as a thread of
+/// its own.
+fn export_current_thread(
+    workspace: &mut Workspace,
+    window: &mut Window,
+    cx: &mut Context<Workspace>,
+) {
+    let thread_id =
+        current_thread(workspace, cx).map(|thread| thread.read(cx).session_id().to_string());
+    let handler = message_handler(cx);
+    let fs = workspace.app_state().fs.clone();
+    cx.spawn_in(window, async move |_, cx| {
+        let thread_id = thread_id.context("no thread is open in the agent panel")?;
+        let handler = handler.context("messages aren't being stored")?;
+        handler.flush(&thread_id).await;
+        let messages = handler.load_stored_thread(&thread_id).await?;
+        anyhow::ensure!(!messages.is_empty(), "thread {thread_id} isn't stored");
+        let Some(path) = cx
+            .update(|_, cx| cx.prompt_for_new_path(paths::home_dir()))?
+            .await??
+        else {
+            return Ok(());
+        };
+        let path = if path.extension().is_none() {
+            path.with_extension("json")
+        } else {
+            path
+        };
+        fs.atomic_write(path, serde_json::to_string_pretty(&messages)?)
+            .await
+    })
+    .detach_and_prompt_err("Failed to export the thread", window, cx, |_, _, _| None);
+}
+
+fn current_thread(workspace: &Workspace, cx: &App) -> Option<Entity<Thread>> {
+    workspace
+        .panel::<AgentPanel>(cx)
+        .and_then(|panel| panel.read(cx).active_thread())
+}
+
+fn message_handler(cx: &App) -> Option<Arc<AiMessageHandler>> {
+    cx.has_global::<MessageHandlerRegistry>()
+        .then(|| get_message_handler(cx))
+        .flatten()
+}
+
+/// Links the agent panel's thread to the GitHub or Jira issue whose URL or key is on the
+/// clipboard.
+fn link_current_thread_to_issue(
+    workspace: &mut Workspace,
+    window: &mut Window,
+    cx: &mut Context<Workspace>,
+) {
+    let thread_id =
+        current_thread(workspace, cx).map(|thread| thread.read(cx).session_id().to_string());
+    let issue = cx.read_from_clipboard().and_then(|item| item.text());
+    let handler = message_handler(cx);
+    cx.spawn_in(window, async move |workspace, cx| {
+        let thread_id = thread_id.context("no thread is open in the agent panel")?;
+        let issue = issue.context("copy the issue's URL or key to link the thread to it")?;
+        let handler = handler.context("messages aren't being stored")?;
+        let issue = handler.link_thread_issue(&thread_id, &issue).await?;
+        workspace.update(cx, |workspace, cx| {
+            let message = format!("Linked the thread to {}", issue.key);
+            workspace.show_toast(
+                Toast::new(
+                    NotificationId::unique::<LinkCurrentThreadToIssue>(),
+                    message,
+                )
+                .autohide(),
+                cx,
+            );
+        })
+    })
+    .detach_and_prompt_err("Failed to link the thread", window, cx, |_, _, _| None);
+}
+
+/// Marks the agent panel's thread resolved, commenting its summary on its issue if the issue
+/// trackers are set to.
+fn resolve_current_thread(
+    workspace: &mut Workspace,
+    window: &mut Window,
+    cx: &mut Context<Workspace>,
+) {
+    let thread = current_thread(workspace, cx);
+    let handler = message_handler(cx);
+    cx.spawn_in(window, async move |workspace, cx| {
+        let thread = thread.context("no thread is open in the agent panel")?;
+        let handler = handler.context("messages aren't being stored")?;
+        let (thread_id, title) = thread.read_with(cx, |thread, _| {
+            (
+                thread.session_id().to_string(),
+                thread.summary().or_default(),
+            )
+        })?;
+        let summary = Thread::wait_for_detailed_summary_or_text(&thread, cx)
+            .await
+            .unwrap_or_default();
+        let issue = handler
+            .resolve_thread_issue(&thread_id, &format!("**{title}**\n\n{summary}"))
+            .await?;
+        workspace.update(cx, |workspace, cx| {
+            let message = format!("Resolved {}", issue.key);
+            workspace.show_toast(
+                Toast::new(NotificationId::unique::<ResolveCurrentThread>(), message).autohide(),
+                cx,
+            );
+        })
+    })
+    .detach_and_prompt_err("Failed to resolve the thread", window, cx, |_, _, _| None);
+}
+
+/// Shows what is wrong with the message handler's configuration, and what is worth maintaining
+/// in the store's tables, after running the configured maintenance statements.
+fn show_store_diagnostics(
+    workspace: &mut Workspace,
+    window: &mut Window,
+    cx: &mut Context<Workspace>,
+) {
+    let diagnostics = config_diagnostics(cx)
+        .iter()
+        .map(|diagnostic| diagnostic.to_string())
+        .collect::<Vec<_>>();
+    let handler = message_handler(cx);
+    cx.spawn_in(window, async move |workspace, cx| {
+        let mut lines = diagnostics;
+        if let Some(handler) = handler {
+            lines.extend(
+                handler
+                    .maintenance_advice()
+                    .await?
+                    .iter()
+                    .map(|advice| advice.to_string()),
+            );
+        }
+        let message = if lines.is_empty() {
+            "Nothing is wrong with the message store".to_string()
+        } else {
+            lines.join("\n")
+        };
+        workspace.update(cx, |workspace, cx| {
+            workspace.show_notification(
+                NotificationId::unique::<ShowStoreDiagnostics>(),
+                cx,
+                |cx| {
+                    cx.new(|cx| {
+                        MessageNotification::new(message, cx)
+                            .with_title("Message store diagnostics")
+                    })
+                },
+            );
+        })
+    })
+    .detach_and_prompt_err("Failed to diagnose the store", window, cx, |_, _, _| None);
+}
+
+fn toggle_persistence(workspace: &mut Workspace, cx: &mut Context<Workspace>) {
+    let paused = !persistence_paused(cx);
+    set_persistence_paused(paused, cx);
+    let message = if paused {
+        "Requests and completions are no longer stored"
+    } else {
+        "Requests and completions are stored again"
+    };
+    workspace.show_toast(
+        Toast::new(NotificationId::unique::<TogglePersistence>(), message).autohide(),
+        cx,
+    );
+}
+
+/// The threads in the message store that the user may read: the pinned ones, then the starred
+/// ones, then the rest, each most recently updated first.
+pub struct StoredThreadHistory {
+    focus_handle: FocusHandle,
+    threads: Vec<StoredThread>,
+    next: Option<HistoryCursor>,
+    error: Option<SharedString>,
+    _load_threads: Task<()>,
+    _set_flag: Task<()>,
+    _copy_instructions: Task<()>,
+}
+
+impl StoredThreadHistory {
+    pub fn new(window: &mut Window, cx: &mut Context<Self>) -> Self {
+        let mut this = Self {
+            focus_handle: cx.focus_handle(),
+            threads: Vec::new(),
+            next: None,
+            error: None,
+            _load_threads: Task::ready(()),
+            _set_flag: Task::ready(()),
+            _copy_instructions: Task::ready(()),
+        };
+        this.reload(window, cx);
+        this
+    }
+
+    fn reload(&mut self, window: &mut Window, cx: &mut Context<Self>) {
+        self.threads.clear();
+        self.next = None;
+        self.load_page(window, cx);
+    }
+
+    fn load_page(&mut self, window: &mut Window, cx: &mut Context<Self>) {
+        let Some(handler) = message_handler(cx) else {
+            self.error = Some("Threads are only listed while messages are stored.".into());
+            cx.notify();
+            return;
+        };
+        let after = self.next.take();
+        self._load_threads = cx.spawn_in(window, async move |this, cx| {
+            let page = handler
+                .list_stored_threads(after.as_ref(), HISTORY_PAGE_SIZE, None, ThreadSort::Flagged)
+                .await;
+            this.update(cx, |this, cx| {
+                match page {
+                    Ok(page) => {
+                        this.threads.extend(page.items);
+                        this.next = page.next;
+                        this.error = None;
+                    }
+                    Err(error) => {
+                        this.error =
+                            Some(format!("Failed to list stored threads: {error:#}").into());
+                    }
+                }
+                cx.notify();
+            })
+            .ok();
+        });
+    }
+
+    /// Sets or clears the flag on the thread, and lists the threads again in their new order.
+    fn set_flag(
+        &mut self,
+        thread_id: String,
+        flag: ThreadFlag,
+        value: bool,
+        window: &mut Window,
+        cx: &mut Context<Self>,
+    ) {
+        let Some(handler) = message_handler(cx) else {
+            return;
+        };
+        self._set_flag = cx.spawn_in(window, async move |this, cx| {
+            let result = handler.set_thread_flag(&thread_id, flag, value).await;
+            this.update_in(cx, |this, window, cx| match result {
+                Ok(()) => this.reload(window, cx),
+                Err(error) => {
+                    this.error = Some(format!("Failed to flag thread: {error:#}").into());
+                    cx.notify();
+                }
+            })
+            .ok();
+        });
+    }
+
+    /// Copies the instructions the thread's requests were made with over its history, as JSON.
+    fn copy_instructions(
+        &mut self,
+        thread_id: String,
+        window: &mut Window,
+        cx: &mut Context<Self>,
+    ) {
+        let Some(handler) = message_handler(cx) else {
+            return;
+        };
+        self._copy_instructions = cx.spawn_in(window, async move |this, cx| {
+            let result = handler.thread_instructions(&thread_id).await;
+            this.update(cx, |this, cx| match result {
+                Ok(records) => {
+                    let records = records
+                        .into_iter()
+                        .map(|record| {
+                            serde_json::json!({
+                                "recorded_at": record.recorded_at.to_rfc3339(),
+                                "instructions": record.instructions,
+                            })
+                        })
+                        .collect::<Vec<_>>();
+                    let json = serde_json::to_string_pretty(&records).unwrap_or_default();
+                    cx.write_to_clipboard(ClipboardItem::new_string(json));
+                }
+                Err(error) => {
+                    this.error =
+                        Some(format!("Failed to load the thread's instructions: {error:#}").into());
+                    cx.notify();
+                }
+            })
+            .ok();
+        });
+    }
+
+    fn render_flag(
+        &self,
+        ix: usize,
+        thread: &StoredThread,
+        flag: ThreadFlag,
+        cx: &mut Context<Self>,
+    ) -> impl IntoElement {
+        let (id, set, icon, tooltip) = match flag {
+            ThreadFlag::Pinned if thread.pinned => ("unpin-thread", true, IconName::Unpin, "Unpin"),
+            ThreadFlag::Pinned => ("pin-thread", false, IconName::Pin, "Pin to the top"),
+            ThreadFlag::Starred if thread.starred => (
+                "unstar-thread",
+                true,
+                IconName::StarFilled,
+                "Remove from favorites",
+            ),
+            ThreadFlag::Starred => ("star-thread", false, IconName::Star, "Add to favorites"),
+        };
+        let thread_id = thread.thread_id.clone();
+        IconButton::new((id, ix), icon)
+            .icon_size(IconSize::Small)
+            .icon_color(if set { Color::Accent } else { Color::Muted })
+            .tooltip(Tooltip::text(tooltip))
+            .on_click(cx.listener(move |this, _, window, cx| {
+                this.set_flag(thread_id.clone(), flag, !set, window, cx);
+            }))
+    }
+
+    fn render_thread(
+        &self,
+        ix: usize,
+        thread: &StoredThread,
+        cx: &mut Context<Self>,
+    ) -> impl IntoElement {
+        let details = [
+            thread.author_name.as_str(),
+            thread.project.as_str(),
+            thread.git_branch.as_str(),
+            thread.updated_at.as_str(),
+        ]
+        .into_iter()
+        .filter(|detail| !detail.is_empty())
+        .collect::<Vec<_>>()
+        .join(" · ");
+        let thread_id = thread.thread_id.clone();
+        h_flex()
+            .id(("stored-thread", ix))
+            .gap_2()
+            .justify_between()
+            .child(
+                v_flex().child(Label::new(thread.thread_id.clone())).child(
+                    Label::new(details)
+                        .size(LabelSize::Small)
+                        .color(Color::Muted),
+                ),
+            )
+            .child(
+                h_flex()
+                    .gap_1()
+                    .child(self.render_flag(ix, thread, ThreadFlag::Pinned, cx))
+                    .child(self.render_flag(ix, thread, ThreadFlag::Starred, cx))
+                    .child(
+                        Button::new(("copy-thread-instructions", ix), "Copy Instructions")
+                            .style(ButtonStyle::Subtle)
+                            .label_size(LabelSize::Small)
+                            .tooltip(Tooltip::text(
+                                "Copy the profile, tools and rules the thread's requests were made with",
+                            ))
+                            .on_click(cx.listener({
+                                let thread_id = thread_id.clone();
+                                move |this, _, window, cx| {
+                                    this.copy_instructions(thread_id.clone(), window, cx);
+                                }
+                            })),
+                    )
+                    .child(
+                        Button::new(("copy-thread-id", ix), "Copy Id")
+                            .style(ButtonStyle::Subtle)
+                            .label_size(LabelSize::Small)
+                            .tooltip(Tooltip::text("Copy the thread's id"))
+                            .on_click(move |_, _, cx| {
+                                cx.write_to_clipboard(ClipboardItem::new_string(thread_id.clone()));
+                            }),
+                    ),
+            )
+    }
+}
+
+impl Render for StoredThreadHistory {
+    fn render(&mut self, _window: &mut Window, cx: &mut Context<Self>) -> impl IntoElement {
+        v_flex()
+            .id("stored-thread-history")
+            .size_full()
+            .p_4()
+            .gap_3()
+            .overflow_y_scroll()
+            .track_focus(&self.focus_handle)
+            .child(Label::new("Stored Threads").size(LabelSize::Large))
+            .when(store_read_only(cx), |this| {
+                this.child(
+                    Banner::new().child(
+                        Label::new("The store is read-only: threads can be browsed, not edited.")
+                            .size(LabelSize::Small),
+                    ),
+                )
+            })
+            .when_some(self.error.clone(), |this, error| {
+                this.child(Label::new(error).color(Color::Error))
+            })
+            .when(self.threads.is_empty() && self.error.is_none(), |this| {
+                this.child(Label::new("No threads are stored yet.").color(Color::Muted))
+            })
+            .children(
+                self.threads
+                    .iter()
+                    .enumerate()
+                    .map(|(ix, thread)| self.render_thread(ix, thread, cx))
+                    .collect::<Vec<_>>(),
+            )
+            .when(self.next.is_some(), |this| {
+                this.child(
+                    Button::new("load-more", "Load More")
+                        .style(ButtonStyle::Subtle)
+                        .on_click(cx.listener(|this, _, window, cx| this.load_page(window, cx))),
+                )
+            })
+    }
+}
+
+impl Focusable for StoredThreadHistory {
+    fn focus_handle(&self, _: &App) -> FocusHandle {
+        self.focus_handle.clone()
+    }
+}
+
+impl Item for StoredThreadHistory {
+    type Event = ();
+
+    fn to_item_events(_: &Self::Event, _: impl FnMut(workspace::item::ItemEvent)) {}
+
+    fn tab_content_text(&self, _detail: usize, _cx: &App) -> SharedString {
+        "Stored Threads".into()
+    }
+
+    fn telemetry_event_text(&self) -> Option<&'static str> {
+        None
+    }
+}
+
+impl EventEmitter<()> for StoredThreadHistory {}
+
+/// The prompt anti-patterns found in the most recently updated stored threads, to help improve
+/// the agent setups that sent them.
+pub struct ConversationLintReport {
+    focus_handle: FocusHandle,
+    lints: Vec<ThreadLint>,
+    linting: bool,
+    error: Option<SharedString>,
+    _lint: Task<()>,
+}
+
+impl ConversationLintReport {
+    pub fn new(window: &mut Window, cx: &mut Context<Self>) -> Self {
+        let mut this = Self {
+            focus_handle: cx.focus_handle(),
+            lints: Vec::new(),
+            linting: false,
+            error: None,
+            _lint: Task::ready(()),
+        };
+        this.lint(window, cx);
+        this
+    }
+
+    fn lint(&mut self, window: &mut Window, cx: &mut Context<Self>) {
+        let Some(handler) = message_handler(cx) else {
+            self.error = Some("Threads are only linted while messages are stored.".into());
+            cx.notify();
+            return;
+        };
+        self.linting = true;
+        self._lint = cx.spawn_in(window, async move |this, cx| {
+            let lints = handler.lint_stored_threads().await;
+            this.update(cx, |this, cx| {
+                this.linting = false;
+                match lints {
+                    Ok(lints) => {
+                        this.lints = lints;
+                        this.error = None;
+                    }
+                    Err(error) => {
+                        this.error =
+                            Some(format!("Failed to lint stored threads: {error:#}").into());
+                    }
+                }
+                cx.notify();
+            })
+            .ok();
+        });
+        cx.notify();
+    }
+
+    fn render_lint(&self, ix: usize, lint: &ThreadLint) -> impl IntoElement {
+        let thread_id = lint.thread.thread_id.clone();
+        v_flex()
+            .id(("thread-lint", ix))
+            .gap_1()
+            .child(
+                h_flex()
+                    .gap_2()
+                    .justify_between()
+                    .child(Label::new(lint.thread.thread_id.clone()))
+                    .child(
+                        Button::new(("copy-linted-thread-id", ix), "Copy Id")
+                            .style(ButtonStyle::Subtle)
+                            .label_size(LabelSize::Small)
+                            .tooltip(Tooltip::text("Copy the thread's id"))
+                            .on_click(move |_, _, cx| {
+                                cx.write_to_clipboard(ClipboardItem::new_string(thread_id.clone()));
+                            }),
+                    ),
+            )
+            .children(lint.findings.iter().map(|finding| {
+                Label::new(finding.to_string())
+                    .size(LabelSize::Small)
+                    .color(Color::Warning)
+            }))
+    }
+}
+
+impl Render for ConversationLintReport {
+    fn render(&mut self, _window: &mut Window, cx: &mut Context<Self>) -> impl IntoElement {
+        v_flex()
+            .id("conversation-lint-report")
+            .size_full()
+            .p_4()
+            .gap_3()
+            .overflow_y_scroll()
+            .track_focus(&self.focus_handle)
+            .child(
+                h_flex()
+                    .justify_between()
+                    .child(Label::new("Conversation Lint").size(LabelSize::Large))
+                    .child(
+                        Button::new("lint-again", "Lint Again")
+                            .style(ButtonStyle::Subtle)
+                            .disabled(self.linting)
+                            .on_click(cx.listener(|this, _, window, cx| this.lint(window, cx))),
+                    ),
+            )
+            .when_some(self.error.clone(), |this, error| {
+                this.child(Label::new(error).color(Color::Error))
+            })
+            .when(self.linting, |this| {
+                this.child(Label::new("Linting stored threads…").color(Color::Muted))
+            })
+            .when(
+                !self.linting && self.lints.is_empty() && self.error.is_none(),
+                |this| {
+                    this.child(
+                        Label::new("No anti-patterns were found in the stored threads.")
+                            .color(Color::Muted),
+                    )
+                },
+            )
+            .children(
+                self.lints
+                    .iter()
+                    .enumerate()
+                    .map(|(ix, lint)| self.render_lint(ix, lint))
+                    .collect::<Vec<_>>(),
+            )
+    }
+}
+
+impl Focusable for ConversationLintReport {
+    fn focus_handle(&self, _: &App) -> FocusHandle {
+        self.focus_handle.clone()
+    }
+}
+
+impl Item for ConversationLintReport {
+    type Event = ();
+
+    fn to_item_events(_: &Self::Event, _: impl FnMut(workspace::item::ItemEvent)) {}
+
+    fn tab_content_text(&self, _detail: usize, _cx: &App) -> SharedString {
+        "Conversation Lint".into()
+    }
+
+    fn telemetry_event_text(&self) -> Option<&'static str> {
+        None
+    }
+}
+
+impl EventEmitter<()> for ConversationLintReport {}
@@ -29,7 +29,7 @@ use gpui::{
 };
 use language::{Buffer, Language, Point};
 use language_model::{
-    ConfiguredModel, LanguageModelRequestMessage, MessageContent, RequestUsage,
+    ConfiguredModel, LanguageModelRequestMessage, MessageContent, RequestSource, RequestUsage,
     ZED_CLOUD_PROVIDER_ID,
 };
 use multi_buffer;
@@ -1405,6 +1405,16 @@ impl MessageEditor {
                         tool_choice: None,
                         stop: vec![],
                         temperature: AgentSettings::temperature_for_model(&model.model, cx),
+                        toolchain: None,
+                        project: None,
+                        prompt_template: None,
+                        origin: None,
+                        buffer: None,
+                        git_branch: None,
+                        schedule_id: None,
+                        fan_out_group: None,
+                        message_id: None,
+                        source: Some(RequestSource::AgentPanel),
                     };
 
                     Some(model.model.count_tokens(request, cx))
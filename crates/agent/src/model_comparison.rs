@@ -0,0 +1,389 @@
+//! Sends a prompt to the default model and the inline assistant's alternatives at once, and
+//! shows their responses side by side. Each response is stored as a thread of its own, in a
+//! fan-out group with the others. Once they're all in, the judge model ranks them, and the best
+//! is inserted into the editor that was active when the comparison was opened.
+
+use std::sync::Arc;
+
+use editor::Editor;
+use futures::StreamExt as _;
+use gpui::{
+    App, Context, Entity, EventEmitter, FocusHandle, Focusable, IntoElement, ParentElement, Render,
+    Styled, Task, WeakEntity, Window,
+};
+use language_model::{
+    FanOutVerdict, JudgeCandidate, LanguageModel, LanguageModelRegistry, LanguageModelRequest,
+    LanguageModelRequestMessage, MessageContent, Role, fan_out_request, judge_fan_out,
+};
+use ui::{Label, LabelSize, prelude::*};
+use workspace::{SplitDirection, Workspace, item::Item};
+use zed_actions::agent::CompareModels;
+use zed_llm_client::CompletionIntent;
+
+pub fn init(cx: &mut App) {
+    cx.observe_new(|workspace: &mut Workspace, _, _| {
+        workspace.register_action(|workspace, _: &CompareModels, window, cx| {
+            let target = workspace
+                .active_item_as::<Editor>(cx)
+                .map(|editor| editor.downgrade());
+            let comparison = cx.new(|cx| ModelComparison::new(target, window, cx));
+            workspace.split_item(SplitDirection::Right, Box::new(comparison), window, cx)
+        });
+    })
+    .detach();
+}
+
+/// The models a prompt is compared across: the default model, then the inline assistant's
+/// alternatives, each once.
+fn compared_models(cx: &App) -> Vec<Arc<dyn LanguageModel>> {
+    let registry = LanguageModelRegistry::read_global(cx);
+    let mut models = Vec::<Arc<dyn LanguageModel>>::new();
+    let candidates = registry
+        .default_model()
+        .map(|configured| configured.model)
+        .into_iter()
+        .chain(registry.inline_alternative_models().iter().cloned());
+    for model in candidates {
+        if !models.iter().any(|other| other.id() == model.id()) {
+            models.push(model);
+        }
+    }
+    models
+}
+
+struct Column {
+    model_name: SharedString,
+    thread_id: SharedString,
+    text: String,
+    error: Option<SharedString>,
+    done: bool,
+}
+
+/// Where the judge is at with the responses of the latest prompt.
+enum Judgement {
+    Pending,
+    Judging,
+    Done(FanOutVerdict),
+    Failed(SharedString),
+}
+
+pub struct ModelComparison {
+    prompt_editor: Entity<Editor>,
+    /// Where the best response is inserted.
+    target: Option<WeakEntity<Editor>>,
+    request: Option<LanguageModelRequest>,
+    group_id: Option<SharedString>,
+    columns: Vec<Column>,
+    judgement: Judgement,
+    error: Option<SharedString>,
+    _stream_responses: Vec<Task<()>>,
+    _judge: Task<()>,
+}
+
+impl ModelComparison {
+    pub fn new(
+        target: Option<WeakEntity<Editor>>,
+        window: &mut Window,
+        cx: &mut Context<Self>,
+    ) -> Self {
+        let prompt_editor = cx.new(|cx| {
+            let mut editor = Editor::single_line(window, cx);
+            editor.set_placeholder_text("Prompt to send to each model…", cx);
+            editor
+        });
+        Self {
+            prompt_editor,
+            target,
+            request: None,
+            group_id: None,
+            columns: Vec::new(),
+            judgement: Judgement::Pending,
+            error: None,
+            _stream_responses: Vec::new(),
+            _judge: Task::ready(()),
+        }
+    }
+
+    fn confirm(&mut self, _: &menu::Confirm, window: &mut Window, cx: &mut Context<Self>) {
+        let prompt = self.prompt_editor.read(cx).text(cx);
+        if prompt.trim().is_empty() {
+            return;
+        }
+        let models = compared_models(cx);
+        if models.is_empty() {
+            self.error = Some("No model is configured to compare.".into());
+            cx.notify();
+            return;
+        }
+        let request = LanguageModelRequest {
+            intent: Some(CompletionIntent::UserPrompt),
+            messages: vec![LanguageModelRequestMessage {
+                role: Role::User,
+                content: vec![MessageContent::Text(prompt)],
+                cache: false,
+            }],
+            ..Default::default()
+        };
+
+        let fan_out = fan_out_request(&request, &models, &cx.to_async());
+        self.request = Some(request);
+        self.judgement = Judgement::Pending;
+        self._judge = Task::ready(());
+        self.error = None;
+        self.group_id = Some(fan_out.group_id.into());
+        self.columns = fan_out
+            .branches
+            .iter()
+            .map(|branch| Column {
+                model_name: branch.model.name().0,
+                thread_id: branch.thread_id.clone().into(),
+                text: String::new(),
+                error: None,
+                done: false,
+            })
+            .collect();
+        self._stream_responses = fan_out
+            .branches
+            .into_iter()
+            .enumerate()
+            .map(|(ix, branch)| {
+                cx.spawn_in(window, async move |this, cx| {
+                    let error = match branch.text.await {
+                        Ok(mut text) => loop {
+                            match text.stream.next().await {
+                                Some(Ok(chunk)) => {
+                                    this.update(cx, |this, cx| {
+                                        this.columns[ix].text.push_str(&chunk);
+                                        cx.notify();
+                                    })
+                                    .ok();
+                                }
+                                Some(Err(error)) => break Some(error.to_string()),
+                                None => break None,
+                            }
+                        },
+                        Err(error) => Some(format!("{error:#}")),
+                    };
+                    this.update_in(cx, |this, window, cx| {
+                        let column = &mut this.columns[ix];
+                        column.error = error.map(Into::into);
+                        column.done = true;
+                        this.judge_when_done(window, cx);
+                        cx.notify();
+                    })
+                    .ok();
+                })
+            })
+            .collect();
+        cx.notify();
+    }
+
+    /// Once every model has responded, has the judge rank the responses, if there are at least
+    /// two to choose from, and inserts the best.
+    fn judge_when_done(&mut self, window: &mut Window, cx: &mut Context<Self>) {
+        if !matches!(self.judgement, Judgement::Pending)
+            || self.columns.iter().any(|column| !column.done)
+        {
+            return;
+        }
+        let candidates = self
+            .columns
+            .iter()
+            .filter(|column| column.error.is_none() && !column.text.trim().is_empty())
+            .map(|column| JudgeCandidate {
+                thread_id: column.thread_id.to_string(),
+                text: column.text.clone(),
+            })
+            .collect::<Vec<_>>();
+        if candidates.len() < 2 {
+            return;
+        }
+        let (Some(request), Some(group_id)) = (self.request.as_ref(), self.group_id.as_ref())
+        else {
+            return;
+        };
+        let Some(judge) = LanguageModelRegistry::read_global(cx).judge_model() else {
+            self.judgement = Judgement::Failed("No judge model is configured.".into());
+            return;
+        };
+        let verdict = judge_fan_out(
+            judge.model,
+            group_id.to_string(),
+            request,
+            candidates,
+            &cx.to_async(),
+        );
+        self.judgement = Judgement::Judging;
+        self._judge = cx.spawn_in(window, async move |this, cx| {
+            let verdict = verdict.await;
+            this.update_in(cx, |this, window, cx| {
+                match verdict {
+                    Ok(verdict) => {
+                        this.insert_winner(&verdict, window, cx);
+                        this.judgement = Judgement::Done(verdict);
+                    }
+                    Err(error) => {
+                        this.judgement = Judgement::Failed(format!("{error:#}").into());
+                    }
+                }
+                cx.notify();
+            })
+            .ok();
+        });
+    }
+
+    fn insert_winner(&self, verdict: &FanOutVerdict, window: &mut Window, cx: &mut App) {
+        let Some(target) = self.target.as_ref().and_then(|target| target.upgrade()) else {
+            return;
+        };
+        let Some(winner) = verdict.winner().and_then(|winner| {
+            self.columns
+                .iter()
+                .find(|column| column.thread_id.as_ref() == winner)
+        }) else {
+            return;
+        };
+        target.update(cx, |editor, cx| editor.insert(&winner.text, window, cx));
+    }
+
+    /// The column's place in the judge's ranking, best first, once the judge is done.
+    fn rank(&self, column: &Column) -> Option<usize> {
+        let Judgement::Done(verdict) = &self.judgement else {
+            return None;
+        };
+        verdict
+            .ranking
+            .iter()
+            .position(|thread_id| thread_id.as_str() == column.thread_id.as_ref())
+    }
+
+    fn render_judgement(&self) -> Option<impl IntoElement> {
+        let (label, color) = match &self.judgement {
+            Judgement::Pending => return None,
+            Judgement::Judging => ("Ranking the responses…".into(), Color::Muted),
+            Judgement::Done(verdict) => (
+                SharedString::from(verdict.rationale.clone()),
+                Color::Default,
+            ),
+            Judgement::Failed(error) => (
+                SharedString::from(format!("Failed to rank the responses: {error}")),
+                Color::Error,
+            ),
+        };
+        Some(
+            v_flex()
+                .gap_1()
+                .child(
+                    Label::new("Judge")
+                        .size(LabelSize::Small)
+                        .color(Color::Muted),
+                )
+                .child(Label::new(label).size(LabelSize::Small).color(color)),
+        )
+    }
+
+    fn render_column(&self, column: &Column, cx: &Context<Self>) -> impl IntoElement {
+        let rank = self.rank(column);
+        v_flex()
+            .flex_1()
+            .min_w_0()
+            .p_2()
+            .gap_1()
+            .border_1()
+            .rounded_md()
+            .border_color(cx.theme().colors().border_variant)
+            .child(
+                h_flex()
+                    .gap_2()
+                    .child(Label::new(column.model_name.clone()))
+                    .when_some(rank, |this, rank| {
+                        let (label, color) = if rank == 0 {
+                            ("Winner".to_string(), Color::Success)
+                        } else {
+                            (format!("#{}", rank + 1), Color::Muted)
+                        };
+                        this.child(Label::new(label).size(LabelSize::Small).color(color))
+                    })
+                    .when(!column.done, |this| {
+                        this.child(
+                            Label::new("Responding…")
+                                .size(LabelSize::Small)
+                                .color(Color::Muted),
+                        )
+                    }),
+            )
+            .child(
+                Label::new(format!("Thread {}", column.thread_id))
+                    .size(LabelSize::Small)
+                    .color(Color::Muted),
+            )
+            .child(div().text_ui_sm(cx).child(column.text.clone()))
+            .when_some(column.error.clone(), |this, error| {
+                this.child(Label::new(error).size(LabelSize::Small).color(Color::Error))
+            })
+    }
+}
+
+impl Render for ModelComparison {
+    fn render(&mut self, _window: &mut Window, cx: &mut Context<Self>) -> impl IntoElement {
+        v_flex()
+            .id("model-comparison")
+            .key_context("ModelComparison")
+            .on_action(cx.listener(Self::confirm))
+            .size_full()
+            .p_4()
+            .gap_3()
+            .overflow_y_scroll()
+            .child(Label::new("Compare Models").size(LabelSize::Large))
+            .child(
+                div()
+                    .py_1()
+                    .px_2()
+                    .border_1()
+                    .rounded_md()
+                    .border_color(cx.theme().colors().border_variant)
+                    .bg(cx.theme().colors().editor_background)
+                    .child(self.prompt_editor.clone()),
+            )
+            .when_some(self.error.clone(), |this, error| {
+                this.child(Label::new(error).color(Color::Error))
+            })
+            .when_some(self.group_id.clone(), |this, group_id| {
+                this.child(
+                    Label::new(format!("Stored in fan-out group {group_id}"))
+                        .size(LabelSize::Small)
+                        .color(Color::Muted),
+                )
+            })
+            .children(self.render_judgement())
+            .child(
+                h_flex().items_start().gap_2().children(
+                    self.columns
+                        .iter()
+                        .map(|column| self.render_column(column, cx)),
+                ),
+            )
+    }
+}
+
+impl Focusable for ModelComparison {
+    fn focus_handle(&self, cx: &App) -> FocusHandle {
+        self.prompt_editor.focus_handle(cx)
+    }
+}
+
+impl Item for ModelComparison {
+    type Event = ();
+
+    fn to_item_events(_: &Self::Event, _: impl FnMut(workspace::item::ItemEvent)) {}
+
+    fn tab_content_text(&self, _detail: usize, _cx: &App) -> SharedString {
+        "Compare Models".into()
+    }
+
+    fn telemetry_event_text(&self) -> Option<&'static str> {
+        None
+    }
+}
+
+impl EventEmitter<()> for ModelComparison {}
@@ -0,0 +1,296 @@
+//! Lists the runs made without a user, e.g. scheduled ones, that finished, and pops up a
+//! notification when one finishes while Zed isn't focused, so that their results are noticed.
+
+use std::rc::Rc;
+
+use agent_settings::{AgentSettings, NotifyWhenAgentWaiting};
+use chrono::Local;
+use futures::StreamExt as _;
+use gpui::{
+    App, Context, Entity, EventEmitter, FocusHandle, Focusable, IntoElement, ParentElement,
+    PlatformDisplay, Render, Styled, Task, Window, WindowHandle,
+};
+use language_model::message_handler::{
+    MessageHandlerRegistry, RunResult, RunStatus, get_message_handler, subscribe_run_results,
+};
+use settings::Settings as _;
+use ui::{Icon, IconName, Label, LabelSize, prelude::*};
+use util::ResultExt as _;
+use workspace::{SplitDirection, Workspace, item::Item};
+use zed_actions::agent::OpenRunInbox;
+
+use crate::ui::{AgentNotification, AgentNotificationEvent};
+
+/// How many of the latest finished runs the inbox lists.
+const INBOX_LIMIT: usize = 100;
+
+pub fn init(cx: &mut App) {
+    cx.observe_new(|workspace: &mut Workspace, _, _| {
+        workspace.register_action(|workspace, _: &OpenRunInbox, window, cx| {
+            open_inbox(workspace, window, cx);
+        });
+    })
+    .detach();
+
+    let mut results = subscribe_run_results(cx);
+    cx.spawn(async move |cx| {
+        while let Some(result) = results.next().await {
+            cx.update(|cx| notify(&result, cx)).ok();
+        }
+    })
+    .detach();
+}
+
+fn open_inbox(workspace: &mut Workspace, window: &mut Window, cx: &mut Context<Workspace>) {
+    if let Some(inbox) = workspace.active_item_as::<RunInbox>(cx) {
+        inbox.update(cx, |inbox, cx| inbox.load_results(window, cx));
+        return;
+    }
+    let inbox = cx.new(|cx| RunInbox::new(window, cx));
+    workspace.split_item(SplitDirection::Right, Box::new(inbox), window, cx)
+}
+
+fn status_icon(status: RunStatus) -> (IconName, Color) {
+    match status {
+        RunStatus::Success => (IconName::Check, Color::Success),
+        RunStatus::Error => (IconName::XCircle, Color::Error),
+        RunStatus::Truncated => (IconName::Warning, Color::Warning),
+        RunStatus::Failed => (IconName::XCircle, Color::Warning),
+    }
+}
+
+fn status_caption(result: &RunResult) -> String {
+    match result.status {
+        RunStatus::Success => "Finished".to_string(),
+        RunStatus::Error => match &result.error {
+            Some(error) => format!("Failed: {error}"),
+            None => "Failed".to_string(),
+        },
+        RunStatus::Truncated => "Stopped at the model's output limit".to_string(),
+        RunStatus::Failed => match &result.error {
+            Some(failures) => format!("Failed its assertions: {failures}"),
+            None => "Failed its assertions".to_string(),
+        },
+    }
+}
+
+/// Pops the run's result up on the screens the agent's notifications go to, unless a Zed
+/// window is focused.
+fn notify(result: &RunResult, cx: &mut App) {
+    if cx
+        .active_window()
+        .is_some_and(|window| window.downcast::<Workspace>().is_some())
+    {
+        return;
+    }
+    let screens = match AgentSettings::get_global(cx).notify_when_agent_waiting {
+        NotifyWhenAgentWaiting::PrimaryScreen => cx.primary_display().into_iter().collect(),
+        NotifyWhenAgentWaiting::AllScreens => cx.displays(),
+        NotifyWhenAgentWaiting::Never => Vec::new(),
+    };
+    let title = SharedString::from(format!("Scheduled run {}", result.schedule_id));
+    let caption = SharedString::from(status_caption(result));
+    let (icon, _) = status_icon(result.status);
+    let pop_ups = screens
+        .into_iter()
+        .filter_map(|screen| pop_up(screen, title.clone(), caption.clone(), icon, cx))
+        .collect::<Vec<_>>();
+    let windows = pop_ups
+        .iter()
+        .map(|(window, _)| *window)
+        .collect::<Vec<_>>();
+    for (_, pop_up) in pop_ups {
+        let windows = windows.clone();
+        cx.subscribe(&pop_up, move |_, event, cx| {
+            if let AgentNotificationEvent::Accepted = event {
+                open_inbox_in_any_window(cx);
+            }
+            for window in &windows {
+                window
+                    .update(cx, |_, window, _| window.remove_window())
+                    .ok();
+            }
+        })
+        .detach();
+    }
+}
+
+fn pop_up(
+    screen: Rc<dyn PlatformDisplay>,
+    title: SharedString,
+    caption: SharedString,
+    icon: IconName,
+    cx: &mut App,
+) -> Option<(WindowHandle<AgentNotification>, Entity<AgentNotification>)> {
+    let options = AgentNotification::window_options(screen, cx);
+    let window = cx
+        .open_window(options, |_, cx| {
+            cx.new(|_| AgentNotification::new(title, caption, icon, None::<SharedString>))
+        })
+        .log_err()?;
+    let pop_up = window.entity(cx).log_err()?;
+    Some((window, pop_up))
+}
+
+fn open_inbox_in_any_window(cx: &mut App) {
+    cx.activate(true);
+    let workspace = cx
+        .windows()
+        .into_iter()
+        .find_map(|window| window.downcast::<Workspace>());
+    if let Some(workspace) = workspace {
+        workspace
+            .update(cx, |workspace, window, cx| {
+                window.activate_window();
+                open_inbox(workspace, window, cx);
+            })
+            .log_err();
+    }
+}
+
+pub struct RunInbox {
+    focus_handle: FocusHandle,
+    results: Vec<RunResult>,
+    error: Option<SharedString>,
+    _load_results: Task<()>,
+    _receive_results: Task<()>,
+}
+
+impl RunInbox {
+    pub fn new(window: &mut Window, cx: &mut Context<Self>) -> Self {
+        let mut results = subscribe_run_results(cx);
+        let receive_results = cx.spawn_in(window, async move |this, cx| {
+            while let Some(result) = results.next().await {
+                this.update(cx, |this, cx| {
+                    this.results.retain(|row| row.thread_id != result.thread_id);
+                    this.results.insert(0, result);
+                    this.results.truncate(INBOX_LIMIT);
+                    cx.notify();
+                })
+                .ok();
+            }
+        });
+        let mut this = Self {
+            focus_handle: cx.focus_handle(),
+            results: Vec::new(),
+            error: None,
+            _load_results: Task::ready(()),
+            _receive_results: receive_results,
+        };
+        this.load_results(window, cx);
+        this
+    }
+
+    fn load_results(&mut self, window: &mut Window, cx: &mut Context<Self>) {
+        let handler = cx
+            .has_global::<MessageHandlerRegistry>()
+            .then(|| get_message_handler(cx))
+            .flatten();
+        let Some(handler) = handler else {
+            self.error = Some("Finished runs are only listed while messages are stored.".into());
+            cx.notify();
+            return;
+        };
+        self._load_results = cx.spawn_in(window, async move |this, cx| {
+            let results = handler.run_results(INBOX_LIMIT).await;
+            this.update(cx, |this, cx| {
+                match results {
+                    Ok(results) => {
+                        this.results = results;
+                        this.error = None;
+                    }
+                    Err(error) => {
+                        this.error =
+                            Some(format!("Failed to load finished runs: {error:#}").into());
+                    }
+                }
+                cx.notify();
+            })
+            .ok();
+        });
+    }
+
+    fn render_result(&self, ix: usize, result: &RunResult) -> impl IntoElement {
+        let (icon, color) = status_icon(result.status);
+        let finished_at = result
+            .finished_at
+            .with_timezone(&Local)
+            .format("%b %-d, %H:%M");
+        h_flex()
+            .id(("run-result", ix))
+            .gap_2()
+            .items_start()
+            .child(Icon::new(icon).color(color).size(IconSize::Small))
+            .child(
+                v_flex()
+                    .flex_1()
+                    .child(
+                        h_flex()
+                            .gap_2()
+                            .child(Label::new(result.schedule_id.clone()))
+                            .child(
+                                Label::new(finished_at.to_string())
+                                    .size(LabelSize::Small)
+                                    .color(Color::Muted),
+                            ),
+                    )
+                    .child(
+                        Label::new(status_caption(result))
+                            .size(LabelSize::Small)
+                            .color(color),
+                    )
+                    .child(
+                        Label::new(format!("Thread {}", result.thread_id))
+                            .size(LabelSize::Small)
+                            .color(Color::Muted),
+                    ),
+            )
+    }
+}
+
+impl Render for RunInbox {
+    fn render(&mut self, _window: &mut Window, _cx: &mut Context<Self>) -> impl IntoElement {
+        v_flex()
+            .id("run-inbox")
+            .size_full()
+            .p_4()
+            .gap_3()
+            .overflow_y_scroll()
+            .track_focus(&self.focus_handle)
+            .child(Label::new("Finished Runs").size(LabelSize::Large))
+            .when_some(self.error.clone(), |this, error| {
+                this.child(Label::new(error).color(Color::Error))
+            })
+            .when(self.results.is_empty() && self.error.is_none(), |this| {
+                this.child(Label::new("No runs have finished yet.").color(Color::Muted))
+            })
+            .children(
+                self.results
+                    .iter()
+                    .enumerate()
+                    .map(|(ix, result)| self.render_result(ix, result)),
+            )
+    }
+}
+
+impl Focusable for RunInbox {
+    fn focus_handle(&self, _: &App) -> FocusHandle {
+        self.focus_handle.clone()
+    }
+}
+
+impl Item for RunInbox {
+    type Event = ();
+
+    fn to_item_events(_: &Self::Event, _: impl FnMut(workspace::item::ItemEvent)) {}
+
+    fn tab_content_text(&self, _detail: usize, _cx: &App) -> SharedString {
+        "Finished Runs".into()
+    }
+
+    fn telemetry_event_text(&self) -> Option<&'static str> {
+        None
+    }
+}
+
+impl EventEmitter<()> for RunInbox {}
@@ -15,7 +15,7 @@ use gpui::{App, Entity, Focusable, Global, Subscription, Task, UpdateGlobal, Wea
 use language::Buffer;
 use language_model::{
     ConfiguredModel, LanguageModelRegistry, LanguageModelRequest, LanguageModelRequestMessage,
-    Role, report_assistant_event,
+    RequestSource, Role, report_assistant_event,
 };
 use project::Project;
 use prompt_store::{PromptBuilder, PromptStore};
@@ -299,6 +299,16 @@ impl TerminalInlineAssistant {
                 tool_choice: None,
                 stop: Vec::new(),
                 temperature,
+                toolchain: None,
+                project: None,
+                prompt_template: None,
+                origin: None,
+                buffer: None,
+                git_branch: None,
+                schedule_id: None,
+                fan_out_group: None,
+                message_id: None,
+                source: Some(RequestSource::TerminalAssist),
             }
         }))
     }
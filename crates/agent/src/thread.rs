@@ -1,6 +1,7 @@
 use std::fmt::Write as _;
 use std::io::Write;
 use std::ops::Range;
+use std::path::Path;
 use std::sync::Arc;
 use std::time::Instant;
 
@@ -15,21 +16,31 @@ use futures::future::Shared;
 use futures::{FutureExt, StreamExt as _};
 use git::repository::DiffType;
 use gpui::{
-    AnyWindowHandle, App, AppContext, AsyncApp, Context, Entity, EventEmitter, SharedString, Task,
-    WeakEntity,
+    AnyWindowHandle, App, AppContext, AsyncApp, Context, Entity, EventEmitter, SharedString,
+    Subscription, Task, WeakEntity,
+};
+use language_model::message_handler::{
+    ExperimentMetric, FileSnapshot, MessageAuthor, MessageHandlerRegistry, PersistedPart,
+    PersistenceAck, PersistenceStatus, PromptExperimentAssignment, ProtoRemotePersistence,
+    RemotePersistence, RulesSnapshot, SessionEnvironment, ThreadInstructions, ThreadLock,
+    get_message_handler, lock_thread, message_author, prompt_experiment_variant,
+    record_prompt_experiment_outcome, save_file_snapshots, save_session_env,
+    save_thread_instructions, set_remote_persistence, subscribe_persistence_acks, tool_input_paths,
 };
 use language_model::{
     ConfiguredModel, LanguageModel, LanguageModelCompletionError, LanguageModelCompletionEvent,
-    LanguageModelId, LanguageModelKnownError, LanguageModelRegistry, LanguageModelRequest,
-    LanguageModelRequestMessage, LanguageModelRequestTool, LanguageModelToolResult,
-    LanguageModelToolResultContent, LanguageModelToolUseId, MessageContent,
-    ModelRequestLimitReachedError, PaymentRequiredError, RequestUsage, Role, SelectedModel,
-    StopReason, TokenUsage,
+    LanguageModelId, LanguageModelImage, LanguageModelKnownError, LanguageModelRegistry,
+    LanguageModelRequest, LanguageModelRequestMessage, LanguageModelRequestTool,
+    LanguageModelToolResult, LanguageModelToolResultContent, LanguageModelToolUseId,
+    MessageContent, ModelRequestLimitReachedError, PaymentRequiredError, ProjectArea,
+    RequestBuffer, RequestOrigin, RequestPromptTemplate, RequestSource, RequestToolchain,
+    RequestUsage, Role, SelectedModel, StopReason, TokenUsage, stream_resumable_completion,
 };
 use postage::stream::Stream as _;
-use project::Project;
 use project::git_store::{GitStore, GitStoreCheckpoint, RepositoryState};
-use prompt_store::{ModelContext, PromptBuilder};
+use project::toolchain_store::ToolchainStoreEvent;
+use project::{Project, ProjectPath};
+use prompt_store::{ASSISTANT_SYSTEM_PROMPT_TEMPLATE, ModelContext, ProjectContext, PromptBuilder};
 use proto::Plan;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
@@ -43,6 +54,7 @@ use zed_llm_client::{CompletionIntent, CompletionRequestStatus};
 
 use crate::ThreadStore;
 use crate::context::{AgentContext, AgentContextHandle, ContextLoadResult, LoadedContext};
+use crate::file_drift::hash_project_files;
 use crate::thread_store::{
     SerializedCrease, SerializedLanguageModel, SerializedMessage, SerializedMessageSegment,
     SerializedThread, SerializedToolResult, SerializedToolUse, SharedProjectContext,
@@ -340,6 +352,8 @@ pub struct Thread {
     checkpoints_by_message: HashMap<MessageId, ThreadCheckpoint>,
     completion_count: usize,
     pending_completions: Vec<PendingCompletion>,
+    /// Held while the thread is generating, so that no other session writes to it meanwhile.
+    run_lock: Option<ThreadLock>,
     project: Entity<Project>,
     prompt_builder: Arc<PromptBuilder>,
     tools: Entity<ToolWorkingSet>,
@@ -353,8 +367,16 @@ pub struct Thread {
     exceeded_window_error: Option<ExceededWindowError>,
     last_usage: Option<RequestUsage>,
     tool_use_limit_reached: bool,
+    /// Who started the thread; imported threads keep their original author.
+    author: Option<MessageAuthor>,
+    model_segments: Vec<ModelSegment>,
     feedback: Option<ThreadFeedback>,
     message_feedback: HashMap<MessageId, ThreadFeedback>,
+    /// Where the writes of each message, and of the completion requested for it, stand in the
+    /// message store.
+    persistence_acks: HashMap<(MessageId, PersistedPart), PersistenceStatus>,
+    /// The instructions last stored for the session, to store them again only once they change.
+    recorded_instructions: Option<ThreadInstructions>,
     last_auto_capture_at: Option<Instant>,
     last_received_chunk_at: Option<Instant>,
     request_callback: Option<
@@ -362,6 +384,11 @@ pub struct Thread {
     >,
     remaining_turns: u32,
     configured_model: Option<ConfiguredModel>,
+    /// The toolchain of the project's active entry, included in requests so suggestions target it.
+    active_toolchain: Option<RequestToolchain>,
+    _active_toolchain_task: Task<()>,
+    _toolchain_subscriptions: Vec<Subscription>,
+    _receive_persistence_acks: Task<()>,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -399,6 +426,18 @@ pub struct ExceededWindowError {
     token_count: usize,
 }
 
+/// A run of requests in a thread that were all sent to the same model. A new segment starts
+/// whenever the thread hands off to another model.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModelSegment {
+    pub model: SerializedLanguageModel,
+    /// The last message in the thread when the first request of the segment was sent.
+    pub first_message_id: Option<MessageId>,
+    pub started_at: DateTime<Utc>,
+    pub request_count: usize,
+    pub token_usage: TokenUsage,
+}
+
 impl Thread {
     pub fn new(
         project: Entity<Project>,
@@ -409,6 +448,8 @@ impl Thread {
     ) -> Self {
         let (detailed_summary_tx, detailed_summary_rx) = postage::watch::channel();
         let configured_model = LanguageModelRegistry::read_global(cx).default_model();
+        let toolchain_subscriptions = Self::watch_active_toolchain(&project, cx);
+        let active_toolchain_task = Self::spawn_active_toolchain_task(project.clone(), cx);
 
         Self {
             id: ThreadId::new(),
@@ -427,6 +468,7 @@ impl Thread {
             checkpoints_by_message: HashMap::default(),
             completion_count: 0,
             pending_completions: Vec::new(),
+            run_lock: None,
             project: project.clone(),
             prompt_builder,
             tools: tools.clone(),
@@ -445,13 +487,21 @@ impl Thread {
             exceeded_window_error: None,
             last_usage: None,
             tool_use_limit_reached: false,
+            author: message_author(cx),
+            model_segments: Vec::new(),
             feedback: None,
             message_feedback: HashMap::default(),
+            persistence_acks: HashMap::default(),
+            recorded_instructions: None,
             last_auto_capture_at: None,
             last_received_chunk_at: None,
             request_callback: None,
             remaining_turns: u32::MAX,
             configured_model,
+            active_toolchain: None,
+            _toolchain_subscriptions: toolchain_subscriptions,
+            _active_toolchain_task: active_toolchain_task,
+            _receive_persistence_acks: Self::receive_persistence_acks(cx),
         }
     }
 
@@ -498,6 +548,8 @@ impl Thread {
         let completion_mode = serialized
             .completion_mode
             .unwrap_or_else(|| AgentSettings::get_global(cx).preferred_completion_mode);
+        let toolchain_subscriptions = Self::watch_active_toolchain(&project, cx);
+        let active_toolchain_task = Self::spawn_active_toolchain_task(project.clone(), cx);
 
         Self {
             id,
@@ -554,6 +606,7 @@ impl Thread {
             checkpoints_by_message: HashMap::default(),
             completion_count: 0,
             pending_completions: Vec::new(),
+            run_lock: None,
             last_restore_checkpoint: None,
             pending_checkpoint: None,
             project: project.clone(),
@@ -567,16 +620,128 @@ impl Thread {
             exceeded_window_error: None,
             last_usage: None,
             tool_use_limit_reached: serialized.tool_use_limit_reached,
+            author: serialized.author,
+            model_segments: serialized.model_segments,
             feedback: None,
             message_feedback: HashMap::default(),
+            persistence_acks: HashMap::default(),
+            recorded_instructions: None,
             last_auto_capture_at: None,
             last_received_chunk_at: None,
             request_callback: None,
             remaining_turns: u32::MAX,
             configured_model,
+            active_toolchain: None,
+            _toolchain_subscriptions: toolchain_subscriptions,
+            _active_toolchain_task: active_toolchain_task,
+            _receive_persistence_acks: Self::receive_persistence_acks(cx),
+        }
+    }
+
+    fn watch_active_toolchain(
+        project: &Entity<Project>,
+        cx: &mut Context<Self>,
+    ) -> Vec<Subscription> {
+        let active_entry_subscription =
+            cx.subscribe(project, |this, project, event: &project::Event, cx| {
+                if let project::Event::ActiveEntryChanged(_) = event {
+                    this._active_toolchain_task = Self::spawn_active_toolchain_task(project, cx);
+                }
+            });
+        let mut subscriptions = vec![active_entry_subscription];
+        if let Some(toolchain_store) = project.read(cx).toolchain_store() {
+            subscriptions.push(cx.subscribe(
+                &toolchain_store,
+                |this, _, _: &ToolchainStoreEvent, cx| {
+                    this._active_toolchain_task =
+                        Self::spawn_active_toolchain_task(this.project.clone(), cx);
+                },
+            ));
+        }
+        subscriptions
+    }
+
+    /// Resolves the active toolchain, and snapshots it with the project's environment for the
+    /// session, so that its stored conversations carry what they ran against.
+    fn spawn_active_toolchain_task(project: Entity<Project>, cx: &mut Context<Self>) -> Task<()> {
+        cx.spawn(async move |this, cx| {
+            let toolchain = Self::resolve_active_toolchain(project.clone(), cx).await;
+            let environment = Self::resolve_project_environment(project, cx).await;
+            this.update(cx, |this, cx| {
+                let session_env = SessionEnvironment::new(
+                    this.id.to_string(),
+                    toolchain.clone(),
+                    &environment,
+                    Some(paths::home_dir().as_path()),
+                );
+                save_session_env(&this.session_id, session_env, cx);
+                this.active_toolchain = toolchain;
+            })
+            .ok();
+        })
+    }
+
+    /// The environment of the project's first worktree, as its shell (e.g. with direnv) sets it.
+    async fn resolve_project_environment(
+        project: Entity<Project>,
+        cx: &mut AsyncApp,
+    ) -> HashMap<String, String> {
+        let environment = project
+            .update(cx, |project, cx| {
+                let worktree = project.visible_worktrees(cx).next()?;
+                let abs_path = worktree.read(cx).abs_path();
+                Some(project.directory_environment(abs_path, cx))
+            })
+            .ok()
+            .flatten();
+        match environment {
+            Some(environment) => environment.await.unwrap_or_default(),
+            None => HashMap::default(),
         }
     }
 
+    async fn resolve_active_toolchain(
+        project: Entity<Project>,
+        cx: &mut AsyncApp,
+    ) -> Option<RequestToolchain> {
+        let (project_path, languages, buffer_language) = project
+            .read_with(cx, |project, cx| {
+                let project_path = project.path_for_entry(project.active_entry()?, cx)?;
+                let buffer_language = project
+                    .get_open_buffer(&project_path, cx)
+                    .and_then(|buffer| Some(buffer.read(cx).language()?.name()));
+                Some((project_path, project.languages().clone(), buffer_language))
+            })
+            .ok()??;
+        let language_name = match buffer_language {
+            Some(language_name) => language_name,
+            None => languages
+                .language_for_file_path(&project_path.path)
+                .await
+                .ok()?
+                .name(),
+        };
+        let directory = ProjectPath {
+            worktree_id: project_path.worktree_id,
+            path: project_path
+                .path
+                .parent()
+                .map_or_else(|| project_path.path.clone(), Arc::from),
+        };
+        let toolchain = project
+            .read_with(cx, |project, cx| {
+                project.active_toolchain(directory, language_name, cx)
+            })
+            .ok()?
+            .await?;
+        Some(RequestToolchain {
+            language: toolchain.language_name.to_string(),
+            version: toolchain_version(&toolchain.name),
+            name: toolchain.name.to_string(),
+            path: toolchain.path.to_string(),
+        })
+    }
+
     pub fn set_request_callback(
         &mut self,
         callback: impl 'static
@@ -589,6 +754,88 @@ impl Thread {
         &self.id
     }
 
+    /// Follows the acknowledgments of the message store's writes of this session's messages.
+    fn receive_persistence_acks(cx: &mut Context<Self>) -> Task<()> {
+        let mut acks = subscribe_persistence_acks(cx);
+        cx.spawn(async move |this, cx| {
+            while let Some(ack) = acks.next().await {
+                let received = this.update(cx, |this, cx| this.receive_persistence_ack(ack, cx));
+                if received.is_err() {
+                    break;
+                }
+            }
+        })
+    }
+
+    fn receive_persistence_ack(&mut self, ack: PersistenceAck, cx: &mut Context<Self>) {
+        if ack.thread_id != self.session_id {
+            return;
+        }
+        let Ok(id) = ack.message_id.parse() else {
+            return;
+        };
+        self.persistence_acks
+            .insert((MessageId(id), ack.part), ack.status);
+        cx.notify();
+    }
+
+    /// The message an assistant message was the completion of the request for: the one before
+    /// it.
+    fn answered_message(&self, id: MessageId) -> Option<MessageId> {
+        let index = self
+            .messages
+            .binary_search_by(|message| message.id.cmp(&id))
+            .ok()?;
+        (self.messages[index].role == Role::Assistant && index > 0)
+            .then(|| self.messages[index - 1].id)
+    }
+
+    /// Whether the message is stored: the request sent for it, and, for an assistant message,
+    /// the completion it was streamed in. The worst status of the two is returned, and `None`
+    /// until the store has acknowledged either.
+    pub fn message_persistence(&self, id: MessageId) -> Option<PersistenceStatus> {
+        let request = self.persistence_acks.get(&(id, PersistedPart::Request));
+        let completion = self.answered_message(id).and_then(|answered| {
+            self.persistence_acks
+                .get(&(answered, PersistedPart::Completion))
+        });
+        [request, completion]
+            .into_iter()
+            .flatten()
+            .max_by_key(|status| match status {
+                PersistenceStatus::Persisted => 0,
+                PersistenceStatus::Pending => 1,
+                PersistenceStatus::Failed(_) => 2,
+            })
+            .cloned()
+    }
+
+    /// Writes what failed to be stored of the message, and of the completion it was streamed
+    /// in, again.
+    pub fn retry_persistence(&self, id: MessageId, cx: &mut Context<Self>) {
+        let handler = cx
+            .has_global::<MessageHandlerRegistry>()
+            .then(|| get_message_handler(cx))
+            .flatten();
+        let Some(handler) = handler else {
+            return;
+        };
+        let thread_id = self.session_id.clone();
+        let message_ids = [Some(id), self.answered_message(id)]
+            .into_iter()
+            .flatten()
+            .map(|id| id.0.to_string())
+            .collect::<Vec<_>>();
+        cx.background_spawn(async move {
+            for message_id in message_ids {
+                if let Err(error) = handler.retry_persistence(&thread_id, &message_id).await {
+                    log::error!("Failed to store message {message_id} again: {error:#}");
+                }
+            }
+        })
+        .detach();
+    }
+
     pub fn session_id(&self) -> &str {
         &self.session_id
     }
@@ -629,6 +876,73 @@ impl Thread {
         cx.notify();
     }
 
+    /// The models this thread has been sent to, in order, with what each of them was used for.
+    pub fn model_segments(&self) -> &[ModelSegment] {
+        &self.model_segments
+    }
+
+    /// Counts a request to `model`, starting a new segment if the thread was using another model.
+    fn record_model_request(&mut self, model: &Arc<dyn LanguageModel>) {
+        let model = SerializedLanguageModel {
+            provider: model.provider_id().0.to_string(),
+            model: model.id().0.to_string(),
+        };
+        let previous = self
+            .model_segments
+            .last()
+            .map(|segment| segment.model.clone());
+        if previous.as_ref() != Some(&model) {
+            if let Some(previous) = previous {
+                telemetry::event!(
+                    "Agent Thread Model Transition",
+                    thread_id = self.id().to_string(),
+                    segment = self.model_segments.len(),
+                    from_provider = previous.provider,
+                    from_model = previous.model,
+                    to_provider = model.provider.clone(),
+                    to_model = model.model.clone()
+                );
+            }
+            self.model_segments.push(ModelSegment {
+                model,
+                first_message_id: self.messages.last().map(|message| message.id),
+                started_at: Utc::now(),
+                request_count: 0,
+                token_usage: TokenUsage::default(),
+            });
+        }
+        if let Some(segment) = self.model_segments.last_mut() {
+            segment.request_count += 1;
+        }
+    }
+
+    /// Stores the profile, tools and rules the request is made with, if they changed since the
+    /// session's last request.
+    fn record_instructions(&mut self, request: &LanguageModelRequest, cx: &mut Context<Self>) {
+        let settings = AgentSettings::get_global(cx);
+        let profile_id = settings.default_profile.clone();
+        let profile_name = settings.profiles.get(&profile_id).map_or_else(
+            || profile_id.to_string(),
+            |profile| profile.name.to_string(),
+        );
+        let rules = self
+            .project_context
+            .borrow()
+            .as_ref()
+            .map(rules_snapshots)
+            .unwrap_or_default();
+        let instructions = ThreadInstructions::new(
+            profile_id.as_str(),
+            profile_name,
+            request.tools.iter().map(|tool| tool.name.clone()),
+            rules,
+        );
+        if self.recorded_instructions.as_ref() != Some(&instructions) {
+            save_thread_instructions(&self.session_id, instructions.clone(), cx);
+            self.recorded_instructions = Some(instructions);
+        }
+    }
+
     pub fn summary(&self) -> &ThreadSummary {
         &self.summary
     }
@@ -673,6 +987,22 @@ impl Thread {
         self.messages.iter()
     }
 
+    /// Restores images attached to a message, which aren't part of the serialized thread, so they
+    /// last only until the thread is reloaded.
+    pub(crate) fn attach_images(
+        &mut self,
+        id: MessageId,
+        images: impl IntoIterator<Item = LanguageModelImage>,
+    ) {
+        let Ok(index) = self
+            .messages
+            .binary_search_by(|message| message.id.cmp(&id))
+        else {
+            return;
+        };
+        self.messages[index].loaded_context.images.extend(images);
+    }
+
     pub fn is_generating(&self) -> bool {
         !self.pending_completions.is_empty() || !self.all_tools_finished()
     }
@@ -872,6 +1202,74 @@ impl Thread {
         self.tool_use_limit_reached
     }
 
+    pub fn author(&self) -> Option<&MessageAuthor> {
+        self.author.as_ref()
+    }
+
+    /// Names the project by its root directories, e.g. `zed` or `zed, zed-extensions`.
+    fn project_name(&self, cx: &App) -> Option<String> {
+        let root_names = self
+            .project
+            .read(cx)
+            .visible_worktrees(cx)
+            .map(|worktree| worktree.read(cx).root_name().to_string())
+            .collect::<Vec<_>>();
+        (!root_names.is_empty()).then(|| root_names.join(", "))
+    }
+
+    /// Stores the thread's messages through the server of a remote project, if its connection is
+    /// set to, e.g. when the team's message store can only be reached from there.
+    fn route_persistence(&self, cx: &mut App) {
+        let Some(ssh_client) = self.project.read(cx).ssh_client() else {
+            return;
+        };
+        let Some(project_name) = self.project_name(cx) else {
+            return;
+        };
+        let ssh_client = ssh_client.read(cx);
+        let persistence = ssh_client
+            .connection_options()
+            .persist_messages_via_host
+            .then(|| {
+                Arc::new(ProtoRemotePersistence::new(ssh_client.proto_client()))
+                    as Arc<dyn RemotePersistence>
+            });
+        set_remote_persistence(project_name, persistence, cx);
+    }
+
+    /// Who in a shared project is making the request, so that the store can tell the host's
+    /// completions from its guests'.
+    fn request_origin(&self, cx: &App) -> Option<RequestOrigin> {
+        let project = self.project.read(cx);
+        if !project.is_shared() {
+            return None;
+        }
+        Some(RequestOrigin {
+            peer_id: project.client().peer_id()?.to_string(),
+            is_host: !project.is_via_collab(),
+        })
+    }
+
+    /// The buffer the user is looking at, taken to be the one the request is mostly about.
+    fn request_buffer(&self, cx: &App) -> Option<RequestBuffer> {
+        let project = self.project.read(cx);
+        let project_path = project.path_for_entry(project.active_entry()?, cx)?;
+        let language = project
+            .get_open_buffer(&project_path, cx)
+            .and_then(|buffer| Some(buffer.read(cx).language()?.name().to_string()));
+        Some(RequestBuffer {
+            language,
+            area: ProjectArea::for_path(&project_path.path),
+        })
+    }
+
+    /// The branch checked out in the project's active repository, if it is on one.
+    fn git_branch(&self, cx: &App) -> Option<String> {
+        let repository = self.project.read(cx).active_repository(cx)?;
+        let branch = repository.read(cx).branch.as_ref()?;
+        Some(branch.name().to_string())
+    }
+
     /// Returns whether all of the tool uses have finished running.
     pub fn all_tools_finished(&self) -> bool {
         // If the only pending tool uses left are the ones with errors, then
@@ -954,6 +1352,14 @@ impl Thread {
         creases: Vec<MessageCrease>,
         cx: &mut Context<Self>,
     ) -> MessageId {
+        let referenced_paths = loaded_context
+            .referenced_buffers
+            .iter()
+            .filter_map(|buffer| {
+                let path = buffer.read(cx).file()?.full_path(cx);
+                Some(path.to_string_lossy().into_owned())
+            })
+            .collect::<Vec<_>>();
         if !loaded_context.referenced_buffers.is_empty() {
             self.action_log.update(cx, |log, cx| {
                 for buffer in loaded_context.referenced_buffers {
@@ -970,6 +1376,7 @@ impl Thread {
             false,
             cx,
         );
+        self.snapshot_referenced_files(referenced_paths, cx);
 
         if let Some(git_checkpoint) = git_checkpoint {
             self.pending_checkpoint = Some(ThreadCheckpoint {
@@ -1186,6 +1593,8 @@ impl Thread {
                     }),
                 completion_mode: Some(this.completion_mode),
                 tool_use_limit_reached: this.tool_use_limit_reached,
+                author: this.author.clone(),
+                model_segments: this.model_segments.clone(),
             })
         })
     }
@@ -1209,11 +1618,89 @@ impl Thread {
             return;
         }
 
+        self.route_persistence(cx);
+        let request = self.to_completion_request(model.clone(), intent, cx);
+
+        if self.run_lock.is_none() {
+            match lock_thread(&request, cx) {
+                Ok(lock) => self.run_lock = Some(lock),
+                Err(busy) => {
+                    cx.emit(ThreadEvent::ShowError(ThreadError::Message {
+                        header: "Thread is busy".into(),
+                        message: busy.to_string().into(),
+                    }));
+                    return;
+                }
+            }
+        }
+
         self.remaining_turns -= 1;
 
-        let request = self.to_completion_request(model.clone(), intent, cx);
+        let handler = cx
+            .has_global::<MessageHandlerRegistry>()
+            .then(|| get_message_handler(cx))
+            .flatten();
+        let Some(handler) = handler else {
+            self.stream_completion(request, model, window, cx);
+            return;
+        };
 
-        self.stream_completion(request, model, window, cx);
+        // The request is only sent once the thread is locked in the store, so that nothing is
+        // sent or stored while a teammate's editor is running the agent in it.
+        let thread_id = self.id.to_string();
+        let session_id = self.session_id.clone();
+        cx.spawn(async move |thread, cx| {
+            let holder = handler
+                .acquire_store_lock(&thread_id, &session_id)
+                .await
+                .unwrap_or_else(|error| {
+                    log::error!("Failed to lock thread {thread_id} in the store: {error:#}");
+                    None
+                });
+            thread
+                .update(cx, |thread, cx| {
+                    let Some(holder) = holder else {
+                        thread.stream_completion(request, model, window, cx);
+                        return;
+                    };
+                    if !thread.is_generating() {
+                        thread.release_run_lock(cx);
+                    }
+                    cx.emit(ThreadEvent::ShowError(ThreadError::Message {
+                        header: "Thread is locked".into(),
+                        message: format!(
+                            "{} is running the agent in this thread. Take it over from the \
+                            history to continue it here.",
+                            holder.display_name()
+                        )
+                        .into(),
+                    }));
+                })
+                .ok();
+        })
+        .detach();
+    }
+
+    /// Releases the thread's locks once the agent has stopped running in it.
+    fn release_run_lock(&mut self, cx: &mut Context<Self>) {
+        if self.run_lock.take().is_none() {
+            return;
+        }
+        let handler = cx
+            .has_global::<MessageHandlerRegistry>()
+            .then(|| get_message_handler(cx))
+            .flatten();
+        let Some(handler) = handler else {
+            return;
+        };
+        let thread_id = self.id.to_string();
+        let session_id = self.session_id.clone();
+        cx.background_spawn(async move {
+            if let Err(error) = handler.release_store_lock(&thread_id, &session_id).await {
+                log::error!("Failed to unlock thread {thread_id} in the store: {error:#}");
+            }
+        })
+        .detach();
     }
 
     pub fn used_tools_since_last_user_message(&self) -> bool {
@@ -1245,6 +1732,16 @@ impl Thread {
             tool_choice: None,
             stop: Vec::new(),
             temperature: AgentSettings::temperature_for_model(&model, cx),
+            toolchain: self.active_toolchain.clone(),
+            project: self.project_name(cx),
+            prompt_template: None,
+            origin: self.request_origin(cx),
+            buffer: self.request_buffer(cx),
+            git_branch: self.git_branch(cx),
+            schedule_id: None,
+            fan_out_group: None,
+            message_id: self.messages.last().map(|message| message.id.0.to_string()),
+            source: Some(RequestSource::AgentPanel),
         };
 
         let available_tools = self.available_tools(cx, model.clone());
@@ -1257,11 +1754,19 @@ impl Thread {
             available_tools: available_tool_names,
         };
 
+        let experiment = self.prompt_experiment(cx);
+        let template_id = experiment
+            .as_ref()
+            .map_or(ASSISTANT_SYSTEM_PROMPT_TEMPLATE, |assignment| {
+                assignment.variant.as_str()
+            });
+
         if let Some(project_context) = self.project_context.borrow().as_ref() {
-            match self
-                .prompt_builder
-                .generate_assistant_system_prompt(project_context, model_context)
-            {
+            match self.prompt_builder.generate_assistant_system_prompt_from(
+                template_id,
+                project_context,
+                model_context,
+            ) {
                 Err(err) => {
                     let message = format!("{err:?}").into();
                     log::error!("{message}");
@@ -1271,11 +1776,23 @@ impl Thread {
                     }));
                 }
                 Ok(system_prompt) => {
+                    let mut content = vec![MessageContent::Text(system_prompt)];
+                    if let Some(toolchain) = &self.active_toolchain {
+                        content.push(MessageContent::Text(toolchain.to_prompt()));
+                    }
                     request.messages.push(LanguageModelRequestMessage {
                         role: Role::System,
-                        content: vec![MessageContent::Text(system_prompt)],
+                        content,
                         cache: true,
                     });
+                    request.prompt_template =
+                        self.prompt_builder
+                            .template_source(template_id)
+                            .map(|source| RequestPromptTemplate {
+                                id: template_id.to_string(),
+                                source,
+                                experiment: experiment.clone(),
+                            });
                 }
             }
         } else {
@@ -1407,6 +1924,16 @@ impl Thread {
             tool_choice: None,
             stop: Vec::new(),
             temperature: AgentSettings::temperature_for_model(model, cx),
+            toolchain: None,
+            project: None,
+            prompt_template: None,
+            origin: None,
+            buffer: None,
+            git_branch: None,
+            schedule_id: None,
+            fan_out_group: None,
+            message_id: None,
+            source: Some(RequestSource::AgentPanel),
         };
 
         for message in &self.messages {
@@ -1490,6 +2017,8 @@ impl Thread {
         cx: &mut Context<Self>,
     ) {
         self.tool_use_limit_reached = false;
+        self.record_model_request(&model);
+        self.record_instructions(&request, cx);
 
         let pending_completion_id = post_inc(&mut self.completion_count);
         let mut request_callback_parameters = if self.request_callback.is_some() {
@@ -1507,7 +2036,8 @@ impl Thread {
         self.last_received_chunk_at = Some(Instant::now());
 
         let task = cx.spawn(async move |thread, cx| {
-            let stream_completion_future = model.stream_completion(request, &cx);
+            let stream_completion_future =
+                stream_resumable_completion(model.clone(), request, &cx);
             let initial_token_usage =
                 thread.read_with(cx, |thread, _cx| thread.cumulative_token_usage);
             let stream_completion = async {
@@ -1570,6 +2100,10 @@ impl Thread {
                                 thread.cumulative_token_usage = thread.cumulative_token_usage
                                     + token_usage
                                     - current_token_usage;
+                                if let Some(segment) = thread.model_segments.last_mut() {
+                                    segment.token_usage =
+                                        segment.token_usage + token_usage - current_token_usage;
+                                }
                                 current_token_usage = token_usage;
                             }
                             LanguageModelCompletionEvent::Text(chunk) => {
@@ -1827,6 +2361,9 @@ impl Thread {
                     }
 
                     cx.emit(ThreadEvent::Stopped(result.map_err(Arc::new)));
+                    if !thread.is_generating() {
+                        thread.release_run_lock(cx);
+                    }
 
                     if let Some((request_callback, (request, response_events))) = thread
                         .request_callback
@@ -2220,6 +2757,9 @@ impl Thread {
         cx: &mut Context<Thread>,
     ) -> Task<()> {
         let tool_name: Arc<str> = tool.name().into();
+        let referenced_paths = tool_input_paths(&input)
+            .map(str::to_string)
+            .collect::<Vec<_>>();
 
         let tool_result = if self.tools.read(cx).is_disabled(&tool.source(), &tool_name) {
             Task::ready(Err(anyhow!("tool is disabled: {tool_name}"))).into()
@@ -2253,6 +2793,7 @@ impl Thread {
                             output,
                             thread.configured_model.as_ref(),
                         );
+                        thread.snapshot_referenced_files(referenced_paths, cx);
                         thread.tool_finished(tool_use_id, pending_tool_use, false, window, cx);
                     })
                     .ok();
@@ -2260,6 +2801,26 @@ impl Thread {
         })
     }
 
+    /// Records the files a tool call or a message's context referenced as they are after it, to
+    /// tell later whether the thread still reflects them.
+    fn snapshot_referenced_files(&self, paths: Vec<String>, cx: &mut Context<Self>) {
+        if paths.is_empty() {
+            return;
+        }
+        let hashes = hash_project_files(&self.project, paths, cx);
+        let thread_id = self.id.to_string();
+        cx.spawn(async move |_, cx| {
+            let snapshots = hashes
+                .await
+                .into_iter()
+                .map(|(path, hash)| FileSnapshot { path, hash })
+                .collect();
+            cx.update(|cx| save_file_snapshots(&thread_id, snapshots, cx))
+                .ok();
+        })
+        .detach();
+    }
+
     fn tool_finished(
         &mut self,
         tool_use_id: LanguageModelToolUseId,
@@ -2304,6 +2865,10 @@ impl Thread {
             );
         }
 
+        if !self.is_generating() {
+            self.release_run_lock(cx);
+        }
+
         if canceled {
             cx.emit(ThreadEvent::CompletionCanceled);
 
@@ -2360,6 +2925,11 @@ impl Thread {
             .collect();
 
         self.message_feedback.insert(message_id, feedback);
+        self.record_experiment_outcome(
+            ExperimentMetric::Rating,
+            feedback == ThreadFeedback::Positive,
+            cx,
+        );
 
         cx.notify();
 
@@ -2414,6 +2984,11 @@ impl Thread {
             let thread_id = self.id().clone();
             let client = self.project.read(cx).client();
             self.feedback = Some(feedback);
+            self.record_experiment_outcome(
+                ExperimentMetric::Rating,
+                feedback == ThreadFeedback::Positive,
+                cx,
+            );
             cx.notify();
 
             cx.background_spawn(async move {
@@ -2648,11 +3223,13 @@ impl Thread {
         self.action_log.update(cx, |action_log, cx| {
             action_log.keep_edits_in_range(buffer, buffer_range, cx)
         });
+        self.record_experiment_outcome(ExperimentMetric::Acceptance, true, cx);
     }
 
     pub fn keep_all_edits(&mut self, cx: &mut Context<Self>) {
         self.action_log
             .update(cx, |action_log, cx| action_log.keep_all_edits(cx));
+        self.record_experiment_outcome(ExperimentMetric::Acceptance, true, cx);
     }
 
     pub fn reject_edits_in_ranges(
@@ -2661,11 +3238,43 @@ impl Thread {
         buffer_ranges: Vec<Range<language::Anchor>>,
         cx: &mut Context<Self>,
     ) -> Task<Result<()>> {
+        self.record_experiment_outcome(ExperimentMetric::Acceptance, false, cx);
         self.action_log.update(cx, |action_log, cx| {
             action_log.reject_edits_in_ranges(buffer, buffer_ranges, cx)
         })
     }
 
+    /// The variant of the system prompt the thread's requests are sent with, if an experiment is
+    /// running on it. A variant whose template isn't registered falls back to the template it
+    /// stands in for, which is then the variant used.
+    fn prompt_experiment(&self, cx: &App) -> Option<PromptExperimentAssignment> {
+        let mut assignment =
+            prompt_experiment_variant(ASSISTANT_SYSTEM_PROMPT_TEMPLATE, &self.id.to_string(), cx)?;
+        if self
+            .prompt_builder
+            .template_source(&assignment.variant)
+            .is_none()
+        {
+            assignment.variant = ASSISTANT_SYSTEM_PROMPT_TEMPLATE.to_string();
+        }
+        Some(assignment)
+    }
+
+    /// Scores the prompt variant this thread's requests were sent with, if an experiment is
+    /// running.
+    fn record_experiment_outcome(&self, metric: ExperimentMetric, success: bool, cx: &mut App) {
+        let Some(assignment) = self.prompt_experiment(cx) else {
+            return;
+        };
+        record_prompt_experiment_outcome(
+            assignment,
+            &self.id.to_string(),
+            metric,
+            if success { 1.0 } else { 0.0 },
+            cx,
+        );
+    }
+
     pub fn action_log(&self) -> &Entity<ActionLog> {
         &self.action_log
     }
@@ -2876,6 +3485,38 @@ struct PendingCompletion {
     _task: Task<()>,
 }
 
+/// Extracts a version like `3.11.4` from a toolchain label such as `Python 3.11.4 (.venv)`.
+fn toolchain_version(name: &str) -> Option<String> {
+    name.split(|c: char| c.is_whitespace() || c == '(' || c == ')')
+        .map(|word| word.trim_start_matches('v'))
+        .find(|word| word.contains('.') && word.chars().all(|c| c.is_ascii_digit() || c == '.'))
+        .map(str::to_string)
+}
+
+/// The rules files of the project's worktrees, and the default rules of the rules library, that
+/// the system prompt includes.
+fn rules_snapshots(context: &ProjectContext) -> Vec<RulesSnapshot> {
+    let files = context.worktrees.iter().filter_map(|worktree| {
+        let rules_file = worktree.rules_file.as_ref()?;
+        Some(RulesSnapshot::ProjectFile {
+            path: Path::new(&worktree.root_name)
+                .join(&rules_file.path_in_worktree)
+                .to_string_lossy()
+                .into_owned(),
+            text: rules_file.text.clone(),
+        })
+    });
+    let user_rules = context
+        .user_rules
+        .iter()
+        .map(|rules| RulesSnapshot::UserRule {
+            id: rules.uuid.0.to_string(),
+            title: rules.title.clone(),
+            text: rules.contents.clone(),
+        });
+    files.chain(user_rules).collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -2894,6 +3535,19 @@ mod tests {
     use util::path;
     use workspace::Workspace;
 
+    #[test]
+    fn test_toolchain_version() {
+        assert_eq!(
+            toolchain_version("Python 3.11.4 (.venv)"),
+            Some("3.11.4".to_string())
+        );
+        assert_eq!(
+            toolchain_version("node v18.16.0"),
+            Some("18.16.0".to_string())
+        );
+        assert_eq!(toolchain_version("Global Python"), None);
+    }
+
     #[gpui::test]
     async fn test_message_with_context(cx: &mut TestAppContext) {
         init_test_settings(cx);
@@ -0,0 +1,287 @@
+//! Portable `.zedthread` bundles, for handing someone else the exact conversation that produced a
+//! change. A bundle holds the serialized thread, some metadata about where it came from and the
+//! images attached to its messages, optionally encrypted with a passphrase.
+//!
+//! Stored threads don't keep the images attached to their messages, so an imported thread's
+//! images are only restored to the thread that is opened on import, and are gone once it is
+//! reloaded from the thread store, e.g. after a restart. Keep the bundle to get them back.
+
+use anyhow::{Context as _, Result, anyhow};
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use chacha20poly1305::{
+    ChaCha20Poly1305, Key, KeyInit as _, Nonce,
+    aead::{Aead as _, Payload},
+};
+use chrono::{DateTime, Utc};
+use gpui::{App, Entity, SharedString, Task};
+use language_model::LanguageModelImage;
+use rand::RngCore as _;
+use serde::{Deserialize, Serialize};
+
+use crate::thread::{MessageId, Thread};
+use crate::thread_store::SerializedThread;
+
+pub const THREAD_BUNDLE_EXTENSION: &str = "zedthread";
+
+const FORMAT: &str = "zedthread";
+const FORMAT_VERSION: u32 = 1;
+const KDF_ROUNDS: u32 = 600_000;
+/// Keeps a crafted bundle from stalling the import.
+const MAX_KDF_ROUNDS: u32 = 10_000_000;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+/// A conversation as it is written to a bundle.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ThreadBundle {
+    pub metadata: ThreadBundleMetadata,
+    /// The thread as it is stored in the thread database, so that bundles from older versions
+    /// can be upgraded like stored threads are.
+    pub thread: serde_json::Value,
+    #[serde(default)]
+    pub attachments: Vec<ThreadBundleAttachment>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThreadBundleMetadata {
+    /// The id the thread had where it was exported. Imported threads get a new one.
+    pub thread_id: String,
+    pub summary: SharedString,
+    pub exported_at: DateTime<Utc>,
+    /// Names of the project's root directories, to tell the recipient what the thread was about.
+    #[serde(default)]
+    pub worktrees: Vec<String>,
+}
+
+/// Content attached to a message that isn't part of its serialized form.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ThreadBundleAttachment {
+    Image {
+        message_id: MessageId,
+        image: LanguageModelImage,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+struct Envelope {
+    format: String,
+    version: u32,
+    #[serde(flatten)]
+    body: EnvelopeBody,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "encoding", rename_all = "snake_case")]
+enum EnvelopeBody {
+    Plain {
+        bundle: ThreadBundle,
+    },
+    /// The bundle's JSON, sealed with ChaCha20-Poly1305 under a key derived from the passphrase
+    /// with PBKDF2-HMAC-SHA256.
+    Encrypted {
+        kdf_rounds: u32,
+        salt: String,
+        nonce: String,
+        ciphertext: String,
+    },
+}
+
+impl ThreadBundle {
+    /// Bundles the thread as it is now.
+    pub fn from_thread(thread: &Entity<Thread>, cx: &mut App) -> Task<Result<Self>> {
+        let thread_id = thread.read(cx).id().to_string();
+        let worktrees = thread
+            .read(cx)
+            .project()
+            .read(cx)
+            .visible_worktrees(cx)
+            .map(|worktree| worktree.read(cx).root_name().to_string())
+            .collect::<Vec<_>>();
+        let attachments =
+            thread
+                .read(cx)
+                .messages()
+                .flat_map(|message| {
+                    message.loaded_context.images.iter().map(|image| {
+                        ThreadBundleAttachment::Image {
+                            message_id: message.id,
+                            image: image.clone(),
+                        }
+                    })
+                })
+                .collect::<Vec<_>>();
+        let serialized = thread.update(cx, |thread, cx| thread.serialize(cx));
+        cx.background_spawn(async move {
+            let serialized = serialized.await?;
+            Ok(Self {
+                metadata: ThreadBundleMetadata {
+                    thread_id,
+                    summary: serialized.summary.clone(),
+                    exported_at: Utc::now(),
+                    worktrees,
+                },
+                thread: serde_json::to_value(&serialized)?,
+                attachments,
+            })
+        })
+    }
+
+    /// The bundled thread, upgraded to the current serialization format.
+    pub fn serialized_thread(&self) -> Result<SerializedThread> {
+        SerializedThread::from_json(&serde_json::to_vec(&self.thread)?)
+    }
+
+    /// Writes the bundle, encrypting it if a passphrase is given.
+    pub fn encode(self, passphrase: Option<&str>) -> Result<String> {
+        self.encode_with_rounds(passphrase, KDF_ROUNDS)
+    }
+
+    fn encode_with_rounds(self, passphrase: Option<&str>, kdf_rounds: u32) -> Result<String> {
+        let body = match passphrase {
+            None => EnvelopeBody::Plain { bundle: self },
+            Some(passphrase) => {
+                let mut salt = [0; SALT_LEN];
+                let mut nonce = [0; NONCE_LEN];
+                rand::thread_rng().fill_bytes(&mut salt);
+                rand::thread_rng().fill_bytes(&mut nonce);
+                let cipher = cipher(passphrase, &salt, kdf_rounds);
+                let plaintext = serde_json::to_vec(&self)?;
+                let ciphertext = cipher
+                    .encrypt(
+                        Nonce::from_slice(&nonce),
+                        Payload {
+                            msg: &plaintext,
+                            aad: FORMAT.as_bytes(),
+                        },
+                    )
+                    .map_err(|_| anyhow!("failed to encrypt thread bundle"))?;
+                EnvelopeBody::Encrypted {
+                    kdf_rounds,
+                    salt: BASE64.encode(salt),
+                    nonce: BASE64.encode(nonce),
+                    ciphertext: BASE64.encode(ciphertext),
+                }
+            }
+        };
+        Ok(serde_json::to_string_pretty(&Envelope {
+            format: FORMAT.to_string(),
+            version: FORMAT_VERSION,
+            body,
+        })?)
+    }
+
+    /// Reads a bundle written by [`ThreadBundle::encode`]. Encrypted bundles need the passphrase
+    /// they were written with.
+    pub fn decode(contents: &str, passphrase: Option<&str>) -> Result<Self> {
+        let envelope = read_envelope(contents)?;
+        match envelope.body {
+            EnvelopeBody::Plain { bundle } => Ok(bundle),
+            EnvelopeBody::Encrypted {
+                kdf_rounds,
+                salt,
+                nonce,
+                ciphertext,
+            } => {
+                let passphrase = passphrase.context("this thread bundle is encrypted")?;
+                anyhow::ensure!(
+                    kdf_rounds <= MAX_KDF_ROUNDS,
+                    "the thread bundle asks for too many key derivation rounds"
+                );
+                let salt = BASE64.decode(salt).context("invalid salt")?;
+                let nonce = BASE64.decode(nonce).context("invalid nonce")?;
+                anyhow::ensure!(nonce.len() == NONCE_LEN, "invalid nonce");
+                let ciphertext = BASE64.decode(ciphertext).context("invalid ciphertext")?;
+                let plaintext = cipher(passphrase, &salt, kdf_rounds)
+                    .decrypt(
+                        Nonce::from_slice(&nonce),
+                        Payload {
+                            msg: &ciphertext,
+                            aad: FORMAT.as_bytes(),
+                        },
+                    )
+                    .map_err(|_| anyhow!("wrong passphrase, or the bundle is corrupted"))?;
+                Ok(serde_json::from_slice(&plaintext)?)
+            }
+        }
+    }
+
+    /// Whether reading the bundle needs a passphrase.
+    pub fn is_encrypted(contents: &str) -> Result<bool> {
+        Ok(matches!(
+            read_envelope(contents)?.body,
+            EnvelopeBody::Encrypted { .. }
+        ))
+    }
+}
+
+fn read_envelope(contents: &str) -> Result<Envelope> {
+    let envelope: Envelope = serde_json::from_str(contents).context("not a thread bundle")?;
+    anyhow::ensure!(envelope.format == FORMAT, "not a thread bundle");
+    anyhow::ensure!(
+        envelope.version <= FORMAT_VERSION,
+        "the thread bundle was written by a newer version of Zed"
+    );
+    Ok(envelope)
+}
+
+fn cipher(passphrase: &str, salt: &[u8], rounds: u32) -> ChaCha20Poly1305 {
+    let mut key = [0; 32];
+    pbkdf2::pbkdf2_hmac::<sha2::Sha256>(passphrase.as_bytes(), salt, rounds, &mut key);
+    ChaCha20Poly1305::new(Key::from_slice(&key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn bundle() -> ThreadBundle {
+        ThreadBundle {
+            metadata: ThreadBundleMetadata {
+                thread_id: "thread-1".into(),
+                summary: "Fix the parser".into(),
+                exported_at: Utc::now(),
+                worktrees: vec!["zed".into()],
+            },
+            thread: json!({
+                "version": SerializedThread::VERSION,
+                "summary": "Fix the parser",
+                "updated_at": "2025-06-01T12:00:00Z",
+                "messages": [{
+                    "id": 0,
+                    "role": "user",
+                    "segments": [{ "type": "text", "text": "Why does this panic?" }],
+                }],
+            }),
+            attachments: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_plain_bundle_round_trips() {
+        let contents = bundle().encode(None).unwrap();
+        assert!(!ThreadBundle::is_encrypted(&contents).unwrap());
+        let decoded = ThreadBundle::decode(&contents, None).unwrap();
+        assert_eq!(decoded.metadata.summary, "Fix the parser");
+        let thread = decoded.serialized_thread().unwrap();
+        assert_eq!(thread.messages.len(), 1);
+    }
+
+    #[test]
+    fn test_encrypted_bundle_needs_the_passphrase() {
+        let contents = bundle().encode_with_rounds(Some("hunter2"), 1_000).unwrap();
+        assert!(ThreadBundle::is_encrypted(&contents).unwrap());
+        assert!(!contents.contains("Fix the parser"));
+        assert!(ThreadBundle::decode(&contents, None).is_err());
+        assert!(ThreadBundle::decode(&contents, Some("hunter3")).is_err());
+        let decoded = ThreadBundle::decode(&contents, Some("hunter2")).unwrap();
+        assert_eq!(decoded.metadata.thread_id, "thread-1");
+    }
+
+    #[test]
+    fn test_rejects_other_files() {
+        assert!(ThreadBundle::decode("{}", None).is_err());
+        assert!(ThreadBundle::decode(r#"{"format":"other","version":1}"#, None).is_err());
+    }
+}
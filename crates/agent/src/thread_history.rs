@@ -1,14 +1,21 @@
 use std::fmt::Display;
 use std::ops::Range;
+use std::rc::Rc;
 use std::sync::Arc;
 
 use chrono::{Datelike as _, Local, NaiveDate, TimeDelta};
+use collections::HashMap;
 use editor::{Editor, EditorEvent};
 use fuzzy::{StringMatch, StringMatchCandidate};
 use gpui::{
-    App, ClickEvent, Empty, Entity, FocusHandle, Focusable, ScrollStrategy, Stateful, Task,
-    UniformListScrollHandle, WeakEntity, Window, uniform_list,
+    App, BackgroundExecutor, ClickEvent, Empty, Entity, FocusHandle, Focusable, PromptLevel,
+    ScrollStrategy, Stateful, Task, UniformListScrollHandle, WeakEntity, Window, uniform_list,
 };
+use language_model::message_handler::{
+    FileChange, FileChangeKind, LockOverride, MessageAuthor, MessageHandlerRegistry,
+    StoreLockHolder, get_message_handler, message_author,
+};
+use project::Project;
 use time::{OffsetDateTime, UtcOffset};
 use ui::{
     HighlightedLabel, IconButtonShape, ListItem, ListItemSpacing, Scrollbar, ScrollbarState,
@@ -16,12 +23,19 @@ use ui::{
 };
 use util::ResultExt;
 
+use crate::file_drift::changed_thread_files;
 use crate::history_store::{HistoryEntry, HistoryStore};
+use crate::thread::ThreadId;
 use crate::{AgentPanel, RemoveSelectedThread};
 
+/// How many of the most recent threads are checked for files changed since, to bound how many
+/// files are read each time the history changes.
+const CHANGED_FILES_CHECKED_THREADS: usize = 50;
+
 pub struct ThreadHistory {
     agent_panel: WeakEntity<AgentPanel>,
     history_store: Entity<HistoryStore>,
+    project: Entity<Project>,
     scroll_handle: UniformListScrollHandle,
     selected_index: usize,
     hovered_index: Option<usize>,
@@ -33,6 +47,14 @@ pub struct ThreadHistory {
     // Maps entry indexes to list item indexes
     separated_item_indexes: Vec<u32>,
     _separated_items_task: Option<Task<()>>,
+    /// The files each thread's tool calls referenced that have changed since, for the threads
+    /// that have any.
+    changed_files: HashMap<ThreadId, Vec<FileChange>>,
+    _changed_files_task: Option<Task<()>>,
+    /// Who else holds the locks of the threads teammates sharing the store are running the agent
+    /// in.
+    lock_holders: HashMap<ThreadId, StoreLockHolder>,
+    _lock_holders_task: Option<Task<()>>,
     search_state: SearchState,
     scrollbar_visibility: bool,
     scrollbar_state: ScrollbarState,
@@ -72,12 +94,13 @@ impl ThreadHistory {
     pub(crate) fn new(
         agent_panel: WeakEntity<AgentPanel>,
         history_store: Entity<HistoryStore>,
+        project: Entity<Project>,
         window: &mut Window,
         cx: &mut Context<Self>,
     ) -> Self {
         let search_editor = cx.new(|cx| {
             let mut editor = Editor::single_line(window, cx);
-            editor.set_placeholder_text("Search threads...", cx);
+            editor.set_placeholder_text("Search threads, or author:name...", cx);
             editor
         });
 
@@ -99,6 +122,7 @@ impl ThreadHistory {
         let mut this = Self {
             agent_panel,
             history_store,
+            project,
             scroll_handle,
             selected_index: 0,
             hovered_index: None,
@@ -111,6 +135,10 @@ impl ThreadHistory {
             scrollbar_state,
             _subscriptions: vec![search_editor_subscription, history_store_subscription],
             _separated_items_task: None,
+            changed_files: HashMap::default(),
+            _changed_files_task: None,
+            lock_holders: HashMap::default(),
+            _lock_holders_task: None,
         };
         this.update_all_entries(cx);
         this
@@ -123,6 +151,8 @@ impl ThreadHistory {
             .into();
 
         self._separated_items_task.take();
+        self.check_changed_files(&new_entries, cx);
+        self.check_lock_holders(&new_entries, cx);
 
         let mut items = Vec::with_capacity(new_entries.len() + 1);
         let mut indexes = Vec::with_capacity(new_entries.len() + 1);
@@ -192,6 +222,134 @@ impl ThreadHistory {
         self._separated_items_task = Some(task);
     }
 
+    /// Finds the files the most recent threads referenced that have changed since, to point out
+    /// the threads that may no longer reflect the project.
+    fn check_changed_files(&mut self, entries: &[HistoryEntry], cx: &mut Context<Self>) {
+        let checks = entries
+            .iter()
+            .filter_map(|entry| match entry {
+                HistoryEntry::Thread(thread) => Some(thread.id.clone()),
+                HistoryEntry::Context(_) => None,
+            })
+            .take(CHANGED_FILES_CHECKED_THREADS)
+            .map(|thread_id| {
+                let changes = changed_thread_files(self.project.clone(), thread_id.to_string(), cx);
+                (thread_id, changes)
+            })
+            .collect::<Vec<_>>();
+        self._changed_files_task = Some(cx.spawn(async move |this, cx| {
+            let mut changed_files = HashMap::default();
+            for (thread_id, changes) in checks {
+                match changes.await {
+                    Ok(changes) => {
+                        if !changes.is_empty() {
+                            changed_files.insert(thread_id, changes);
+                        }
+                    }
+                    Err(error) => {
+                        // The others would most likely fail the same way.
+                        log::error!("Failed to check the files threads referenced: {error:#}");
+                        break;
+                    }
+                }
+            }
+            this.update(cx, |this, cx| {
+                this.changed_files = changed_files;
+                cx.notify();
+            })
+            .ok();
+        }));
+    }
+
+    /// Finds which of the threads another user's editor holds the lock of, in a store shared
+    /// with them.
+    fn check_lock_holders(&mut self, entries: &[HistoryEntry], cx: &mut Context<Self>) {
+        let handler = cx
+            .has_global::<MessageHandlerRegistry>()
+            .then(|| get_message_handler(cx))
+            .flatten();
+        let Some(handler) = handler else {
+            return;
+        };
+        let thread_ids = entries
+            .iter()
+            .filter_map(|entry| match entry {
+                HistoryEntry::Thread(thread) => Some(thread.id.to_string()),
+                HistoryEntry::Context(_) => None,
+            })
+            .collect::<Vec<_>>();
+        let author = message_author(cx);
+        self._lock_holders_task = Some(cx.spawn(async move |this, cx| {
+            let holders = match handler.store_lock_holders(&thread_ids).await {
+                Ok(holders) => holders,
+                Err(error) => {
+                    log::error!("Failed to check which threads are locked: {error:#}");
+                    return;
+                }
+            };
+            this.update(cx, |this, cx| {
+                this.lock_holders = held_by_others(holders, author.as_ref());
+                cx.notify();
+            })
+            .ok();
+        }));
+    }
+
+    /// Asks whether to take the thread over from the editor holding its lock, or only to unlock
+    /// it, and does so, recording who did in the store.
+    fn override_lock(
+        &mut self,
+        thread_id: ThreadId,
+        holder: StoreLockHolder,
+        window: &mut Window,
+        cx: &mut Context<Self>,
+    ) {
+        let handler = cx
+            .has_global::<MessageHandlerRegistry>()
+            .then(|| get_message_handler(cx))
+            .flatten();
+        let Some(handler) = handler else {
+            return;
+        };
+        let answer = window.prompt(
+            PromptLevel::Warning,
+            &format!(
+                "{} is running the agent in this thread",
+                holder.display_name()
+            ),
+            Some(
+                "Taking the thread over unlocks it and opens it here, and their agent stops at its \
+                next request. Either way, the override is recorded in the store.",
+            ),
+            &["Take Over", "Force Unlock", "Cancel"],
+            cx,
+        );
+        cx.spawn_in(window, async move |this, cx| {
+            let action = match answer.await? {
+                0 => LockOverride::TakeOver,
+                1 => LockOverride::ForceUnlock,
+                _ => return anyhow::Ok(()),
+            };
+            handler
+                .override_store_lock(&thread_id.to_string(), action)
+                .await?;
+            let open_thread = this.update_in(cx, |this, window, cx| {
+                this.lock_holders.remove(&thread_id);
+                cx.notify();
+                (action == LockOverride::TakeOver).then(|| {
+                    this.agent_panel.update(cx, |agent_panel, cx| {
+                        agent_panel.open_thread_by_id(&thread_id, window, cx)
+                    })
+                })
+            })?;
+            if let Some(open_thread) = open_thread {
+                open_thread?.await?;
+            }
+            Ok(())
+        })
+        .detach_and_log_err(cx);
+    }
+
     fn search(&mut self, query: SharedString, cx: &mut Context<Self>) {
         if query.is_empty() {
             self.search_state = SearchState::Empty;
@@ -204,32 +362,7 @@ impl ThreadHistory {
         let fuzzy_search_task = cx.background_spawn({
             let query = query.clone();
             let executor = cx.background_executor().clone();
-            async move {
-                let mut candidates = Vec::with_capacity(all_entries.len());
-
-                for (idx, entry) in all_entries.iter().enumerate() {
-                    match entry {
-                        HistoryEntry::Thread(thread) => {
-                            candidates.push(StringMatchCandidate::new(idx, &thread.summary));
-                        }
-                        HistoryEntry::Context(context) => {
-                            candidates.push(StringMatchCandidate::new(idx, &context.title));
-                        }
-                    }
-                }
-
-                const MAX_MATCHES: usize = 100;
-
-                fuzzy::match_strings(
-                    &candidates,
-                    &query,
-                    false,
-                    MAX_MATCHES,
-                    &Default::default(),
-                    executor,
-                )
-                .await
-            }
+            async move { search_entries(&all_entries, &query, executor).await }
         });
 
         let task = cx.spawn({
@@ -496,27 +629,53 @@ impl ThreadHistory {
     ) -> AnyElement {
         match item {
             ListItemType::Entry { index, format } => match self.all_entries.get(*index) {
-                Some(entry) => h_flex()
-                    .w_full()
-                    .pb_1()
-                    .child(
-                        HistoryEntryElement::new(entry.clone(), self.agent_panel.clone())
-                            .highlight_positions(highlight_positions)
-                            .timestamp_format(*format)
-                            .selected(list_entry_ix == Some(self.selected_index))
-                            .hovered(list_entry_ix == self.hovered_index)
-                            .on_hover(cx.listener(move |this, is_hovered, _window, cx| {
-                                if *is_hovered {
-                                    this.hovered_index = list_entry_ix;
-                                } else if this.hovered_index == list_entry_ix {
-                                    this.hovered_index = None;
-                                }
-
-                                cx.notify();
-                            }))
-                            .into_any_element(),
-                    )
-                    .into_any(),
+                Some(entry) => {
+                    let (changed_files, lock_holder) = match entry {
+                        HistoryEntry::Thread(thread) => (
+                            self.changed_files.get(&thread.id),
+                            self.lock_holders
+                                .get(&thread.id)
+                                .map(|holder| (thread.id.clone(), holder.clone())),
+                        ),
+                        HistoryEntry::Context(_) => (None, None),
+                    };
+                    let this = cx.entity().downgrade();
+                    h_flex()
+                        .w_full()
+                        .pb_1()
+                        .child(
+                            HistoryEntryElement::new(entry.clone(), self.agent_panel.clone())
+                                .changed_files(changed_files.cloned().unwrap_or_default())
+                                .when_some(lock_holder, |element, (thread_id, holder)| {
+                                    element.lock_holder(holder.clone(), move |window, cx| {
+                                        this.update(cx, |this, cx| {
+                                            this.override_lock(
+                                                thread_id.clone(),
+                                                holder.clone(),
+                                                window,
+                                                cx,
+                                            )
+                                        })
+                                        .ok();
+                                    })
+                                })
+                                .highlight_positions(highlight_positions)
+                                .timestamp_format(*format)
+                                .selected(list_entry_ix == Some(self.selected_index))
+                                .hovered(list_entry_ix == self.hovered_index)
+                                .on_hover(cx.listener(move |this, is_hovered, _window, cx| {
+                                    if *is_hovered {
+                                        this.hovered_index = list_entry_ix;
+                                    } else if this.hovered_index == list_entry_ix {
+                                        this.hovered_index = None;
+                                    }
+
+                                    cx.notify();
+                                }))
+                                .into_any_element(),
+                        )
+                        .into_any()
+                }
                 None => Empty.into_any_element(),
             },
             ListItemType::BucketSeparator(bucket) => div()
@@ -611,6 +770,84 @@ impl Render for ThreadHistory {
     }
 }
 
+/// Splits `author:<name>` terms out of a history search, leaving the rest of the query to match
+/// summaries.
+fn split_author_terms(query: &str) -> (Vec<&str>, String) {
+    let mut authors = Vec::new();
+    let mut rest = Vec::new();
+    for term in query.split_whitespace() {
+        match term.strip_prefix("author:") {
+            Some(author) if !author.is_empty() => authors.push(author),
+            _ => rest.push(term),
+        }
+    }
+    (authors, rest.join(" "))
+}
+
+/// The locks held by someone other than the author, keyed by thread.
+fn held_by_others(
+    holders: Vec<StoreLockHolder>,
+    author: Option<&MessageAuthor>,
+) -> HashMap<ThreadId, StoreLockHolder> {
+    holders
+        .into_iter()
+        .filter(|holder| holder.author.as_ref() != author)
+        .map(|holder| (ThreadId::from(holder.thread_id.as_str()), holder))
+        .collect()
+}
+
+async fn search_entries(
+    entries: &[HistoryEntry],
+    query: &str,
+    executor: BackgroundExecutor,
+) -> Vec<StringMatch> {
+    const MAX_MATCHES: usize = 100;
+
+    let (authors, query) = split_author_terms(query);
+    let mut candidates = Vec::with_capacity(entries.len());
+    for (idx, entry) in entries.iter().enumerate() {
+        let candidate = match entry {
+            HistoryEntry::Thread(thread) => {
+                let by_author = authors.iter().all(|query| {
+                    thread
+                        .author
+                        .as_ref()
+                        .is_some_and(|author| author.matches(query))
+                });
+                by_author.then(|| StringMatchCandidate::new(idx, &thread.summary))
+            }
+            // Text threads aren't attributed to anyone.
+            HistoryEntry::Context(context) => authors
+                .is_empty()
+                .then(|| StringMatchCandidate::new(idx, &context.title)),
+        };
+        candidates.extend(candidate);
+    }
+
+    if query.is_empty() {
+        return candidates
+            .into_iter()
+            .take(MAX_MATCHES)
+            .map(|candidate| StringMatch {
+                candidate_id: candidate.id,
+                score: 0.,
+                positions: Vec::new(),
+                string: candidate.string,
+            })
+            .collect();
+    }
+
+    fuzzy::match_strings(
+        &candidates,
+        &query,
+        false,
+        MAX_MATCHES,
+        &Default::default(),
+        executor,
+    )
+    .await
+}
+
 #[derive(IntoElement)]
 pub struct HistoryEntryElement {
     entry: HistoryEntry,
@@ -618,6 +855,8 @@ pub struct HistoryEntryElement {
     selected: bool,
     hovered: bool,
     highlight_positions: Vec<usize>,
+    changed_files: Vec<FileChange>,
+    lock_holder: Option<(StoreLockHolder, Rc<dyn Fn(&mut Window, &mut App)>)>,
     timestamp_format: EntryTimeFormat,
     on_hover: Box<dyn Fn(&bool, &mut Window, &mut App) + 'static>,
 }
@@ -630,6 +869,8 @@ impl HistoryEntryElement {
             selected: false,
             hovered: false,
             highlight_positions: vec![],
+            changed_files: Vec::new(),
+            lock_holder: None,
             timestamp_format: EntryTimeFormat::DateAndTime,
             on_hover: Box::new(|_, _, _| {}),
         }
@@ -650,6 +891,22 @@ impl HistoryEntryElement {
         self
     }
 
+    /// The files the thread referenced that have changed since, to mark it as possibly stale.
+    pub fn changed_files(mut self, changed_files: Vec<FileChange>) -> Self {
+        self.changed_files = changed_files;
+        self
+    }
+
+    /// Who else holds the thread's lock, and what clicking it to take it from them does.
+    pub fn lock_holder(
+        mut self,
+        holder: StoreLockHolder,
+        on_override: impl Fn(&mut Window, &mut App) + 'static,
+    ) -> Self {
+        self.lock_holder = Some((holder, Rc::new(on_override)));
+        self
+    }
+
     pub fn on_hover(mut self, on_hover: impl Fn(&bool, &mut Window, &mut App) + 'static) -> Self {
         self.on_hover = Box::new(on_hover);
         self
@@ -679,6 +936,39 @@ impl RenderOnce for HistoryEntryElement {
         let thread_timestamp =
             self.timestamp_format
                 .format_timestamp(&self.agent_panel, timestamp, cx);
+        // Only threads from someone else are labeled, e.g. ones imported or from a shared store.
+        let author = match &self.entry {
+            HistoryEntry::Thread(thread) => thread
+                .author
+                .as_ref()
+                .filter(|author| Some(*author) != message_author(cx).as_ref())
+                .map(|author| SharedString::from(author.display_name().to_string())),
+            HistoryEntry::Context(_) => None,
+        };
+        let changed_files = (!self.changed_files.is_empty()).then(|| {
+            let mut tooltip = "Changed since this thread referenced them:".to_string();
+            for change in &self.changed_files {
+                let kind = match change.kind {
+                    FileChangeKind::Modified => "modified",
+                    FileChangeKind::Created => "created",
+                    FileChangeKind::Deleted => "deleted",
+                };
+                tooltip.push_str(&format!("\n{} ({kind})", change.path));
+            }
+            tooltip
+        });
+        let lock_holder = self.lock_holder.map(|(holder, on_override)| {
+            let since = holder
+                .acquired_at
+                .with_timezone(&Local)
+                .format("%-I:%M %p")
+                .to_string();
+            let tooltip = format!(
+                "Locked by {} since {since}. Click to take it over or unlock it.",
+                holder.display_name()
+            );
+            (tooltip, on_override)
+        });
 
         ListItem::new(SharedString::from(id))
             .rounded()
@@ -695,9 +985,42 @@ impl RenderOnce for HistoryEntryElement {
                             .truncate(),
                     )
                     .child(
-                        Label::new(thread_timestamp)
-                            .color(Color::Muted)
-                            .size(LabelSize::XSmall),
+                        h_flex()
+                            .gap_1()
+                            .when_some(changed_files, |this, tooltip| {
+                                this.child(
+                                    div()
+                                        .id("changed-files")
+                                        .child(
+                                            Icon::new(IconName::Warning)
+                                                .size(IconSize::XSmall)
+                                                .color(Color::Warning),
+                                        )
+                                        .tooltip(Tooltip::text(tooltip)),
+                                )
+                            })
+                            .when_some(lock_holder, |this, (tooltip, on_override)| {
+                                this.child(
+                                    IconButton::new("lock-holder", IconName::LockOutlined)
+                                        .shape(IconButtonShape::Square)
+                                        .icon_size(IconSize::XSmall)
+                                        .icon_color(Color::Warning)
+                                        .tooltip(Tooltip::text(tooltip))
+                                        .on_click(move |_, window, cx| on_override(window, cx)),
+                                )
+                            })
+                            .when_some(author, |this, author| {
+                                this.child(
+                                    Label::new(author)
+                                        .color(Color::Muted)
+                                        .size(LabelSize::XSmall),
+                                )
+                            })
+                            .child(
+                                Label::new(thread_timestamp)
+                                    .color(Color::Muted)
+                                    .size(LabelSize::XSmall),
+                            ),
                     ),
             )
             .on_hover(self.on_hover)
@@ -873,6 +1196,46 @@ mod tests {
     use super::*;
     use chrono::NaiveDate;
 
+    #[test]
+    fn test_only_others_locks_are_shown() {
+        let author = |id: &str| MessageAuthor {
+            id: id.into(),
+            name: None,
+            email: None,
+        };
+        let holder = |thread_id: &str, author| StoreLockHolder {
+            thread_id: thread_id.into(),
+            session_id: "session".into(),
+            author,
+            acquired_at: chrono::Utc::now(),
+            renewed_at: chrono::Utc::now(),
+        };
+        let holders = vec![
+            holder("mine", Some(author("ada"))),
+            holder("theirs", Some(author("grace"))),
+            holder("unattributed", None),
+        ];
+        let mut held = held_by_others(holders, Some(&author("ada")))
+            .into_keys()
+            .map(|thread_id| thread_id.to_string())
+            .collect::<Vec<_>>();
+        held.sort();
+        assert_eq!(held, ["theirs", "unattributed"]);
+    }
+
+    #[test]
+    fn test_split_author_terms() {
+        assert_eq!(
+            split_author_terms("author:ada parser fix"),
+            (vec!["ada"], "parser fix".to_string())
+        );
+        assert_eq!(
+            split_author_terms("author: crash author:grace"),
+            (vec!["grace"], "author: crash".to_string())
+        );
+        assert_eq!(split_author_terms("crash"), (vec![], "crash".to_string()));
+    }
+
     #[test]
     fn test_time_bucket_from_dates() {
         let today = NaiveDate::from_ymd_opt(2023, 1, 15).unwrap();
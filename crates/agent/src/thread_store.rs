@@ -17,6 +17,7 @@ use gpui::{
     Subscription, Task, prelude::*,
 };
 
+use language_model::message_handler::MessageAuthor;
 use language_model::{LanguageModelToolResultContent, LanguageModelToolUseId, Role, TokenUsage};
 use project::context_server_store::{ContextServerStatus, ContextServerStore};
 use project::{Project, ProjectItem, ProjectPath, Worktree};
@@ -31,7 +32,8 @@ use util::ResultExt as _;
 
 use crate::context_server_tool::ContextServerTool;
 use crate::thread::{
-    DetailedSummaryState, ExceededWindowError, MessageId, ProjectSnapshot, Thread, ThreadId,
+    DetailedSummaryState, ExceededWindowError, MessageId, ModelSegment, ProjectSnapshot, Thread,
+    ThreadId,
 };
 use indoc::indoc;
 use sqlez::{
@@ -490,6 +492,23 @@ impl ThreadStore {
         })
     }
 
+    /// Stores a thread that was serialized elsewhere under a new id.
+    pub fn import_thread(
+        &self,
+        serialized_thread: SerializedThread,
+        cx: &mut Context<Self>,
+    ) -> Task<Result<ThreadId>> {
+        let id = ThreadId::new();
+        let database_future = ThreadsDatabase::global_future(cx);
+        cx.spawn(async move |this, cx| {
+            let database = database_future.await.map_err(|err| anyhow!(err))?;
+            database.save_thread(id.clone(), serialized_thread).await?;
+
+            this.update(cx, |this, cx| this.reload(cx))?.await?;
+            Ok(id)
+        })
+    }
+
     pub fn delete_thread(&mut self, id: &ThreadId, cx: &mut Context<Self>) -> Task<Result<()>> {
         let id = id.clone();
         let database_future = ThreadsDatabase::global_future(cx);
@@ -690,6 +709,8 @@ pub struct SerializedThreadMetadata {
     pub id: ThreadId,
     pub summary: SharedString,
     pub updated_at: DateTime<Utc>,
+    #[serde(default)]
+    pub author: Option<MessageAuthor>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -714,9 +735,14 @@ pub struct SerializedThread {
     pub completion_mode: Option<CompletionMode>,
     #[serde(default)]
     pub tool_use_limit_reached: bool,
+    /// Who started the thread, when messages are attributed to someone.
+    #[serde(default)]
+    pub author: Option<MessageAuthor>,
+    #[serde(default)]
+    pub model_segments: Vec<ModelSegment>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct SerializedLanguageModel {
     pub provider: String,
     pub model: String,
@@ -856,6 +882,8 @@ impl LegacySerializedThread {
             model: None,
             completion_mode: None,
             tool_use_limit_reached: false,
+            author: None,
+            model_segments: Vec::new(),
         }
     }
 }
@@ -969,6 +997,14 @@ impl ThreadsDatabase {
             "})?()
         .map_err(|e| anyhow!("Failed to create threads table: {}", e))?;
 
+        let mut author_columns = connection.select_row::<i64>(
+            "SELECT COUNT(*) FROM pragma_table_info('threads') WHERE name = 'author'",
+        )?;
+        if author_columns()?.unwrap_or(0) == 0 {
+            connection.exec("ALTER TABLE threads ADD COLUMN author TEXT")?()
+                .map_err(|e| anyhow!("Failed to add author to threads table: {}", e))?;
+        }
+
         let db = Self {
             executor: executor.clone(),
             connection: Arc::new(Mutex::new(connection)),
@@ -1052,6 +1088,11 @@ impl ThreadsDatabase {
         let json_data = serde_json::to_string(&thread)?;
         let summary = thread.summary.to_string();
         let updated_at = thread.updated_at.to_rfc3339();
+        let author = thread
+            .author
+            .as_ref()
+            .map(serde_json::to_string)
+            .transpose()?;
 
         let connection = connection.lock().unwrap();
 
@@ -1059,11 +1100,11 @@ impl ThreadsDatabase {
         let data_type = DataType::Zstd;
         let data = compressed;
 
-        let mut insert = connection.exec_bound::<(ThreadId, String, String, DataType, Vec<u8>)>(indoc! {"
-            INSERT OR REPLACE INTO threads (id, summary, updated_at, data_type, data) VALUES (?, ?, ?, ?, ?)
+        let mut insert = connection.exec_bound::<(ThreadId, String, String, DataType, Vec<u8>, Option<String>)>(indoc! {"
+            INSERT OR REPLACE INTO threads (id, summary, updated_at, data_type, data, author) VALUES (?, ?, ?, ?, ?, ?)
         "})?;
 
-        insert((id, summary, updated_at, data_type, data))?;
+        insert((id, summary, updated_at, data_type, data, author))?;
 
         Ok(())
     }
@@ -1073,19 +1114,20 @@ impl ThreadsDatabase {
 
         self.executor.spawn(async move {
             let connection = connection.lock().unwrap();
-            let mut select =
-                connection.select_bound::<(), (ThreadId, String, String)>(indoc! {"
-                SELECT id, summary, updated_at FROM threads ORDER BY updated_at DESC
+            let mut select = connection
+                .select_bound::<(), (ThreadId, String, String, Option<String>)>(indoc! {"
+                SELECT id, summary, updated_at, author FROM threads ORDER BY updated_at DESC
             "})?;
 
             let rows = select(())?;
             let mut threads = Vec::new();
 
-            for (id, summary, updated_at) in rows {
+            for (id, summary, updated_at, author) in rows {
                 threads.push(SerializedThreadMetadata {
                     id,
                     summary: summary.into(),
                     updated_at: DateTime::parse_from_rfc3339(&updated_at)?.with_timezone(&Utc),
+                    author: author.and_then(|author| serde_json::from_str(&author).log_err()),
                 });
             }
 
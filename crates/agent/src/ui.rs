@@ -1,5 +1,6 @@
 mod agent_notification;
 mod animated_label;
+mod bundle_passphrase_modal;
 mod context_pill;
 mod max_mode_tooltip;
 mod onboarding_modal;
@@ -8,6 +9,7 @@ mod upsell;
 
 pub use agent_notification::*;
 pub use animated_label::*;
+pub use bundle_passphrase_modal::*;
 pub use context_pill::*;
 pub use max_mode_tooltip::*;
 pub use onboarding_modal::*;
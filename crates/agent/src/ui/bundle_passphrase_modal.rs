@@ -0,0 +1,99 @@
+use editor::Editor;
+use futures::channel::oneshot;
+use gpui::{DismissEvent, Entity, EventEmitter, FocusHandle, Focusable};
+use ui::{Headline, HeadlineSize, prelude::*};
+use workspace::{ModalView, Workspace};
+
+/// Asks for the passphrase a thread bundle is encrypted with.
+pub struct BundlePassphraseModal {
+    title: SharedString,
+    editor: Entity<Editor>,
+    tx: Option<oneshot::Sender<String>>,
+}
+
+impl EventEmitter<DismissEvent> for BundlePassphraseModal {}
+impl ModalView for BundlePassphraseModal {}
+
+impl Focusable for BundlePassphraseModal {
+    fn focus_handle(&self, cx: &App) -> FocusHandle {
+        self.editor.focus_handle(cx)
+    }
+}
+
+impl BundlePassphraseModal {
+    /// Shows the modal, resolving to the entered passphrase, or to an error if it is dismissed.
+    pub fn prompt(
+        workspace: &mut Workspace,
+        title: impl Into<SharedString>,
+        window: &mut Window,
+        cx: &mut Context<Workspace>,
+    ) -> oneshot::Receiver<String> {
+        let (tx, rx) = oneshot::channel();
+        let title = title.into();
+        workspace.toggle_modal(window, cx, |window, cx| Self::new(title, tx, window, cx));
+        rx
+    }
+
+    fn new(
+        title: SharedString,
+        tx: oneshot::Sender<String>,
+        window: &mut Window,
+        cx: &mut Context<Self>,
+    ) -> Self {
+        let editor = cx.new(|cx| {
+            let mut editor = Editor::single_line(window, cx);
+            editor.set_masked(true, cx);
+            editor.set_placeholder_text("Passphrase", cx);
+            editor
+        });
+        Self {
+            title,
+            editor,
+            tx: Some(tx),
+        }
+    }
+
+    fn cancel(&mut self, _: &menu::Cancel, _window: &mut Window, cx: &mut Context<Self>) {
+        cx.emit(DismissEvent);
+    }
+
+    fn confirm(&mut self, _: &menu::Confirm, _window: &mut Window, cx: &mut Context<Self>) {
+        let passphrase = self.editor.read(cx).text(cx);
+        if passphrase.is_empty() {
+            return;
+        }
+        if let Some(tx) = self.tx.take() {
+            tx.send(passphrase).ok();
+        }
+        cx.emit(DismissEvent);
+    }
+}
+
+impl Render for BundlePassphraseModal {
+    fn render(&mut self, _window: &mut Window, cx: &mut Context<Self>) -> impl IntoElement {
+        v_flex()
+            .key_context("BundlePassphraseModal")
+            .on_action(cx.listener(Self::cancel))
+            .on_action(cx.listener(Self::confirm))
+            .elevation_2(cx)
+            .w(rems(28.))
+            .child(
+                h_flex()
+                    .px_3()
+                    .pt_2()
+                    .pb_1()
+                    .gap_1p5()
+                    .child(Icon::new(IconName::LockOutlined).size(IconSize::XSmall))
+                    .child(Headline::new(self.title.clone()).size(HeadlineSize::XSmall)),
+            )
+            .child(
+                div()
+                    .py_2()
+                    .px_3()
+                    .bg(cx.theme().colors().editor_background)
+                    .border_t_1()
+                    .border_color(cx.theme().colors().border_variant)
+                    .child(self.editor.clone()),
+            )
+    }
+}
@@ -98,6 +98,7 @@ pub struct AgentSettings {
     pub inline_assistant_model: Option<LanguageModelSelection>,
     pub commit_message_model: Option<LanguageModelSelection>,
     pub thread_summary_model: Option<LanguageModelSelection>,
+    pub judge_model: Option<LanguageModelSelection>,
     pub inline_alternatives: Vec<LanguageModelSelection>,
     pub using_outdated_settings_version: bool,
     pub default_profile: AgentProfileId,
@@ -108,6 +109,7 @@ pub struct AgentSettings {
     pub play_sound_when_agent_done: bool,
     pub stream_edits: bool,
     pub single_file_review: bool,
+    pub conversation_heat_threshold: Option<usize>,
     pub model_parameters: Vec<LanguageModelParameters>,
     pub preferred_completion_mode: CompletionMode,
     pub enable_feedback: bool,
@@ -267,6 +269,7 @@ impl AgentSettingsContent {
                     inline_assistant_model: None,
                     commit_message_model: None,
                     thread_summary_model: None,
+                    judge_model: None,
                     inline_alternatives: None,
                     default_profile: None,
                     default_view: None,
@@ -275,6 +278,7 @@ impl AgentSettingsContent {
                     notify_when_agent_waiting: None,
                     stream_edits: None,
                     single_file_review: None,
+                    conversation_heat_threshold: None,
                     model_parameters: Vec::new(),
                     preferred_completion_mode: None,
                     enable_feedback: None,
@@ -300,6 +304,7 @@ impl AgentSettingsContent {
                 inline_assistant_model: None,
                 commit_message_model: None,
                 thread_summary_model: None,
+                judge_model: None,
                 inline_alternatives: None,
                 default_profile: None,
                 default_view: None,
@@ -308,6 +313,7 @@ impl AgentSettingsContent {
                 notify_when_agent_waiting: None,
                 stream_edits: None,
                 single_file_review: None,
+                conversation_heat_threshold: None,
                 model_parameters: Vec::new(),
                 preferred_completion_mode: None,
                 enable_feedback: None,
@@ -587,6 +593,7 @@ impl Default for VersionedAgentSettingsContent {
             inline_assistant_model: None,
             commit_message_model: None,
             thread_summary_model: None,
+            judge_model: None,
             inline_alternatives: None,
             default_profile: None,
             default_view: None,
@@ -595,6 +602,7 @@ impl Default for VersionedAgentSettingsContent {
             notify_when_agent_waiting: None,
             stream_edits: None,
             single_file_review: None,
+            conversation_heat_threshold: None,
             model_parameters: Vec::new(),
             preferred_completion_mode: None,
             enable_feedback: None,
@@ -634,6 +642,8 @@ pub struct AgentSettingsContentV2 {
     commit_message_model: Option<LanguageModelSelection>,
     /// Model to use for generating thread summaries. Defaults to default_model when not specified.
     thread_summary_model: Option<LanguageModelSelection>,
+    /// Model to use for ranking the responses of the models a prompt is compared across. Defaults to default_model when not specified.
+    judge_model: Option<LanguageModelSelection>,
     /// Additional models with which to generate alternatives when performing inline assists.
     inline_alternatives: Option<Vec<LanguageModelSelection>>,
     /// The default profile to use in the Agent.
@@ -667,6 +677,11 @@ pub struct AgentSettingsContentV2 {
     ///
     /// Default: true
     single_file_review: Option<bool>,
+    /// How many stored threads must have referenced a file, through their tool calls or
+    /// context, for its editors to link to them from the gutter. Not shown when unset.
+    ///
+    /// Default: null
+    conversation_heat_threshold: Option<usize>,
     /// Additional parameters for language model requests. When making a request
     /// to a model, parameters will be taken from the last entry in this list
     /// that matches the model's provider and name. In each entry, both provider
@@ -877,6 +892,7 @@ impl Settings for AgentSettings {
             settings.thread_summary_model = value
                 .thread_summary_model
                 .or(settings.thread_summary_model.take());
+            settings.judge_model = value.judge_model.or(settings.judge_model.take());
             merge(&mut settings.inline_alternatives, value.inline_alternatives);
             merge(
                 &mut settings.always_allow_tool_actions,
@@ -892,6 +908,9 @@ impl Settings for AgentSettings {
             );
             merge(&mut settings.stream_edits, value.stream_edits);
             merge(&mut settings.single_file_review, value.single_file_review);
+            settings.conversation_heat_threshold = value
+                .conversation_heat_threshold
+                .or(settings.conversation_heat_threshold.take());
             merge(&mut settings.default_profile, value.default_profile);
             merge(&mut settings.default_view, value.default_view);
             merge(
@@ -1019,6 +1038,7 @@ mod tests {
                             inline_assistant_model: None,
                             commit_message_model: None,
                             thread_summary_model: None,
+                            judge_model: None,
                             inline_alternatives: None,
                             enabled: None,
                             button: None,
@@ -1033,6 +1053,7 @@ mod tests {
                             notify_when_agent_waiting: None,
                             stream_edits: None,
                             single_file_review: None,
+                            conversation_heat_threshold: None,
                             enable_feedback: None,
                             model_parameters: Vec::new(),
                             preferred_completion_mode: None,
@@ -21,7 +21,7 @@ use language::{AnchorRangeExt, Bias, Buffer, LanguageRegistry, OffsetRangeExt, P
 use language_model::{
     LanguageModel, LanguageModelCacheConfiguration, LanguageModelCompletionEvent,
     LanguageModelImage, LanguageModelRegistry, LanguageModelRequest, LanguageModelRequestMessage,
-    LanguageModelToolUseId, MessageContent, PaymentRequiredError, Role, StopReason,
+    LanguageModelToolUseId, MessageContent, PaymentRequiredError, RequestSource, Role, StopReason,
     report_assistant_event,
 };
 use open_ai::Model as OpenAiModel;
@@ -2281,6 +2281,16 @@ impl AssistantContext {
             tool_choice: None,
             stop: Vec::new(),
             temperature: model.and_then(|model| AgentSettings::temperature_for_model(model, cx)),
+            toolchain: None,
+            project: None,
+            prompt_template: None,
+            origin: None,
+            buffer: None,
+            git_branch: None,
+            schedule_id: None,
+            fan_out_group: None,
+            message_id: None,
+            source: Some(RequestSource::TextThread),
         };
         for message in self.messages(cx) {
             if message.status != MessageStatus::Done {
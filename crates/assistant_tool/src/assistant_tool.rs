@@ -1,4 +1,5 @@
 mod action_log;
+mod command_output;
 pub mod outline;
 mod tool_registry;
 mod tool_schema;
@@ -26,6 +27,7 @@ use project::Project;
 use workspace::Workspace;
 
 pub use crate::action_log::*;
+pub use crate::command_output::*;
 pub use crate::tool_registry::*;
 pub use crate::tool_schema::*;
 pub use crate::tool_working_set::*;
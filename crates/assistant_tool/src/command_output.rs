@@ -0,0 +1,45 @@
+/// How many bytes of a command's output are kept for the model.
+pub const COMMAND_OUTPUT_LIMIT: usize = 16 * 1024;
+
+/// The output and status of a command run in a container, for tools to report to the model.
+#[derive(Debug, Default, PartialEq)]
+pub struct CommandOutput {
+    /// The command's interleaved stdout and stderr, up to [`COMMAND_OUTPUT_LIMIT`] bytes.
+    pub output: String,
+    /// Whether output was dropped past the limit.
+    pub truncated: bool,
+    pub exit_code: Option<i64>,
+    /// Whether the command was still running when the tool stopped waiting for it.
+    pub timed_out: bool,
+}
+
+impl CommandOutput {
+    /// Appends a chunk of output, truncating it at a character boundary once the limit is hit.
+    pub fn append(&mut self, chunk: &str) {
+        let remaining = COMMAND_OUTPUT_LIMIT.saturating_sub(self.output.len());
+        if chunk.len() <= remaining {
+            self.output.push_str(chunk);
+            return;
+        }
+        let mut end_ix = remaining;
+        while !chunk.is_char_boundary(end_ix) {
+            end_ix -= 1;
+        }
+        self.output.push_str(&chunk[..end_ix]);
+        self.truncated = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_output_is_truncated_at_a_char_boundary() {
+        let mut output = CommandOutput::default();
+        output.append(&"a".repeat(COMMAND_OUTPUT_LIMIT - 1));
+        output.append("éé");
+        assert!(output.truncated);
+        assert_eq!(output.output.len(), COMMAND_OUTPUT_LIMIT - 1);
+    }
+}
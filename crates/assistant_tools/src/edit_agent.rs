@@ -660,6 +660,16 @@ impl EditAgent {
             tools,
             stop: Vec::new(),
             temperature: None,
+            toolchain: None,
+            project: None,
+            prompt_template: None,
+            origin: None,
+            buffer: None,
+            git_branch: None,
+            schedule_id: None,
+            fan_out_group: None,
+            message_id: None,
+            source: conversation.source,
         };
 
         Ok(self.model.stream_completion_text(request, cx).await?.stream)
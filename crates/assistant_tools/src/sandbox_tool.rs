@@ -0,0 +1,431 @@
+use crate::schema::json_schema_for;
+use anyhow::{Context as _, Result, anyhow};
+use assistant_tool::{ActionLog, CommandOutput, Tool, ToolResult};
+use bollard::{
+    Docker,
+    container::{
+        Config, CreateContainerOptions, LogOutput, LogsOptions, RemoveContainerOptions,
+        StartContainerOptions, WaitContainerOptions,
+    },
+    errors::Error as DockerError,
+    image::CreateImageOptions,
+    models::HostConfig,
+};
+use futures::StreamExt as _;
+use gpui::{AnyWindowHandle, App, Entity, Task};
+use gpui_tokio::Tokio;
+use language_model::{
+    _retrieve_ids, LanguageModel, LanguageModelRequest, LanguageModelToolSchemaFormat, RequestIds,
+    message_handler::{ContentValue, LanguageModelArgs, Message, get_message_handler},
+};
+use project::Project;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, pin::pin, sync::Arc, time::Duration};
+use ui::IconName;
+use util::{ResultExt as _, markdown::MarkdownInlineCode};
+
+const SANDBOX_IMAGE: &str = "python:3.12-alpine";
+const SANDBOX_LABEL: &str = "dev.zed.sandbox";
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_TIMEOUT: Duration = Duration::from_secs(300);
+const MEMORY_LIMIT: i64 = 512 * 1024 * 1024;
+const NANO_CPUS: i64 = 1_000_000_000;
+const PIDS_LIMIT: i64 = 256;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SandboxLanguage {
+    /// A POSIX shell script, run with `sh`.
+    Shell,
+    /// A Python 3 program.
+    Python,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct SandboxToolInput {
+    /// The language of the snippet.
+    language: SandboxLanguage,
+    /// The snippet to run.
+    code: String,
+    /// Whether the snippet needs network access. Off by default.
+    #[serde(default)]
+    network: bool,
+    /// Seconds to let the snippet run before it is stopped. Defaults to 30, at most 300.
+    #[serde(default)]
+    timeout_secs: Option<u64>,
+}
+
+impl SandboxToolInput {
+    fn command(&self) -> Vec<String> {
+        let interpreter = match self.language {
+            SandboxLanguage::Shell => ["sh", "-c"],
+            SandboxLanguage::Python => ["python", "-c"],
+        };
+        interpreter
+            .into_iter()
+            .map(String::from)
+            .chain([self.code.clone()])
+            .collect()
+    }
+
+    fn timeout(&self) -> Duration {
+        self.timeout_secs
+            .map_or(DEFAULT_TIMEOUT, Duration::from_secs)
+            .min(MAX_TIMEOUT)
+    }
+}
+
+/// What the model is told about the snippet's run.
+fn sandbox_message(output: &CommandOutput, timeout: Duration) -> String {
+    let content = output.output.trim();
+    let status = if output.timed_out {
+        format!("Snippet was stopped after {} seconds.", timeout.as_secs())
+    } else {
+        match output.exit_code {
+            Some(0) => "Snippet ran successfully.".to_string(),
+            Some(code) => format!("Snippet failed with exit code {code}."),
+            None => "Snippet exited without a status.".to_string(),
+        }
+    };
+    if content.is_empty() {
+        status
+    } else if output.truncated {
+        format!(
+            "{status}\n\nOutput too long. The first {} bytes:\n\n```\n{content}\n```",
+            content.len()
+        )
+    } else {
+        format!("{status}\n\n```\n{content}\n```")
+    }
+}
+
+pub struct SandboxTool;
+
+impl SandboxTool {
+    pub const NAME: &str = "sandbox";
+}
+
+impl Tool for SandboxTool {
+    fn name(&self) -> String {
+        Self::NAME.to_string()
+    }
+
+    fn needs_confirmation(&self, _: &serde_json::Value, _: &App) -> bool {
+        true
+    }
+
+    fn may_perform_edits(&self) -> bool {
+        false
+    }
+
+    fn description(&self) -> String {
+        include_str!("./sandbox_tool/description.md").to_string()
+    }
+
+    fn icon(&self) -> IconName {
+        IconName::Terminal
+    }
+
+    fn input_schema(&self, format: LanguageModelToolSchemaFormat) -> Result<serde_json::Value> {
+        json_schema_for::<SandboxToolInput>(format)
+    }
+
+    fn ui_text(&self, input: &serde_json::Value) -> String {
+        match serde_json::from_value::<SandboxToolInput>(input.clone()) {
+            Ok(input) => {
+                let language = match input.language {
+                    SandboxLanguage::Shell => "shell",
+                    SandboxLanguage::Python => "Python",
+                };
+                let first_line = input.code.lines().next().unwrap_or_default();
+                let network = if input.network { " with network" } else { "" };
+                format!(
+                    "Run {language} snippet {}{network}",
+                    MarkdownInlineCode(first_line)
+                )
+            }
+            Err(_) => "Run snippet in sandbox".to_string(),
+        }
+    }
+
+    fn run(
+        self: Arc<Self>,
+        input: serde_json::Value,
+        request: Arc<LanguageModelRequest>,
+        _project: Entity<Project>,
+        _action_log: Entity<ActionLog>,
+        model: Arc<dyn LanguageModel>,
+        _window: Option<AnyWindowHandle>,
+        cx: &mut App,
+    ) -> ToolResult {
+        let input: SandboxToolInput = match serde_json::from_value(input) {
+            Ok(input) => input,
+            Err(err) => return Task::ready(Err(anyhow!(err))).into(),
+        };
+        let docker = match Docker::connect_with_local_defaults() {
+            Ok(docker) => docker,
+            Err(error) => {
+                return Task::ready(Err(anyhow!("Docker is unavailable: {error}"))).into();
+            }
+        };
+        let ids = _retrieve_ids(&request);
+        let message_handler = get_message_handler(cx);
+        let agent_name = message_handler.as_ref().map(|message_handler| {
+            message_handler.agent_name(&LanguageModelArgs::from_request(model.id(), &request))
+        });
+        let run = Tokio::spawn(cx, {
+            let input = input.clone();
+            async move { run_in_sandbox(docker, &input).await }
+        });
+        cx.background_spawn(async move {
+            let output = run
+                .await
+                .map_err(anyhow::Error::from)
+                .and_then(|result| result);
+            let message = match &output {
+                Ok(output) => sandbox_message(output, input.timeout()),
+                Err(error) => format!("Sandbox failed: {error:#}"),
+            };
+            if let Some((message_handler, agent_name)) = message_handler.zip(agent_name) {
+                let messages = sandbox_messages(&input, &message, &ids, &agent_name);
+                message_handler
+                    .save_append_messages(messages, &ids)
+                    .await
+                    .log_err();
+            }
+            output?;
+            Ok(message.into())
+        })
+        .into()
+    }
+}
+
+/// The tool call and its result, in the shape the conversation store keeps messages in.
+fn sandbox_messages(
+    input: &SandboxToolInput,
+    result: &str,
+    ids: &RequestIds,
+    agent_name: &str,
+) -> Vec<Message> {
+    let call_id = uuid::Uuid::new_v4().to_string();
+    let arguments = serde_json::to_value(input).unwrap_or_default();
+    let tool_call = HashMap::from_iter([
+        ("id".to_string(), serde_json::Value::String(call_id.clone())),
+        (
+            "name".to_string(),
+            serde_json::Value::String(SandboxTool::NAME.to_string()),
+        ),
+        ("args".to_string(), arguments),
+    ]);
+    let response_metadata = HashMap::from_iter([(
+        "prompt_id".to_string(),
+        serde_json::Value::String(ids.prompt_id.clone()),
+    )]);
+    vec![
+        Message::Ai {
+            content: ContentValue::new(String::new()),
+            id: ids.checkpoint_id.clone(),
+            name: Some(agent_name.to_string()),
+            example: false,
+            invalid_tool_calls: None,
+            tool_calls: Some(tool_call),
+            additional_kwargs: Default::default(),
+            response_metadata: response_metadata.clone(),
+        },
+        Message::Tool {
+            content: ContentValue::new(result.to_string()),
+            id: call_id.clone(),
+            name: Some(agent_name.to_string()),
+            example: false,
+            tool_call_id: Some(call_id),
+            tool_name: Some(SandboxTool::NAME.to_string()),
+            additional_kwargs: Default::default(),
+            response_metadata,
+        },
+    ]
+}
+
+/// Runs the snippet in a new container, which is removed once it exits or times out.
+async fn run_in_sandbox(docker: Docker, input: &SandboxToolInput) -> Result<CommandOutput> {
+    ensure_image(&docker).await?;
+    let name = format!("zed-sandbox-{}", uuid::Uuid::new_v4());
+    let config = Config {
+        image: Some(SANDBOX_IMAGE.to_string()),
+        cmd: Some(input.command()),
+        working_dir: Some("/tmp".to_string()),
+        network_disabled: Some(!input.network),
+        labels: Some(HashMap::from_iter([(
+            SANDBOX_LABEL.to_string(),
+            "true".to_string(),
+        )])),
+        host_config: Some(HostConfig {
+            network_mode: (!input.network).then(|| "none".to_string()),
+            memory: Some(MEMORY_LIMIT),
+            nano_cpus: Some(NANO_CPUS),
+            pids_limit: Some(PIDS_LIMIT),
+            cap_drop: Some(vec!["ALL".to_string()]),
+            security_opt: Some(vec!["no-new-privileges".to_string()]),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    docker
+        .create_container(
+            Some(CreateContainerOptions {
+                name: name.clone(),
+                platform: None,
+            }),
+            config,
+        )
+        .await
+        .context("failed to create sandbox container")?;
+    let output = run_container(&docker, &name, input.timeout()).await;
+    docker
+        .remove_container(
+            &name,
+            Some(RemoveContainerOptions {
+                force: true,
+                ..Default::default()
+            }),
+        )
+        .await
+        .log_err();
+    output
+}
+
+async fn run_container(docker: &Docker, name: &str, timeout: Duration) -> Result<CommandOutput> {
+    docker
+        .start_container(name, None::<StartContainerOptions<String>>)
+        .await
+        .context("failed to start sandbox container")?;
+
+    let mut result = CommandOutput::default();
+    let wait = async {
+        let mut wait = pin!(docker.wait_container(
+            name,
+            Some(WaitContainerOptions {
+                condition: "not-running",
+            }),
+        ));
+        match wait.next().await {
+            Some(Ok(response)) => Ok(Some(response.status_code)),
+            Some(Err(DockerError::DockerContainerWaitError { code, .. })) => Ok(Some(code)),
+            Some(Err(error)) => Err(anyhow::Error::from(error)),
+            None => Ok(None),
+        }
+    };
+    match tokio::time::timeout(timeout, wait).await {
+        Ok(exit_code) => result.exit_code = exit_code?,
+        Err(_) => result.timed_out = true,
+    }
+
+    let mut logs = pin!(docker.logs(
+        name,
+        Some(LogsOptions::<String> {
+            stdout: true,
+            stderr: true,
+            ..Default::default()
+        }),
+    ));
+    while let Some(chunk) = logs.next().await {
+        let message = match chunk? {
+            LogOutput::StdOut { message }
+            | LogOutput::StdErr { message }
+            | LogOutput::Console { message }
+            | LogOutput::StdIn { message } => message,
+        };
+        result.append(&String::from_utf8_lossy(&message));
+    }
+    Ok(result)
+}
+
+async fn ensure_image(docker: &Docker) -> Result<()> {
+    match docker.inspect_image(SANDBOX_IMAGE).await {
+        Ok(_) => return Ok(()),
+        Err(DockerError::DockerResponseServerError {
+            status_code: 404, ..
+        }) => {}
+        Err(error) => return Err(error.into()),
+    }
+    let mut pull = pin!(docker.create_image(
+        Some(CreateImageOptions {
+            from_image: SANDBOX_IMAGE,
+            ..Default::default()
+        }),
+        None,
+        None,
+    ));
+    while let Some(info) = pull.next().await {
+        info.with_context(|| format!("failed to pull {SANDBOX_IMAGE}"))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use language_model::message_handler::DEFAULT_AGENT_NAME;
+
+    fn input(code: &str) -> SandboxToolInput {
+        SandboxToolInput {
+            language: SandboxLanguage::Python,
+            code: code.to_string(),
+            network: false,
+            timeout_secs: Some(1_000),
+        }
+    }
+
+    #[test]
+    fn test_sandbox_command_and_timeout() {
+        let input = input("print(1)");
+        assert_eq!(input.command(), vec!["python", "-c", "print(1)"]);
+        assert_eq!(input.timeout(), MAX_TIMEOUT);
+    }
+
+    #[test]
+    fn test_sandbox_message() {
+        let output = CommandOutput {
+            output: "Traceback\n".to_string(),
+            exit_code: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(
+            sandbox_message(&output, DEFAULT_TIMEOUT),
+            "Snippet failed with exit code 1.\n\n```\nTraceback\n```"
+        );
+    }
+
+    #[test]
+    fn test_sandbox_messages_link_call_and_result() {
+        let ids = RequestIds {
+            thread_id: "thread".to_string(),
+            checkpoint_id: "checkpoint".to_string(),
+            session_id: "session".to_string(),
+            prompt_id: "prompt".to_string(),
+        };
+        let messages = sandbox_messages(
+            &input("print(1)"),
+            "Snippet ran successfully.",
+            &ids,
+            DEFAULT_AGENT_NAME,
+        );
+        let [
+            Message::Ai {
+                tool_calls: Some(tool_calls),
+                ..
+            },
+            Message::Tool {
+                tool_call_id: Some(tool_call_id),
+                tool_name,
+                ..
+            },
+        ] = messages.as_slice()
+        else {
+            panic!("expected a tool call and its result, got {messages:?}");
+        };
+        assert_eq!(tool_calls["id"], serde_json::json!(tool_call_id));
+        assert_eq!(tool_calls["args"]["code"], serde_json::json!("print(1)"));
+        assert_eq!(tool_name.as_deref(), Some(SandboxTool::NAME));
+    }
+}
@@ -587,6 +587,16 @@ impl ExampleInstance {
                 tools: Vec::new(),
                 tool_choice: None,
                 stop: Vec::new(),
+                toolchain: None,
+                project: None,
+                prompt_template: None,
+                origin: None,
+                buffer: None,
+                git_branch: None,
+                schedule_id: None,
+                fan_out_group: None,
+                message_id: None,
+                source: None,
             };
 
             let model = model.clone();
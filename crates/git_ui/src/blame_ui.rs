@@ -19,6 +19,7 @@ use time::OffsetDateTime;
 use time_format::format_local_timestamp;
 use ui::{ContextMenu, Divider, IconButtonShape, prelude::*};
 use workspace::Workspace;
+use zed_actions::agent::OpenCommitThread;
 
 const GIT_BLAME_MAX_AUTHOR_CHARS_DISPLAYED: usize = 20;
 
@@ -406,6 +407,12 @@ fn deploy_blame_entry_context_menu(
                     })
                 },
             )
+            .action(
+                "Open agent thread",
+                Box::new(OpenCommitThread {
+                    sha: blame_entry.sha.to_string(),
+                }),
+            )
     });
 
     editor.update(cx, move |editor, cx| {
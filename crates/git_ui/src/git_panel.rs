@@ -37,7 +37,7 @@ use itertools::Itertools;
 use language::{Buffer, File};
 use language_model::{
     ConfiguredModel, LanguageModel, LanguageModelRegistry, LanguageModelRequest,
-    LanguageModelRequestMessage, Role,
+    LanguageModelRequestMessage, RequestSource, Role,
 };
 use menu::{Confirm, SecondaryConfirm, SelectFirst, SelectLast, SelectNext, SelectPrevious};
 use multi_buffer::ExcerptInfo;
@@ -1778,6 +1778,16 @@ impl GitPanel {
                     tool_choice: None,
                     stop: Vec::new(),
                     temperature,
+                    toolchain: None,
+                    project: None,
+                    prompt_template: None,
+                    origin: None,
+                    buffer: None,
+                    git_branch: None,
+                    schedule_id: None,
+                    fan_out_group: None,
+                    message_id: None,
+                    source: Some(RequestSource::GitCommit),
                 };
 
                 let stream = model.stream_completion_text(request, &cx);
@@ -0,0 +1,92 @@
+use anyhow::Result;
+use futures::future::BoxFuture;
+use gpui::AsyncApp;
+use std::sync::Arc;
+
+use crate::message_handler::create_conversation_id;
+use crate::{LanguageModel, LanguageModelRequest, LanguageModelTextStream};
+
+/// A request sent to several models at once, to compare their responses.
+pub struct FanOut {
+    /// Shared by the threads of the responses.
+    pub group_id: String,
+    pub branches: Vec<FanOutBranch>,
+}
+
+/// The response of one of the models a request was fanned out to.
+pub struct FanOutBranch {
+    pub model: Arc<dyn LanguageModel>,
+    /// The thread the response is stored under, a sibling of the other models' threads.
+    pub thread_id: String,
+    pub text: BoxFuture<'static, Result<LanguageModelTextStream>>,
+}
+
+/// Copies of the request, each starting a thread of its own, in one fan-out group.
+fn sibling_requests(
+    request: &LanguageModelRequest,
+    count: usize,
+) -> (String, Vec<LanguageModelRequest>) {
+    let group_id = create_conversation_id();
+    let requests = (0..count)
+        .map(|_| LanguageModelRequest {
+            thread_id: Some(create_conversation_id()),
+            fan_out_group: Some(group_id.clone()),
+            ..request.clone()
+        })
+        .collect();
+    (group_id, requests)
+}
+
+/// Sends the request to each of the models concurrently. Each response is stored by its model's
+/// provider under a sibling thread, tagged with the group they share.
+pub fn fan_out_request(
+    request: &LanguageModelRequest,
+    models: &[Arc<dyn LanguageModel>],
+    cx: &AsyncApp,
+) -> FanOut {
+    let (group_id, requests) = sibling_requests(request, models.len());
+    let branches = models
+        .iter()
+        .zip(requests)
+        .map(|(model, request)| FanOutBranch {
+            model: model.clone(),
+            thread_id: request.thread_id.clone().unwrap_or_default(),
+            text: model.stream_completion_text(request, cx),
+        })
+        .collect();
+    FanOut { group_id, branches }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{LanguageModelRequestMessage, MessageContent, Role};
+
+    #[test]
+    fn test_sibling_requests_share_a_group() {
+        let request = LanguageModelRequest {
+            thread_id: Some("original".into()),
+            messages: vec![LanguageModelRequestMessage {
+                role: Role::User,
+                content: vec![MessageContent::Text("Explain this function".into())],
+                cache: false,
+            }],
+            temperature: Some(0.2),
+            ..Default::default()
+        };
+
+        let (group_id, siblings) = sibling_requests(&request, 3);
+        assert_eq!(siblings.len(), 3);
+        for (ix, sibling) in siblings.iter().enumerate() {
+            assert_eq!(sibling.fan_out_group.as_deref(), Some(group_id.as_str()));
+            assert_eq!(sibling.messages, request.messages);
+            assert_eq!(sibling.temperature, request.temperature);
+            assert_ne!(sibling.thread_id, request.thread_id);
+            assert!(
+                siblings[..ix]
+                    .iter()
+                    .all(|other| other.thread_id != sibling.thread_id)
+            );
+        }
+    }
+}
@@ -0,0 +1,188 @@
+use anyhow::{Result, anyhow};
+use futures::{FutureExt as _, future::BoxFuture};
+use gpui::AsyncApp;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::fmt::Write as _;
+use std::sync::Arc;
+
+use crate::message_handler::{
+    MessageHandlerRegistry, create_conversation_id, get_message_handler_async,
+};
+use crate::{
+    LanguageModel, LanguageModelRequest, LanguageModelRequestMessage, MessageContent, Role,
+    StructuredOutputSchema, stream_structured_output,
+};
+
+/// One of the responses of a fan-out, as the judge sees it: without the model that sent it.
+#[derive(Debug, Clone)]
+pub struct JudgeCandidate {
+    /// The thread the response is stored under.
+    pub thread_id: String,
+    pub text: String,
+}
+
+/// What the judge model is asked to respond with.
+#[derive(Debug, Deserialize, JsonSchema)]
+struct JudgeResponse {
+    /// The numbers of the responses, best first.
+    ranking: Vec<usize>,
+    /// Why the responses are ranked the way they are.
+    rationale: String,
+}
+
+/// How the judge model ranked the responses of a fan-out.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FanOutVerdict {
+    pub group_id: String,
+    /// The thread the judge's request and response are stored under.
+    pub judge_thread_id: String,
+    /// The threads of the responses, best first.
+    pub ranking: Vec<String>,
+    pub rationale: String,
+}
+
+impl FanOutVerdict {
+    /// The thread of the best response.
+    pub fn winner(&self) -> Option<&str> {
+        self.ranking.first().map(String::as_str)
+    }
+}
+
+/// Maps the judge's numbering back to the candidates' threads. Numbers that don't name a
+/// candidate, or name one again, are dropped, and candidates the judge left out are ranked last,
+/// in their original order.
+fn verdict_from_response(
+    group_id: String,
+    judge_thread_id: String,
+    candidates: &[JudgeCandidate],
+    response: JudgeResponse,
+) -> FanOutVerdict {
+    let mut ranking = Vec::with_capacity(candidates.len());
+    let numbers = response
+        .ranking
+        .into_iter()
+        .filter_map(|number| number.checked_sub(1))
+        .chain(0..candidates.len());
+    for ix in numbers {
+        if let Some(candidate) = candidates.get(ix) {
+            if !ranking.contains(&candidate.thread_id) {
+                ranking.push(candidate.thread_id.clone());
+            }
+        }
+    }
+    FanOutVerdict {
+        group_id,
+        judge_thread_id,
+        ranking,
+        rationale: response.rationale,
+    }
+}
+
+/// Asks the judge to rank the candidates as answers to the original request's last user message.
+fn judge_request(original: &LanguageModelRequest, candidates: &[JudgeCandidate]) -> String {
+    let task = original
+        .messages
+        .iter()
+        .rev()
+        .find(|message| message.role == Role::User)
+        .map(|message| message.string_contents())
+        .unwrap_or_default();
+    let mut prompt = String::from(
+        "Several models were given the same task. Rank their responses from best to worst, \
+         judging correctness first, then completeness, then clarity.\n\n",
+    );
+    writeln!(prompt, "<task>\n{}\n</task>\n", task.trim()).ok();
+    for (ix, candidate) in candidates.iter().enumerate() {
+        writeln!(
+            prompt,
+            "<response number=\"{}\">\n{}\n</response>\n",
+            ix + 1,
+            candidate.text.trim()
+        )
+        .ok();
+    }
+    prompt.push_str(
+        "Respond with only a JSON object with a `ranking` field, listing the numbers of all the \
+         responses best first, and a `rationale` field explaining the ranking.",
+    );
+    prompt
+}
+
+/// Sends the candidates to the judge model, and stores its ranking with the fan-out group they
+/// were responses in. The judge's own request is stored as a thread in the group too.
+pub fn judge_fan_out(
+    judge: Arc<dyn LanguageModel>,
+    group_id: String,
+    original: &LanguageModelRequest,
+    candidates: Vec<JudgeCandidate>,
+    cx: &AsyncApp,
+) -> BoxFuture<'static, Result<FanOutVerdict>> {
+    let judge_thread_id = create_conversation_id();
+    let request = LanguageModelRequest {
+        thread_id: Some(judge_thread_id.clone()),
+        prompt_id: None,
+        fan_out_group: Some(group_id.clone()),
+        message_id: None,
+        messages: vec![LanguageModelRequestMessage {
+            role: Role::User,
+            content: vec![MessageContent::Text(judge_request(original, &candidates))],
+            cache: false,
+        }],
+        tools: Vec::new(),
+        tool_choice: None,
+        temperature: Some(0.0),
+        ..original.clone()
+    };
+    let schema = match StructuredOutputSchema::of::<JudgeResponse>() {
+        Ok(schema) => schema,
+        Err(error) => return futures::future::ready(Err(error)).boxed(),
+    };
+    let handler = cx
+        .update(|cx| {
+            cx.has_global::<MessageHandlerRegistry>()
+                .then(|| get_message_handler_async(cx))
+                .flatten()
+        })
+        .ok()
+        .flatten();
+    let output = stream_structured_output(judge, request, schema, cx);
+    async move {
+        let output = output.await.map_err(|error| anyhow!(error))?;
+        let response = serde_json::from_value::<JudgeResponse>(output.value)?;
+        let verdict = verdict_from_response(group_id, judge_thread_id, &candidates, response);
+        if let Some(handler) = handler {
+            handler.save_fan_out_verdict(&verdict).await?;
+        }
+        Ok(verdict)
+    }
+    .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verdict_ranks_every_candidate_once() {
+        let candidates = ["a", "b", "c"]
+            .into_iter()
+            .map(|thread_id| JudgeCandidate {
+                thread_id: thread_id.into(),
+                text: format!("response {thread_id}"),
+            })
+            .collect::<Vec<_>>();
+        let response = JudgeResponse {
+            ranking: vec![3, 0, 3, 7, 1],
+            rationale: "The third response handles the empty case.".into(),
+        };
+
+        let verdict = verdict_from_response("group".into(), "judge".into(), &candidates, response);
+        assert_eq!(verdict.ranking, ["c", "a", "b"]);
+        assert_eq!(verdict.winner(), Some("c"));
+        assert_eq!(
+            verdict.rationale,
+            "The third response handles the empty case."
+        );
+    }
+}
@@ -0,0 +1,504 @@
+//! Runs graphs hosted on a LangGraph server through its assistants API, streaming what the graph's
+//! nodes produce back as completion events.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+use anyhow::{Context as _, Result, anyhow};
+use futures::{
+    AsyncBufReadExt, AsyncReadExt, StreamExt,
+    io::BufReader,
+    stream::{self, BoxStream},
+};
+use http_client::{AsyncBody, HttpClient, Method, Request as HttpRequest};
+use serde::Deserialize;
+use serde_json::{Value, json};
+
+use crate::message_handler::{AiMessageHandler, ContentValue, LanguageModelArgs, Message, peek_db};
+use crate::{
+    _retrieve_ids, LanguageModelCompletionError, LanguageModelCompletionEvent, LanguageModelId,
+    LanguageModelRequest, LanguageModelToolUse, RequestIds, Role, StopReason, TokenUsage,
+};
+
+/// What the server is asked to stream: message chunks as the graph's LLM calls produce them, and
+/// each node's state update once it finishes.
+const STREAM_MODES: &[&str] = &["messages-tuple", "updates"];
+
+pub struct LangGraphClient {
+    http_client: Arc<dyn HttpClient>,
+    api_url: String,
+    api_key: Option<String>,
+}
+
+impl LangGraphClient {
+    pub fn new(http_client: Arc<dyn HttpClient>, api_url: String, api_key: Option<String>) -> Self {
+        Self {
+            http_client,
+            api_url: api_url.trim_end_matches('/').to_string(),
+            api_key,
+        }
+    }
+
+    /// Runs the assistant on the request's thread, persisting the request and the streamed events
+    /// under the request's ids like any other completion.
+    pub async fn stream_run(
+        &self,
+        assistant_id: &str,
+        request: &LanguageModelRequest,
+        message_handler: Option<Arc<AiMessageHandler>>,
+    ) -> Result<
+        BoxStream<'static, Result<LanguageModelCompletionEvent, LanguageModelCompletionError>>,
+    > {
+        let ids = _retrieve_ids(request);
+        let args = LanguageModelArgs::from_request(
+            LanguageModelId::from(format!("langgraph/{assistant_id}")),
+            request,
+        );
+        if let Some(handler) = &message_handler {
+            handler
+                .save_completion_req(request, &ids, args.clone())
+                .await;
+        }
+
+        self.ensure_thread(&ids.thread_id).await?;
+        let body = json!({
+            "assistant_id": assistant_id,
+            "input": { "messages": input_messages(request, &ids) },
+            "stream_mode": STREAM_MODES,
+        });
+        let response = self
+            .post(&format!("/threads/{}/runs/stream", ids.thread_id), &body)
+            .await?;
+
+        let mut parser = SseParser::default();
+        let mut mapper = RunEventMapper::default();
+        let events = BufReader::new(response.into_body())
+            .lines()
+            .map(move |line| match line {
+                Ok(line) => match parser.push_line(&line) {
+                    Some(event) => mapper.map_event(event),
+                    None => Vec::new(),
+                },
+                Err(error) => vec![Err(LanguageModelCompletionError::Other(error.into()))],
+            })
+            .flat_map(stream::iter);
+        Ok(peek_db(events, message_handler, ids, args))
+    }
+
+    /// Creates the thread unless the server already has it, so runs continue earlier ones.
+    async fn ensure_thread(&self, thread_id: &str) -> Result<()> {
+        self.post(
+            "/threads",
+            &json!({ "thread_id": thread_id, "if_exists": "do_nothing" }),
+        )
+        .await
+        .with_context(|| format!("failed to create LangGraph thread {thread_id}"))?;
+        Ok(())
+    }
+
+    async fn post(&self, path: &str, body: &Value) -> Result<http_client::Response<AsyncBody>> {
+        let mut request = HttpRequest::builder()
+            .method(Method::POST)
+            .uri(format!("{}{path}", self.api_url))
+            .header("Content-Type", "application/json");
+        if let Some(api_key) = &self.api_key {
+            request = request.header("X-Api-Key", api_key);
+        }
+        let request = request.body(AsyncBody::from(serde_json::to_string(body)?))?;
+        let mut response = self.http_client.send(request).await?;
+        if response.status().is_success() {
+            return Ok(response);
+        }
+        let mut body = String::new();
+        response.body_mut().read_to_string(&mut body).await?;
+        Err(anyhow!(
+            "LangGraph server responded with {}: {body}",
+            response.status()
+        ))
+    }
+}
+
+/// The request's messages as the graph's `messages` state expects them. The graph's state merges
+/// messages by id, and each request resends the thread's history, so a message is given an id by
+/// its place in the thread: the same in every run, and different from every other message's.
+fn input_messages(request: &LanguageModelRequest, ids: &RequestIds) -> Vec<Message> {
+    request
+        .messages
+        .iter()
+        .enumerate()
+        .map(|(index, message)| {
+            let content = ContentValue::new(message.string_contents());
+            let id = format!("{}:{index}", ids.thread_id);
+            match message.role {
+                Role::User => Message::Human {
+                    content,
+                    id,
+                    name: None,
+                    example: false,
+                    additional_kwargs: HashMap::new(),
+                    response_metadata: HashMap::new(),
+                },
+                Role::Assistant => Message::Ai {
+                    content,
+                    id,
+                    name: None,
+                    example: false,
+                    invalid_tool_calls: None,
+                    tool_calls: None,
+                    additional_kwargs: HashMap::new(),
+                    response_metadata: HashMap::new(),
+                },
+                Role::System => Message::System {
+                    content,
+                    id,
+                    name: None,
+                    example: false,
+                    additional_kwargs: HashMap::new(),
+                    response_metadata: HashMap::new(),
+                },
+            }
+        })
+        .collect()
+}
+
+#[derive(Debug, PartialEq)]
+struct SseEvent {
+    event: String,
+    data: String,
+}
+
+/// Assembles server-sent events from the lines of the response body.
+#[derive(Default)]
+struct SseParser {
+    event: Option<String>,
+    data: Vec<String>,
+}
+
+impl SseParser {
+    fn push_line(&mut self, line: &str) -> Option<SseEvent> {
+        if line.is_empty() {
+            let event = self.event.take();
+            let data = std::mem::take(&mut self.data);
+            if event.is_none() && data.is_empty() {
+                return None;
+            }
+            return Some(SseEvent {
+                event: event.unwrap_or_else(|| "message".to_string()),
+                data: data.join("\n"),
+            });
+        }
+        if let Some(event) = line.strip_prefix("event:") {
+            self.event = Some(event.trim().to_string());
+        } else if let Some(data) = line.strip_prefix("data:") {
+            self.data
+                .push(data.strip_prefix(' ').unwrap_or(data).to_string());
+        }
+        None
+    }
+}
+
+#[derive(Deserialize)]
+struct MessageChunk {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    id: Option<String>,
+    #[serde(default)]
+    content: Value,
+    #[serde(default)]
+    tool_call_chunks: Vec<ToolCallChunk>,
+    #[serde(default)]
+    usage_metadata: Option<UsageMetadata>,
+}
+
+#[derive(Deserialize)]
+struct ToolCallChunk {
+    #[serde(default)]
+    index: Option<usize>,
+    #[serde(default)]
+    id: Option<String>,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    args: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct UsageMetadata {
+    #[serde(default)]
+    input_tokens: u32,
+    #[serde(default)]
+    output_tokens: u32,
+}
+
+#[derive(Default)]
+struct PendingToolCall {
+    id: String,
+    name: String,
+    args: String,
+}
+
+/// Turns the run's events into completion events. Tool call arguments arrive in pieces, so tool
+/// uses are emitted once their message is done.
+#[derive(Default)]
+struct RunEventMapper {
+    current_message: Option<String>,
+    pending_tool_calls: BTreeMap<usize, PendingToolCall>,
+    used_tools: bool,
+}
+
+type MappedEvent = Result<LanguageModelCompletionEvent, LanguageModelCompletionError>;
+
+impl RunEventMapper {
+    fn map_event(&mut self, event: SseEvent) -> Vec<MappedEvent> {
+        match event.event.as_str() {
+            "messages" => match serde_json::from_str::<(MessageChunk, Value)>(&event.data) {
+                Ok((chunk, _metadata)) => self.map_chunk(chunk),
+                Err(error) => vec![Err(anyhow!("invalid LangGraph message: {error}").into())],
+            },
+            "updates" => {
+                if let Ok(Value::Object(updates)) = serde_json::from_str(&event.data) {
+                    for node in updates.keys() {
+                        log::debug!("LangGraph node {node} finished");
+                    }
+                }
+                Vec::new()
+            }
+            "error" => {
+                let message = serde_json::from_str::<Value>(&event.data)
+                    .ok()
+                    .and_then(|error| error.get("message")?.as_str().map(str::to_string))
+                    .unwrap_or(event.data);
+                vec![Err(anyhow!("LangGraph run failed: {message}").into())]
+            }
+            "end" => {
+                let mut events = self.flush_tool_calls();
+                events.push(Ok(LanguageModelCompletionEvent::Stop(if self.used_tools {
+                    StopReason::ToolUse
+                } else {
+                    StopReason::EndTurn
+                })));
+                events
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn map_chunk(&mut self, chunk: MessageChunk) -> Vec<MappedEvent> {
+        // Tool results and the input echoed back by the graph aren't part of the response.
+        if !matches!(chunk.kind.as_str(), "AIMessageChunk" | "ai") {
+            return Vec::new();
+        }
+        let mut events = Vec::new();
+        if chunk.id.is_some() && chunk.id != self.current_message {
+            events.extend(self.flush_tool_calls());
+            self.current_message = chunk.id.clone();
+            if let Some(message_id) = chunk.id {
+                events.push(Ok(LanguageModelCompletionEvent::StartMessage {
+                    message_id,
+                }));
+            }
+        }
+        match chunk.content {
+            Value::String(text) if !text.is_empty() => {
+                events.push(Ok(LanguageModelCompletionEvent::Text(text)))
+            }
+            Value::Array(blocks) => {
+                for block in blocks {
+                    if let Some(text) = block.get("text").and_then(Value::as_str) {
+                        events.push(Ok(LanguageModelCompletionEvent::Text(text.to_string())));
+                    } else if let Some(text) = block.get("thinking").and_then(Value::as_str) {
+                        events.push(Ok(LanguageModelCompletionEvent::Thinking {
+                            text: text.to_string(),
+                            signature: None,
+                        }));
+                    }
+                }
+            }
+            _ => {}
+        }
+        for tool_call in chunk.tool_call_chunks {
+            let pending = self
+                .pending_tool_calls
+                .entry(tool_call.index.unwrap_or_default())
+                .or_default();
+            if let Some(id) = tool_call.id {
+                pending.id = id;
+            }
+            if let Some(name) = tool_call.name {
+                pending.name = name;
+            }
+            pending
+                .args
+                .push_str(tool_call.args.as_deref().unwrap_or_default());
+        }
+        if let Some(usage) = chunk.usage_metadata {
+            events.push(Ok(LanguageModelCompletionEvent::UsageUpdate(TokenUsage {
+                input_tokens: usage.input_tokens,
+                output_tokens: usage.output_tokens,
+                ..Default::default()
+            })));
+        }
+        events
+    }
+
+    fn flush_tool_calls(&mut self) -> Vec<MappedEvent> {
+        std::mem::take(&mut self.pending_tool_calls)
+            .into_values()
+            .map(|tool_call| {
+                self.used_tools = true;
+                let raw_input = if tool_call.args.is_empty() {
+                    "{}".to_string()
+                } else {
+                    tool_call.args
+                };
+                match serde_json::from_str(&raw_input) {
+                    Ok(input) => Ok(LanguageModelCompletionEvent::ToolUse(
+                        LanguageModelToolUse {
+                            id: tool_call.id.into(),
+                            name: tool_call.name.into(),
+                            raw_input,
+                            input,
+                            is_input_complete: true,
+                        },
+                    )),
+                    Err(error) => Err(LanguageModelCompletionError::BadInputJson {
+                        id: tool_call.id.into(),
+                        tool_name: tool_call.name.into(),
+                        raw_input: raw_input.into(),
+                        json_parse_error: error.to_string(),
+                    }),
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn events(lines: &[&str]) -> Vec<LanguageModelCompletionEvent> {
+        let mut parser = SseParser::default();
+        let mut mapper = RunEventMapper::default();
+        lines
+            .iter()
+            .filter_map(|line| parser.push_line(line))
+            .flat_map(|event| mapper.map_event(event))
+            .map(|event| event.unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_sse_parser() {
+        let mut parser = SseParser::default();
+        assert_eq!(parser.push_line("event: metadata"), None);
+        assert_eq!(parser.push_line("data: {\"a\":"), None);
+        assert_eq!(parser.push_line("data: 1}"), None);
+        assert_eq!(
+            parser.push_line(""),
+            Some(SseEvent {
+                event: "metadata".to_string(),
+                data: "{\"a\":\n1}".to_string(),
+            })
+        );
+        assert_eq!(parser.push_line(""), None);
+    }
+
+    #[test]
+    fn test_run_events_become_completion_events() {
+        let events = events(&[
+            "event: messages",
+            r#"data: [{"type":"AIMessageChunk","id":"run-1","content":"Hel"},{"langgraph_node":"agent"}]"#,
+            "",
+            "event: messages",
+            r#"data: [{"type":"AIMessageChunk","id":"run-1","content":"lo","tool_call_chunks":[{"index":0,"id":"call-1","name":"grep","args":"{\"regex\""}]},{}]"#,
+            "",
+            "event: messages",
+            r#"data: [{"type":"AIMessageChunk","id":"run-1","content":"","tool_call_chunks":[{"index":0,"args":":\"fn\"}"}],"usage_metadata":{"input_tokens":10,"output_tokens":4}},{}]"#,
+            "",
+            "event: updates",
+            r#"data: {"agent":{"messages":[]}}"#,
+            "",
+            "event: messages",
+            r#"data: [{"type":"tool","id":"tool-1","content":"src/main.rs"},{}]"#,
+            "",
+            "event: end",
+            "data: null",
+            "",
+        ]);
+        assert_eq!(
+            events,
+            vec![
+                LanguageModelCompletionEvent::StartMessage {
+                    message_id: "run-1".to_string()
+                },
+                LanguageModelCompletionEvent::Text("Hel".to_string()),
+                LanguageModelCompletionEvent::Text("lo".to_string()),
+                LanguageModelCompletionEvent::UsageUpdate(TokenUsage {
+                    input_tokens: 10,
+                    output_tokens: 4,
+                    ..Default::default()
+                }),
+                LanguageModelCompletionEvent::ToolUse(LanguageModelToolUse {
+                    id: "call-1".into(),
+                    name: "grep".into(),
+                    raw_input: r#"{"regex":"fn"}"#.to_string(),
+                    input: json!({ "regex": "fn" }),
+                    is_input_complete: true,
+                }),
+                LanguageModelCompletionEvent::Stop(StopReason::ToolUse),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_run_errors_are_reported() {
+        let mut mapper = RunEventMapper::default();
+        let events = mapper.map_event(SseEvent {
+            event: "error".to_string(),
+            data: r#"{"error":"ValueError","message":"bad input"}"#.to_string(),
+        });
+        assert_eq!(events.len(), 1);
+        let error = events.into_iter().next().unwrap().unwrap_err();
+        assert_eq!(error.to_string(), "LangGraph run failed: bad input");
+    }
+
+    #[test]
+    fn test_input_messages_keep_their_place_in_the_thread() {
+        let message = |role, text: &str| crate::LanguageModelRequestMessage {
+            role,
+            content: vec![crate::MessageContent::Text(text.to_string())],
+            cache: false,
+        };
+        let mut request = LanguageModelRequest {
+            thread_id: Some("session".into()),
+            session_id: Some("thread".into()),
+            prompt_id: Some("prompt".into()),
+            messages: vec![
+                message(Role::System, "be brief"),
+                message(Role::User, "hi"),
+                message(Role::Assistant, "hello"),
+            ],
+            ..Default::default()
+        };
+        let message_ids = |request: &LanguageModelRequest| {
+            let ids = _retrieve_ids(request);
+            input_messages(request, &ids)
+                .iter()
+                .map(|message| serde_json::to_value(message).unwrap()["id"].clone())
+                .collect::<Vec<_>>()
+        };
+        let first_run = message_ids(&request);
+        assert_eq!(
+            first_run,
+            [json!("thread:0"), json!("thread:1"), json!("thread:2")]
+        );
+
+        request.prompt_id = Some("next prompt".into());
+        request.messages.push(message(Role::User, "and again"));
+        let second_run = message_ids(&request);
+        assert_eq!(second_run[..3], first_run[..]);
+        assert_eq!(second_run[3], json!("thread:3"));
+    }
+}
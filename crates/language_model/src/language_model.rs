@@ -1,10 +1,17 @@
+mod fan_out;
+mod judge;
 mod model;
 mod rate_limiter;
 mod registry;
 mod request;
+mod resumable_stream;
 mod role;
+mod stream_tee;
+mod structured_output;
 mod telemetry;
+mod tokenizer;
 
+pub mod langgraph;
 pub mod message_handler;
 
 #[cfg(any(test, feature = "test-support"))]
@@ -16,6 +23,8 @@ pub use crate::message_handler::{
 use serde_json;
 use std::collections::HashMap;
 
+pub use crate::fan_out::*;
+pub use crate::judge::*;
 use crate::message_handler::{
     AiMessageHandler, MessageHandlerConfig, init_message_handler, peek_db,
 };
@@ -23,8 +32,12 @@ pub use crate::model::*;
 pub use crate::rate_limiter::*;
 pub use crate::registry::*;
 pub use crate::request::*;
+pub use crate::resumable_stream::*;
 pub use crate::role::*;
+pub use crate::stream_tee::*;
+pub use crate::structured_output::*;
 pub use crate::telemetry::*;
+pub use crate::tokenizer::*;
 use anyhow::{Context as _, Result};
 use client::Client;
 use futures::FutureExt;
@@ -288,6 +301,11 @@ pub trait LanguageModel: Send + Sync {
         false
     }
 
+    /// Whether this model carries on a trailing assistant message rather than replying to it.
+    fn supports_assistant_prefill(&self) -> bool {
+        false
+    }
+
     fn tool_input_format(&self) -> LanguageModelToolSchemaFormat {
         LanguageModelToolSchemaFormat::JsonSchema
     }
@@ -0,0 +1,95 @@
+use serde::{Deserialize, Serialize};
+
+use super::Message;
+
+/// Who sent the messages being stored, so that conversations from a team sharing one store can
+/// be told apart.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct MessageAuthor {
+    /// Stable identifier: the configured email, or the Zed account when there isn't one.
+    pub id: String,
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+}
+
+impl MessageAuthor {
+    /// The name to show for the author.
+    pub fn display_name(&self) -> &str {
+        self.name
+            .as_deref()
+            .or(self.email.as_deref())
+            .unwrap_or(&self.id)
+    }
+
+    /// Whether a search for `query` should find this author.
+    pub fn matches(&self, query: &str) -> bool {
+        let query = query.to_lowercase();
+        [Some(&self.id), self.name.as_ref(), self.email.as_ref()]
+            .into_iter()
+            .flatten()
+            .any(|field| field.to_lowercase().contains(&query))
+    }
+}
+
+/// Records the author in each message's `additional_kwargs`, so that stored blobs stay
+/// attributable on their own.
+pub(crate) fn stamp_author(messages: &mut [Message], author: &MessageAuthor) {
+    let Ok(author) = serde_json::to_value(author) else {
+        return;
+    };
+    for message in messages {
+        let additional_kwargs = match message {
+            Message::Human {
+                additional_kwargs, ..
+            }
+            | Message::Ai {
+                additional_kwargs, ..
+            }
+            | Message::System {
+                additional_kwargs, ..
+            }
+            | Message::Tool {
+                additional_kwargs, ..
+            }
+            | Message::Function {
+                additional_kwargs, ..
+            } => additional_kwargs,
+        };
+        additional_kwargs.insert("author".to_string(), author.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message_handler::ContentValue;
+
+    #[test]
+    fn test_stamp_author() {
+        let author = MessageAuthor {
+            id: "ada@example.com".into(),
+            name: Some("Ada".into()),
+            email: Some("ada@example.com".into()),
+        };
+        let mut messages = vec![Message::Human {
+            content: ContentValue::new("hi".into()),
+            id: "1".into(),
+            name: None,
+            example: false,
+            additional_kwargs: Default::default(),
+            response_metadata: Default::default(),
+        }];
+        stamp_author(&mut messages, &author);
+        let Message::Human {
+            additional_kwargs, ..
+        } = &messages[0]
+        else {
+            panic!("expected a human message");
+        };
+        assert_eq!(additional_kwargs["author"]["name"], "Ada");
+        assert!(author.matches("ADA"));
+        assert!(author.matches("example.com"));
+        assert!(!author.matches("grace"));
+    }
+}
@@ -0,0 +1,109 @@
+use collections::HashSet;
+
+use super::MessageAuthor;
+
+/// What a shared store knows about a thread before any of its messages are read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredThread {
+    pub thread_id: String,
+    /// Who started the thread. Empty for threads stored before messages were attributed.
+    pub author_id: String,
+    pub author_name: String,
+    /// The project the thread was started in. Empty when its first request didn't say.
+    pub project: String,
+    /// Everyone who has written to the thread, its author included.
+    pub contributors: Vec<String>,
+    /// The git branch the thread was started on, or empty if it wasn't started on one.
+    pub git_branch: String,
+    pub updated_at: String,
+    /// Kept at the top of the history by the user.
+    pub pinned: bool,
+    /// Marked by the user as a favorite.
+    pub starred: bool,
+}
+
+/// Decides which threads in a shared store each reader may see. It is consulted before any
+/// thread is listed or loaded.
+pub trait StoreAuthorizer: Send + Sync {
+    /// Whether `reader` may read the thread. `reader` is `None` when nobody is configured as the
+    /// author of this handler's messages.
+    fn can_read(&self, thread: &StoredThread, reader: Option<&MessageAuthor>) -> bool;
+}
+
+/// Lets everyone read every thread, as stores did before authorizers existed.
+pub struct AllowAll;
+
+impl StoreAuthorizer for AllowAll {
+    fn can_read(&self, _thread: &StoredThread, _reader: Option<&MessageAuthor>) -> bool {
+        true
+    }
+}
+
+/// Limits what a reader sees to the threads they started or wrote to, plus those started by the
+/// listed authors or in the listed projects.
+#[derive(Debug, Clone, Default)]
+pub struct VisibilityRules {
+    pub authors: HashSet<String>,
+    pub projects: HashSet<String>,
+}
+
+impl StoreAuthorizer for VisibilityRules {
+    fn can_read(&self, thread: &StoredThread, reader: Option<&MessageAuthor>) -> bool {
+        reader.is_some_and(|reader| {
+            reader.id == thread.author_id || thread.contributors.contains(&reader.id)
+        }) || self.authors.contains(&thread.author_id)
+            || self.projects.contains(&thread.project)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn thread(author_id: &str, project: &str) -> StoredThread {
+        StoredThread {
+            thread_id: "thread".into(),
+            author_id: author_id.into(),
+            author_name: String::new(),
+            project: project.into(),
+            contributors: vec![author_id.into()],
+            git_branch: String::new(),
+            updated_at: String::new(),
+            pinned: false,
+            starred: false,
+        }
+    }
+
+    #[test]
+    fn test_visibility_rules() {
+        let ada = MessageAuthor {
+            id: "ada@example.com".into(),
+            name: Some("Ada".into()),
+            email: None,
+        };
+        let rules = VisibilityRules {
+            authors: HashSet::from_iter(["grace@example.com".to_string()]),
+            projects: HashSet::from_iter(["zed".to_string()]),
+        };
+
+        assert!(rules.can_read(&thread("ada@example.com", "other"), Some(&ada)));
+        assert!(rules.can_read(&thread("grace@example.com", "other"), Some(&ada)));
+        assert!(rules.can_read(&thread("linus@example.com", "zed"), Some(&ada)));
+        assert!(!rules.can_read(&thread("linus@example.com", "other"), Some(&ada)));
+        // Readers see the threads they wrote to, but a thread belongs to whoever started it:
+        // an allowed author writing to it doesn't share it.
+        let mut joined = thread("linus@example.com", "other");
+        joined
+            .contributors
+            .extend(["ada@example.com".into(), "grace@example.com".into()]);
+        assert!(rules.can_read(&joined, Some(&ada)));
+        let bob = MessageAuthor {
+            id: "bob@example.com".into(),
+            name: None,
+            email: None,
+        };
+        assert!(!rules.can_read(&joined, Some(&bob)));
+        assert!(!rules.can_read(&thread("ada@example.com", "other"), None));
+        assert!(AllowAll.can_read(&thread("linus@example.com", "other"), None));
+    }
+}
@@ -0,0 +1,168 @@
+use anyhow::{Context as _, bail};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// How checkpoint blobs are written. Blobs are read back whatever they were written with, so the
+/// encoding can be changed at any time; existing rows keep theirs until converted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum BlobEncoding {
+    /// UTF-8 JSON, readable from SQL.
+    #[default]
+    Json,
+    MessagePack,
+    Bincode,
+}
+
+/// Starts binary blobs. JSON can't start with 0xFF, so blobs written before encodings were
+/// configurable are told apart by its absence.
+const BLOB_MAGIC: [u8; 2] = [0xFF, b'Z'];
+/// Follows the magic, so that the layout after the header can change.
+const BLOB_FORMAT_VERSION: u8 = 1;
+const HEADER_LEN: usize = BLOB_MAGIC.len() + 2;
+
+impl BlobEncoding {
+    fn tag(&self) -> Option<u8> {
+        match self {
+            BlobEncoding::Json => None,
+            BlobEncoding::MessagePack => Some(1),
+            BlobEncoding::Bincode => Some(2),
+        }
+    }
+}
+
+/// The encoding the blob was written with.
+#[cfg_attr(not(feature = "postgres"), allow(dead_code))]
+pub(crate) fn blob_encoding(blob: &[u8]) -> anyhow::Result<BlobEncoding> {
+    if !blob.starts_with(&BLOB_MAGIC) {
+        return Ok(BlobEncoding::Json);
+    }
+    let header = blob.get(..HEADER_LEN).context("truncated blob header")?;
+    let version = header[BLOB_MAGIC.len()];
+    if version != BLOB_FORMAT_VERSION {
+        bail!("unsupported blob format version {version}");
+    }
+    match header[BLOB_MAGIC.len() + 1] {
+        1 => Ok(BlobEncoding::MessagePack),
+        2 => Ok(BlobEncoding::Bincode),
+        tag => bail!("unknown blob encoding {tag}"),
+    }
+}
+
+pub(crate) fn encode_blob(value: &Value, encoding: BlobEncoding) -> anyhow::Result<Vec<u8>> {
+    let Some(tag) = encoding.tag() else {
+        return Ok(serde_json::to_vec(value)?);
+    };
+    let mut blob = BLOB_MAGIC.to_vec();
+    blob.extend([BLOB_FORMAT_VERSION, tag]);
+    match encoding {
+        BlobEncoding::Json => {}
+        BlobEncoding::MessagePack => blob.extend(rmp_serde::to_vec(value)?),
+        BlobEncoding::Bincode => blob.extend(bincode::serialize(&BincodeValue::from(value))?),
+    }
+    Ok(blob)
+}
+
+#[cfg_attr(not(feature = "postgres"), allow(dead_code))]
+pub(crate) fn decode_blob(blob: &[u8]) -> anyhow::Result<Value> {
+    match blob_encoding(blob)? {
+        BlobEncoding::Json => Ok(serde_json::from_slice(blob)?),
+        BlobEncoding::MessagePack => Ok(rmp_serde::from_slice(&blob[HEADER_LEN..])?),
+        BlobEncoding::Bincode => {
+            Ok(bincode::deserialize::<BincodeValue>(&blob[HEADER_LEN..])?.into())
+        }
+    }
+}
+
+/// A JSON value in a form bincode can read back, since bincode can't decode types like [`Value`]
+/// that only know their shape once they see the data.
+#[derive(Serialize, Deserialize)]
+enum BincodeValue {
+    Null,
+    Bool(bool),
+    U64(u64),
+    I64(i64),
+    F64(f64),
+    String(String),
+    Array(Vec<BincodeValue>),
+    Object(Vec<(String, BincodeValue)>),
+}
+
+impl From<&Value> for BincodeValue {
+    fn from(value: &Value) -> Self {
+        match value {
+            Value::Null => BincodeValue::Null,
+            Value::Bool(value) => BincodeValue::Bool(*value),
+            Value::Number(number) => {
+                if let Some(number) = number.as_u64() {
+                    BincodeValue::U64(number)
+                } else if let Some(number) = number.as_i64() {
+                    BincodeValue::I64(number)
+                } else {
+                    BincodeValue::F64(number.as_f64().unwrap_or_default())
+                }
+            }
+            Value::String(value) => BincodeValue::String(value.clone()),
+            Value::Array(values) => BincodeValue::Array(values.iter().map(Into::into).collect()),
+            Value::Object(map) => BincodeValue::Object(
+                map.iter()
+                    .map(|(key, value)| (key.clone(), value.into()))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl From<BincodeValue> for Value {
+    fn from(value: BincodeValue) -> Self {
+        match value {
+            BincodeValue::Null => Value::Null,
+            BincodeValue::Bool(value) => Value::Bool(value),
+            BincodeValue::U64(number) => Value::from(number),
+            BincodeValue::I64(number) => Value::from(number),
+            BincodeValue::F64(number) => serde_json::Number::from_f64(number)
+                .map(Value::Number)
+                .unwrap_or(Value::Null),
+            BincodeValue::String(value) => Value::String(value),
+            BincodeValue::Array(values) => {
+                Value::Array(values.into_iter().map(Into::into).collect())
+            }
+            BincodeValue::Object(entries) => Value::Object(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (key, value.into()))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trips_every_encoding() {
+        let value = serde_json::json!([{
+            "content": "hello",
+            "id": "1",
+            "example": false,
+            "response_metadata": { "usage": { "input_tokens": 12, "delta": -3, "cost": 0.5 } },
+            "name": null,
+        }]);
+        for encoding in [
+            BlobEncoding::Json,
+            BlobEncoding::MessagePack,
+            BlobEncoding::Bincode,
+        ] {
+            let blob = encode_blob(&value, encoding).unwrap();
+            assert_eq!(blob_encoding(&blob).unwrap(), encoding);
+            assert_eq!(decode_blob(&blob).unwrap(), value);
+        }
+
+        let mut unknown_version = encode_blob(&value, BlobEncoding::Bincode).unwrap();
+        unknown_version[BLOB_MAGIC.len()] = BLOB_FORMAT_VERSION + 1;
+        assert!(decode_blob(&unknown_version).is_err());
+    }
+}
@@ -0,0 +1,76 @@
+use collections::HashMap;
+use parking_lot::Mutex;
+
+/// What the handler tracks per thread or per request between calls, e.g. the prompt counted for a
+/// checkpoint until its completion ends. Entries that are never taken back out, e.g. because a
+/// stream was dropped before it ended, are evicted once the map is full, oldest first.
+pub(crate) struct BoundedMap<V> {
+    capacity: usize,
+    entries: Mutex<Entries<V>>,
+}
+
+struct Entries<V> {
+    next_insert: u64,
+    values: HashMap<String, (u64, V)>,
+}
+
+impl<V: Clone> BoundedMap<V> {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: Mutex::new(Entries {
+                next_insert: 0,
+                values: HashMap::default(),
+            }),
+        }
+    }
+
+    /// Inserts the value, returning the one it replaced.
+    pub(crate) fn insert(&self, key: String, value: V) -> Option<V> {
+        let mut entries = self.entries.lock();
+        if entries.values.len() >= self.capacity && !entries.values.contains_key(&key) {
+            let oldest = entries
+                .values
+                .iter()
+                .min_by_key(|(_, (inserted, _))| *inserted)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                entries.values.remove(&oldest);
+            }
+        }
+        let inserted = entries.next_insert;
+        entries.next_insert += 1;
+        entries
+            .values
+            .insert(key, (inserted, value))
+            .map(|(_, value)| value)
+    }
+
+    pub(crate) fn get(&self, key: &str) -> Option<V> {
+        let entries = self.entries.lock();
+        entries.values.get(key).map(|(_, value)| value.clone())
+    }
+
+    pub(crate) fn remove(&self, key: &str) -> Option<V> {
+        let mut entries = self.entries.lock();
+        entries.values.remove(key).map(|(_, value)| value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_evicts_oldest_once_full() {
+        let map = BoundedMap::new(2);
+        assert_eq!(map.insert("a".into(), 1), None);
+        assert_eq!(map.insert("b".into(), 2), None);
+        assert_eq!(map.insert("a".into(), 3), Some(1));
+        map.insert("c".into(), 4);
+        assert_eq!(map.get("a"), Some(3));
+        assert_eq!(map.get("b"), None);
+        assert_eq!(map.remove("c"), Some(4));
+        assert_eq!(map.get("c"), None);
+    }
+}
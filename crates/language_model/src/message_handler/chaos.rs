@@ -0,0 +1,202 @@
+use anyhow::bail;
+use futures::StreamExt as _;
+use futures::stream::{self, BoxStream};
+use parking_lot::Mutex;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use crate::{LanguageModelCompletionError, LanguageModelCompletionEvent};
+
+/// Faults injected into the store or the providers, to exercise the paths taken when they are
+/// slow or failing, e.g. keeping messages in the local cache until the store is back.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct ChaosPolicy {
+    /// The fraction of operations that fail outright, from 0 to 1.
+    pub drop_rate: f64,
+    /// How long each operation is delayed by, in milliseconds.
+    pub latency_ms: u64,
+    /// The fraction of completion streams that fail partway through, from 0 to 1. Only
+    /// providers' completions stream.
+    pub stream_error_rate: f64,
+    /// Seeds which operations fail, to inject the same faults on each run. Random when unset.
+    pub seed: Option<u64>,
+}
+
+impl Default for ChaosPolicy {
+    fn default() -> Self {
+        Self {
+            drop_rate: 0.0,
+            latency_ms: 0,
+            stream_error_rate: 0.0,
+            seed: None,
+        }
+    }
+}
+
+/// Where faults are injected.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct ChaosConfig {
+    /// Faults in the operations on the message store.
+    pub store: Option<ChaosPolicy>,
+    /// Faults in the completions of every provider.
+    pub providers: Option<ChaosPolicy>,
+}
+
+/// Injects the faults of a policy.
+pub struct Chaos {
+    policy: ChaosPolicy,
+    /// The state of the xorshift generator the faults are drawn from.
+    state: Mutex<u64>,
+}
+
+/// How many events a completion stream that fails partway through sends at most before failing.
+const MAX_EVENTS_BEFORE_FAULT: f64 = 8.0;
+
+impl Chaos {
+    pub fn new(policy: ChaosPolicy) -> Self {
+        let seed = policy
+            .seed
+            .unwrap_or_else(|| uuid::Uuid::new_v4().as_u128() as u64);
+        Self {
+            policy,
+            // Xorshift never leaves zero.
+            state: Mutex::new(seed.max(1)),
+        }
+    }
+
+    /// A number in `[0, 1)`.
+    fn roll(&self) -> f64 {
+        let mut state = self.state.lock();
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        (*state >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn latency(&self) -> Option<Duration> {
+        (self.policy.latency_ms > 0).then(|| Duration::from_millis(self.policy.latency_ms))
+    }
+
+    /// Delays the operation, then fails it, as the policy says.
+    pub async fn disrupt(&self, operation: &str) -> anyhow::Result<()> {
+        if let Some(latency) = self.latency() {
+            smol::Timer::after(latency).await;
+        }
+        if self.roll() < self.policy.drop_rate {
+            bail!("injected fault: {operation} was dropped");
+        }
+        Ok(())
+    }
+
+    /// Delays the stream, then fails it before its first event or partway through, as the policy
+    /// says.
+    pub fn disrupt_stream<T>(
+        &self,
+        stream: T,
+    ) -> BoxStream<'static, Result<LanguageModelCompletionEvent, LanguageModelCompletionError>>
+    where
+        T: futures::Stream<
+                Item = Result<LanguageModelCompletionEvent, LanguageModelCompletionError>,
+            > + Send
+            + 'static,
+    {
+        let fail_after = if self.roll() < self.policy.drop_rate {
+            Some(0)
+        } else if self.roll() < self.policy.stream_error_rate {
+            Some(1 + (self.roll() * MAX_EVENTS_BEFORE_FAULT) as usize)
+        } else {
+            None
+        };
+        let latency = self.latency();
+        let delay = stream::once(async move {
+            if let Some(latency) = latency {
+                smol::Timer::after(latency).await;
+            }
+        })
+        .filter_map(|_| async { None });
+        let Some(fail_after) = fail_after else {
+            return delay.chain(stream).boxed();
+        };
+        let failing = stream::unfold(
+            (stream.boxed(), Some(0)),
+            move |(mut stream, sent)| async move {
+                let sent = sent?;
+                if sent == fail_after {
+                    let error = LanguageModelCompletionError::Other(anyhow::Error::new(
+                        std::io::Error::new(
+                            std::io::ErrorKind::ConnectionReset,
+                            "injected fault: the completion stream was interrupted",
+                        ),
+                    ));
+                    return Some((Err(error), (stream, None)));
+                }
+                let event = stream.next().await?;
+                Some((event, (stream, Some(sent + 1))))
+            },
+        );
+        delay.chain(failing).boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::StopReason;
+
+    #[test]
+    fn test_faults_follow_the_policy() {
+        let chaos = |drop_rate, stream_error_rate| {
+            Chaos::new(ChaosPolicy {
+                drop_rate,
+                stream_error_rate,
+                seed: Some(7),
+                ..ChaosPolicy::default()
+            })
+        };
+        let events = || {
+            stream::iter(
+                [
+                    LanguageModelCompletionEvent::Text("Hello".into()),
+                    LanguageModelCompletionEvent::Stop(StopReason::EndTurn),
+                ]
+                .into_iter()
+                .map(Ok),
+            )
+        };
+
+        smol::block_on(async {
+            assert!(chaos(1.0, 0.0).disrupt("save").await.is_err());
+            assert!(chaos(0.0, 1.0).disrupt("save").await.is_ok());
+
+            let dropped = chaos(1.0, 0.0)
+                .disrupt_stream(events())
+                .collect::<Vec<_>>()
+                .await;
+            assert!(matches!(dropped.as_slice(), [Err(_)]));
+
+            let intact = chaos(0.0, 0.0)
+                .disrupt_stream(events())
+                .collect::<Vec<_>>()
+                .await;
+            assert!(intact.iter().all(Result::is_ok));
+            assert_eq!(intact.len(), 2);
+
+            let long = || {
+                stream::iter(
+                    (0..20).map(|ix| Ok(LanguageModelCompletionEvent::Text(ix.to_string()))),
+                )
+            };
+            let interrupted = chaos(0.0, 1.0)
+                .disrupt_stream(long())
+                .collect::<Vec<_>>()
+                .await;
+            let (last, sent) = interrupted.split_last().unwrap();
+            assert!(last.is_err());
+            assert!(!sent.is_empty() && sent.len() <= MAX_EVENTS_BEFORE_FAULT as usize);
+            assert!(sent.iter().all(Result::is_ok));
+        });
+    }
+}
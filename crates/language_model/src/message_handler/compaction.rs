@@ -0,0 +1,123 @@
+use futures::future::BoxFuture;
+use std::collections::HashMap;
+
+use super::{ContentValue, Message};
+use crate::RequestIds;
+
+/// The intent the summaries of threads are stored under.
+pub(crate) const CONTEXT_SUMMARY_INTENT: &str = "ThreadContextSummarization";
+
+/// When a thread's stored history is summarized so that older checkpoints can be left out of
+/// what is loaded to resume it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompactionPolicy {
+    /// Tokens across the thread's uncompacted checkpoints past which it is compacted.
+    pub max_stored_tokens: u64,
+}
+
+/// Writes the summary that stands in for a thread's compacted checkpoints, e.g. by asking a model.
+pub trait ContextSummarizer: Send + Sync {
+    /// `ids` are those of the thread's latest request. Requests made for the summary under them
+    /// with the [`CONTEXT_SUMMARY_INTENT`] aren't stored, since they hold the thread's history
+    /// again; compaction stores the summary itself.
+    fn summarize(
+        &self,
+        ids: &RequestIds,
+        messages: Vec<Message>,
+    ) -> BoxFuture<'static, anyhow::Result<String>>;
+}
+
+/// The tokens counted for the messages when they were saved: each prompt message's count and
+/// each completion's `token_usage` event.
+#[cfg_attr(not(feature = "postgres"), allow(dead_code))]
+pub(crate) fn stored_token_count(messages: &[Message]) -> i64 {
+    messages
+        .iter()
+        .map(|message| {
+            let prompt_tokens = message
+                .response_metadata()
+                .get("token_count")
+                .and_then(|count| count.get("tokens")?.as_i64());
+            let completion_tokens = match message {
+                Message::System {
+                    additional_kwargs, ..
+                } if additional_kwargs
+                    .get("event")
+                    .and_then(|event| event.as_str())
+                    == Some("token_usage") =>
+                {
+                    additional_kwargs
+                        .get("completion_tokens")
+                        .and_then(|tokens| tokens.as_i64())
+                }
+                _ => None,
+            };
+            prompt_tokens.unwrap_or_default() + completion_tokens.unwrap_or_default()
+        })
+        .sum()
+}
+
+/// The summary of the thread's checkpoints up to `compacted_through_seq`. Its intent stores it
+/// under the `context_summarization` task path.
+pub(crate) fn context_summary_message(
+    thread_id: &str,
+    summary: String,
+    compacted_through_seq: i64,
+    agent_name: &str,
+) -> Message {
+    Message::System {
+        content: ContentValue::new(summary),
+        id: thread_id.to_string(),
+        name: Some(agent_name.to_string()),
+        example: false,
+        additional_kwargs: HashMap::from_iter([
+            (
+                "event".to_string(),
+                serde_json::Value::from("context_summary"),
+            ),
+            (
+                "compacted_through_seq".to_string(),
+                serde_json::Value::from(compacted_through_seq),
+            ),
+        ]),
+        response_metadata: HashMap::from_iter([(
+            "intent".to_string(),
+            serde_json::Value::from(CONTEXT_SUMMARY_INTENT),
+        )]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message_handler::DEFAULT_AGENT_NAME;
+
+    #[test]
+    fn test_stored_token_count() {
+        let mut summary =
+            context_summary_message("thread", "The user asked...".into(), 3, DEFAULT_AGENT_NAME);
+        assert_eq!(stored_token_count(&[summary.clone()]), 0);
+
+        if let Message::System {
+            response_metadata, ..
+        } = &mut summary
+        {
+            response_metadata.insert(
+                "token_count".to_string(),
+                serde_json::json!({ "tokens": 5, "tokenizer": "estimate" }),
+            );
+        }
+        let usage = Message::System {
+            content: ContentValue::new("token_usage".into()),
+            id: "thread".into(),
+            name: None,
+            example: false,
+            additional_kwargs: HashMap::from_iter([
+                ("event".to_string(), serde_json::json!("token_usage")),
+                ("completion_tokens".to_string(), serde_json::json!(40)),
+            ]),
+            response_metadata: HashMap::new(),
+        };
+        assert_eq!(stored_token_count(&[summary, usage]), 45);
+    }
+}
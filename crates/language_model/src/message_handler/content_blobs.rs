@@ -0,0 +1,135 @@
+use collections::{HashMap, HashSet};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+/// Strings at least this long are stored once in `content_blobs` and referenced by hash from the
+/// checkpoints that contain them. File contents attached as context repeat across turns, so most
+/// of a file-heavy thread's size is the same few strings.
+pub(crate) const CONTENT_BLOB_MIN_LEN: usize = 1024;
+
+/// The key of the object that stands in for a string moved to `content_blobs`.
+const CONTENT_BLOB_REF: &str = "$content_blob";
+
+pub(crate) fn content_hash(content: &str) -> String {
+    format!("{:x}", Sha256::digest(content.as_bytes()))
+}
+
+/// Replaces each string of at least `min_len` bytes in `value` with a reference to its hash,
+/// returning the strings by hash.
+pub(crate) fn extract_content_blobs(value: &mut Value, min_len: usize) -> HashMap<String, String> {
+    let mut blobs = HashMap::default();
+    extract(value, min_len, &mut blobs);
+    blobs
+}
+
+fn extract(value: &mut Value, min_len: usize, blobs: &mut HashMap<String, String>) {
+    match value {
+        Value::String(content) if content.len() >= min_len => {
+            let content = std::mem::take(content);
+            let hash = content_hash(&content);
+            *value = serde_json::json!({ CONTENT_BLOB_REF: hash });
+            blobs.insert(hash, content);
+        }
+        Value::Array(values) => {
+            for value in values {
+                extract(value, min_len, blobs);
+            }
+        }
+        Value::Object(map) => {
+            for value in map.values_mut() {
+                extract(value, min_len, blobs);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// The hashes of the strings `value` references.
+pub(crate) fn referenced_content_blobs(value: &Value) -> HashSet<String> {
+    let mut hashes = HashSet::default();
+    referenced(value, &mut hashes);
+    hashes
+}
+
+fn referenced(value: &Value, hashes: &mut HashSet<String>) {
+    if let Some(hash) = content_blob_ref(value) {
+        hashes.insert(hash.to_string());
+        return;
+    }
+    match value {
+        Value::Array(values) => {
+            for value in values {
+                referenced(value, hashes);
+            }
+        }
+        Value::Object(map) => {
+            for value in map.values() {
+                referenced(value, hashes);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Puts the referenced strings back in place of their references.
+pub(crate) fn resolve_content_blobs(
+    value: &mut Value,
+    blobs: &HashMap<String, String>,
+) -> anyhow::Result<()> {
+    if let Some(hash) = content_blob_ref(value) {
+        let content = blobs
+            .get(hash)
+            .ok_or_else(|| anyhow::anyhow!("missing content blob {hash}"))?;
+        *value = Value::String(content.clone());
+        return Ok(());
+    }
+    match value {
+        Value::Array(values) => {
+            for value in values {
+                resolve_content_blobs(value, blobs)?;
+            }
+        }
+        Value::Object(map) => {
+            for value in map.values_mut() {
+                resolve_content_blobs(value, blobs)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn content_blob_ref(value: &Value) -> Option<&str> {
+    let map = value.as_object()?;
+    if map.len() != 1 {
+        return None;
+    }
+    map.get(CONTENT_BLOB_REF)?.as_str()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trips_and_deduplicates() {
+        let file = "fn main() {}\n".repeat(100);
+        let original = serde_json::json!([
+            { "content": file, "id": "1" },
+            { "content": [{ "text": file }], "id": "2" },
+        ]);
+
+        let mut stored = original.clone();
+        let blobs = extract_content_blobs(&mut stored, CONTENT_BLOB_MIN_LEN);
+        assert_eq!(blobs.len(), 1);
+        assert_eq!(stored[0]["id"], "1");
+        assert_eq!(
+            referenced_content_blobs(&stored),
+            blobs.keys().cloned().collect()
+        );
+
+        resolve_content_blobs(&mut stored, &blobs).unwrap();
+        assert_eq!(stored, original);
+        assert!(resolve_content_blobs(&mut original.clone(), &HashMap::default()).is_ok());
+    }
+}
@@ -0,0 +1,64 @@
+use serde::{Deserialize, Serialize};
+
+use super::Message;
+
+/// The fraction of the context window past which a request counts as nearing its limit, leaving
+/// room to summarize the thread before the next request overflows.
+pub const CONTEXT_WARNING_THRESHOLD: f32 = 0.8;
+
+/// How much of the model's context window a request's prompt took up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContextBudget {
+    pub used_tokens: usize,
+    pub max_tokens: usize,
+}
+
+impl ContextBudget {
+    pub fn utilization(&self) -> f32 {
+        if self.max_tokens == 0 {
+            return 0.;
+        }
+        self.used_tokens as f32 / self.max_tokens as f32
+    }
+
+    /// Whether the thread should be summarized before it overflows the context window.
+    pub fn is_near_limit(&self) -> bool {
+        self.utilization() >= CONTEXT_WARNING_THRESHOLD
+    }
+}
+
+/// Records the request's context utilization in each message's `response_metadata`.
+pub(crate) fn stamp_context_budget(messages: &mut [Message], budget: ContextBudget) {
+    let context_window = serde_json::json!({
+        "used_tokens": budget.used_tokens,
+        "max_tokens": budget.max_tokens,
+        "utilization": budget.utilization(),
+    });
+    for message in messages {
+        message
+            .response_metadata_mut()
+            .insert("context_window".to_string(), context_window.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_near_limit() {
+        let budget = |used_tokens| ContextBudget {
+            used_tokens,
+            max_tokens: 200_000,
+        };
+        assert!(!budget(100_000).is_near_limit());
+        assert!(budget(160_000).is_near_limit());
+        assert!(
+            !ContextBudget {
+                used_tokens: 10,
+                max_tokens: 0
+            }
+            .is_near_limit()
+        );
+    }
+}
@@ -0,0 +1,270 @@
+use collections::{HashMap, HashSet};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::tool_pairs::request_contents;
+use super::{ContentValue, Message, StoredMessage, StoredThread};
+use crate::MessageContent;
+
+/// The `response_metadata` entry of the names of the tools a request declared schemas for.
+pub(crate) const REQUEST_TOOLS: &str = "tools";
+
+/// How large a request may grow before its thread is flagged for never being summarized.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct LintPolicy {
+    /// The tokens a request may send while its thread has no context summary.
+    pub max_unsummarized_tokens: i64,
+    /// How many of the most recently updated threads are linted.
+    pub max_threads: usize,
+}
+
+impl Default for LintPolicy {
+    fn default() -> Self {
+        Self {
+            max_unsummarized_tokens: 100_000,
+            max_threads: 50,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LintKind {
+    /// A request sent more tokens than the policy allows, and the thread was never summarized.
+    UnsummarizedContext,
+    /// A request sent the same system prompt more than once.
+    RepeatedSystemPrompt,
+    /// A request sent tool calls or results of a tool it declared no schema for.
+    MissingToolSchema,
+}
+
+/// An anti-pattern found in one of a thread's stored requests.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LintFinding {
+    pub kind: LintKind,
+    /// The checkpoint of the first request it was found in.
+    pub seq: i64,
+    pub detail: String,
+}
+
+impl std::fmt::Display for LintFinding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "checkpoint {}: {}", self.seq, self.detail)
+    }
+}
+
+/// The findings of a stored thread that something was found wrong with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ThreadLint {
+    pub thread: StoredThread,
+    pub findings: Vec<LintFinding>,
+}
+
+/// The tokens counted for the request's messages when they were saved.
+fn prompt_tokens(messages: &[&Message]) -> i64 {
+    messages
+        .iter()
+        .filter_map(|message| {
+            message
+                .response_metadata()
+                .get("token_count")?
+                .get("tokens")?
+                .as_i64()
+        })
+        .sum()
+}
+
+fn is_context_summary(message: &Message) -> bool {
+    message
+        .additional_kwargs()
+        .get("event")
+        .and_then(|event| event.as_str())
+        == Some("context_summary")
+}
+
+/// The system prompts the request sent, leaving out the events stored as system messages.
+fn system_prompts<'a>(messages: &[&'a Message]) -> Vec<&'a str> {
+    messages
+        .iter()
+        .filter_map(|message| match message {
+            Message::System {
+                content: ContentValue::Single(content),
+                additional_kwargs,
+                ..
+            } if !additional_kwargs.contains_key("event") => Some(content.as_str()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The tools the request's messages call or hold results of, if it recorded which tools it
+/// declared. Requests stored before they did can't be told apart from those declaring none.
+fn undeclared_tools(messages: &[&Message]) -> Vec<String> {
+    let Some(declared) = messages.iter().find_map(|message| {
+        message
+            .response_metadata()
+            .get(REQUEST_TOOLS)?
+            .as_array()
+            .map(|tools| {
+                tools
+                    .iter()
+                    .filter_map(|tool| tool.as_str())
+                    .collect::<HashSet<_>>()
+            })
+    }) else {
+        return Vec::new();
+    };
+    let mut undeclared = Vec::new();
+    for message in messages {
+        for content in request_contents(message.content()).unwrap_or_default() {
+            let name = match content {
+                MessageContent::ToolUse(tool_use) => tool_use.name,
+                MessageContent::ToolResult(tool_result) => tool_result.tool_name,
+                _ => continue,
+            };
+            if !declared.contains(name.as_ref()) && !undeclared.contains(&name.to_string()) {
+                undeclared.push(name.to_string());
+            }
+        }
+    }
+    undeclared
+}
+
+/// The anti-patterns in the thread's stored requests: one grown past the policy's tokens while
+/// the thread was never summarized, system prompts sent twice in one request, and tools used
+/// without their schemas. Each request holds the thread's history up to it, so each finding is
+/// reported once, at the first request it was found in.
+pub fn lint_thread(messages: &[StoredMessage], policy: &LintPolicy) -> Vec<LintFinding> {
+    let mut checkpoints = Vec::<(i64, Vec<&Message>)>::new();
+    for message in messages {
+        match checkpoints.last_mut() {
+            Some((seq, messages)) if *seq == message.seq => messages.push(&message.message),
+            _ => checkpoints.push((message.seq, vec![&message.message])),
+        }
+    }
+
+    let mut findings = Vec::new();
+    let mut summarized = false;
+    let mut flagged_context = false;
+    let mut repeated_prompts = HashSet::default();
+    let mut undeclared = HashSet::default();
+    for (seq, messages) in checkpoints {
+        summarized |= messages.iter().any(|message| is_context_summary(message));
+        let tokens = prompt_tokens(&messages);
+        if !summarized && !flagged_context && tokens > policy.max_unsummarized_tokens {
+            flagged_context = true;
+            findings.push(LintFinding {
+                kind: LintKind::UnsummarizedContext,
+                seq,
+                detail: format!(
+                    "the request sent {tokens} tokens, and the thread was never summarized."
+                ),
+            });
+        }
+
+        let mut prompt_counts = HashMap::<&str, usize>::default();
+        for prompt in system_prompts(&messages) {
+            *prompt_counts.entry(prompt).or_default() += 1;
+        }
+        let mut repeated = prompt_counts
+            .into_iter()
+            .filter(|(prompt, count)| *count > 1 && repeated_prompts.insert(prompt.to_string()))
+            .collect::<Vec<_>>();
+        repeated.sort();
+        for (prompt, count) in repeated {
+            findings.push(LintFinding {
+                kind: LintKind::RepeatedSystemPrompt,
+                seq,
+                detail: format!(
+                    "the request sent the same {}-character system prompt {count} times.",
+                    prompt.len()
+                ),
+            });
+        }
+
+        for tool in undeclared_tools(&messages) {
+            if undeclared.insert(tool.clone()) {
+                findings.push(LintFinding {
+                    kind: LintKind::MissingToolSchema,
+                    seq,
+                    detail: format!("the request used `{tool}` without declaring its schema."),
+                });
+            }
+        }
+    }
+    findings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request_message(seq: i64, system: bool, content: &str, tokens: i64) -> StoredMessage {
+        let response_metadata = std::collections::HashMap::from_iter([
+            (
+                "token_count".to_string(),
+                serde_json::json!({ "tokens": tokens }),
+            ),
+            (REQUEST_TOOLS.to_string(), serde_json::json!(["read_file"])),
+        ]);
+        let content = ContentValue::new(content.to_string());
+        let message = if system {
+            Message::System {
+                content,
+                id: "thread".to_string(),
+                name: None,
+                example: false,
+                additional_kwargs: Default::default(),
+                response_metadata,
+            }
+        } else {
+            Message::Human {
+                content,
+                id: "thread".to_string(),
+                name: None,
+                example: false,
+                additional_kwargs: Default::default(),
+                response_metadata,
+            }
+        };
+        StoredMessage {
+            seq,
+            index: 0,
+            message,
+        }
+    }
+
+    #[test]
+    fn test_lint_thread() {
+        let tool_use = serde_json::json!([{ "ToolUse": {
+            "id": "call",
+            "name": "terminal",
+            "raw_input": "{}",
+            "input": {},
+            "is_input_complete": true
+        }}])
+        .to_string();
+        let messages = [
+            request_message(1, true, "You are an agent.", 10),
+            request_message(1, false, "\"hi\"", 90_000),
+            request_message(2, true, "You are an agent.", 10),
+            request_message(2, true, "You are an agent.", 10),
+            request_message(2, false, &tool_use, 120_000),
+            request_message(3, true, "You are an agent.", 10),
+            request_message(3, true, "You are an agent.", 10),
+            request_message(3, false, &tool_use, 130_000),
+        ];
+        let findings = lint_thread(&messages, &LintPolicy::default())
+            .into_iter()
+            .map(|finding| (finding.kind, finding.seq))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            findings,
+            [
+                (LintKind::UnsummarizedContext, 2),
+                (LintKind::RepeatedSystemPrompt, 2),
+                (LintKind::MissingToolSchema, 2),
+            ]
+        );
+    }
+}
@@ -0,0 +1,112 @@
+use serde::{Deserialize, Serialize};
+
+use super::Message;
+
+/// Tries several versions of a system-prompt template against each other. Each thread is
+/// assigned one of the variants, which are the names of other registered templates.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PromptExperiment {
+    pub id: String,
+    /// The template the variants stand in for.
+    pub template_id: String,
+    pub variants: Vec<String>,
+}
+
+impl PromptExperiment {
+    /// Picks the thread's variant. The pick looks random across threads but is the same every
+    /// time for a given thread, so that a thread keeps its variant across restarts.
+    pub fn assign(&self, thread_id: &str) -> Option<PromptExperimentAssignment> {
+        if self.variants.is_empty() {
+            return None;
+        }
+        let index =
+            fnv1a(format!("{}:{thread_id}", self.id).as_bytes()) % self.variants.len() as u64;
+        Some(PromptExperimentAssignment {
+            experiment_id: self.id.clone(),
+            variant: self.variants[index as usize].clone(),
+        })
+    }
+}
+
+/// The variant a thread was assigned, as recorded on its messages and outcomes.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct PromptExperimentAssignment {
+    pub experiment_id: String,
+    pub variant: String,
+}
+
+/// What is measured to compare variants.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ExperimentMetric {
+    /// 1 for each kept edit, 0 for each rejected one.
+    Acceptance,
+    /// 1 for each positive rating, 0 for each negative one.
+    Rating,
+}
+
+impl ExperimentMetric {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ExperimentMetric::Acceptance => "acceptance",
+            ExperimentMetric::Rating => "rating",
+        }
+    }
+}
+
+/// How a variant did on one metric.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VariantStats {
+    pub variant: String,
+    pub metric: String,
+    pub samples: i64,
+    pub threads: i64,
+    pub mean: f64,
+}
+
+/// Records the variant in each message's `response_metadata`.
+pub(crate) fn stamp_experiment(messages: &mut [Message], assignment: &PromptExperimentAssignment) {
+    let Ok(assignment) = serde_json::to_value(assignment) else {
+        return;
+    };
+    for message in messages {
+        message
+            .response_metadata_mut()
+            .insert("prompt_experiment".to_string(), assignment.clone());
+    }
+}
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use collections::HashSet;
+
+    #[test]
+    fn test_assignment_is_stable_and_spread() {
+        let experiment = PromptExperiment {
+            id: "terse".into(),
+            template_id: "assistant_system_prompt".into(),
+            variants: vec!["assistant_system_prompt".into(), "terse_prompt".into()],
+        };
+        let assigned = (0..64)
+            .map(|ix| {
+                let thread_id = format!("thread-{ix}");
+                let assignment = experiment.assign(&thread_id).unwrap();
+                assert_eq!(experiment.assign(&thread_id), Some(assignment.clone()));
+                assignment.variant
+            })
+            .collect::<HashSet<_>>();
+        assert_eq!(assigned.len(), 2);
+
+        let empty = PromptExperiment {
+            variants: Vec::new(),
+            ..experiment
+        };
+        assert_eq!(empty.assign("thread-0"), None);
+    }
+}
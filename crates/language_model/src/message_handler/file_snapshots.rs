@@ -0,0 +1,118 @@
+use collections::HashMap;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// The fields of a tool's input that name the files it works on.
+const TOOL_PATH_FIELDS: &[&str] = &["path", "source_path", "destination_path"];
+
+/// A file a thread's tool calls referenced, as it was after the latest of them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileSnapshot {
+    /// The project path, starting with the worktree's root name, as the tools take it.
+    pub path: String,
+    /// The [`content_hash`] of the file, or `None` if it didn't exist.
+    pub hash: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileChangeKind {
+    Modified,
+    Created,
+    Deleted,
+}
+
+/// A file that changed since a thread referenced it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileChange {
+    pub path: String,
+    pub kind: FileChangeKind,
+}
+
+pub fn content_hash(content: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(content))
+}
+
+/// The paths the tool call's input names.
+pub fn tool_input_paths(input: &serde_json::Value) -> impl Iterator<Item = &str> {
+    TOOL_PATH_FIELDS
+        .iter()
+        .filter_map(|field| input.get(field)?.as_str())
+        .filter(|path| !path.is_empty())
+}
+
+/// The snapshotted files whose hash differs from `current`, the hash of each file now, or `None`
+/// for the ones that no longer exist. Files missing from `current`, e.g. of a worktree that
+/// isn't open, are left out, since whether they changed isn't known.
+pub fn changed_files(
+    snapshots: &[FileSnapshot],
+    current: &HashMap<String, Option<String>>,
+) -> Vec<FileChange> {
+    snapshots
+        .iter()
+        .filter_map(|snapshot| {
+            let kind = match (&snapshot.hash, current.get(&snapshot.path)?) {
+                (Some(then), Some(now)) if then != now => FileChangeKind::Modified,
+                (Some(_), None) => FileChangeKind::Deleted,
+                (None, Some(_)) => FileChangeKind::Created,
+                _ => return None,
+            };
+            Some(FileChange {
+                path: snapshot.path.clone(),
+                kind,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_changed_files() {
+        let input = json!({"source_path": "zed/a.rs", "destination_path": "zed/b.rs", "path": ""});
+        assert_eq!(
+            tool_input_paths(&input).collect::<Vec<_>>(),
+            ["zed/a.rs", "zed/b.rs"]
+        );
+
+        let snapshot = |path: &str, content: Option<&str>| FileSnapshot {
+            path: path.into(),
+            hash: content.map(|content| content_hash(content.as_bytes())),
+        };
+        let snapshots = [
+            snapshot("zed/same.rs", Some("fn a() {}")),
+            snapshot("zed/edited.rs", Some("fn a() {}")),
+            snapshot("zed/deleted.rs", Some("fn a() {}")),
+            snapshot("zed/created.rs", None),
+            snapshot("other/unknown.rs", Some("fn a() {}")),
+        ];
+        let current = HashMap::from_iter(
+            [
+                snapshot("zed/same.rs", Some("fn a() {}")),
+                snapshot("zed/edited.rs", Some("fn b() {}")),
+                snapshot("zed/deleted.rs", None),
+                snapshot("zed/created.rs", Some("fn a() {}")),
+            ]
+            .map(|snapshot| (snapshot.path, snapshot.hash)),
+        );
+        assert_eq!(
+            changed_files(&snapshots, &current),
+            [
+                FileChange {
+                    path: "zed/edited.rs".into(),
+                    kind: FileChangeKind::Modified,
+                },
+                FileChange {
+                    path: "zed/deleted.rs".into(),
+                    kind: FileChangeKind::Deleted,
+                },
+                FileChange {
+                    path: "zed/created.rs".into(),
+                    kind: FileChangeKind::Created,
+                },
+            ]
+        );
+    }
+}
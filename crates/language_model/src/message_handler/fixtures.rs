@@ -0,0 +1,224 @@
+use anyhow::{Context as _, Result};
+use parking_lot::Mutex;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use super::response_cache::request_hash;
+use crate::{
+    CompletionStreamObserver, LanguageModelCompletionError, LanguageModelCompletionEvent,
+    LanguageModelId, LanguageModelRequest,
+};
+
+/// Whether completions are recorded as fixtures, or answered from them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum FixtureMode {
+    /// Requests are sent as usual, and each completed response is written as a fixture.
+    Record,
+    /// Requests are never sent: each is answered with its fixture, and fails without one.
+    Replay,
+}
+
+/// Where completion fixtures are kept, and whether they are recorded or replayed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct FixturePolicy {
+    pub mode: FixtureMode,
+    /// The directory of the fixtures, one JSON file per request.
+    ///
+    /// Default: the `completion_fixtures` directory in Zed's data directory
+    #[serde(default)]
+    pub directory: Option<PathBuf>,
+}
+
+/// A response as written to its fixture.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Fixture {
+    model_id: String,
+    events: Vec<LanguageModelCompletionEvent>,
+}
+
+/// Records the event streams of completions as fixtures, keyed by what the model was asked, and
+/// replays them in place of the providers, so that runs can be repeated exactly.
+pub struct CompletionFixtures {
+    mode: FixtureMode,
+    directory: PathBuf,
+    /// The fixture paths of the checkpoints whose responses are to be recorded once they
+    /// complete.
+    pending: Mutex<HashMap<String, PathBuf>>,
+}
+
+impl CompletionFixtures {
+    pub fn new(policy: FixturePolicy) -> Self {
+        Self {
+            mode: policy.mode,
+            directory: policy
+                .directory
+                .unwrap_or_else(|| paths::data_dir().join("completion_fixtures")),
+            pending: Mutex::default(),
+        }
+    }
+
+    pub fn mode(&self) -> FixtureMode {
+        self.mode
+    }
+
+    fn path(&self, request: &LanguageModelRequest, model_id: &LanguageModelId) -> Option<PathBuf> {
+        let key = request_hash(request, model_id)?;
+        Some(self.directory.join(format!("{key}.json")))
+    }
+
+    /// The events of the request's fixture.
+    pub fn load(
+        &self,
+        request: &LanguageModelRequest,
+        model_id: &LanguageModelId,
+    ) -> Result<Vec<LanguageModelCompletionEvent>> {
+        let path = self
+            .path(request, model_id)
+            .context("the request can't be keyed")?;
+        let fixture = std::fs::read(&path)
+            .with_context(|| format!("no completion fixture at {}", path.display()))?;
+        let fixture = serde_json::from_slice::<Fixture>(&fixture)
+            .with_context(|| format!("invalid completion fixture at {}", path.display()))?;
+        Ok(fixture.events)
+    }
+
+    fn save(&self, path: &Path, fixture: &Fixture) -> Result<()> {
+        std::fs::create_dir_all(&self.directory)?;
+        std::fs::write(path, serde_json::to_vec_pretty(fixture)?)?;
+        Ok(())
+    }
+
+    /// Records the checkpoint's response once it completes, when recording.
+    pub(crate) fn expect_response(
+        &self,
+        request: &LanguageModelRequest,
+        model_id: &LanguageModelId,
+        checkpoint_id: &str,
+    ) {
+        if self.mode != FixtureMode::Record {
+            return;
+        }
+        if let Some(path) = self.path(request, model_id) {
+            self.pending.lock().insert(checkpoint_id.to_string(), path);
+        }
+    }
+
+    /// An observer that records the checkpoint's response, if it is expected.
+    pub(crate) fn recorder(
+        self: &Arc<Self>,
+        checkpoint_id: &str,
+        model_id: &LanguageModelId,
+    ) -> Option<FixtureRecorder> {
+        let path = self.pending.lock().remove(checkpoint_id)?;
+        Some(FixtureRecorder {
+            fixtures: self.clone(),
+            path,
+            model_id: model_id.0.to_string(),
+            events: Mutex::default(),
+            stopped: AtomicBool::new(false),
+            failed: AtomicBool::new(false),
+        })
+    }
+}
+
+/// Collects a response's events, and writes them as a fixture if the model stopped without an
+/// error.
+pub(crate) struct FixtureRecorder {
+    fixtures: Arc<CompletionFixtures>,
+    path: PathBuf,
+    model_id: String,
+    events: Mutex<Vec<LanguageModelCompletionEvent>>,
+    stopped: AtomicBool,
+    failed: AtomicBool,
+}
+
+impl CompletionStreamObserver for FixtureRecorder {
+    fn on_event(&self, event: &LanguageModelCompletionEvent) {
+        if matches!(event, LanguageModelCompletionEvent::Stop(_)) {
+            self.stopped.store(true, Ordering::SeqCst);
+        }
+        self.events.lock().push(event.clone());
+    }
+
+    fn on_error(&self, _error: &LanguageModelCompletionError) {
+        self.failed.store(true, Ordering::SeqCst);
+    }
+
+    fn on_end(&self) {
+        if !self.stopped.load(Ordering::SeqCst) || self.failed.load(Ordering::SeqCst) {
+            return;
+        }
+        let fixture = Fixture {
+            model_id: self.model_id.clone(),
+            events: std::mem::take(&mut *self.events.lock()),
+        };
+        if let Err(error) = self.fixtures.save(&self.path, &fixture) {
+            log::error!(
+                "Failed to record the completion fixture at {}: {error:#}",
+                self.path.display()
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{LanguageModelRequestMessage, MessageContent, Role, StopReason};
+
+    #[test]
+    fn test_recorded_responses_are_replayed() {
+        let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let policy = |mode| FixturePolicy {
+            mode,
+            directory: Some(directory.clone()),
+        };
+        let model_id = LanguageModelId("model".into());
+        let request = |text: &str| LanguageModelRequest {
+            thread_id: Some(uuid::Uuid::new_v4().to_string()),
+            messages: vec![LanguageModelRequestMessage {
+                role: Role::User,
+                content: vec![MessageContent::Text(text.into())],
+                cache: false,
+            }],
+            ..Default::default()
+        };
+        let events = vec![
+            LanguageModelCompletionEvent::Text("Hello".into()),
+            LanguageModelCompletionEvent::Stop(StopReason::EndTurn),
+        ];
+
+        let recording = Arc::new(CompletionFixtures::new(policy(FixtureMode::Record)));
+        recording.expect_response(&request("Greet me"), &model_id, "failed");
+        let recorder = recording.recorder("failed", &model_id).unwrap();
+        recorder.on_event(&events[0]);
+        recorder.on_error(&LanguageModelCompletionError::Other(anyhow::anyhow!(
+            "overloaded"
+        )));
+        recorder.on_end();
+        assert!(recording.load(&request("Greet me"), &model_id).is_err());
+
+        recording.expect_response(&request("Greet me"), &model_id, "completed");
+        let recorder = recording.recorder("completed", &model_id).unwrap();
+        for event in &events {
+            recorder.on_event(event);
+        }
+        recorder.on_end();
+
+        let replaying = Arc::new(CompletionFixtures::new(policy(FixtureMode::Replay)));
+        assert_eq!(
+            replaying.load(&request("Greet me"), &model_id).unwrap(),
+            events
+        );
+        assert!(replaying.load(&request("Greet them"), &model_id).is_err());
+        replaying.expect_response(&request("Greet me"), &model_id, "replayed");
+        assert!(replaying.recorder("replayed", &model_id).is_none());
+
+        std::fs::remove_dir_all(directory).ok();
+    }
+}
@@ -0,0 +1,72 @@
+use collections::HashMap;
+
+/// What a garbage collection of the store deleted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GarbageCollection {
+    pub checkpoints: u64,
+    pub content_blobs: u64,
+}
+
+/// A stored checkpoint, as garbage collection sees it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CollectedCheckpoint {
+    pub thread_id: String,
+    pub checkpoint_id: String,
+    pub seq: i64,
+    /// Whether the checkpoint is kept regardless of the others: it was written within the
+    /// retention period, or something (e.g. a branch or an annotation) references it.
+    pub root: bool,
+}
+
+/// The checkpoints no root reaches. A root reaches the checkpoints of its thread up to itself,
+/// since whatever references it is about the conversation that led up to it, and a context
+/// summary stands in for the checkpoints before it. So a thread is only pruned back to its
+/// latest root, and deleted whole if it has none.
+pub fn unreachable_checkpoints(checkpoints: &[CollectedCheckpoint]) -> Vec<&CollectedCheckpoint> {
+    let mut reachable_through = HashMap::default();
+    for checkpoint in checkpoints.iter().filter(|checkpoint| checkpoint.root) {
+        let through = reachable_through
+            .entry(checkpoint.thread_id.as_str())
+            .or_insert(checkpoint.seq);
+        *through = (*through).max(checkpoint.seq);
+    }
+    checkpoints
+        .iter()
+        .filter(|checkpoint| {
+            reachable_through
+                .get(checkpoint.thread_id.as_str())
+                .is_none_or(|through| checkpoint.seq > *through)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checkpoints_before_a_root_are_reachable() {
+        let checkpoint = |thread_id: &str, seq, root| CollectedCheckpoint {
+            thread_id: thread_id.into(),
+            checkpoint_id: format!("{thread_id}-{seq}"),
+            seq,
+            root,
+        };
+        let checkpoints = [
+            // Branched from at its second checkpoint, and continued past it since.
+            checkpoint("branched", 1, false),
+            checkpoint("branched", 2, true),
+            checkpoint("branched", 3, false),
+            // Written to within the retention period.
+            checkpoint("active", 4, false),
+            checkpoint("active", 5, true),
+            checkpoint("abandoned", 6, false),
+            checkpoint("abandoned", 7, false),
+        ];
+        let unreachable = unreachable_checkpoints(&checkpoints)
+            .into_iter()
+            .map(|checkpoint| checkpoint.checkpoint_id.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(unreachable, ["branched-3", "abandoned-6", "abandoned-7"]);
+    }
+}
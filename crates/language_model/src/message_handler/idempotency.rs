@@ -0,0 +1,134 @@
+use sha2::{Digest, Sha256};
+
+use super::Message;
+use crate::RequestIds;
+
+const IDEMPOTENCY_KEY: &str = "idempotency_key";
+
+/// Which write of a checkpoint a batch of messages is. Together with the thread and checkpoint it
+/// identifies the write, so that a retried or replayed one is recognized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckpointEvent {
+    /// The request's messages.
+    Request,
+    /// The note that the thread moved to another model.
+    ModelTransition,
+    /// The completion's events, numbered in the order the stream produced them.
+    Completion(usize),
+    CompletionError,
+    /// The note that the user cancelled the completion before it ended.
+    Cancelled,
+    TokenUsage,
+    /// The note that an interceptor blocked the request.
+    RequestBlocked,
+    /// The note of the secrets found in the request's context.
+    SecretScan,
+    /// The structured output the completion's text was parsed into, or why it couldn't be.
+    StructuredOutput,
+    /// The messages a thread forked as a template starts with.
+    Template,
+    /// The note of where an interrupted response was spliced with the rest of it.
+    StreamSplice,
+}
+
+/// The key of a write, the same every time it is made.
+pub fn idempotency_key(ids: &RequestIds, event: CheckpointEvent) -> String {
+    let event = match event {
+        CheckpointEvent::Request => "request".to_string(),
+        CheckpointEvent::ModelTransition => "model_transition".to_string(),
+        CheckpointEvent::Completion(index) => format!("completion:{index}"),
+        CheckpointEvent::CompletionError => "completion_error".to_string(),
+        CheckpointEvent::Cancelled => "cancelled".to_string(),
+        CheckpointEvent::TokenUsage => "token_usage".to_string(),
+        CheckpointEvent::RequestBlocked => "request_blocked".to_string(),
+        CheckpointEvent::SecretScan => "secret_scan".to_string(),
+        CheckpointEvent::StructuredOutput => "structured_output".to_string(),
+        CheckpointEvent::Template => "template".to_string(),
+        CheckpointEvent::StreamSplice => "stream_splice".to_string(),
+    };
+    let hash = Sha256::digest(format!("{}\0{}\0{event}", ids.thread_id, ids.checkpoint_id));
+    format!("{hash:x}")
+}
+
+/// Records the write's key in the messages' `response_metadata`, where every backend, and the
+/// machines messages are forwarded to, read it from.
+pub(crate) fn stamp_idempotency_key(
+    messages: &mut [Message],
+    ids: &RequestIds,
+    event: CheckpointEvent,
+) {
+    let key = serde_json::Value::from(idempotency_key(ids, event));
+    for message in messages {
+        message
+            .response_metadata_mut()
+            .insert(IDEMPOTENCY_KEY.to_string(), key.clone());
+    }
+}
+
+/// Gives a part of a write that was split up, e.g. by the message rules, a key of its own, so
+/// that the parts after the first aren't taken for replays of it.
+pub(crate) fn split_idempotency_key(messages: &mut [Message], part: usize) {
+    let Some(key) = write_idempotency_key(messages) else {
+        return;
+    };
+    let key = serde_json::Value::from(format!("{key}:{part}"));
+    for message in messages {
+        message
+            .response_metadata_mut()
+            .insert(IDEMPOTENCY_KEY.to_string(), key.clone());
+    }
+}
+
+/// The key of the write the messages were stamped for, if any. Messages without one are always
+/// written.
+pub(crate) fn write_idempotency_key(messages: &[Message]) -> Option<&str> {
+    messages
+        .first()?
+        .response_metadata()
+        .get(IDEMPOTENCY_KEY)?
+        .as_str()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message_handler::ContentValue;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_idempotency_key_is_deterministic() {
+        let ids = |checkpoint_id: &str| RequestIds {
+            thread_id: "thread".into(),
+            checkpoint_id: checkpoint_id.into(),
+            session_id: "session".into(),
+            prompt_id: "prompt".into(),
+        };
+        assert_eq!(
+            idempotency_key(&ids("1"), CheckpointEvent::Completion(3)),
+            idempotency_key(&ids("1"), CheckpointEvent::Completion(3))
+        );
+        assert_ne!(
+            idempotency_key(&ids("1"), CheckpointEvent::Completion(3)),
+            idempotency_key(&ids("1"), CheckpointEvent::Completion(4))
+        );
+        assert_ne!(
+            idempotency_key(&ids("1"), CheckpointEvent::Request),
+            idempotency_key(&ids("2"), CheckpointEvent::Request)
+        );
+
+        let mut messages = vec![Message::Human {
+            content: ContentValue::new("hi".into()),
+            id: "1".into(),
+            name: None,
+            example: false,
+            additional_kwargs: HashMap::new(),
+            response_metadata: HashMap::new(),
+        }];
+        assert_eq!(write_idempotency_key(&messages), None);
+        stamp_idempotency_key(&mut messages, &ids("1"), CheckpointEvent::Request);
+        assert_eq!(
+            write_idempotency_key(&messages),
+            Some(idempotency_key(&ids("1"), CheckpointEvent::Request).as_str())
+        );
+    }
+}
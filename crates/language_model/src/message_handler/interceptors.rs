@@ -0,0 +1,207 @@
+use futures::{Stream, StreamExt as _, stream::BoxStream};
+use gpui::AsyncApp;
+use std::sync::Arc;
+
+use super::{MessageHandlerRegistry, request_interceptors, response_interceptors};
+use crate::{
+    LanguageModel, LanguageModelCompletionError, LanguageModelCompletionEvent, LanguageModelRequest,
+};
+
+/// Returned by an interceptor to stop a request from being sent.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("request blocked by {interceptor}: {reason}")]
+pub struct RequestBlocked {
+    pub interceptor: String,
+    /// Why, without repeating what was found, since that may be what shouldn't leave the machine.
+    pub reason: String,
+}
+
+/// Changes requests before they are sent, whichever provider they are sent to, e.g. to add
+/// metadata to the system prompt, enforce a limit on what is sent, or append retrieved context.
+pub trait RequestInterceptor: Send + Sync {
+    /// Called with each request just before the model's provider maps, stores and sends it.
+    /// Returning an error blocks the request, and the interceptors after this one aren't called.
+    fn intercept(
+        &self,
+        request: &mut LanguageModelRequest,
+        model: &dyn LanguageModel,
+    ) -> Result<(), RequestBlocked>;
+}
+
+/// Scans the request's worktree context for secrets, then runs the registered interceptors over
+/// it, in the order they were registered. Every provider calls this first, so that what is
+/// stored is what was sent. A blocked request is recorded in its thread instead.
+pub fn intercept_request(
+    mut request: LanguageModelRequest,
+    model: &dyn LanguageModel,
+    cx: &AsyncApp,
+) -> Result<LanguageModelRequest, RequestBlocked> {
+    let interceptors = cx.update(|cx| request_interceptors(cx)).unwrap_or_default();
+    let handler = cx
+        .update(|cx| {
+            cx.try_global::<MessageHandlerRegistry>()?
+                .message_handler
+                .clone()
+        })
+        .ok()
+        .flatten();
+    let result = match &handler {
+        Some(handler) => handler.scan_secrets(&mut request, model.id()),
+        None => Ok(()),
+    }
+    .and_then(|()| apply_interceptors(&interceptors, &mut request, model));
+    if let Err(blocked) = result {
+        log::warn!("{blocked}");
+        if let Some(handler) = handler {
+            handler.record_request_blocked(&request, model.id(), &blocked);
+        }
+        return Err(blocked);
+    }
+    Ok(request)
+}
+
+/// Changes the events of completion streams before they reach whoever requested them, e.g. to
+/// strip a provider's boilerplate or make absolute paths relative to the workspace. What is
+/// stored is what the model sent.
+pub trait ResponseInterceptor: Send + Sync {
+    /// What the interceptor is disabled by, for a provider, in `disabled_response_interceptors`.
+    fn name(&self) -> &str;
+
+    /// Called with each event, in the order the interceptors were registered.
+    fn intercept(&self, event: &mut LanguageModelCompletionEvent);
+}
+
+/// The response interceptors enabled for a model's provider, taken when its completion is
+/// requested.
+pub struct ResponseInterceptors(Vec<Arc<dyn ResponseInterceptor>>);
+
+impl ResponseInterceptors {
+    pub fn for_model(model: &dyn LanguageModel, cx: &AsyncApp) -> Self {
+        let provider_id = model.provider_id();
+        Self(
+            cx.update(|cx| response_interceptors(&provider_id, cx))
+                .unwrap_or_default(),
+        )
+    }
+
+    pub fn apply<T>(
+        self,
+        stream: T,
+    ) -> BoxStream<'static, Result<LanguageModelCompletionEvent, LanguageModelCompletionError>>
+    where
+        T: Stream<Item = Result<LanguageModelCompletionEvent, LanguageModelCompletionError>>
+            + Send
+            + 'static,
+    {
+        if self.0.is_empty() {
+            return stream.boxed();
+        }
+        stream
+            .map(move |event| {
+                event.map(|mut event| {
+                    for interceptor in &self.0 {
+                        interceptor.intercept(&mut event);
+                    }
+                    event
+                })
+            })
+            .boxed()
+    }
+}
+
+fn apply_interceptors(
+    interceptors: &[Arc<dyn RequestInterceptor>],
+    request: &mut LanguageModelRequest,
+    model: &dyn LanguageModel,
+) -> Result<(), RequestBlocked> {
+    for interceptor in interceptors {
+        interceptor.intercept(request, model)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake_provider::FakeLanguageModel;
+    use crate::{LanguageModelRequestMessage, MessageContent, Role};
+
+    struct AppendSystemMessage(&'static str);
+
+    impl RequestInterceptor for AppendSystemMessage {
+        fn intercept(
+            &self,
+            request: &mut LanguageModelRequest,
+            model: &dyn LanguageModel,
+        ) -> Result<(), RequestBlocked> {
+            request.messages.push(LanguageModelRequestMessage {
+                role: Role::System,
+                content: vec![MessageContent::Text(format!("{} {}", self.0, model.id().0))],
+                cache: false,
+            });
+            Ok(())
+        }
+    }
+
+    struct ReplaceText(&'static str, &'static str);
+
+    impl ResponseInterceptor for ReplaceText {
+        fn name(&self) -> &str {
+            "replace_text"
+        }
+
+        fn intercept(&self, event: &mut LanguageModelCompletionEvent) {
+            if let LanguageModelCompletionEvent::Text(text) = event {
+                *text = text.replace(self.0, self.1);
+            }
+        }
+    }
+
+    #[test]
+    fn test_interceptors_apply_in_registration_order() {
+        let interceptors: Vec<Arc<dyn RequestInterceptor>> = vec![
+            Arc::new(AppendSystemMessage("first")),
+            Arc::new(AppendSystemMessage("second")),
+        ];
+        let model = FakeLanguageModel::default();
+        let mut request = LanguageModelRequest::default();
+        apply_interceptors(&interceptors, &mut request, &model).unwrap();
+        let messages = request
+            .messages
+            .iter()
+            .map(|message| message.string_contents())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            [
+                format!("first {}", model.id().0),
+                format!("second {}", model.id().0)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_response_interceptors_apply_in_registration_order() {
+        let interceptors = ResponseInterceptors(vec![
+            Arc::new(ReplaceText("/home/me/project/", "")),
+            Arc::new(ReplaceText("src/main.rs", "`src/main.rs`")),
+        ]);
+        let stream = futures::stream::iter([
+            Ok(LanguageModelCompletionEvent::Text(
+                "See /home/me/project/src/main.rs".into(),
+            )),
+            Ok(LanguageModelCompletionEvent::StartMessage {
+                message_id: "message".into(),
+            }),
+        ]);
+        let events = smol::block_on(interceptors.apply(stream).collect::<Vec<_>>());
+        assert!(matches!(
+            &events[0],
+            Ok(LanguageModelCompletionEvent::Text(text)) if text == "See `src/main.rs`"
+        ));
+        assert!(matches!(
+            &events[1],
+            Ok(LanguageModelCompletionEvent::StartMessage { .. })
+        ));
+    }
+}
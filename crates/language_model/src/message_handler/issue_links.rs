@@ -0,0 +1,302 @@
+use anyhow::{Context as _, Result, anyhow, bail};
+use base64::Engine as _;
+use futures::AsyncReadExt;
+use http_client::{AsyncBody, HttpClient, Method, Request as HttpRequest};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::sync::Arc;
+use url::Url;
+
+pub const GITHUB_API_URL: &str = "https://api.github.com";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IssueTracker {
+    GitHub,
+    Jira,
+}
+
+impl IssueTracker {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IssueTracker::GitHub => "github",
+            IssueTracker::Jira => "jira",
+        }
+    }
+
+    pub fn from_name(tracker: &str) -> Option<Self> {
+        match tracker {
+            "github" => Some(IssueTracker::GitHub),
+            "jira" => Some(IssueTracker::Jira),
+            _ => None,
+        }
+    }
+}
+
+/// The issue a thread is about.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IssueLink {
+    pub tracker: IssueTracker,
+    /// `owner/repo#123` for GitHub issues, `PROJ-123` for Jira ones.
+    pub key: String,
+    pub url: String,
+}
+
+/// A stored thread's issue, and whether the thread was marked resolved.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ThreadIssue {
+    pub link: IssueLink,
+    pub resolved: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GitHubIssuesConfig {
+    pub api_url: String,
+    pub token: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct JiraConfig {
+    /// The site the issues are on, e.g. `https://acme.atlassian.net`.
+    pub base_url: String,
+    pub email: String,
+    pub api_token: String,
+}
+
+/// The trackers summaries of resolved threads are posted to. A tracker without credentials is
+/// only linked to.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct IssueTrackerConfig {
+    pub github: Option<GitHubIssuesConfig>,
+    pub jira: Option<JiraConfig>,
+    /// Where bare Jira keys, e.g. `PROJ-123`, point when no Jira credentials are set.
+    pub jira_base_url: Option<String>,
+    /// Whether marking a thread resolved posts its summary to its issue.
+    pub comment_on_resolve: bool,
+}
+
+impl IssueTrackerConfig {
+    fn jira_base_url(&self) -> Option<&str> {
+        self.jira
+            .as_ref()
+            .map(|jira| jira.base_url.as_str())
+            .or(self.jira_base_url.as_deref())
+    }
+}
+
+/// Reads the issue the user gave: a GitHub issue or pull request URL, `owner/repo#123`, a Jira
+/// issue URL, or a Jira key, which needs the Jira site to be known.
+pub fn parse_issue_link(input: &str, jira_base_url: Option<&str>) -> Result<IssueLink> {
+    let input = input.trim();
+    if let Ok(url) = Url::parse(input) {
+        let segments = url
+            .path_segments()
+            .map(|segments| segments.filter(|s| !s.is_empty()).collect::<Vec<_>>())
+            .unwrap_or_default();
+        return match segments.as_slice() {
+            [.., "browse", key] if is_jira_key(key) => Ok(IssueLink {
+                tracker: IssueTracker::Jira,
+                key: key.to_string(),
+                url: input.to_string(),
+            }),
+            [owner, repo, "issues" | "pull", number, ..] if is_issue_number(number) => {
+                Ok(IssueLink {
+                    tracker: IssueTracker::GitHub,
+                    key: format!("{owner}/{repo}#{number}"),
+                    url: input.to_string(),
+                })
+            }
+            _ => bail!("{input} isn't a GitHub or Jira issue"),
+        };
+    }
+    let repository_issue = input.split_once('#').filter(|(repository, number)| {
+        repository.split('/').filter(|s| !s.is_empty()).count() == 2 && is_issue_number(number)
+    });
+    if let Some((repository, number)) = repository_issue {
+        return Ok(IssueLink {
+            tracker: IssueTracker::GitHub,
+            key: input.to_string(),
+            url: format!("https://github.com/{repository}/issues/{number}"),
+        });
+    }
+    if is_jira_key(input) {
+        let base_url = jira_base_url
+            .with_context(|| format!("set the Jira site to link {input} to a Jira issue"))?;
+        return Ok(IssueLink {
+            tracker: IssueTracker::Jira,
+            key: input.to_string(),
+            url: format!("{}/browse/{input}", base_url.trim_end_matches('/')),
+        });
+    }
+    bail!("{input} isn't a GitHub or Jira issue")
+}
+
+/// A stored thread's issue from its tracker, key, url and whether it was resolved.
+pub(crate) fn thread_issue(row: (String, String, String, bool)) -> Result<ThreadIssue> {
+    let (tracker, key, url, resolved) = row;
+    let tracker = IssueTracker::from_name(&tracker)
+        .with_context(|| format!("unknown issue tracker {tracker}"))?;
+    Ok(ThreadIssue {
+        link: IssueLink { tracker, key, url },
+        resolved,
+    })
+}
+
+fn is_issue_number(number: &str) -> bool {
+    !number.is_empty() && number.chars().all(|c| c.is_ascii_digit())
+}
+
+/// Jira keys are the project's key, in capitals, and the issue's number, e.g. `PROJ-123`.
+fn is_jira_key(key: &str) -> bool {
+    let Some((project, number)) = key.split_once('-') else {
+        return false;
+    };
+    project.starts_with(|c: char| c.is_ascii_uppercase())
+        && project
+            .chars()
+            .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_')
+        && is_issue_number(number)
+}
+
+/// A comment to post to an issue tracker.
+pub struct IssueComment {
+    url: String,
+    authorization: String,
+    payload: Value,
+}
+
+/// Posts the summaries of resolved threads to their issues.
+pub struct IssueCommenter {
+    http_client: Arc<dyn HttpClient>,
+    config: IssueTrackerConfig,
+}
+
+impl IssueCommenter {
+    pub fn new(http_client: Arc<dyn HttpClient>, config: IssueTrackerConfig) -> Self {
+        Self {
+            http_client,
+            config,
+        }
+    }
+
+    pub fn config(&self) -> &IssueTrackerConfig {
+        &self.config
+    }
+
+    pub fn parse(&self, input: &str) -> Result<IssueLink> {
+        parse_issue_link(input, self.config.jira_base_url())
+    }
+
+    /// The call commenting `body` on the issue. Credentials are only sent to the configured
+    /// trackers, so Jira issues must be on the configured site.
+    pub fn comment(&self, issue: &IssueLink, body: &str) -> Result<IssueComment> {
+        match issue.tracker {
+            IssueTracker::GitHub => {
+                let github = self
+                    .config
+                    .github
+                    .as_ref()
+                    .context("no GitHub token is set")?;
+                let (repository, number) = issue
+                    .key
+                    .split_once('#')
+                    .with_context(|| format!("{} isn't a GitHub issue", issue.key))?;
+                Ok(IssueComment {
+                    url: format!(
+                        "{}/repos/{repository}/issues/{number}/comments",
+                        github.api_url.trim_end_matches('/')
+                    ),
+                    authorization: format!("Bearer {}", github.token),
+                    payload: json!({ "body": body }),
+                })
+            }
+            IssueTracker::Jira => {
+                let jira = self
+                    .config
+                    .jira
+                    .as_ref()
+                    .context("no Jira credentials are set")?;
+                let base_url = jira.base_url.trim_end_matches('/');
+                if !issue.url.starts_with(&format!("{base_url}/")) {
+                    bail!("{} isn't on {base_url}", issue.url);
+                }
+                let credentials = base64::engine::general_purpose::STANDARD
+                    .encode(format!("{}:{}", jira.email, jira.api_token));
+                Ok(IssueComment {
+                    url: format!("{base_url}/rest/api/2/issue/{}/comment", issue.key),
+                    authorization: format!("Basic {credentials}"),
+                    payload: json!({ "body": body }),
+                })
+            }
+        }
+    }
+
+    pub async fn post(&self, comment: IssueComment) -> Result<()> {
+        let IssueComment {
+            url,
+            authorization,
+            payload,
+        } = comment;
+        let request = HttpRequest::builder()
+            .method(Method::POST)
+            .uri(url)
+            .header("Content-Type", "application/json")
+            .header("Accept", "application/json")
+            .header("Authorization", authorization)
+            .body(AsyncBody::from(serde_json::to_string(&payload)?))?;
+        let mut response = self.http_client.send(request).await?;
+        if response.status().is_success() {
+            return Ok(());
+        }
+        let mut body = String::new();
+        response.body_mut().read_to_string(&mut body).await?;
+        Err(anyhow!(
+            "the issue tracker responded with {}: {body}",
+            response.status()
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_client::FakeHttpClient;
+
+    #[test]
+    fn test_issue_links() {
+        let github = parse_issue_link("https://github.com/zed-industries/zed/issues/42", None);
+        assert_eq!(github.unwrap().key, "zed-industries/zed#42");
+        let short = parse_issue_link("zed-industries/zed#42", None).unwrap();
+        assert_eq!(short.url, "https://github.com/zed-industries/zed/issues/42");
+        let jira = parse_issue_link("https://acme.atlassian.net/browse/PROJ-7", None).unwrap();
+        assert_eq!(
+            (jira.tracker, jira.key.as_str()),
+            (IssueTracker::Jira, "PROJ-7")
+        );
+        assert!(parse_issue_link("PROJ-7", None).is_err());
+        let key = parse_issue_link("PROJ-7", Some("https://acme.atlassian.net/")).unwrap();
+        assert_eq!(key.url, "https://acme.atlassian.net/browse/PROJ-7");
+        assert!(parse_issue_link("https://example.com/docs", None).is_err());
+
+        let commenter = IssueCommenter::new(
+            FakeHttpClient::with_404_response(),
+            IssueTrackerConfig {
+                jira: Some(JiraConfig {
+                    base_url: "https://acme.atlassian.net".to_string(),
+                    email: "ada@example.com".to_string(),
+                    api_token: "token".to_string(),
+                }),
+                ..IssueTrackerConfig::default()
+            },
+        );
+        let comment = commenter.comment(&key, "Fixed").unwrap();
+        assert_eq!(
+            comment.url,
+            "https://acme.atlassian.net/rest/api/2/issue/PROJ-7/comment"
+        );
+        assert_eq!(comment.payload["body"], "Fixed");
+        let elsewhere = parse_issue_link("https://evil.example/browse/PROJ-7", None).unwrap();
+        assert!(commenter.comment(&elsewhere, "Fixed").is_err());
+        assert!(commenter.comment(&short, "Fixed").is_err());
+    }
+}
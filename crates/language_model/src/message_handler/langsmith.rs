@@ -0,0 +1,414 @@
+use crate::{LanguageModelCompletionEvent, LanguageModelToolUse, RequestIds, TokenUsage};
+use anyhow::{Result, anyhow};
+use chrono::{DateTime, Utc};
+use collections::HashMap;
+use futures::{AsyncReadExt, FutureExt as _};
+use http_client::{AsyncBody, HttpClient, Method, Request as HttpRequest};
+use parking_lot::Mutex;
+use serde_json::{Value, json};
+use std::sync::Arc;
+
+use super::{ContentValue, LanguageModelArgs, Message, WriteLanes};
+
+pub const LANGSMITH_API_URL: &str = "https://api.smith.langchain.com";
+
+/// Where traces are sent and which LangSmith project they are filed under.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LangSmithConfig {
+    pub api_url: String,
+    pub api_key: String,
+    pub project: String,
+}
+
+/// A request whose response is still streaming.
+struct OpenRun {
+    id: uuid::Uuid,
+    dotted_order: String,
+    output: String,
+    usage: Option<TokenUsage>,
+}
+
+/// A call to LangSmith's runs API.
+pub struct LangSmithRequest {
+    method: Method,
+    path: String,
+    payload: Value,
+}
+
+/// Mirrors persisted completions to LangSmith: each request becomes an `llm` run, with a child
+/// `tool` run for every tool the model calls, finished with the response once the model stops.
+pub struct LangSmithExporter {
+    http_client: Arc<dyn HttpClient>,
+    config: LangSmithConfig,
+    open_runs: Mutex<HashMap<String, OpenRun>>,
+    /// Each run's calls, sent one at a time in the order they were made, so that LangSmith hears
+    /// of a run before its children and before it is finished.
+    queued_calls: Arc<WriteLanes>,
+}
+
+impl LangSmithExporter {
+    pub fn new(http_client: Arc<dyn HttpClient>, config: LangSmithConfig) -> Self {
+        Self {
+            http_client,
+            config,
+            open_runs: Mutex::default(),
+            queued_calls: Arc::default(),
+        }
+    }
+
+    pub fn config(&self) -> &LangSmithConfig {
+        &self.config
+    }
+
+    /// Opens a run for the request. The returned call creates it.
+    pub fn start_run(
+        &self,
+        messages: &[Message],
+        ids: &RequestIds,
+        language_model_args: &LanguageModelArgs,
+    ) -> LangSmithRequest {
+        let now = Utc::now();
+        let id = uuid::Uuid::new_v4();
+        let dotted_order = dotted_order(None, now, id);
+        let payload = json!({
+            "id": id.to_string(),
+            "trace_id": id.to_string(),
+            "dotted_order": dotted_order,
+            "name": language_model_args.model_id.0.as_ref(),
+            "run_type": "llm",
+            "session_name": self.config.project,
+            "start_time": now.to_rfc3339(),
+            "inputs": { "messages": messages },
+            "extra": { "metadata": run_metadata(ids, language_model_args) },
+        });
+        self.open_runs.lock().insert(
+            ids.checkpoint_id.clone(),
+            OpenRun {
+                id,
+                dotted_order,
+                output: String::new(),
+                usage: None,
+            },
+        );
+        LangSmithRequest {
+            method: Method::POST,
+            path: "/runs".to_string(),
+            payload,
+        }
+    }
+
+    /// Adds the event to the request's run, returning the call to make if LangSmith needs to hear
+    /// about it now.
+    pub fn record_event(
+        &self,
+        event: &LanguageModelCompletionEvent,
+        ids: &RequestIds,
+    ) -> Option<LangSmithRequest> {
+        let (method, path, payload) = {
+            let mut open_runs = self.open_runs.lock();
+            let run = open_runs.get_mut(&ids.checkpoint_id)?;
+            match event {
+                LanguageModelCompletionEvent::Text(text) => {
+                    run.output.push_str(text);
+                    return None;
+                }
+                LanguageModelCompletionEvent::UsageUpdate(usage) => {
+                    run.usage = Some(*usage);
+                    return None;
+                }
+                LanguageModelCompletionEvent::ToolUse(tool_use) if tool_use.is_input_complete => {
+                    let now = Utc::now();
+                    let id = uuid::Uuid::new_v4();
+                    let payload = json!({
+                        "id": id.to_string(),
+                        "trace_id": run.id.to_string(),
+                        "parent_run_id": run.id.to_string(),
+                        "dotted_order": dotted_order(Some(&run.dotted_order), now, id),
+                        "name": tool_use.name.as_ref(),
+                        "run_type": "tool",
+                        "session_name": self.config.project,
+                        "start_time": now.to_rfc3339(),
+                        "end_time": now.to_rfc3339(),
+                        "inputs": tool_use.input,
+                        "extra": { "metadata": { "tool_use_id": tool_use.id.to_string() } },
+                    });
+                    (Method::POST, "/runs".to_string(), payload)
+                }
+                LanguageModelCompletionEvent::Stop(reason) => {
+                    let run = open_runs.remove(&ids.checkpoint_id)?;
+                    let mut finish = finish_run(run);
+                    finish.payload["outputs"]["stop_reason"] = json!(format!("{reason:?}"));
+                    return Some(finish);
+                }
+                _ => return None,
+            }
+        };
+        Some(LangSmithRequest {
+            method,
+            path,
+            payload,
+        })
+    }
+
+    /// Finishes the request's run if it is still open, as it is when the stream failed or was
+    /// dropped before the model stopped, returning the call that closes it.
+    pub fn end_run(&self, ids: &RequestIds, error: Option<&str>) -> Option<LangSmithRequest> {
+        let run = self.open_runs.lock().remove(&ids.checkpoint_id)?;
+        let mut finish = finish_run(run);
+        if let Some(error) = error {
+            finish.payload["error"] = json!(error);
+        }
+        Some(finish)
+    }
+
+    /// Queues the call behind the calls made before it for the same run.
+    pub fn export(self: &Arc<Self>, ids: &RequestIds, request: LangSmithRequest) {
+        let exporter = self.clone();
+        self.queued_calls.enqueue(&ids.checkpoint_id, |_| {
+            async move {
+                if let Err(error) = exporter.send(request).await {
+                    log::error!("Failed to export trace to LangSmith: {error:#}");
+                }
+            }
+            .boxed()
+        });
+    }
+
+    pub async fn send(&self, request: LangSmithRequest) -> Result<()> {
+        let LangSmithRequest {
+            method,
+            path,
+            payload,
+        } = request;
+        let request = HttpRequest::builder()
+            .method(method)
+            .uri(format!(
+                "{}{path}",
+                self.config.api_url.trim_end_matches('/')
+            ))
+            .header("Content-Type", "application/json")
+            .header("x-api-key", &self.config.api_key)
+            .body(AsyncBody::from(serde_json::to_string(&payload)?))?;
+        let mut response = self.http_client.send(request).await?;
+        if response.status().is_success() {
+            return Ok(());
+        }
+        let mut body = String::new();
+        response.body_mut().read_to_string(&mut body).await?;
+        Err(anyhow!(
+            "LangSmith responded with {}: {body}",
+            response.status()
+        ))
+    }
+}
+
+/// The event as it is traced, given what the message rules left of the message it is stored as:
+/// it isn't traced when they keep the message out of the store, and its content is traced as
+/// they redacted it. Stops are traced either way, to finish their run.
+pub(crate) fn traced_event(
+    event: &LanguageModelCompletionEvent,
+    message: Option<&Message>,
+) -> Option<LanguageModelCompletionEvent> {
+    if let LanguageModelCompletionEvent::Stop(_) = event {
+        return Some(event.clone());
+    }
+    let content = match message?.content() {
+        ContentValue::Single(content) => content.clone(),
+        ContentValue::Multiple(contents) => contents.join(""),
+    };
+    Some(match event {
+        LanguageModelCompletionEvent::Text(_) => LanguageModelCompletionEvent::Text(content),
+        LanguageModelCompletionEvent::ToolUse(tool_use) => {
+            // Tool uses are stored as their input, which redaction may leave invalid JSON.
+            let input = serde_json::from_str(&content).unwrap_or(Value::String(content.clone()));
+            LanguageModelCompletionEvent::ToolUse(LanguageModelToolUse {
+                raw_input: content,
+                input,
+                ..tool_use.clone()
+            })
+        }
+        event => event.clone(),
+    })
+}
+
+/// The call that finishes the run with what the model produced.
+fn finish_run(run: OpenRun) -> LangSmithRequest {
+    let mut outputs = json!({ "output": run.output });
+    if let Some(usage) = run.usage {
+        outputs["usage_metadata"] = json!({
+            "input_tokens": usage.input_tokens,
+            "output_tokens": usage.output_tokens,
+            "total_tokens": usage.input_tokens + usage.output_tokens,
+        });
+    }
+    LangSmithRequest {
+        method: Method::PATCH,
+        path: format!("/runs/{}", run.id),
+        payload: json!({
+            "outputs": outputs,
+            "end_time": Utc::now().to_rfc3339(),
+        }),
+    }
+}
+
+fn run_metadata(ids: &RequestIds, language_model_args: &LanguageModelArgs) -> Value {
+    let mut metadata = json!({
+        "thread_id": ids.thread_id,
+        "session_id": ids.session_id,
+        "prompt_id": ids.prompt_id,
+        "checkpoint_id": ids.checkpoint_id,
+    });
+    if let Some(intent) = &language_model_args.intent {
+        metadata["intent"] = json!(intent);
+    }
+    if let Some(temperature) = language_model_args.temperature {
+        metadata["temperature"] = json!(temperature);
+    }
+    metadata
+}
+
+/// LangSmith orders runs within a trace by this key: the parent's key, then the run's start time
+/// and id.
+fn dotted_order(parent: Option<&str>, start_time: DateTime<Utc>, id: uuid::Uuid) -> String {
+    let segment = format!("{}{id}", start_time.format("%Y%m%dT%H%M%S%6fZ"));
+    match parent {
+        Some(parent) => format!("{parent}.{segment}"),
+        None => segment,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{LanguageModelId, StopReason};
+    use chrono::TimeZone;
+    use http_client::FakeHttpClient;
+
+    #[test]
+    fn test_tool_uses_become_child_runs() {
+        let exporter = LangSmithExporter::new(
+            FakeHttpClient::with_404_response(),
+            LangSmithConfig {
+                api_url: LANGSMITH_API_URL.to_string(),
+                api_key: "key".to_string(),
+                project: "zed".to_string(),
+            },
+        );
+        let ids = RequestIds {
+            thread_id: "thread".to_string(),
+            checkpoint_id: "checkpoint".to_string(),
+            session_id: "session".to_string(),
+            prompt_id: "prompt".to_string(),
+        };
+        let args = LanguageModelArgs::new(LanguageModelId::from("model".to_string()));
+        let parent = exporter.start_run(&[], &ids, &args);
+        assert_eq!(parent.payload["run_type"], "llm");
+        assert_eq!(parent.payload["session_name"], "zed");
+        let parent_id = parent.payload["id"].clone();
+
+        let text = LanguageModelCompletionEvent::Text("Searching".to_string());
+        assert!(exporter.record_event(&text, &ids).is_none());
+        let tool_use = LanguageModelCompletionEvent::ToolUse(LanguageModelToolUse {
+            id: "tool-1".into(),
+            name: "grep".into(),
+            raw_input: "{}".to_string(),
+            input: json!({}),
+            is_input_complete: true,
+        });
+        let child = exporter.record_event(&tool_use, &ids).unwrap();
+        assert_eq!(child.payload["run_type"], "tool");
+        assert_eq!(child.payload["parent_run_id"], parent_id);
+
+        let stop = LanguageModelCompletionEvent::Stop(StopReason::ToolUse);
+        let finish = exporter.record_event(&stop, &ids).unwrap();
+        assert_eq!(finish.method, Method::PATCH);
+        assert_eq!(
+            finish.path,
+            format!("/runs/{}", parent_id.as_str().unwrap())
+        );
+        assert_eq!(finish.payload["outputs"]["output"], "Searching");
+        assert!(exporter.record_event(&stop, &ids).is_none());
+    }
+
+    #[test]
+    fn test_runs_left_open_are_closed_with_their_error() {
+        let exporter = LangSmithExporter::new(
+            FakeHttpClient::with_404_response(),
+            LangSmithConfig {
+                api_url: LANGSMITH_API_URL.to_string(),
+                api_key: "key".to_string(),
+                project: "zed".to_string(),
+            },
+        );
+        let ids = RequestIds {
+            thread_id: "thread".to_string(),
+            checkpoint_id: "checkpoint".to_string(),
+            session_id: "session".to_string(),
+            prompt_id: "prompt".to_string(),
+        };
+        let args = LanguageModelArgs::new(LanguageModelId::from("model".to_string()));
+        let run_id = exporter.start_run(&[], &ids, &args).payload["id"].clone();
+        let text = LanguageModelCompletionEvent::Text("Partial".to_string());
+        assert!(exporter.record_event(&text, &ids).is_none());
+
+        let finish = exporter.end_run(&ids, Some("connection reset")).unwrap();
+        assert_eq!(finish.method, Method::PATCH);
+        assert_eq!(finish.path, format!("/runs/{}", run_id.as_str().unwrap()));
+        assert_eq!(finish.payload["outputs"]["output"], "Partial");
+        assert_eq!(finish.payload["error"], "connection reset");
+        assert!(exporter.end_run(&ids, None).is_none());
+        let stop = LanguageModelCompletionEvent::Stop(StopReason::EndTurn);
+        assert!(exporter.record_event(&stop, &ids).is_none());
+    }
+
+    #[test]
+    fn test_traced_events_follow_the_message_rules() {
+        let redacted = Message::Ai {
+            content: ContentValue::new("{\"path\":\"[redacted]\"}".into()),
+            id: "thread".into(),
+            name: None,
+            example: false,
+            invalid_tool_calls: None,
+            tool_calls: None,
+            additional_kwargs: Default::default(),
+            response_metadata: Default::default(),
+        };
+        let tool_use = LanguageModelCompletionEvent::ToolUse(LanguageModelToolUse {
+            id: "tool-1".into(),
+            name: "read_file".into(),
+            raw_input: "{\"path\":\".env\"}".to_string(),
+            input: json!({ "path": ".env" }),
+            is_input_complete: true,
+        });
+        let Some(LanguageModelCompletionEvent::ToolUse(traced)) =
+            traced_event(&tool_use, Some(&redacted))
+        else {
+            panic!("expected the tool use to be traced");
+        };
+        assert_eq!(traced.input, json!({ "path": "[redacted]" }));
+
+        let text = LanguageModelCompletionEvent::Text("Reading .env".to_string());
+        assert_eq!(traced_event(&text, None), None);
+        let stop = LanguageModelCompletionEvent::Stop(StopReason::EndTurn);
+        assert_eq!(traced_event(&stop, None), Some(stop));
+    }
+
+    #[test]
+    fn test_dotted_order() {
+        let start_time = Utc
+            .with_ymd_and_hms(2025, 6, 1, 12, 30, 5)
+            .unwrap()
+            .checked_add_signed(chrono::Duration::microseconds(42))
+            .unwrap();
+        let id = uuid::Uuid::nil();
+        let parent = dotted_order(None, start_time, id);
+        assert_eq!(
+            parent,
+            "20250601T123005000042Z00000000-0000-0000-0000-000000000000"
+        );
+        assert_eq!(
+            dotted_order(Some(&parent), start_time, id),
+            format!("{parent}.{parent}")
+        );
+    }
+}
@@ -0,0 +1,692 @@
+use anyhow::Result;
+use chrono::Utc;
+use parking_lot::Mutex;
+use sqlez::connection::Connection;
+use std::path::Path;
+
+use super::idempotency::write_idempotency_key;
+use super::issue_links::thread_issue;
+use super::schema_version::{deserialize_messages, serialize_messages};
+use super::{
+    IssueLink, Message, MessageAuthor, Page, StoredThread, TagSuggestion, ThreadCursor, ThreadFlag,
+    ThreadIssue, ThreadSort, messages_git_branch,
+};
+use crate::RequestIds;
+
+/// Checkpoints replicated per run of [`super::AiMessageHandler::replicate_local_cache`].
+pub(crate) const REPLICATION_BATCH_SIZE: usize = 64;
+
+/// A local SQLite copy of every message the handler saves, written before the remote store is,
+/// so that nothing is lost while it is unreachable and the history can be read back without a
+/// round trip. Checkpoints stay marked pending until the remote store has them.
+pub struct LocalMessageCache {
+    connection: Mutex<Connection>,
+}
+
+/// Messages saved locally that the remote store doesn't have yet.
+pub(crate) struct PendingCheckpoint {
+    pub id: i64,
+    pub ids: RequestIds,
+    pub author: Option<MessageAuthor>,
+    pub messages: Vec<Message>,
+}
+
+impl LocalMessageCache {
+    pub fn open(path: &Path) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        Self::new(Connection::open_file(&path.to_string_lossy()))
+    }
+
+    #[cfg(test)]
+    pub(crate) fn open_test(name: &str) -> Result<Self> {
+        Self::new(Connection::open_memory(Some(name)))
+    }
+
+    fn new(connection: Connection) -> Result<Self> {
+        connection.exec("PRAGMA journal_mode=WAL")?()?;
+        connection.exec(
+            "CREATE TABLE IF NOT EXISTS local_checkpoints (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                thread_id TEXT NOT NULL,
+                prompt_id TEXT NOT NULL,
+                session_id TEXT NOT NULL,
+                checkpoint_id TEXT NOT NULL,
+                author TEXT,
+                author_id TEXT NOT NULL,
+                author_name TEXT NOT NULL,
+                project TEXT NOT NULL,
+                messages TEXT NOT NULL,
+                created_at TEXT NOT NULL,
+                replicated INTEGER NOT NULL DEFAULT 0
+            )",
+        )?()?;
+        connection.exec(
+            "CREATE INDEX IF NOT EXISTS local_checkpoints_thread_id_idx
+                ON local_checkpoints (thread_id, id)",
+        )?()?;
+        connection.exec(
+            "CREATE INDEX IF NOT EXISTS local_checkpoints_replicated_idx
+                ON local_checkpoints (replicated, id)",
+        )?()?;
+        // Whether each thread's local copy is all of it, i.e. the thread started here rather than
+        // in the remote store. NULL until that has been checked.
+        connection.exec(
+            "CREATE TABLE IF NOT EXISTS local_threads (
+                thread_id TEXT PRIMARY KEY,
+                complete INTEGER
+            )",
+        )?()?;
+        // The git branch each thread was started on.
+        connection.exec(
+            "CREATE TABLE IF NOT EXISTS local_thread_branches (
+                thread_id TEXT PRIMARY KEY,
+                git_branch TEXT NOT NULL
+            )",
+        )?()?;
+        // The flags the user set on each thread.
+        connection.exec(
+            "CREATE TABLE IF NOT EXISTS local_thread_flags (
+                thread_id TEXT PRIMARY KEY,
+                pinned INTEGER NOT NULL DEFAULT 0,
+                starred INTEGER NOT NULL DEFAULT 0
+            )",
+        )?()?;
+        // The tags the user put on each thread.
+        connection.exec(
+            "CREATE TABLE IF NOT EXISTS local_thread_tags (
+                thread_id TEXT NOT NULL,
+                tag TEXT NOT NULL,
+                PRIMARY KEY (thread_id, tag)
+            )",
+        )?()?;
+        // The issue each thread is about.
+        connection.exec(
+            "CREATE TABLE IF NOT EXISTS local_thread_issues (
+                thread_id TEXT PRIMARY KEY,
+                tracker TEXT NOT NULL,
+                issue_key TEXT NOT NULL,
+                url TEXT NOT NULL,
+                resolved INTEGER NOT NULL DEFAULT 0
+            )",
+        )?()?;
+        // The local checkpoint each write was saved as, by idempotency key.
+        connection.exec(
+            "CREATE TABLE IF NOT EXISTS local_writes (
+                idempotency_key TEXT PRIMARY KEY,
+                checkpoint INTEGER NOT NULL
+            )",
+        )?()?;
+        Ok(Self {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    /// Saves the messages as pending, returning the checkpoint's local id. A write that was saved
+    /// before isn't saved again; the id it was saved as is returned instead.
+    pub(crate) fn append(
+        &self,
+        messages: &[Message],
+        ids: &RequestIds,
+        author: Option<&MessageAuthor>,
+    ) -> Result<i64> {
+        let project = messages
+            .iter()
+            .find_map(|message| message.response_metadata().get("project")?.as_str())
+            .unwrap_or_default()
+            .to_string();
+        let idempotency_key = write_idempotency_key(messages);
+        let connection = self.connection.lock();
+        if let Some(idempotency_key) = idempotency_key {
+            let mut select_write = connection.select_row_bound::<&str, i64>(
+                "SELECT checkpoint FROM local_writes WHERE idempotency_key = ?",
+            )?;
+            if let Some(id) = select_write(idempotency_key)? {
+                return Ok(id);
+            }
+        }
+        let mut insert_thread = connection.exec_bound::<&str>(
+            "INSERT OR IGNORE INTO local_threads (thread_id, complete) VALUES (?, NULL)",
+        )?;
+        insert_thread(ids.thread_id.as_str())?;
+        if let Some(git_branch) = messages_git_branch(messages) {
+            let mut insert_branch = connection.exec_bound::<(&str, &str)>(
+                "INSERT OR IGNORE INTO local_thread_branches (thread_id, git_branch) VALUES (?, ?)",
+            )?;
+            insert_branch((ids.thread_id.as_str(), git_branch))?;
+        }
+        let mut insert_checkpoint = connection.exec_bound::<(
+            &str,
+            &str,
+            &str,
+            &str,
+            Option<String>,
+            &str,
+            &str,
+            String,
+            String,
+            String,
+        )>(
+            "INSERT INTO local_checkpoints
+                (thread_id, prompt_id, session_id, checkpoint_id, author, author_id, author_name,
+                 project, messages, created_at)
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        )?;
+        insert_checkpoint((
+            ids.thread_id.as_str(),
+            ids.prompt_id.as_str(),
+            ids.session_id.as_str(),
+            ids.checkpoint_id.as_str(),
+            author.map(serde_json::to_string).transpose()?,
+            author.map_or("", |author| author.id.as_str()),
+            author.map_or("", |author| author.display_name()),
+            project,
+            serialize_messages(messages)?.to_string(),
+            Utc::now().to_rfc3339(),
+        ))?;
+        let mut select_id = connection.select_row::<i64>("SELECT last_insert_rowid()")?;
+        let id = select_id()?.ok_or_else(|| anyhow::anyhow!("no checkpoint was inserted"))?;
+        if let Some(idempotency_key) = idempotency_key {
+            let mut insert_write = connection.exec_bound::<(&str, i64)>(
+                "INSERT INTO local_writes (idempotency_key, checkpoint) VALUES (?, ?)
+                    ON CONFLICT (idempotency_key) DO NOTHING",
+            )?;
+            insert_write((idempotency_key, id))?;
+        }
+        Ok(id)
+    }
+
+    pub(crate) fn mark_replicated(&self, id: i64) -> Result<()> {
+        let connection = self.connection.lock();
+        let mut update = connection
+            .exec_bound::<i64>("UPDATE local_checkpoints SET replicated = 1 WHERE id = ?")?;
+        update(id)
+    }
+
+    /// The oldest checkpoints after `after` that the remote store doesn't have yet, only of the
+    /// thread if one is given.
+    pub(crate) fn pending(
+        &self,
+        thread_id: Option<&str>,
+        after: i64,
+        limit: usize,
+    ) -> Result<Vec<PendingCheckpoint>> {
+        let connection = self.connection.lock();
+        let mut select = connection.select_bound::<
+            (Option<&str>, i64, i64),
+            (i64, String, String, String, String, Option<String>, String),
+        >(
+            "SELECT id, thread_id, prompt_id, session_id, checkpoint_id, author, messages
+                FROM local_checkpoints
+                WHERE replicated = 0 AND id > ?2 AND (?1 IS NULL OR thread_id = ?1)
+                ORDER BY id
+                LIMIT ?3",
+        )?;
+        let rows = select((thread_id, after, limit as i64))?;
+        rows.into_iter()
+            .map(
+                |(id, thread_id, prompt_id, session_id, checkpoint_id, author, messages)| {
+                    Ok(PendingCheckpoint {
+                        id,
+                        ids: RequestIds {
+                            thread_id,
+                            checkpoint_id,
+                            session_id,
+                            prompt_id,
+                        },
+                        author: author
+                            .map(|author| serde_json::from_str(&author))
+                            .transpose()?,
+                        messages: deserialize_messages(serde_json::from_str(&messages)?)?,
+                    })
+                },
+            )
+            .collect()
+    }
+
+    /// Whether the thread has checkpoints the remote store doesn't have yet.
+    pub(crate) fn has_pending(&self, thread_id: &str) -> Result<bool> {
+        let connection = self.connection.lock();
+        let mut select = connection.select_row_bound::<&str, bool>(
+            "SELECT EXISTS (
+                SELECT 1 FROM local_checkpoints WHERE thread_id = ? AND replicated = 0
+            )",
+        )?;
+        Ok(select(thread_id)?.unwrap_or_default())
+    }
+
+    /// Whether the local copy of the thread is all of it, if that is known yet.
+    pub(crate) fn is_complete(&self, thread_id: &str) -> Result<Option<bool>> {
+        let connection = self.connection.lock();
+        let mut select = connection.select_row_bound::<&str, Option<bool>>(
+            "SELECT complete FROM local_threads WHERE thread_id = ?",
+        )?;
+        Ok(select(thread_id)?.flatten())
+    }
+
+    pub(crate) fn set_complete(&self, thread_id: &str, complete: bool) -> Result<()> {
+        let connection = self.connection.lock();
+        let mut update = connection.exec_bound::<(bool, &str)>(
+            "UPDATE local_threads SET complete = ? WHERE thread_id = ?",
+        )?;
+        update((complete, thread_id))
+    }
+
+    pub(crate) fn get_thread(&self, thread_id: &str) -> Result<Option<StoredThread>> {
+        let connection = self.connection.lock();
+        let mut select = connection.select_row_bound::<&str, StoredThreadRow>(
+            "SELECT c.thread_id,
+                    (SELECT author_id FROM local_checkpoints
+                        WHERE thread_id = c.thread_id ORDER BY id LIMIT 1),
+                    (SELECT author_name FROM local_checkpoints
+                        WHERE thread_id = c.thread_id ORDER BY id LIMIT 1),
+                    (SELECT project FROM local_checkpoints
+                        WHERE thread_id = c.thread_id ORDER BY id LIMIT 1),
+                    coalesce(max(b.git_branch), ''), max(c.created_at),
+                    coalesce(max(f.pinned), 0), coalesce(max(f.starred), 0),
+                    json_group_array(DISTINCT c.author_id) FILTER (WHERE c.author_id <> '')
+                FROM local_checkpoints c
+                LEFT JOIN local_thread_branches b ON b.thread_id = c.thread_id
+                LEFT JOIN local_thread_flags f ON f.thread_id = c.thread_id
+                WHERE c.thread_id = ?
+                GROUP BY c.thread_id",
+        )?;
+        select(thread_id)?.map(stored_thread).transpose()
+    }
+
+    pub(crate) fn set_thread_flag(
+        &self,
+        thread_id: &str,
+        flag: ThreadFlag,
+        value: bool,
+    ) -> Result<()> {
+        let column = flag.column();
+        let connection = self.connection.lock();
+        let mut upsert = connection.exec_bound::<(&str, bool)>(&format!(
+            "INSERT INTO local_thread_flags (thread_id, {column}) VALUES (?, ?)
+                ON CONFLICT (thread_id) DO UPDATE SET {column} = excluded.{column}"
+        ))?;
+        upsert((thread_id, value))
+    }
+
+    pub(crate) fn add_thread_tag(&self, thread_id: &str, tag: &str) -> Result<()> {
+        let connection = self.connection.lock();
+        let mut insert = connection.exec_bound::<(&str, &str)>(
+            "INSERT OR IGNORE INTO local_thread_tags (thread_id, tag) VALUES (?, ?)",
+        )?;
+        insert((thread_id, tag))
+    }
+
+    pub(crate) fn remove_thread_tag(&self, thread_id: &str, tag: &str) -> Result<()> {
+        let connection = self.connection.lock();
+        let mut delete = connection.exec_bound::<(&str, &str)>(
+            "DELETE FROM local_thread_tags WHERE thread_id = ? AND tag = ?",
+        )?;
+        delete((thread_id, tag))
+    }
+
+    pub(crate) fn thread_tags(&self, thread_id: &str) -> Result<Vec<String>> {
+        let connection = self.connection.lock();
+        let mut select = connection.select_bound::<&str, String>(
+            "SELECT tag FROM local_thread_tags WHERE thread_id = ? ORDER BY tag",
+        )?;
+        select(thread_id)
+    }
+
+    /// The tags matching the `LIKE` pattern once lowercased, those on the most threads first.
+    pub(crate) fn tag_suggestions(
+        &self,
+        pattern: &str,
+        limit: usize,
+    ) -> Result<Vec<TagSuggestion>> {
+        let connection = self.connection.lock();
+        let mut select = connection.select_bound::<(&str, i64), (String, i64)>(
+            "SELECT tag, count(*) FROM local_thread_tags
+                WHERE lower(tag) LIKE ? ESCAPE '\\'
+                GROUP BY tag
+                ORDER BY count(*) DESC, tag
+                LIMIT ?",
+        )?;
+        Ok(select((pattern, limit as i64))?
+            .into_iter()
+            .map(|(tag, threads)| TagSuggestion { tag, threads })
+            .collect())
+    }
+
+    pub(crate) fn link_thread_issue(&self, thread_id: &str, issue: &IssueLink) -> Result<()> {
+        let connection = self.connection.lock();
+        let mut upsert = connection.exec_bound::<(&str, &str, &str, &str)>(
+            "INSERT OR REPLACE INTO local_thread_issues (thread_id, tracker, issue_key, url)
+                VALUES (?, ?, ?, ?)",
+        )?;
+        upsert((thread_id, issue.tracker.as_str(), &issue.key, &issue.url))
+    }
+
+    pub(crate) fn unlink_thread_issue(&self, thread_id: &str) -> Result<()> {
+        let connection = self.connection.lock();
+        let mut delete =
+            connection.exec_bound::<&str>("DELETE FROM local_thread_issues WHERE thread_id = ?")?;
+        delete(thread_id)
+    }
+
+    pub(crate) fn thread_issue(&self, thread_id: &str) -> Result<Option<ThreadIssue>> {
+        let connection = self.connection.lock();
+        let mut select = connection.select_row_bound::<&str, (String, String, String, bool)>(
+            "SELECT tracker, issue_key, url, resolved FROM local_thread_issues WHERE thread_id = ?",
+        )?;
+        select(thread_id)?.map(thread_issue).transpose()
+    }
+
+    pub(crate) fn set_thread_issue_resolved(&self, thread_id: &str, resolved: bool) -> Result<()> {
+        let connection = self.connection.lock();
+        let mut update = connection.exec_bound::<(bool, &str)>(
+            "UPDATE local_thread_issues SET resolved = ? WHERE thread_id = ?",
+        )?;
+        update((resolved, thread_id))
+    }
+
+    /// A page of the threads in the order `sort` gives, only of those started on `git_branch` if
+    /// one is given.
+    pub(crate) fn list_threads(
+        &self,
+        after: Option<&ThreadCursor>,
+        limit: usize,
+        git_branch: Option<&str>,
+        sort: ThreadSort,
+    ) -> Result<Page<StoredThread, ThreadCursor>> {
+        let connection = self.connection.lock();
+        let mut select = connection.select_bound::<(
+            Option<&str>,
+            i64,
+            bool,
+            Option<&str>,
+            Option<&str>,
+            bool,
+            bool,
+        ), StoredThreadRow>(
+            "SELECT c.thread_id,
+                    (SELECT author_id FROM local_checkpoints
+                        WHERE thread_id = c.thread_id ORDER BY id LIMIT 1),
+                    (SELECT author_name FROM local_checkpoints
+                        WHERE thread_id = c.thread_id ORDER BY id LIMIT 1),
+                    (SELECT project FROM local_checkpoints
+                        WHERE thread_id = c.thread_id ORDER BY id LIMIT 1),
+                    coalesce(max(b.git_branch), ''), max(c.created_at),
+                    coalesce(max(f.pinned), 0), coalesce(max(f.starred), 0),
+                    json_group_array(DISTINCT c.author_id) FILTER (WHERE c.author_id <> '')
+                FROM local_checkpoints c
+                LEFT JOIN local_thread_branches b ON b.thread_id = c.thread_id
+                LEFT JOIN local_thread_flags f ON f.thread_id = c.thread_id
+                WHERE ?1 IS NULL OR b.git_branch = ?1
+                GROUP BY c.thread_id
+                HAVING ?4 IS NULL
+                    OR (NOT ?3 AND (max(c.created_at), c.thread_id) < (?4, ?5))
+                    OR (?3 AND (coalesce(max(f.pinned), 0), coalesce(max(f.starred), 0),
+                                max(c.created_at), c.thread_id) < (?6, ?7, ?4, ?5))
+                ORDER BY CASE WHEN ?3 THEN coalesce(max(f.pinned), 0) END DESC,
+                         CASE WHEN ?3 THEN coalesce(max(f.starred), 0) END DESC,
+                         max(c.created_at) DESC, c.thread_id DESC
+                LIMIT ?2",
+        )?;
+        let rows = select((
+            git_branch,
+            limit as i64,
+            sort == ThreadSort::Flagged,
+            after.map(|cursor| cursor.updated_at.as_str()),
+            after.map(|cursor| cursor.thread_id.as_str()),
+            after.is_some_and(|cursor| cursor.pinned),
+            after.is_some_and(|cursor| cursor.starred),
+        ))?;
+        Ok(Page::from_rows(
+            rows.into_iter().map(stored_thread).collect::<Result<_>>()?,
+            limit,
+            ThreadCursor::after,
+        ))
+    }
+
+    /// The thread's messages, oldest first.
+    pub(crate) fn load_thread(&self, thread_id: &str) -> Result<Vec<Message>> {
+        let connection = self.connection.lock();
+        let mut select = connection.select_bound::<&str, String>(
+            "SELECT messages FROM local_checkpoints WHERE thread_id = ? ORDER BY id",
+        )?;
+        let blobs = select(thread_id)?;
+        let mut messages = Vec::new();
+        for blob in blobs {
+            messages.extend(deserialize_messages(serde_json::from_str(&blob)?)?);
+        }
+        Ok(messages)
+    }
+}
+
+type StoredThreadRow = (
+    String,
+    String,
+    String,
+    String,
+    String,
+    String,
+    bool,
+    bool,
+    String,
+);
+
+fn stored_thread(row: StoredThreadRow) -> Result<StoredThread> {
+    let (
+        thread_id,
+        author_id,
+        author_name,
+        project,
+        git_branch,
+        updated_at,
+        pinned,
+        starred,
+        contributors,
+    ) = row;
+    Ok(StoredThread {
+        thread_id,
+        author_id,
+        author_name,
+        project,
+        contributors: serde_json::from_str(&contributors)?,
+        git_branch,
+        updated_at,
+        pinned,
+        starred,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message_handler::ContentValue;
+    use crate::message_handler::idempotency::{CheckpointEvent, stamp_idempotency_key};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_pending_until_replicated() {
+        let cache = LocalMessageCache::open_test("test_pending_until_replicated").unwrap();
+        let ids = RequestIds {
+            thread_id: "thread".into(),
+            checkpoint_id: "checkpoint".into(),
+            session_id: "session".into(),
+            prompt_id: "prompt".into(),
+        };
+        let message = Message::System {
+            content: ContentValue::new("You are a helpful assistant".into()),
+            id: "1".into(),
+            name: None,
+            example: false,
+            additional_kwargs: Default::default(),
+            response_metadata: Default::default(),
+        };
+
+        let id = cache.append(&[message.clone()], &ids, None).unwrap();
+        cache.append(&[message], &ids, None).unwrap();
+        assert_eq!(cache.load_thread("thread").unwrap().len(), 2);
+        assert_eq!(cache.is_complete("thread").unwrap(), None);
+        cache.set_complete("thread", true).unwrap();
+        assert_eq!(cache.is_complete("thread").unwrap(), Some(true));
+
+        cache.mark_replicated(id).unwrap();
+        let pending = cache.pending(None, 0, REPLICATION_BATCH_SIZE).unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].ids.checkpoint_id, "checkpoint");
+        assert!(cache.pending(Some("other"), 0, 10).unwrap().is_empty());
+        assert!(cache.pending(None, pending[0].id, 10).unwrap().is_empty());
+        assert_eq!(
+            cache
+                .list_threads(None, 10, None, ThreadSort::Recent)
+                .unwrap()
+                .items[0]
+                .thread_id,
+            "thread"
+        );
+    }
+
+    #[test]
+    fn test_replayed_write_is_saved_once() {
+        let cache = LocalMessageCache::open_test("test_replayed_write_is_saved_once").unwrap();
+        let ids = RequestIds {
+            thread_id: "thread".into(),
+            checkpoint_id: "checkpoint".into(),
+            session_id: "session".into(),
+            prompt_id: "prompt".into(),
+        };
+        let mut messages = [Message::System {
+            content: ContentValue::new("You are a helpful assistant".into()),
+            id: "1".into(),
+            name: None,
+            example: false,
+            additional_kwargs: Default::default(),
+            response_metadata: Default::default(),
+        }];
+        stamp_idempotency_key(&mut messages, &ids, CheckpointEvent::Request);
+
+        let id = cache.append(&messages, &ids, None).unwrap();
+        assert_eq!(cache.append(&messages, &ids, None).unwrap(), id);
+        assert_eq!(cache.load_thread("thread").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_threads_listed_by_branch() {
+        let cache = LocalMessageCache::open_test("test_threads_listed_by_branch").unwrap();
+        let append = |thread_id: &str, git_branch: &str| {
+            let ids = RequestIds {
+                thread_id: thread_id.into(),
+                checkpoint_id: format!("{thread_id}-{git_branch}"),
+                session_id: "session".into(),
+                prompt_id: "prompt".into(),
+            };
+            let message = Message::Human {
+                content: ContentValue::new("Fix the build".into()),
+                id: thread_id.into(),
+                name: None,
+                example: false,
+                additional_kwargs: Default::default(),
+                response_metadata: HashMap::from_iter([(
+                    "git_branch".to_string(),
+                    serde_json::Value::from(git_branch),
+                )]),
+            };
+            cache.append(&[message], &ids, None).unwrap();
+        };
+        append("a", "main");
+        append("b", "feature");
+        // Later requests on another branch don't move the thread.
+        append("a", "feature");
+
+        let listed = |git_branch| {
+            cache
+                .list_threads(None, 10, git_branch, ThreadSort::Recent)
+                .unwrap()
+                .items
+                .into_iter()
+                .map(|thread| (thread.thread_id, thread.git_branch))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(listed(Some("main")), [("a".into(), "main".into())]);
+        assert_eq!(listed(Some("feature")), [("b".into(), "feature".into())]);
+        assert_eq!(listed(None).len(), 2);
+    }
+
+    #[test]
+    fn test_flagged_threads_are_listed_first() {
+        let cache = LocalMessageCache::open_test("test_flagged_threads_are_listed_first").unwrap();
+        for thread_id in ["pinned", "starred", "recent"] {
+            let ids = RequestIds {
+                thread_id: thread_id.into(),
+                checkpoint_id: thread_id.into(),
+                session_id: "session".into(),
+                prompt_id: "prompt".into(),
+            };
+            let message = Message::Human {
+                content: ContentValue::new("Fix the build".into()),
+                id: thread_id.into(),
+                name: None,
+                example: false,
+                additional_kwargs: Default::default(),
+                response_metadata: Default::default(),
+            };
+            cache.append(&[message], &ids, None).unwrap();
+        }
+        cache
+            .set_thread_flag("pinned", ThreadFlag::Pinned, true)
+            .unwrap();
+        cache
+            .set_thread_flag("starred", ThreadFlag::Starred, true)
+            .unwrap();
+        cache
+            .set_thread_flag("recent", ThreadFlag::Starred, true)
+            .unwrap();
+        cache
+            .set_thread_flag("recent", ThreadFlag::Starred, false)
+            .unwrap();
+
+        let listed = |sort| {
+            cache
+                .list_threads(None, 10, None, sort)
+                .unwrap()
+                .items
+                .into_iter()
+                .map(|thread| (thread.thread_id, thread.pinned, thread.starred))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            listed(ThreadSort::Flagged),
+            [
+                ("pinned".to_string(), true, false),
+                ("starred".to_string(), false, true),
+                ("recent".to_string(), false, false),
+            ]
+        );
+        let recent = listed(ThreadSort::Recent);
+        assert_eq!(recent.len(), 3);
+        assert_eq!(
+            cache
+                .get_thread("pinned")
+                .unwrap()
+                .map(|thread| thread.pinned),
+            Some(true)
+        );
+
+        for sort in [ThreadSort::Flagged, ThreadSort::Recent] {
+            let mut paged = Vec::new();
+            let mut after = None;
+            loop {
+                let page = cache.list_threads(after.as_ref(), 1, None, sort).unwrap();
+                paged.extend(
+                    page.items
+                        .into_iter()
+                        .map(|thread| (thread.thread_id, thread.pinned, thread.starred)),
+                );
+                let Some(next) = page.next else {
+                    break;
+                };
+                after = Some(next);
+            }
+            assert_eq!(paged, listed(sort));
+        }
+    }
+}
@@ -0,0 +1,179 @@
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// When the store's tables and indexes are worth maintaining, and what is run when the store's
+/// diagnostics are shown.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct MaintenancePolicy {
+    /// The share of a table's rows that are dead past which vacuuming it is recommended.
+    pub dead_tuple_ratio: f64,
+    /// How many dead rows a table must have for vacuuming it to be recommended, so that small
+    /// tables aren't.
+    pub min_dead_tuples: i64,
+    /// How large an index that was never scanned must be for dropping it to be recommended.
+    pub min_unused_index_bytes: i64,
+    /// Statements run before the store is inspected, e.g. `VACUUM (ANALYZE) ide_checkpoints`.
+    pub statements: Vec<String>,
+}
+
+impl Default for MaintenancePolicy {
+    fn default() -> Self {
+        Self {
+            dead_tuple_ratio: 0.2,
+            min_dead_tuples: 10_000,
+            min_unused_index_bytes: 8 * 1024 * 1024,
+            statements: Vec::new(),
+        }
+    }
+}
+
+/// How a table of the store is doing, from Postgres' statistics.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableHealth {
+    pub table: String,
+    pub live_tuples: i64,
+    pub dead_tuples: i64,
+    /// The size of the table with its indexes and TOAST data.
+    pub total_bytes: i64,
+    /// When the table was last vacuumed, by hand or by autovacuum.
+    pub last_vacuum: Option<DateTime<Utc>>,
+    /// When the table was last analyzed, by hand or by autovacuum.
+    pub last_analyze: Option<DateTime<Utc>>,
+}
+
+/// How often an index of the store is used, from Postgres' statistics.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexUsage {
+    pub table: String,
+    pub index: String,
+    pub scans: i64,
+    pub bytes: i64,
+    /// Whether the index backs a primary key or unique constraint, which it is needed for
+    /// however rarely it is scanned.
+    pub unique: bool,
+}
+
+/// What Postgres' statistics say about the store's tables and indexes.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MaintenanceReport {
+    pub tables: Vec<TableHealth>,
+    pub indexes: Vec<IndexUsage>,
+}
+
+/// Something worth doing to one of the store's tables or indexes, and the statement doing it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MaintenanceAdvice {
+    /// The table or index it is about.
+    pub relation: String,
+    pub reason: String,
+    pub statement: String,
+}
+
+impl std::fmt::Display for MaintenanceAdvice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "`{}`: {} Run `{}`.",
+            self.relation, self.reason, self.statement
+        )
+    }
+}
+
+/// What the report recommends: vacuuming tables with many dead rows, analyzing tables that
+/// never were, and dropping large indexes that are never scanned.
+pub fn maintenance_advice(
+    report: &MaintenanceReport,
+    policy: &MaintenancePolicy,
+) -> Vec<MaintenanceAdvice> {
+    let mut advice = Vec::new();
+    for table in &report.tables {
+        let tuples = table.live_tuples + table.dead_tuples;
+        let dead_ratio = if tuples == 0 {
+            0.0
+        } else {
+            table.dead_tuples as f64 / tuples as f64
+        };
+        if table.dead_tuples >= policy.min_dead_tuples && dead_ratio >= policy.dead_tuple_ratio {
+            advice.push(MaintenanceAdvice {
+                relation: table.table.clone(),
+                reason: format!(
+                    "{:.0}% of its rows ({}) are dead.",
+                    dead_ratio * 100.0,
+                    table.dead_tuples
+                ),
+                statement: format!("VACUUM (ANALYZE) {}", table.table),
+            });
+        } else if table.last_analyze.is_none() && table.live_tuples > 0 {
+            advice.push(MaintenanceAdvice {
+                relation: table.table.clone(),
+                reason: "has never been analyzed, so its query plans are guessed.".to_string(),
+                statement: format!("ANALYZE {}", table.table),
+            });
+        }
+    }
+    for index in &report.indexes {
+        if index.scans == 0 && !index.unique && index.bytes >= policy.min_unused_index_bytes {
+            advice.push(MaintenanceAdvice {
+                relation: index.index.clone(),
+                reason: format!(
+                    "on {} has never been scanned, yet takes {} MiB.",
+                    index.table,
+                    index.bytes / (1024 * 1024)
+                ),
+                statement: format!("DROP INDEX CONCURRENTLY {}", index.index),
+            });
+        }
+    }
+    advice
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_maintenance_advice() {
+        let table = |table: &str, live_tuples, dead_tuples, analyzed: bool| TableHealth {
+            table: table.to_string(),
+            live_tuples,
+            dead_tuples,
+            total_bytes: 0,
+            last_vacuum: None,
+            last_analyze: analyzed.then(Utc::now),
+        };
+        let index = |index: &str, scans, unique| IndexUsage {
+            table: "ide_checkpoints".to_string(),
+            index: index.to_string(),
+            scans,
+            bytes: 64 * 1024 * 1024,
+            unique,
+        };
+        let report = MaintenanceReport {
+            tables: vec![
+                table("ide_checkpoints", 60_000, 40_000, true),
+                table("thread_tags", 10, 90, true),
+                table("run_results", 500, 0, false),
+                table("thread_flags", 0, 0, false),
+            ],
+            indexes: vec![
+                index("ide_checkpoints_pkey", 0, true),
+                index("ide_checkpoints_project_idx", 0, false),
+                index("ide_checkpoints_thread_id_idx", 12, false),
+            ],
+        };
+        let statements = maintenance_advice(&report, &MaintenancePolicy::default())
+            .into_iter()
+            .map(|advice| advice.statement)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            statements,
+            [
+                "VACUUM (ANALYZE) ide_checkpoints",
+                "ANALYZE run_results",
+                "DROP INDEX CONCURRENTLY ide_checkpoints_project_idx",
+            ]
+        );
+    }
+}
@@ -0,0 +1,343 @@
+use anyhow::Context as _;
+use regex::Regex;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::idempotency::split_idempotency_key;
+use super::{ContentValue, Message, MessageType};
+
+/// What replaces the parts of a message a rule redacts.
+pub const REDACTED: &str = "[redacted]";
+
+/// A rule applied to each message before it is stored, e.g.
+///
+/// ```json
+/// { "when": { "role": "tool", "content": "(?i)api[_-]?key\\S*" }, "action": "redact" }
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct MessageRule {
+    pub when: MessageMatch,
+    pub action: MessageAction,
+}
+
+/// Which messages a rule applies to. Every condition given has to hold; a rule without any
+/// applies to every message.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct MessageMatch {
+    pub role: Option<MessageType>,
+    /// The intent of the request the message was part of, e.g. `ThreadSummarization`.
+    pub intent: Option<String>,
+    /// The id of the model the message was sent to or received from.
+    pub model: Option<String>,
+    /// A regex, which has to match somewhere in the message's content.
+    pub content: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageAction {
+    /// Doesn't store the message.
+    Drop,
+    /// Replaces what the rule's `content` regex matches, or all of the content without one.
+    Redact,
+    /// Stores the message only where listed.
+    Route(Vec<MessageSink>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageSink {
+    LocalCache,
+    Store,
+}
+
+/// Where a group of messages is stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct MessageRoute {
+    pub local_cache: bool,
+    pub store: bool,
+}
+
+impl MessageRoute {
+    pub const EVERYWHERE: Self = Self {
+        local_cache: true,
+        store: true,
+    };
+
+    fn new(sinks: &[MessageSink]) -> Self {
+        Self {
+            local_cache: sinks.contains(&MessageSink::LocalCache),
+            store: sinks.contains(&MessageSink::Store),
+        }
+    }
+}
+
+/// Messages the rules have been applied to, grouped by where they are stored. Messages are
+/// only stored, forwarded or exported once filtered, so that what the rules withhold doesn't
+/// reach any of them.
+#[derive(Debug, Clone)]
+pub(crate) struct FilteredMessages {
+    groups: Vec<(Vec<Message>, MessageRoute)>,
+}
+
+impl FilteredMessages {
+    /// Applies the filter's rules to the messages, or stores them everywhere without one.
+    pub fn new(filter: Option<&MessageFilter>, messages: Vec<Message>) -> Self {
+        let mut groups = match filter {
+            Some(filter) => filter.apply(messages),
+            None => vec![(messages, MessageRoute::EVERYWHERE)],
+        };
+        for (part, (messages, _)) in groups.iter_mut().enumerate().skip(1) {
+            split_idempotency_key(messages, part);
+        }
+        Self { groups }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.groups.iter().all(|(messages, _)| messages.is_empty())
+    }
+
+    pub fn len(&self) -> usize {
+        self.groups.iter().map(|(messages, _)| messages.len()).sum()
+    }
+
+    pub fn messages(&self) -> impl Iterator<Item = &Message> {
+        self.groups.iter().flat_map(|(messages, _)| messages)
+    }
+
+    pub fn messages_mut(&mut self) -> impl Iterator<Item = &mut Message> {
+        self.groups.iter_mut().flat_map(|(messages, _)| messages)
+    }
+
+    /// The messages that may leave this machine, i.e. that are routed to the store.
+    pub fn exported(&self) -> impl Iterator<Item = &Message> {
+        self.groups
+            .iter()
+            .filter(|(_, route)| route.store)
+            .flat_map(|(messages, _)| messages)
+    }
+
+    pub fn into_groups(self) -> Vec<(Vec<Message>, MessageRoute)> {
+        self.groups
+    }
+}
+
+struct CompiledRule {
+    rule: MessageRule,
+    content: Option<Regex>,
+}
+
+/// The configured rules, with their regexes compiled.
+pub struct MessageFilter {
+    rules: Vec<CompiledRule>,
+}
+
+impl MessageFilter {
+    pub fn new(rules: Vec<MessageRule>) -> anyhow::Result<Self> {
+        let rules = rules
+            .into_iter()
+            .enumerate()
+            .map(|(ix, rule)| {
+                let content = rule
+                    .when
+                    .content
+                    .as_deref()
+                    .map(Regex::new)
+                    .transpose()
+                    .with_context(|| format!("invalid content regex in message rule {ix}"))?;
+                anyhow::Ok(CompiledRule { rule, content })
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self { rules })
+    }
+
+    /// Applies the rules to each message in order: a drop or a route ends its evaluation, while
+    /// later rules still apply to redacted messages. Returns consecutive messages grouped by
+    /// where they are to be stored.
+    pub(crate) fn apply(&self, messages: Vec<Message>) -> Vec<(Vec<Message>, MessageRoute)> {
+        let mut groups: Vec<(Vec<Message>, MessageRoute)> = Vec::new();
+        for mut message in messages {
+            let mut route = Some(MessageRoute::EVERYWHERE);
+            for CompiledRule { rule, content } in &self.rules {
+                if !rule.when.matches(&message, content.as_ref()) {
+                    continue;
+                }
+                match &rule.action {
+                    MessageAction::Drop => route = None,
+                    MessageAction::Redact => redact(&mut message, content.as_ref()),
+                    MessageAction::Route(sinks) => route = Some(MessageRoute::new(sinks)),
+                }
+                if !matches!(rule.action, MessageAction::Redact) {
+                    break;
+                }
+            }
+            let Some(route) = route else {
+                continue;
+            };
+            match groups.last_mut() {
+                Some((group, group_route)) if *group_route == route => group.push(message),
+                _ => groups.push((vec![message], route)),
+            }
+        }
+        groups
+    }
+}
+
+impl MessageMatch {
+    fn matches(&self, message: &Message, content: Option<&Regex>) -> bool {
+        let metadata = |key: &str| {
+            message
+                .response_metadata()
+                .get(key)
+                .and_then(|value| value.as_str())
+                // Model ids are stored debug-formatted, in quotes.
+                .map(|value| value.trim_matches('"').to_string())
+        };
+        self.role.is_none_or(|role| role == message_type(message))
+            && self
+                .intent
+                .as_ref()
+                .is_none_or(|intent| metadata("intent").as_ref() == Some(intent))
+            && self
+                .model
+                .as_ref()
+                .is_none_or(|model| metadata("model_id").as_ref() == Some(model))
+            && content.is_none_or(|content| match message.content() {
+                ContentValue::Single(text) => content.is_match(text),
+                ContentValue::Multiple(texts) => texts.iter().any(|text| content.is_match(text)),
+            })
+    }
+}
+
+fn message_type(message: &Message) -> MessageType {
+    match message {
+        Message::Human { .. } => MessageType::Human,
+        Message::Ai { .. } => MessageType::Ai,
+        Message::System { .. } => MessageType::System,
+        Message::Tool { .. } => MessageType::Tool,
+        Message::Function { .. } => MessageType::Function,
+    }
+}
+
+fn redact(message: &mut Message, pattern: Option<&Regex>) {
+    let (content, additional_kwargs) = match message {
+        Message::Human {
+            content,
+            additional_kwargs,
+            ..
+        }
+        | Message::Ai {
+            content,
+            additional_kwargs,
+            ..
+        }
+        | Message::System {
+            content,
+            additional_kwargs,
+            ..
+        }
+        | Message::Tool {
+            content,
+            additional_kwargs,
+            ..
+        }
+        | Message::Function {
+            content,
+            additional_kwargs,
+            ..
+        } => (content, additional_kwargs),
+    };
+    let redact_text = |text: &mut String| {
+        *text = match pattern {
+            Some(pattern) => pattern.replace_all(text, REDACTED).into_owned(),
+            None => REDACTED.to_string(),
+        }
+    };
+    match content {
+        ContentValue::Single(text) => redact_text(text),
+        ContentValue::Multiple(texts) => texts.iter_mut().for_each(redact_text),
+    }
+    // Thinking is stored next to its content too.
+    if let Some(serde_json::Value::String(thinking)) = additional_kwargs.get_mut("thinking") {
+        redact_text(thinking);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn message(content: &str, intent: &str) -> Message {
+        Message::Human {
+            content: ContentValue::new(content.into()),
+            id: content.into(),
+            name: None,
+            example: false,
+            additional_kwargs: HashMap::new(),
+            response_metadata: HashMap::from_iter([("intent".to_string(), intent.into())]),
+        }
+    }
+
+    #[test]
+    fn test_rules_drop_redact_and_route() {
+        let filter = MessageFilter::new(vec![
+            MessageRule {
+                when: MessageMatch {
+                    content: Some("sk-[a-z0-9]+".into()),
+                    ..Default::default()
+                },
+                action: MessageAction::Redact,
+            },
+            MessageRule {
+                when: MessageMatch {
+                    intent: Some("ThreadSummarization".into()),
+                    ..Default::default()
+                },
+                action: MessageAction::Drop,
+            },
+            MessageRule {
+                when: MessageMatch {
+                    role: Some(MessageType::Human),
+                    content: Some("private".into()),
+                    ..Default::default()
+                },
+                action: MessageAction::Route(vec![MessageSink::LocalCache]),
+            },
+        ])
+        .unwrap();
+
+        let groups = filter.apply(vec![
+            message("key sk-abc123", "UserPrompt"),
+            message("summary", "ThreadSummarization"),
+            message("private sk-def", "UserPrompt"),
+        ]);
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].1, MessageRoute::EVERYWHERE);
+        assert!(
+            matches!(groups[0].0[0].content(), ContentValue::Single(text) if text == "key [redacted]")
+        );
+        assert_eq!(
+            groups[1].1,
+            MessageRoute {
+                local_cache: true,
+                store: false
+            }
+        );
+        assert!(
+            matches!(groups[1].0[0].content(), ContentValue::Single(text) if text == "private [redacted]")
+        );
+
+        assert!(
+            MessageFilter::new(vec![MessageRule {
+                when: MessageMatch {
+                    content: Some("(".into()),
+                    ..Default::default()
+                },
+                action: MessageAction::Drop,
+            }])
+            .is_err()
+        );
+    }
+}
@@ -1,27 +1,205 @@
+mod author;
+mod authorizer;
+mod blob_encoding;
+mod bounded_map;
+mod chaos;
+mod compaction;
+mod config_validation;
+#[cfg(feature = "postgres")]
+mod content_blobs;
+mod context_budget;
+mod conversation_lint;
+mod experiments;
+mod file_snapshots;
+mod fixtures;
+mod garbage_collection;
+mod guardrails;
+mod idempotency;
+mod interceptors;
+mod issue_links;
+mod langsmith;
+#[cfg(feature = "sqlite")]
+mod local_cache;
+mod maintenance;
+mod message_rules;
+mod noop;
+mod notifications;
+mod pagination;
+mod partitioning;
+mod pending_writes;
+mod persistence_acks;
+mod persistence_features;
+mod pii;
+#[cfg(feature = "postgres")]
 mod postgres;
+mod prompt_cache;
+mod prompt_templates;
+mod raw_exchanges;
 mod registry;
+mod remote_persistence;
+mod replay;
+mod response_cache;
+mod run_results;
+mod sampling;
+mod scheduler;
+mod schema_version;
+mod secrets;
+mod sequencing;
+mod session_env;
+mod shadow;
+mod store_health;
+mod thread_cache;
+mod thread_instructions;
+mod thread_locks;
+mod thread_tags;
+mod thread_templates;
+mod token_counts;
+mod tool_pairs;
+mod usage;
 
 use crate::{LanguageModelId, RequestIds};
-use futures::{Stream, StreamExt};
+use futures::channel::{mpsc, oneshot};
+use futures::future::BoxFuture;
+use futures::stream::BoxStream;
+use futures::{FutureExt as _, Stream, StreamExt};
 
 use crate::{
-    LanguageModelCompletionError, LanguageModelCompletionEvent, LanguageModelRequest,
-    LanguageModelRequestMessage, Role,
+    CompletionStreamObserver, CompletionStreamTee, FanOutVerdict, LanguageModelCompletionError,
+    LanguageModelCompletionEvent, LanguageModelRequest, LanguageModelRequestMessage,
+    LanguageModelRequestTool, LanguageModelToolUse, MessageContent, RequestBuffer, RequestOrigin,
+    RequestPromptTemplate, RequestSource, RequestToolchain, Role, StreamSplice, StructuredOutput,
+    StructuredOutputError, StructuredOutputSchema, TokenUsage, Tokenizers,
 };
+pub use author::MessageAuthor;
+pub use authorizer::{AllowAll, StoreAuthorizer, StoredThread, VisibilityRules};
+pub use blob_encoding::BlobEncoding;
+use bounded_map::BoundedMap;
+pub use chaos::{Chaos, ChaosConfig, ChaosPolicy};
+use chrono::{DateTime, NaiveDate, Utc};
+pub use compaction::{CompactionPolicy, ContextSummarizer};
+pub use config_validation::{CONNECTION_STRING_VAR, ConfigDiagnostic, ConfigSeverity, StorageMode};
+pub use context_budget::{CONTEXT_WARNING_THRESHOLD, ContextBudget};
+use conversation_lint::REQUEST_TOOLS;
+pub use conversation_lint::{LintFinding, LintKind, LintPolicy, ThreadLint, lint_thread};
 use enum_fields::EnumFields;
+pub use experiments::{
+    ExperimentMetric, PromptExperiment, PromptExperimentAssignment, VariantStats,
+};
+pub use file_snapshots::{
+    FileChange, FileChangeKind, FileSnapshot, changed_files, content_hash, tool_input_paths,
+};
+pub use fixtures::{CompletionFixtures, FixtureMode, FixturePolicy};
+pub use garbage_collection::{CollectedCheckpoint, GarbageCollection, unreachable_checkpoints};
 use gpui::Global;
+pub use guardrails::{GuardrailAction, GuardrailRule, Guardrails};
+use http_client::HttpClient;
+use idempotency::stamp_idempotency_key;
+pub use idempotency::{CheckpointEvent, idempotency_key};
+pub use interceptors::{
+    RequestBlocked, RequestInterceptor, ResponseInterceptor, ResponseInterceptors,
+    intercept_request,
+};
+pub use issue_links::{
+    GITHUB_API_URL, GitHubIssuesConfig, IssueComment, IssueCommenter, IssueLink, IssueTracker,
+    IssueTrackerConfig, JiraConfig, ThreadIssue, parse_issue_link,
+};
+pub use langsmith::{LANGSMITH_API_URL, LangSmithConfig, LangSmithExporter, LangSmithRequest};
+#[cfg(feature = "sqlite")]
+pub use local_cache::LocalMessageCache;
+#[cfg(feature = "sqlite")]
+use local_cache::REPLICATION_BATCH_SIZE;
+pub use maintenance::{
+    IndexUsage, MaintenanceAdvice, MaintenancePolicy, MaintenanceReport, TableHealth,
+    maintenance_advice,
+};
+use message_rules::{FilteredMessages, MessageRoute};
+pub use message_rules::{
+    MessageAction, MessageFilter, MessageMatch, MessageRule, MessageSink, REDACTED,
+};
+#[cfg(not(feature = "sqlite"))]
+pub use noop::LocalMessageCache;
+#[cfg(not(feature = "postgres"))]
+pub use noop::NoopDatabaseClient;
+#[cfg(not(feature = "sqlite"))]
+use noop::REPLICATION_BATCH_SIZE;
+pub use notifications::{
+    CompletionOutcome, NotificationPolicy, NotificationRule, ThreadFacts, ThreadNotifier,
+};
+pub use pagination::{HistoryCursor, Page, StoredMessage, ThreadCursor, ThreadFlag, ThreadSort};
+use parking_lot::Mutex;
+pub use partitioning::{CheckpointPartitioning, PartitionMaintenance};
+pub use pending_writes::FlushAck;
+use pending_writes::PendingWrites;
+use persistence_acks::FailedWrite;
+pub use persistence_acks::{PersistedPart, PersistenceAck, PersistenceAcks, PersistenceStatus};
+pub use persistence_features::{PersistenceFeatures, PersistenceFeaturesContent};
+pub use pii::{PII_CATEGORIES, PiiCategory, classify_pii};
+#[cfg(feature = "postgres")]
 pub use postgres::PostgresDatabaseClient;
+pub use prompt_cache::{PromptCacheStats, PromptCacheUsage};
+pub use prompt_templates::{PromptTemplate, PromptTemplateRef};
+use raw_exchanges::RawExchangeCapture;
+pub use raw_exchanges::{MAX_RAW_BODY_LEN, RawExchange, SCRUBBED};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use store_health::StoreWrites;
+pub use store_health::{DegradedCause, DegradedStore, StoreHealth};
+use thread_cache::{
+    THREAD_CACHE_CAPACITY, THREAD_CACHE_MAX_MESSAGES, THREAD_CACHE_TTL, ThreadCache,
+};
+pub use thread_instructions::{RecordedInstructions, RulesSnapshot, ThreadInstructions};
+pub use thread_locks::{
+    LockOverride, STORE_LOCK_LAPSE_MINUTES, StoreLockHolder, ThreadBusy, ThreadLock, ThreadLocks,
+};
+use thread_tags::tag_prefix_pattern;
+pub use thread_tags::{MAX_TAG_LEN, TagSuggestion, normalize_tag};
+pub use thread_templates::{template_messages, template_parent};
+use tool_pairs::request_contents;
+pub use tool_pairs::{TOOL_PAIR_REPAIR, ToolPairRepair, repair_tool_pairs};
+pub use usage::{
+    RequestUsageRecord, TOKEN_BUCKET_BOUNDS, TokenBucket, UsageAggregate, UsageBreakdown,
+    UsageDimension, UsageExportConfig, UsageExporter, UsageHeatmap, UsageHeatmapRow,
+    UsageHistogramRow, usage_aggregates,
+};
 // pub use example::run_message_handler_example;
 pub use registry::{
-    MessageHandlerConfig, MessageHandlerRegistry, create_conversation_id, get_message_handler,
-    get_message_handler_async, init_message_handler,
+    MessageHandlerConfig, MessageHandlerRegistry, config_diagnostics, create_conversation_id,
+    get_message_handler, get_message_handler_async, init_message_handler, job_statuses,
+    lock_thread, message_author, persistence_features, persistence_paused,
+    prompt_experiment_variant, record_prompt_experiment_outcome, record_run_result,
+    register_request_interceptor, register_response_interceptor, register_tokenizer,
+    request_interceptors, response_interceptors, save_commit_thread, save_file_snapshots,
+    save_session_env, save_thread_instructions, schedule_job, set_agent_identity, set_chaos,
+    set_checkpoint_partitioning, set_collaboration_persistence, set_compaction_policy,
+    set_completion_fixtures, set_context_summarizer, set_conversation_retention,
+    set_disabled_response_interceptors, set_issue_commenter, set_job_schedules, set_lint_policy,
+    set_maintenance_policy, set_message_author, set_message_rules, set_persistence_features,
+    set_persistence_paused, set_prompt_experiments, set_raw_exchange_capture,
+    set_remote_persistence, set_response_cache_policy, set_sampling_policy, set_secret_scan,
+    set_shadow_persistence, set_store_authorizer, set_thread_notifier, set_trace_exporter,
+    set_usage_exporter, shadow_stats, store_health, store_read_only, subscribe_llm_traffic,
+    subscribe_persistence_acks, subscribe_run_results, unschedule_job,
 };
+pub use remote_persistence::{
+    ProtoRemotePersistence, RemotePersistence, RemotePersistenceRoutes, forwarded_messages,
+};
+pub use replay::replay_messages;
+pub use response_cache::{ResponseCache, ResponseCachePolicy, cached_completion};
+pub use run_results::{RunResult, RunResults, RunStatus};
+pub use sampling::{SamplingDecision, SamplingPolicy, SamplingReason, ThreadSampler};
+pub use scheduler::{JobState, JobStatus, Schedule, ScheduledJob};
+pub use schema_version::MESSAGE_SCHEMA_VERSION;
+pub use secrets::{SecretAction, SecretFinding, SecretScanPolicy, SecretScanner};
+pub use sequencing::message_sequence;
+pub use session_env::{SESSION_ENV_VARS, SessionEnvironment, sanitize_env};
+pub use shadow::{SHADOW_SUMMARY_INTERVAL, ShadowPersistence, ShadowStats};
 
 /// Message types compatible with LangGraph's data model
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum MessageType {
     #[serde(rename = "human")]
@@ -138,324 +316,4052 @@ pub enum Message {
     },
 }
 
-/// Interface for database operations
-pub trait DatabaseClient: Send + Sync {
-    async fn save_append_messages(&self, message: Vec<Message>, ids: &RequestIds);
-}
+/// The `additional_kwargs` entry of a message whose content failed to serialize, with the error
+/// and the debug form of the content, stored in place of the content lost.
+pub const PERSIST_FAILED: &str = "persist_failed";
 
-/// Message handler for interfacing with LangGraph and database storage
-pub struct AiMessageHandler {
-    database_client: Option<Arc<PostgresDatabaseClient>>,
+/// The `event` of the note saved after the part of a completion that was streamed before the
+/// user cancelled it.
+pub const CANCELLED_BY_USER: &str = "cancelled_by_user";
+
+/// How many checkpoints of a thread are read at a time when all of its messages are needed.
+const STORED_MESSAGES_PAGE_SIZE: usize = 100;
+
+/// How many requests' prompt counts are kept waiting for their completions to end, more than are
+/// ever in flight at once.
+const PROMPT_TOKENS_CAPACITY: usize = 256;
+
+/// How many threads' last models are kept to notice hand-offs, more than are ever active at once.
+const LAST_MODELS_CAPACITY: usize = 1024;
+
+/// The `response_metadata` entry of the git branch a request was made on.
+const GIT_BRANCH: &str = "git_branch";
+
+/// The git branch the messages' request was made on, if it was on one.
+pub(crate) fn messages_git_branch(messages: &[Message]) -> Option<&str> {
+    messages
+        .iter()
+        .find_map(|message| message.response_metadata().get(GIT_BRANCH)?.as_str())
+        .filter(|git_branch| !git_branch.is_empty())
 }
 
-pub trait MessageHandlerTrait: Send + Sync {}
+/// The `response_metadata` entry of the scheduled run a request was made by.
+const SCHEDULE_ID: &str = "schedule_id";
 
-impl MessageHandlerTrait for AiMessageHandler {}
+/// The scheduled run the messages' thread was started by, if one started it.
+pub fn messages_schedule_id(messages: &[Message]) -> Option<&str> {
+    messages
+        .iter()
+        .find_map(|message| message.response_metadata().get(SCHEDULE_ID)?.as_str())
+}
 
-impl Global for AiMessageHandler {}
+/// The `response_metadata` entry of the group of sibling requests a request was fanned out to.
+const FAN_OUT_GROUP: &str = "fan_out_group";
 
-#[derive(Clone)]
-pub struct LanguageModelArgs {
-    pub model_id: LanguageModelId,
-    pub temperature: Option<f32>,
-    pub intent: Option<String>,
-    pub mode: Option<String>,
-    pub prompt_id: Option<String>,
+/// The fan-out group the messages' thread is one of the siblings of, if it is.
+pub fn messages_fan_out_group(messages: &[Message]) -> Option<&str> {
+    messages
+        .iter()
+        .find_map(|message| message.response_metadata().get(FAN_OUT_GROUP)?.as_str())
 }
 
-impl LanguageModelArgs {
-    pub fn new(model_id: LanguageModelId) -> Self {
-        Self {
-            model_id,
-            temperature: None,
-            intent: None,
-            mode: None,
-            prompt_id: None,
+impl Message {
+    /// The id of the thread the message belongs to.
+    pub(crate) fn id_mut(&mut self) -> &mut String {
+        match self {
+            Message::Human { id, .. }
+            | Message::Ai { id, .. }
+            | Message::System { id, .. }
+            | Message::Tool { id, .. }
+            | Message::Function { id, .. } => id,
         }
     }
 
-    pub fn from_request(model_id: LanguageModelId, request: &LanguageModelRequest) -> Self {
-        Self {
-            model_id,
-            temperature: request.temperature,
-            intent: request.intent.as_ref().map(|i| format!("{:?}", i)),
-            mode: request.mode.as_ref().map(|m| format!("{:?}", m)),
-            prompt_id: request.prompt_id.clone(),
+    pub(crate) fn response_metadata_mut(&mut self) -> &mut HashMap<String, serde_json::Value> {
+        match self {
+            Message::Human {
+                response_metadata, ..
+            }
+            | Message::Ai {
+                response_metadata, ..
+            }
+            | Message::System {
+                response_metadata, ..
+            }
+            | Message::Tool {
+                response_metadata, ..
+            }
+            | Message::Function {
+                response_metadata, ..
+            } => response_metadata,
         }
     }
 }
 
-pub fn peek_db<T>(
-    stream: T,
-    message_handler: Option<Arc<AiMessageHandler>>,
-    ids: RequestIds,
-    language_model_args: LanguageModelArgs,
-) -> T
-where
-    T: Stream<Item = Result<LanguageModelCompletionEvent, LanguageModelCompletionError>>,
-{
-    if let Some(handler) = message_handler {
-        let stream =
-            AiMessageHandler::inspect_stream(stream, handler.clone(), ids, language_model_args);
-        stream
-    } else {
-        stream
-    }
-}
-
-impl AiMessageHandler {
-    pub fn new(database_client: Option<Arc<PostgresDatabaseClient>>) -> Self {
-        Self { database_client }
-    }
+/// The store messages are saved to, as chosen by the enabled backend feature. Without one, the
+/// handler never connects and only caches messages locally.
+#[cfg(feature = "postgres")]
+pub type StoreClient = PostgresDatabaseClient;
+#[cfg(not(feature = "postgres"))]
+pub type StoreClient = NoopDatabaseClient;
 
-    pub async fn save_completion_req(
+/// Interface for database operations
+pub trait DatabaseClient: Send + Sync {
+    async fn save_append_messages(
         &self,
-        request_message: &LanguageModelRequest,
+        message: Vec<Message>,
         ids: &RequestIds,
-        language_model_args: LanguageModelArgs,
-    ) {
-        let collected = request_message
-            .messages
-            .iter()
-            .flat_map(|r| {
-                Self::map_from_completion_request(r, ids, &language_model_args).into_iter()
-            })
-            .collect::<Vec<Message>>();
-        let _ = self.save_append_messages(collected, ids).await;
-    }
+        author: Option<&MessageAuthor>,
+    ) -> anyhow::Result<()>;
 
-    pub async fn save_completion_event(
+    /// Stored threads, in the order `sort` gives, starting after `after`. Only those started on
+    /// `git_branch` are listed if one is given.
+    async fn list_threads(
         &self,
-        request_message: &LanguageModelCompletionEvent,
-        ids: &RequestIds,
-        language_model_args: &LanguageModelArgs,
-    ) {
-        if let Some(msg) = Self::map_from_completion_event(
-            request_message,
-            &ids.checkpoint_id,
-            language_model_args,
-        ) {
-            let _ = self.save_append_messages(vec![msg], ids).await;
-        }
-    }
+        after: Option<&ThreadCursor>,
+        limit: usize,
+        git_branch: Option<&str>,
+        sort: ThreadSort,
+    ) -> anyhow::Result<Page<StoredThread, ThreadCursor>>;
 
-    fn build_response_metadata(
-        language_model_args: &LanguageModelArgs,
-    ) -> HashMap<String, serde_json::Value> {
-        let mut response_metadata = HashMap::new();
+    /// Sets or clears the flag on the thread.
+    async fn set_thread_flag(
+        &self,
+        thread_id: &str,
+        flag: ThreadFlag,
+        value: bool,
+    ) -> anyhow::Result<()>;
 
-        response_metadata.insert(
-            "model_id".to_string(),
-            serde_json::Value::from(format!("{:?}", language_model_args.model_id.0.to_string())),
-        );
+    /// Tags the thread. Adding a tag the thread already has changes nothing.
+    async fn add_thread_tag(&self, thread_id: &str, tag: &str) -> anyhow::Result<()>;
 
-        if let Some(temperature) = language_model_args.temperature {
-            response_metadata.insert(
-                "temperature".to_string(),
-                serde_json::Value::from(temperature),
-            );
-        }
-        if let Some(intent) = &language_model_args.intent {
-            response_metadata.insert(
-                "intent".to_string(),
-                serde_json::Value::from(intent.clone()),
-            );
-        }
-        if let Some(mode) = &language_model_args.mode {
-            response_metadata.insert("mode".to_string(), serde_json::Value::from(mode.clone()));
-        }
-        if let Some(prompt_id) = &language_model_args.prompt_id {
-            response_metadata.insert(
-                "prompt_id".to_string(),
-                serde_json::Value::from(prompt_id.clone()),
-            );
-        }
-        response_metadata
-    }
+    async fn remove_thread_tag(&self, thread_id: &str, tag: &str) -> anyhow::Result<()>;
 
-    pub fn map_from_completion_request(
-        request_message: &LanguageModelRequestMessage,
-        id: &RequestIds,
-        language_model_args: &LanguageModelArgs,
-    ) -> Option<Message> {
-        let content = match serde_json::to_string(&request_message.content) {
-            Ok(content) => content,
-            Err(e) => {
-                log::error!("Failed to serialize request message content: {}", e);
-                String::default()
-            }
-        };
-        let content_value = ContentValue::new(content);
-        let id = id.thread_id.to_string();
+    /// The thread's tags, in alphabetical order.
+    async fn thread_tags(&self, thread_id: &str) -> anyhow::Result<Vec<String>>;
 
-        let response_metadata = Self::build_response_metadata(language_model_args);
+    /// The tags matching the `LIKE` pattern once lowercased, those on the most threads first.
+    async fn tag_suggestions(
+        &self,
+        pattern: &str,
+        limit: usize,
+    ) -> anyhow::Result<Vec<TagSuggestion>>;
 
-        match &request_message.role {
-            Role::User => Some(Message::Human {
-                content: content_value,
-                id,
-                name: Some("ZedIdeAgent".to_string()),
-                example: false,
-                additional_kwargs: HashMap::new(),
-                response_metadata,
-            }),
-            Role::System => Some(Message::System {
-                content: content_value,
-                id,
-                name: Some("ZedIdeAgent".to_string()),
-                example: false,
-                additional_kwargs: HashMap::new(),
-                response_metadata,
-            }),
-            Role::Assistant => Some(Message::Ai {
-                content: content_value,
-                id,
-                name: Some("ZedIdeAgent".to_string()),
-                example: false,
-                invalid_tool_calls: None,
-                tool_calls: None,
-                additional_kwargs: HashMap::new(),
-                response_metadata,
-            }),
-        }
-    }
+    /// Links the thread to the issue, replacing the issue it was linked to and clearing whether
+    /// it was resolved.
+    async fn link_thread_issue(&self, thread_id: &str, issue: &IssueLink) -> anyhow::Result<()>;
 
-    pub fn map_from_completion_event(
-        request_message: &LanguageModelCompletionEvent,
+    async fn unlink_thread_issue(&self, thread_id: &str) -> anyhow::Result<()>;
+
+    async fn thread_issue(&self, thread_id: &str) -> anyhow::Result<Option<ThreadIssue>>;
+
+    async fn set_thread_issue_resolved(
+        &self,
         thread_id: &str,
-        language_model_args: &LanguageModelArgs,
-    ) -> Option<Message> {
-        let response_metadata = Self::build_response_metadata(&language_model_args);
-        match request_message {
-            LanguageModelCompletionEvent::StatusUpdate { .. } => None,
-            LanguageModelCompletionEvent::StartMessage { .. } => None,
-            LanguageModelCompletionEvent::Text(text) => {
-                let id = thread_id.to_string();
-                Some(Message::Ai {
-                    content: ContentValue::new(text.clone()),
-                    id,
-                    name: Some("ZedIdeAgent".to_string()),
-                    example: false,
-                    invalid_tool_calls: None,
-                    tool_calls: None,
-                    additional_kwargs: HashMap::new(),
-                    response_metadata,
-                })
-            }
-            LanguageModelCompletionEvent::Thinking { text, signature } => {
-                let id = thread_id.to_string();
-                let mut additional_kwargs = HashMap::new();
-                additional_kwargs.insert(
-                    "thinking".to_string(),
-                    serde_json::Value::String(text.clone()),
-                );
-                if let Some(sig) = signature {
-                    additional_kwargs.insert(
-                        "signature".to_string(),
-                        serde_json::Value::String(sig.clone()),
-                    );
-                }
+        resolved: bool,
+    ) -> anyhow::Result<()>;
 
-                Some(Message::Ai {
-                    content: ContentValue::new(text.clone()),
-                    id,
-                    name: Some("ZedIdeAgent".to_string()),
-                    example: false,
-                    invalid_tool_calls: None,
-                    tool_calls: None,
-                    additional_kwargs,
-                    response_metadata,
-                })
-            }
-            LanguageModelCompletionEvent::Stop(_) => {
-                let id = thread_id.to_string();
-                Some(Message::Ai {
-                    content: ContentValue::new("STOP".to_string()),
-                    id,
-                    name: Some("ZedIdeAgent".to_string()),
-                    example: false,
-                    invalid_tool_calls: None,
-                    tool_calls: None,
-                    additional_kwargs: HashMap::new(),
-                    response_metadata,
-                })
-            }
-            LanguageModelCompletionEvent::ToolUse(tool_use) => {
-                let content = match serde_json::to_string(&tool_use.input) {
-                    Ok(content) => content,
-                    Err(e) => {
-                        log::error!("Failed to serialize tool use input: {}", e);
-                        String::default()
-                    }
-                };
-                let mut additional_kwargs = HashMap::new();
-                additional_kwargs.insert(
-                    "raw_input".to_string(),
-                    serde_json::Value::String(tool_use.raw_input.clone()),
-                );
-                additional_kwargs.insert(
-                    "is_input_complete".to_string(),
-                    serde_json::Value::Bool(tool_use.is_input_complete),
-                );
+    async fn get_thread(&self, thread_id: &str) -> anyhow::Result<Option<StoredThread>>;
 
-                Some(Message::Tool {
-                    content: ContentValue::new(content),
-                    id: tool_use.id.to_string(),
-                    name: Some("ZedIdeAgent".to_string()),
-                    example: false,
-                    tool_call_id: Some(tool_use.id.to_string()),
-                    tool_name: Some(tool_use.name.as_ref().to_string()),
-                    additional_kwargs,
-                    response_metadata,
-                })
-            }
-            LanguageModelCompletionEvent::UsageUpdate(_token_usage) => None,
-        }
-    }
+    /// The thread's messages, oldest first, from the `limit` checkpoints after `after_seq`.
+    async fn list_messages(
+        &self,
+        thread_id: &str,
+        after_seq: Option<i64>,
+        limit: usize,
+    ) -> anyhow::Result<Page<StoredMessage, i64>>;
 
-    /// Save a message to the database
-    pub async fn save_append_messages(
+    /// The messages stored for the thread, oldest first, leaving out compacted checkpoints.
+    async fn load_thread(&self, thread_id: &str) -> anyhow::Result<Vec<Message>>;
+
+    /// The tokens counted across the thread's uncompacted checkpoints.
+    async fn stored_token_count(&self, thread_id: &str) -> anyhow::Result<i64>;
+
+    /// The messages [`Self::load_thread`] returns, with the checkpoints they are stored in.
+    async fn uncompacted_messages(&self, thread_id: &str) -> anyhow::Result<Vec<StoredMessage>>;
+
+    /// Stores `summary` in place of the thread's checkpoints up to `through_seq`, leaving them
+    /// out of what it is resumed from, and returns how many were compacted. Nothing is compacted
+    /// if messages were stored in the thread after the one numbered `through_sequence`, since the
+    /// summary doesn't cover them.
+    async fn compact_thread(
         &self,
-        messages: Vec<Message>,
+        summary: Message,
         ids: &RequestIds,
-    ) -> anyhow::Result<()> {
-        if let Some(ref db_client) = self.database_client {
-            db_client.save_append_messages(messages, ids).await;
-        }
+        author: Option<&MessageAuthor>,
+        through_seq: i64,
+        through_sequence: Option<u64>,
+    ) -> anyhow::Result<u64>;
+
+    /// Like [`Self::load_thread`], but reads the thread a batch at a time as the stream is polled,
+    /// so that it never has to be held in memory whole.
+    fn stream_messages(&self, thread_id: &str) -> BoxStream<'static, anyhow::Result<Message>>;
+
+    /// Stores `source` as the next version of the template, unless it is already its latest
+    /// version.
+    async fn register_prompt_template(
+        &self,
+        id: &str,
+        source: &str,
+    ) -> anyhow::Result<PromptTemplateRef>;
+
+    /// Every version of the template, oldest first.
+    async fn prompt_template_versions(&self, id: &str) -> anyhow::Result<Vec<PromptTemplate>>;
+
+    async fn record_experiment_outcome(
+        &self,
+        assignment: &PromptExperimentAssignment,
+        thread_id: &str,
+        metric: ExperimentMetric,
+        value: f64,
+    ) -> anyhow::Result<()>;
+
+    /// How each of the experiment's variants did on each metric.
+    async fn experiment_results(&self, experiment_id: &str) -> anyhow::Result<Vec<VariantStats>>;
+
+    /// Records what the checkpoint's completion did with the provider's prompt cache. Recording
+    /// a checkpoint again changes nothing.
+    async fn record_prompt_cache_usage(
+        &self,
+        ids: &RequestIds,
+        usage: &PromptCacheUsage,
+    ) -> anyhow::Result<()>;
+
+    /// How well each version of each template was cached.
+    async fn prompt_cache_stats(&self) -> anyhow::Result<Vec<PromptCacheStats>>;
+
+    /// Records what the checkpoint's completion used, and the buffer its request was made from.
+    /// Recording a checkpoint again changes nothing.
+    async fn record_request_usage(
+        &self,
+        ids: &RequestIds,
+        usage: &RequestUsageRecord,
+    ) -> anyhow::Result<()>;
+
+    /// What the requests made from `since` on used, by day and by `dimension`.
+    async fn usage_breakdown(
+        &self,
+        dimension: UsageDimension,
+        since: NaiveDate,
+    ) -> anyhow::Result<Vec<UsageBreakdown>>;
+
+    /// How many requests to each model on `day` used each bucket's worth of tokens.
+    async fn usage_histogram(&self, day: NaiveDate) -> anyhow::Result<Vec<UsageHistogramRow>>;
+
+    /// Stores an HTTP exchange the checkpoint's completion was requested with.
+    async fn save_raw_exchange(
+        &self,
+        ids: &RequestIds,
+        exchange: &RawExchange,
+    ) -> anyhow::Result<()>;
+
+    /// Records that `referrer`, e.g. a branch or an annotation, references the checkpoint, so
+    /// that garbage collection keeps it and the checkpoints of its thread before it.
+    async fn add_checkpoint_reference(
+        &self,
+        thread_id: &str,
+        checkpoint_id: &str,
+        referrer: &str,
+    ) -> anyhow::Result<()>;
+
+    async fn remove_checkpoint_reference(
+        &self,
+        thread_id: &str,
+        checkpoint_id: &str,
+        referrer: &str,
+    ) -> anyhow::Result<()>;
+
+    /// Deletes the checkpoints that haven't been written within `retention` and that no
+    /// checkpoint kept reaches (see [`unreachable_checkpoints`]), with what is stored for them,
+    /// and then the content blobs no checkpoint references anymore.
+    async fn collect_garbage(&self, retention: Duration) -> anyhow::Result<GarbageCollection>;
+
+    /// Partitions the checkpoints by the month they were written in, if they aren't yet, creates
+    /// the partitions of the months ahead and detaches those past `detach_after_months`.
+    async fn maintain_partitions(
+        &self,
+        partitioning: &CheckpointPartitioning,
+    ) -> anyhow::Result<PartitionMaintenance>;
+
+    /// What Postgres' statistics say about the store's tables and indexes.
+    async fn maintenance_report(&self) -> anyhow::Result<MaintenanceReport>;
+
+    /// Runs a maintenance statement, e.g. `VACUUM`, outside of a transaction.
+    async fn run_maintenance(&self, statement: &str) -> anyhow::Result<()>;
+
+    /// Stores the environment the session's conversations ran against, merging its toolchains
+    /// into those of any earlier snapshot of the session.
+    async fn save_session_env(
+        &self,
+        session_id: &str,
+        env: &SessionEnvironment,
+    ) -> anyhow::Result<()>;
+
+    /// The latest snapshot of the session's environment, if one was stored.
+    async fn session_env(&self, session_id: &str) -> anyhow::Result<Option<SessionEnvironment>>;
+
+    /// Records in the session's metadata whether the thread is stored. A thread recorded as
+    /// stored is never recorded as not stored again.
+    async fn save_sampling_decision(
+        &self,
+        session_id: &str,
+        thread_id: &str,
+        decision: &SamplingDecision,
+    ) -> anyhow::Result<()>;
+
+    /// Stores the snapshots of the files the thread's tool calls referenced, replacing any
+    /// earlier snapshot of the same files.
+    async fn save_file_snapshots(
+        &self,
+        thread_id: &str,
+        snapshots: &[FileSnapshot],
+    ) -> anyhow::Result<()>;
+
+    /// The latest snapshot of each file the thread's tool calls referenced.
+    async fn file_snapshots(&self, thread_id: &str) -> anyhow::Result<Vec<FileSnapshot>>;
+
+    /// The threads that referenced the file, most recently first.
+    async fn threads_referencing_file(
+        &self,
+        path: &str,
+        limit: usize,
+    ) -> anyhow::Result<Vec<String>>;
+
+    /// Records that the commit was made during the thread's session, replacing any thread it
+    /// was recorded for before.
+    async fn save_commit_thread(&self, sha: &str, thread_id: &str) -> anyhow::Result<()>;
+
+    /// The thread whose session the commit was made during, if one was recorded.
+    async fn commit_thread(&self, sha: &str) -> anyhow::Result<Option<String>>;
+
+    /// Locks the thread for the session, or renews the session's lock, unless another session
+    /// holds a lock that hasn't lapsed, which is returned instead.
+    async fn acquire_store_thread_lock(
+        &self,
+        thread_id: &str,
+        session_id: &str,
+        author: Option<&MessageAuthor>,
+    ) -> anyhow::Result<Option<StoreLockHolder>>;
+
+    /// Releases the session's lock of the thread, if it holds it.
+    async fn release_store_thread_lock(
+        &self,
+        thread_id: &str,
+        session_id: &str,
+    ) -> anyhow::Result<()>;
+
+    /// The locks of the threads that haven't lapsed.
+    async fn store_thread_lock_holders(
+        &self,
+        thread_ids: &[String],
+    ) -> anyhow::Result<Vec<StoreLockHolder>>;
+
+    /// Releases the thread's lock whoever holds it, recording who released it and how in the
+    /// lock audit log. Returns the holder it was taken from, if it was held.
+    async fn override_store_thread_lock(
+        &self,
+        thread_id: &str,
+        action: LockOverride,
+        author: Option<&MessageAuthor>,
+    ) -> anyhow::Result<Option<StoreLockHolder>>;
+
+    /// Stores the tool schemas by their hash, leaving those already stored as they are.
+    async fn save_tool_schemas(&self, schemas: &[ToolSchema]) -> anyhow::Result<()>;
+
+    /// The stored tool schemas with the given hashes.
+    async fn tool_schemas(&self, hashes: &[String]) -> anyhow::Result<Vec<ToolSchema>>;
+
+    /// Stores how a tool call's input compared to its schema, replacing the result stored for
+    /// the call before.
+    async fn save_tool_call_validation(
+        &self,
+        validation: &ToolCallValidation,
+    ) -> anyhow::Result<()>;
+
+    /// The latest `limit` threads in which a model made invalid tool calls, by provider and
+    /// model, of `provider` only if given, most recently invalid first.
+    async fn threads_with_invalid_tool_calls(
+        &self,
+        provider: Option<&str>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<InvalidToolCallThread>>;
+
+    /// Records the instructions the thread's requests are made with from now on.
+    async fn save_thread_instructions(
+        &self,
+        thread_id: &str,
+        instructions: &ThreadInstructions,
+    ) -> anyhow::Result<()>;
+
+    /// The instructions the thread's requests were made with, as first recorded and after each
+    /// change, oldest first.
+    async fn thread_instructions(
+        &self,
+        thread_id: &str,
+    ) -> anyhow::Result<Vec<RecordedInstructions>>;
+
+    async fn save_run_result(&self, result: &RunResult) -> anyhow::Result<()>;
+
+    /// The results of the latest `limit` runs made without a user, most recent first.
+    async fn run_results(&self, limit: usize) -> anyhow::Result<Vec<RunResult>>;
+
+    /// Stores how the judge model ranked the responses of a fan-out group, replacing any earlier
+    /// ranking of the group.
+    async fn save_fan_out_verdict(&self, verdict: &FanOutVerdict) -> anyhow::Result<()>;
+
+    async fn fan_out_verdict(&self, group_id: &str) -> anyhow::Result<Option<FanOutVerdict>>;
+}
+
+/// Something sent to or received from a model, as the handler saw it go by.
+#[derive(Debug, Clone)]
+pub struct TrafficEvent {
+    pub ids: RequestIds,
+    pub model_id: LanguageModelId,
+    pub kind: TrafficKind,
+}
+
+#[derive(Debug, Clone)]
+pub enum TrafficKind {
+    /// A request's messages, with the tokens its prompt was counted at locally.
+    Request {
+        messages: Vec<(Role, String)>,
+        prompt_tokens: usize,
+    },
+    /// An event of the completion stream, as it arrived.
+    Event(LanguageModelCompletionEvent),
+    Error(String),
+    /// A request message's content failed to serialize, so a placeholder was stored instead.
+    PersistFailed {
+        error: String,
+    },
+    /// The completion ended, with what the provider reported it used, if anything.
+    End {
+        usage: Option<TokenUsage>,
+    },
+}
+
+/// Hands every request and completion event the handler sees to whoever is watching, whether
+/// or not it is stored.
+#[derive(Default)]
+pub struct LlmTraffic {
+    subscribers: Mutex<Vec<mpsc::UnboundedSender<TrafficEvent>>>,
+}
+
+impl LlmTraffic {
+    /// Receives the traffic from now on, until the receiver is dropped.
+    pub fn subscribe(&self) -> mpsc::UnboundedReceiver<TrafficEvent> {
+        let (tx, rx) = mpsc::unbounded();
+        self.subscribers.lock().push(tx);
+        rx
+    }
+
+    /// Sends the event to the subscribers, only building it if there are any.
+    pub(crate) fn publish(&self, event: impl FnOnce() -> TrafficEvent) {
+        let mut subscribers = self.subscribers.lock();
+        subscribers.retain(|subscriber| !subscriber.is_closed());
+        if subscribers.is_empty() {
+            return;
+        }
+        let event = event();
+        for subscriber in subscribers.iter() {
+            subscriber.unbounded_send(event.clone()).ok();
+        }
+    }
+}
+
+const WRITE_LANE: &str = "write_lane";
+
+/// Where a stored message's write fell in its thread's write lane.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LaneSlot {
+    /// The checkpoint of the completion stream the message was written by, which tells the
+    /// streams writing to the thread at once apart.
+    pub stream: String,
+    /// How many writes were queued on the lane before this one, since it last drained.
+    pub position: u64,
+}
+
+/// Writes each thread's messages one at a time, in the order they were queued, so that the
+/// completions streaming into a thread at once, e.g. those of sub-agents, are stored in one
+/// interleaving rather than in whatever order their writes reach the store.
+#[derive(Default)]
+pub(crate) struct WriteLanes {
+    next_lane: AtomicU64,
+    lanes: Mutex<HashMap<String, Lane>>,
+}
+
+/// A write whose place on the lane is taken, and whose future is made once the lanes are
+/// unlocked.
+type QueuedWrite = oneshot::Receiver<BoxFuture<'static, ()>>;
+
+/// A thread's lane, open while it has writes queued.
+struct Lane {
+    id: u64,
+    next_position: u64,
+    queued: usize,
+    writes: mpsc::UnboundedSender<QueuedWrite>,
+}
+
+impl WriteLanes {
+    /// Queues a write on the thread's lane, after the writes queued on it before. `write` is
+    /// called with the write's position on the lane, once its place is taken. Writes reach the
+    /// store in the order of the lane, which numbers their messages in that order.
+    pub(crate) fn enqueue(
+        self: &Arc<Self>,
+        thread_id: &str,
+        write: impl FnOnce(u64) -> BoxFuture<'static, ()>,
+    ) {
+        let (write_tx, write_rx) = oneshot::channel();
+        let position = {
+            let mut lanes = self.lanes.lock();
+            let lane = lanes
+                .entry(thread_id.to_string())
+                .or_insert_with(|| self.open(thread_id));
+            let position = lane.next_position;
+            lane.next_position += 1;
+            lane.queued += 1;
+            lane.writes.unbounded_send(write_rx).ok();
+            position
+        };
+        write_tx.send(write(position)).ok();
+    }
+
+    /// Opens a lane for the thread, whose writes are done until none are queued on it.
+    fn open(self: &Arc<Self>, thread_id: &str) -> Lane {
+        let id = self.next_lane.fetch_add(1, Ordering::SeqCst);
+        let (writes, mut queued) = mpsc::unbounded::<QueuedWrite>();
+        let lanes = self.clone();
+        let thread_id = thread_id.to_string();
+        smol::spawn(async move {
+            while let Some(write) = queued.next().await {
+                if let Ok(write) = write.await {
+                    write.await;
+                }
+                let mut lanes = lanes.lanes.lock();
+                let Some(lane) = lanes.get_mut(&thread_id).filter(|lane| lane.id == id) else {
+                    return;
+                };
+                lane.queued -= 1;
+                if lane.queued == 0 {
+                    lanes.remove(&thread_id);
+                    return;
+                }
+            }
+        })
+        .detach();
+        Lane {
+            id,
+            next_position: 0,
+            queued: 0,
+            writes,
+        }
+    }
+}
+
+/// Records where the messages of the completion stream with the checkpoint were written on their
+/// thread's lane, in their `response_metadata`.
+pub(crate) fn stamp_write_lane(messages: &mut [Message], checkpoint_id: &str, position: u64) {
+    for message in messages {
+        message.response_metadata_mut().insert(
+            WRITE_LANE.to_string(),
+            serde_json::json!({ "stream": checkpoint_id, "position": position }),
+        );
+    }
+}
+
+/// Where the message was written on its thread's lane. Messages written outside a lane have none.
+pub fn message_write_lane(message: &Message) -> Option<LaneSlot> {
+    let slot = message.response_metadata().get(WRITE_LANE)?;
+    Some(LaneSlot {
+        stream: slot.get("stream")?.as_str()?.to_string(),
+        position: slot.get("position")?.as_u64()?,
+    })
+}
+
+/// Which participants of a shared project store their completions, since the host and each guest
+/// stream their own and would otherwise all write to a shared store.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CollaborationPersistence {
+    /// Everyone stores their completions, tagged with the peer that made them.
+    #[default]
+    TagPeer,
+    /// Only the host stores completions; guests' aren't stored.
+    HostOnly,
+}
+
+impl CollaborationPersistence {
+    /// Whether completions made by `origin` are stored. Requests from unshared projects always are.
+    pub fn persists(&self, origin: Option<&RequestOrigin>) -> bool {
+        match (self, origin) {
+            (CollaborationPersistence::HostOnly, Some(origin)) => origin.is_host,
+            _ => true,
+        }
+    }
+}
+
+/// The name stored messages are saved under when no other is configured.
+pub const DEFAULT_AGENT_NAME: &str = "ZedIdeAgent";
+
+/// The names stored messages are saved under, so that consumers of the store can tell apart
+/// where they came from.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct AgentIdentity {
+    /// The name of messages no other name is configured for.
+    pub name: String,
+    /// Names by the intent of the request the messages were saved for, e.g. `InlineAssist`,
+    /// `TerminalInlineAssist`, `UserPrompt` or `GenerateGitCommitMessage`.
+    pub by_intent: collections::HashMap<String, String>,
+    /// Names by the project the request was made in, the name of its first worktree. Names by
+    /// intent take precedence over them.
+    pub by_project: collections::HashMap<String, String>,
+}
+
+impl Default for AgentIdentity {
+    fn default() -> Self {
+        Self {
+            name: DEFAULT_AGENT_NAME.to_string(),
+            by_intent: Default::default(),
+            by_project: Default::default(),
+        }
+    }
+}
+
+impl AgentIdentity {
+    /// The name of messages saved for a request with the intent, made in the project.
+    pub fn name_for(&self, intent: Option<&str>, project: Option<&str>) -> &str {
+        intent
+            .and_then(|intent| self.by_intent.get(intent))
+            .or_else(|| project.and_then(|project| self.by_project.get(project)))
+            .unwrap_or(&self.name)
+    }
+}
+
+/// Message handler for interfacing with LangGraph and database storage
+pub struct AiMessageHandler {
+    database_client: Option<Arc<StoreClient>>,
+    trace_exporter: Option<Arc<LangSmithExporter>>,
+    issue_commenter: Option<Arc<IssueCommenter>>,
+    notifier: Option<Arc<ThreadNotifier>>,
+    maintenance_policy: MaintenancePolicy,
+    lint_policy: LintPolicy,
+    agent_identity: AgentIdentity,
+    author: Option<MessageAuthor>,
+    authorizer: Arc<dyn StoreAuthorizer>,
+    /// The model and checkpoint of the last request saved for each thread, to notice hand-offs.
+    last_models: Arc<BoundedMap<(LanguageModelId, String)>>,
+    /// The latest registered version of each template, to avoid registering it on every request.
+    prompt_templates: Mutex<HashMap<String, (Arc<str>, PromptTemplateRef)>>,
+    thread_cache: Mutex<ThreadCache>,
+    local_cache: Option<Arc<LocalMessageCache>>,
+    /// Local checkpoints being saved to the remote store, so that they aren't saved twice.
+    replicating: Mutex<HashSet<i64>>,
+    /// Held while pending local checkpoints are saved to the remote store.
+    replication: smol::lock::Mutex<()>,
+    tokenizers: Tokenizers,
+    /// The locally counted size of each checkpoint's prompt, until its completion ends.
+    prompt_tokens: Arc<BoundedMap<usize>>,
+    compaction_policy: Option<CompactionPolicy>,
+    context_summarizer: Option<Arc<dyn ContextSummarizer>>,
+    /// Threads being compacted, so that a thread isn't summarized twice at once.
+    compacting: Mutex<HashSet<String>>,
+    /// The hashes of the tool schemas stored, to store each only once.
+    saved_tool_schemas: Mutex<HashSet<String>>,
+    collaboration_persistence: CollaborationPersistence,
+    remote_persistence: RemotePersistenceRoutes,
+    /// Set in shadow mode, where nothing is written.
+    shadow: Option<Arc<ShadowPersistence>>,
+    sampler: Option<Arc<ThreadSampler>>,
+    message_filter: Option<Arc<MessageFilter>>,
+    thread_locks: ThreadLocks,
+    traffic: Arc<LlmTraffic>,
+    /// Whether the raw HTTP exchanges with providers are stored too, to debug the providers.
+    capture_raw_exchanges: bool,
+    response_cache: Option<Arc<ResponseCache>>,
+    fixtures: Option<Arc<CompletionFixtures>>,
+    /// Faults injected into the store's operations, to test how their failures are handled.
+    store_chaos: Option<Arc<Chaos>>,
+    /// Faults injected into the providers' completions.
+    provider_chaos: Option<Arc<Chaos>>,
+    /// The writes of completion events still in flight, to [`Self::flush`] a thread.
+    pending_writes: Arc<PendingWrites>,
+    /// Each thread's writes of completions, done one at a time in the order they were queued.
+    write_lanes: Arc<WriteLanes>,
+    secret_scanner: Option<Arc<SecretScanner>>,
+    /// Set while the user has paused storing messages.
+    persistence_paused: bool,
+    /// The parts of completions that are stored while storing isn't paused.
+    persistence_features: PersistenceFeatures,
+    /// Set when the store is only browsed, e.g. a team's shared history.
+    read_only: bool,
+    persistence_acks: Arc<PersistenceAcks>,
+    store_writes: StoreWrites,
+}
+
+pub trait MessageHandlerTrait: Send + Sync {}
+
+impl MessageHandlerTrait for AiMessageHandler {}
+
+impl Global for AiMessageHandler {}
+
+/// Where an event falls in its completion stream, and in its thread.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EventPosition {
+    pub index: usize,
+    /// Where its write fell among the writes of the thread's concurrent completions.
+    pub lane_position: u64,
+}
+
+/// The `response_metadata` entry of the hashes of the schemas a request declared, by tool name.
+pub(crate) const REQUEST_TOOL_SCHEMAS: &str = "tool_schemas";
+
+/// A tool's definition as a request offered it, stored once by the hash of its content, so that
+/// the tool calls made against it can be checked and replays can offer it unchanged.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolSchema {
+    pub name: String,
+    pub description: String,
+    pub input_schema: serde_json::Value,
+}
+
+impl ToolSchema {
+    /// The hash of the definition, with object keys sorted so that the order a tool built its
+    /// schema in doesn't change it.
+    pub fn hash(&self) -> String {
+        let definition = serde_json::json!({
+            "name": self.name,
+            "description": self.description,
+            "input_schema": self.input_schema,
+        });
+        content_hash(sorted_keys(definition).to_string().as_bytes())
+    }
+
+    pub fn to_request_tool(&self) -> LanguageModelRequestTool {
+        LanguageModelRequestTool {
+            name: self.name.clone(),
+            description: self.description.clone(),
+            input_schema: self.input_schema.clone(),
+        }
+    }
+}
+
+impl From<&LanguageModelRequestTool> for ToolSchema {
+    fn from(tool: &LanguageModelRequestTool) -> Self {
+        Self {
+            name: tool.name.clone(),
+            description: tool.description.clone(),
+            input_schema: tool.input_schema.clone(),
+        }
+    }
+}
+
+fn sorted_keys(value: serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(map) => {
+            let mut entries = map.into_iter().collect::<Vec<_>>();
+            entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+            serde_json::Value::Object(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (key, sorted_keys(value)))
+                    .collect(),
+            )
+        }
+        serde_json::Value::Array(values) => {
+            serde_json::Value::Array(values.into_iter().map(sorted_keys).collect())
+        }
+        value => value,
+    }
+}
+
+/// The entry recorded on a request's messages, of the hash of each schema by its tool's name.
+pub(crate) fn schema_hashes(schemas: &[ToolSchema]) -> serde_json::Value {
+    serde_json::Value::Object(
+        schemas
+            .iter()
+            .map(|schema| (schema.name.clone(), serde_json::Value::from(schema.hash())))
+            .collect(),
+    )
+}
+
+/// The hashes a message recorded of its request's schemas, by tool name.
+fn message_schema_hashes(message: &Message) -> Option<HashMap<String, String>> {
+    let hashes = message
+        .response_metadata()
+        .get(REQUEST_TOOL_SCHEMAS)?
+        .as_object()?;
+    Some(
+        hashes
+            .iter()
+            .filter_map(|(name, hash)| Some((name.clone(), hash.as_str()?.to_string())))
+            .collect(),
+    )
+}
+
+/// The hashes of the schemas the thread's latest stored request declared, sorted by tool name.
+pub(crate) fn latest_request_schema_hashes(messages: &[StoredMessage]) -> Vec<String> {
+    let Some(hashes) = messages
+        .iter()
+        .rev()
+        .find_map(|message| message_schema_hashes(&message.message))
+    else {
+        return Vec::new();
+    };
+    let mut hashes = hashes.into_iter().collect::<Vec<_>>();
+    hashes.sort();
+    hashes.into_iter().map(|(_, hash)| hash).collect()
+}
+
+/// The hashes of every schema the thread's stored requests declared.
+pub(crate) fn recorded_schema_hashes(messages: &[StoredMessage]) -> Vec<String> {
+    let mut hashes = messages
+        .iter()
+        .filter_map(|message| message_schema_hashes(&message.message))
+        .flat_map(|hashes| hashes.into_values())
+        .collect::<Vec<_>>();
+    hashes.sort();
+    hashes.dedup();
+    hashes
+}
+
+/// A stored tool call whose input doesn't match the schema its request declared for the tool.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvalidToolCall {
+    /// The checkpoint of the first request it was sent in.
+    pub seq: i64,
+    pub tool_use_id: String,
+    pub tool_name: String,
+    pub errors: Vec<String>,
+}
+
+/// The tool calls in the thread's stored requests whose input doesn't match the schema, among
+/// `schemas` by hash, that their request declared for the tool. Each request holds the thread's
+/// history up to it, so each call is checked once, against the first request it was sent in.
+pub fn invalid_tool_calls(
+    messages: &[StoredMessage],
+    schemas: &collections::HashMap<String, ToolSchema>,
+) -> Vec<InvalidToolCall> {
+    let mut checked = HashSet::new();
+    let mut validators = HashMap::<String, Option<StructuredOutputSchema>>::new();
+    let mut invalid = Vec::new();
+    for message in messages {
+        let Some(hashes) = message_schema_hashes(&message.message) else {
+            continue;
+        };
+        for content in request_contents(message.message.content()).unwrap_or_default() {
+            let MessageContent::ToolUse(tool_use) = content else {
+                continue;
+            };
+            if !checked.insert(tool_use.id.to_string()) {
+                continue;
+            }
+            let Some((hash, schema)) = hashes
+                .get(tool_use.name.as_ref())
+                .and_then(|hash| Some((hash, schemas.get(hash)?)))
+            else {
+                continue;
+            };
+            let validator = validators
+                .entry(hash.clone())
+                .or_insert_with(|| StructuredOutputSchema::new(schema.input_schema.clone()).ok());
+            let Some(validator) = validator else {
+                continue;
+            };
+            let errors = validator.validate(&tool_use.input);
+            if !errors.is_empty() {
+                invalid.push(InvalidToolCall {
+                    seq: message.seq,
+                    tool_use_id: tool_use.id.to_string(),
+                    tool_name: tool_use.name.to_string(),
+                    errors,
+                });
+            }
+        }
+    }
+    invalid
+}
+
+/// How a tool call's input compared to the schema its request declared for the tool, checked as
+/// the call is stored.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ToolCallValidation {
+    pub thread_id: String,
+    pub checkpoint_id: String,
+    pub tool_use_id: String,
+    pub tool_name: String,
+    /// The hash of the schema the input was checked against, unless the request didn't declare
+    /// the tool.
+    pub schema_hash: Option<String>,
+    pub provider: Option<String>,
+    pub model_id: String,
+    /// Why the input doesn't match, empty when it does.
+    pub errors: Vec<String>,
+}
+
+impl ToolCallValidation {
+    /// Checks the call's input against the schema the request declared for its tool. Calls to a
+    /// tool the request didn't declare are invalid, while a schema that doesn't compile isn't
+    /// held against the calls made with it.
+    pub(crate) fn new(
+        tool_use: &LanguageModelToolUse,
+        ids: &RequestIds,
+        language_model_args: &LanguageModelArgs,
+    ) -> Self {
+        let schema = language_model_args
+            .tool_schemas
+            .iter()
+            .find(|schema| schema.name == tool_use.name.as_ref());
+        let errors = match schema {
+            Some(schema) => StructuredOutputSchema::new(schema.input_schema.clone())
+                .map(|validator| validator.validate(&tool_use.input))
+                .unwrap_or_default(),
+            None => vec![format!(
+                "the request didn't declare a tool named {:?}",
+                tool_use.name
+            )],
+        };
+        Self {
+            thread_id: ids.thread_id.clone(),
+            checkpoint_id: ids.checkpoint_id.clone(),
+            tool_use_id: tool_use.id.to_string(),
+            tool_name: tool_use.name.to_string(),
+            schema_hash: schema.map(ToolSchema::hash),
+            provider: language_model_args.provider.clone(),
+            model_id: language_model_args.model_id.0.to_string(),
+            errors,
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+/// A thread in which a provider's model made tool calls that didn't match their schemas.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvalidToolCallThread {
+    pub thread_id: String,
+    pub provider: Option<String>,
+    pub model_id: String,
+    pub invalid_calls: usize,
+    /// Every call of the model's in the thread that was checked, valid or not.
+    pub checked_calls: usize,
+    pub last_invalid_at: DateTime<Utc>,
+}
+
+#[derive(Clone)]
+pub struct LanguageModelArgs {
+    pub model_id: LanguageModelId,
+    pub temperature: Option<f32>,
+    pub intent: Option<String>,
+    pub mode: Option<String>,
+    pub prompt_id: Option<String>,
+    pub toolchain: Option<RequestToolchain>,
+    pub project: Option<String>,
+    pub prompt_template: Option<RequestPromptTemplate>,
+    /// The model's context window, to record how much of it each request uses.
+    pub max_tokens: Option<usize>,
+    pub origin: Option<RequestOrigin>,
+    pub buffer: Option<RequestBuffer>,
+    pub git_branch: Option<String>,
+    pub schedule_id: Option<String>,
+    pub fan_out_group: Option<String>,
+    /// The names of the tools the request declared schemas for, recorded on its messages.
+    pub tools: Option<Vec<String>>,
+    /// The definitions of those tools, stored by hash and recorded on its messages.
+    pub tool_schemas: Vec<ToolSchema>,
+    /// The message the request was sent for, whose writes are acknowledged.
+    pub message_id: Option<String>,
+    /// The feature the request was made from, to break down usage by feature.
+    pub source: Option<RequestSource>,
+    /// The provider the request was sent to, to compare how providers' models do.
+    pub provider: Option<String>,
+}
+
+impl LanguageModelArgs {
+    pub fn new(model_id: LanguageModelId) -> Self {
+        Self {
+            model_id,
+            temperature: None,
+            intent: None,
+            mode: None,
+            prompt_id: None,
+            toolchain: None,
+            project: None,
+            prompt_template: None,
+            max_tokens: None,
+            origin: None,
+            buffer: None,
+            git_branch: None,
+            schedule_id: None,
+            fan_out_group: None,
+            tools: None,
+            tool_schemas: Vec::new(),
+            message_id: None,
+            source: None,
+            provider: None,
+        }
+    }
+
+    pub fn from_request(model_id: LanguageModelId, request: &LanguageModelRequest) -> Self {
+        Self {
+            model_id,
+            temperature: request.temperature,
+            intent: request.intent.as_ref().map(|i| format!("{:?}", i)),
+            mode: request.mode.as_ref().map(|m| format!("{:?}", m)),
+            prompt_id: request.prompt_id.clone(),
+            toolchain: request.toolchain.clone(),
+            project: request.project.clone(),
+            prompt_template: request.prompt_template.clone(),
+            max_tokens: None,
+            origin: request.origin.clone(),
+            buffer: request.buffer.clone(),
+            git_branch: request.git_branch.clone(),
+            schedule_id: request.schedule_id.clone(),
+            fan_out_group: request.fan_out_group.clone(),
+            tools: Some(request.tools.iter().map(|tool| tool.name.clone()).collect()),
+            tool_schemas: request.tools.iter().map(ToolSchema::from).collect(),
+            message_id: request.message_id.clone(),
+            source: request.source,
+            provider: None,
+        }
+    }
+
+    pub fn with_max_tokens(mut self, max_tokens: usize) -> Self {
+        self.max_tokens = Some(max_tokens);
+        self
+    }
+
+    pub fn with_provider(mut self, provider: impl Into<String>) -> Self {
+        self.provider = Some(provider.into());
+        self
+    }
+}
+
+/// The client to request the checkpoint's completion with, which captures the raw exchanges
+/// while the handler does.
+pub fn raw_exchange_client(
+    message_handler: Option<&Arc<AiMessageHandler>>,
+    http_client: Arc<dyn HttpClient>,
+    ids: &RequestIds,
+) -> Arc<dyn HttpClient> {
+    match message_handler {
+        Some(handler) => handler.raw_exchange_client(http_client, ids),
+        None => http_client,
+    }
+}
+
+pub fn peek_db<T>(
+    stream: T,
+    message_handler: Option<Arc<AiMessageHandler>>,
+    ids: RequestIds,
+    language_model_args: LanguageModelArgs,
+) -> BoxStream<'static, Result<LanguageModelCompletionEvent, LanguageModelCompletionError>>
+where
+    T: Stream<Item = Result<LanguageModelCompletionEvent, LanguageModelCompletionError>>
+        + Send
+        + 'static,
+{
+    if let Some(handler) = message_handler {
+        // Faults are injected before the stream is observed, so that they're stored like the
+        // provider's own.
+        let stream = match &handler.provider_chaos {
+            Some(chaos) => chaos.disrupt_stream(stream),
+            None => stream.boxed(),
+        };
+        AiMessageHandler::inspect_stream(stream, handler.clone(), ids, language_model_args).boxed()
+    } else {
+        stream.boxed()
+    }
+}
+
+/// Saves each event of a completion stream as it arrives, and how many tokens the completion
+/// used once it ends.
+struct CompletionPersister {
+    handler: Arc<AiMessageHandler>,
+    ids: RequestIds,
+    language_model_args: LanguageModelArgs,
+    /// The text the model has produced, to count its tokens when the provider doesn't.
+    response_text: Mutex<String>,
+    provider_usage: Mutex<Option<TokenUsage>>,
+    /// How the completion ended, for the notifier's rules.
+    outcome: Mutex<Option<CompletionOutcome>>,
+    /// The error the stream failed with, to close its trace run with.
+    error: Mutex<Option<String>>,
+    /// How many events the stream has produced, to number them for their idempotency keys.
+    events: AtomicUsize,
+    /// When the stream started, to note how long it ran if it's cancelled.
+    started_at: Instant,
+}
+
+impl CompletionPersister {
+    /// Adds the completion's tokens to its thread's, and checks the thread against the
+    /// notifier's rules.
+    fn notify(&self, tokens: i64) {
+        self.handler.notify_completion(self.thread_facts(), tokens);
+    }
+
+    /// What the notifier's rules check the completion's thread against.
+    fn thread_facts(&self) -> ThreadFacts {
+        ThreadFacts {
+            thread_id: self.ids.thread_id.clone(),
+            model_id: Some(self.language_model_args.model_id.0.to_string()),
+            project: self.language_model_args.project.clone(),
+            outcome: self.outcome.lock().take(),
+            tags: Vec::new(),
+        }
+    }
+
+    /// Closes the completion's trace run if the model didn't stop it, e.g. because the stream
+    /// failed or was dropped. It is closed on the thread's write lane, after the events queued
+    /// before it have been traced.
+    fn close_trace(&self) {
+        let Some(exporter) = self.handler.trace_exporter.clone() else {
+            return;
+        };
+        let error = match (self.error.lock().take(), *self.outcome.lock()) {
+            (Some(error), _) => Some(error),
+            (None, Some(CompletionOutcome::Cancelled)) => Some(CANCELLED_BY_USER.to_string()),
+            (None, _) => None,
+        };
+        let ids = self.ids.clone();
+        self.handler.write_lanes.enqueue(&self.ids.thread_id, |_| {
+            async move {
+                if let Some(request) = exporter.end_run(&ids, error.as_deref()) {
+                    exporter.export(&ids, request);
+                }
+            }
+            .boxed()
+        });
+    }
+
+    /// Saves a note about how the completion went, after the events it has produced.
+    fn save_note(
+        &self,
+        content: String,
+        kwargs: HashMap<String, serde_json::Value>,
+        event: CheckpointEvent,
+    ) {
+        let mut message = Message::System {
+            content: ContentValue::new(content),
+            id: self.ids.thread_id.clone(),
+            name: Some(self.handler.agent_name(&self.language_model_args)),
+            example: false,
+            additional_kwargs: kwargs,
+            response_metadata: AiMessageHandler::build_response_metadata(&self.language_model_args),
+        };
+        stamp_idempotency_key(std::slice::from_mut(&mut message), &self.ids, event);
+        let write = self.handler.pending_writes.begin(&self.ids.thread_id);
+        let handler = self.handler.clone();
+        let ids = self.ids.clone();
+        let message_id = self.language_model_args.message_id.clone();
+        self.handler
+            .write_lanes
+            .enqueue(&self.ids.thread_id, |position| {
+                stamp_write_lane(
+                    std::slice::from_mut(&mut message),
+                    &ids.checkpoint_id,
+                    position,
+                );
+                async move {
+                    let _ = handler
+                        .save_acknowledged(
+                            handler.filter_messages(vec![message]),
+                            &ids,
+                            message_id.as_deref(),
+                            PersistedPart::Completion,
+                        )
+                        .await;
+                    drop(write);
+                }
+                .boxed()
+            });
+    }
+}
+
+impl CompletionStreamObserver for CompletionPersister {
+    fn on_event(&self, event: &LanguageModelCompletionEvent) {
+        match event {
+            LanguageModelCompletionEvent::Text(text)
+            | LanguageModelCompletionEvent::Thinking { text, .. } => {
+                self.response_text.lock().push_str(text)
+            }
+            LanguageModelCompletionEvent::ToolUse(tool_use) if tool_use.is_input_complete => {
+                self.response_text.lock().push_str(&tool_use.raw_input)
+            }
+            LanguageModelCompletionEvent::UsageUpdate(usage) => {
+                *self.provider_usage.lock() = Some(*usage)
+            }
+            LanguageModelCompletionEvent::Stop(reason) => {
+                *self.outcome.lock() = Some((*reason).into())
+            }
+            _ => {}
+        }
+        self.handler.traffic.publish(|| TrafficEvent {
+            ids: self.ids.clone(),
+            model_id: self.language_model_args.model_id.clone(),
+            kind: TrafficKind::Event(event.clone()),
+        });
+        self.handler.queue_completion_event(
+            event,
+            self.events.fetch_add(1, Ordering::SeqCst),
+            &self.ids,
+            &self.language_model_args,
+        );
+    }
+
+    fn on_error(&self, error: &LanguageModelCompletionError) {
+        self.handler.traffic.publish(|| TrafficEvent {
+            ids: self.ids.clone(),
+            model_id: self.language_model_args.model_id.clone(),
+            kind: TrafficKind::Error(error.to_string()),
+        });
+        *self.outcome.lock() = Some(CompletionOutcome::Error);
+        *self.error.lock() = Some(error.to_string());
+        if !self.handler.persists(&self.language_model_args) {
+            return;
+        }
+        self.save_note(
+            error.to_string(),
+            HashMap::from_iter([(
+                "event".to_string(),
+                serde_json::Value::from("completion_error"),
+            )]),
+            CheckpointEvent::CompletionError,
+        );
+    }
+
+    /// Marks the events saved so far as a response the user cut short, so that the stored turn
+    /// reads as truncated rather than as one the model ended.
+    fn on_cancel(&self) {
+        *self.outcome.lock() = Some(CompletionOutcome::Cancelled);
+        if !self.handler.persists(&self.language_model_args) {
+            return;
+        }
+        let elapsed = self.started_at.elapsed();
+        self.save_note(
+            CANCELLED_BY_USER.to_string(),
+            HashMap::from_iter([
+                (
+                    "event".to_string(),
+                    serde_json::Value::from(CANCELLED_BY_USER),
+                ),
+                (
+                    "elapsed_ms".to_string(),
+                    serde_json::Value::from(elapsed.as_millis() as u64),
+                ),
+                (
+                    "streamed_events".to_string(),
+                    serde_json::Value::from(self.events.load(Ordering::SeqCst)),
+                ),
+            ]),
+            CheckpointEvent::Cancelled,
+        );
+    }
+
+    fn on_end(&self) {
+        let response_text = std::mem::take(&mut *self.response_text.lock());
+        let provider_usage = self.provider_usage.lock().take();
+        self.handler.traffic.publish(|| TrafficEvent {
+            ids: self.ids.clone(),
+            model_id: self.language_model_args.model_id.clone(),
+            kind: TrafficKind::End {
+                usage: provider_usage,
+            },
+        });
+        let prompt_tokens = self.handler.prompt_tokens.remove(&self.ids.checkpoint_id);
+        self.close_trace();
+        if !self.handler.persists(&self.language_model_args) {
+            return;
+        }
+        let persists_usage = self
+            .handler
+            .persistence_features
+            .contains(PersistenceFeatures::PERSIST_USAGE);
+        if response_text.is_empty() && provider_usage.is_none() {
+            self.notify(0);
+            return;
+        }
+        let mut message = Message::System {
+            content: ContentValue::new("token_usage".to_string()),
+            id: self.ids.thread_id.clone(),
+            name: Some(self.handler.agent_name(&self.language_model_args)),
+            example: false,
+            additional_kwargs: HashMap::default(),
+            response_metadata: AiMessageHandler::build_response_metadata(&self.language_model_args),
+        };
+        stamp_idempotency_key(
+            std::slice::from_mut(&mut message),
+            &self.ids,
+            CheckpointEvent::TokenUsage,
+        );
+        if let Some(template) = self
+            .handler
+            .registered_prompt_template(&self.language_model_args)
+        {
+            prompt_templates::stamp_prompt_template(std::slice::from_mut(&mut message), &template);
+        }
+        let handler = self.handler.clone();
+        let ids = self.ids.clone();
+        let language_model_args = self.language_model_args.clone();
+        // The completion is counted off the main thread, since counting a long one takes a while.
+        let counted = handler
+            .tokenizers
+            .count_tokens_in_background(&language_model_args.model_id, vec![response_text]);
+        let usage = {
+            let handler = handler.clone();
+            let language_model_args = language_model_args.clone();
+            let facts = self.thread_facts();
+            async move {
+                let completion = counted.await[0];
+                let usage = RequestUsageRecord::new(
+                    &language_model_args,
+                    provider_usage.as_ref(),
+                    prompt_tokens,
+                    completion.tokens,
+                );
+                handler.notify_completion(facts, usage.input_tokens + usage.output_tokens);
+                let kwargs = token_counts::token_usage_kwargs(
+                    provider_usage.as_ref(),
+                    prompt_tokens,
+                    completion,
+                );
+                (usage, kwargs)
+            }
+        };
+        let saved = if persists_usage {
+            let (saved_tx, saved_rx) = oneshot::channel();
+            let write = handler.pending_writes.begin(&ids.thread_id);
+            let handler = handler.clone();
+            let ids = ids.clone();
+            let message_id = language_model_args.message_id.clone();
+            self.handler
+                .write_lanes
+                .enqueue(&self.ids.thread_id, |position| {
+                    stamp_write_lane(
+                        std::slice::from_mut(&mut message),
+                        &ids.checkpoint_id,
+                        position,
+                    );
+                    async move {
+                        let (usage, kwargs) = usage.await;
+                        if let Message::System {
+                            additional_kwargs, ..
+                        } = &mut message
+                        {
+                            *additional_kwargs = kwargs;
+                        }
+                        let _ = handler
+                            .save_acknowledged(
+                                handler.filter_messages(vec![message]),
+                                &ids,
+                                message_id.as_deref(),
+                                PersistedPart::Completion,
+                            )
+                            .await;
+                        drop(write);
+                        saved_tx.send(usage).ok();
+                    }
+                    .boxed()
+                });
+            Some(saved_rx)
+        } else {
+            smol::spawn(usage).detach();
+            None
+        };
+        smol::spawn(async move {
+            if let Some(saved) = saved {
+                if let Ok(usage) = saved.await {
+                    if let Some(provider_usage) = &provider_usage {
+                        if let Err(error) = handler
+                            .record_prompt_cache_usage(&ids, &language_model_args, provider_usage)
+                            .await
+                        {
+                            log::error!("Failed to record prompt cache usage: {error:#}");
+                        }
+                    }
+                    if let Err(error) = handler.record_request_usage(&ids, &usage).await {
+                        log::error!("Failed to record request usage: {error:#}");
+                    }
+                }
+            }
+            if let Err(error) = handler.compact_thread_if_needed(&ids).await {
+                log::error!("Failed to compact thread {}: {error:#}", ids.thread_id);
+            }
+        })
+        .detach();
+    }
+}
+
+impl AiMessageHandler {
+    pub fn new(database_client: Option<Arc<StoreClient>>) -> Self {
+        Self {
+            database_client,
+            trace_exporter: None,
+            issue_commenter: None,
+            notifier: None,
+            maintenance_policy: MaintenancePolicy::default(),
+            lint_policy: LintPolicy::default(),
+            agent_identity: AgentIdentity::default(),
+            author: None,
+            authorizer: Arc::new(AllowAll),
+            last_models: Arc::new(BoundedMap::new(LAST_MODELS_CAPACITY)),
+            prompt_templates: Mutex::default(),
+            thread_cache: Mutex::new(ThreadCache::new(
+                THREAD_CACHE_CAPACITY,
+                THREAD_CACHE_MAX_MESSAGES,
+                THREAD_CACHE_TTL,
+            )),
+            local_cache: None,
+            replicating: Mutex::default(),
+            replication: smol::lock::Mutex::new(()),
+            tokenizers: Tokenizers::default(),
+            prompt_tokens: Arc::new(BoundedMap::new(PROMPT_TOKENS_CAPACITY)),
+            compaction_policy: None,
+            context_summarizer: None,
+            compacting: Mutex::default(),
+            saved_tool_schemas: Mutex::default(),
+            collaboration_persistence: CollaborationPersistence::default(),
+            remote_persistence: RemotePersistenceRoutes::default(),
+            shadow: None,
+            sampler: None,
+            message_filter: None,
+            thread_locks: ThreadLocks::default(),
+            traffic: Arc::default(),
+            capture_raw_exchanges: false,
+            response_cache: None,
+            fixtures: None,
+            store_chaos: None,
+            provider_chaos: None,
+            pending_writes: Arc::default(),
+            write_lanes: Arc::default(),
+            secret_scanner: None,
+            persistence_paused: false,
+            persistence_features: PersistenceFeatures::default(),
+            read_only: false,
+            persistence_acks: Arc::default(),
+            store_writes: StoreWrites::default(),
+        }
+    }
+
+    /// Also stores the raw HTTP exchanges of the completions requested through
+    /// [`Self::raw_exchange_client`].
+    pub fn with_raw_exchange_capture(mut self, capture_raw_exchanges: bool) -> Self {
+        self.capture_raw_exchanges = capture_raw_exchanges;
+        self
+    }
+
+    /// The client to request the checkpoint's completion with: one that stores each exchange
+    /// under the checkpoint while raw exchanges are captured, or `http_client` itself.
+    pub fn raw_exchange_client(
+        self: &Arc<Self>,
+        http_client: Arc<dyn HttpClient>,
+        ids: &RequestIds,
+    ) -> Arc<dyn HttpClient> {
+        if !self.capture_raw_exchanges || self.database_client.is_none() || self.discards_writes() {
+            return http_client;
+        }
+        Arc::new(RawExchangeCapture {
+            inner: http_client,
+            handler: self.clone(),
+            ids: ids.clone(),
+        })
+    }
+
+    /// Notes in the request's thread that an interceptor blocked it.
+    pub fn record_request_blocked(
+        self: &Arc<Self>,
+        request: &LanguageModelRequest,
+        model_id: LanguageModelId,
+        blocked: &RequestBlocked,
+    ) {
+        let language_model_args = LanguageModelArgs::from_request(model_id, request);
+        if !self.persists(&language_model_args) {
+            return;
+        }
+        let ids = crate::_retrieve_ids(request);
+        let mut message = Message::System {
+            content: ContentValue::new(blocked.to_string()),
+            id: ids.thread_id.clone(),
+            name: Some(self.agent_name(&language_model_args)),
+            example: false,
+            additional_kwargs: HashMap::from_iter([
+                ("event".to_string(), "request_blocked".into()),
+                (
+                    "interceptor".to_string(),
+                    blocked.interceptor.clone().into(),
+                ),
+            ]),
+            response_metadata: Self::build_response_metadata(&language_model_args),
+        };
+        stamp_idempotency_key(
+            std::slice::from_mut(&mut message),
+            &ids,
+            CheckpointEvent::RequestBlocked,
+        );
+        let handler = self.clone();
+        smol::spawn(async move {
+            let _ = handler.save_append_messages(vec![message], &ids).await;
+        })
+        .detach();
+    }
+
+    /// Runs the secret scanner over the request's worktree context, redacting what it finds when
+    /// its policy says to, and notes in the request's thread what was found, without the secrets,
+    /// and what was done about it. Returns an error if the request is to be blocked.
+    pub fn scan_secrets(
+        self: &Arc<Self>,
+        request: &mut LanguageModelRequest,
+        model_id: LanguageModelId,
+    ) -> Result<(), RequestBlocked> {
+        let Some(secret_scanner) = &self.secret_scanner else {
+            return Ok(());
+        };
+        let findings = secret_scanner.scan_request(request);
+        if findings.is_empty() {
+            return Ok(());
+        }
+        let action = secret_scanner.action();
+        let kinds = findings
+            .iter()
+            .map(|finding| finding.kind.as_str())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>()
+            .join(", ");
+        log::warn!(
+            "Found {} secrets ({kinds}) in the context of a request to {}: {action:?}",
+            findings.len(),
+            model_id.0
+        );
+
+        let language_model_args = LanguageModelArgs::from_request(model_id, request);
+        if self.persists(&language_model_args) {
+            let ids = crate::_retrieve_ids(request);
+            let mut message = Message::System {
+                content: ContentValue::new(format!(
+                    "Found {} secrets in the request's context: {kinds}",
+                    findings.len()
+                )),
+                id: ids.thread_id.clone(),
+                name: Some(self.agent_name(&language_model_args)),
+                example: false,
+                additional_kwargs: HashMap::from_iter([
+                    ("event".to_string(), "secret_scan".into()),
+                    (
+                        "action".to_string(),
+                        serde_json::to_value(action).unwrap_or_default(),
+                    ),
+                    (
+                        "findings".to_string(),
+                        serde_json::to_value(&findings).unwrap_or_default(),
+                    ),
+                ]),
+                response_metadata: Self::build_response_metadata(&language_model_args),
+            };
+            stamp_idempotency_key(
+                std::slice::from_mut(&mut message),
+                &ids,
+                CheckpointEvent::SecretScan,
+            );
+            let handler = self.clone();
+            smol::spawn(async move {
+                let _ = handler.save_append_messages(vec![message], &ids).await;
+            })
+            .detach();
+        }
+
+        match action {
+            SecretAction::Block => Err(RequestBlocked {
+                interceptor: "secret_scanner".to_string(),
+                reason: format!("its context holds secrets ({kinds})"),
+            }),
+            SecretAction::Redact | SecretAction::Warn => Ok(()),
+        }
+    }
+
+    /// Notes, in the request's thread, what the completion's text was parsed into, with the text
+    /// as sent and what it was repaired to, or why it couldn't be parsed.
+    pub fn record_structured_output(
+        self: &Arc<Self>,
+        request: &LanguageModelRequest,
+        model_id: LanguageModelId,
+        output: &Result<StructuredOutput, StructuredOutputError>,
+    ) {
+        let language_model_args = LanguageModelArgs::from_request(model_id, request);
+        if !self.persists(&language_model_args) {
+            return;
+        }
+        let ids = crate::_retrieve_ids(request);
+        let (raw, repaired, errors) = match output {
+            Ok(output) => (Some(&output.raw), output.repaired.as_ref(), Vec::new()),
+            Err(StructuredOutputError::Invalid {
+                raw,
+                repaired,
+                errors,
+                ..
+            }) => (Some(raw), repaired.as_ref(), errors.clone()),
+            Err(StructuredOutputError::Unparseable { raw, .. }) => (Some(raw), None, Vec::new()),
+            Err(StructuredOutputError::Completion(_)) => (None, None, Vec::new()),
+        };
+        let content = match output {
+            Ok(output) => output.value.to_string(),
+            Err(error) => error.to_string(),
+        };
+        let mut message = Message::System {
+            content: ContentValue::new(content),
+            id: ids.thread_id.clone(),
+            name: Some(self.agent_name(&language_model_args)),
+            example: false,
+            additional_kwargs: HashMap::from_iter([
+                ("event".to_string(), "structured_output".into()),
+                ("raw".to_string(), raw.cloned().into()),
+                ("repaired".to_string(), repaired.cloned().into()),
+                ("errors".to_string(), errors.into()),
+            ]),
+            response_metadata: Self::build_response_metadata(&language_model_args),
+        };
+        stamp_idempotency_key(
+            std::slice::from_mut(&mut message),
+            &ids,
+            CheckpointEvent::StructuredOutput,
+        );
+        let handler = self.clone();
+        smol::spawn(async move {
+            let _ = handler.save_append_messages(vec![message], &ids).await;
+        })
+        .detach();
+    }
+
+    /// Notes, in the request's thread, where a response interrupted partway through was spliced
+    /// with the rest of it, between the two, so that the thread reads as what the user saw.
+    pub fn record_stream_splice(
+        self: &Arc<Self>,
+        request: &LanguageModelRequest,
+        model_id: LanguageModelId,
+        splice: &StreamSplice,
+    ) {
+        let language_model_args = LanguageModelArgs::from_request(model_id, request);
+        if !self.persists(&language_model_args) {
+            return;
+        }
+        let ids = crate::_retrieve_ids(request);
+        let mut message = Message::System {
+            content: ContentValue::new(splice.error.clone()),
+            id: ids.thread_id.clone(),
+            name: Some(self.agent_name(&language_model_args)),
+            example: false,
+            additional_kwargs: HashMap::from_iter([
+                ("event".to_string(), "stream_splice".into()),
+                (
+                    "splice".to_string(),
+                    serde_json::to_value(splice).unwrap_or_default(),
+                ),
+            ]),
+            response_metadata: Self::build_response_metadata(&language_model_args),
+        };
+        stamp_idempotency_key(
+            std::slice::from_mut(&mut message),
+            &ids,
+            CheckpointEvent::StreamSplice,
+        );
+        let handler = self.clone();
+        smol::spawn(async move {
+            let _ = handler.save_append_messages(vec![message], &ids).await;
+        })
+        .detach();
+    }
+
+    fn save_raw_exchange(&self, ids: RequestIds, exchange: RawExchange) {
+        let Some(db_client) = self.database_client.clone() else {
+            return;
+        };
+        smol::spawn(async move {
+            if let Err(error) = db_client.save_raw_exchange(&ids, &exchange).await {
+                log::error!(
+                    "Failed to save the raw exchange with {}: {error:#}",
+                    exchange.url
+                );
+            }
+        })
+        .detach();
+    }
+
+    /// Publishes the requests and completion events this handler sees to the traffic's
+    /// subscribers.
+    pub fn with_traffic(mut self, traffic: Arc<LlmTraffic>) -> Self {
+        self.traffic = traffic;
+        self
+    }
+
+    /// Acknowledges the writes of the messages requests were sent for to the acks' subscribers.
+    pub fn with_persistence_acks(mut self, persistence_acks: Arc<PersistenceAcks>) -> Self {
+        self.persistence_acks = persistence_acks;
+        self
+    }
+
+    /// Reports whether its writes reach the store, to keep the store's health up to date.
+    pub(crate) fn with_store_writes(mut self, store_writes: StoreWrites) -> Self {
+        self.store_writes = store_writes;
+        self
+    }
+
+    /// Refuses writes to threads locked by another session's agent run.
+    pub fn with_thread_locks(mut self, thread_locks: ThreadLocks) -> Self {
+        self.thread_locks = thread_locks;
+        self
+    }
+
+    /// Drops, redacts or routes the messages this handler saves as the filter's rules say.
+    pub fn with_message_filter(mut self, message_filter: Option<Arc<MessageFilter>>) -> Self {
+        self.message_filter = message_filter;
+        self
+    }
+
+    /// Caches the responses to requests whose intents the cache's policy names, and answers
+    /// identical requests with them through [`cached_completion`].
+    pub fn with_response_cache(mut self, response_cache: Option<Arc<ResponseCache>>) -> Self {
+        self.response_cache = response_cache;
+        self
+    }
+
+    /// Records completions as fixtures, or answers requests with them through
+    /// [`cached_completion`] instead of sending them, as the fixtures' mode says.
+    pub fn with_fixtures(mut self, fixtures: Option<Arc<CompletionFixtures>>) -> Self {
+        self.fixtures = fixtures;
+        self
+    }
+
+    /// Delays and fails the store's operations, and the providers' completions, as the chaos
+    /// policies say, to exercise the paths taken when they fail without a real outage.
+    pub fn with_chaos(
+        mut self,
+        store_chaos: Option<Arc<Chaos>>,
+        provider_chaos: Option<Arc<Chaos>>,
+    ) -> Self {
+        self.store_chaos = store_chaos;
+        self.provider_chaos = provider_chaos;
+        self
+    }
+
+    /// Scans the worktree context of requests for secrets before they are sent, as the scanner's
+    /// policy says.
+    pub fn with_secret_scanner(mut self, secret_scanner: Option<Arc<SecretScanner>>) -> Self {
+        self.secret_scanner = secret_scanner;
+        self
+    }
+
+    /// Stores no requests or completions while paused.
+    pub fn with_persistence_paused(mut self, persistence_paused: bool) -> Self {
+        self.persistence_paused = persistence_paused;
+        self
+    }
+
+    /// Stores only the given parts of completions.
+    pub fn with_persistence_features(mut self, persistence_features: PersistenceFeatures) -> Self {
+        self.persistence_features = persistence_features;
+        self
+    }
+
+    /// Writes nothing to the store, so that it can be browsed, searched and exported safely.
+    pub fn with_read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    pub fn read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Whether what would be written to the store is dropped, in shadow or read-only mode.
+    fn discards_writes(&self) -> bool {
+        self.shadow.is_some() || self.read_only
+    }
+
+    /// Fails the user's edits of a read-only store, which would otherwise silently do nothing.
+    fn ensure_writable(&self) -> anyhow::Result<()> {
+        if self.read_only {
+            anyhow::bail!("the message store is read-only");
+        }
+        Ok(())
+    }
+
+    /// Delays or fails the store operation, if the store's chaos policy says so.
+    async fn disrupt_store(&self, operation: &str) -> anyhow::Result<()> {
+        match &self.store_chaos {
+            Some(chaos) => chaos.disrupt(operation).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Stores only the threads the sampler picks, or has to store after all.
+    pub fn with_sampler(mut self, sampler: Option<Arc<ThreadSampler>>) -> Self {
+        self.sampler = sampler;
+        self
+    }
+
+    /// Encodes and counts the messages this handler saves, but discards them instead of writing
+    /// them anywhere.
+    pub fn with_shadow_persistence(mut self, shadow: Option<Arc<ShadowPersistence>>) -> Self {
+        self.shadow = shadow;
+        self
+    }
+
+    /// Stores the messages of the routed projects through their remote persistence instead.
+    pub fn with_remote_persistence(mut self, remote_persistence: RemotePersistenceRoutes) -> Self {
+        self.remote_persistence = remote_persistence;
+        self
+    }
+
+    /// Sets which participants of shared projects store their completions.
+    pub fn with_collaboration_persistence(
+        mut self,
+        collaboration_persistence: CollaborationPersistence,
+    ) -> Self {
+        self.collaboration_persistence = collaboration_persistence;
+        self
+    }
+
+    fn persists(&self, language_model_args: &LanguageModelArgs) -> bool {
+        // Compaction stores the summaries it requests in place of what they summarize.
+        language_model_args.intent.as_deref() != Some(compaction::CONTEXT_SUMMARY_INTENT)
+            && !self.persistence_paused
+            && !self.read_only
+            && self
+                .collaboration_persistence
+                .persists(language_model_args.origin.as_ref())
+    }
+
+    /// Summarizes threads whose stored history grows past the policy's limit with the summarizer.
+    pub fn with_compaction(
+        mut self,
+        policy: Option<CompactionPolicy>,
+        summarizer: Option<Arc<dyn ContextSummarizer>>,
+    ) -> Self {
+        self.compaction_policy = policy;
+        self.context_summarizer = summarizer;
+        self
+    }
+
+    /// Counts the tokens of the messages this handler saves with the first of these that knows
+    /// the model, estimating otherwise.
+    pub fn with_tokenizers(mut self, tokenizers: Tokenizers) -> Self {
+        self.tokenizers = tokenizers;
+        self
+    }
+
+    /// Where the prompts counted for requests are kept until their completions end.
+    pub(crate) fn with_prompt_tokens(mut self, prompt_tokens: Arc<BoundedMap<usize>>) -> Self {
+        self.prompt_tokens = prompt_tokens;
+        self
+    }
+
+    pub(crate) fn with_last_models(
+        mut self,
+        last_models: Arc<BoundedMap<(LanguageModelId, String)>>,
+    ) -> Self {
+        self.last_models = last_models;
+        self
+    }
+
+    /// Writes messages to the local cache before the remote store, and reads them back from it.
+    pub fn with_local_cache(mut self, local_cache: Option<Arc<LocalMessageCache>>) -> Self {
+        self.local_cache = local_cache;
+        self
+    }
+
+    /// Restricts which stored threads this handler reads back.
+    pub fn with_authorizer(mut self, authorizer: Arc<dyn StoreAuthorizer>) -> Self {
+        self.authorizer = authorizer;
+        self
+    }
+
+    /// Attributes the messages this handler saves to the author.
+    pub fn with_author(mut self, author: Option<MessageAuthor>) -> Self {
+        self.author = author;
+        self
+    }
+
+    pub fn author(&self) -> Option<&MessageAuthor> {
+        self.author.as_ref()
+    }
+
+    /// Also mirrors the completions this handler saves to LangSmith.
+    pub fn with_trace_exporter(mut self, trace_exporter: Option<Arc<LangSmithExporter>>) -> Self {
+        self.trace_exporter = trace_exporter;
+        self
+    }
+
+    /// Posts the summaries of threads marked resolved to their issues, if it is set to.
+    pub fn with_issue_commenter(mut self, issue_commenter: Option<Arc<IssueCommenter>>) -> Self {
+        self.issue_commenter = issue_commenter;
+        self
+    }
+
+    /// Also posts notifications about the stored threads that match the notifier's rules.
+    pub fn with_notifier(mut self, notifier: Option<Arc<ThreadNotifier>>) -> Self {
+        self.notifier = notifier;
+        self
+    }
+
+    /// Sets what the store's maintenance advice is based on, and what is run before it.
+    pub fn with_maintenance_policy(mut self, maintenance_policy: MaintenancePolicy) -> Self {
+        self.maintenance_policy = maintenance_policy;
+        self
+    }
+
+    /// Sets how large a request may grow before its unsummarized thread is flagged, and how many
+    /// threads are linted.
+    pub fn with_lint_policy(mut self, lint_policy: LintPolicy) -> Self {
+        self.lint_policy = lint_policy;
+        self
+    }
+
+    /// Sets the names messages are stored under.
+    pub fn with_agent_identity(mut self, agent_identity: AgentIdentity) -> Self {
+        self.agent_identity = agent_identity;
+        self
+    }
+
+    /// The name messages saved for the request are stored under.
+    pub fn agent_name(&self, language_model_args: &LanguageModelArgs) -> String {
+        self.agent_identity
+            .name_for(
+                language_model_args.intent.as_deref(),
+                language_model_args.project.as_deref(),
+            )
+            .to_string()
+    }
+
+    /// Adds a completion's tokens to its thread's, and checks the thread against the notifier's
+    /// rules.
+    fn notify_completion(self: &Arc<Self>, facts: ThreadFacts, tokens: i64) {
+        let Some(notifier) = &self.notifier else {
+            return;
+        };
+        notifier.record_tokens(&facts.thread_id, tokens);
+        let handler = self.clone();
+        smol::spawn(async move { handler.notify_thread(facts).await }).detach();
+    }
+
+    async fn notify_thread(&self, mut facts: ThreadFacts) {
+        let Some(notifier) = &self.notifier else {
+            return;
+        };
+        if notifier.needs_tags() {
+            match self.thread_tags(&facts.thread_id).await {
+                Ok(tags) => facts.tags = tags,
+                Err(error) => {
+                    log::error!("Failed to read the tags of {}: {error:#}", facts.thread_id)
+                }
+            }
+        }
+        for notification in notifier.notifications(&facts) {
+            if let Err(error) = notifier.send(notification).await {
+                log::error!("Failed to notify about {}: {error:#}", facts.thread_id);
+            }
+        }
+    }
+
+    fn export_trace(&self, ids: &RequestIds, request: Option<LangSmithRequest>) {
+        if let (Some(exporter), Some(request)) = (&self.trace_exporter, request) {
+            exporter.export(ids, request);
+        }
+    }
+
+    pub async fn save_completion_req(
+        self: &Arc<Self>,
+        request_message: &LanguageModelRequest,
+        ids: &RequestIds,
+        language_model_args: LanguageModelArgs,
+    ) {
+        let agent_name = self.agent_name(&language_model_args);
+        let counts = self
+            .tokenizers
+            .count_tokens_in_background(
+                &language_model_args.model_id,
+                request_message
+                    .messages
+                    .iter()
+                    .map(|message| message.string_contents())
+                    .collect(),
+            )
+            .await;
+        let prompt_tokens = counts.iter().map(|count| count.tokens).sum();
+        let mut collected = request_message
+            .messages
+            .iter()
+            .zip(counts)
+            .flat_map(|(r, count)| {
+                let r = self.persistence_features.request_message(r)?;
+                let mut message =
+                    Self::map_from_completion_request(&r, ids, &language_model_args, &agent_name)?;
+                if let Some(failure) = message.additional_kwargs().get(PERSIST_FAILED) {
+                    let error = failure["error"].as_str().unwrap_or_default().to_string();
+                    self.traffic.publish(|| TrafficEvent {
+                        ids: ids.clone(),
+                        model_id: language_model_args.model_id.clone(),
+                        kind: TrafficKind::PersistFailed { error },
+                    });
+                }
+                token_counts::stamp_token_count(&mut message, count);
+                Some(message)
+            })
+            .collect::<Vec<Message>>();
+        self.prompt_tokens
+            .insert(ids.checkpoint_id.clone(), prompt_tokens);
+        if let Some(response_cache) = &self.response_cache {
+            response_cache.expect_response(
+                request_message,
+                &language_model_args.model_id,
+                &ids.checkpoint_id,
+            );
+        }
+        if let Some(fixtures) = &self.fixtures {
+            fixtures.expect_response(
+                request_message,
+                &language_model_args.model_id,
+                &ids.checkpoint_id,
+            );
+        }
+        self.traffic.publish(|| TrafficEvent {
+            ids: ids.clone(),
+            model_id: language_model_args.model_id.clone(),
+            kind: TrafficKind::Request {
+                messages: request_message
+                    .messages
+                    .iter()
+                    .map(|message| (message.role, message.string_contents()))
+                    .collect(),
+                prompt_tokens,
+            },
+        });
+        if let Some(max_tokens) = language_model_args.max_tokens {
+            let budget = ContextBudget {
+                used_tokens: prompt_tokens,
+                max_tokens,
+            };
+            context_budget::stamp_context_budget(&mut collected, budget);
+        }
+        if !self.persists(&language_model_args) || collected.is_empty() {
+            return;
+        }
+        self.stamp_prompt_template(&mut collected, &language_model_args)
+            .await;
+        self.save_tool_schemas(&language_model_args).await;
+        stamp_idempotency_key(&mut collected, ids, CheckpointEvent::Request);
+        let transition = self
+            .model_transition(ids, &language_model_args)
+            .map(|mut transition| {
+                stamp_idempotency_key(
+                    std::slice::from_mut(&mut transition),
+                    ids,
+                    CheckpointEvent::ModelTransition,
+                );
+                transition
+            });
+        // Written on the thread's lane, so that the request is stored, and its trace run started,
+        // before the events of its completion and after those of the completions before it.
+        let (saved_tx, saved_rx) = oneshot::channel();
+        let write = self.pending_writes.begin(&ids.thread_id);
+        let handler = self.clone();
+        self.write_lanes.enqueue(&ids.thread_id, |position| {
+            stamp_write_lane(&mut collected, &ids.checkpoint_id, position);
+            let ids = ids.clone();
+            async move {
+                if let Some(transition) = transition {
+                    let _ = handler.save_append_messages(vec![transition], &ids).await;
+                }
+                let collected = handler.filter_messages(collected);
+                if let Some(exporter) = &handler.trace_exporter {
+                    let exported = collected.exported().cloned().collect::<Vec<_>>();
+                    handler.export_trace(
+                        &ids,
+                        Some(exporter.start_run(&exported, &ids, &language_model_args)),
+                    );
+                }
+                let _ = handler
+                    .save_acknowledged(
+                        collected,
+                        &ids,
+                        language_model_args.message_id.as_deref(),
+                        PersistedPart::Request,
+                    )
+                    .await;
+                drop(write);
+                saved_tx.send(()).ok();
+            }
+            .boxed()
+        });
+        saved_rx.await.ok();
+    }
+
+    /// Saves the event at `index` of a completion on its thread's write lane, so that the events
+    /// of the completions streaming into the thread at once are stored in the order they came in.
+    pub fn queue_completion_event(
+        self: &Arc<Self>,
+        event: &LanguageModelCompletionEvent,
+        index: usize,
+        ids: &RequestIds,
+        language_model_args: &LanguageModelArgs,
+    ) {
+        let write = self.pending_writes.begin(&ids.thread_id);
+        let handler = self.clone();
+        let event = event.clone();
+        self.write_lanes.enqueue(&ids.thread_id, |lane_position| {
+            let position = EventPosition {
+                index,
+                lane_position,
+            };
+            let ids = ids.clone();
+            let language_model_args = language_model_args.clone();
+            async move {
+                handler
+                    .save_completion_event(&event, position, &ids, &language_model_args)
+                    .await;
+                drop(write);
+            }
+            .boxed()
+        });
+    }
+
+    /// Saves one event of a completion, at the position the stream produced it in.
+    pub async fn save_completion_event(
+        &self,
+        request_message: &LanguageModelCompletionEvent,
+        position: EventPosition,
+        ids: &RequestIds,
+        language_model_args: &LanguageModelArgs,
+    ) {
+        if !self.persists(language_model_args)
+            || !self.persistence_features.persists_event(request_message)
+        {
+            return;
+        }
+        if let LanguageModelCompletionEvent::ToolUse(tool_use) = request_message {
+            if tool_use.is_input_complete {
+                self.save_tool_call_validation(tool_use, ids, language_model_args)
+                    .await;
+            }
+        }
+        let Some(msg) = Self::map_from_completion_event(
+            request_message,
+            &ids.checkpoint_id,
+            language_model_args,
+            &self.agent_name(language_model_args),
+        ) else {
+            if let Some(exporter) = &self.trace_exporter {
+                self.export_trace(ids, exporter.record_event(request_message, ids));
+            }
+            return;
+        };
+        let mut messages = vec![msg];
+        self.stamp_prompt_template(&mut messages, language_model_args)
+            .await;
+        stamp_idempotency_key(
+            &mut messages,
+            ids,
+            CheckpointEvent::Completion(position.index),
+        );
+        stamp_write_lane(&mut messages, &ids.checkpoint_id, position.lane_position);
+        let messages = self.filter_messages(messages);
+        if let Some(exporter) = &self.trace_exporter {
+            if let Some(event) =
+                langsmith::traced_event(request_message, messages.exported().next())
+            {
+                self.export_trace(ids, exporter.record_event(&event, ids));
+            }
+        }
+        let _ = self
+            .save_acknowledged(
+                messages,
+                ids,
+                language_model_args.message_id.as_deref(),
+                PersistedPart::Completion,
+            )
+            .await;
+    }
+
+    fn build_response_metadata(
+        language_model_args: &LanguageModelArgs,
+    ) -> HashMap<String, serde_json::Value> {
+        let mut response_metadata = HashMap::new();
+
+        response_metadata.insert(
+            "model_id".to_string(),
+            serde_json::Value::from(format!("{:?}", language_model_args.model_id.0.to_string())),
+        );
+        if let Some(provider) = &language_model_args.provider {
+            response_metadata.insert(
+                "provider".to_string(),
+                serde_json::Value::from(provider.clone()),
+            );
+        }
+
+        if let Some(temperature) = language_model_args.temperature {
+            response_metadata.insert(
+                "temperature".to_string(),
+                serde_json::Value::from(temperature),
+            );
+        }
+        if let Some(intent) = &language_model_args.intent {
+            response_metadata.insert(
+                "intent".to_string(),
+                serde_json::Value::from(intent.clone()),
+            );
+        }
+        if let Some(mode) = &language_model_args.mode {
+            response_metadata.insert("mode".to_string(), serde_json::Value::from(mode.clone()));
+        }
+        if let Some(prompt_id) = &language_model_args.prompt_id {
+            response_metadata.insert(
+                "prompt_id".to_string(),
+                serde_json::Value::from(prompt_id.clone()),
+            );
+        }
+        if let Some(toolchain) = &language_model_args.toolchain {
+            match serde_json::to_value(toolchain) {
+                Ok(toolchain) => {
+                    response_metadata.insert("toolchain".to_string(), toolchain);
+                }
+                Err(e) => log::error!("Failed to serialize request toolchain: {}", e),
+            }
+        }
+        if let Some(project) = &language_model_args.project {
+            response_metadata.insert(
+                "project".to_string(),
+                serde_json::Value::from(project.clone()),
+            );
+        }
+        if let Some(git_branch) = &language_model_args.git_branch {
+            response_metadata.insert(
+                GIT_BRANCH.to_string(),
+                serde_json::Value::from(git_branch.clone()),
+            );
+        }
+        if let Some(schedule_id) = &language_model_args.schedule_id {
+            response_metadata.insert(
+                SCHEDULE_ID.to_string(),
+                serde_json::Value::from(schedule_id.clone()),
+            );
+        }
+        if let Some(fan_out_group) = &language_model_args.fan_out_group {
+            response_metadata.insert(
+                FAN_OUT_GROUP.to_string(),
+                serde_json::Value::from(fan_out_group.clone()),
+            );
+        }
+        if let Some(origin) = &language_model_args.origin {
+            match serde_json::to_value(origin) {
+                Ok(origin) => {
+                    response_metadata.insert("origin".to_string(), origin);
+                }
+                Err(e) => log::error!("Failed to serialize request origin: {}", e),
+            }
+        }
+        if let Some(buffer) = &language_model_args.buffer {
+            match serde_json::to_value(buffer) {
+                Ok(buffer) => {
+                    response_metadata.insert("buffer".to_string(), buffer);
+                }
+                Err(e) => log::error!("Failed to serialize request buffer: {}", e),
+            }
+        }
+        if let Some(source) = language_model_args.source {
+            response_metadata.insert(
+                "source".to_string(),
+                serde_json::Value::from(source.as_str()),
+            );
+        }
+        response_metadata
+    }
+
+    /// Stores `source` as the next version of the template, unless it is already its latest
+    /// version.
+    pub async fn register_prompt_template(
+        &self,
+        id: &str,
+        source: Arc<str>,
+    ) -> anyhow::Result<PromptTemplateRef> {
+        if let Some((registered_source, template)) = self.prompt_templates.lock().get(id) {
+            if *registered_source == source {
+                return Ok(template.clone());
+            }
+        }
+        let db_client = self
+            .database_client
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("no database to register prompt templates in"))?;
+        self.ensure_writable()?;
+        let template = db_client.register_prompt_template(id, &source).await?;
+        self.prompt_templates
+            .lock()
+            .insert(id.to_string(), (source, template.clone()));
+        Ok(template)
+    }
+
+    /// Every registered version of the template, oldest first.
+    pub async fn prompt_template_versions(&self, id: &str) -> anyhow::Result<Vec<PromptTemplate>> {
+        let Some(db_client) = &self.database_client else {
+            return Ok(Vec::new());
+        };
+        db_client.prompt_template_versions(id).await
+    }
+
+    /// The given version of the template, or its latest one.
+    pub async fn prompt_template(
+        &self,
+        id: &str,
+        version: Option<i32>,
+    ) -> anyhow::Result<Option<PromptTemplate>> {
+        let versions = self.prompt_template_versions(id).await?;
+        Ok(match version {
+            Some(version) => versions
+                .into_iter()
+                .find(|template| template.version == version),
+            None => versions.into_iter().last(),
+        })
+    }
+
+    /// Registers the template the request's system prompt was rendered from and records its
+    /// version on the messages.
+    async fn stamp_prompt_template(
+        &self,
+        messages: &mut [Message],
+        language_model_args: &LanguageModelArgs,
+    ) {
+        if self.database_client.is_none() || self.discards_writes() {
+            return;
+        }
+        let Some(template) = &language_model_args.prompt_template else {
+            return;
+        };
+        match self
+            .register_prompt_template(&template.id, template.source.clone())
+            .await
+        {
+            Ok(template) => prompt_templates::stamp_prompt_template(messages, &template),
+            Err(error) => log::error!("Failed to register prompt template: {error:#}"),
+        }
+        if let Some(assignment) = &template.experiment {
+            experiments::stamp_experiment(messages, assignment);
+        }
+    }
+
+    /// Stores the schemas of the tools the request declared that aren't stored yet.
+    async fn save_tool_schemas(&self, language_model_args: &LanguageModelArgs) {
+        let Some(db_client) = &self.database_client else {
+            return;
+        };
+        if self.discards_writes() {
+            return;
+        }
+        let unsaved = {
+            let saved = self.saved_tool_schemas.lock();
+            language_model_args
+                .tool_schemas
+                .iter()
+                .map(|schema| (schema.hash(), schema))
+                .filter(|(hash, _)| !saved.contains(hash))
+                .collect::<Vec<_>>()
+        };
+        if unsaved.is_empty() {
+            return;
+        }
+        let schemas = unsaved
+            .iter()
+            .map(|(_, schema)| (*schema).clone())
+            .collect::<Vec<_>>();
+        match db_client.save_tool_schemas(&schemas).await {
+            Ok(()) => self
+                .saved_tool_schemas
+                .lock()
+                .extend(unsaved.into_iter().map(|(hash, _)| hash)),
+            Err(error) => log::error!("Failed to store tool schemas: {error:#}"),
+        }
+    }
+
+    /// The stored tool schemas with the given hashes.
+    pub async fn tool_schemas(&self, hashes: &[String]) -> anyhow::Result<Vec<ToolSchema>> {
+        let Some(db_client) = &self.database_client else {
+            return Ok(Vec::new());
+        };
+        if hashes.is_empty() {
+            return Ok(Vec::new());
+        }
+        db_client.tool_schemas(hashes).await
+    }
+
+    /// The tools the thread's latest stored request declared, as it declared them, to offer
+    /// them unchanged when the request is sent again.
+    pub async fn replay_request_tools(
+        &self,
+        thread_id: &str,
+    ) -> anyhow::Result<Vec<LanguageModelRequestTool>> {
+        let messages = self.all_stored_messages(thread_id).await?;
+        let hashes = latest_request_schema_hashes(&messages);
+        let schemas = self.tool_schemas(&hashes).await?;
+        Ok(hashes
+            .iter()
+            .filter_map(|hash| schemas.iter().find(|schema| schema.hash() == *hash))
+            .map(ToolSchema::to_request_tool)
+            .collect())
+    }
+
+    /// Checks the tool call against the schema its request declared, storing the result.
+    async fn save_tool_call_validation(
+        &self,
+        tool_use: &LanguageModelToolUse,
+        ids: &RequestIds,
+        language_model_args: &LanguageModelArgs,
+    ) {
+        let Some(db_client) = &self.database_client else {
+            return;
+        };
+        if self.discards_writes() {
+            return;
+        }
+        let validation = ToolCallValidation::new(tool_use, ids, language_model_args);
+        if let Err(error) = db_client.save_tool_call_validation(&validation).await {
+            log::error!("Failed to store tool call validation: {error:#}");
+        }
+    }
+
+    /// The latest `limit` threads in which a model made tool calls that didn't match their
+    /// schemas, of `provider` only if given, to compare how reliably providers' models call tools.
+    pub async fn threads_with_invalid_tool_calls(
+        &self,
+        provider: Option<&str>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<InvalidToolCallThread>> {
+        let Some(db_client) = &self.database_client else {
+            return Ok(Vec::new());
+        };
+        let mut threads = Vec::new();
+        for thread in db_client
+            .threads_with_invalid_tool_calls(provider, limit)
+            .await?
+        {
+            if self.can_read_thread(db_client, &thread.thread_id).await? {
+                threads.push(thread);
+            }
+        }
+        Ok(threads)
+    }
+
+    /// The tool calls of the thread whose input doesn't match the schema their request declared.
+    pub async fn invalid_tool_calls(
+        &self,
+        thread_id: &str,
+    ) -> anyhow::Result<Vec<InvalidToolCall>> {
+        let messages = self.all_stored_messages(thread_id).await?;
+        let schemas = self
+            .tool_schemas(&recorded_schema_hashes(&messages))
+            .await?
+            .into_iter()
+            .map(|schema| (schema.hash(), schema))
+            .collect();
+        Ok(invalid_tool_calls(&messages, &schemas))
+    }
+
+    /// Records how the thread's variant did, e.g. that an edit made with it was kept.
+    pub async fn record_experiment_outcome(
+        &self,
+        assignment: &PromptExperimentAssignment,
+        thread_id: &str,
+        metric: ExperimentMetric,
+        value: f64,
+    ) -> anyhow::Result<()> {
+        let Some(db_client) = &self.database_client else {
+            return Ok(());
+        };
+        if self.discards_writes() {
+            return Ok(());
+        }
+        db_client
+            .record_experiment_outcome(assignment, thread_id, metric, value)
+            .await
+    }
+
+    /// How each of the experiment's variants did on each metric.
+    pub async fn experiment_results(
+        &self,
+        experiment_id: &str,
+    ) -> anyhow::Result<Vec<VariantStats>> {
+        let Some(db_client) = &self.database_client else {
+            return Ok(Vec::new());
+        };
+        db_client.experiment_results(experiment_id).await
+    }
+
+    /// Records what the completion did with the provider's prompt cache, attributed to the
+    /// version of the template its request was built from.
+    async fn record_prompt_cache_usage(
+        &self,
+        ids: &RequestIds,
+        language_model_args: &LanguageModelArgs,
+        usage: &TokenUsage,
+    ) -> anyhow::Result<()> {
+        let Some(db_client) = &self.database_client else {
+            return Ok(());
+        };
+        if self.discards_writes() {
+            return Ok(());
+        }
+        let template = self.registered_prompt_template(language_model_args);
+        db_client
+            .record_prompt_cache_usage(ids, &PromptCacheUsage::new(template, usage))
+            .await
+    }
+
+    /// The version of the request's template that was registered for it, if any.
+    fn registered_prompt_template(
+        &self,
+        language_model_args: &LanguageModelArgs,
+    ) -> Option<PromptTemplateRef> {
+        let template = language_model_args.prompt_template.as_ref()?;
+        let prompt_templates = self.prompt_templates.lock();
+        let (source, registered) = prompt_templates.get(&template.id)?;
+        (*source == template.source).then(|| registered.clone())
+    }
+
+    /// How much of each template's prompts was read from the providers' prompt caches, to tell
+    /// which templates' prefixes are worth making more cacheable.
+    pub async fn prompt_cache_stats(&self) -> anyhow::Result<Vec<PromptCacheStats>> {
+        let Some(db_client) = &self.database_client else {
+            return Ok(Vec::new());
+        };
+        db_client.prompt_cache_stats().await
+    }
+
+    async fn record_request_usage(
+        &self,
+        ids: &RequestIds,
+        usage: &RequestUsageRecord,
+    ) -> anyhow::Result<()> {
+        let Some(db_client) = &self.database_client else {
+            return Ok(());
+        };
+        if self.discards_writes() {
+            return Ok(());
+        }
+        db_client.record_request_usage(ids, usage).await
+    }
+
+    /// How many requests were made, and how many tokens they used, on each day from `since` on,
+    /// by the language or project area of the buffers they were made from.
+    pub async fn usage_breakdown(
+        &self,
+        dimension: UsageDimension,
+        since: NaiveDate,
+    ) -> anyhow::Result<Vec<UsageBreakdown>> {
+        let Some(db_client) = &self.database_client else {
+            return Ok(Vec::new());
+        };
+        db_client.usage_breakdown(dimension, since).await
+    }
+
+    /// What the requests to each model on `day` used, without anything that identifies who made
+    /// them or what they said, leaving out the models with fewer than `min_requests` requests.
+    pub async fn usage_aggregates(
+        &self,
+        day: NaiveDate,
+        min_requests: i64,
+    ) -> anyhow::Result<Vec<UsageAggregate>> {
+        let Some(db_client) = &self.database_client else {
+            return Ok(Vec::new());
+        };
+        let rows = db_client.usage_histogram(day).await?;
+        Ok(usage_aggregates(&rows, min_requests))
+    }
+
+    /// Records that `referrer` references the checkpoint, so that garbage collection keeps it.
+    pub async fn add_checkpoint_reference(
+        &self,
+        thread_id: &str,
+        checkpoint_id: &str,
+        referrer: &str,
+    ) -> anyhow::Result<()> {
+        let Some(db_client) = &self.database_client else {
+            return Ok(());
+        };
+        self.ensure_writable()?;
+        db_client
+            .add_checkpoint_reference(thread_id, checkpoint_id, referrer)
+            .await
+    }
+
+    pub async fn remove_checkpoint_reference(
+        &self,
+        thread_id: &str,
+        checkpoint_id: &str,
+        referrer: &str,
+    ) -> anyhow::Result<()> {
+        let Some(db_client) = &self.database_client else {
+            return Ok(());
+        };
+        self.ensure_writable()?;
+        db_client
+            .remove_checkpoint_reference(thread_id, checkpoint_id, referrer)
+            .await
+    }
+
+    /// Prunes what hasn't been written within `retention` from the store, keeping whatever is
+    /// still referenced. Nothing is deleted in shadow or read-only mode.
+    pub async fn collect_garbage(&self, retention: Duration) -> anyhow::Result<GarbageCollection> {
+        let Some(db_client) = &self.database_client else {
+            return Ok(GarbageCollection::default());
+        };
+        if self.discards_writes() {
+            return Ok(GarbageCollection::default());
+        }
+        let collection = db_client.collect_garbage(retention).await?;
+        log::info!(
+            "Collected {} checkpoints and {} content blobs from the message store",
+            collection.checkpoints,
+            collection.content_blobs
+        );
+        Ok(collection)
+    }
+
+    /// Keeps the store's checkpoints partitioned by month. Nothing is changed in shadow or
+    /// read-only mode.
+    pub async fn maintain_checkpoint_partitions(
+        &self,
+        partitioning: &CheckpointPartitioning,
+    ) -> anyhow::Result<PartitionMaintenance> {
+        let Some(db_client) = &self.database_client else {
+            return Ok(PartitionMaintenance::default());
+        };
+        if self.discards_writes() {
+            return Ok(PartitionMaintenance::default());
+        }
+        let maintenance = db_client.maintain_partitions(partitioning).await?;
+        if !maintenance.created.is_empty() || !maintenance.detached.is_empty() {
+            log::info!(
+                "Created checkpoint partitions {:?} and detached {:?}",
+                maintenance.created,
+                maintenance.detached
+            );
+        }
+        Ok(maintenance)
+    }
+
+    /// Runs the policy's maintenance statements, then recommends what else is worth running
+    /// against the store's tables and indexes. Nothing is run in shadow or read-only mode.
+    pub async fn maintenance_advice(&self) -> anyhow::Result<Vec<MaintenanceAdvice>> {
+        let Some(db_client) = &self.database_client else {
+            anyhow::bail!("messages aren't being stored");
+        };
+        if !self.discards_writes() {
+            for statement in &self.maintenance_policy.statements {
+                db_client
+                    .run_maintenance(statement)
+                    .await
+                    .map_err(|error| error.context(format!("failed to run `{statement}`")))?;
+            }
+        }
+        let report = db_client.maintenance_report().await?;
+        Ok(maintenance_advice(&report, &self.maintenance_policy))
+    }
+
+    /// The anti-patterns found in the most recently updated threads this handler's author may
+    /// read, leaving out the threads nothing was found in.
+    pub async fn lint_stored_threads(&self) -> anyhow::Result<Vec<ThreadLint>> {
+        let threads = self
+            .list_stored_threads(None, self.lint_policy.max_threads, None, ThreadSort::Recent)
+            .await?;
+        let mut lints = Vec::new();
+        for thread in threads.items {
+            let messages = self.all_stored_messages(&thread.thread_id).await?;
+            let findings = lint_thread(&messages, &self.lint_policy);
+            if !findings.is_empty() {
+                lints.push(ThreadLint { thread, findings });
+            }
+        }
+        Ok(lints)
+    }
+
+    /// The messages of the thread's latest stored request, to send again, e.g. to replay the
+    /// thread as a regression test.
+    pub async fn replay_request_messages(
+        &self,
+        thread_id: &str,
+    ) -> anyhow::Result<Vec<LanguageModelRequestMessage>> {
+        let messages = self.all_stored_messages(thread_id).await?;
+        Ok(replay_messages(&messages))
+    }
+
+    /// Every stored message of the thread, read a page of checkpoints at a time.
+    async fn all_stored_messages(&self, thread_id: &str) -> anyhow::Result<Vec<StoredMessage>> {
+        let mut messages = Vec::new();
+        let mut after_seq = None;
+        loop {
+            let page = self
+                .list_stored_messages(thread_id, after_seq, STORED_MESSAGES_PAGE_SIZE)
+                .await?;
+            messages.extend(page.items);
+            match page.next {
+                Some(next) => after_seq = Some(next),
+                None => return Ok(messages),
+            }
+        }
+    }
+
+    /// Stores the environment the session's conversations ran against, so that they can be
+    /// reproduced later.
+    pub async fn save_session_env(
+        &self,
+        session_id: &str,
+        env: &SessionEnvironment,
+    ) -> anyhow::Result<()> {
+        let Some(db_client) = &self.database_client else {
+            return Ok(());
+        };
+        if self.discards_writes() {
+            return Ok(());
+        }
+        db_client.save_session_env(session_id, env).await
+    }
+
+    /// The environment the session's conversations ran against, as of its latest snapshot.
+    pub async fn session_env(
+        &self,
+        session_id: &str,
+    ) -> anyhow::Result<Option<SessionEnvironment>> {
+        let Some(db_client) = &self.database_client else {
+            return Ok(None);
+        };
+        db_client.session_env(session_id).await
+    }
+
+    /// Records the files the thread's tool calls referenced as they are now, to tell later
+    /// whether the thread still reflects them.
+    pub async fn save_file_snapshots(
+        &self,
+        thread_id: &str,
+        snapshots: &[FileSnapshot],
+    ) -> anyhow::Result<()> {
+        let Some(db_client) = &self.database_client else {
+            return Ok(());
+        };
+        if self.discards_writes() || snapshots.is_empty() {
+            return Ok(());
+        }
+        db_client.save_file_snapshots(thread_id, snapshots).await
+    }
+
+    /// The files the thread's tool calls referenced, as of the latest of them, to compare with
+    /// [`changed_files`].
+    pub async fn file_snapshots(&self, thread_id: &str) -> anyhow::Result<Vec<FileSnapshot>> {
+        let Some(db_client) = &self.database_client else {
+            return Ok(Vec::new());
+        };
+        self.readable_thread(db_client.as_ref(), thread_id).await?;
+        db_client.file_snapshots(thread_id).await
+    }
+
+    /// The threads whose tool calls or context referenced the file, most recently first, by the
+    /// project path [`FileSnapshot`]s are stored under.
+    pub async fn threads_referencing_file(
+        &self,
+        path: &str,
+        limit: usize,
+    ) -> anyhow::Result<Vec<String>> {
+        let Some(db_client) = &self.database_client else {
+            return Ok(Vec::new());
+        };
+        let mut threads = Vec::new();
+        for thread_id in db_client.threads_referencing_file(path, limit).await? {
+            if self.can_read_thread(db_client, &thread_id).await? {
+                threads.push(thread_id);
+            }
+        }
+        Ok(threads)
+    }
+
+    /// Records that the commit was made during the thread's agent session, so that the thread
+    /// can be opened from the commit.
+    pub async fn save_commit_thread(&self, sha: &str, thread_id: &str) -> anyhow::Result<()> {
+        let Some(db_client) = &self.database_client else {
+            return Ok(());
+        };
+        if self.discards_writes() {
+            return Ok(());
+        }
+        db_client.save_commit_thread(sha, thread_id).await
+    }
+
+    /// The thread whose agent session the commit was made during, if one was recorded.
+    pub async fn commit_thread(&self, sha: &str) -> anyhow::Result<Option<String>> {
+        let Some(db_client) = &self.database_client else {
+            return Ok(None);
+        };
+        let Some(thread_id) = db_client.commit_thread(sha).await? else {
+            return Ok(None);
+        };
+        Ok(self
+            .can_read_thread(db_client, &thread_id)
+            .await?
+            .then_some(thread_id))
+    }
+
+    /// Locks the thread in the store for the session's agent run, so that teammates sharing the
+    /// store see it's in use. Returns whoever else holds the lock instead, if anyone does.
+    pub async fn acquire_store_lock(
+        &self,
+        thread_id: &str,
+        session_id: &str,
+    ) -> anyhow::Result<Option<StoreLockHolder>> {
+        let Some(db_client) = &self.database_client else {
+            return Ok(None);
+        };
+        if self.discards_writes() {
+            return Ok(None);
+        }
+        db_client
+            .acquire_store_thread_lock(thread_id, session_id, self.author.as_ref())
+            .await
+    }
+
+    pub async fn release_store_lock(
+        &self,
+        thread_id: &str,
+        session_id: &str,
+    ) -> anyhow::Result<()> {
+        let Some(db_client) = &self.database_client else {
+            return Ok(());
+        };
+        if self.discards_writes() {
+            return Ok(());
+        }
+        db_client
+            .release_store_thread_lock(thread_id, session_id)
+            .await
+    }
+
+    /// Who holds the locks of the threads, of those that are locked.
+    pub async fn store_lock_holders(
+        &self,
+        thread_ids: &[String],
+    ) -> anyhow::Result<Vec<StoreLockHolder>> {
+        let Some(db_client) = &self.database_client else {
+            return Ok(Vec::new());
+        };
+        if thread_ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        db_client.store_thread_lock_holders(thread_ids).await
+    }
+
+    /// Takes the thread's lock from whoever holds it, recording that this handler's author did.
+    pub async fn override_store_lock(
+        &self,
+        thread_id: &str,
+        action: LockOverride,
+    ) -> anyhow::Result<Option<StoreLockHolder>> {
+        let Some(db_client) = &self.database_client else {
+            return Ok(None);
+        };
+        db_client
+            .override_store_thread_lock(thread_id, action, self.author.as_ref())
+            .await
+    }
+
+    /// Records the profile, tools and rules the thread's requests are made with, for replays and
+    /// audits to know what the model was told.
+    pub async fn save_thread_instructions(
+        &self,
+        thread_id: &str,
+        instructions: &ThreadInstructions,
+    ) -> anyhow::Result<()> {
+        let Some(db_client) = &self.database_client else {
+            return Ok(());
+        };
+        if self.discards_writes() {
+            return Ok(());
+        }
+        db_client
+            .save_thread_instructions(thread_id, instructions)
+            .await
+    }
+
+    /// The instructions the thread's requests were made with over its history, oldest first.
+    pub async fn thread_instructions(
+        &self,
+        thread_id: &str,
+    ) -> anyhow::Result<Vec<RecordedInstructions>> {
+        let Some(db_client) = &self.database_client else {
+            return Ok(Vec::new());
+        };
+        self.readable_thread(db_client.as_ref(), thread_id).await?;
+        db_client.thread_instructions(thread_id).await
+    }
+
+    /// Records how a run made without a user ended, for the inbox of finished runs.
+    pub async fn save_run_result(&self, result: &RunResult) -> anyhow::Result<()> {
+        let Some(db_client) = &self.database_client else {
+            return Ok(());
+        };
+        if self.discards_writes() {
+            return Ok(());
+        }
+        db_client.save_run_result(result).await
+    }
+
+    /// The results of the latest runs made without a user, most recent first.
+    pub async fn run_results(&self, limit: usize) -> anyhow::Result<Vec<RunResult>> {
+        let Some(db_client) = &self.database_client else {
+            return Ok(Vec::new());
+        };
+        db_client.run_results(limit).await
+    }
+
+    /// Records how the judge model ranked the responses of a fan-out group.
+    pub async fn save_fan_out_verdict(&self, verdict: &FanOutVerdict) -> anyhow::Result<()> {
+        let Some(db_client) = &self.database_client else {
+            return Ok(());
+        };
+        if self.discards_writes() {
+            return Ok(());
+        }
+        db_client.save_fan_out_verdict(verdict).await
+    }
+
+    /// How the judge model ranked the responses of the fan-out group, if it was judged.
+    pub async fn fan_out_verdict(&self, group_id: &str) -> anyhow::Result<Option<FanOutVerdict>> {
+        let Some(db_client) = &self.database_client else {
+            return Ok(None);
+        };
+        db_client.fan_out_verdict(group_id).await
+    }
+
+    /// Once the thread's uncompacted checkpoints hold more tokens than the compaction policy
+    /// allows, stores a summary of them under the `context_summarization` task path and leaves
+    /// them out of what the thread is resumed from. Returns whether the thread was compacted.
+    /// The local cache keeps the whole history.
+    pub async fn compact_thread_if_needed(&self, ids: &RequestIds) -> anyhow::Result<bool> {
+        let thread_id = ids.thread_id.as_str();
+        let (Some(db_client), Some(policy), Some(summarizer)) = (
+            &self.database_client,
+            self.compaction_policy,
+            self.context_summarizer.clone(),
+        ) else {
+            return Ok(false);
+        };
+        if self.discards_writes() {
+            return Ok(false);
+        }
+        if db_client.stored_token_count(thread_id).await? < policy.max_stored_tokens as i64 {
+            return Ok(false);
+        }
+        if !self.compacting.lock().insert(thread_id.to_string()) {
+            return Ok(false);
+        }
+        let result = self
+            .compact_thread(db_client, summarizer.as_ref(), ids)
+            .await;
+        self.compacting.lock().remove(thread_id);
+        result
+    }
+
+    async fn compact_thread(
+        &self,
+        db_client: &StoreClient,
+        summarizer: &dyn ContextSummarizer,
+        request_ids: &RequestIds,
+    ) -> anyhow::Result<bool> {
+        let thread_id = request_ids.thread_id.as_str();
+        // Only what has reached the store is summarized. The thread's writes in flight are
+        // waited for, and checkpoints the local cache has yet to replicate would be appended to
+        // after they were compacted, so the thread is left until they are replicated.
+        self.pending_writes.flush(thread_id).await;
+        if let Some(local_cache) = &self.local_cache {
+            if local_cache.has_pending(thread_id)? {
+                return Ok(false);
+            }
+        }
+        let stored = db_client.uncompacted_messages(thread_id).await?;
+        let Some(through_seq) = stored.last().map(|message| message.seq) else {
+            return Ok(false);
+        };
+        let through_sequence = stored
+            .iter()
+            .filter_map(|stored| message_sequence(&stored.message))
+            .max();
+        let messages = stored
+            .into_iter()
+            .map(|stored| stored.message)
+            .collect::<Vec<_>>();
+        let summary = summarizer.summarize(request_ids, messages).await?;
+
+        // Counted like the thread's latest request, so that the summary counts towards the next
+        // compaction.
+        let model_id = self
+            .last_models
+            .get(thread_id)
+            .map(|(model_id, _)| model_id.clone())
+            .unwrap_or_else(|| LanguageModelId::from(String::new()));
+        let count = self.tokenizers.count_tokens(&model_id, &summary);
+        let mut summary = compaction::context_summary_message(
+            thread_id,
+            summary,
+            through_seq,
+            self.agent_identity
+                .name_for(Some(compaction::CONTEXT_SUMMARY_INTENT), None),
+        );
+        token_counts::stamp_token_count(&mut summary, count);
+        let checkpoint_id = uuid::Uuid::new_v4().to_string();
+        let ids = RequestIds {
+            thread_id: thread_id.to_string(),
+            checkpoint_id: checkpoint_id.clone(),
+            session_id: thread_id.to_string(),
+            prompt_id: checkpoint_id,
+        };
+        let compacted = db_client
+            .compact_thread(
+                summary,
+                &ids,
+                self.author.as_ref(),
+                through_seq,
+                through_sequence,
+            )
+            .await?;
+        if compacted == 0 {
+            return Ok(false);
+        }
+        self.thread_cache.lock().invalidate(thread_id);
+        Ok(true)
+    }
+
+    /// A `model_transition` event when the thread's previous request went to another model,
+    /// linking the checkpoint that ended the previous model's segment to the one starting the next.
+    fn model_transition(
+        &self,
+        ids: &RequestIds,
+        language_model_args: &LanguageModelArgs,
+    ) -> Option<Message> {
+        let (previous_model, previous_checkpoint_id) = self.last_models.insert(
+            ids.thread_id.clone(),
+            (
+                language_model_args.model_id.clone(),
+                ids.checkpoint_id.clone(),
+            ),
+        )?;
+        if previous_model == language_model_args.model_id {
+            return None;
+        }
+
+        let additional_kwargs = HashMap::from_iter([
+            (
+                "event".to_string(),
+                serde_json::Value::from("model_transition"),
+            ),
+            (
+                "from_model".to_string(),
+                serde_json::Value::from(previous_model.0.to_string()),
+            ),
+            (
+                "to_model".to_string(),
+                serde_json::Value::from(language_model_args.model_id.0.to_string()),
+            ),
+            (
+                "from_checkpoint_id".to_string(),
+                serde_json::Value::from(previous_checkpoint_id),
+            ),
+            (
+                "to_checkpoint_id".to_string(),
+                serde_json::Value::from(ids.checkpoint_id.clone()),
+            ),
+        ]);
+        Some(Message::System {
+            content: ContentValue::new("model_transition".to_string()),
+            id: ids.thread_id.clone(),
+            name: Some(self.agent_name(language_model_args)),
+            example: false,
+            additional_kwargs,
+            response_metadata: Self::build_response_metadata(language_model_args),
+        })
+    }
+
+    pub fn map_from_completion_request(
+        request_message: &LanguageModelRequestMessage,
+        id: &RequestIds,
+        language_model_args: &LanguageModelArgs,
+        agent_name: &str,
+    ) -> Option<Message> {
+        let mut additional_kwargs = HashMap::new();
+        let content = match serde_json::to_string(&request_message.content) {
+            Ok(content) => content,
+            Err(e) => {
+                log::error!("Failed to serialize request message content: {}", e);
+                additional_kwargs.insert(
+                    PERSIST_FAILED.to_string(),
+                    serde_json::json!({
+                        "error": e.to_string(),
+                        "content": format!("{:?}", request_message.content),
+                    }),
+                );
+                format!("[content not stored: it failed to serialize: {e}]")
+            }
+        };
+        let content_value = ContentValue::new(content);
+        let id = id.thread_id.to_string();
+
+        let mut response_metadata = Self::build_response_metadata(language_model_args);
+        if let Some(tools) = &language_model_args.tools {
+            response_metadata.insert(
+                REQUEST_TOOLS.to_string(),
+                serde_json::Value::from(tools.clone()),
+            );
+        }
+        if !language_model_args.tool_schemas.is_empty() {
+            response_metadata.insert(
+                REQUEST_TOOL_SCHEMAS.to_string(),
+                schema_hashes(&language_model_args.tool_schemas),
+            );
+        }
+
+        match &request_message.role {
+            Role::User => Some(Message::Human {
+                content: content_value,
+                id,
+                name: Some(agent_name.to_string()),
+                example: false,
+                additional_kwargs,
+                response_metadata,
+            }),
+            Role::System => Some(Message::System {
+                content: content_value,
+                id,
+                name: Some(agent_name.to_string()),
+                example: false,
+                additional_kwargs,
+                response_metadata,
+            }),
+            Role::Assistant => Some(Message::Ai {
+                content: content_value,
+                id,
+                name: Some(agent_name.to_string()),
+                example: false,
+                invalid_tool_calls: None,
+                tool_calls: None,
+                additional_kwargs,
+                response_metadata,
+            }),
+        }
+    }
+
+    pub fn map_from_completion_event(
+        request_message: &LanguageModelCompletionEvent,
+        thread_id: &str,
+        language_model_args: &LanguageModelArgs,
+        agent_name: &str,
+    ) -> Option<Message> {
+        let response_metadata = Self::build_response_metadata(&language_model_args);
+        match request_message {
+            LanguageModelCompletionEvent::StatusUpdate { .. } => None,
+            LanguageModelCompletionEvent::StartMessage { .. } => None,
+            LanguageModelCompletionEvent::Text(text) => {
+                let id = thread_id.to_string();
+                Some(Message::Ai {
+                    content: ContentValue::new(text.clone()),
+                    id,
+                    name: Some(agent_name.to_string()),
+                    example: false,
+                    invalid_tool_calls: None,
+                    tool_calls: None,
+                    additional_kwargs: HashMap::new(),
+                    response_metadata,
+                })
+            }
+            LanguageModelCompletionEvent::Thinking { text, signature } => {
+                let id = thread_id.to_string();
+                let mut additional_kwargs = HashMap::new();
+                additional_kwargs.insert(
+                    "thinking".to_string(),
+                    serde_json::Value::String(text.clone()),
+                );
+                if let Some(sig) = signature {
+                    additional_kwargs.insert(
+                        "signature".to_string(),
+                        serde_json::Value::String(sig.clone()),
+                    );
+                }
+
+                Some(Message::Ai {
+                    content: ContentValue::new(text.clone()),
+                    id,
+                    name: Some(agent_name.to_string()),
+                    example: false,
+                    invalid_tool_calls: None,
+                    tool_calls: None,
+                    additional_kwargs,
+                    response_metadata,
+                })
+            }
+            LanguageModelCompletionEvent::Stop(_) => {
+                let id = thread_id.to_string();
+                Some(Message::Ai {
+                    content: ContentValue::new("STOP".to_string()),
+                    id,
+                    name: Some(agent_name.to_string()),
+                    example: false,
+                    invalid_tool_calls: None,
+                    tool_calls: None,
+                    additional_kwargs: HashMap::new(),
+                    response_metadata,
+                })
+            }
+            LanguageModelCompletionEvent::ToolUse(tool_use) => {
+                let content = match serde_json::to_string(&tool_use.input) {
+                    Ok(content) => content,
+                    Err(e) => {
+                        log::error!("Failed to serialize tool use input: {}", e);
+                        String::default()
+                    }
+                };
+                let mut additional_kwargs = HashMap::new();
+                additional_kwargs.insert(
+                    "raw_input".to_string(),
+                    serde_json::Value::String(tool_use.raw_input.clone()),
+                );
+                additional_kwargs.insert(
+                    "is_input_complete".to_string(),
+                    serde_json::Value::Bool(tool_use.is_input_complete),
+                );
+
+                Some(Message::Tool {
+                    content: ContentValue::new(content),
+                    id: tool_use.id.to_string(),
+                    name: Some(agent_name.to_string()),
+                    example: false,
+                    tool_call_id: Some(tool_use.id.to_string()),
+                    tool_name: Some(tool_use.name.as_ref().to_string()),
+                    additional_kwargs,
+                    response_metadata,
+                })
+            }
+            LanguageModelCompletionEvent::UsageUpdate(_token_usage) => None,
+        }
+    }
+
+    /// Saves the messages, acknowledging the write under the message the request was sent for,
+    /// if it has one, and keeping the messages to retry the write if it fails.
+    async fn save_acknowledged(
+        &self,
+        messages: FilteredMessages,
+        ids: &RequestIds,
+        message_id: Option<&str>,
+        part: PersistedPart,
+    ) -> anyhow::Result<()> {
+        let message_id =
+            message_id.filter(|_| self.database_client.is_some() || self.local_cache.is_some());
+        let Some(message_id) = message_id else {
+            return self.save_filtered_messages(messages, ids).await;
+        };
+        self.persistence_acks
+            .begin(&ids.thread_id, message_id, part);
+        let result = self.save_filtered_messages(messages.clone(), ids).await;
+        let failure = result.as_ref().err().map(|error| {
+            let write = FailedWrite {
+                part,
+                messages,
+                ids: ids.clone(),
+            };
+            (write, format!("{error:#}"))
+        });
+        self.persistence_acks
+            .finish(&ids.thread_id, message_id, part, failure);
+        result
+    }
+
+    /// Writes the message's failed writes again.
+    pub async fn retry_persistence(&self, thread_id: &str, message_id: &str) -> anyhow::Result<()> {
+        let mut result = Ok(());
+        for write in self.persistence_acks.take_failed(thread_id, message_id) {
+            let retried = self
+                .save_acknowledged(write.messages, &write.ids, Some(message_id), write.part)
+                .await;
+            if result.is_ok() {
+                result = retried;
+            }
+        }
+        result
+    }
+
+    /// Save a message to the database
+    pub async fn save_append_messages(
+        &self,
+        messages: Vec<Message>,
+        ids: &RequestIds,
+    ) -> anyhow::Result<()> {
+        self.save_filtered_messages(self.filter_messages(messages), ids)
+            .await
+    }
+
+    /// Applies the message rules to messages about to be stored, forwarded or exported.
+    fn filter_messages(&self, messages: Vec<Message>) -> FilteredMessages {
+        FilteredMessages::new(self.message_filter.as_deref(), messages)
+    }
+
+    async fn save_filtered_messages(
+        &self,
+        messages: FilteredMessages,
+        ids: &RequestIds,
+    ) -> anyhow::Result<()> {
+        let Some(sampler) = &self.sampler else {
+            return self.save_sampled_messages(messages, ids).await;
+        };
+        let sampled = sampler.sample(messages, ids);
+        if let Some(decision) = &sampled.decision {
+            if let Err(error) = self.save_sampling_decision(ids, decision).await {
+                log::error!(
+                    "Failed to record whether thread {} is stored: {error:#}",
+                    ids.thread_id
+                );
+            }
+        }
+        for (messages, ids) in sampled.checkpoints {
+            self.save_sampled_messages(messages, &ids).await?;
+        }
+        Ok(())
+    }
+
+    async fn save_sampling_decision(
+        &self,
+        ids: &RequestIds,
+        decision: &SamplingDecision,
+    ) -> anyhow::Result<()> {
+        let Some(db_client) = &self.database_client else {
+            return Ok(());
+        };
+        if self.discards_writes() {
+            return Ok(());
+        }
+        db_client
+            .save_sampling_decision(&ids.session_id, &ids.thread_id, decision)
+            .await
+    }
+
+    async fn save_sampled_messages(
+        &self,
+        messages: FilteredMessages,
+        ids: &RequestIds,
+    ) -> anyhow::Result<()> {
+        let mut result = Ok(());
+        for (messages, route) in messages.into_groups() {
+            let saved = self.forward_or_save_messages(messages, ids, route).await;
+            if result.is_ok() {
+                result = saved;
+            }
+        }
+        result
+    }
+
+    async fn forward_or_save_messages(
+        &self,
+        messages: Vec<Message>,
+        ids: &RequestIds,
+        route: MessageRoute,
+    ) -> anyhow::Result<()> {
+        // Messages kept out of the store are kept out of the host's store too.
+        let remote_persistence = self
+            .remote_persistence
+            .route(&messages)
+            .filter(|_| route.store && !self.discards_writes());
+        if let Some(remote_persistence) = remote_persistence {
+            match remote_persistence
+                .forward(messages.clone(), ids.clone(), self.author.clone())
+                .await
+            {
+                Ok(()) => return Ok(()),
+                Err(error) => {
+                    log::error!("Failed to forward messages, storing them here: {error:#}")
+                }
+            }
+        }
+        self.save_messages_by(messages, ids, self.author.as_ref(), route)
+            .await
+    }
+
+    /// Saves messages forwarded from another machine, attributed to whoever sent them there.
+    /// They were filtered by the sender's rules, and are filtered by this machine's too.
+    pub async fn save_forwarded_messages(
+        &self,
+        messages: Vec<Message>,
+        ids: &RequestIds,
+        author: Option<MessageAuthor>,
+    ) -> anyhow::Result<()> {
+        let author = author.as_ref().or(self.author.as_ref());
+        let mut result = Ok(());
+        for (messages, route) in self.filter_messages(messages).into_groups() {
+            let saved = self.save_messages_by(messages, ids, author, route).await;
+            if result.is_ok() {
+                result = saved;
+            }
+        }
+        result
+    }
+
+    async fn save_messages_by(
+        &self,
+        mut messages: Vec<Message>,
+        ids: &RequestIds,
+        author: Option<&MessageAuthor>,
+        route: MessageRoute,
+    ) -> anyhow::Result<()> {
+        if self.read_only {
+            return Ok(());
+        }
+        if let Err(busy) = self.thread_locks.check(ids) {
+            log::error!("Not saving messages: {busy}");
+            return Err(busy.into());
+        }
+        if let Some(message_author) = author {
+            author::stamp_author(&mut messages, message_author);
+        }
+        pii::tag_pii(&mut messages);
+        self.store_messages(messages, ids, author, route).await
+    }
+
+    async fn store_messages(
+        &self,
+        messages: Vec<Message>,
+        ids: &RequestIds,
+        author: Option<&MessageAuthor>,
+        route: MessageRoute,
+    ) -> anyhow::Result<()> {
+        if let Some(shadow) = &self.shadow {
+            if let Err(error) = shadow.record(&messages) {
+                log::error!("Failed to encode messages in shadow mode: {error:#}");
+            }
+            return Ok(());
+        }
+        let local_cache = self.local_cache.as_ref().filter(|_| route.local_cache);
+        // While older checkpoints of the thread are still only local, e.g. from while the remote
+        // store was unreachable, this one is saved after them rather than ahead of them.
+        let queued = local_cache.is_some_and(|local_cache| {
+            local_cache
+                .has_pending(&ids.thread_id)
+                .inspect_err(|error| {
+                    log::error!("Failed to read the local message cache: {error:#}")
+                })
+                .unwrap_or(true)
+        });
+        let local_id = local_cache.and_then(|local_cache| {
+            local_cache
+                .append(&messages, ids, author)
+                .inspect_err(|error| log::error!("Failed to cache messages locally: {error:#}"))
+                .ok()
+        });
+        if !route.store {
+            // Marked as replicated so that they stay local.
+            if let Some((local_cache, local_id)) = local_cache.zip(local_id) {
+                if let Err(error) = local_cache.mark_replicated(local_id) {
+                    log::error!("Failed to keep messages local: {error:#}");
+                }
+            }
+            if local_cache.is_some() && local_id.is_none() {
+                anyhow::bail!("failed to cache messages locally");
+            }
+            return Ok(());
+        }
+        if let Some(ref db_client) = self.database_client {
+            let result = if queued && local_id.is_some() {
+                self.replicate_pending(Some(&ids.thread_id))
+                    .await
+                    .map(|_| ())
+            } else {
+                self.replicate(db_client, messages, ids, author, local_id)
+                    .await
+            };
+            self.thread_cache.lock().invalidate(&ids.thread_id);
+            if let Err(error) = result {
+                if local_id.is_some() {
+                    log::error!("Failed to store messages, keeping them locally: {error:#}");
+                } else {
+                    log::error!("Failed to store messages: {error:#}");
+                    return Err(error);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Saves messages to the remote store, marking their local copy, if any, as replicated.
+    async fn replicate(
+        &self,
+        db_client: &StoreClient,
+        messages: Vec<Message>,
+        ids: &RequestIds,
+        author: Option<&MessageAuthor>,
+        local_id: Option<i64>,
+    ) -> anyhow::Result<()> {
+        self.disrupt_store("save_append_messages").await?;
+        let Some(local_cache) = &self.local_cache else {
+            let result = db_client.save_append_messages(messages, ids, author).await;
+            self.store_writes.record(&result);
+            return result;
+        };
+        if let Some(local_id) = local_id {
+            if !self.replicating.lock().insert(local_id) {
+                return Ok(());
+            }
+        }
+        let result = async {
+            // Until the remote store has some of the thread, the local copy is all of it.
+            if local_cache.is_complete(&ids.thread_id)?.is_none() {
+                let complete = db_client.get_thread(&ids.thread_id).await?.is_none();
+                local_cache.set_complete(&ids.thread_id, complete)?;
+            }
+            let written = db_client.save_append_messages(messages, ids, author).await;
+            self.store_writes.record(&written);
+            written?;
+            if let Some(local_id) = local_id {
+                local_cache.mark_replicated(local_id)?;
+            }
+            anyhow::Ok(())
+        }
+        .await;
+        if let Some(local_id) = local_id {
+            self.replicating.lock().remove(&local_id);
+        }
+        result
+    }
+
+    /// Saves the messages only the local cache has, e.g. from while the remote store was
+    /// unreachable, returning how many checkpoints were saved. Stops at the first failure, so that
+    /// a thread's checkpoints reach the remote store in order.
+    pub async fn replicate_local_cache(&self) -> anyhow::Result<usize> {
+        self.replicate_pending(None).await
+    }
+
+    /// Saves the checkpoints only the local cache has, of the thread if one is given, oldest
+    /// first. One run saves them at a time, and a thread whose checkpoint a live write is saving
+    /// is left for a later run, so that no checkpoint overtakes an older one of its thread.
+    async fn replicate_pending(&self, thread_id: Option<&str>) -> anyhow::Result<usize> {
+        let (Some(local_cache), Some(db_client)) = (&self.local_cache, &self.database_client)
+        else {
+            return Ok(0);
+        };
+        if self.discards_writes() {
+            return Ok(0);
+        }
+        let _replication = self.replication.lock().await;
+        let mut replicated = 0;
+        let mut after = 0;
+        let mut waiting = HashSet::new();
+        loop {
+            let pending = local_cache.pending(thread_id, after, REPLICATION_BATCH_SIZE)?;
+            let batch_len = pending.len();
+            for checkpoint in pending {
+                after = checkpoint.id;
+                if waiting.contains(&checkpoint.ids.thread_id)
+                    || self.replicating.lock().contains(&checkpoint.id)
+                {
+                    waiting.insert(checkpoint.ids.thread_id);
+                    continue;
+                }
+                self.replicate(
+                    db_client,
+                    checkpoint.messages,
+                    &checkpoint.ids,
+                    checkpoint.author.as_ref(),
+                    Some(checkpoint.id),
+                )
+                .await?;
+                self.thread_cache
+                    .lock()
+                    .invalidate(&checkpoint.ids.thread_id);
+                replicated += 1;
+            }
+            if batch_len < REPLICATION_BATCH_SIZE {
+                return Ok(replicated);
+            }
+        }
+    }
+
+    /// The thread's local copy, if it is all of the thread or there is no remote store to read
+    /// instead.
+    fn local_thread(&self, thread_id: &str) -> anyhow::Result<Option<Vec<Message>>> {
+        let Some(local_cache) = &self.local_cache else {
+            return Ok(None);
+        };
+        if self.database_client.is_some() && local_cache.is_complete(thread_id)? != Some(true) {
+            return Ok(None);
+        }
+        let Some(thread) = local_cache.get_thread(thread_id)? else {
+            return Ok(None);
+        };
+        anyhow::ensure!(
+            self.authorizer.can_read(&thread, self.author.as_ref()),
+            "not allowed to read thread {thread_id}"
+        );
+        Ok(Some(local_cache.load_thread(thread_id)?))
+    }
+
+    /// A page of stored threads that this handler's author may read, in the order `sort` gives,
+    /// e.g. to surface the conversations of the branch the user switched to when `git_branch` is
+    /// given. The local cache is listed first, without a round trip, then the threads only the
+    /// remote store has. Pass the returned `next` cursor back to continue the listing.
+    pub async fn list_stored_threads(
+        &self,
+        after: Option<&HistoryCursor>,
+        limit: usize,
+        git_branch: Option<&str>,
+        sort: ThreadSort,
+    ) -> anyhow::Result<Page<StoredThread, HistoryCursor>> {
+        let remote_after = match after {
+            Some(HistoryCursor::Remote(after)) => after.as_ref(),
+            local_after => {
+                if let Some(local_cache) = &self.local_cache {
+                    let local_after = match local_after {
+                        Some(HistoryCursor::Local(after)) => Some(after),
+                        _ => None,
+                    };
+                    let page = local_cache.list_threads(local_after, limit, git_branch, sort)?;
+                    let next = match page.next {
+                        Some(next) => Some(HistoryCursor::Local(next)),
+                        None => self
+                            .database_client
+                            .as_ref()
+                            .map(|_| HistoryCursor::Remote(None)),
+                    };
+                    // A listing that finds nothing locally goes straight on to the remote store.
+                    if !page.items.is_empty() || local_after.is_some() || next.is_none() {
+                        return Ok(Page {
+                            items: page
+                                .items
+                                .into_iter()
+                                .filter(|thread| {
+                                    self.authorizer.can_read(thread, self.author.as_ref())
+                                })
+                                .collect(),
+                            next,
+                        });
+                    }
+                }
+                None
+            }
+        };
+        let Some(db_client) = &self.database_client else {
+            return Ok(Page::default());
+        };
+        self.disrupt_store("list_threads").await?;
+        // Only the unfiltered first page of the most recent threads is cached.
+        let page = if remote_after.is_none() && git_branch.is_none() && sort == ThreadSort::Recent {
+            self.first_thread_page(db_client.as_ref(), limit).await?
+        } else {
+            Arc::new(
+                db_client
+                    .list_threads(remote_after, limit, git_branch, sort)
+                    .await?,
+            )
+        };
+        // Threads the local cache has were listed with it, and threads the author may not read
+        // aren't listed. Both are dropped from the page without refilling it, so a page can come
+        // back short while `next` still points further on.
+        let mut items = Vec::new();
+        for thread in page.items.iter() {
+            if let Some(local_cache) = &self.local_cache {
+                if local_cache.get_thread(&thread.thread_id)?.is_some() {
+                    continue;
+                }
+            }
+            if self.authorizer.can_read(thread, self.author.as_ref()) {
+                items.push(thread.clone());
+            }
+        }
+        Ok(Page {
+            items,
+            next: page
+                .next
+                .clone()
+                .map(|next| HistoryCursor::Remote(Some(next))),
+        })
+    }
+
+    /// Pins or stars the thread, or takes the flag off it, in the store the threads are listed
+    /// from.
+    pub async fn set_thread_flag(
+        &self,
+        thread_id: &str,
+        flag: ThreadFlag,
+        value: bool,
+    ) -> anyhow::Result<()> {
+        self.ensure_writable()?;
+        if self.database_client.is_none() && self.local_cache.is_none() {
+            anyhow::bail!("messages aren't being stored");
+        }
+        if let Some(db_client) = &self.database_client {
+            self.disrupt_store("set_thread_flag").await?;
+            db_client.set_thread_flag(thread_id, flag, value).await?;
+        }
+        // The local cache is listed first, so it is flagged too.
+        if let Some(local_cache) = &self.local_cache {
+            local_cache.set_thread_flag(thread_id, flag, value)?;
+        }
+        self.thread_cache.lock().invalidate(thread_id);
         Ok(())
     }
 
+    /// Tags the thread, e.g. with the feature, ticket or customer it is about, returning the tag
+    /// as it was stored.
+    pub async fn add_thread_tag(&self, thread_id: &str, tag: &str) -> anyhow::Result<String> {
+        self.ensure_writable()?;
+        let tag = normalize_tag(tag)?;
+        match (&self.database_client, &self.local_cache) {
+            (Some(db_client), _) => {
+                self.disrupt_store("add_thread_tag").await?;
+                db_client.add_thread_tag(thread_id, &tag).await?;
+            }
+            (None, Some(local_cache)) => local_cache.add_thread_tag(thread_id, &tag)?,
+            (None, None) => anyhow::bail!("messages aren't being stored"),
+        }
+        self.notify_thread(ThreadFacts {
+            thread_id: thread_id.to_string(),
+            ..ThreadFacts::default()
+        })
+        .await;
+        Ok(tag)
+    }
+
+    pub async fn remove_thread_tag(&self, thread_id: &str, tag: &str) -> anyhow::Result<()> {
+        self.ensure_writable()?;
+        let tag = normalize_tag(tag)?;
+        match (&self.database_client, &self.local_cache) {
+            (Some(db_client), _) => {
+                self.disrupt_store("remove_thread_tag").await?;
+                db_client.remove_thread_tag(thread_id, &tag).await
+            }
+            (None, Some(local_cache)) => local_cache.remove_thread_tag(thread_id, &tag),
+            (None, None) => anyhow::bail!("messages aren't being stored"),
+        }
+    }
+
+    pub async fn thread_tags(&self, thread_id: &str) -> anyhow::Result<Vec<String>> {
+        match (&self.database_client, &self.local_cache) {
+            (Some(db_client), _) => {
+                self.readable_thread(db_client.as_ref(), thread_id).await?;
+                self.disrupt_store("thread_tags").await?;
+                db_client.thread_tags(thread_id).await
+            }
+            (None, Some(local_cache)) => local_cache.thread_tags(thread_id),
+            (None, None) => Ok(Vec::new()),
+        }
+    }
+
+    /// The tags already on stored threads that start with `prefix`, ignoring case, to complete
+    /// the tag the user is typing. The tags on the most threads come first.
+    pub async fn tag_suggestions(
+        &self,
+        prefix: &str,
+        limit: usize,
+    ) -> anyhow::Result<Vec<TagSuggestion>> {
+        let pattern = tag_prefix_pattern(prefix);
+        match (&self.database_client, &self.local_cache) {
+            (Some(db_client), _) => {
+                self.disrupt_store("tag_suggestions").await?;
+                db_client.tag_suggestions(&pattern, limit).await
+            }
+            (None, Some(local_cache)) => local_cache.tag_suggestions(&pattern, limit),
+            (None, None) => Ok(Vec::new()),
+        }
+    }
+
+    /// Links the thread to the issue it is about: a GitHub issue or pull request, by URL or as
+    /// `owner/repo#123`, or a Jira issue, by URL or by key.
+    pub async fn link_thread_issue(
+        &self,
+        thread_id: &str,
+        issue: &str,
+    ) -> anyhow::Result<IssueLink> {
+        self.ensure_writable()?;
+        let issue = match &self.issue_commenter {
+            Some(issue_commenter) => issue_commenter.parse(issue)?,
+            None => parse_issue_link(issue, None)?,
+        };
+        match (&self.database_client, &self.local_cache) {
+            (Some(db_client), _) => {
+                self.disrupt_store("link_thread_issue").await?;
+                db_client.link_thread_issue(thread_id, &issue).await?;
+            }
+            (None, Some(local_cache)) => local_cache.link_thread_issue(thread_id, &issue)?,
+            (None, None) => anyhow::bail!("messages aren't being stored"),
+        }
+        Ok(issue)
+    }
+
+    pub async fn unlink_thread_issue(&self, thread_id: &str) -> anyhow::Result<()> {
+        self.ensure_writable()?;
+        match (&self.database_client, &self.local_cache) {
+            (Some(db_client), _) => {
+                self.disrupt_store("unlink_thread_issue").await?;
+                db_client.unlink_thread_issue(thread_id).await
+            }
+            (None, Some(local_cache)) => local_cache.unlink_thread_issue(thread_id),
+            (None, None) => anyhow::bail!("messages aren't being stored"),
+        }
+    }
+
+    pub async fn thread_issue(&self, thread_id: &str) -> anyhow::Result<Option<ThreadIssue>> {
+        match (&self.database_client, &self.local_cache) {
+            (Some(db_client), _) => {
+                self.readable_thread(db_client.as_ref(), thread_id).await?;
+                self.disrupt_store("thread_issue").await?;
+                db_client.thread_issue(thread_id).await
+            }
+            (None, Some(local_cache)) => local_cache.thread_issue(thread_id),
+            (None, None) => Ok(None),
+        }
+    }
+
+    /// Marks the thread's issue resolved, first commenting `summary` on it if the issue commenter
+    /// is set to, so that a comment that failed to post is posted when the thread is resolved
+    /// again. Threads already resolved aren't commented on twice.
+    pub async fn resolve_thread_issue(
+        &self,
+        thread_id: &str,
+        summary: &str,
+    ) -> anyhow::Result<IssueLink> {
+        self.ensure_writable()?;
+        let Some(issue) = self.thread_issue(thread_id).await? else {
+            anyhow::bail!("thread {thread_id} isn't linked to an issue");
+        };
+        if issue.resolved {
+            return Ok(issue.link);
+        }
+        if let Some(issue_commenter) = self
+            .issue_commenter
+            .as_ref()
+            .filter(|issue_commenter| issue_commenter.config().comment_on_resolve)
+        {
+            let comment = issue_commenter.comment(&issue.link, summary)?;
+            issue_commenter.post(comment).await?;
+        }
+        match (&self.database_client, &self.local_cache) {
+            (Some(db_client), _) => {
+                self.disrupt_store("set_thread_issue_resolved").await?;
+                db_client.set_thread_issue_resolved(thread_id, true).await?;
+            }
+            (None, Some(local_cache)) => local_cache.set_thread_issue_resolved(thread_id, true)?,
+            (None, None) => anyhow::bail!("messages aren't being stored"),
+        }
+        Ok(issue.link)
+    }
+
+    async fn first_thread_page(
+        &self,
+        db_client: &StoreClient,
+        limit: usize,
+    ) -> anyhow::Result<Arc<Page<StoredThread, ThreadCursor>>> {
+        let generation = {
+            let cache = self.thread_cache.lock();
+            if let Some(page) = cache.listing(limit) {
+                return Ok(page);
+            }
+            cache.generation()
+        };
+        let page = Arc::new(
+            db_client
+                .list_threads(None, limit, None, ThreadSort::Recent)
+                .await?,
+        );
+        self.thread_cache
+            .lock()
+            .set_listing(generation, limit, page.clone());
+        Ok(page)
+    }
+
+    /// Whether this handler's author may read the stored thread, e.g. to leave it out of a
+    /// listing that spans threads. Threads the store doesn't have aren't readable.
+    async fn can_read_thread(
+        &self,
+        db_client: &StoreClient,
+        thread_id: &str,
+    ) -> anyhow::Result<bool> {
+        self.disrupt_store("get_thread").await?;
+        Ok(db_client
+            .get_thread(thread_id)
+            .await?
+            .is_some_and(|thread| self.authorizer.can_read(&thread, self.author.as_ref())))
+    }
+
+    async fn readable_thread(
+        &self,
+        db_client: &StoreClient,
+        thread_id: &str,
+    ) -> anyhow::Result<StoredThread> {
+        self.disrupt_store("get_thread").await?;
+        let thread = db_client
+            .get_thread(thread_id)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("no stored thread with id {thread_id}"))?;
+        anyhow::ensure!(
+            self.authorizer.can_read(&thread, self.author.as_ref()),
+            "not allowed to read thread {thread_id}"
+        );
+        Ok(thread)
+    }
+
+    /// Resolves once the thread's completion events, up to its latest `Stop`, and the usage of
+    /// the completions that ended, are written: to the store, or to the local cache if the store
+    /// failed. Flush a thread before reading it back, e.g. to export it, to read all of it. Events
+    /// of completions still streaming when this is called may be written after it resolves.
+    pub fn flush(&self, thread_id: &str) -> BoxFuture<'static, FlushAck> {
+        let flush = self.pending_writes.flush(thread_id);
+        async move {
+            let ack = flush.await;
+            log::debug!(
+                "Flushed {} writes of thread {} in {:?}",
+                ack.writes,
+                ack.thread_id,
+                ack.waited
+            );
+            ack
+        }
+        .boxed()
+    }
+
+    /// Re-links the tool results of a thread stored before they were paired with their calls.
+    fn repair_loaded_thread(thread_id: &str, messages: &mut [Message]) {
+        let repair = repair_tool_pairs(messages);
+        if !repair.is_clean() {
+            log::info!("Repaired the tool calls of thread {thread_id} on load: {repair:?}");
+        }
+    }
+
+    /// The messages stored for the thread, if this handler's author may read it, with their tool
+    /// results linked to their calls by [`repair_tool_pairs`].
+    pub async fn load_stored_thread(&self, thread_id: &str) -> anyhow::Result<Vec<Message>> {
+        if let Some(mut messages) = self.local_thread(thread_id)? {
+            Self::repair_loaded_thread(thread_id, &mut messages);
+            return Ok(messages);
+        }
+        let Some(db_client) = &self.database_client else {
+            return Ok(Vec::new());
+        };
+        self.readable_thread(db_client.as_ref(), thread_id).await?;
+        let generation = {
+            let mut cache = self.thread_cache.lock();
+            if let Some(messages) = cache.get(thread_id) {
+                return Ok(messages.as_ref().clone());
+            }
+            cache.generation()
+        };
+        let mut messages = db_client.load_thread(thread_id).await?;
+        Self::repair_loaded_thread(thread_id, &mut messages);
+        self.thread_cache.lock().insert(
+            generation,
+            thread_id.to_string(),
+            Arc::new(messages.clone()),
+        );
+        Ok(messages)
+    }
+
+    /// Starts the thread of `ids` seeded with the stored thread's system prompt and context
+    /// messages, linked back to it, e.g. for a recurring workflow like writing release notes.
+    /// Returns the messages the new thread starts with.
+    pub async fn fork_thread_as_template(
+        &self,
+        thread_id: &str,
+        ids: &RequestIds,
+    ) -> anyhow::Result<Vec<Message>> {
+        let messages = self.load_stored_thread(thread_id).await?;
+        let template = template_messages(&messages, thread_id, ids);
+        anyhow::ensure!(
+            !template.is_empty(),
+            "thread {thread_id} has no system prompt or context to start from"
+        );
+        self.save_append_messages(template.clone(), ids).await?;
+        Ok(template)
+    }
+
+    /// The messages stored for the thread, oldest first, read lazily as the stream is polled,
+    /// if this handler's author may read it. Suited to threads too long to load at once, e.g.
+    /// when exporting or re-embedding them.
+    pub async fn stream_stored_messages(
+        &self,
+        thread_id: &str,
+    ) -> anyhow::Result<BoxStream<'static, anyhow::Result<Message>>> {
+        let Some(db_client) = &self.database_client else {
+            return Ok(futures::stream::empty().boxed());
+        };
+        self.readable_thread(db_client.as_ref(), thread_id).await?;
+        Ok(db_client.stream_messages(thread_id))
+    }
+
+    /// Re-encodes stored checkpoints in the store's configured blob encoding, returning how many
+    /// were rewritten.
+    pub async fn convert_stored_blobs(&self) -> anyhow::Result<usize> {
+        let Some(db_client) = &self.database_client else {
+            return Ok(0);
+        };
+        self.ensure_writable()?;
+        db_client.convert_blob_encoding().await
+    }
+
+    /// A page of the thread's stored messages, oldest first, if this handler's author may read
+    /// it. `limit` counts saved checkpoints, each of which may hold several messages.
+    pub async fn list_stored_messages(
+        &self,
+        thread_id: &str,
+        after_seq: Option<i64>,
+        limit: usize,
+    ) -> anyhow::Result<Page<StoredMessage, i64>> {
+        let Some(db_client) = &self.database_client else {
+            return Ok(Page::default());
+        };
+        self.readable_thread(db_client.as_ref(), thread_id).await?;
+        db_client.list_messages(thread_id, after_seq, limit).await
+    }
+
+    /// Tees the stream so that its events are saved as they are read. Add other observers to
+    /// the returned tee rather than wrapping the stream again.
     pub fn inspect_stream<T>(
         s: T,
         handler: Arc<AiMessageHandler>,
         ids: RequestIds,
         language_model_args: LanguageModelArgs,
-    ) -> T
+    ) -> CompletionStreamTee<T>
     where
         T: Stream<Item = Result<LanguageModelCompletionEvent, LanguageModelCompletionError>>,
     {
-        s.inspect(move |result_ref| {
-            let result = result_ref;
-            let arc = handler.clone();
-            let ids = ids.clone();
-            let language_model_args = language_model_args.clone();
-
-            if let Ok(res) = result {
-                let res = res.clone();
-                smol::spawn(async move {
-                    arc.save_completion_event(&res, &ids, &language_model_args)
-                        .await;
-                })
-                .detach();
-            }
-        })
-        .into_inner()
+        let recorder = handler
+            .response_cache
+            .as_ref()
+            .and_then(|response_cache| response_cache.recorder(&ids.checkpoint_id));
+        let fixture_recorder = handler.fixtures.as_ref().and_then(|fixtures| {
+            fixtures.recorder(&ids.checkpoint_id, &language_model_args.model_id)
+        });
+        let tee = CompletionStreamTee::new(s).with_observer(Arc::new(CompletionPersister {
+            handler,
+            ids,
+            language_model_args,
+            response_text: Mutex::default(),
+            provider_usage: Mutex::default(),
+            outcome: Mutex::default(),
+            error: Mutex::default(),
+            events: AtomicUsize::new(0),
+            started_at: Instant::now(),
+        }));
+        let tee = match recorder {
+            Some(recorder) => tee.with_observer(Arc::new(recorder)),
+            None => tee,
+        };
+        match fixture_recorder {
+            Some(recorder) => tee.with_observer(Arc::new(recorder)),
+            None => tee,
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use futures::FutureExt as _;
     use serde_json::json;
 
     #[test]
@@ -549,6 +4455,67 @@ mod tests {
         assert_eq!(actual, expected);
     }
 
+    #[test]
+    fn test_model_transition() {
+        let handler = AiMessageHandler::new(None);
+        let ids = |checkpoint_id: &str| RequestIds {
+            thread_id: "thread".to_string(),
+            checkpoint_id: checkpoint_id.to_string(),
+            session_id: "session".to_string(),
+            prompt_id: "prompt".to_string(),
+        };
+        let sonnet = LanguageModelArgs::new(LanguageModelId::from("sonnet".to_string()));
+        let gpt = LanguageModelArgs::new(LanguageModelId::from("gpt".to_string()));
+
+        assert!(handler.model_transition(&ids("1"), &sonnet).is_none());
+        assert!(handler.model_transition(&ids("2"), &sonnet).is_none());
+        let Some(Message::System {
+            additional_kwargs, ..
+        }) = handler.model_transition(&ids("3"), &gpt)
+        else {
+            panic!("expected a model transition");
+        };
+        assert_eq!(additional_kwargs["event"], "model_transition");
+        assert_eq!(additional_kwargs["from_model"], "sonnet");
+        assert_eq!(additional_kwargs["to_model"], "gpt");
+        assert_eq!(additional_kwargs["from_checkpoint_id"], "2");
+        assert_eq!(additional_kwargs["to_checkpoint_id"], "3");
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_locked_threads_refuse_other_sessions_writes() {
+        let local_cache = Arc::new(LocalMessageCache::open_test("locked_threads").unwrap());
+        let handler = AiMessageHandler::new(None).with_local_cache(Some(local_cache.clone()));
+        // As the agent sends them, the runs of a stored thread share its session id and differ
+        // in their thread id.
+        let request = |run: &str| LanguageModelRequest {
+            thread_id: Some(run.to_string()),
+            session_id: Some("thread".to_string()),
+            ..Default::default()
+        };
+        let message = |text: &str| Message::Human {
+            content: ContentValue::new(text.to_string()),
+            id: "thread".into(),
+            name: None,
+            example: false,
+            additional_kwargs: HashMap::new(),
+            response_metadata: HashMap::new(),
+        };
+
+        let run = crate::_retrieve_ids(&request("run"));
+        let _lock = handler.thread_locks.acquire(&run).unwrap();
+        smol::block_on(handler.save_append_messages(vec![message("Fix the parser.")], &run))
+            .unwrap();
+        let other_run = crate::_retrieve_ids(&request("other run"));
+        let error = smol::block_on(
+            handler.save_append_messages(vec![message("Fix the lexer.")], &other_run),
+        )
+        .unwrap_err();
+        assert!(error.downcast_ref::<ThreadBusy>().is_some());
+        assert_eq!(local_cache.load_thread(&run.thread_id).unwrap().len(), 1);
+    }
+
     #[test]
     fn test_content_value_serialization() {
         // Test single string content
@@ -572,4 +4539,252 @@ mod tests {
             assert_eq!(s, &vec!["Hello".to_string(), "World".to_string()]);
         }
     }
+
+    #[test]
+    fn test_traffic_is_only_built_for_subscribers() {
+        let traffic = LlmTraffic::default();
+        let event = |kind| TrafficEvent {
+            ids: RequestIds {
+                thread_id: "thread".into(),
+                checkpoint_id: "checkpoint".into(),
+                session_id: "session".into(),
+                prompt_id: "prompt".into(),
+            },
+            model_id: LanguageModelId("model".into()),
+            kind,
+        };
+        traffic.publish(|| unreachable!("built without subscribers"));
+
+        let mut rx = traffic.subscribe();
+        traffic.publish(|| event(TrafficKind::Error("overloaded".into())));
+        let received = rx.try_next().unwrap().unwrap();
+        assert!(matches!(received.kind, TrafficKind::Error(error) if error == "overloaded"));
+
+        drop(rx);
+        traffic.publish(|| unreachable!("built after the subscriber left"));
+        assert!(traffic.subscribers.lock().is_empty());
+    }
+
+    #[test]
+    fn test_writes_are_done_in_the_order_they_were_queued() {
+        let lanes = Arc::new(WriteLanes::default());
+        let written = Arc::new(Mutex::new(Vec::new()));
+        // The first write waits until every write is queued, so that the writes after it would
+        // be done first if they weren't queued behind it.
+        let (release_tx, release_rx) = oneshot::channel::<()>();
+        let mut release_rx = Some(release_rx);
+        for thread_id in ["thread", "other", "thread", "thread"] {
+            let written = written.clone();
+            let release = release_rx.take();
+            lanes.enqueue(thread_id, |position| {
+                async move {
+                    if let Some(release) = release {
+                        release.await.ok();
+                    }
+                    written.lock().push((thread_id, position));
+                }
+                .boxed()
+            });
+        }
+        let done = ["thread", "other"].map(|thread_id| {
+            let (done_tx, done_rx) = oneshot::channel();
+            lanes.enqueue(thread_id, |_| {
+                async move {
+                    done_tx.send(()).ok();
+                }
+                .boxed()
+            });
+            done_rx
+        });
+        release_tx.send(()).ok();
+        smol::block_on(futures::future::join_all(done));
+
+        let thread_writes = written
+            .lock()
+            .iter()
+            .filter(|(thread_id, _)| *thread_id == "thread")
+            .map(|(_, position)| *position)
+            .collect::<Vec<_>>();
+        assert_eq!(thread_writes, [0, 1, 2]);
+        let other_writes = written
+            .lock()
+            .iter()
+            .filter(|(thread_id, _)| *thread_id == "other")
+            .map(|(_, position)| *position)
+            .collect::<Vec<_>>();
+        assert_eq!(other_writes, [0]);
+
+        let mut message = Message::System {
+            content: ContentValue::new("token_usage".to_string()),
+            id: "thread".into(),
+            name: None,
+            example: false,
+            additional_kwargs: Default::default(),
+            response_metadata: Default::default(),
+        };
+        assert_eq!(message_write_lane(&message), None);
+        stamp_write_lane(std::slice::from_mut(&mut message), "checkpoint", 2);
+        assert_eq!(
+            message_write_lane(&message),
+            Some(LaneSlot {
+                stream: "checkpoint".into(),
+                position: 2
+            })
+        );
+    }
+
+    #[test]
+    fn test_invalid_tool_calls_against_declared_schemas() {
+        let schema = ToolSchema {
+            name: "read_file".into(),
+            description: "Reads a file.".into(),
+            input_schema: serde_json::json!({
+                "type": "object",
+                "properties": { "path": { "type": "string" } },
+                "required": ["path"],
+            }),
+        };
+        let reordered = ToolSchema {
+            input_schema: serde_json::json!({
+                "required": ["path"],
+                "properties": { "path": { "type": "string" } },
+                "type": "object",
+            }),
+            ..schema.clone()
+        };
+        assert_eq!(schema.hash(), reordered.hash());
+
+        let tool_uses = serde_json::json!([
+            { "ToolUse": {
+                "id": "valid",
+                "name": "read_file",
+                "raw_input": "",
+                "input": { "path": "src/main.rs" },
+                "is_input_complete": true
+            }},
+            { "ToolUse": {
+                "id": "invalid",
+                "name": "read_file",
+                "raw_input": "",
+                "input": { "file": "src/main.rs" },
+                "is_input_complete": true
+            }}
+        ]);
+        let message = |seq| StoredMessage {
+            seq,
+            index: 0,
+            message: Message::Ai {
+                content: ContentValue::new(tool_uses.to_string()),
+                id: "thread".into(),
+                name: None,
+                example: false,
+                invalid_tool_calls: None,
+                tool_calls: None,
+                additional_kwargs: Default::default(),
+                response_metadata: std::collections::HashMap::from_iter([(
+                    REQUEST_TOOL_SCHEMAS.to_string(),
+                    schema_hashes(std::slice::from_ref(&schema)),
+                )]),
+            },
+        };
+        let schemas = collections::HashMap::from_iter([(schema.hash(), schema)]);
+        let invalid = invalid_tool_calls(&[message(1), message(2)], &schemas);
+        assert_eq!(
+            invalid
+                .iter()
+                .map(|call| (call.seq, call.tool_use_id.as_str()))
+                .collect::<Vec<_>>(),
+            [(1, "invalid")]
+        );
+    }
+
+    #[test]
+    fn test_tool_call_validation() {
+        let schema = ToolSchema {
+            name: "read_file".into(),
+            description: "Reads a file.".into(),
+            input_schema: serde_json::json!({
+                "type": "object",
+                "properties": { "path": { "type": "string" } },
+                "required": ["path"],
+            }),
+        };
+        let mut args = LanguageModelArgs::new(crate::LanguageModelId::from("model".to_string()))
+            .with_provider("anthropic");
+        args.tool_schemas = vec![schema.clone()];
+        let ids = RequestIds {
+            thread_id: "thread".into(),
+            checkpoint_id: "checkpoint".into(),
+            session_id: "session".into(),
+            prompt_id: "prompt".into(),
+        };
+        let tool_use = |name: &str, input| LanguageModelToolUse {
+            id: "tool".into(),
+            name: name.into(),
+            raw_input: String::new(),
+            input,
+            is_input_complete: true,
+        };
+
+        let valid = ToolCallValidation::new(
+            &tool_use("read_file", serde_json::json!({ "path": "src/main.rs" })),
+            &ids,
+            &args,
+        );
+        assert!(valid.is_valid());
+        assert_eq!(valid.schema_hash, Some(schema.hash()));
+        assert_eq!(valid.provider.as_deref(), Some("anthropic"));
+
+        let invalid = ToolCallValidation::new(
+            &tool_use("read_file", serde_json::json!({ "file": "src/main.rs" })),
+            &ids,
+            &args,
+        );
+        assert!(!invalid.is_valid());
+
+        let undeclared = ToolCallValidation::new(
+            &tool_use("delete_file", serde_json::json!({ "path": "src/main.rs" })),
+            &ids,
+            &args,
+        );
+        assert!(!undeclared.is_valid());
+        assert_eq!(undeclared.schema_hash, None);
+    }
+
+    #[test]
+    fn test_host_only_skips_guests() {
+        let origin = |is_host| RequestOrigin {
+            peer_id: "1/2".into(),
+            is_host,
+        };
+        let host_only = CollaborationPersistence::HostOnly;
+        assert!(host_only.persists(None));
+        assert!(host_only.persists(Some(&origin(true))));
+        assert!(!host_only.persists(Some(&origin(false))));
+        assert!(CollaborationPersistence::TagPeer.persists(Some(&origin(false))));
+    }
+
+    #[test]
+    fn test_name_for() {
+        let identity = AgentIdentity {
+            by_intent: collections::HashMap::from_iter([(
+                "InlineAssist".into(),
+                "ZedInlineAssist".into(),
+            )]),
+            by_project: collections::HashMap::from_iter([("zed".into(), "ZedRepoAgent".into())]),
+            ..Default::default()
+        };
+        assert_eq!(
+            identity.name_for(Some("InlineAssist"), Some("zed")),
+            "ZedInlineAssist"
+        );
+        assert_eq!(
+            identity.name_for(Some("UserPrompt"), Some("zed")),
+            "ZedRepoAgent"
+        );
+        assert_eq!(
+            identity.name_for(Some("UserPrompt"), None),
+            DEFAULT_AGENT_NAME
+        );
+    }
 }
@@ -0,0 +1,531 @@
+//! Stand-ins for the backends left out of the build, so that the handler compiles without any
+//! database dependency.
+
+#[cfg(not(feature = "postgres"))]
+pub use database::NoopDatabaseClient;
+#[cfg(not(feature = "sqlite"))]
+pub use local_cache::LocalMessageCache;
+#[cfg(not(feature = "sqlite"))]
+pub(crate) use local_cache::REPLICATION_BATCH_SIZE;
+
+#[cfg(not(feature = "postgres"))]
+mod database {
+    use anyhow::{Result, bail};
+    use chrono::NaiveDate;
+    use futures::StreamExt as _;
+    use futures::stream::BoxStream;
+    use std::time::Duration;
+
+    use crate::message_handler::{
+        BlobEncoding, CheckpointPartitioning, DatabaseClient, ExperimentMetric, FileSnapshot,
+        GarbageCollection, InvalidToolCallThread, IssueLink, LockOverride, MaintenanceReport,
+        Message, MessageAuthor, Page, PartitionMaintenance, PromptCacheStats, PromptCacheUsage,
+        PromptExperimentAssignment, PromptTemplate, PromptTemplateRef, RawExchange,
+        RecordedInstructions, RequestUsageRecord, SamplingDecision, SessionEnvironment,
+        StoreLockHolder, StoredMessage, StoredThread, TagSuggestion, ThreadCursor, ThreadFlag,
+        ThreadInstructions, ThreadIssue, ThreadSort, ToolCallValidation, ToolSchema,
+        UsageBreakdown, UsageDimension, UsageHistogramRow, VariantStats,
+    };
+    use crate::{FanOutVerdict, RequestIds};
+
+    /// The store of builds without a backend: it can't be connected to, so messages are only
+    /// cached locally, and stores nothing if constructed some other way.
+    pub struct NoopDatabaseClient;
+
+    impl NoopDatabaseClient {
+        pub async fn new(_connection_string: &str) -> Result<Self> {
+            bail!("built without a message store backend, e.g. the `postgres` feature")
+        }
+
+        pub async fn new_read_only(connection_string: &str) -> Result<Self> {
+            Self::new(connection_string).await
+        }
+
+        pub fn with_blob_encoding(self, _blob_encoding: BlobEncoding) -> Self {
+            self
+        }
+
+        pub async fn convert_blob_encoding(&self) -> Result<usize> {
+            Ok(0)
+        }
+
+        pub async fn smoke_test(&self, _read_only: bool) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    impl DatabaseClient for NoopDatabaseClient {
+        async fn save_append_messages(
+            &self,
+            _message: Vec<Message>,
+            _ids: &RequestIds,
+            _author: Option<&MessageAuthor>,
+        ) -> Result<()> {
+            Ok(())
+        }
+
+        async fn list_threads(
+            &self,
+            _after: Option<&ThreadCursor>,
+            _limit: usize,
+            _git_branch: Option<&str>,
+            _sort: ThreadSort,
+        ) -> Result<Page<StoredThread, ThreadCursor>> {
+            Ok(Page::default())
+        }
+
+        async fn set_thread_flag(
+            &self,
+            _thread_id: &str,
+            _flag: ThreadFlag,
+            _value: bool,
+        ) -> Result<()> {
+            Ok(())
+        }
+
+        async fn add_thread_tag(&self, _thread_id: &str, _tag: &str) -> Result<()> {
+            Ok(())
+        }
+
+        async fn remove_thread_tag(&self, _thread_id: &str, _tag: &str) -> Result<()> {
+            Ok(())
+        }
+
+        async fn thread_tags(&self, _thread_id: &str) -> Result<Vec<String>> {
+            Ok(Vec::new())
+        }
+
+        async fn tag_suggestions(
+            &self,
+            _pattern: &str,
+            _limit: usize,
+        ) -> Result<Vec<TagSuggestion>> {
+            Ok(Vec::new())
+        }
+
+        async fn link_thread_issue(&self, _thread_id: &str, _issue: &IssueLink) -> Result<()> {
+            Ok(())
+        }
+
+        async fn unlink_thread_issue(&self, _thread_id: &str) -> Result<()> {
+            Ok(())
+        }
+
+        async fn thread_issue(&self, _thread_id: &str) -> Result<Option<ThreadIssue>> {
+            Ok(None)
+        }
+
+        async fn set_thread_issue_resolved(&self, _thread_id: &str, _resolved: bool) -> Result<()> {
+            Ok(())
+        }
+
+        async fn get_thread(&self, _thread_id: &str) -> Result<Option<StoredThread>> {
+            Ok(None)
+        }
+
+        async fn list_messages(
+            &self,
+            _thread_id: &str,
+            _after_seq: Option<i64>,
+            _limit: usize,
+        ) -> Result<Page<StoredMessage, i64>> {
+            Ok(Page::default())
+        }
+
+        async fn load_thread(&self, _thread_id: &str) -> Result<Vec<Message>> {
+            Ok(Vec::new())
+        }
+
+        async fn stored_token_count(&self, _thread_id: &str) -> Result<i64> {
+            Ok(0)
+        }
+
+        async fn uncompacted_messages(&self, _thread_id: &str) -> Result<Vec<StoredMessage>> {
+            Ok(Vec::new())
+        }
+
+        async fn compact_thread(
+            &self,
+            _summary: Message,
+            _ids: &RequestIds,
+            _author: Option<&MessageAuthor>,
+            _through_seq: i64,
+            _through_sequence: Option<u64>,
+        ) -> Result<u64> {
+            Ok(0)
+        }
+
+        fn stream_messages(&self, _thread_id: &str) -> BoxStream<'static, Result<Message>> {
+            futures::stream::empty().boxed()
+        }
+
+        async fn register_prompt_template(
+            &self,
+            id: &str,
+            _source: &str,
+        ) -> Result<PromptTemplateRef> {
+            Ok(PromptTemplateRef {
+                id: id.to_string(),
+                version: 0,
+            })
+        }
+
+        async fn prompt_template_versions(&self, _id: &str) -> Result<Vec<PromptTemplate>> {
+            Ok(Vec::new())
+        }
+
+        async fn record_experiment_outcome(
+            &self,
+            _assignment: &PromptExperimentAssignment,
+            _thread_id: &str,
+            _metric: ExperimentMetric,
+            _value: f64,
+        ) -> Result<()> {
+            Ok(())
+        }
+
+        async fn experiment_results(&self, _experiment_id: &str) -> Result<Vec<VariantStats>> {
+            Ok(Vec::new())
+        }
+
+        async fn record_prompt_cache_usage(
+            &self,
+            _ids: &RequestIds,
+            _usage: &PromptCacheUsage,
+        ) -> Result<()> {
+            Ok(())
+        }
+
+        async fn prompt_cache_stats(&self) -> Result<Vec<PromptCacheStats>> {
+            Ok(Vec::new())
+        }
+
+        async fn record_request_usage(
+            &self,
+            _ids: &RequestIds,
+            _usage: &RequestUsageRecord,
+        ) -> Result<()> {
+            Ok(())
+        }
+
+        async fn usage_breakdown(
+            &self,
+            _dimension: UsageDimension,
+            _since: NaiveDate,
+        ) -> Result<Vec<UsageBreakdown>> {
+            Ok(Vec::new())
+        }
+
+        async fn usage_histogram(&self, _day: NaiveDate) -> Result<Vec<UsageHistogramRow>> {
+            Ok(Vec::new())
+        }
+
+        async fn save_raw_exchange(
+            &self,
+            _ids: &RequestIds,
+            _exchange: &RawExchange,
+        ) -> Result<()> {
+            Ok(())
+        }
+
+        async fn add_checkpoint_reference(
+            &self,
+            _thread_id: &str,
+            _checkpoint_id: &str,
+            _referrer: &str,
+        ) -> Result<()> {
+            Ok(())
+        }
+
+        async fn remove_checkpoint_reference(
+            &self,
+            _thread_id: &str,
+            _checkpoint_id: &str,
+            _referrer: &str,
+        ) -> Result<()> {
+            Ok(())
+        }
+
+        async fn collect_garbage(&self, _retention: Duration) -> Result<GarbageCollection> {
+            Ok(GarbageCollection::default())
+        }
+
+        async fn maintain_partitions(
+            &self,
+            _partitioning: &CheckpointPartitioning,
+        ) -> Result<PartitionMaintenance> {
+            Ok(PartitionMaintenance::default())
+        }
+
+        async fn maintenance_report(&self) -> Result<MaintenanceReport> {
+            Ok(MaintenanceReport::default())
+        }
+
+        async fn run_maintenance(&self, _statement: &str) -> Result<()> {
+            Ok(())
+        }
+
+        async fn save_session_env(
+            &self,
+            _session_id: &str,
+            _env: &SessionEnvironment,
+        ) -> Result<()> {
+            Ok(())
+        }
+
+        async fn save_sampling_decision(
+            &self,
+            _session_id: &str,
+            _thread_id: &str,
+            _decision: &SamplingDecision,
+        ) -> Result<()> {
+            Ok(())
+        }
+
+        async fn session_env(&self, _session_id: &str) -> Result<Option<SessionEnvironment>> {
+            Ok(None)
+        }
+
+        async fn save_file_snapshots(
+            &self,
+            _thread_id: &str,
+            _snapshots: &[FileSnapshot],
+        ) -> Result<()> {
+            Ok(())
+        }
+
+        async fn file_snapshots(&self, _thread_id: &str) -> Result<Vec<FileSnapshot>> {
+            Ok(Vec::new())
+        }
+
+        async fn threads_referencing_file(
+            &self,
+            _path: &str,
+            _limit: usize,
+        ) -> Result<Vec<String>> {
+            Ok(Vec::new())
+        }
+
+        async fn save_commit_thread(&self, _sha: &str, _thread_id: &str) -> Result<()> {
+            Ok(())
+        }
+
+        async fn commit_thread(&self, _sha: &str) -> Result<Option<String>> {
+            Ok(None)
+        }
+
+        async fn acquire_store_thread_lock(
+            &self,
+            _thread_id: &str,
+            _session_id: &str,
+            _author: Option<&MessageAuthor>,
+        ) -> Result<Option<StoreLockHolder>> {
+            Ok(None)
+        }
+
+        async fn release_store_thread_lock(
+            &self,
+            _thread_id: &str,
+            _session_id: &str,
+        ) -> Result<()> {
+            Ok(())
+        }
+
+        async fn store_thread_lock_holders(
+            &self,
+            _thread_ids: &[String],
+        ) -> Result<Vec<StoreLockHolder>> {
+            Ok(Vec::new())
+        }
+
+        async fn override_store_thread_lock(
+            &self,
+            _thread_id: &str,
+            _action: LockOverride,
+            _author: Option<&MessageAuthor>,
+        ) -> Result<Option<StoreLockHolder>> {
+            Ok(None)
+        }
+
+        async fn save_tool_schemas(&self, _schemas: &[ToolSchema]) -> Result<()> {
+            Ok(())
+        }
+
+        async fn tool_schemas(&self, _hashes: &[String]) -> Result<Vec<ToolSchema>> {
+            Ok(Vec::new())
+        }
+
+        async fn save_tool_call_validation(&self, _validation: &ToolCallValidation) -> Result<()> {
+            Ok(())
+        }
+
+        async fn threads_with_invalid_tool_calls(
+            &self,
+            _provider: Option<&str>,
+            _limit: usize,
+        ) -> Result<Vec<InvalidToolCallThread>> {
+            Ok(Vec::new())
+        }
+
+        async fn save_thread_instructions(
+            &self,
+            _thread_id: &str,
+            _instructions: &ThreadInstructions,
+        ) -> Result<()> {
+            Ok(())
+        }
+
+        async fn thread_instructions(&self, _thread_id: &str) -> Result<Vec<RecordedInstructions>> {
+            Ok(Vec::new())
+        }
+
+        async fn save_run_result(&self, _result: &RunResult) -> Result<()> {
+            Ok(())
+        }
+
+        async fn run_results(&self, _limit: usize) -> Result<Vec<RunResult>> {
+            Ok(Vec::new())
+        }
+
+        async fn save_fan_out_verdict(&self, _verdict: &FanOutVerdict) -> Result<()> {
+            Ok(())
+        }
+
+        async fn fan_out_verdict(&self, _group_id: &str) -> Result<Option<FanOutVerdict>> {
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(not(feature = "sqlite"))]
+mod local_cache {
+    use anyhow::{Result, bail};
+    use std::path::Path;
+
+    use crate::RequestIds;
+    use crate::message_handler::{
+        IssueLink, Message, MessageAuthor, Page, StoredThread, TagSuggestion, ThreadCursor,
+        ThreadFlag, ThreadIssue, ThreadSort,
+    };
+
+    pub(crate) const REPLICATION_BATCH_SIZE: usize = 64;
+
+    /// The local cache of builds without the `sqlite` feature, which can't be opened.
+    pub struct LocalMessageCache;
+
+    /// Never constructed, since nothing is cached.
+    #[allow(dead_code)]
+    pub(crate) struct PendingCheckpoint {
+        pub id: i64,
+        pub ids: RequestIds,
+        pub author: Option<MessageAuthor>,
+        pub messages: Vec<Message>,
+    }
+
+    impl LocalMessageCache {
+        pub fn open(_path: &Path) -> Result<Self> {
+            bail!("built without the `sqlite` feature")
+        }
+
+        pub(crate) fn append(
+            &self,
+            _messages: &[Message],
+            _ids: &RequestIds,
+            _author: Option<&MessageAuthor>,
+        ) -> Result<i64> {
+            bail!("built without the `sqlite` feature")
+        }
+
+        pub(crate) fn mark_replicated(&self, _id: i64) -> Result<()> {
+            Ok(())
+        }
+
+        pub(crate) fn pending(
+            &self,
+            _thread_id: Option<&str>,
+            _after: i64,
+            _limit: usize,
+        ) -> Result<Vec<PendingCheckpoint>> {
+            Ok(Vec::new())
+        }
+
+        pub(crate) fn has_pending(&self, _thread_id: &str) -> Result<bool> {
+            Ok(false)
+        }
+
+        pub(crate) fn is_complete(&self, _thread_id: &str) -> Result<Option<bool>> {
+            Ok(None)
+        }
+
+        pub(crate) fn set_complete(&self, _thread_id: &str, _complete: bool) -> Result<()> {
+            Ok(())
+        }
+
+        pub(crate) fn get_thread(&self, _thread_id: &str) -> Result<Option<StoredThread>> {
+            Ok(None)
+        }
+
+        pub(crate) fn set_thread_flag(
+            &self,
+            _thread_id: &str,
+            _flag: ThreadFlag,
+            _value: bool,
+        ) -> Result<()> {
+            Ok(())
+        }
+
+        pub(crate) fn add_thread_tag(&self, _thread_id: &str, _tag: &str) -> Result<()> {
+            Ok(())
+        }
+
+        pub(crate) fn remove_thread_tag(&self, _thread_id: &str, _tag: &str) -> Result<()> {
+            Ok(())
+        }
+
+        pub(crate) fn thread_tags(&self, _thread_id: &str) -> Result<Vec<String>> {
+            Ok(Vec::new())
+        }
+
+        pub(crate) fn tag_suggestions(
+            &self,
+            _pattern: &str,
+            _limit: usize,
+        ) -> Result<Vec<TagSuggestion>> {
+            Ok(Vec::new())
+        }
+
+        pub(crate) fn link_thread_issue(&self, _thread_id: &str, _issue: &IssueLink) -> Result<()> {
+            Ok(())
+        }
+
+        pub(crate) fn unlink_thread_issue(&self, _thread_id: &str) -> Result<()> {
+            Ok(())
+        }
+
+        pub(crate) fn thread_issue(&self, _thread_id: &str) -> Result<Option<ThreadIssue>> {
+            Ok(None)
+        }
+
+        pub(crate) fn set_thread_issue_resolved(
+            &self,
+            _thread_id: &str,
+            _resolved: bool,
+        ) -> Result<()> {
+            Ok(())
+        }
+
+        pub(crate) fn list_threads(
+            &self,
+            _after: Option<&ThreadCursor>,
+            _limit: usize,
+            _git_branch: Option<&str>,
+            _sort: ThreadSort,
+        ) -> Result<Page<StoredThread, ThreadCursor>> {
+            Ok(Page::default())
+        }
+
+        pub(crate) fn load_thread(&self, _thread_id: &str) -> Result<Vec<Message>> {
+            Ok(Vec::new())
+        }
+    }
+}
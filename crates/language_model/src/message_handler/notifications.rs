@@ -0,0 +1,250 @@
+use anyhow::{Result, anyhow};
+use collections::{HashMap, HashSet};
+use futures::AsyncReadExt;
+use http_client::{AsyncBody, HttpClient, Method, Request as HttpRequest};
+use parking_lot::Mutex;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::sync::Arc;
+
+use crate::StopReason;
+
+/// How a completion ended, for rules to match.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CompletionOutcome {
+    EndTurn,
+    MaxTokens,
+    ToolUse,
+    Refusal,
+    /// The completion failed.
+    Error,
+    /// The user cancelled the completion before it ended.
+    Cancelled,
+}
+
+impl From<StopReason> for CompletionOutcome {
+    fn from(reason: StopReason) -> Self {
+        match reason {
+            StopReason::EndTurn => CompletionOutcome::EndTurn,
+            StopReason::MaxTokens => CompletionOutcome::MaxTokens,
+            StopReason::ToolUse => CompletionOutcome::ToolUse,
+            StopReason::Refusal => CompletionOutcome::Refusal,
+        }
+    }
+}
+
+/// The stored threads a notification is sent about. A thread matches once it meets every
+/// criterion the rule sets, and is only notified about once per rule.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct NotificationRule {
+    /// Names the rule in the notifications.
+    pub name: String,
+    /// How one of the thread's completions must have ended, if set.
+    pub outcomes: Vec<CompletionOutcome>,
+    /// How many tokens the thread's completions must have used together, if set.
+    pub min_tokens: Option<i64>,
+    /// Tags the thread must have one of, ignoring case, if set.
+    pub tags: Vec<String>,
+}
+
+impl NotificationRule {
+    fn is_empty(&self) -> bool {
+        self.outcomes.is_empty() && self.min_tokens.is_none() && self.tags.is_empty()
+    }
+
+    fn matches(&self, thread: &ThreadActivity, facts: &ThreadFacts) -> bool {
+        !self.is_empty()
+            && (self.outcomes.is_empty()
+                || facts
+                    .outcome
+                    .is_some_and(|outcome| self.outcomes.contains(&outcome)))
+            && self
+                .min_tokens
+                .is_none_or(|min_tokens| thread.tokens >= min_tokens)
+            && (self.tags.is_empty()
+                || self.tags.iter().any(|tag| {
+                    facts
+                        .tags
+                        .iter()
+                        .any(|thread_tag| thread_tag.eq_ignore_ascii_case(tag))
+                }))
+    }
+}
+
+/// Where notifications about stored threads are posted, e.g. a Slack incoming webhook, and
+/// which threads they are sent about.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct NotificationPolicy {
+    pub webhook_url: String,
+    pub rules: Vec<NotificationRule>,
+}
+
+/// What is known about a thread when it is checked against the rules.
+#[derive(Debug, Clone, Default)]
+pub struct ThreadFacts {
+    pub thread_id: String,
+    pub model_id: Option<String>,
+    pub project: Option<String>,
+    /// How the completion that just ended ended, if one did.
+    pub outcome: Option<CompletionOutcome>,
+    pub tags: Vec<String>,
+}
+
+#[derive(Default)]
+struct ThreadActivity {
+    tokens: i64,
+    /// The rules already notified about.
+    notified: HashSet<String>,
+}
+
+/// Posts a summary of each stored thread that matches one of the policy's rules to its webhook.
+pub struct ThreadNotifier {
+    http_client: Arc<dyn HttpClient>,
+    policy: NotificationPolicy,
+    threads: Mutex<HashMap<String, ThreadActivity>>,
+}
+
+impl ThreadNotifier {
+    pub fn new(http_client: Arc<dyn HttpClient>, policy: NotificationPolicy) -> Self {
+        Self {
+            http_client,
+            policy,
+            threads: Mutex::default(),
+        }
+    }
+
+    pub fn policy(&self) -> &NotificationPolicy {
+        &self.policy
+    }
+
+    /// Whether any rule looks at tags, so that they're only read when they matter.
+    pub fn needs_tags(&self) -> bool {
+        self.policy.rules.iter().any(|rule| !rule.tags.is_empty())
+    }
+
+    /// Adds the tokens a completion of the thread used.
+    pub fn record_tokens(&self, thread_id: &str, tokens: i64) {
+        self.threads
+            .lock()
+            .entry(thread_id.to_string())
+            .or_default()
+            .tokens += tokens;
+    }
+
+    /// The notifications to post about the thread: one for each rule it now matches that it
+    /// wasn't notified about.
+    pub fn notifications(&self, facts: &ThreadFacts) -> Vec<Value> {
+        let mut threads = self.threads.lock();
+        let thread = threads.entry(facts.thread_id.clone()).or_default();
+        let due = self
+            .policy
+            .rules
+            .iter()
+            .filter(|rule| !thread.notified.contains(&rule.name) && rule.matches(thread, facts))
+            .collect::<Vec<_>>();
+        let notifications = due
+            .iter()
+            .map(|rule| slack_message(rule, thread.tokens, facts))
+            .collect();
+        thread
+            .notified
+            .extend(due.into_iter().map(|rule| rule.name.clone()));
+        notifications
+    }
+
+    pub async fn send(&self, notification: Value) -> Result<()> {
+        let request = HttpRequest::builder()
+            .method(Method::POST)
+            .uri(&self.policy.webhook_url)
+            .header("Content-Type", "application/json")
+            .body(AsyncBody::from(serde_json::to_string(&notification)?))?;
+        let mut response = self.http_client.send(request).await?;
+        if response.status().is_success() {
+            return Ok(());
+        }
+        let mut body = String::new();
+        response.body_mut().read_to_string(&mut body).await?;
+        Err(anyhow!(
+            "the webhook responded with {}: {body}",
+            response.status()
+        ))
+    }
+}
+
+/// A Slack message summarizing the thread. Other webhooks receive the same JSON, whose `text`
+/// holds the whole summary.
+fn slack_message(rule: &NotificationRule, tokens: i64, facts: &ThreadFacts) -> Value {
+    let mut fields = vec![format!("*Thread:* `{}`", facts.thread_id)];
+    if let Some(model_id) = &facts.model_id {
+        fields.push(format!("*Model:* {model_id}"));
+    }
+    if let Some(project) = facts.project.as_ref().filter(|project| !project.is_empty()) {
+        fields.push(format!("*Project:* {project}"));
+    }
+    if let Some(outcome) = facts.outcome {
+        fields.push(format!("*Ended with:* {outcome:?}"));
+    }
+    fields.push(format!("*Tokens:* {tokens}"));
+    if !facts.tags.is_empty() {
+        fields.push(format!("*Tags:* {}", facts.tags.join(", ")));
+    }
+    json!({
+        "text": format!("Stored thread matched `{}`\n{}", rule.name, fields.join("\n")),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_client::FakeHttpClient;
+
+    #[test]
+    fn test_threads_are_notified_once_per_matching_rule() {
+        let notifier = ThreadNotifier::new(
+            FakeHttpClient::with_404_response(),
+            NotificationPolicy {
+                webhook_url: "https://hooks.slack.com/services/T/B/X".to_string(),
+                rules: vec![
+                    NotificationRule {
+                        name: "errors".to_string(),
+                        outcomes: vec![CompletionOutcome::Error, CompletionOutcome::Refusal],
+                        ..NotificationRule::default()
+                    },
+                    NotificationRule {
+                        name: "expensive billing".to_string(),
+                        min_tokens: Some(1000),
+                        tags: vec!["billing".to_string()],
+                        ..NotificationRule::default()
+                    },
+                    NotificationRule {
+                        name: "everything".to_string(),
+                        ..NotificationRule::default()
+                    },
+                ],
+            },
+        );
+        let mut facts = ThreadFacts {
+            thread_id: "thread".to_string(),
+            outcome: Some(CompletionOutcome::EndTurn),
+            tags: vec!["Billing".to_string()],
+            ..ThreadFacts::default()
+        };
+        notifier.record_tokens("thread", 600);
+        assert!(notifier.notifications(&facts).is_empty());
+
+        notifier.record_tokens("thread", 600);
+        let notifications = notifier.notifications(&facts);
+        assert_eq!(notifications.len(), 1);
+        let text = notifications[0]["text"].as_str().unwrap();
+        assert!(text.contains("`expensive billing`"));
+        assert!(text.contains("*Tokens:* 1200"));
+
+        facts.outcome = Some(CompletionOutcome::Error);
+        assert_eq!(notifier.notifications(&facts).len(), 1);
+        assert!(notifier.notifications(&facts).is_empty());
+    }
+}
@@ -0,0 +1,120 @@
+use serde::{Deserialize, Serialize};
+
+use super::{Message, StoredThread};
+
+/// One page of a listing, with the cursor to pass back for the next one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Page<T, C> {
+    pub items: Vec<T>,
+    /// `None` once the listing is exhausted.
+    pub next: Option<C>,
+}
+
+impl<T, C> Default for Page<T, C> {
+    fn default() -> Self {
+        Self {
+            items: Vec::new(),
+            next: None,
+        }
+    }
+}
+
+impl<T, C> Page<T, C> {
+    /// Builds a page from rows fetched with `limit`, pointing `next` after the last row if the
+    /// page came back full.
+    #[cfg_attr(not(any(feature = "postgres", feature = "sqlite")), allow(dead_code))]
+    pub(crate) fn from_rows(items: Vec<T>, limit: usize, cursor: impl Fn(&T) -> C) -> Self {
+        let next = if items.len() == limit {
+            items.last().map(cursor)
+        } else {
+            None
+        };
+        Self { items, next }
+    }
+}
+
+/// Where a listing of threads continues from.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ThreadCursor {
+    pub updated_at: String,
+    pub thread_id: String,
+    /// The flags of the last thread listed, for listings sorted by them.
+    #[serde(default)]
+    pub pinned: bool,
+    #[serde(default)]
+    pub starred: bool,
+}
+
+impl ThreadCursor {
+    /// The cursor that continues a listing after the thread.
+    #[cfg_attr(not(any(feature = "postgres", feature = "sqlite")), allow(dead_code))]
+    pub(crate) fn after(thread: &StoredThread) -> Self {
+        Self {
+            updated_at: thread.updated_at.clone(),
+            thread_id: thread.thread_id.clone(),
+            pinned: thread.pinned,
+            starred: thread.starred,
+        }
+    }
+}
+
+/// Where a listing of the history continues from. The threads the local cache has are listed
+/// first, then those only the remote store has.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HistoryCursor {
+    Local(ThreadCursor),
+    /// `None` at the start of the remote store's threads.
+    Remote(Option<ThreadCursor>),
+}
+
+/// How a listing of threads is ordered.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ThreadSort {
+    /// Most recently updated first.
+    #[default]
+    Recent,
+    /// Pinned threads first, then starred ones, each most recently updated first.
+    Flagged,
+}
+
+/// A flag the user sets on a stored thread to find it again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ThreadFlag {
+    Pinned,
+    Starred,
+}
+
+impl ThreadFlag {
+    /// The column the flag is stored in.
+    pub(crate) fn column(&self) -> &'static str {
+        match self {
+            ThreadFlag::Pinned => "pinned",
+            ThreadFlag::Starred => "starred",
+        }
+    }
+}
+
+/// A stored message with its position in the thread. Messages saved for the same request share
+/// its checkpoint's `seq`, and are told apart by `index`.
+#[derive(Debug, Clone)]
+pub struct StoredMessage {
+    pub seq: i64,
+    pub index: usize,
+    pub message: Message,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_cursor_only_for_full_pages() {
+        let full = Page::from_rows(vec![1, 2, 3], 3, |item| *item);
+        assert_eq!(full.next, Some(3));
+        let partial = Page::from_rows(vec![1, 2], 3, |item| *item);
+        assert_eq!(partial.next, None);
+    }
+}
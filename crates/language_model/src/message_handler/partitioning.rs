@@ -0,0 +1,129 @@
+use chrono::{Datelike as _, Months, NaiveDate};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+const PARTITION_PREFIX: &str = "ide_checkpoints_";
+
+/// How the checkpoints table is partitioned by the month its checkpoints were written in, so
+/// that long-lived stores don't grow one unbounded table.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct CheckpointPartitioning {
+    /// How many months' partitions are created ahead of the current one.
+    pub months_ahead: u32,
+    /// How many months' partitions, counting the current one, stay attached. Older ones are
+    /// detached into tables of their own, out of the store's reads. Kept attached when unset.
+    pub detach_after_months: Option<u32>,
+}
+
+impl Default for CheckpointPartitioning {
+    fn default() -> Self {
+        Self {
+            months_ahead: 1,
+            detach_after_months: None,
+        }
+    }
+}
+
+/// What a run of partition maintenance changed, by partition name.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PartitionMaintenance {
+    pub created: Vec<String>,
+    pub detached: Vec<String>,
+}
+
+pub(crate) fn month_start(day: NaiveDate) -> NaiveDate {
+    day.with_day(1).unwrap_or(day)
+}
+
+/// The partition holding the checkpoints written in the month, e.g. `ide_checkpoints_y2025m06`.
+pub(crate) fn partition_name(month: NaiveDate) -> String {
+    format!(
+        "{PARTITION_PREFIX}y{:04}m{:02}",
+        month.year(),
+        month.month()
+    )
+}
+
+fn partition_month(name: &str) -> Option<NaiveDate> {
+    let date = name.strip_prefix(PARTITION_PREFIX)?.strip_prefix('y')?;
+    let (year, month) = date.split_once('m')?;
+    NaiveDate::from_ymd_opt(year.parse().ok()?, month.parse().ok()?, 1)
+}
+
+/// The range of the month's partition, as `FOR VALUES FROM (..) TO (..)` bounds.
+pub(crate) fn partition_bounds(month: NaiveDate) -> (NaiveDate, NaiveDate) {
+    let month = month_start(month);
+    (month, month + Months::new(1))
+}
+
+/// The months whose partitions should exist on `today`: the current one and those ahead.
+pub(crate) fn months_to_create(
+    today: NaiveDate,
+    partitioning: &CheckpointPartitioning,
+) -> Vec<NaiveDate> {
+    let current = month_start(today);
+    (0..=partitioning.months_ahead)
+        .map(|ahead| current + Months::new(ahead))
+        .collect()
+}
+
+/// The attached partitions, by name, that are old enough to detach on `today`.
+pub(crate) fn partitions_to_detach<'a>(
+    attached: &'a [String],
+    today: NaiveDate,
+    partitioning: &CheckpointPartitioning,
+) -> Vec<&'a String> {
+    let Some(kept_months) = partitioning.detach_after_months else {
+        return Vec::new();
+    };
+    let oldest_kept = month_start(today) - Months::new(kept_months.saturating_sub(1));
+    attached
+        .iter()
+        .filter(|name| partition_month(name).is_some_and(|month| month < oldest_kept))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_partitions_by_month() {
+        let today = NaiveDate::from_ymd_opt(2025, 12, 14).unwrap();
+        let partitioning = CheckpointPartitioning {
+            months_ahead: 1,
+            detach_after_months: Some(3),
+        };
+        let months = months_to_create(today, &partitioning);
+        assert_eq!(
+            months
+                .iter()
+                .map(|month| partition_name(*month))
+                .collect::<Vec<_>>(),
+            ["ide_checkpoints_y2025m12", "ide_checkpoints_y2026m01"]
+        );
+        assert_eq!(
+            partition_bounds(months[1]),
+            (
+                NaiveDate::from_ymd_opt(2026, 1, 1).unwrap(),
+                NaiveDate::from_ymd_opt(2026, 2, 1).unwrap()
+            )
+        );
+
+        let attached = [
+            "ide_checkpoints_y2025m08".to_string(),
+            "ide_checkpoints_y2025m09".to_string(),
+            "ide_checkpoints_y2025m10".to_string(),
+            "ide_checkpoints_y2025m12".to_string(),
+            "ide_checkpoints_archive".to_string(),
+        ];
+        assert_eq!(
+            partitions_to_detach(&attached, today, &partitioning),
+            [&attached[0], &attached[1]]
+        );
+        assert!(
+            partitions_to_detach(&attached, today, &CheckpointPartitioning::default()).is_empty()
+        );
+    }
+}
@@ -0,0 +1,123 @@
+use futures::FutureExt as _;
+use futures::channel::oneshot;
+use futures::future::{BoxFuture, Shared, join_all};
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Resolves once its write is done, whether or not it succeeded.
+type WriteDone = Shared<oneshot::Receiver<()>>;
+
+/// The writes of each thread's completion events still in flight, so that callers can wait for
+/// a thread's events to be written before reading them back.
+#[derive(Default)]
+pub(crate) struct PendingWrites {
+    next_id: AtomicU64,
+    writes: Mutex<HashMap<String, Vec<(u64, WriteDone)>>>,
+}
+
+/// A write in flight, done when dropped.
+pub(crate) struct PendingWrite {
+    writes: Arc<PendingWrites>,
+    thread_id: String,
+    id: u64,
+    _done: oneshot::Sender<()>,
+}
+
+impl Drop for PendingWrite {
+    fn drop(&mut self) {
+        let mut writes = self.writes.writes.lock();
+        if let Some(thread_writes) = writes.get_mut(&self.thread_id) {
+            thread_writes.retain(|(id, _)| *id != self.id);
+            if thread_writes.is_empty() {
+                writes.remove(&self.thread_id);
+            }
+        }
+    }
+}
+
+/// What a flush waited for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlushAck {
+    pub thread_id: String,
+    /// How many of the thread's writes were still in flight when the flush started.
+    pub writes: usize,
+    pub waited: Duration,
+}
+
+impl PendingWrites {
+    /// Tracks a write of the thread's, until the returned guard is dropped.
+    pub(crate) fn begin(self: &Arc<Self>, thread_id: &str) -> PendingWrite {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let (done, done_rx) = oneshot::channel();
+        self.writes
+            .lock()
+            .entry(thread_id.to_string())
+            .or_default()
+            .push((id, done_rx.shared()));
+        PendingWrite {
+            writes: self.clone(),
+            thread_id: thread_id.to_string(),
+            id,
+            _done: done,
+        }
+    }
+
+    /// Resolves once the thread's writes in flight now are done. Writes begun later aren't
+    /// waited for.
+    pub(crate) fn flush(&self, thread_id: &str) -> BoxFuture<'static, FlushAck> {
+        let started_at = Instant::now();
+        let in_flight = self
+            .writes
+            .lock()
+            .get(thread_id)
+            .map(|writes| {
+                writes
+                    .iter()
+                    .map(|(_, done)| done.clone())
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        let thread_id = thread_id.to_string();
+        async move {
+            let writes = in_flight.len();
+            join_all(in_flight).await;
+            FlushAck {
+                thread_id,
+                writes,
+                waited: started_at.elapsed(),
+            }
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flush_waits_for_the_writes_in_flight() {
+        let writes = Arc::new(PendingWrites::default());
+        let first = writes.begin("thread");
+        let second = writes.begin("thread");
+        let _other_thread = writes.begin("other");
+
+        let mut flush = writes.flush("thread");
+        let _later = writes.begin("thread");
+        assert!((&mut flush).now_or_never().is_none());
+        drop(first);
+        assert!((&mut flush).now_or_never().is_none());
+        drop(second);
+        let ack = flush.now_or_never().unwrap();
+        assert_eq!(ack.thread_id, "thread");
+        assert_eq!(ack.writes, 2);
+
+        assert_eq!(
+            writes.flush("idle").now_or_never().map(|ack| ack.writes),
+            Some(0)
+        );
+    }
+}
@@ -0,0 +1,205 @@
+use futures::channel::mpsc;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+
+use super::RequestIds;
+use super::message_rules::FilteredMessages;
+
+/// Which of a message's writes an acknowledgment is about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PersistedPart {
+    /// The request sent for the message.
+    Request,
+    /// The completion of that request: its events, errors and usage.
+    Completion,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PersistenceStatus {
+    /// Some of the writes are still in flight.
+    Pending,
+    /// All of the writes are in the store, or in the local cache to be replicated.
+    Persisted,
+    /// A write failed, with this error, and wasn't retried since.
+    Failed(String),
+}
+
+/// Where the writes of one part of a message stand.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PersistenceAck {
+    /// The thread the message is stored in.
+    pub thread_id: String,
+    /// The id the sender gave the message, from [`crate::LanguageModelRequest::message_id`].
+    pub message_id: String,
+    pub part: PersistedPart,
+    pub status: PersistenceStatus,
+}
+
+/// A write that failed, kept to be retried.
+pub(crate) struct FailedWrite {
+    pub part: PersistedPart,
+    pub messages: FilteredMessages,
+    pub ids: RequestIds,
+}
+
+type AckKey = (String, String, PersistedPart);
+
+#[derive(Default)]
+struct AckState {
+    in_flight: usize,
+    failed: Vec<(FailedWrite, String)>,
+}
+
+impl AckState {
+    fn status(&self) -> PersistenceStatus {
+        if self.in_flight > 0 {
+            PersistenceStatus::Pending
+        } else if let Some((_, error)) = self.failed.last() {
+            PersistenceStatus::Failed(error.clone())
+        } else {
+            PersistenceStatus::Persisted
+        }
+    }
+}
+
+/// Acknowledges the writes of each message to whoever sent it, so that it can show which of its
+/// messages are stored, and keeps the writes that failed until they're retried.
+#[derive(Default)]
+pub struct PersistenceAcks {
+    subscribers: Mutex<Vec<mpsc::UnboundedSender<PersistenceAck>>>,
+    writes: Mutex<HashMap<AckKey, AckState>>,
+}
+
+impl PersistenceAcks {
+    /// Receives the acknowledgments from now on, until the receiver is dropped.
+    pub fn subscribe(&self) -> mpsc::UnboundedReceiver<PersistenceAck> {
+        let (tx, rx) = mpsc::unbounded();
+        self.subscribers.lock().push(tx);
+        rx
+    }
+
+    fn publish(&self, (thread_id, message_id, part): &AckKey, status: PersistenceStatus) {
+        let mut subscribers = self.subscribers.lock();
+        subscribers.retain(|subscriber| !subscriber.is_closed());
+        for subscriber in subscribers.iter() {
+            subscriber
+                .unbounded_send(PersistenceAck {
+                    thread_id: thread_id.clone(),
+                    message_id: message_id.clone(),
+                    part: *part,
+                    status: status.clone(),
+                })
+                .ok();
+        }
+    }
+
+    /// Applies the change to the part's state, publishing its status if that changed. Parts
+    /// whose writes are all persisted are forgotten.
+    fn update(&self, key: AckKey, change: impl FnOnce(&mut AckState)) {
+        let mut writes = self.writes.lock();
+        let state = writes.entry(key.clone()).or_default();
+        let before = state.status();
+        change(state);
+        let after = state.status();
+        if after == PersistenceStatus::Persisted {
+            writes.remove(&key);
+        }
+        drop(writes);
+        if after != before {
+            self.publish(&key, after);
+        }
+    }
+
+    pub(crate) fn begin(&self, thread_id: &str, message_id: &str, part: PersistedPart) {
+        let key = (thread_id.to_string(), message_id.to_string(), part);
+        self.update(key, |state| state.in_flight += 1);
+    }
+
+    /// Ends a write begun with [`Self::begin`], keeping it to be retried if it failed.
+    pub(crate) fn finish(
+        &self,
+        thread_id: &str,
+        message_id: &str,
+        part: PersistedPart,
+        failure: Option<(FailedWrite, String)>,
+    ) {
+        let key = (thread_id.to_string(), message_id.to_string(), part);
+        self.update(key, |state| {
+            state.in_flight = state.in_flight.saturating_sub(1);
+            state.failed.extend(failure);
+        });
+    }
+
+    /// Takes the failed writes of the message's parts, to write them again.
+    pub(crate) fn take_failed(&self, thread_id: &str, message_id: &str) -> Vec<FailedWrite> {
+        let mut writes = self.writes.lock();
+        [PersistedPart::Request, PersistedPart::Completion]
+            .into_iter()
+            .filter_map(|part| {
+                writes.get_mut(&(thread_id.to_string(), message_id.to_string(), part))
+            })
+            .flat_map(|state| std::mem::take(&mut state.failed))
+            .map(|(write, _)| write)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids() -> RequestIds {
+        RequestIds {
+            thread_id: "thread".into(),
+            checkpoint_id: "checkpoint".into(),
+            session_id: "session".into(),
+            prompt_id: "prompt".into(),
+        }
+    }
+
+    #[test]
+    fn test_acks_follow_the_writes_of_each_part() {
+        let acks = PersistenceAcks::default();
+        let mut subscriber = acks.subscribe();
+        let mut received = || {
+            std::iter::from_fn(|| subscriber.try_next().ok().flatten())
+                .map(|ack| (ack.part, ack.status))
+                .collect::<Vec<_>>()
+        };
+
+        acks.begin("thread", "1", PersistedPart::Request);
+        acks.begin("thread", "1", PersistedPart::Completion);
+        acks.begin("thread", "1", PersistedPart::Completion);
+        acks.finish("thread", "1", PersistedPart::Request, None);
+        acks.finish("thread", "1", PersistedPart::Completion, None);
+        let failed = FailedWrite {
+            part: PersistedPart::Completion,
+            messages: FilteredMessages::new(None, Vec::new()),
+            ids: ids(),
+        };
+        acks.finish(
+            "thread",
+            "1",
+            PersistedPart::Completion,
+            Some((failed, "store is down".into())),
+        );
+        assert_eq!(
+            received(),
+            [
+                (PersistedPart::Request, PersistenceStatus::Pending),
+                (PersistedPart::Completion, PersistenceStatus::Pending),
+                (PersistedPart::Request, PersistenceStatus::Persisted),
+                (
+                    PersistedPart::Completion,
+                    PersistenceStatus::Failed("store is down".into())
+                ),
+            ]
+        );
+
+        let retried = acks.take_failed("thread", "1");
+        assert_eq!(retried.len(), 1);
+        assert_eq!(retried[0].part, PersistedPart::Completion);
+        assert!(acks.take_failed("thread", "1").is_empty());
+        assert!(acks.take_failed("other", "1").is_empty());
+    }
+}
@@ -0,0 +1,152 @@
+use bitflags::bitflags;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{LanguageModelCompletionEvent, LanguageModelRequestMessage, MessageContent};
+
+bitflags! {
+    /// The parts of completions the handler writes to the store, so that users can keep, e.g.,
+    /// the model's thinking or the output of their tools out of it. Everything is stored by
+    /// default.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct PersistenceFeatures: u8 {
+        /// The messages requests are sent with.
+        const PERSIST_REQUESTS = 1 << 0;
+        /// The text, tool calls and stops of completions.
+        const PERSIST_RESPONSES = 1 << 1;
+        /// The thinking of completions, and of the assistant messages requests are sent with.
+        const PERSIST_THINKING = 1 << 2;
+        /// The results of tool calls in the messages requests are sent with.
+        const PERSIST_TOOL_RESULTS = 1 << 3;
+        /// The token usage of completions.
+        const PERSIST_USAGE = 1 << 4;
+    }
+}
+
+impl Default for PersistenceFeatures {
+    fn default() -> Self {
+        Self::all()
+    }
+}
+
+impl PersistenceFeatures {
+    /// Whether the completion event is stored.
+    pub fn persists_event(&self, event: &LanguageModelCompletionEvent) -> bool {
+        match event {
+            LanguageModelCompletionEvent::Thinking { .. } => self.contains(Self::PERSIST_THINKING),
+            LanguageModelCompletionEvent::UsageUpdate(_) => self.contains(Self::PERSIST_USAGE),
+            _ => self.contains(Self::PERSIST_RESPONSES),
+        }
+    }
+
+    /// The message as it is stored, without the content that isn't, or `None` when none of it
+    /// is.
+    pub fn request_message(
+        &self,
+        message: &LanguageModelRequestMessage,
+    ) -> Option<LanguageModelRequestMessage> {
+        if !self.contains(Self::PERSIST_REQUESTS) {
+            return None;
+        }
+        let content = message
+            .content
+            .iter()
+            .filter(|content| match content {
+                MessageContent::Thinking { .. } | MessageContent::RedactedThinking(_) => {
+                    self.contains(Self::PERSIST_THINKING)
+                }
+                MessageContent::ToolResult(_) => self.contains(Self::PERSIST_TOOL_RESULTS),
+                _ => true,
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        if content.is_empty() && !message.content.is_empty() {
+            return None;
+        }
+        Some(LanguageModelRequestMessage {
+            content,
+            ..message.clone()
+        })
+    }
+}
+
+/// Which parts of completions are stored, as configured. Parts left unset are stored.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct PersistenceFeaturesContent {
+    pub persist_requests: Option<bool>,
+    pub persist_responses: Option<bool>,
+    pub persist_thinking: Option<bool>,
+    pub persist_tool_results: Option<bool>,
+    pub persist_usage: Option<bool>,
+}
+
+impl From<PersistenceFeaturesContent> for PersistenceFeatures {
+    fn from(content: PersistenceFeaturesContent) -> Self {
+        let mut features = Self::all();
+        for (enabled, feature) in [
+            (content.persist_requests, Self::PERSIST_REQUESTS),
+            (content.persist_responses, Self::PERSIST_RESPONSES),
+            (content.persist_thinking, Self::PERSIST_THINKING),
+            (content.persist_tool_results, Self::PERSIST_TOOL_RESULTS),
+            (content.persist_usage, Self::PERSIST_USAGE),
+        ] {
+            features.set(feature, enabled.unwrap_or(true));
+        }
+        features
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{LanguageModelToolResult, LanguageModelToolResultContent, Role};
+
+    #[test]
+    fn test_only_enabled_parts_are_persisted() {
+        let features = PersistenceFeatures::from(PersistenceFeaturesContent {
+            persist_thinking: Some(false),
+            persist_tool_results: Some(false),
+            ..Default::default()
+        });
+        assert!(
+            !features.persists_event(&LanguageModelCompletionEvent::Thinking {
+                text: "Let me check the parser.".into(),
+                signature: None,
+            })
+        );
+        assert!(features.persists_event(&LanguageModelCompletionEvent::Text("Done.".into())));
+
+        let message = LanguageModelRequestMessage {
+            role: Role::Assistant,
+            content: vec![
+                MessageContent::Thinking {
+                    text: "Let me check the parser.".into(),
+                    signature: None,
+                },
+                MessageContent::Text("Reading it now.".into()),
+            ],
+            cache: false,
+        };
+        assert_eq!(
+            features.request_message(&message).unwrap().content,
+            [MessageContent::Text("Reading it now.".into())]
+        );
+
+        let tool_result = LanguageModelRequestMessage {
+            role: Role::User,
+            content: vec![MessageContent::ToolResult(LanguageModelToolResult {
+                tool_use_id: "tool".into(),
+                tool_name: "read_file".into(),
+                is_error: false,
+                content: LanguageModelToolResultContent::Text("fn main() {}".into()),
+                output: None,
+            })],
+            cache: false,
+        };
+        assert_eq!(features.request_message(&tool_result), None);
+        assert_eq!(
+            PersistenceFeatures::default().request_message(&tool_result),
+            Some(tool_result)
+        );
+    }
+}
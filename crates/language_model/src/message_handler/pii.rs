@@ -0,0 +1,156 @@
+use regex::Regex;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::sync::LazyLock;
+
+use super::{ContentValue, Message};
+
+/// The `additional_kwargs` entry listing the categories of personal data found in a message.
+pub const PII_CATEGORIES: &str = "pii_categories";
+
+/// A category of personal data a message's content may hold.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum PiiCategory {
+    Email,
+    /// A person's name, where the text introduces or signs with one.
+    Name,
+    /// An API key, token, password or private key.
+    Key,
+}
+
+impl PiiCategory {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PiiCategory::Email => "email",
+            PiiCategory::Name => "name",
+            PiiCategory::Key => "key",
+        }
+    }
+}
+
+static EMAIL: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\b[A-Za-z0-9._%+-]+@[A-Za-z0-9-]+(?:\.[A-Za-z0-9-]+)*\.[A-Za-z]{2,}\b").unwrap()
+});
+
+/// Names are only recognized where the text says it is one, since telling a name from any other
+/// capitalized word takes more than a regex.
+static NAME: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?:(?i:my name is|i am called|regards,|sincerely,|thanks,|cheers,)\s*)[A-Z][a-z]+(?:[ \t]+[A-Z][a-z]+)*",
+    )
+    .unwrap()
+});
+
+static KEY: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(concat!(
+        r"\bsk-[A-Za-z0-9_-]{16,}",
+        r"|\bAKIA[0-9A-Z]{16}\b",
+        r"|\bgh[pousr]_[A-Za-z0-9]{36}\b",
+        r"|\bxox[abprs]-[A-Za-z0-9-]{10,}",
+        r"|-----BEGIN [A-Z ]*PRIVATE KEY-----",
+        r#"|(?i:api[_-]?key|secret|token|password)["']?\s*[:=]\s*["']?[^\s"']{8,}"#,
+    ))
+    .unwrap()
+});
+
+/// The categories of personal data the text holds.
+pub fn classify_pii(text: &str) -> BTreeSet<PiiCategory> {
+    [
+        (PiiCategory::Email, &*EMAIL),
+        (PiiCategory::Name, &*NAME),
+        (PiiCategory::Key, &*KEY),
+    ]
+    .into_iter()
+    .filter(|(_, pattern)| pattern.is_match(text))
+    .map(|(category, _)| category)
+    .collect()
+}
+
+/// Lists the categories of personal data found in each message's content under
+/// [`PII_CATEGORIES`], so that the messages holding them can be found, and purged, later.
+/// Messages without any are left untagged.
+pub(crate) fn tag_pii(messages: &mut [Message]) {
+    for message in messages {
+        let categories = match message.content() {
+            ContentValue::Single(text) => classify_pii(text),
+            ContentValue::Multiple(texts) => {
+                texts.iter().flat_map(|text| classify_pii(text)).collect()
+            }
+        };
+        if categories.is_empty() {
+            continue;
+        }
+        message.additional_kwargs_mut().insert(
+            PII_CATEGORIES.to_string(),
+            serde_json::Value::from(
+                categories
+                    .iter()
+                    .map(PiiCategory::as_str)
+                    .collect::<Vec<_>>(),
+            ),
+        );
+    }
+}
+
+/// The categories the messages were tagged with, for the checkpoint storing them.
+pub(crate) fn stored_pii_categories(messages: &[Message]) -> Vec<String> {
+    messages
+        .iter()
+        .filter_map(|message| message.additional_kwargs().get(PII_CATEGORIES)?.as_array())
+        .flatten()
+        .filter_map(|category| category.as_str())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .map(str::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn message(content: &str) -> Message {
+        Message::Human {
+            content: ContentValue::new(content.into()),
+            id: "thread".into(),
+            name: None,
+            example: false,
+            additional_kwargs: HashMap::new(),
+            response_metadata: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_messages_are_tagged_with_their_pii() {
+        assert_eq!(
+            classify_pii("Mail ada@example.com, my name is Ada Lovelace"),
+            BTreeSet::from([PiiCategory::Email, PiiCategory::Name])
+        );
+        assert_eq!(
+            classify_pii("export OPENAI_API_KEY=sk-abcdefghijklmnop1234"),
+            BTreeSet::from([PiiCategory::Key])
+        );
+        assert_eq!(
+            classify_pii("fn main() { let name = User::new(); }"),
+            BTreeSet::new()
+        );
+
+        let mut messages = vec![
+            message("Thanks, Grace Hopper"),
+            message("Refactor the parser"),
+            message("password: hunter2hunter2 for ops@example.org"),
+        ];
+        tag_pii(&mut messages);
+        assert_eq!(
+            messages[0].additional_kwargs().get(PII_CATEGORIES),
+            Some(&serde_json::json!(["name"]))
+        );
+        assert_eq!(messages[1].additional_kwargs().get(PII_CATEGORIES), None);
+        assert_eq!(stored_pii_categories(&messages), ["email", "key", "name"]);
+    }
+}
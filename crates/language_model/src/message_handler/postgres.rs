@@ -1,37 +1,246 @@
-use crate::RequestIds;
-use crate::message_handler::{DatabaseClient, Message};
-use anyhow::Result;
-use chrono::Utc;
-use sqlx::{Connection, Executor, PgConnection, PgPool, postgres::PgPoolOptions};
+use crate::message_handler::blob_encoding::{blob_encoding, decode_blob, encode_blob};
+use crate::message_handler::compaction::stored_token_count;
+use crate::message_handler::content_blobs::{
+    CONTENT_BLOB_MIN_LEN, extract_content_blobs, referenced_content_blobs, resolve_content_blobs,
+};
+use crate::message_handler::garbage_collection::{
+    CollectedCheckpoint, GarbageCollection, unreachable_checkpoints,
+};
+use crate::message_handler::idempotency::write_idempotency_key;
+use crate::message_handler::issue_links::thread_issue;
+use crate::message_handler::partitioning::{
+    months_to_create, partition_bounds, partition_name, partitions_to_detach,
+};
+use crate::message_handler::pii::stored_pii_categories;
+use crate::message_handler::schema_version::{deserialize_messages, serialize_messages};
+use crate::message_handler::sequencing::{sort_by_sequence, stamp_sequence};
+use crate::message_handler::{
+    BlobEncoding, CheckpointPartitioning, DatabaseClient, ExperimentMetric, FileSnapshot,
+    IndexUsage, InvalidToolCallThread, IssueLink, LockOverride, MaintenanceReport, Message,
+    MessageAuthor, Page, PartitionMaintenance, PromptCacheStats, PromptCacheUsage,
+    PromptExperimentAssignment, PromptTemplate, PromptTemplateRef, RawExchange,
+    RecordedInstructions, RequestUsageRecord, RunResult, RunStatus, STORE_LOCK_LAPSE_MINUTES,
+    SamplingDecision, SessionEnvironment, StoreLockHolder, StoredMessage, StoredThread,
+    TOKEN_BUCKET_BOUNDS, TableHealth, TagSuggestion, ThreadCursor, ThreadFlag, ThreadInstructions,
+    ThreadIssue, ThreadSort, ToolCallValidation, ToolSchema, UsageBreakdown, UsageDimension,
+    UsageHistogramRow, VariantStats, messages_git_branch,
+};
+use crate::{FanOutVerdict, RequestIds};
+use anyhow::{Context as _, Result};
+use chrono::{DateTime, NaiveDate, Utc};
+use collections::HashSet;
+use futures::StreamExt as _;
+use futures::stream::{self, BoxStream};
+use sqlx::{
+    Connection, Executor, PgConnection, PgPool, Postgres, Transaction, postgres::PgPoolOptions,
+};
+use std::collections::VecDeque;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Duration;
 
 /// A PostgreSQL implementation of the DatabaseClient trait
 pub struct PostgresDatabaseClient {
     pool: Option<Arc<PgPool>>,
+    blob_encoding: BlobEncoding,
+    /// Whether `ide_checkpoints` is partitioned by month, which keys checkpoints by their month
+    /// too.
+    partitioned: AtomicBool,
 }
 
 impl PostgresDatabaseClient {
     /// Creates a new PostgreSQL database client
     pub async fn new(connection_string: &str) -> Result<Self> {
+        Self::connect(connection_string, false).await
+    }
+
+    /// Creates a client that can only read the store: its sessions refuse writes, and the schema
+    /// is left as it is.
+    pub async fn new_read_only(connection_string: &str) -> Result<Self> {
+        Self::connect(connection_string, true).await
+    }
+
+    async fn connect(connection_string: &str, read_only: bool) -> Result<Self> {
         log::info!("Connecting to postgres.");
 
         let pool = PgPoolOptions::new()
             .max_connections(5)
             .acquire_timeout(Duration::from_secs(3))
+            .after_connect(move |conn, _| {
+                Box::pin(async move {
+                    if read_only {
+                        conn.execute("SET default_transaction_read_only = on")
+                            .await?;
+                    }
+                    Ok(())
+                })
+            })
             .connect(connection_string)
             .await?;
 
-        log::info!("Connected to postgres... initializing schema");
+        if read_only {
+            log::info!("Connected to postgres read-only.");
+        } else {
+            log::info!("Connected to postgres... initializing schema");
 
-        // Ensure tables exist
-        Self::initialize_schema(&pool).await?;
+            // Ensure tables exist
+            Self::initialize_schema(&pool).await?;
 
-        log::info!("Initialized schema.");
+            log::info!("Initialized schema.");
+        }
 
-        Ok(Self {
+        let (partitioned,): (bool,) = sqlx::query_as(
+            r#"
+                SELECT EXISTS (
+                    SELECT 1 FROM pg_partitioned_table
+                    WHERE partrelid = to_regclass('ide_checkpoints')
+                )
+                "#,
+        )
+        .fetch_one(&pool)
+        .await?;
+        let client = Self {
             pool: Some(Arc::new(pool)),
-        })
+            blob_encoding: BlobEncoding::default(),
+            partitioned: AtomicBool::new(partitioned),
+        };
+        // Writes fail without a partition for the current month, so it is created whether or not
+        // partitioning is still configured.
+        if partitioned && !read_only {
+            client.create_partitions(&Default::default()).await?;
+        }
+        Ok(client)
+    }
+
+    /// The columns checkpoints are upserted on.
+    fn checkpoint_key(&self) -> &'static str {
+        if self.partitioned.load(Ordering::SeqCst) {
+            "(thread_id, checkpoint_id, checkpoint_month)"
+        } else {
+            "(thread_id, checkpoint_id)"
+        }
+    }
+
+    /// Moves the checkpoints into a table partitioned by the month they were written in, keyed
+    /// by their month too, so that a checkpoint written to across months has a row in each.
+    /// Checkpoints without a timestamp predate them, and go in the oldest month's partition.
+    async fn partition_by_month(&self) -> Result<()> {
+        let pool = self.pool()?;
+        let mut transaction = pool.begin().await?;
+        let (seq,): (Option<String>,) =
+            sqlx::query_as("SELECT pg_get_serial_sequence('ide_checkpoints', 'seq')")
+                .fetch_one(&mut *transaction)
+                .await?;
+        sqlx::raw_sql(
+            r#"
+                ALTER TABLE ide_checkpoints RENAME TO ide_checkpoints_unpartitioned;
+                CREATE TABLE ide_checkpoints
+                (
+                    LIKE ide_checkpoints_unpartitioned INCLUDING DEFAULTS,
+                    checkpoint_month date default date_trunc('month', now())::date not null,
+                    primary key (thread_id, checkpoint_id, checkpoint_month)
+                ) PARTITION BY RANGE (checkpoint_month);
+                "#,
+        )
+        .execute(&mut *transaction)
+        .await?;
+        if let Some(seq) = &seq {
+            sqlx::raw_sql(&format!(
+                "ALTER SEQUENCE {seq} OWNED BY ide_checkpoints.seq"
+            ))
+            .execute(&mut *transaction)
+            .await?;
+        }
+        const MONTH: &str = r#"
+            date_trunc('month', coalesce(
+                NULLIF(o.checkpoint_ts, '')::timestamptz,
+                (SELECT min(NULLIF(checkpoint_ts, '')::timestamptz) FROM ide_checkpoints_unpartitioned),
+                now()
+            ))::date
+            "#;
+        let months: Vec<(NaiveDate,)> = sqlx::query_as(&format!(
+            "SELECT DISTINCT {MONTH} FROM ide_checkpoints_unpartitioned o"
+        ))
+        .fetch_all(&mut *transaction)
+        .await?;
+        for (month,) in months {
+            Self::create_partition(&mut transaction, month).await?;
+        }
+        sqlx::query(&format!(
+            r#"
+                INSERT INTO ide_checkpoints
+                SELECT o.*, {MONTH}
+                FROM ide_checkpoints_unpartitioned o
+                "#
+        ))
+        .execute(&mut *transaction)
+        .await?;
+        sqlx::raw_sql("DROP TABLE ide_checkpoints_unpartitioned")
+            .execute(&mut *transaction)
+            .await?;
+        transaction.commit().await?;
+        self.partitioned.store(true, Ordering::SeqCst);
+        // The indexes went with the unpartitioned table.
+        Self::initialize_schema(pool).await
+    }
+
+    async fn create_partition(
+        transaction: &mut Transaction<'_, Postgres>,
+        month: NaiveDate,
+    ) -> Result<()> {
+        let (from, to) = partition_bounds(month);
+        sqlx::raw_sql(&format!(
+            r#"
+                CREATE TABLE IF NOT EXISTS {}
+                PARTITION OF ide_checkpoints FOR VALUES FROM ('{from}') TO ('{to}')
+                "#,
+            partition_name(month)
+        ))
+        .execute(&mut **transaction)
+        .await?;
+        Ok(())
+    }
+
+    /// The partitions attached to `ide_checkpoints`, by name.
+    async fn attached_partitions(&self) -> Result<Vec<String>> {
+        let rows: Vec<(String,)> = sqlx::query_as(
+            r#"
+                SELECT c.relname::text
+                FROM pg_inherits i
+                JOIN pg_class c ON c.oid = i.inhrelid
+                WHERE i.inhparent = 'ide_checkpoints'::regclass
+                ORDER BY c.relname
+                "#,
+        )
+        .fetch_all(self.pool()?)
+        .await?;
+        Ok(rows.into_iter().map(|(name,)| name).collect())
+    }
+
+    /// Creates the partitions of the current month and those ahead that don't exist yet,
+    /// returning their names.
+    async fn create_partitions(
+        &self,
+        partitioning: &CheckpointPartitioning,
+    ) -> Result<Vec<String>> {
+        let attached = self.attached_partitions().await?;
+        let mut created = Vec::new();
+        let mut transaction = self.pool()?.begin().await?;
+        for month in months_to_create(Utc::now().date_naive(), partitioning) {
+            let name = partition_name(month);
+            if !attached.contains(&name) {
+                Self::create_partition(&mut transaction, month).await?;
+                created.push(name);
+            }
+        }
+        transaction.commit().await?;
+        Ok(created)
+    }
+
+    /// Sets how new checkpoints are encoded.
+    pub fn with_blob_encoding(mut self, blob_encoding: BlobEncoding) -> Self {
+        self.blob_encoding = blob_encoding;
+        self
     }
 
     /// Initialize the database schema if it doesn't exist
@@ -54,6 +263,346 @@ create index if not exists  ide_checkpoints_thread_id_idx
     on ide_checkpoints (thread_id);
 create index if not exists  ide_checkpoints_thread_id_checkpoint_id_idx
     on ide_checkpoints (thread_id, checkpoint_id);
+
+-- Stores shared by a team attribute each checkpoint to whoever sent it.
+alter table ide_checkpoints add column if not exists author_id text default ''::text not null;
+alter table ide_checkpoints add column if not exists author_name text default ''::text not null;
+create index if not exists  ide_checkpoints_author_id_idx
+    on ide_checkpoints (author_id);
+
+-- Lets shared stores scope what each reader sees by project.
+alter table ide_checkpoints add column if not exists project text default ''::text not null;
+create index if not exists  ide_checkpoints_project_idx
+    on ide_checkpoints (project);
+
+-- Orders a thread's checkpoints for paging through its messages.
+alter table ide_checkpoints add column if not exists seq bigserial;
+create index if not exists  ide_checkpoints_thread_id_seq_idx
+    on ide_checkpoints (thread_id, seq);
+create index if not exists  ide_checkpoints_checkpoint_ts_idx
+    on ide_checkpoints (checkpoint_ts);
+
+-- The number the next message of each thread is stored with. Messages are numbered as they are
+-- stored, under the thread's row, so that they are numbered in the order their writes commit in
+-- whichever machine wrote them.
+create table if not exists  thread_sequences
+(
+    thread_id text primary key,
+    next      bigint not null
+);
+
+-- Tokens counted in each checkpoint, and whether a context summary stands in for it when the
+-- thread is resumed.
+alter table ide_checkpoints add column if not exists token_count bigint default 0 not null;
+alter table ide_checkpoints add column if not exists compacted boolean default false not null;
+
+-- The categories of personal data found in each checkpoint's messages, e.g. `email` or `key`,
+-- to find the checkpoints to purge of them.
+alter table ide_checkpoints add column if not exists pii_categories text[] default '{}' not null;
+create index if not exists  ide_checkpoints_pii_categories_idx
+    on ide_checkpoints using gin (pii_categories);
+
+-- The writes each checkpoint has had, by idempotency key, so that a retried or replayed write
+-- isn't appended twice.
+create table if not exists  ide_checkpoint_writes
+(
+    idempotency_key text                       not null primary key,
+    thread_id       text                       not null,
+    written_at      timestamptz default now()  not null
+);
+create index if not exists  ide_checkpoint_writes_thread_id_idx
+    on ide_checkpoint_writes (thread_id);
+
+-- Long strings in checkpoint blobs, usually file contents, stored once and referenced by hash.
+create table if not exists  content_blobs
+(
+    hash       text                       not null primary key,
+    content    text                       not null,
+    created_at timestamptz default now()  not null
+);
+
+create table if not exists  prompt_templates
+(
+    template_id text                       not null,
+    version     integer                    not null,
+    source      text                       not null,
+    created_at  timestamptz default now()  not null,
+    primary key (template_id, version)
+);
+
+create table if not exists  prompt_experiment_outcomes
+(
+    experiment_id text                       not null,
+    variant       text                       not null,
+    thread_id     text                       not null,
+    metric        text                       not null,
+    value         double precision           not null,
+    recorded_at   timestamptz default now()  not null
+);
+
+create index if not exists  prompt_experiment_outcomes_experiment_id_idx
+    on prompt_experiment_outcomes (experiment_id);
+
+-- What each completion's prompt did with the provider's prompt cache, by template version.
+create table if not exists  prompt_cache_usage
+(
+    thread_id             text                       not null,
+    checkpoint_id         text                       not null,
+    template_id           text default ''::text      not null,
+    template_version      integer,
+    input_tokens          bigint                     not null,
+    cache_read_tokens     bigint                     not null,
+    cache_creation_tokens bigint                     not null,
+    recorded_at           timestamptz default now()  not null,
+    primary key (thread_id, checkpoint_id)
+);
+create index if not exists  prompt_cache_usage_template_idx
+    on prompt_cache_usage (template_id, template_version);
+
+-- What each completion used, by the language and project area of the buffer its request was
+-- made from, and the feature it was made from. Kept when its checkpoint is garbage collected,
+-- as the history of usage.
+create table if not exists  request_usage
+(
+    thread_id     text                       not null,
+    checkpoint_id text                       not null,
+    model_id      text                       not null,
+    language      text,
+    area          text,
+    input_tokens  bigint                     not null,
+    output_tokens bigint                     not null,
+    recorded_at   timestamptz default now()  not null,
+    primary key (thread_id, checkpoint_id)
+);
+create index if not exists  request_usage_recorded_at_idx
+    on request_usage (recorded_at);
+alter table request_usage add column if not exists source text;
+
+-- The HTTP exchanges completions were requested with, with credentials scrubbed, while they
+-- are captured to debug providers.
+create table if not exists  raw_exchanges
+(
+    thread_id        text                       not null,
+    checkpoint_id    text                       not null,
+    method           text                       not null,
+    url              text                       not null,
+    request_headers  jsonb                      not null,
+    request_body     text                       not null,
+    status           integer,
+    response_headers jsonb                      not null,
+    response_body    text                       not null,
+    error            text,
+    captured_at      timestamptz default now()  not null
+);
+create index if not exists  raw_exchanges_checkpoint_idx
+    on raw_exchanges (thread_id, checkpoint_id);
+
+-- What references each checkpoint from outside its thread, e.g. a branch or an annotation, so
+-- that garbage collection keeps it and the checkpoints before it.
+create table if not exists  checkpoint_references
+(
+    thread_id     text                       not null,
+    checkpoint_id text                       not null,
+    referrer      text                       not null,
+    created_at    timestamptz default now()  not null,
+    primary key (thread_id, checkpoint_id, referrer)
+);
+
+-- The toolchains and sanitized environment each session's conversations ran against, joined
+-- to them by ide_checkpoints.session_id.
+create table if not exists  session_env
+(
+    session_id  text primary key,
+    thread_id   text                       not null,
+    toolchains  jsonb                      not null,
+    env         jsonb                      not null,
+    captured_at timestamptz default now()  not null
+);
+-- Whether the session's thread is stored, for when only a sample of threads is.
+alter table session_env add column if not exists sampling jsonb;
+
+-- The files each thread's tool calls or context referenced, with their hash after the latest of
+-- them, to tell whether the thread still reflects them. A null hash is a file that didn't exist.
+create table if not exists  file_snapshots
+(
+    thread_id   text                       not null,
+    path        text                       not null,
+    hash        text,
+    recorded_at timestamptz default now()  not null,
+    primary key (thread_id, path)
+);
+create index if not exists  file_snapshots_path_idx
+    on file_snapshots (path, recorded_at);
+
+-- The editor session running the agent in each thread, so that teammates sharing the store
+-- don't run it in the same thread at once. Locks lapse unless their session renews them.
+create table if not exists  thread_store_locks
+(
+    thread_id   text primary key,
+    session_id  text                       not null,
+    author      jsonb,
+    acquired_at timestamptz default now()  not null,
+    renewed_at  timestamptz default now()  not null
+);
+
+-- Each time a thread's lock was taken from the session holding it, by whom and how.
+create table if not exists  thread_lock_overrides
+(
+    thread_id           text                       not null,
+    action              text                       not null,
+    previous_session_id text,
+    previous_author     jsonb,
+    author              jsonb,
+    recorded_at         timestamptz default now()  not null
+);
+create index if not exists  thread_lock_overrides_thread_idx
+    on thread_lock_overrides (thread_id, recorded_at);
+
+-- The thread whose agent session each commit was made during, to open it from the commit.
+create table if not exists  commit_threads
+(
+    sha         text primary key,
+    thread_id   text                       not null,
+    recorded_at timestamptz default now()  not null
+);
+
+-- The definition of each tool offered in a request, by the hash of its content, recorded on the
+-- request's messages, to check the tool calls made against it and to replay the request with it.
+create table if not exists  tool_schemas
+(
+    hash         text primary key,
+    name         text                       not null,
+    description  text                       not null,
+    input_schema jsonb                      not null,
+    recorded_at  timestamptz default now()  not null
+);
+
+-- How the input of each tool call a model made compared to the schema its request declared for
+-- the tool, checked as the call is stored, to find the providers whose models call tools wrongly.
+create table if not exists  tool_call_validations
+(
+    thread_id     text                       not null,
+    tool_use_id   text                       not null,
+    checkpoint_id text                       not null,
+    tool_name     text                       not null,
+    schema_hash   text,
+    provider      text,
+    model_id      text                       not null,
+    valid         boolean                    not null,
+    errors        jsonb                      not null,
+    recorded_at   timestamptz default now()  not null,
+    primary key (thread_id, tool_use_id)
+);
+create index if not exists  tool_call_validations_invalid_idx
+    on tool_call_validations (provider, recorded_at) where not valid;
+
+-- The agent profile, tools and rules each thread's requests were made with, recorded when the
+-- thread starts and whenever they change, so that replays and audits know what the model was
+-- told.
+create table if not exists  thread_instructions
+(
+    thread_id    text                       not null,
+    profile_id   text                       not null,
+    profile_name text                       not null,
+    tools        jsonb                      not null,
+    rules        jsonb                      not null,
+    recorded_at  timestamptz default now()  not null
+);
+create index if not exists  thread_instructions_thread_idx
+    on thread_instructions (thread_id, recorded_at);
+
+-- The git branch each thread was started on, to list the threads of a branch.
+create table if not exists  thread_branches
+(
+    thread_id   text primary key,
+    git_branch  text                       not null,
+    recorded_at timestamptz default now()  not null
+);
+create index if not exists  thread_branches_git_branch_idx
+    on thread_branches (git_branch);
+
+-- The flags the user set on each thread, to keep the threads that matter at the top of the
+-- history.
+create table if not exists  thread_flags
+(
+    thread_id  text primary key,
+    pinned     boolean     default false  not null,
+    starred    boolean     default false  not null,
+    updated_at timestamptz default now()  not null
+);
+
+-- The tags the user put on each thread, e.g. the feature, ticket or customer it is about.
+create table if not exists  thread_tags
+(
+    thread_id text                       not null,
+    tag       text                       not null,
+    tagged_at timestamptz default now()  not null,
+    primary key (thread_id, tag)
+);
+create index if not exists  thread_tags_tag_idx
+    on thread_tags (lower(tag) text_pattern_ops);
+
+-- The GitHub or Jira issue each thread is about, and when the thread was marked resolved.
+create table if not exists  thread_issues
+(
+    thread_id   text primary key,
+    tracker     text                       not null,
+    issue_key   text                       not null,
+    url         text                       not null,
+    linked_at   timestamptz default now()  not null,
+    resolved_at timestamptz
+);
+
+-- How each run made without a user, e.g. a scheduled one, ended, for the inbox of finished runs.
+create table if not exists  run_results
+(
+    thread_id   text primary key,
+    schedule_id text                       not null,
+    status      text                       not null,
+    error       text,
+    finished_at timestamptz                not null
+);
+create index if not exists  run_results_finished_at_idx
+    on run_results (finished_at);
+
+-- How the judge model ranked the responses of each fan-out group, keyed by the group, with the
+-- judge's own thread.
+create table if not exists  fan_out_verdicts
+(
+    group_id   text primary key,
+    thread_id  text                       not null,
+    ranking    jsonb                      not null,
+    rationale  text                       not null,
+    created_at timestamptz default now()  not null
+);
+
+-- One row per thread, kept up to date as its checkpoints are written, so that the history is
+-- paged through an index instead of aggregating every checkpoint for each page. Threads keep the
+-- author and project of their first write.
+create table if not exists  thread_summaries
+(
+    thread_id    text primary key,
+    author_id    text                not null,
+    author_name  text                not null,
+    project      text                not null,
+    updated_at   text                not null,
+    contributors text[] default '{}' not null
+);
+create index if not exists  thread_summaries_updated_at_idx
+    on thread_summaries (updated_at desc, thread_id desc);
+
+-- Summarizes the threads written before the summaries were kept.
+insert into thread_summaries (thread_id, author_id, author_name, project, updated_at, contributors)
+select thread_id,
+       (array_agg(author_id order by seq))[1],
+       (array_agg(author_name order by seq))[1],
+       (array_agg(project order by seq))[1],
+       max(checkpoint_ts),
+       coalesce(array_agg(distinct author_id) filter (where author_id <> ''), '{}')
+from ide_checkpoints
+where not exists (select 1 from thread_summaries)
+group by thread_id
+on conflict (thread_id) do nothing;
             "#,
         )
         .execute(pool)
@@ -62,38 +611,37 @@ create index if not exists  ide_checkpoints_thread_id_checkpoint_id_idx
         .map(|p| Ok(()))?
     }
 
-    fn _parse_sql_query(ids: &RequestIds, json: &String, task_path: &str) -> String {
-        let json = json.replace("'", "");
-
-        let f = format!(
+    /// Appends the bound messages to the checkpoint's row, creating the row on the checkpoint's
+    /// first write. The new messages are concatenated onto the stored array with `||` inside the
+    /// upsert, under the row's lock, so concurrent appends to one checkpoint each build on the
+    /// others' messages instead of overwriting them, and the stored array is never read back by
+    /// the client. The array ends up in the order the appends commit in, which is the order their
+    /// messages are numbered in. An append that adds nothing leaves the row unwritten.
+    fn append_checkpoint_sql(checkpoint_key: &str) -> String {
+        format!(
             r#"
-                INSERT INTO ide_checkpoints (thread_id, prompt_id, session_id, checkpoint_ts, checkpoint_id, blob, task_path)
-                VALUES ('{}',
-                        '{}',
-                        '{}',
-                        now(),
-                        '{}',
-                        convert_to('{}', 'UTF8'),
-                        '{}')
-                ON CONFLICT (thread_id, checkpoint_id)
+                INSERT INTO ide_checkpoints (thread_id, prompt_id, session_id, checkpoint_ts, checkpoint_id, blob, task_path, author_id, author_name, project, token_count, pii_categories)
+                VALUES ($1, $2, $3, now(), $4, convert_to($5, 'UTF8'), $6, $7, $8, $9, $10, $11)
+                ON CONFLICT {checkpoint_key}
                 DO UPDATE
-                SET blob = convert_to(
+                SET token_count = ide_checkpoints.token_count + excluded.token_count,
+                    pii_categories = ARRAY(
+                        SELECT DISTINCT unnest(ide_checkpoints.pii_categories || excluded.pii_categories)
+                    ),
+                    blob = convert_to(
                         (
-                            (
-                                COALESCE(
-                                        convert_from(ide_checkpoints.blob, 'UTF8')::jsonb,
-                                        '[]'::jsonb
-                                ) || '{}'::jsonb
-                                )::text
-                            ),
-                    'UTF8');
-                "#,
-            &ids.thread_id, &ids.prompt_id, &ids.session_id, &ids.checkpoint_id, &json, task_path, &json
-        );
-
-        log::info!("Here is sql query\n{}", &f);
-
-        f
+                            COALESCE(
+                                convert_from(ide_checkpoints.blob, 'UTF8')::jsonb,
+                                '[]'::jsonb
+                            ) || $5::jsonb
+                        )::text,
+                        'UTF8'
+                    )
+                WHERE $5::jsonb <> '[]'::jsonb
+                   OR excluded.token_count <> 0
+                   OR NOT excluded.pii_categories <@ ide_checkpoints.pii_categories
+                "#
+        )
     }
 
     fn _parse_task_path<'a>(message: &Vec<Message>) -> &'a str {
@@ -125,57 +673,2222 @@ create index if not exists  ide_checkpoints_thread_id_checkpoint_id_idx
         }
         task_path
     }
-}
 
-impl DatabaseClient for PostgresDatabaseClient {
-    async fn save_append_messages(&self, message: Vec<Message>, ids: &RequestIds) {
-        let message_clone = message.clone();
-        let pool = self.pool.clone();
+    fn _parse_project(message: &Vec<Message>) -> String {
+        message.iter()
+            .find_map(|f| {
+                f.response_metadata().get("project")
+                    .and_then(|j| j.as_str())
+                    .map(|s| s.to_string())
+            })
+            .unwrap_or_default()
+    }
 
-        if pool.as_ref().is_none() {
-            log::error!("Database pool is not initialized");
-            return;
-        }
+    /// Records the branch the thread was started on. Its later requests don't move it, even if
+    /// they were made on another branch.
+    async fn record_thread_branch(&self, thread_id: &str, git_branch: &str) -> Result<()> {
+        sqlx::query(
+            r#"
+                INSERT INTO thread_branches (thread_id, git_branch)
+                VALUES ($1, $2)
+                ON CONFLICT (thread_id) DO NOTHING
+                "#,
+        )
+        .bind(thread_id)
+        .bind(git_branch)
+        .execute(self.pool()?)
+        .await?;
+        Ok(())
+    }
+
+    fn pool(&self) -> Result<&PgPool> {
+        self.pool
+            .as_deref()
+            .context("Database pool is not initialized")
+    }
+
+    /// Records the write the messages were stamped for, returning whether it hasn't been made
+    /// before. Messages without an idempotency key are always written.
+    async fn claim_write(
+        transaction: &mut Transaction<'_, Postgres>,
+        messages: &[Message],
+        ids: &RequestIds,
+    ) -> Result<bool> {
+        let Some(idempotency_key) = write_idempotency_key(messages) else {
+            return Ok(true);
+        };
+        let result = sqlx::query(
+            r#"
+                INSERT INTO ide_checkpoint_writes (idempotency_key, thread_id)
+                VALUES ($1, $2)
+                ON CONFLICT (idempotency_key) DO NOTHING
+                "#,
+        )
+        .bind(idempotency_key)
+        .bind(&ids.thread_id)
+        .execute(&mut **transaction)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Numbers the messages after the thread's stored messages. The thread's counter stays locked
+    /// until the transaction ends, so a thread's writes are numbered in the order they commit in.
+    async fn stamp_stored_sequences(
+        transaction: &mut Transaction<'_, Postgres>,
+        messages: &mut [Message],
+        ids: &RequestIds,
+    ) -> Result<()> {
+        let count = messages.len() as i64;
+        let (next,): (i64,) = sqlx::query_as(
+            r#"
+                INSERT INTO thread_sequences (thread_id, next)
+                VALUES ($1, $2)
+                ON CONFLICT (thread_id) DO UPDATE SET next = thread_sequences.next + excluded.next
+                RETURNING next
+                "#,
+        )
+        .bind(&ids.thread_id)
+        .bind(count)
+        .fetch_one(&mut **transaction)
+        .await?;
+        stamp_sequence(messages, (next - count) as u64);
+        Ok(())
+    }
 
+    /// Moves the long strings in the messages to `content_blobs`, returning what is left to store
+    /// in the checkpoint.
+    async fn store_content_blobs(&self, messages: &[Message]) -> Result<serde_json::Value> {
+        let mut value = serialize_messages(messages)?;
+        let blobs = extract_content_blobs(&mut value, CONTENT_BLOB_MIN_LEN);
+        if !blobs.is_empty() {
+            let (hashes, contents): (Vec<String>, Vec<String>) = blobs.into_iter().unzip();
+            sqlx::query(
+                r#"
+                    INSERT INTO content_blobs (hash, content)
+                    SELECT * FROM unnest($1::text[], $2::text[])
+                    ON CONFLICT (hash) DO NOTHING
+                    "#,
+            )
+            .bind(hashes)
+            .bind(contents)
+            .execute(self.pool()?)
+            .await?;
+        }
+        Ok(value)
+    }
 
+    /// Appends the messages to their checkpoint in the transaction, committing it.
+    async fn append_messages(
+        &self,
+        mut transaction: Transaction<'_, Postgres>,
+        mut message: Vec<Message>,
+        ids: &RequestIds,
+        author: Option<&MessageAuthor>,
+    ) -> Result<()> {
         let task_path = Self::_parse_task_path(&message);
+        let project = Self::_parse_project(&message);
+        let token_count = stored_token_count(&message);
+        let pii_categories = stored_pii_categories(&message);
+        if let Some(git_branch) = messages_git_branch(&message) {
+            self.record_thread_branch(&ids.thread_id, git_branch)
+                .await?;
+        }
 
-        let message_json_res = serde_json::to_string(&message_clone);
+        if !Self::claim_write(&mut transaction, &message, ids).await? {
+            log::debug!("Checkpoint {} already has this write", ids.checkpoint_id);
+            return Ok(());
+        }
+        Self::stamp_stored_sequences(&mut transaction, &mut message, ids).await?;
+        Self::record_thread_summary(&mut transaction, ids, author, &project).await?;
 
-        if let Ok(json) = &message_json_res {
-            let sql_res = sqlx::raw_sql(&Self::_parse_sql_query(ids, json, task_path))
-                .execute(&*pool.unwrap())
+        // Checkpoints keep the encoding they were first written with, whatever is configured now.
+        let encoding = self
+            .stored_blob_encoding(&mut transaction, ids)
+            .await?
+            .unwrap_or(self.blob_encoding);
+        if encoding != BlobEncoding::Json {
+            return self
+                .save_encoded_checkpoint(
+                    transaction,
+                    encoding,
+                    &message,
+                    ids,
+                    author,
+                    task_path,
+                    &project,
+                    token_count,
+                    &pii_categories,
+                )
                 .await;
+        }
+
+        let json = serde_json::to_string(&self.store_content_blobs(&message).await?)?;
+        sqlx::query(&Self::append_checkpoint_sql(self.checkpoint_key()))
+            .bind(&ids.thread_id)
+            .bind(&ids.prompt_id)
+            .bind(&ids.session_id)
+            .bind(&ids.checkpoint_id)
+            .bind(&json)
+            .bind(task_path)
+            .bind(author.map_or(String::new(), |author| author.id.clone()))
+            .bind(author.map_or(String::new(), |author| author.display_name().to_string()))
+            .bind(&project)
+            .bind(token_count)
+            .bind(&pii_categories)
+            .execute(&mut *transaction)
+            .await?;
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    /// Marks the thread as updated in its summary, creating the summary on the thread's first
+    /// write, and adds the author to its contributors.
+    async fn record_thread_summary(
+        transaction: &mut Transaction<'_, Postgres>,
+        ids: &RequestIds,
+        author: Option<&MessageAuthor>,
+        project: &str,
+    ) -> Result<()> {
+        sqlx::query(
+            r#"
+                INSERT INTO thread_summaries (thread_id, author_id, author_name, project, updated_at, contributors)
+                VALUES ($1, $2, $3, $4, now()::text, ARRAY(SELECT $2::text WHERE $2 <> ''))
+                ON CONFLICT (thread_id) DO UPDATE
+                SET updated_at = excluded.updated_at,
+                    contributors = ARRAY(
+                        SELECT DISTINCT unnest(thread_summaries.contributors || excluded.contributors)
+                        ORDER BY 1
+                    )
+                "#,
+        )
+        .bind(&ids.thread_id)
+        .bind(author.map_or(String::new(), |author| author.id.clone()))
+        .bind(author.map_or(String::new(), |author| author.display_name().to_string()))
+        .bind(project)
+        .execute(&mut **transaction)
+        .await?;
+        Ok(())
+    }
+
+    /// Narrows a query of the checkpoint's rows to the one upserted into. Partitioned
+    /// checkpoints are upserted into the current month's row, which is the only partition read.
+    fn current_month(&self) -> &'static str {
+        if self.partitioned.load(Ordering::SeqCst) {
+            "AND checkpoint_month = date_trunc('month', now())::date"
+        } else {
+            ""
+        }
+    }
+
+    /// The encoding the checkpoint's row was written with, locking the row until the transaction
+    /// ends. `None` before the checkpoint's first write.
+    async fn stored_blob_encoding(
+        &self,
+        transaction: &mut Transaction<'_, Postgres>,
+        ids: &RequestIds,
+    ) -> Result<Option<BlobEncoding>> {
+        let header: Option<(Vec<u8>,)> = sqlx::query_as(&format!(
+            r#"
+                SELECT substring(blob FROM 1 FOR 4)
+                FROM ide_checkpoints
+                WHERE thread_id = $1 AND checkpoint_id = $2 {}
+                FOR UPDATE
+                "#,
+            self.current_month()
+        ))
+        .bind(&ids.thread_id)
+        .bind(&ids.checkpoint_id)
+        .fetch_optional(&mut **transaction)
+        .await?;
+        header.map(|(header,)| blob_encoding(&header)).transpose()
+    }
+
+    /// Saves the messages to a checkpoint in a binary encoding. Unlike JSON, which is appended in
+    /// SQL, the checkpoint is decoded and re-encoded with the new messages appended, in the
+    /// encoding it was first written with. Its row is created before it is read, so that the
+    /// read locks it even on the checkpoint's first write.
+    async fn save_encoded_checkpoint(
+        &self,
+        mut transaction: Transaction<'_, Postgres>,
+        encoding: BlobEncoding,
+        messages: &[Message],
+        ids: &RequestIds,
+        author: Option<&MessageAuthor>,
+        task_path: &str,
+        project: &str,
+        token_count: i64,
+        pii_categories: &[String],
+    ) -> Result<()> {
+        let value = self.store_content_blobs(messages).await?;
+        let serde_json::Value::Array(messages) = value else {
+            anyhow::bail!("messages are not serialized as a list");
+        };
+        sqlx::query(&format!(
+            r#"
+                INSERT INTO ide_checkpoints (thread_id, prompt_id, session_id, checkpoint_ts, checkpoint_id, blob, task_path, author_id, author_name, project)
+                VALUES ($1, $2, $3, now(), $4, $5, $6, $7, $8, $9)
+                ON CONFLICT {}
+                DO NOTHING
+                "#,
+            self.checkpoint_key()
+        ))
+        .bind(&ids.thread_id)
+        .bind(&ids.prompt_id)
+        .bind(&ids.session_id)
+        .bind(&ids.checkpoint_id)
+        .bind(encode_blob(&serde_json::Value::Array(Vec::new()), encoding)?)
+        .bind(task_path)
+        .bind(author.map_or(String::new(), |author| author.id.clone()))
+        .bind(author.map_or(String::new(), |author| author.display_name().to_string()))
+        .bind(project)
+        .execute(&mut *transaction)
+        .await?;
+
+        let current_month = self.current_month();
+        let (blob,): (Vec<u8>,) = sqlx::query_as(&format!(
+            r#"
+                SELECT blob
+                FROM ide_checkpoints
+                WHERE thread_id = $1 AND checkpoint_id = $2 {current_month}
+                FOR UPDATE
+                "#
+        ))
+        .bind(&ids.thread_id)
+        .bind(&ids.checkpoint_id)
+        .fetch_one(&mut *transaction)
+        .await?;
+        let serde_json::Value::Array(mut merged) = decode_blob(&blob)? else {
+            anyhow::bail!("checkpoint {} is not a list of messages", ids.checkpoint_id);
+        };
+        merged.extend(messages);
+
+        sqlx::query(&format!(
+            r#"
+                UPDATE ide_checkpoints
+                SET blob = $3,
+                    token_count = token_count + $4,
+                    pii_categories = ARRAY(SELECT DISTINCT unnest(pii_categories || $5::text[]))
+                WHERE thread_id = $1 AND checkpoint_id = $2 {current_month}
+                "#
+        ))
+        .bind(&ids.thread_id)
+        .bind(&ids.checkpoint_id)
+        .bind(encode_blob(
+            &serde_json::Value::Array(merged),
+            blob_encoding(&blob)?,
+        )?)
+        .bind(token_count)
+        .bind(pii_categories)
+        .execute(&mut *transaction)
+        .await?;
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    /// Checks that the store can be read from and, unless the client only reads it, written to,
+    /// without writing anything: a store that accepts connections may still be a read-only
+    /// replica, or keep its tables from the user.
+    pub async fn smoke_test(&self, read_only: bool) -> Result<()> {
+        let pool = self.pool()?;
+        sqlx::query("SELECT 1 FROM ide_checkpoints LIMIT 1")
+            .execute(pool)
+            .await
+            .context("the checkpoints can't be read")?;
+        if read_only {
+            return Ok(());
+        }
+        let (in_recovery, can_insert): (bool, bool) = sqlx::query_as(
+            "SELECT pg_is_in_recovery(), has_table_privilege('ide_checkpoints', 'INSERT')",
+        )
+        .fetch_one(pool)
+        .await?;
+        if in_recovery {
+            anyhow::bail!("the store is a read-only replica");
+        }
+        if !can_insert {
+            anyhow::bail!("the store's user may not write checkpoints");
+        }
+        Ok(())
+    }
+
+    /// Re-encodes the checkpoints written with an encoding other than the configured one,
+    /// returning how many were rewritten. Checkpoints written to meanwhile are left for a later
+    /// run.
+    pub async fn convert_blob_encoding(&self) -> Result<usize> {
+        let mut after_seq = 0;
+        let mut converted = 0;
+        loop {
+            let rows: Vec<(i64, Vec<u8>)> = sqlx::query_as(
+                r#"
+                    SELECT seq, blob
+                    FROM ide_checkpoints
+                    WHERE seq > $1
+                    ORDER BY seq
+                    LIMIT $2
+                    "#,
+            )
+            .bind(after_seq)
+            .bind(CHECKPOINT_BATCH_SIZE as i64)
+            .fetch_all(self.pool()?)
+            .await?;
+            let Some((last_seq, _)) = rows.last() else {
+                return Ok(converted);
+            };
+            after_seq = *last_seq;
+
+            for (seq, blob) in rows {
+                if blob_encoding(&blob)? == self.blob_encoding {
+                    continue;
+                }
+                let encoded = encode_blob(&decode_blob(&blob)?, self.blob_encoding)?;
+                let result = sqlx::query(
+                    "UPDATE ide_checkpoints SET blob = $1 WHERE seq = $2 AND blob = $3",
+                )
+                .bind(encoded)
+                .bind(seq)
+                .bind(blob)
+                .execute(self.pool()?)
+                .await?;
+                converted += result.rows_affected() as usize;
+            }
+        }
+    }
 
-            if let Err(e) = sql_res {
-                log::error!("Found sql err {}!", &e);
+    /// Deletes the content blobs that no checkpoint references, among those stored before
+    /// `before`, so that the blobs of checkpoints being written meanwhile are left alone.
+    async fn collect_content_blobs(&self, before: chrono::DateTime<Utc>) -> Result<u64> {
+        let mut referenced = HashSet::default();
+        let mut after_seq = 0;
+        loop {
+            let rows: Vec<(i64, Vec<u8>)> = sqlx::query_as(
+                r#"
+                    SELECT seq, blob
+                    FROM ide_checkpoints
+                    WHERE seq > $1
+                    ORDER BY seq
+                    LIMIT $2
+                    "#,
+            )
+            .bind(after_seq)
+            .bind(CHECKPOINT_BATCH_SIZE as i64)
+            .fetch_all(self.pool()?)
+            .await?;
+            let Some((last_seq, _)) = rows.last() else {
+                break;
+            };
+            after_seq = *last_seq;
+            for (_, blob) in rows {
+                referenced.extend(referenced_content_blobs(&decode_blob(&blob)?));
             }
-        } else if let Err(e) = &message_json_res {
-            log::error!("Found err: {}", &e);
         }
+        let result =
+            sqlx::query("DELETE FROM content_blobs WHERE created_at < $1 AND NOT (hash = ANY($2))")
+                .bind(before)
+                .bind(referenced.into_iter().collect::<Vec<_>>())
+                .execute(self.pool()?)
+                .await?;
+        Ok(result.rows_affected())
     }
 }
 
-#[cfg(test)]
-mod test_db_client {
-    use std::collections::HashMap;
-    use crate::{AiMessageContent, MessageContent};
-    use crate::message_handler::{ContentValue, Message, PostgresDatabaseClient};
+/// Decodes checkpoint blobs, reading back the content they reference from `content_blobs`. Each
+/// checkpoint's messages are put in order by their `sequence`, for checkpoints stored before
+/// appends were numbered as they committed, and every read of a thread goes through here, so
+/// that they all read its messages in one order.
+async fn decode_checkpoints(
+    conn: &mut PgConnection,
+    blobs: &[Vec<u8>],
+) -> Result<Vec<Vec<Message>>> {
+    let mut values = blobs
+        .iter()
+        .map(|blob| decode_blob(blob))
+        .collect::<Result<Vec<_>>>()?;
+    let hashes = values
+        .iter()
+        .flat_map(referenced_content_blobs)
+        .collect::<HashSet<_>>();
+    if !hashes.is_empty() {
+        let contents: Vec<(String, String)> =
+            sqlx::query_as("SELECT hash, content FROM content_blobs WHERE hash = ANY($1)")
+                .bind(hashes.into_iter().collect::<Vec<_>>())
+                .fetch_all(&mut *conn)
+                .await?;
+        let contents = contents.into_iter().collect();
+        for value in &mut values {
+            resolve_content_blobs(value, &contents)?;
+        }
+    }
+    values
+        .into_iter()
+        .map(|value| {
+            let mut messages = deserialize_messages(value)?;
+            sort_by_sequence(&mut messages);
+            Ok(messages)
+        })
+        .collect()
+}
 
-    #[test]
-    fn test_append_messages() {
-        let parsed = PostgresDatabaseClient::_parse_task_path(&vec![Message::Ai {
-            content: ContentValue::Single("hello".to_string()),
-            id: "".to_string(),
-            name: None,
-            example: false,
-            invalid_tool_calls: None,
-            tool_calls: None,
-            additional_kwargs: Default::default(),
-            response_metadata: [("intent".to_string(), serde_json::Value::String("ThreadSummarization".to_string()))].into_iter().collect::<HashMap<String, serde_json::Value>>(),
-        }]);
+/// Checkpoints fetched per round trip when reading through many of them.
+const CHECKPOINT_BATCH_SIZE: usize = 64;
 
-        assert_eq!(parsed, "summarization");
+/// Where a stream of a thread's messages is up to. The rows are read through a server-side
+/// cursor, which lives as long as the transaction it was declared in.
+enum MessageCursor {
+    Start {
+        pool: PgPool,
+        thread_id: String,
+    },
+    Open {
+        transaction: Transaction<'static, Postgres>,
+        buffered: VecDeque<Message>,
+    },
+}
+
+async fn next_message(mut cursor: MessageCursor) -> Result<Option<(Message, MessageCursor)>> {
+    loop {
+        cursor = match cursor {
+            MessageCursor::Start { pool, thread_id } => {
+                let mut transaction = pool.begin().await?;
+                sqlx::query(
+                    r#"
+                        DECLARE thread_messages NO SCROLL CURSOR FOR
+                        SELECT blob
+                        FROM ide_checkpoints
+                        WHERE thread_id = $1 AND NOT compacted
+                        ORDER BY seq
+                        "#,
+                )
+                .bind(thread_id)
+                .execute(&mut *transaction)
+                .await?;
+                MessageCursor::Open {
+                    transaction,
+                    buffered: VecDeque::new(),
+                }
+            }
+            MessageCursor::Open {
+                mut transaction,
+                mut buffered,
+            } => {
+                if let Some(message) = buffered.pop_front() {
+                    return Ok(Some((
+                        message,
+                        MessageCursor::Open {
+                            transaction,
+                            buffered,
+                        },
+                    )));
+                }
+                let blobs: Vec<(Vec<u8>,)> = sqlx::query_as(&format!(
+                    "FETCH FORWARD {CHECKPOINT_BATCH_SIZE} FROM thread_messages"
+                ))
+                .fetch_all(&mut *transaction)
+                .await?;
+                if blobs.is_empty() {
+                    transaction.commit().await?;
+                    return Ok(None);
+                }
+                let blobs = blobs.into_iter().map(|(blob,)| blob).collect::<Vec<_>>();
+                for messages in decode_checkpoints(&mut transaction, &blobs).await? {
+                    buffered.extend(messages);
+                }
+                MessageCursor::Open {
+                    transaction,
+                    buffered,
+                }
+            }
+        };
+    }
+}
+
+type StoredThreadRow = (
+    String,
+    String,
+    String,
+    String,
+    String,
+    String,
+    bool,
+    bool,
+    Vec<String>,
+);
+
+type RunResultRow = (String, String, String, Option<String>, DateTime<Utc>);
+
+type InvalidToolCallThreadRow = (String, Option<String>, String, i64, i64, DateTime<Utc>);
+
+type StoreLockHolderRow = (String, String, Option<String>, DateTime<Utc>, DateTime<Utc>);
+
+fn store_lock_holder(row: StoreLockHolderRow) -> Result<StoreLockHolder> {
+    let (thread_id, session_id, author, acquired_at, renewed_at) = row;
+    Ok(StoreLockHolder {
+        thread_id,
+        session_id,
+        author: author
+            .map(|author| serde_json::from_str(&author))
+            .transpose()?,
+        acquired_at,
+        renewed_at,
+    })
+}
+
+fn stored_thread(row: StoredThreadRow) -> StoredThread {
+    let (
+        thread_id,
+        author_id,
+        author_name,
+        project,
+        git_branch,
+        updated_at,
+        pinned,
+        starred,
+        contributors,
+    ) = row;
+    StoredThread {
+        thread_id,
+        author_id,
+        author_name,
+        project,
+        contributors,
+        git_branch,
+        updated_at,
+        pinned,
+        starred,
+    }
+}
+
+impl DatabaseClient for PostgresDatabaseClient {
+    async fn save_append_messages(
+        &self,
+        message: Vec<Message>,
+        ids: &RequestIds,
+        author: Option<&MessageAuthor>,
+    ) -> Result<()> {
+        let transaction = self.pool()?.begin().await?;
+        self.append_messages(transaction, message, ids, author)
+            .await
+    }
+
+    async fn list_threads(
+        &self,
+        after: Option<&ThreadCursor>,
+        limit: usize,
+        git_branch: Option<&str>,
+        sort: ThreadSort,
+    ) -> Result<Page<StoredThread, ThreadCursor>> {
+        // Unflagged threads have no row in `thread_flags`, and sort as if they had one with both
+        // flags cleared.
+        let rows: Vec<StoredThreadRow> = sqlx::query_as(
+            r#"
+                SELECT s.thread_id, s.author_id, s.author_name, s.project,
+                       coalesce(b.git_branch, ''), s.updated_at,
+                       coalesce(f.pinned, false), coalesce(f.starred, false),
+                       s.contributors
+                FROM thread_summaries s
+                LEFT JOIN thread_branches b ON b.thread_id = s.thread_id
+                LEFT JOIN thread_flags f ON f.thread_id = s.thread_id
+                WHERE ($4::text IS NULL OR b.git_branch = $4)
+                  AND ($1::text IS NULL
+                    OR (NOT $5 AND (s.updated_at, s.thread_id) < ($1, $2))
+                    OR ($5 AND (coalesce(f.pinned, false),
+                                coalesce(f.starred, false),
+                                s.updated_at,
+                                s.thread_id) < ($6, $7, $1, $2)))
+                ORDER BY CASE WHEN $5 THEN coalesce(f.pinned, false) END DESC,
+                         CASE WHEN $5 THEN coalesce(f.starred, false) END DESC,
+                         s.updated_at DESC, s.thread_id DESC
+                LIMIT $3
+                "#,
+        )
+        .bind(after.map(|cursor| cursor.updated_at.clone()))
+        .bind(after.map(|cursor| cursor.thread_id.clone()))
+        .bind(limit as i64)
+        .bind(git_branch)
+        .bind(sort == ThreadSort::Flagged)
+        .bind(after.is_some_and(|cursor| cursor.pinned))
+        .bind(after.is_some_and(|cursor| cursor.starred))
+        .fetch_all(self.pool()?)
+        .await?;
+
+        Ok(Page::from_rows(
+            rows.into_iter().map(stored_thread).collect(),
+            limit,
+            ThreadCursor::after,
+        ))
+    }
+
+    async fn set_thread_flag(&self, thread_id: &str, flag: ThreadFlag, value: bool) -> Result<()> {
+        let column = flag.column();
+        sqlx::query(&format!(
+            r#"
+                INSERT INTO thread_flags (thread_id, {column})
+                VALUES ($1, $2)
+                ON CONFLICT (thread_id) DO UPDATE
+                SET {column} = EXCLUDED.{column},
+                    updated_at = now()
+                "#
+        ))
+        .bind(thread_id)
+        .bind(value)
+        .execute(self.pool()?)
+        .await?;
+        Ok(())
+    }
+
+    async fn add_thread_tag(&self, thread_id: &str, tag: &str) -> Result<()> {
+        sqlx::query(
+            r#"
+                INSERT INTO thread_tags (thread_id, tag)
+                VALUES ($1, $2)
+                ON CONFLICT (thread_id, tag) DO NOTHING
+                "#,
+        )
+        .bind(thread_id)
+        .bind(tag)
+        .execute(self.pool()?)
+        .await?;
+        Ok(())
+    }
+
+    async fn remove_thread_tag(&self, thread_id: &str, tag: &str) -> Result<()> {
+        sqlx::query("DELETE FROM thread_tags WHERE thread_id = $1 AND tag = $2")
+            .bind(thread_id)
+            .bind(tag)
+            .execute(self.pool()?)
+            .await?;
+        Ok(())
+    }
+
+    async fn thread_tags(&self, thread_id: &str) -> Result<Vec<String>> {
+        let rows: Vec<(String,)> =
+            sqlx::query_as("SELECT tag FROM thread_tags WHERE thread_id = $1 ORDER BY tag")
+                .bind(thread_id)
+                .fetch_all(self.pool()?)
+                .await?;
+        Ok(rows.into_iter().map(|(tag,)| tag).collect())
+    }
+
+    async fn tag_suggestions(&self, pattern: &str, limit: usize) -> Result<Vec<TagSuggestion>> {
+        let rows: Vec<(String, i64)> = sqlx::query_as(
+            r#"
+                SELECT tag, count(*)
+                FROM thread_tags
+                WHERE lower(tag) LIKE $1
+                GROUP BY tag
+                ORDER BY count(*) DESC, tag
+                LIMIT $2
+                "#,
+        )
+        .bind(pattern)
+        .bind(limit as i64)
+        .fetch_all(self.pool()?)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|(tag, threads)| TagSuggestion { tag, threads })
+            .collect())
     }
 
-}
\ No newline at end of file
+    async fn link_thread_issue(&self, thread_id: &str, issue: &IssueLink) -> Result<()> {
+        sqlx::query(
+            r#"
+                INSERT INTO thread_issues (thread_id, tracker, issue_key, url)
+                VALUES ($1, $2, $3, $4)
+                ON CONFLICT (thread_id) DO UPDATE
+                SET tracker = EXCLUDED.tracker,
+                    issue_key = EXCLUDED.issue_key,
+                    url = EXCLUDED.url,
+                    linked_at = now(),
+                    resolved_at = NULL
+                "#,
+        )
+        .bind(thread_id)
+        .bind(issue.tracker.as_str())
+        .bind(&issue.key)
+        .bind(&issue.url)
+        .execute(self.pool()?)
+        .await?;
+        Ok(())
+    }
+
+    async fn unlink_thread_issue(&self, thread_id: &str) -> Result<()> {
+        sqlx::query("DELETE FROM thread_issues WHERE thread_id = $1")
+            .bind(thread_id)
+            .execute(self.pool()?)
+            .await?;
+        Ok(())
+    }
+
+    async fn thread_issue(&self, thread_id: &str) -> Result<Option<ThreadIssue>> {
+        let row: Option<(String, String, String, bool)> = sqlx::query_as(
+            r#"
+                SELECT tracker, issue_key, url, resolved_at IS NOT NULL
+                FROM thread_issues
+                WHERE thread_id = $1
+                "#,
+        )
+        .bind(thread_id)
+        .fetch_optional(self.pool()?)
+        .await?;
+        row.map(thread_issue).transpose()
+    }
+
+    async fn set_thread_issue_resolved(&self, thread_id: &str, resolved: bool) -> Result<()> {
+        sqlx::query(
+            r#"
+                UPDATE thread_issues
+                SET resolved_at = CASE WHEN $2 THEN coalesce(resolved_at, now()) END
+                WHERE thread_id = $1
+                "#,
+        )
+        .bind(thread_id)
+        .bind(resolved)
+        .execute(self.pool()?)
+        .await?;
+        Ok(())
+    }
+
+    async fn get_thread(&self, thread_id: &str) -> Result<Option<StoredThread>> {
+        let row: Option<StoredThreadRow> = sqlx::query_as(
+            r#"
+                SELECT s.thread_id, s.author_id, s.author_name, s.project,
+                       coalesce(b.git_branch, ''), s.updated_at,
+                       coalesce(f.pinned, false), coalesce(f.starred, false),
+                       s.contributors
+                FROM thread_summaries s
+                LEFT JOIN thread_branches b ON b.thread_id = s.thread_id
+                LEFT JOIN thread_flags f ON f.thread_id = s.thread_id
+                WHERE s.thread_id = $1
+                "#,
+        )
+        .bind(thread_id)
+        .fetch_optional(self.pool()?)
+        .await?;
+
+        Ok(row.map(stored_thread))
+    }
+
+    async fn list_messages(
+        &self,
+        thread_id: &str,
+        after_seq: Option<i64>,
+        limit: usize,
+    ) -> Result<Page<StoredMessage, i64>> {
+        let rows: Vec<(i64, Vec<u8>)> = sqlx::query_as(
+            r#"
+                SELECT seq, blob
+                FROM ide_checkpoints
+                WHERE thread_id = $1 AND seq > $2
+                ORDER BY seq
+                LIMIT $3
+                "#,
+        )
+        .bind(thread_id)
+        .bind(after_seq.unwrap_or(0))
+        .bind(limit as i64)
+        .fetch_all(self.pool()?)
+        .await?;
+
+        let next = if rows.len() == limit {
+            rows.last().map(|(seq, _)| *seq)
+        } else {
+            None
+        };
+        let (seqs, blobs): (Vec<i64>, Vec<Vec<u8>>) = rows.into_iter().unzip();
+        let mut conn = self.pool()?.acquire().await?;
+        let checkpoints = decode_checkpoints(&mut conn, &blobs).await?;
+        let mut items = Vec::new();
+        for (seq, messages) in seqs.into_iter().zip(checkpoints) {
+            items.extend(
+                messages
+                    .into_iter()
+                    .enumerate()
+                    .map(|(index, message)| StoredMessage {
+                        seq,
+                        index,
+                        message,
+                    }),
+            );
+        }
+        Ok(Page { items, next })
+    }
+
+    async fn register_prompt_template(&self, id: &str, source: &str) -> Result<PromptTemplateRef> {
+        // Another client may register the same version concurrently, in which case the insert is
+        // skipped and the latest version is looked up again.
+        for _ in 0..3 {
+            let latest: Option<(i32, String)> = sqlx::query_as(
+                r#"
+                    SELECT version, source
+                    FROM prompt_templates
+                    WHERE template_id = $1
+                    ORDER BY version DESC
+                    LIMIT 1
+                    "#,
+            )
+            .bind(id)
+            .fetch_optional(self.pool()?)
+            .await?;
+
+            let version = match latest {
+                Some((version, latest_source)) if latest_source == source => {
+                    return Ok(PromptTemplateRef {
+                        id: id.to_string(),
+                        version,
+                    });
+                }
+                Some((version, _)) => version + 1,
+                None => 1,
+            };
+
+            let inserted = sqlx::query(
+                r#"
+                    INSERT INTO prompt_templates (template_id, version, source)
+                    VALUES ($1, $2, $3)
+                    ON CONFLICT (template_id, version) DO NOTHING
+                    "#,
+            )
+            .bind(id)
+            .bind(version)
+            .bind(source)
+            .execute(self.pool()?)
+            .await?;
+
+            if inserted.rows_affected() == 1 {
+                return Ok(PromptTemplateRef {
+                    id: id.to_string(),
+                    version,
+                });
+            }
+        }
+        anyhow::bail!("failed to register prompt template {id}")
+    }
+
+    async fn prompt_template_versions(&self, id: &str) -> Result<Vec<PromptTemplate>> {
+        let rows: Vec<(String, i32, String, String)> = sqlx::query_as(
+            r#"
+                SELECT template_id, version, source, created_at::text
+                FROM prompt_templates
+                WHERE template_id = $1
+                ORDER BY version
+                "#,
+        )
+        .bind(id)
+        .fetch_all(self.pool()?)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(id, version, source, created_at)| PromptTemplate {
+                id,
+                version,
+                source,
+                created_at,
+            })
+            .collect())
+    }
+
+    async fn record_experiment_outcome(
+        &self,
+        assignment: &PromptExperimentAssignment,
+        thread_id: &str,
+        metric: ExperimentMetric,
+        value: f64,
+    ) -> Result<()> {
+        sqlx::query(
+            r#"
+                INSERT INTO prompt_experiment_outcomes (experiment_id, variant, thread_id, metric, value)
+                VALUES ($1, $2, $3, $4, $5)
+                "#,
+        )
+        .bind(&assignment.experiment_id)
+        .bind(&assignment.variant)
+        .bind(thread_id)
+        .bind(metric.as_str())
+        .bind(value)
+        .execute(self.pool()?)
+        .await?;
+        Ok(())
+    }
+
+    async fn experiment_results(&self, experiment_id: &str) -> Result<Vec<VariantStats>> {
+        let rows: Vec<(String, String, i64, i64, f64)> = sqlx::query_as(
+            r#"
+                SELECT variant, metric, count(*), count(distinct thread_id), avg(value)
+                FROM prompt_experiment_outcomes
+                WHERE experiment_id = $1
+                GROUP BY variant, metric
+                ORDER BY variant, metric
+                "#,
+        )
+        .bind(experiment_id)
+        .fetch_all(self.pool()?)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(variant, metric, samples, threads, mean)| VariantStats {
+                variant,
+                metric,
+                samples,
+                threads,
+                mean,
+            })
+            .collect())
+    }
+
+    async fn record_prompt_cache_usage(
+        &self,
+        ids: &RequestIds,
+        usage: &PromptCacheUsage,
+    ) -> Result<()> {
+        sqlx::query(
+            r#"
+                INSERT INTO prompt_cache_usage (thread_id, checkpoint_id, template_id, template_version, input_tokens, cache_read_tokens, cache_creation_tokens)
+                VALUES ($1, $2, $3, $4, $5, $6, $7)
+                ON CONFLICT (thread_id, checkpoint_id) DO NOTHING
+                "#,
+        )
+        .bind(&ids.thread_id)
+        .bind(&ids.checkpoint_id)
+        .bind(usage.template.as_ref().map_or("", |template| template.id.as_str()))
+        .bind(usage.template.as_ref().map(|template| template.version))
+        .bind(usage.input_tokens)
+        .bind(usage.cache_read_tokens)
+        .bind(usage.cache_creation_tokens)
+        .execute(self.pool()?)
+        .await?;
+        Ok(())
+    }
+
+    async fn record_request_usage(
+        &self,
+        ids: &RequestIds,
+        usage: &RequestUsageRecord,
+    ) -> Result<()> {
+        sqlx::query(
+            r#"
+                INSERT INTO request_usage (thread_id, checkpoint_id, model_id, language, area, source, input_tokens, output_tokens)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+                ON CONFLICT (thread_id, checkpoint_id) DO NOTHING
+                "#,
+        )
+        .bind(&ids.thread_id)
+        .bind(&ids.checkpoint_id)
+        .bind(&usage.model_id)
+        .bind(&usage.language)
+        .bind(usage.area.map(|area| area.as_str()))
+        .bind(usage.source.map(|source| source.as_str()))
+        .bind(usage.input_tokens)
+        .bind(usage.output_tokens)
+        .execute(self.pool()?)
+        .await?;
+        Ok(())
+    }
+
+    async fn usage_breakdown(
+        &self,
+        dimension: UsageDimension,
+        since: NaiveDate,
+    ) -> Result<Vec<UsageBreakdown>> {
+        let column = match dimension {
+            UsageDimension::Language => "language",
+            UsageDimension::ProjectArea => "area",
+            UsageDimension::Source => "source",
+        };
+        let rows: Vec<(NaiveDate, Option<String>, i64, i64, i64)> = sqlx::query_as(&format!(
+            r#"
+                SELECT (recorded_at AT TIME ZONE 'UTC')::date AS day, {column}, count(*),
+                       sum(input_tokens)::bigint, sum(output_tokens)::bigint
+                FROM request_usage
+                WHERE recorded_at >= $1::timestamp AT TIME ZONE 'UTC'
+                GROUP BY day, {column}
+                ORDER BY day, {column}
+                "#
+        ))
+        .bind(since)
+        .fetch_all(self.pool()?)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(
+                |(day, key, requests, input_tokens, output_tokens)| UsageBreakdown {
+                    day,
+                    key,
+                    requests,
+                    input_tokens,
+                    output_tokens,
+                },
+            )
+            .collect())
+    }
+
+    async fn usage_histogram(&self, day: NaiveDate) -> Result<Vec<UsageHistogramRow>> {
+        let rows: Vec<(String, i32, i64, i64, i64)> = sqlx::query_as(
+            r#"
+                SELECT model_id, width_bucket(input_tokens + output_tokens, $2::bigint[]) AS bucket,
+                       count(*), sum(input_tokens)::bigint, sum(output_tokens)::bigint
+                FROM request_usage
+                WHERE recorded_at >= $1::timestamp AT TIME ZONE 'UTC'
+                  AND recorded_at < ($1 + 1)::timestamp AT TIME ZONE 'UTC'
+                GROUP BY model_id, bucket
+                ORDER BY model_id, bucket
+                "#,
+        )
+        .bind(day)
+        .bind(TOKEN_BUCKET_BOUNDS.as_slice())
+        .fetch_all(self.pool()?)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(
+                |(model_id, bucket, requests, input_tokens, output_tokens)| UsageHistogramRow {
+                    day,
+                    model_id,
+                    bucket: bucket as usize,
+                    requests,
+                    input_tokens,
+                    output_tokens,
+                },
+            )
+            .collect())
+    }
+
+    async fn save_raw_exchange(&self, ids: &RequestIds, exchange: &RawExchange) -> Result<()> {
+        sqlx::query(
+            r#"
+                INSERT INTO raw_exchanges (thread_id, checkpoint_id, method, url, request_headers, request_body, status, response_headers, response_body, error)
+                VALUES ($1, $2, $3, $4, $5::jsonb, $6, $7, $8::jsonb, $9, $10)
+                "#,
+        )
+        .bind(&ids.thread_id)
+        .bind(&ids.checkpoint_id)
+        .bind(&exchange.method)
+        .bind(&exchange.url)
+        .bind(serde_json::to_string(&exchange.request_headers)?)
+        .bind(&exchange.request_body)
+        .bind(exchange.status.map(i32::from))
+        .bind(serde_json::to_string(&exchange.response_headers)?)
+        .bind(&exchange.response_body)
+        .bind(&exchange.error)
+        .execute(self.pool()?)
+        .await?;
+        Ok(())
+    }
+
+    async fn prompt_cache_stats(&self) -> Result<Vec<PromptCacheStats>> {
+        let rows: Vec<(String, Option<i32>, i64, i64, i64, i64)> = sqlx::query_as(
+            r#"
+                SELECT template_id, template_version, count(*),
+                       sum(input_tokens)::bigint, sum(cache_read_tokens)::bigint, sum(cache_creation_tokens)::bigint
+                FROM prompt_cache_usage
+                GROUP BY template_id, template_version
+                ORDER BY template_id, template_version
+                "#,
+        )
+        .fetch_all(self.pool()?)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(
+                |(
+                    id,
+                    version,
+                    completions,
+                    input_tokens,
+                    cache_read_tokens,
+                    cache_creation_tokens,
+                )| {
+                    PromptCacheStats {
+                        template: version.map(|version| PromptTemplateRef { id, version }),
+                        completions,
+                        input_tokens,
+                        cache_read_tokens,
+                        cache_creation_tokens,
+                    }
+                },
+            )
+            .collect())
+    }
+
+    async fn add_checkpoint_reference(
+        &self,
+        thread_id: &str,
+        checkpoint_id: &str,
+        referrer: &str,
+    ) -> Result<()> {
+        sqlx::query(
+            r#"
+                INSERT INTO checkpoint_references (thread_id, checkpoint_id, referrer)
+                VALUES ($1, $2, $3)
+                ON CONFLICT (thread_id, checkpoint_id, referrer) DO NOTHING
+                "#,
+        )
+        .bind(thread_id)
+        .bind(checkpoint_id)
+        .bind(referrer)
+        .execute(self.pool()?)
+        .await?;
+        Ok(())
+    }
+
+    async fn remove_checkpoint_reference(
+        &self,
+        thread_id: &str,
+        checkpoint_id: &str,
+        referrer: &str,
+    ) -> Result<()> {
+        sqlx::query(
+            r#"
+                DELETE FROM checkpoint_references
+                WHERE thread_id = $1 AND checkpoint_id = $2 AND referrer = $3
+                "#,
+        )
+        .bind(thread_id)
+        .bind(checkpoint_id)
+        .bind(referrer)
+        .execute(self.pool()?)
+        .await?;
+        Ok(())
+    }
+
+    async fn collect_garbage(&self, retention: Duration) -> Result<GarbageCollection> {
+        let started_at = Utc::now();
+        let cutoff = started_at - chrono::Duration::from_std(retention)?;
+        let mut transaction = self.pool()?.begin().await?;
+        // Checkpoints without a timestamp predate them, so their age is unknown and they are
+        // kept.
+        let rows: Vec<(String, String, i64, bool)> = sqlx::query_as(
+            r#"
+                SELECT c.thread_id, c.checkpoint_id, c.seq,
+                       COALESCE(NULLIF(c.checkpoint_ts, '')::timestamptz >= $1, true)
+                           OR EXISTS (
+                               SELECT 1
+                               FROM checkpoint_references r
+                               WHERE r.thread_id = c.thread_id AND r.checkpoint_id = c.checkpoint_id
+                           )
+                FROM ide_checkpoints c
+                FOR UPDATE OF c
+                "#,
+        )
+        .bind(cutoff)
+        .fetch_all(&mut *transaction)
+        .await?;
+        let checkpoints = rows
+            .into_iter()
+            .map(
+                |(thread_id, checkpoint_id, seq, root)| CollectedCheckpoint {
+                    thread_id,
+                    checkpoint_id,
+                    seq,
+                    root,
+                },
+            )
+            .collect::<Vec<_>>();
+        let (thread_ids, checkpoint_ids): (Vec<&str>, Vec<&str>) =
+            unreachable_checkpoints(&checkpoints)
+                .into_iter()
+                .map(|checkpoint| {
+                    (
+                        checkpoint.thread_id.as_str(),
+                        checkpoint.checkpoint_id.as_str(),
+                    )
+                })
+                .unzip();
+
+        let mut collection = GarbageCollection::default();
+        if !thread_ids.is_empty() {
+            for table in ["ide_checkpoints", "prompt_cache_usage", "raw_exchanges"] {
+                let result = sqlx::query(&format!(
+                    r#"
+                        DELETE FROM {table}
+                        WHERE (thread_id, checkpoint_id) IN (
+                            SELECT * FROM unnest($1::text[], $2::text[])
+                        )
+                        "#
+                ))
+                .bind(&thread_ids)
+                .bind(&checkpoint_ids)
+                .execute(&mut *transaction)
+                .await?;
+                if table == "ide_checkpoints" {
+                    collection.checkpoints = result.rows_affected();
+                }
+            }
+            // The writes of threads that are gone; those of the threads that are left still
+            // guard against replays.
+            sqlx::query(
+                r#"
+                    DELETE FROM ide_checkpoint_writes w
+                    WHERE w.thread_id = ANY($1)
+                      AND NOT EXISTS (SELECT 1 FROM ide_checkpoints c WHERE c.thread_id = w.thread_id)
+                    "#,
+            )
+            .bind(&thread_ids)
+            .execute(&mut *transaction)
+            .await?;
+            for table in [
+                "file_snapshots",
+                "commit_threads",
+                "thread_store_locks",
+                "thread_lock_overrides",
+                "thread_instructions",
+                "tool_call_validations",
+                "thread_branches",
+                "thread_flags",
+                "thread_summaries",
+                "thread_tags",
+                "thread_issues",
+                "run_results",
+                "fan_out_verdicts",
+            ] {
+                sqlx::query(&format!(
+                    r#"
+                        DELETE FROM {table} t
+                        WHERE t.thread_id = ANY($1)
+                          AND NOT EXISTS (SELECT 1 FROM ide_checkpoints c WHERE c.thread_id = t.thread_id)
+                        "#
+                ))
+                .bind(&thread_ids)
+                .execute(&mut *transaction)
+                .await?;
+            }
+        }
+        sqlx::query(
+            r#"
+                DELETE FROM session_env s
+                WHERE s.captured_at < $1
+                  AND NOT EXISTS (SELECT 1 FROM ide_checkpoints c WHERE c.session_id = s.session_id)
+                "#,
+        )
+        .bind(cutoff)
+        .execute(&mut *transaction)
+        .await?;
+        transaction.commit().await?;
+
+        collection.content_blobs = self.collect_content_blobs(started_at).await?;
+        Ok(collection)
+    }
+
+    async fn maintain_partitions(
+        &self,
+        partitioning: &CheckpointPartitioning,
+    ) -> Result<PartitionMaintenance> {
+        if !self.partitioned.load(Ordering::SeqCst) {
+            log::info!("Partitioning ide_checkpoints by month");
+            self.partition_by_month().await?;
+        }
+        let created = self.create_partitions(partitioning).await?;
+        let attached = self.attached_partitions().await?;
+        let mut detached = Vec::new();
+        for name in partitions_to_detach(&attached, Utc::now().date_naive(), partitioning) {
+            sqlx::raw_sql(&format!(
+                "ALTER TABLE ide_checkpoints DETACH PARTITION {name}"
+            ))
+            .execute(self.pool()?)
+            .await?;
+            detached.push(name.clone());
+        }
+        Ok(PartitionMaintenance { created, detached })
+    }
+
+    async fn maintenance_report(&self) -> Result<MaintenanceReport> {
+        let tables: Vec<(
+            String,
+            i64,
+            i64,
+            i64,
+            Option<DateTime<Utc>>,
+            Option<DateTime<Utc>>,
+        )> = sqlx::query_as(
+            r#"
+                SELECT relname::text, n_live_tup, n_dead_tup, pg_total_relation_size(relid),
+                       greatest(last_vacuum, last_autovacuum),
+                       greatest(last_analyze, last_autoanalyze)
+                FROM pg_stat_user_tables
+                WHERE schemaname = current_schema()
+                ORDER BY relname
+                "#,
+        )
+        .fetch_all(self.pool()?)
+        .await?;
+        let indexes: Vec<(String, String, i64, i64, bool)> = sqlx::query_as(
+            r#"
+                SELECT s.relname::text, s.indexrelname::text, s.idx_scan,
+                       pg_relation_size(s.indexrelid), i.indisunique
+                FROM pg_stat_user_indexes s
+                JOIN pg_index i ON i.indexrelid = s.indexrelid
+                WHERE s.schemaname = current_schema()
+                ORDER BY s.relname, s.indexrelname
+                "#,
+        )
+        .fetch_all(self.pool()?)
+        .await?;
+        Ok(MaintenanceReport {
+            tables: tables
+                .into_iter()
+                .map(
+                    |(table, live_tuples, dead_tuples, total_bytes, last_vacuum, last_analyze)| {
+                        TableHealth {
+                            table,
+                            live_tuples,
+                            dead_tuples,
+                            total_bytes,
+                            last_vacuum,
+                            last_analyze,
+                        }
+                    },
+                )
+                .collect(),
+            indexes: indexes
+                .into_iter()
+                .map(|(table, index, scans, bytes, unique)| IndexUsage {
+                    table,
+                    index,
+                    scans,
+                    bytes,
+                    unique,
+                })
+                .collect(),
+        })
+    }
+
+    async fn run_maintenance(&self, statement: &str) -> Result<()> {
+        // Statements run through the simple query protocol aren't wrapped in a transaction,
+        // which `VACUUM` can't run in.
+        sqlx::raw_sql(statement).execute(self.pool()?).await?;
+        Ok(())
+    }
+
+    async fn save_session_env(&self, session_id: &str, env: &SessionEnvironment) -> Result<()> {
+        sqlx::query(
+            r#"
+                INSERT INTO session_env (session_id, thread_id, toolchains, env)
+                VALUES ($1, $2, $3::jsonb, $4::jsonb)
+                ON CONFLICT (session_id) DO UPDATE
+                SET toolchains = session_env.toolchains || EXCLUDED.toolchains,
+                    env = EXCLUDED.env,
+                    captured_at = now()
+                "#,
+        )
+        .bind(session_id)
+        .bind(&env.thread_id)
+        .bind(serde_json::to_string(&env.toolchains)?)
+        .bind(serde_json::to_string(&env.env)?)
+        .execute(self.pool()?)
+        .await?;
+        Ok(())
+    }
+
+    async fn session_env(&self, session_id: &str) -> Result<Option<SessionEnvironment>> {
+        let row: Option<(String, String, String, Option<String>)> = sqlx::query_as(
+            r#"
+                SELECT thread_id, toolchains::text, env::text, sampling::text
+                FROM session_env
+                WHERE session_id = $1
+                "#,
+        )
+        .bind(session_id)
+        .fetch_optional(self.pool()?)
+        .await?;
+        row.map(|(thread_id, toolchains, env, sampling)| {
+            Ok(SessionEnvironment {
+                thread_id,
+                toolchains: serde_json::from_str(&toolchains)?,
+                env: serde_json::from_str(&env)?,
+                sampling: sampling
+                    .map(|sampling| serde_json::from_str(&sampling))
+                    .transpose()?,
+            })
+        })
+        .transpose()
+    }
+
+    async fn save_sampling_decision(
+        &self,
+        session_id: &str,
+        thread_id: &str,
+        decision: &SamplingDecision,
+    ) -> Result<()> {
+        // A session whose environment wasn't captured yet gets an empty snapshot, which the
+        // first capture fills in.
+        sqlx::query(
+            r#"
+                INSERT INTO session_env (session_id, thread_id, toolchains, env, sampling)
+                VALUES ($1, $2, '{}'::jsonb, '{}'::jsonb, $3::jsonb)
+                ON CONFLICT (session_id) DO UPDATE
+                SET sampling = CASE
+                    WHEN session_env.sampling->>'reason' IS NOT NULL THEN session_env.sampling
+                    ELSE EXCLUDED.sampling
+                END
+                "#,
+        )
+        .bind(session_id)
+        .bind(thread_id)
+        .bind(serde_json::to_string(decision)?)
+        .execute(self.pool()?)
+        .await?;
+        Ok(())
+    }
+
+    async fn save_file_snapshots(&self, thread_id: &str, snapshots: &[FileSnapshot]) -> Result<()> {
+        let (paths, hashes): (Vec<&str>, Vec<Option<&str>>) = snapshots
+            .iter()
+            .map(|snapshot| (snapshot.path.as_str(), snapshot.hash.as_deref()))
+            .unzip();
+        sqlx::query(
+            r#"
+                INSERT INTO file_snapshots (thread_id, path, hash)
+                SELECT $1, * FROM unnest($2::text[], $3::text[])
+                ON CONFLICT (thread_id, path) DO UPDATE
+                SET hash = EXCLUDED.hash,
+                    recorded_at = now()
+                "#,
+        )
+        .bind(thread_id)
+        .bind(paths)
+        .bind(hashes)
+        .execute(self.pool()?)
+        .await?;
+        Ok(())
+    }
+
+    async fn file_snapshots(&self, thread_id: &str) -> Result<Vec<FileSnapshot>> {
+        let rows: Vec<(String, Option<String>)> = sqlx::query_as(
+            r#"
+                SELECT path, hash
+                FROM file_snapshots
+                WHERE thread_id = $1
+                ORDER BY path
+                "#,
+        )
+        .bind(thread_id)
+        .fetch_all(self.pool()?)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|(path, hash)| FileSnapshot { path, hash })
+            .collect())
+    }
+
+    async fn threads_referencing_file(&self, path: &str, limit: usize) -> Result<Vec<String>> {
+        let rows: Vec<(String,)> = sqlx::query_as(
+            r#"
+                SELECT thread_id
+                FROM file_snapshots
+                WHERE path = $1
+                ORDER BY recorded_at DESC
+                LIMIT $2
+                "#,
+        )
+        .bind(path)
+        .bind(limit as i64)
+        .fetch_all(self.pool()?)
+        .await?;
+        Ok(rows.into_iter().map(|(thread_id,)| thread_id).collect())
+    }
+
+    async fn save_commit_thread(&self, sha: &str, thread_id: &str) -> Result<()> {
+        sqlx::query(
+            r#"
+                INSERT INTO commit_threads (sha, thread_id)
+                VALUES ($1, $2)
+                ON CONFLICT (sha) DO UPDATE
+                SET thread_id = EXCLUDED.thread_id,
+                    recorded_at = now()
+                "#,
+        )
+        .bind(sha)
+        .bind(thread_id)
+        .execute(self.pool()?)
+        .await?;
+        Ok(())
+    }
+
+    async fn commit_thread(&self, sha: &str) -> Result<Option<String>> {
+        let row: Option<(String,)> = sqlx::query_as(
+            r#"
+                SELECT thread_id
+                FROM commit_threads
+                WHERE sha = $1
+                "#,
+        )
+        .bind(sha)
+        .fetch_optional(self.pool()?)
+        .await?;
+        Ok(row.map(|(thread_id,)| thread_id))
+    }
+
+    async fn acquire_store_thread_lock(
+        &self,
+        thread_id: &str,
+        session_id: &str,
+        author: Option<&MessageAuthor>,
+    ) -> Result<Option<StoreLockHolder>> {
+        let acquired: Option<(String,)> = sqlx::query_as(
+            r#"
+                INSERT INTO thread_store_locks (thread_id, session_id, author)
+                VALUES ($1, $2, $3::jsonb)
+                ON CONFLICT (thread_id) DO UPDATE
+                SET session_id = EXCLUDED.session_id,
+                    author = EXCLUDED.author,
+                    acquired_at = CASE
+                        WHEN thread_store_locks.session_id = EXCLUDED.session_id
+                        THEN thread_store_locks.acquired_at
+                        ELSE now()
+                    END,
+                    renewed_at = now()
+                WHERE thread_store_locks.session_id = EXCLUDED.session_id
+                    OR thread_store_locks.renewed_at < now() - make_interval(mins => $4)
+                RETURNING thread_id
+                "#,
+        )
+        .bind(thread_id)
+        .bind(session_id)
+        .bind(author.map(serde_json::to_string).transpose()?)
+        .bind(STORE_LOCK_LAPSE_MINUTES as i32)
+        .fetch_optional(self.pool()?)
+        .await?;
+        if acquired.is_some() {
+            return Ok(None);
+        }
+        let holder: Option<StoreLockHolderRow> = sqlx::query_as(
+            r#"
+                SELECT thread_id, session_id, author::text, acquired_at, renewed_at
+                FROM thread_store_locks
+                WHERE thread_id = $1
+                "#,
+        )
+        .bind(thread_id)
+        .fetch_optional(self.pool()?)
+        .await?;
+        holder.map(store_lock_holder).transpose()
+    }
+
+    async fn release_store_thread_lock(&self, thread_id: &str, session_id: &str) -> Result<()> {
+        sqlx::query(
+            r#"
+                DELETE FROM thread_store_locks
+                WHERE thread_id = $1 AND session_id = $2
+                "#,
+        )
+        .bind(thread_id)
+        .bind(session_id)
+        .execute(self.pool()?)
+        .await?;
+        Ok(())
+    }
+
+    async fn store_thread_lock_holders(
+        &self,
+        thread_ids: &[String],
+    ) -> Result<Vec<StoreLockHolder>> {
+        let rows: Vec<StoreLockHolderRow> = sqlx::query_as(
+            r#"
+                SELECT thread_id, session_id, author::text, acquired_at, renewed_at
+                FROM thread_store_locks
+                WHERE thread_id = ANY($1)
+                    AND renewed_at >= now() - make_interval(mins => $2)
+                "#,
+        )
+        .bind(thread_ids)
+        .bind(STORE_LOCK_LAPSE_MINUTES as i32)
+        .fetch_all(self.pool()?)
+        .await?;
+        rows.into_iter().map(store_lock_holder).collect()
+    }
+
+    async fn override_store_thread_lock(
+        &self,
+        thread_id: &str,
+        action: LockOverride,
+        author: Option<&MessageAuthor>,
+    ) -> Result<Option<StoreLockHolder>> {
+        let mut transaction = self.pool()?.begin().await?;
+        let previous: Option<StoreLockHolderRow> = sqlx::query_as(
+            r#"
+                DELETE FROM thread_store_locks
+                WHERE thread_id = $1
+                RETURNING thread_id, session_id, author::text, acquired_at, renewed_at
+                "#,
+        )
+        .bind(thread_id)
+        .fetch_optional(&mut *transaction)
+        .await?;
+        let previous = previous.map(store_lock_holder).transpose()?;
+        sqlx::query(
+            r#"
+                INSERT INTO thread_lock_overrides (
+                    thread_id, action, previous_session_id, previous_author, author
+                )
+                VALUES ($1, $2, $3, $4::jsonb, $5::jsonb)
+                "#,
+        )
+        .bind(thread_id)
+        .bind(action.as_str())
+        .bind(previous.as_ref().map(|holder| holder.session_id.clone()))
+        .bind(
+            previous
+                .as_ref()
+                .and_then(|holder| holder.author.as_ref())
+                .map(serde_json::to_string)
+                .transpose()?,
+        )
+        .bind(author.map(serde_json::to_string).transpose()?)
+        .execute(&mut *transaction)
+        .await?;
+        transaction.commit().await?;
+        Ok(previous)
+    }
+
+    async fn save_tool_schemas(&self, schemas: &[ToolSchema]) -> Result<()> {
+        let hashes = schemas.iter().map(ToolSchema::hash).collect::<Vec<_>>();
+        let names = schemas
+            .iter()
+            .map(|schema| schema.name.as_str())
+            .collect::<Vec<_>>();
+        let descriptions = schemas
+            .iter()
+            .map(|schema| schema.description.as_str())
+            .collect::<Vec<_>>();
+        let input_schemas = schemas
+            .iter()
+            .map(|schema| schema.input_schema.to_string())
+            .collect::<Vec<_>>();
+        sqlx::query(
+            r#"
+                INSERT INTO tool_schemas (hash, name, description, input_schema)
+                SELECT hash, name, description, input_schema::jsonb
+                FROM unnest($1::text[], $2::text[], $3::text[], $4::text[])
+                    AS s(hash, name, description, input_schema)
+                ON CONFLICT (hash) DO NOTHING
+                "#,
+        )
+        .bind(hashes)
+        .bind(names)
+        .bind(descriptions)
+        .bind(input_schemas)
+        .execute(self.pool()?)
+        .await?;
+        Ok(())
+    }
+
+    async fn tool_schemas(&self, hashes: &[String]) -> Result<Vec<ToolSchema>> {
+        let rows: Vec<(String, String, String)> = sqlx::query_as(
+            r#"
+                SELECT name, description, input_schema::text
+                FROM tool_schemas
+                WHERE hash = ANY($1)
+                "#,
+        )
+        .bind(hashes)
+        .fetch_all(self.pool()?)
+        .await?;
+        rows.into_iter()
+            .map(|(name, description, input_schema)| {
+                Ok(ToolSchema {
+                    name,
+                    description,
+                    input_schema: serde_json::from_str(&input_schema)?,
+                })
+            })
+            .collect()
+    }
+
+    async fn save_tool_call_validation(&self, validation: &ToolCallValidation) -> Result<()> {
+        sqlx::query(
+            r#"
+                INSERT INTO tool_call_validations (
+                    thread_id, tool_use_id, checkpoint_id, tool_name, schema_hash, provider,
+                    model_id, valid, errors
+                )
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9::jsonb)
+                ON CONFLICT (thread_id, tool_use_id) DO UPDATE
+                SET checkpoint_id = EXCLUDED.checkpoint_id,
+                    tool_name = EXCLUDED.tool_name,
+                    schema_hash = EXCLUDED.schema_hash,
+                    provider = EXCLUDED.provider,
+                    model_id = EXCLUDED.model_id,
+                    valid = EXCLUDED.valid,
+                    errors = EXCLUDED.errors,
+                    recorded_at = now()
+                "#,
+        )
+        .bind(&validation.thread_id)
+        .bind(&validation.tool_use_id)
+        .bind(&validation.checkpoint_id)
+        .bind(&validation.tool_name)
+        .bind(&validation.schema_hash)
+        .bind(&validation.provider)
+        .bind(&validation.model_id)
+        .bind(validation.is_valid())
+        .bind(serde_json::to_string(&validation.errors)?)
+        .execute(self.pool()?)
+        .await?;
+        Ok(())
+    }
+
+    async fn threads_with_invalid_tool_calls(
+        &self,
+        provider: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<InvalidToolCallThread>> {
+        let rows: Vec<InvalidToolCallThreadRow> = sqlx::query_as(
+            r#"
+                SELECT thread_id, provider, model_id,
+                       count(*) FILTER (WHERE NOT valid),
+                       count(*),
+                       max(recorded_at) FILTER (WHERE NOT valid) AS last_invalid_at
+                FROM tool_call_validations
+                WHERE $1::text IS NULL OR provider = $1
+                GROUP BY thread_id, provider, model_id
+                HAVING bool_or(NOT valid)
+                ORDER BY last_invalid_at DESC
+                LIMIT $2
+                "#,
+        )
+        .bind(provider)
+        .bind(limit as i64)
+        .fetch_all(self.pool()?)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(
+                |(thread_id, provider, model_id, invalid_calls, checked_calls, last_invalid_at)| {
+                    InvalidToolCallThread {
+                        thread_id,
+                        provider,
+                        model_id,
+                        invalid_calls: invalid_calls as usize,
+                        checked_calls: checked_calls as usize,
+                        last_invalid_at,
+                    }
+                },
+            )
+            .collect())
+    }
+
+    async fn save_thread_instructions(
+        &self,
+        thread_id: &str,
+        instructions: &ThreadInstructions,
+    ) -> Result<()> {
+        sqlx::query(
+            r#"
+                INSERT INTO thread_instructions (thread_id, profile_id, profile_name, tools, rules)
+                VALUES ($1, $2, $3, $4::jsonb, $5::jsonb)
+                "#,
+        )
+        .bind(thread_id)
+        .bind(&instructions.profile_id)
+        .bind(&instructions.profile_name)
+        .bind(serde_json::to_string(&instructions.tools)?)
+        .bind(serde_json::to_string(&instructions.rules)?)
+        .execute(self.pool()?)
+        .await?;
+        Ok(())
+    }
+
+    async fn thread_instructions(&self, thread_id: &str) -> Result<Vec<RecordedInstructions>> {
+        let rows: Vec<(DateTime<Utc>, String, String, String, String)> = sqlx::query_as(
+            r#"
+                SELECT recorded_at, profile_id, profile_name, tools::text, rules::text
+                FROM thread_instructions
+                WHERE thread_id = $1
+                ORDER BY recorded_at
+                "#,
+        )
+        .bind(thread_id)
+        .fetch_all(self.pool()?)
+        .await?;
+        rows.into_iter()
+            .map(|(recorded_at, profile_id, profile_name, tools, rules)| {
+                Ok(RecordedInstructions {
+                    recorded_at,
+                    instructions: ThreadInstructions {
+                        profile_id,
+                        profile_name,
+                        tools: serde_json::from_str(&tools)?,
+                        rules: serde_json::from_str(&rules)?,
+                    },
+                })
+            })
+            .collect()
+    }
+
+    async fn save_run_result(&self, result: &RunResult) -> Result<()> {
+        sqlx::query(
+            r#"
+                INSERT INTO run_results (thread_id, schedule_id, status, error, finished_at)
+                VALUES ($1, $2, $3, $4, $5)
+                ON CONFLICT (thread_id) DO UPDATE
+                SET status = EXCLUDED.status,
+                    error = EXCLUDED.error,
+                    finished_at = EXCLUDED.finished_at
+                "#,
+        )
+        .bind(&result.thread_id)
+        .bind(&result.schedule_id)
+        .bind(result.status.as_str())
+        .bind(&result.error)
+        .bind(result.finished_at)
+        .execute(self.pool()?)
+        .await?;
+        Ok(())
+    }
+
+    async fn run_results(&self, limit: usize) -> Result<Vec<RunResult>> {
+        let rows: Vec<RunResultRow> = sqlx::query_as(
+            r#"
+                SELECT thread_id, schedule_id, status, error, finished_at
+                FROM run_results
+                ORDER BY finished_at DESC
+                LIMIT $1
+                "#,
+        )
+        .bind(limit as i64)
+        .fetch_all(self.pool()?)
+        .await?;
+        rows.into_iter()
+            .map(|(thread_id, schedule_id, status, error, finished_at)| {
+                Ok(RunResult {
+                    status: RunStatus::parse(&status)
+                        .with_context(|| format!("unknown run status {status}"))?,
+                    thread_id,
+                    schedule_id,
+                    error,
+                    finished_at,
+                })
+            })
+            .collect()
+    }
+
+    async fn save_fan_out_verdict(&self, verdict: &FanOutVerdict) -> Result<()> {
+        sqlx::query(
+            r#"
+                INSERT INTO fan_out_verdicts (group_id, thread_id, ranking, rationale)
+                VALUES ($1, $2, $3::jsonb, $4)
+                ON CONFLICT (group_id) DO UPDATE
+                SET thread_id = EXCLUDED.thread_id,
+                    ranking = EXCLUDED.ranking,
+                    rationale = EXCLUDED.rationale,
+                    created_at = now()
+                "#,
+        )
+        .bind(&verdict.group_id)
+        .bind(&verdict.judge_thread_id)
+        .bind(serde_json::to_string(&verdict.ranking)?)
+        .bind(&verdict.rationale)
+        .execute(self.pool()?)
+        .await?;
+        Ok(())
+    }
+
+    async fn fan_out_verdict(&self, group_id: &str) -> Result<Option<FanOutVerdict>> {
+        let row: Option<(String, String, String)> = sqlx::query_as(
+            r#"
+                SELECT thread_id, ranking::text, rationale
+                FROM fan_out_verdicts
+                WHERE group_id = $1
+                "#,
+        )
+        .bind(group_id)
+        .fetch_optional(self.pool()?)
+        .await?;
+        row.map(|(judge_thread_id, ranking, rationale)| {
+            Ok(FanOutVerdict {
+                group_id: group_id.to_string(),
+                judge_thread_id,
+                ranking: serde_json::from_str(&ranking)?,
+                rationale,
+            })
+        })
+        .transpose()
+    }
+
+    async fn load_thread(&self, thread_id: &str) -> Result<Vec<Message>> {
+        let blobs: Vec<(Vec<u8>,)> = sqlx::query_as(
+            r#"
+                SELECT blob
+                FROM ide_checkpoints
+                WHERE thread_id = $1 AND NOT compacted
+                ORDER BY seq
+                "#,
+        )
+        .bind(thread_id)
+        .fetch_all(self.pool()?)
+        .await?;
+
+        let blobs = blobs.into_iter().map(|(blob,)| blob).collect::<Vec<_>>();
+        let mut conn = self.pool()?.acquire().await?;
+        Ok(decode_checkpoints(&mut conn, &blobs)
+            .await?
+            .into_iter()
+            .flatten()
+            .collect())
+    }
+
+    async fn stored_token_count(&self, thread_id: &str) -> Result<i64> {
+        let (token_count,): (i64,) = sqlx::query_as(
+            r#"
+                SELECT COALESCE(sum(token_count), 0)::bigint
+                FROM ide_checkpoints
+                WHERE thread_id = $1 AND NOT compacted
+                "#,
+        )
+        .bind(thread_id)
+        .fetch_one(self.pool()?)
+        .await?;
+        Ok(token_count)
+    }
+
+    async fn uncompacted_messages(&self, thread_id: &str) -> Result<Vec<StoredMessage>> {
+        let rows: Vec<(i64, Vec<u8>)> = sqlx::query_as(
+            r#"
+                SELECT seq, blob
+                FROM ide_checkpoints
+                WHERE thread_id = $1 AND NOT compacted
+                ORDER BY seq
+                "#,
+        )
+        .bind(thread_id)
+        .fetch_all(self.pool()?)
+        .await?;
+
+        let (seqs, blobs): (Vec<i64>, Vec<Vec<u8>>) = rows.into_iter().unzip();
+        let mut conn = self.pool()?.acquire().await?;
+        let checkpoints = decode_checkpoints(&mut conn, &blobs).await?;
+        Ok(seqs
+            .into_iter()
+            .zip(checkpoints)
+            .flat_map(|(seq, messages)| {
+                messages
+                    .into_iter()
+                    .enumerate()
+                    .map(move |(index, message)| StoredMessage {
+                        seq,
+                        index,
+                        message,
+                    })
+            })
+            .collect())
+    }
+
+    async fn compact_thread(
+        &self,
+        summary: Message,
+        ids: &RequestIds,
+        author: Option<&MessageAuthor>,
+        through_seq: i64,
+        through_sequence: Option<u64>,
+    ) -> Result<u64> {
+        let mut transaction = self.pool()?.begin().await?;
+        // Locks the thread's counter, so that nothing is stored in the thread until the summary
+        // is.
+        let (next,): (i64,) = sqlx::query_as(
+            r#"
+                INSERT INTO thread_sequences (thread_id, next)
+                VALUES ($1, 0)
+                ON CONFLICT (thread_id) DO UPDATE SET next = thread_sequences.next
+                RETURNING next
+                "#,
+        )
+        .bind(&ids.thread_id)
+        .fetch_one(&mut *transaction)
+        .await?;
+        if next != through_sequence.map_or(0, |sequence| sequence as i64 + 1) {
+            log::debug!(
+                "Thread {} was written to while it was summarized, so it isn't compacted",
+                ids.thread_id
+            );
+            return Ok(0);
+        }
+        let result = sqlx::query(
+            r#"
+                UPDATE ide_checkpoints
+                SET compacted = true
+                WHERE thread_id = $1 AND seq <= $2 AND NOT compacted
+                "#,
+        )
+        .bind(&ids.thread_id)
+        .bind(through_seq)
+        .execute(&mut *transaction)
+        .await?;
+        self.append_messages(transaction, vec![summary], ids, author)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    fn stream_messages(&self, thread_id: &str) -> BoxStream<'static, Result<Message>> {
+        let pool = match self.pool() {
+            Ok(pool) => pool.clone(),
+            Err(error) => return stream::once(async { Err(error) }).boxed(),
+        };
+        stream::try_unfold(
+            MessageCursor::Start {
+                pool,
+                thread_id: thread_id.to_string(),
+            },
+            next_message,
+        )
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod test_db_client {
+    use crate::RequestIds;
+    use crate::message_handler::{
+        CONNECTION_STRING_VAR, ContentValue, DatabaseClient, Message, MessageAuthor,
+        PostgresDatabaseClient, message_sequence,
+    };
+    use futures::TryStreamExt as _;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_append_messages() {
+        let parsed = PostgresDatabaseClient::_parse_task_path(&vec![Message::Ai {
+            content: ContentValue::Single("hello".to_string()),
+            id: "".to_string(),
+            name: None,
+            example: false,
+            invalid_tool_calls: None,
+            tool_calls: None,
+            additional_kwargs: Default::default(),
+            response_metadata: [("intent".to_string(), serde_json::Value::String("ThreadSummarization".to_string()))].into_iter().collect::<HashMap<String, serde_json::Value>>(),
+        }]);
+
+        assert_eq!(parsed, "summarization");
+    }
+
+    /// Appends batches to one checkpoint all at once, against the store `ZED_LLM_POSTGRES_URL`
+    /// names. Skipped when it names none.
+    #[test]
+    fn test_concurrent_appends_keep_every_message_in_order() {
+        let Ok(connection_string) = std::env::var(CONNECTION_STRING_VAR) else {
+            return;
+        };
+        futures::executor::block_on(async {
+            let client = PostgresDatabaseClient::new(&connection_string)
+                .await
+                .unwrap();
+            let ids = RequestIds {
+                thread_id: format!("append-test-{}", uuid::Uuid::new_v4()),
+                checkpoint_id: uuid::Uuid::new_v4().to_string(),
+                session_id: "session".to_string(),
+                prompt_id: "prompt".to_string(),
+            };
+            let batches = (0..8).map(|batch| {
+                (0..4)
+                    .map(|ix| Message::Ai {
+                        content: ContentValue::new(format!("{batch}:{ix}")),
+                        id: ids.thread_id.clone(),
+                        name: None,
+                        example: false,
+                        invalid_tool_calls: None,
+                        tool_calls: None,
+                        additional_kwargs: Default::default(),
+                        response_metadata: Default::default(),
+                    })
+                    .collect::<Vec<_>>()
+            });
+            futures::future::try_join_all(
+                batches.map(|messages| client.save_append_messages(messages, &ids, None)),
+            )
+            .await
+            .unwrap();
+
+            let loaded = client.load_thread(&ids.thread_id).await.unwrap();
+            let streamed = client
+                .stream_messages(&ids.thread_id)
+                .try_collect::<Vec<_>>()
+                .await
+                .unwrap();
+            assert_eq!(
+                serde_json::to_value(&loaded).unwrap(),
+                serde_json::to_value(&streamed).unwrap()
+            );
+            let sequences = loaded.iter().map(message_sequence).collect::<Vec<_>>();
+            assert_eq!(sequences, (0..32).map(Some).collect::<Vec<_>>());
+            // Each batch is numbered in one go, so its messages stay together and in order.
+            let contents = loaded
+                .iter()
+                .map(|message| match message.content() {
+                    ContentValue::Single(content) => content.clone(),
+                    ContentValue::Multiple(contents) => contents.join(""),
+                })
+                .collect::<Vec<_>>();
+            for batch in contents.chunks(4) {
+                let (batch_id, _) = batch[0].split_once(':').unwrap();
+                let expected = (0..4)
+                    .map(|ix| format!("{batch_id}:{ix}"))
+                    .collect::<Vec<_>>();
+                assert_eq!(batch, expected);
+            }
+        });
+    }
+
+    /// Saves quoted values from two authors to one thread, against the store
+    /// `ZED_LLM_POSTGRES_URL` names. Skipped when it names none.
+    #[test]
+    fn test_threads_keep_their_values_and_first_author() {
+        let Ok(connection_string) = std::env::var(CONNECTION_STRING_VAR) else {
+            return;
+        };
+        futures::executor::block_on(async {
+            let client = PostgresDatabaseClient::new(&connection_string)
+                .await
+                .unwrap();
+            let thread_id = format!("author-test-{}", uuid::Uuid::new_v4());
+            let author = |id: &str, name: &str| MessageAuthor {
+                id: id.to_string(),
+                name: Some(name.to_string()),
+                email: None,
+            };
+            for (author, content) in [
+                (
+                    author("obrien@example.com", "Siobhan O'Brien"),
+                    "Don't break the build",
+                ),
+                (author("ada@example.com", "Ada"), "It's fixed"),
+            ] {
+                let ids = RequestIds {
+                    thread_id: thread_id.clone(),
+                    checkpoint_id: uuid::Uuid::new_v4().to_string(),
+                    session_id: "session".to_string(),
+                    prompt_id: "prompt".to_string(),
+                };
+                let message = Message::Human {
+                    content: ContentValue::new(content.to_string()),
+                    id: thread_id.clone(),
+                    name: None,
+                    example: false,
+                    additional_kwargs: Default::default(),
+                    response_metadata: Default::default(),
+                };
+                client
+                    .save_append_messages(vec![message], &ids, Some(&author))
+                    .await
+                    .unwrap();
+            }
+
+            let thread = client.get_thread(&thread_id).await.unwrap().unwrap();
+            assert_eq!(thread.author_id, "obrien@example.com");
+            assert_eq!(thread.author_name, "Siobhan O'Brien");
+            let mut contributors = thread.contributors.clone();
+            contributors.sort();
+            assert_eq!(contributors, ["ada@example.com", "obrien@example.com"]);
+            let contents = client
+                .load_thread(&thread_id)
+                .await
+                .unwrap()
+                .iter()
+                .map(|message| serde_json::to_value(message.content()).unwrap())
+                .collect::<Vec<_>>();
+            assert_eq!(contents, ["Don't break the build", "It's fixed"]);
+        });
+    }
+}
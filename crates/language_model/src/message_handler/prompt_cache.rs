@@ -0,0 +1,66 @@
+use super::PromptTemplateRef;
+use crate::TokenUsage;
+
+/// How much of the prompts sent with one version of a template was read from the provider's
+/// prompt cache, summed over every completion whose provider reported usage.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PromptCacheStats {
+    /// `None` for the completions requested without a template.
+    pub template: Option<PromptTemplateRef>,
+    pub completions: i64,
+    /// Prompt tokens that were neither read from nor written to the cache.
+    pub input_tokens: i64,
+    pub cache_read_tokens: i64,
+    pub cache_creation_tokens: i64,
+}
+
+impl PromptCacheStats {
+    /// The share of prompt tokens read from the cache, from 0 to 1. A template whose prefix
+    /// changes between requests, e.g. by interpolating something early on, scores low.
+    pub fn hit_rate(&self) -> f64 {
+        let prompt_tokens = self.input_tokens + self.cache_read_tokens + self.cache_creation_tokens;
+        if prompt_tokens == 0 {
+            return 0.;
+        }
+        self.cache_read_tokens as f64 / prompt_tokens as f64
+    }
+}
+
+/// What one completion's prompt did with the cache, as recorded for [`PromptCacheStats`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct PromptCacheUsage {
+    pub template: Option<PromptTemplateRef>,
+    pub input_tokens: i64,
+    pub cache_read_tokens: i64,
+    pub cache_creation_tokens: i64,
+}
+
+impl PromptCacheUsage {
+    pub fn new(template: Option<PromptTemplateRef>, usage: &TokenUsage) -> Self {
+        Self {
+            template,
+            input_tokens: usage.input_tokens.into(),
+            cache_read_tokens: usage.cache_read_input_tokens.into(),
+            cache_creation_tokens: usage.cache_creation_input_tokens.into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hit_rate() {
+        let stats = |input_tokens, cache_read_tokens, cache_creation_tokens| PromptCacheStats {
+            template: None,
+            completions: 1,
+            input_tokens,
+            cache_read_tokens,
+            cache_creation_tokens,
+        };
+        assert_eq!(stats(0, 0, 0).hit_rate(), 0.);
+        assert_eq!(stats(100, 300, 0).hit_rate(), 0.75);
+        assert_eq!(stats(50, 0, 150).hit_rate(), 0.);
+    }
+}
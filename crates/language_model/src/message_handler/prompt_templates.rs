@@ -0,0 +1,67 @@
+use serde::{Deserialize, Serialize};
+
+use super::Message;
+
+/// A version of a system-prompt template, as registered in the store.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PromptTemplate {
+    pub id: String,
+    pub version: i32,
+    pub source: String,
+    pub created_at: String,
+}
+
+/// Names one version of a template, as recorded on the messages it was used for.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct PromptTemplateRef {
+    pub id: String,
+    pub version: i32,
+}
+
+/// Records the template in each message's `response_metadata`, so that results can be correlated
+/// with the prompt that produced them.
+pub(crate) fn stamp_prompt_template(messages: &mut [Message], template: &PromptTemplateRef) {
+    let Ok(template) = serde_json::to_value(template) else {
+        return;
+    };
+    for message in messages {
+        message
+            .response_metadata_mut()
+            .insert("prompt_template".to_string(), template.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message_handler::ContentValue;
+
+    #[test]
+    fn test_stamp_prompt_template() {
+        let mut messages = vec![Message::System {
+            content: ContentValue::new("You are a helpful assistant".into()),
+            id: "1".into(),
+            name: None,
+            example: false,
+            additional_kwargs: Default::default(),
+            response_metadata: Default::default(),
+        }];
+        stamp_prompt_template(
+            &mut messages,
+            &PromptTemplateRef {
+                id: "assistant_system_prompt".into(),
+                version: 3,
+            },
+        );
+        let Message::System {
+            response_metadata, ..
+        } = &messages[0]
+        else {
+            panic!("expected a system message");
+        };
+        assert_eq!(
+            response_metadata["prompt_template"],
+            serde_json::json!({ "id": "assistant_system_prompt", "version": 3 })
+        );
+    }
+}
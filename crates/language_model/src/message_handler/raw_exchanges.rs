@@ -0,0 +1,222 @@
+use futures::{AsyncRead, FutureExt as _, future::BoxFuture};
+use http_client::{
+    AsyncBody, HttpClient, Inner, Request, Response, Url,
+    http::{HeaderMap, Uri},
+};
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use super::AiMessageHandler;
+use crate::RequestIds;
+
+/// What replaces the values of headers and query parameters that may hold credentials.
+pub const SCRUBBED: &str = "[scrubbed]";
+
+/// How much of each body is kept, so that a runaway response doesn't fill the store.
+pub const MAX_RAW_BODY_LEN: usize = 4 * 1024 * 1024;
+
+/// An HTTP exchange with a provider as it went over the wire, stored next to the messages the
+/// handler mapped it to, with credentials scrubbed.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RawExchange {
+    pub method: String,
+    pub url: String,
+    pub request_headers: Vec<(String, String)>,
+    pub request_body: String,
+    pub status: Option<u16>,
+    pub response_headers: Vec<(String, String)>,
+    pub response_body: String,
+    /// Why the response, or all of its body, wasn't received.
+    pub error: Option<String>,
+}
+
+/// Sends a checkpoint's requests through `inner`, and saves each exchange under the checkpoint
+/// once its response has been read.
+pub(crate) struct RawExchangeCapture {
+    pub inner: Arc<dyn HttpClient>,
+    pub handler: Arc<AiMessageHandler>,
+    pub ids: RequestIds,
+}
+
+impl HttpClient for RawExchangeCapture {
+    fn type_name(&self) -> &'static str {
+        self.inner.type_name()
+    }
+
+    fn proxy(&self) -> Option<&Url> {
+        self.inner.proxy()
+    }
+
+    fn send(
+        &self,
+        req: Request<AsyncBody>,
+    ) -> BoxFuture<'static, anyhow::Result<Response<AsyncBody>>> {
+        let mut exchange = RawExchange {
+            method: req.method().to_string(),
+            url: scrub_url(req.uri()),
+            request_headers: scrub_headers(req.headers()),
+            request_body: match &req.body().0 {
+                Inner::Empty => String::new(),
+                Inner::Bytes(bytes) => body_text(bytes.get_ref()),
+                Inner::AsyncReader(_) => "[streamed]".to_string(),
+            },
+            ..RawExchange::default()
+        };
+        let response = self.inner.send(req);
+        let handler = self.handler.clone();
+        let ids = self.ids.clone();
+        async move {
+            match response.await {
+                Ok(response) => {
+                    exchange.status = Some(response.status().as_u16());
+                    exchange.response_headers = scrub_headers(response.headers());
+                    let (parts, body) = response.into_parts();
+                    let body = CapturedBody {
+                        body,
+                        captured: Vec::new(),
+                        pending: Some((handler, ids, exchange)),
+                    };
+                    Ok(Response::from_parts(parts, AsyncBody::from_reader(body)))
+                }
+                Err(error) => {
+                    exchange.error = Some(format!("{error:#}"));
+                    handler.save_raw_exchange(ids, exchange);
+                    Err(error)
+                }
+            }
+        }
+        .boxed()
+    }
+}
+
+/// A response body that keeps a copy of what is read from it, and saves the exchange when it
+/// ends or is dropped.
+struct CapturedBody {
+    body: AsyncBody,
+    captured: Vec<u8>,
+    pending: Option<(Arc<AiMessageHandler>, RequestIds, RawExchange)>,
+}
+
+impl CapturedBody {
+    fn finish(&mut self, error: Option<String>) {
+        if let Some((handler, ids, mut exchange)) = self.pending.take() {
+            exchange.response_body = body_text(&self.captured);
+            exchange.error = error;
+            handler.save_raw_exchange(ids, exchange);
+        }
+    }
+}
+
+impl AsyncRead for CapturedBody {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        let result = Pin::new(&mut this.body).poll_read(cx, buf);
+        match &result {
+            Poll::Ready(Ok(0)) => this.finish(None),
+            Poll::Ready(Ok(read)) => {
+                let room = MAX_RAW_BODY_LEN.saturating_sub(this.captured.len());
+                this.captured.extend_from_slice(&buf[..(*read).min(room)]);
+            }
+            Poll::Ready(Err(error)) => this.finish(Some(error.to_string())),
+            Poll::Pending => {}
+        }
+        result
+    }
+}
+
+impl Drop for CapturedBody {
+    fn drop(&mut self) {
+        self.finish(Some("the response was dropped before it ended".to_string()));
+    }
+}
+
+fn body_text(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(&bytes[..bytes.len().min(MAX_RAW_BODY_LEN)]).into_owned()
+}
+
+/// Whether a header or query parameter may hold credentials, e.g. `Authorization`, `x-api-key`
+/// or Google's `key`, but not e.g. `anthropic-ratelimit-tokens-remaining`.
+fn is_secret(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    matches!(
+        name.as_str(),
+        "authorization" | "proxy-authorization" | "cookie" | "set-cookie"
+    ) || name.split(['-', '_']).any(|part| {
+        matches!(
+            part,
+            "key" | "apikey" | "token" | "secret" | "signature" | "password"
+        )
+    })
+}
+
+fn scrub_headers(headers: &HeaderMap) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = if is_secret(name.as_str()) {
+                SCRUBBED.to_string()
+            } else {
+                String::from_utf8_lossy(value.as_bytes()).into_owned()
+            };
+            (name.to_string(), value)
+        })
+        .collect()
+}
+
+fn scrub_url(uri: &Uri) -> String {
+    let Ok(mut url) = Url::parse(&uri.to_string()) else {
+        return uri.to_string();
+    };
+    if url.query().is_some() {
+        let pairs = url
+            .query_pairs()
+            .map(|(name, value)| {
+                let value = if is_secret(&name) {
+                    SCRUBBED.into()
+                } else {
+                    value
+                };
+                (name.into_owned(), value.into_owned())
+            })
+            .collect::<Vec<_>>();
+        url.query_pairs_mut().clear().extend_pairs(pairs);
+    }
+    url.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_client::http::HeaderValue;
+
+    #[test]
+    fn test_credentials_are_scrubbed() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-api-key", HeaderValue::from_static("sk-ant-123"));
+        headers.insert("authorization", HeaderValue::from_static("Bearer abc"));
+        headers.insert("content-type", HeaderValue::from_static("application/json"));
+        headers.insert(
+            "anthropic-ratelimit-tokens-remaining",
+            HeaderValue::from_static("1000"),
+        );
+        let headers = scrub_headers(&headers);
+        assert!(headers.contains(&("x-api-key".into(), SCRUBBED.into())));
+        assert!(headers.contains(&("authorization".into(), SCRUBBED.into())));
+        assert!(headers.contains(&("content-type".into(), "application/json".into())));
+        assert!(headers.contains(&("anthropic-ratelimit-tokens-remaining".into(), "1000".into())));
+
+        let uri: Uri = "https://example.com/v1beta/models/gemini:stream?alt=sse&key=AIza123"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            scrub_url(&uri),
+            "https://example.com/v1beta/models/gemini:stream?alt=sse&key=%5Bscrubbed%5D"
+        );
+    }
+}
@@ -0,0 +1,146 @@
+use anyhow::{Context as _, Result};
+use client::AnyProtoClient;
+use futures::{FutureExt, future::BoxFuture};
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use super::{Message, MessageAuthor};
+use crate::RequestIds;
+
+/// Stores messages somewhere other than this handler's store, e.g. through the server of a remote
+/// project when this machine can't reach the team's store.
+pub trait RemotePersistence: Send + Sync {
+    fn forward(
+        &self,
+        messages: Vec<Message>,
+        ids: RequestIds,
+        author: Option<MessageAuthor>,
+    ) -> BoxFuture<'static, Result<()>>;
+}
+
+/// Forwards messages to the message handler of a remote server over its connection.
+pub struct ProtoRemotePersistence {
+    client: AnyProtoClient,
+}
+
+impl ProtoRemotePersistence {
+    pub fn new(client: AnyProtoClient) -> Self {
+        Self { client }
+    }
+}
+
+impl RemotePersistence for ProtoRemotePersistence {
+    fn forward(
+        &self,
+        messages: Vec<Message>,
+        ids: RequestIds,
+        author: Option<MessageAuthor>,
+    ) -> BoxFuture<'static, Result<()>> {
+        let client = self.client.clone();
+        async move {
+            let request = proto::PersistCompletionMessages {
+                thread_id: ids.thread_id,
+                checkpoint_id: ids.checkpoint_id,
+                session_id: ids.session_id,
+                prompt_id: ids.prompt_id,
+                messages: serde_json::to_string(&messages)?,
+                author: author
+                    .map(|author| serde_json::to_string(&author))
+                    .transpose()?,
+            };
+            client
+                .request(request)
+                .await
+                .context("forwarding messages to the remote server")?;
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+/// Where the messages of each project are stored instead of the handler's store, by project name.
+/// Shared between handlers, so that connections can be routed without rebuilding the handler.
+#[derive(Clone, Default)]
+pub struct RemotePersistenceRoutes(Arc<Mutex<HashMap<String, Arc<dyn RemotePersistence>>>>);
+
+impl RemotePersistenceRoutes {
+    pub(crate) fn set(&self, project: String, persistence: Option<Arc<dyn RemotePersistence>>) {
+        let mut routes = self.0.lock();
+        match persistence {
+            Some(persistence) => routes.insert(project, persistence),
+            None => routes.remove(&project),
+        };
+    }
+
+    /// Where the messages are to be stored, going by the project they were sent from.
+    pub(crate) fn route(&self, messages: &[Message]) -> Option<Arc<dyn RemotePersistence>> {
+        let routes = self.0.lock();
+        if routes.is_empty() {
+            return None;
+        }
+        let project = messages
+            .iter()
+            .find_map(|message| message.response_metadata().get("project")?.as_str())?;
+        routes.get(project).cloned()
+    }
+}
+
+/// The messages and ids of a forwarded request, as the remote server's handler stores them.
+pub fn forwarded_messages(
+    request: proto::PersistCompletionMessages,
+) -> Result<(Vec<Message>, RequestIds, Option<MessageAuthor>)> {
+    let messages = serde_json::from_str(&request.messages)?;
+    let author = request
+        .author
+        .map(|author| serde_json::from_str(&author))
+        .transpose()?;
+    let ids = RequestIds {
+        thread_id: request.thread_id,
+        checkpoint_id: request.checkpoint_id,
+        session_id: request.session_id,
+        prompt_id: request.prompt_id,
+    };
+    Ok((messages, ids, author))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message_handler::ContentValue;
+
+    struct Discard;
+
+    impl RemotePersistence for Discard {
+        fn forward(
+            &self,
+            _: Vec<Message>,
+            _: RequestIds,
+            _: Option<MessageAuthor>,
+        ) -> BoxFuture<'static, Result<()>> {
+            futures::future::ready(Ok(())).boxed()
+        }
+    }
+
+    #[test]
+    fn test_route_by_project() {
+        let message = |project: &str| Message::Human {
+            content: ContentValue::new("hi".into()),
+            id: "1".into(),
+            name: None,
+            example: false,
+            additional_kwargs: HashMap::new(),
+            response_metadata: HashMap::from_iter([(
+                "project".to_string(),
+                serde_json::Value::from(project),
+            )]),
+        };
+        let routes = RemotePersistenceRoutes::default();
+        routes.set("zed".into(), Some(Arc::new(Discard)));
+        assert!(routes.route(&[message("zed")]).is_some());
+        assert!(routes.route(&[message("other")]).is_none());
+
+        routes.set("zed".into(), None);
+        assert!(routes.route(&[message("zed")]).is_none());
+    }
+}
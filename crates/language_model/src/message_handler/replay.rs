@@ -0,0 +1,87 @@
+use super::tool_pairs::request_contents;
+use super::{Message, StoredMessage};
+use crate::{LanguageModelRequestMessage, Role};
+
+/// The request message a stored message was saved from, if it was saved from one rather than
+/// from a completion event.
+fn request_message(message: &Message) -> Option<LanguageModelRequestMessage> {
+    let role = match message {
+        Message::Human { .. } => Role::User,
+        Message::System {
+            additional_kwargs, ..
+        } if !additional_kwargs.contains_key("event") => Role::System,
+        Message::Ai { .. } => Role::Assistant,
+        _ => return None,
+    };
+    Some(LanguageModelRequestMessage {
+        role,
+        content: request_contents(message.content())?,
+        cache: false,
+    })
+}
+
+/// The messages of the thread's latest stored request, which holds its history up to the last
+/// turn, so that it can be sent again, e.g. to another model.
+pub fn replay_messages(messages: &[StoredMessage]) -> Vec<LanguageModelRequestMessage> {
+    let Some(seq) = messages
+        .iter()
+        .rev()
+        .find(|message| request_message(&message.message).is_some())
+        .map(|message| message.seq)
+    else {
+        return Vec::new();
+    };
+    messages
+        .iter()
+        .filter(|message| message.seq == seq)
+        .filter_map(|message| request_message(&message.message))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MessageContent;
+
+    #[test]
+    fn test_replay_messages_of_latest_request() {
+        let message = |seq, message_type: &str, content: &str| StoredMessage {
+            seq,
+            index: 0,
+            message: serde_json::from_value::<Message>(serde_json::json!({
+                "type": message_type,
+                "content": content,
+                "id": "thread",
+                "name": null,
+                "invalid_tool_calls": null,
+                "tool_calls": null,
+            }))
+            .unwrap(),
+        };
+        let text =
+            |text: &str| serde_json::to_string(&[MessageContent::Text(text.into())]).unwrap();
+        let messages = [
+            message(1, "system", &text("You review diffs.")),
+            message(1, "human", &text("Review this.")),
+            message(1, "ai", "Looks good."),
+            message(2, "system", &text("You review diffs.")),
+            message(2, "human", &text("Review this.")),
+            message(2, "ai", &text("Looks good.")),
+            message(2, "human", &text("And this?")),
+            message(2, "ai", "It breaks the build."),
+        ];
+        let replayed = replay_messages(&messages);
+        assert_eq!(
+            replayed
+                .iter()
+                .map(|message| (message.role, message.string_contents()))
+                .collect::<Vec<_>>(),
+            [
+                (Role::System, "You review diffs.".to_string()),
+                (Role::User, "Review this.".to_string()),
+                (Role::Assistant, "Looks good.".to_string()),
+                (Role::User, "And this?".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,322 @@
+use futures::{FutureExt as _, future::BoxFuture, stream::BoxStream};
+use gpui::AsyncApp;
+use parking_lot::Mutex;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use super::{
+    FixtureMode, LanguageModelArgs, MessageHandlerRegistry, ResponseInterceptors, peek_db,
+};
+use crate::{
+    CompletionStreamObserver, LanguageModel, LanguageModelCompletionError,
+    LanguageModelCompletionEvent, LanguageModelId, LanguageModelRequest,
+};
+
+/// The hash of the model, messages, tools and temperature of the request: what the model was
+/// asked, regardless of the thread it was asked in.
+pub(crate) fn request_hash(
+    request: &LanguageModelRequest,
+    model_id: &LanguageModelId,
+) -> Option<String> {
+    let asked = serde_json::to_string(&(
+        model_id.0.as_ref(),
+        &request.messages,
+        &request.tools,
+        request.temperature,
+    ))
+    .ok()?;
+    Some(format!("{:x}", Sha256::digest(asked)))
+}
+
+/// Which requests are answered with the response to an identical request made recently, instead
+/// of being sent again.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct ResponseCachePolicy {
+    /// The intents of the requests that are cached, e.g. `ThreadSummarization`. Requests without
+    /// an intent aren't.
+    pub intents: Vec<String>,
+    /// How long a response is reused for, in seconds.
+    pub ttl_seconds: u64,
+    /// How many responses are kept. Past this, the oldest is dropped.
+    pub max_entries: usize,
+}
+
+impl Default for ResponseCachePolicy {
+    fn default() -> Self {
+        Self {
+            intents: Vec::new(),
+            ttl_seconds: 600,
+            max_entries: 256,
+        }
+    }
+}
+
+struct CachedResponse {
+    stored_at: Instant,
+    events: Vec<LanguageModelCompletionEvent>,
+}
+
+/// The completed responses to recent requests, by a hash of what the model was asked.
+pub struct ResponseCache {
+    policy: ResponseCachePolicy,
+    entries: Mutex<HashMap<String, CachedResponse>>,
+    /// The keys of the checkpoints whose responses are to be cached once they complete.
+    pending: Mutex<HashMap<String, String>>,
+}
+
+impl ResponseCache {
+    pub fn new(policy: ResponseCachePolicy) -> Self {
+        Self {
+            policy,
+            entries: Mutex::default(),
+            pending: Mutex::default(),
+        }
+    }
+
+    pub fn policy(&self) -> &ResponseCachePolicy {
+        &self.policy
+    }
+
+    /// The hash of the model, messages, tools and temperature of the request, if its intent is
+    /// cached.
+    pub fn key(
+        &self,
+        request: &LanguageModelRequest,
+        model_id: &LanguageModelId,
+    ) -> Option<String> {
+        let intent = format!("{:?}", request.intent.as_ref()?);
+        if !self.policy.intents.contains(&intent) {
+            return None;
+        }
+        request_hash(request, model_id)
+    }
+
+    /// The events of the response cached under the key, unless it has expired.
+    pub fn get(&self, key: &str) -> Option<Vec<LanguageModelCompletionEvent>> {
+        let ttl = Duration::from_secs(self.policy.ttl_seconds);
+        let mut entries = self.entries.lock();
+        entries.retain(|_, response| response.stored_at.elapsed() < ttl);
+        entries.get(key).map(|response| response.events.clone())
+    }
+
+    fn insert(&self, key: String, events: Vec<LanguageModelCompletionEvent>) {
+        let ttl = Duration::from_secs(self.policy.ttl_seconds);
+        let mut entries = self.entries.lock();
+        entries.retain(|_, response| response.stored_at.elapsed() < ttl);
+        while entries.len() >= self.policy.max_entries.max(1) {
+            let Some(oldest) = entries
+                .iter()
+                .min_by_key(|(_, response)| response.stored_at)
+                .map(|(key, _)| key.clone())
+            else {
+                break;
+            };
+            entries.remove(&oldest);
+        }
+        entries.insert(
+            key,
+            CachedResponse {
+                stored_at: Instant::now(),
+                events,
+            },
+        );
+    }
+
+    /// Caches the checkpoint's response once it completes, unless one is cached already, e.g.
+    /// because this is it being replayed.
+    pub(crate) fn expect_response(
+        &self,
+        request: &LanguageModelRequest,
+        model_id: &LanguageModelId,
+        checkpoint_id: &str,
+    ) {
+        let Some(key) = self.key(request, model_id) else {
+            return;
+        };
+        if self.get(&key).is_none() {
+            self.pending.lock().insert(checkpoint_id.to_string(), key);
+        }
+    }
+
+    /// An observer that caches the checkpoint's response, if it is expected.
+    pub(crate) fn recorder(self: &Arc<Self>, checkpoint_id: &str) -> Option<ResponseRecorder> {
+        let key = self.pending.lock().remove(checkpoint_id)?;
+        Some(ResponseRecorder {
+            cache: self.clone(),
+            key,
+            events: Mutex::default(),
+            stopped: AtomicBool::new(false),
+            failed: AtomicBool::new(false),
+        })
+    }
+}
+
+/// Collects a response's events, and caches them if the model stopped without an error.
+pub(crate) struct ResponseRecorder {
+    cache: Arc<ResponseCache>,
+    key: String,
+    events: Mutex<Vec<LanguageModelCompletionEvent>>,
+    stopped: AtomicBool,
+    failed: AtomicBool,
+}
+
+impl CompletionStreamObserver for ResponseRecorder {
+    fn on_event(&self, event: &LanguageModelCompletionEvent) {
+        if matches!(event, LanguageModelCompletionEvent::Stop(_)) {
+            self.stopped.store(true, Ordering::SeqCst);
+        }
+        self.events.lock().push(event.clone());
+    }
+
+    fn on_error(&self, _error: &LanguageModelCompletionError) {
+        self.failed.store(true, Ordering::SeqCst);
+    }
+
+    fn on_end(&self) {
+        if self.stopped.load(Ordering::SeqCst) && !self.failed.load(Ordering::SeqCst) {
+            self.cache
+                .insert(self.key.clone(), std::mem::take(&mut *self.events.lock()));
+        }
+    }
+}
+
+/// The cached response to the request, if an identical one was answered recently, replayed as
+/// if the model had sent it, and stored like any other. When fixtures are replayed, every request
+/// is answered with its fixture instead, or fails without one. Every provider checks this before
+/// it sends a request.
+pub fn cached_completion(
+    request: &LanguageModelRequest,
+    model: &dyn LanguageModel,
+    cx: &AsyncApp,
+) -> Option<
+    BoxFuture<
+        'static,
+        anyhow::Result<
+            BoxStream<'static, Result<LanguageModelCompletionEvent, LanguageModelCompletionError>>,
+        >,
+    >,
+> {
+    let handler = cx
+        .update(|cx| {
+            cx.try_global::<MessageHandlerRegistry>()?
+                .message_handler
+                .clone()
+        })
+        .ok()
+        .flatten()?;
+    let replayed = handler
+        .fixtures
+        .clone()
+        .filter(|fixtures| fixtures.mode() == FixtureMode::Replay);
+    let events = match replayed {
+        Some(fixtures) => {
+            log::debug!("Answering a request to {} from its fixture", model.id().0);
+            fixtures.load(request, &model.id())
+        }
+        None => {
+            let cache = handler.response_cache.clone()?;
+            let events = cache.get(&cache.key(request, &model.id())?)?;
+            log::debug!(
+                "Answering a request to {} from the response cache",
+                model.id().0
+            );
+            Ok(events)
+        }
+    };
+    let response_interceptors = ResponseInterceptors::for_model(model, cx);
+    let request = request.clone();
+    let ids = crate::_retrieve_ids(&request);
+    let language_model_args = LanguageModelArgs::from_request(model.id(), &request);
+    let max_tokens = model.max_token_count();
+    Some(
+        async move {
+            let events = events?;
+            handler
+                .save_completion_req(
+                    &request,
+                    &ids,
+                    language_model_args.clone().with_max_tokens(max_tokens),
+                )
+                .await;
+            Ok(response_interceptors.apply(peek_db(
+                futures::stream::iter(events.into_iter().map(Ok)),
+                Some(handler),
+                ids,
+                language_model_args,
+            )))
+        }
+        .boxed(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{LanguageModelRequestMessage, MessageContent, Role, StopReason};
+    use zed_llm_client::CompletionIntent;
+
+    #[test]
+    fn test_only_completed_responses_to_cached_intents_are_reused() {
+        let cache = Arc::new(ResponseCache::new(ResponseCachePolicy {
+            intents: vec!["ThreadSummarization".into()],
+            ..ResponseCachePolicy::default()
+        }));
+        let model_id = LanguageModelId("model".into());
+        let request = |intent, text: &str| LanguageModelRequest {
+            intent: Some(intent),
+            messages: vec![LanguageModelRequestMessage {
+                role: Role::User,
+                content: vec![MessageContent::Text(text.into())],
+                cache: false,
+            }],
+            ..Default::default()
+        };
+        let summarize = request(CompletionIntent::ThreadSummarization, "Summarize this");
+        assert!(
+            cache
+                .key(
+                    &request(CompletionIntent::UserPrompt, "Summarize this"),
+                    &model_id
+                )
+                .is_none()
+        );
+
+        cache.expect_response(&summarize, &model_id, "failed");
+        let recorder = cache.recorder("failed").unwrap();
+        recorder.on_event(&LanguageModelCompletionEvent::Text("A summ".into()));
+        recorder.on_error(&LanguageModelCompletionError::Other(anyhow::anyhow!(
+            "overloaded"
+        )));
+        recorder.on_end();
+        let key = cache.key(&summarize, &model_id).unwrap();
+        assert!(cache.get(&key).is_none());
+
+        cache.expect_response(&summarize, &model_id, "completed");
+        let recorder = cache.recorder("completed").unwrap();
+        recorder.on_event(&LanguageModelCompletionEvent::Text("A summary".into()));
+        recorder.on_event(&LanguageModelCompletionEvent::Stop(StopReason::EndTurn));
+        recorder.on_end();
+        assert_eq!(cache.get(&key).unwrap().len(), 2);
+        assert!(
+            cache
+                .get(
+                    &cache
+                        .key(
+                            &request(CompletionIntent::ThreadSummarization, "Summarize that"),
+                            &model_id
+                        )
+                        .unwrap()
+                )
+                .is_none()
+        );
+
+        cache.expect_response(&summarize, &model_id, "replayed");
+        assert!(cache.recorder("replayed").is_none());
+    }
+}
@@ -0,0 +1,101 @@
+use chrono::{DateTime, Utc};
+use futures::channel::mpsc;
+use parking_lot::Mutex;
+
+/// How a run made without a user ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunStatus {
+    Success,
+    Error,
+    /// The model stopped at its output limit, so the result may be cut short.
+    Truncated,
+    /// The run finished, but its output failed the regression test it was made for.
+    Failed,
+}
+
+impl RunStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RunStatus::Success => "success",
+            RunStatus::Error => "error",
+            RunStatus::Truncated => "truncated",
+            RunStatus::Failed => "failed",
+        }
+    }
+
+    pub fn parse(status: &str) -> Option<Self> {
+        match status {
+            "success" => Some(RunStatus::Success),
+            "error" => Some(RunStatus::Error),
+            "truncated" => Some(RunStatus::Truncated),
+            "failed" => Some(RunStatus::Failed),
+            _ => None,
+        }
+    }
+}
+
+/// A finished run made without a user, e.g. a scheduled one, and the thread it was stored as.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunResult {
+    pub thread_id: String,
+    /// The id of the scheduled run, or of the regression test, that made it.
+    pub schedule_id: String,
+    pub status: RunStatus,
+    /// What went wrong, for runs that failed.
+    pub error: Option<String>,
+    pub finished_at: DateTime<Utc>,
+}
+
+/// Hands each run result to whoever is watching, whether or not it is stored.
+#[derive(Default)]
+pub struct RunResults {
+    subscribers: Mutex<Vec<mpsc::UnboundedSender<RunResult>>>,
+}
+
+impl RunResults {
+    /// Receives the results of the runs that finish from now on, until the receiver is dropped.
+    pub fn subscribe(&self) -> mpsc::UnboundedReceiver<RunResult> {
+        let (tx, rx) = mpsc::unbounded();
+        self.subscribers.lock().push(tx);
+        rx
+    }
+
+    pub(crate) fn publish(&self, result: &RunResult) {
+        let mut subscribers = self.subscribers.lock();
+        subscribers.retain(|subscriber| !subscriber.is_closed());
+        for subscriber in subscribers.iter() {
+            subscriber.unbounded_send(result.clone()).ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_results_reach_subscribers() {
+        for status in [
+            RunStatus::Success,
+            RunStatus::Error,
+            RunStatus::Truncated,
+            RunStatus::Failed,
+        ] {
+            assert_eq!(RunStatus::parse(status.as_str()), Some(status));
+        }
+
+        let results = RunResults::default();
+        let mut subscriber = results.subscribe();
+        drop(results.subscribe());
+        let result = RunResult {
+            thread_id: "thread".into(),
+            schedule_id: "nightly-review".into(),
+            status: RunStatus::Truncated,
+            error: None,
+            finished_at: Utc::now(),
+        };
+        results.publish(&result);
+        assert_eq!(subscriber.try_next().unwrap(), Some(result));
+        assert_eq!(results.subscribers.lock().len(), 1);
+    }
+}
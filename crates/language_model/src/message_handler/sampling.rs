@@ -0,0 +1,310 @@
+use parking_lot::Mutex;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::time::{Duration, Instant};
+
+use super::Message;
+use super::bounded_map::BoundedMap;
+use super::message_rules::FilteredMessages;
+use crate::RequestIds;
+
+/// How many messages of a thread that wasn't sampled are held, in case it turns out to have to be
+/// stored after all. Past this, its earliest messages are dropped.
+const MAX_DEFERRED_MESSAGES: usize = 2048;
+
+/// How long the messages of a thread that wasn't sampled are held after it was last written to.
+/// A thread idle for longer is dropped, and decided on again if it's resumed.
+const DEFERRED_IDLE_TTL: Duration = Duration::from_secs(30 * 60);
+
+/// How many stored threads the sampler remembers the decision of.
+const MAX_STORED_THREADS: usize = 4096;
+
+/// Which threads are stored, for when storing every thread is too much.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct SamplingPolicy {
+    /// The fraction of threads stored, from 0 to 1.
+    pub rate: f32,
+    /// Whether threads whose completions fail are stored even if they weren't sampled.
+    pub always_persist_errors: bool,
+    /// Whether threads in which the model uses tools are stored even if they weren't sampled.
+    pub always_persist_tool_use: bool,
+}
+
+impl Default for SamplingPolicy {
+    fn default() -> Self {
+        Self {
+            rate: 1.,
+            always_persist_errors: true,
+            always_persist_tool_use: true,
+        }
+    }
+}
+
+impl SamplingPolicy {
+    /// Whether the thread is sampled. Like experiment variants, the pick is the same every time
+    /// for a given thread, so that a thread is stored or not across restarts.
+    pub fn samples(&self, thread_id: &str) -> bool {
+        let hash = thread_id
+            .bytes()
+            .fold(0xcbf29ce484222325_u64, |hash, byte| {
+                (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+            });
+        (hash as f64 / u64::MAX as f64) < self.rate as f64
+    }
+
+    /// Why a thread that wasn't sampled has to be stored after all, going by its latest messages.
+    fn promotes(&self, messages: &FilteredMessages) -> Option<SamplingReason> {
+        messages.messages().find_map(|message| match message {
+            Message::Tool { .. } if self.always_persist_tool_use => Some(SamplingReason::ToolUse),
+            Message::System {
+                additional_kwargs, ..
+            } if self.always_persist_errors
+                && additional_kwargs
+                    .get("event")
+                    .and_then(|event| event.as_str())
+                    == Some("completion_error") =>
+            {
+                Some(SamplingReason::Error)
+            }
+            _ => None,
+        })
+    }
+}
+
+/// Why a thread is stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SamplingReason {
+    Sampled,
+    Error,
+    ToolUse,
+}
+
+/// Whether a thread is stored, recorded in its session's metadata once it's decided, so that the
+/// threads that aren't stored are accounted for too.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SamplingDecision {
+    /// Why the thread is stored, `None` while it isn't.
+    pub reason: Option<SamplingReason>,
+    pub rate: f32,
+}
+
+/// The messages of a thread that wasn't sampled, held until it's promoted or goes idle.
+struct DeferredThread {
+    checkpoints: Vec<(FilteredMessages, RequestIds)>,
+    last_sampled: Instant,
+}
+
+/// What to do with messages the sampler was given.
+pub(crate) struct Sampled {
+    /// The messages to store now.
+    pub(crate) checkpoints: Vec<(FilteredMessages, RequestIds)>,
+    /// Set when the thread was just decided on, to record in its session's metadata.
+    pub(crate) decision: Option<SamplingDecision>,
+}
+
+/// Decides which threads are stored when they are first saved, and holds the messages of those
+/// that aren't.
+pub struct ThreadSampler {
+    policy: SamplingPolicy,
+    /// Why each of the threads decided on most recently is stored. Once evicted, a sampled thread
+    /// is sampled again, while a promoted one is held again until it's promoted again.
+    stored: BoundedMap<SamplingReason>,
+    deferred: Mutex<HashMap<String, DeferredThread>>,
+}
+
+impl ThreadSampler {
+    pub fn new(policy: SamplingPolicy) -> Self {
+        Self {
+            policy,
+            stored: BoundedMap::new(MAX_STORED_THREADS),
+            deferred: Mutex::default(),
+        }
+    }
+
+    /// The messages to store now: none while the thread isn't stored, and everything held for it
+    /// once it is promoted. Stored messages record why in their `response_metadata`.
+    pub(crate) fn sample(&self, messages: FilteredMessages, ids: &RequestIds) -> Sampled {
+        self.sample_at(messages, ids, Instant::now())
+    }
+
+    fn sample_at(&self, messages: FilteredMessages, ids: &RequestIds, now: Instant) -> Sampled {
+        let thread_id = &ids.thread_id;
+        let mut deferred = self.deferred.lock();
+        deferred.retain(|_, thread| now.duration_since(thread.last_sampled) < DEFERRED_IDLE_TTL);
+        if let Some(reason) = self.stored.get(thread_id) {
+            return Sampled {
+                checkpoints: self.stamp(vec![(messages, ids.clone())], reason),
+                decision: None,
+            };
+        }
+        let mut decision = None;
+        let thread = match deferred.entry(thread_id.clone()) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(_) if self.policy.samples(thread_id) => {
+                let reason = SamplingReason::Sampled;
+                self.stored.insert(thread_id.clone(), reason);
+                return Sampled {
+                    checkpoints: self.stamp(vec![(messages, ids.clone())], reason),
+                    decision: Some(self.decision(Some(reason))),
+                };
+            }
+            Entry::Vacant(entry) => {
+                decision = Some(self.decision(None));
+                entry.insert(DeferredThread {
+                    checkpoints: Vec::new(),
+                    last_sampled: now,
+                })
+            }
+        };
+        thread.last_sampled = now;
+        let promotion = self.policy.promotes(&messages);
+        thread.checkpoints.push((messages, ids.clone()));
+        let Some(reason) = promotion else {
+            let mut held: usize = thread
+                .checkpoints
+                .iter()
+                .map(|(messages, _)| messages.len())
+                .sum();
+            while held > MAX_DEFERRED_MESSAGES && thread.checkpoints.len() > 1 {
+                held -= thread.checkpoints.remove(0).0.len();
+            }
+            return Sampled {
+                checkpoints: Vec::new(),
+                decision,
+            };
+        };
+        let checkpoints = deferred
+            .remove(thread_id)
+            .map(|thread| thread.checkpoints)
+            .unwrap_or_default();
+        self.stored.insert(thread_id.clone(), reason);
+        Sampled {
+            checkpoints: self.stamp(checkpoints, reason),
+            decision: Some(self.decision(Some(reason))),
+        }
+    }
+
+    fn decision(&self, reason: Option<SamplingReason>) -> SamplingDecision {
+        SamplingDecision {
+            reason,
+            rate: self.policy.rate,
+        }
+    }
+
+    fn stamp(
+        &self,
+        mut checkpoints: Vec<(FilteredMessages, RequestIds)>,
+        reason: SamplingReason,
+    ) -> Vec<(FilteredMessages, RequestIds)> {
+        let sampling = serde_json::json!({ "reason": reason, "rate": self.policy.rate });
+        for (messages, _) in &mut checkpoints {
+            for message in messages.messages_mut() {
+                message
+                    .response_metadata_mut()
+                    .insert("sampling".to_string(), sampling.clone());
+            }
+        }
+        checkpoints
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message_handler::ContentValue;
+
+    fn ids(thread_id: &str, checkpoint_id: &str) -> RequestIds {
+        RequestIds {
+            thread_id: thread_id.into(),
+            checkpoint_id: checkpoint_id.into(),
+            session_id: "session".into(),
+            prompt_id: "prompt".into(),
+        }
+    }
+
+    #[test]
+    fn test_unsampled_thread_is_held_until_tool_use() {
+        let sampler = ThreadSampler::new(SamplingPolicy {
+            rate: 0.,
+            ..Default::default()
+        });
+        let human = Message::Human {
+            content: ContentValue::new("hi".into()),
+            id: "1".into(),
+            name: None,
+            example: false,
+            additional_kwargs: HashMap::new(),
+            response_metadata: HashMap::new(),
+        };
+        let tool = Message::Tool {
+            content: ContentValue::new("{}".into()),
+            id: "2".into(),
+            name: None,
+            example: false,
+            tool_call_id: None,
+            tool_name: None,
+            additional_kwargs: HashMap::new(),
+            response_metadata: HashMap::new(),
+        };
+
+        let filtered = |messages| FilteredMessages::new(None, messages);
+        let sampled = sampler.sample(filtered(vec![human.clone()]), &ids("t", "1"));
+        assert!(sampled.checkpoints.is_empty());
+        assert_eq!(sampled.decision.and_then(|decision| decision.reason), None);
+        assert!(sampled.decision.is_some());
+        let stored = sampler.sample(filtered(vec![tool]), &ids("t", "2"));
+        assert_eq!(stored.checkpoints.len(), 2);
+        assert_eq!(
+            stored.decision.and_then(|decision| decision.reason),
+            Some(SamplingReason::ToolUse)
+        );
+        let held = stored.checkpoints[0].0.messages().next().unwrap();
+        assert!(matches!(held, Message::Human { .. }));
+        assert_eq!(held.response_metadata()["sampling"]["reason"], "tool_use");
+        let stored = sampler.sample(filtered(vec![human]), &ids("t", "3"));
+        assert_eq!(stored.checkpoints.len(), 1);
+        assert!(stored.decision.is_none());
+        assert!(sampler.deferred.lock().is_empty());
+
+        assert!(SamplingPolicy::default().samples("t"));
+    }
+
+    #[test]
+    fn test_idle_deferred_threads_are_dropped() {
+        let sampler = ThreadSampler::new(SamplingPolicy {
+            rate: 0.,
+            ..Default::default()
+        });
+        let human = Message::Human {
+            content: ContentValue::new("hi".into()),
+            id: "1".into(),
+            name: None,
+            example: false,
+            additional_kwargs: HashMap::new(),
+            response_metadata: HashMap::new(),
+        };
+        let filtered = |messages| FilteredMessages::new(None, messages);
+        let start = Instant::now();
+        sampler.sample_at(filtered(vec![human.clone()]), &ids("t", "1"), start);
+        sampler.sample_at(
+            filtered(vec![human.clone()]),
+            &ids("other", "1"),
+            start + DEFERRED_IDLE_TTL / 2,
+        );
+        assert_eq!(sampler.deferred.lock().len(), 2);
+
+        let resumed = sampler.sample_at(
+            filtered(vec![human]),
+            &ids("other", "2"),
+            start + DEFERRED_IDLE_TTL,
+        );
+        assert!(resumed.decision.is_none());
+        let deferred = sampler.deferred.lock();
+        assert!(!deferred.contains_key("t"));
+        assert_eq!(deferred["other"].checkpoints.len(), 2);
+    }
+}
@@ -0,0 +1,382 @@
+use anyhow::{Context as _, Result, anyhow};
+use chrono::{DateTime, Datelike, Duration, DurationRound as _, TimeZone as _, Timelike, Utc};
+use collections::HashMap;
+use futures::future::BoxFuture;
+use gpui::{App, AppContext as _, Subscription, Task};
+use parking_lot::Mutex;
+use std::{future::Future, str::FromStr, sync::Arc};
+
+use super::AiMessageHandler;
+use super::registry::MessageHandlerRegistry;
+
+/// When a job runs, in the five-field cron format (`minute hour day-of-month month day-of-week`,
+/// in UTC). Fields accept `*`, numbers, ranges (`1-5`), lists (`1,15`) and steps (`*/10`).
+/// `@hourly`, `@daily`, `@weekly` and `@monthly` are accepted as shorthands.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Schedule {
+    source: String,
+    minutes: u64,
+    hours: u64,
+    days_of_month: u64,
+    months: u64,
+    days_of_week: u64,
+    /// Cron matches either day field when both are restricted, and both otherwise.
+    any_day: bool,
+}
+
+impl FromStr for Schedule {
+    type Err = anyhow::Error;
+
+    fn from_str(source: &str) -> Result<Self> {
+        let expression = match source.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            expression => expression,
+        };
+        let fields = expression.split_whitespace().collect::<Vec<_>>();
+        let [minutes, hours, days_of_month, months, days_of_week] = fields[..] else {
+            return Err(anyhow!(
+                "expected 5 fields in schedule {source:?}, found {}",
+                fields.len()
+            ));
+        };
+        let any_day = days_of_month.starts_with('*') || days_of_week.starts_with('*');
+        let mut days_of_week = parse_field(days_of_week, 0, 7).context("day of week")?;
+        // Both 0 and 7 are Sunday.
+        if days_of_week & (1 << 7) != 0 {
+            days_of_week |= 1;
+        }
+        Ok(Self {
+            source: source.trim().to_string(),
+            minutes: parse_field(minutes, 0, 59).context("minute")?,
+            hours: parse_field(hours, 0, 23).context("hour")?,
+            days_of_month: parse_field(days_of_month, 1, 31).context("day of month")?,
+            months: parse_field(months, 1, 12).context("month")?,
+            days_of_week,
+            any_day,
+        })
+    }
+}
+
+fn parse_field(field: &str, min: u32, max: u32) -> Result<u64> {
+    let mut bits = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().context("invalid step")?),
+            None => (part, 1),
+        };
+        anyhow::ensure!(step > 0, "step must be positive");
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((start, end)) = range.split_once('-') {
+            (start.parse()?, end.parse()?)
+        } else {
+            let value = range.parse()?;
+            (value, if step > 1 { max } else { value })
+        };
+        anyhow::ensure!(
+            min <= start && start <= end && end <= max,
+            "{part:?} is outside {min}-{max}"
+        );
+        for value in (start..=end).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+impl Schedule {
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    fn matches_day(&self, time: DateTime<Utc>) -> bool {
+        let day_of_month = self.days_of_month & (1 << time.day()) != 0;
+        let day_of_week = self.days_of_week & (1 << time.weekday().num_days_from_sunday()) != 0;
+        if self.any_day {
+            day_of_month && day_of_week
+        } else {
+            day_of_month || day_of_week
+        }
+    }
+
+    /// The first time after `after` the schedule fires, if it fires within the next few years.
+    pub fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let mut time = after.duration_trunc(Duration::minutes(1)).ok()? + Duration::minutes(1);
+        let limit = after + Duration::days(5 * 366);
+        while time <= limit {
+            if self.months & (1 << time.month()) == 0 {
+                let (year, month) = if time.month() == 12 {
+                    (time.year() + 1, 1)
+                } else {
+                    (time.year(), time.month() + 1)
+                };
+                time = Utc.with_ymd_and_hms(year, month, 1, 0, 0, 0).single()?;
+            } else if !self.matches_day(time) {
+                time = time.duration_trunc(Duration::days(1)).ok()? + Duration::days(1);
+            } else if self.hours & (1 << time.hour()) == 0 {
+                time = time.duration_trunc(Duration::hours(1)).ok()? + Duration::hours(1);
+            } else if self.minutes & (1 << time.minute()) == 0 {
+                time += Duration::minutes(1);
+            } else {
+                return Some(time);
+            }
+        }
+        None
+    }
+}
+
+/// Periodic maintenance work, run with whichever handler is current when it fires.
+pub trait ScheduledJob: Send + Sync + 'static {
+    fn run(&self, handler: Arc<AiMessageHandler>) -> BoxFuture<'static, Result<()>>;
+}
+
+impl<F, Fut> ScheduledJob for F
+where
+    F: Fn(Arc<AiMessageHandler>) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<()>> + Send + 'static,
+{
+    fn run(&self, handler: Arc<AiMessageHandler>) -> BoxFuture<'static, Result<()>> {
+        Box::pin(self(handler))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobState {
+    Scheduled,
+    Running,
+    /// Turned off in the settings.
+    Disabled,
+    /// Stopped because Zed is quitting.
+    Cancelled,
+}
+
+/// What a job has been up to, for introspection.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JobStatus {
+    pub name: String,
+    pub schedule: Option<String>,
+    pub state: JobState,
+    pub next_run_at: Option<DateTime<Utc>>,
+    pub last_started_at: Option<DateTime<Utc>>,
+    pub last_finished_at: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+    pub runs: usize,
+    pub failures: usize,
+}
+
+struct JobEntry {
+    schedule: Schedule,
+    job: Arc<dyn ScheduledJob>,
+    task: Option<Task<()>>,
+}
+
+/// Runs the registered jobs on their schedules. Owned by the message handler registry.
+#[derive(Default)]
+pub(crate) struct JobScheduler {
+    jobs: HashMap<String, JobEntry>,
+    /// Schedules from the settings, by job name. `None` turns a job off.
+    overrides: HashMap<String, Option<Schedule>>,
+    statuses: Arc<Mutex<HashMap<String, JobStatus>>>,
+    quit_subscription: Option<Subscription>,
+}
+
+impl JobScheduler {
+    pub(crate) fn schedule(
+        &mut self,
+        name: String,
+        schedule: Schedule,
+        job: Arc<dyn ScheduledJob>,
+        cx: &mut App,
+    ) {
+        if self.quit_subscription.is_none() {
+            self.quit_subscription = Some(cx.on_app_quit(|cx| {
+                cx.default_global::<MessageHandlerRegistry>()
+                    .scheduler
+                    .shutdown();
+                async {}
+            }));
+        }
+        self.jobs.insert(
+            name.clone(),
+            JobEntry {
+                schedule,
+                job,
+                task: None,
+            },
+        );
+        self.restart(&name, cx);
+    }
+
+    pub(crate) fn unschedule(&mut self, name: &str) {
+        self.jobs.remove(name);
+        self.statuses.lock().remove(name);
+    }
+
+    pub(crate) fn set_overrides(
+        &mut self,
+        overrides: HashMap<String, Option<Schedule>>,
+        cx: &mut App,
+    ) {
+        if overrides == self.overrides {
+            return;
+        }
+        self.overrides = overrides;
+        let names = self.jobs.keys().cloned().collect::<Vec<_>>();
+        for name in names {
+            self.restart(&name, cx);
+        }
+    }
+
+    pub(crate) fn statuses(&self) -> Vec<JobStatus> {
+        let mut statuses = self.statuses.lock().values().cloned().collect::<Vec<_>>();
+        statuses.sort_by(|a, b| a.name.cmp(&b.name));
+        statuses
+    }
+
+    /// Stops every job. Jobs that are running are dropped mid-way.
+    pub(crate) fn shutdown(&mut self) {
+        for entry in self.jobs.values_mut() {
+            entry.task.take();
+        }
+        for status in self.statuses.lock().values_mut() {
+            status.state = JobState::Cancelled;
+            status.next_run_at = None;
+        }
+    }
+
+    fn restart(&mut self, name: &str, cx: &mut App) {
+        let Some(entry) = self.jobs.get_mut(name) else {
+            return;
+        };
+        let schedule = match self.overrides.get(name) {
+            Some(schedule) => schedule.clone(),
+            None => Some(entry.schedule.clone()),
+        };
+        let previous = self.statuses.lock().remove(name);
+        let status = JobStatus {
+            name: name.to_string(),
+            schedule: schedule
+                .as_ref()
+                .map(|schedule| schedule.source().to_string()),
+            state: if schedule.is_some() {
+                JobState::Scheduled
+            } else {
+                JobState::Disabled
+            },
+            next_run_at: None,
+            last_started_at: previous.as_ref().and_then(|status| status.last_started_at),
+            last_finished_at: previous.as_ref().and_then(|status| status.last_finished_at),
+            last_error: previous
+                .as_ref()
+                .and_then(|status| status.last_error.clone()),
+            runs: previous.as_ref().map_or(0, |status| status.runs),
+            failures: previous.as_ref().map_or(0, |status| status.failures),
+        };
+        self.statuses.lock().insert(name.to_string(), status);
+        entry.task = schedule.map(|schedule| {
+            let name = name.to_string();
+            let job = entry.job.clone();
+            let statuses = self.statuses.clone();
+            cx.spawn(async move |cx| {
+                loop {
+                    let now = Utc::now();
+                    let next_run_at = schedule.next_after(now);
+                    if let Some(status) = statuses.lock().get_mut(&name) {
+                        status.next_run_at = next_run_at;
+                    }
+                    let Some(next_run_at) = next_run_at else {
+                        break;
+                    };
+                    let wait = (next_run_at - now).to_std().unwrap_or_default();
+                    cx.background_executor().timer(wait).await;
+
+                    let Ok(handler) = cx.update(|cx| {
+                        cx.try_global::<MessageHandlerRegistry>()
+                            .and_then(|registry| registry.message_handler.clone())
+                    }) else {
+                        break;
+                    };
+                    let Some(handler) = handler else {
+                        continue;
+                    };
+                    if let Some(status) = statuses.lock().get_mut(&name) {
+                        status.state = JobState::Running;
+                        status.last_started_at = Some(Utc::now());
+                    }
+                    let result = job.run(handler).await;
+                    if let Err(error) = &result {
+                        log::error!("Scheduled job {name} failed: {error:#}");
+                    }
+                    if let Some(status) = statuses.lock().get_mut(&name) {
+                        status.state = JobState::Scheduled;
+                        status.last_finished_at = Some(Utc::now());
+                        status.runs += 1;
+                        status.last_error = result.err().map(|error| format!("{error:#}"));
+                        if status.last_error.is_some() {
+                            status.failures += 1;
+                        }
+                    }
+                }
+            })
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(year, month, day, hour, minute, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_next_after() {
+        let every_quarter_hour: Schedule = "*/15 * * * *".parse().unwrap();
+        assert_eq!(
+            every_quarter_hour.next_after(at(2025, 6, 1, 12, 7)),
+            Some(at(2025, 6, 1, 12, 15))
+        );
+        assert_eq!(
+            every_quarter_hour.next_after(at(2025, 6, 1, 12, 45)),
+            Some(at(2025, 6, 1, 13, 0))
+        );
+
+        let nightly: Schedule = "30 3 * * *".parse().unwrap();
+        assert_eq!(
+            nightly.next_after(at(2025, 12, 31, 4, 0)),
+            Some(at(2026, 1, 1, 3, 30))
+        );
+
+        // 2025-06-02 is a Monday.
+        let weekdays: Schedule = "0 9 * * 1-5".parse().unwrap();
+        assert_eq!(
+            weekdays.next_after(at(2025, 5, 31, 10, 0)),
+            Some(at(2025, 6, 2, 9, 0))
+        );
+
+        let monthly: Schedule = "@monthly".parse().unwrap();
+        assert_eq!(
+            monthly.next_after(at(2025, 2, 14, 0, 0)),
+            Some(at(2025, 3, 1, 0, 0))
+        );
+
+        let leap_day: Schedule = "0 0 29 2 *".parse().unwrap();
+        assert_eq!(
+            leap_day.next_after(at(2025, 1, 1, 0, 0)),
+            Some(at(2028, 2, 29, 0, 0))
+        );
+    }
+
+    #[test]
+    fn test_invalid_schedules() {
+        assert!("* * * *".parse::<Schedule>().is_err());
+        assert!("60 * * * *".parse::<Schedule>().is_err());
+        assert!("*/0 * * * *".parse::<Schedule>().is_err());
+        assert!("0 0 * 13 *".parse::<Schedule>().is_err());
+    }
+}
@@ -0,0 +1,87 @@
+use anyhow::{Context as _, Result, bail};
+use serde_json::Value;
+
+use super::Message;
+
+const SCHEMA_VERSION: &str = "schema_version";
+
+/// The version of the stored form of [`Message`] this build writes. Whenever a change to the
+/// enum would keep messages stored before it from deserializing, bump this, and add an upcaster
+/// from the previous version to [`UPCASTERS`].
+pub const MESSAGE_SCHEMA_VERSION: u64 = 1;
+
+/// Converts a stored message from each version to the next, starting from the messages stored
+/// before they were versioned, which count as version 0.
+const UPCASTERS: [fn(&mut Value) -> Result<()>; MESSAGE_SCHEMA_VERSION as usize] = [upcast_v0];
+
+/// Version 1 only started stamping the version, so unversioned messages already match it.
+fn upcast_v0(_message: &mut Value) -> Result<()> {
+    Ok(())
+}
+
+/// Serializes the messages to store them, each stamped with the version it is written in.
+pub(crate) fn serialize_messages(messages: &[Message]) -> Result<Value> {
+    let mut value = serde_json::to_value(messages)?;
+    if let Value::Array(messages) = &mut value {
+        for message in messages {
+            if let Value::Object(message) = message {
+                message.insert(SCHEMA_VERSION.to_string(), MESSAGE_SCHEMA_VERSION.into());
+            }
+        }
+    }
+    Ok(value)
+}
+
+/// Reads stored messages back, upcasting those written in older versions.
+pub(crate) fn deserialize_messages(value: Value) -> Result<Vec<Message>> {
+    let Value::Array(messages) = value else {
+        bail!("stored messages aren't a list");
+    };
+    messages.into_iter().map(upcast_message).collect()
+}
+
+fn upcast_message(mut message: Value) -> Result<Message> {
+    let version = message
+        .as_object_mut()
+        .and_then(|message| message.remove(SCHEMA_VERSION))
+        .map_or(Ok(0), |version| {
+            version
+                .as_u64()
+                .context("the schema version isn't a number")
+        })?;
+    if version > MESSAGE_SCHEMA_VERSION {
+        bail!(
+            "the message was stored in schema version {version}, newer than this build's \
+             {MESSAGE_SCHEMA_VERSION}"
+        );
+    }
+    for upcast in &UPCASTERS[version as usize..] {
+        upcast(&mut message)?;
+    }
+    Ok(serde_json::from_value(message)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_messages_of_every_version_are_read() {
+        let unversioned = json!({
+            "type": "human",
+            "content": "hello",
+            "id": "thread",
+            "name": null,
+        });
+        let mut versioned =
+            serialize_messages(&deserialize_messages(json!([unversioned])).unwrap()).unwrap();
+        assert_eq!(versioned[0][SCHEMA_VERSION], MESSAGE_SCHEMA_VERSION);
+
+        let messages = deserialize_messages(versioned.clone()).unwrap();
+        assert!(matches!(&messages[..], [Message::Human { id, .. }] if id == "thread"));
+
+        versioned[0][SCHEMA_VERSION] = (MESSAGE_SCHEMA_VERSION + 1).into();
+        assert!(deserialize_messages(versioned).is_err());
+    }
+}
@@ -0,0 +1,55 @@
+use super::Message;
+
+const SEQUENCE: &str = "sequence";
+
+/// Numbers the messages consecutively from `first`, in their `response_metadata`. Stores number
+/// a thread's messages as they are written, from a counter kept with the thread, so that the
+/// numbers keep increasing across restarts and across the machines writing to the store, and
+/// don't depend on any clock.
+pub(crate) fn stamp_sequence(messages: &mut [Message], first: u64) {
+    for (sequence, message) in (first..).zip(messages) {
+        message
+            .response_metadata_mut()
+            .insert(SEQUENCE.to_string(), sequence.into());
+    }
+}
+
+/// Where the message falls in its thread. Messages stored before they were numbered have none.
+pub fn message_sequence(message: &Message) -> Option<u64> {
+    message.response_metadata().get(SEQUENCE)?.as_u64()
+}
+
+/// Puts messages in the order they were stored in. Messages without a number keep their stored
+/// order, ahead of numbered ones, since they were stored before messages were numbered.
+pub(crate) fn sort_by_sequence(messages: &mut [Message]) {
+    messages.sort_by_key(message_sequence);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message_handler::ContentValue;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_messages_are_sorted_by_sequence() {
+        let message = |content: &str| Message::Human {
+            content: ContentValue::new(content.into()),
+            id: content.into(),
+            name: None,
+            example: false,
+            additional_kwargs: HashMap::new(),
+            response_metadata: HashMap::new(),
+        };
+        let mut later = [message("c")];
+        stamp_sequence(&mut later, 7);
+        let mut earlier = [message("a"), message("b")];
+        stamp_sequence(&mut earlier, 5);
+        let mut messages = vec![message("legacy")];
+        messages.extend(later);
+        messages.extend(earlier);
+        sort_by_sequence(&mut messages);
+        let order = messages.iter().map(message_sequence).collect::<Vec<_>>();
+        assert_eq!(order, [None, Some(5), Some(6), Some(7)]);
+    }
+}
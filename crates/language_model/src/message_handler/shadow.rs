@@ -0,0 +1,99 @@
+use parking_lot::Mutex;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use super::blob_encoding::encode_blob;
+use super::{BlobEncoding, Message};
+
+/// How many checkpoints pass between the summaries logged in shadow mode.
+pub const SHADOW_SUMMARY_INTERVAL: u64 = 50;
+
+/// Runs saves through the serialization the store would, then discards them, counting what would
+/// have been written. For trying out a configuration, and measuring its overhead, before storing
+/// anything.
+pub struct ShadowPersistence {
+    blob_encoding: BlobEncoding,
+    stats: Mutex<ShadowStats>,
+}
+
+/// What shadow mode would have written so far.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ShadowStats {
+    pub checkpoints: u64,
+    pub messages: u64,
+    pub encoded_bytes: u64,
+    /// Time spent serializing and encoding.
+    pub encode_time: Duration,
+}
+
+impl fmt::Display for ShadowStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let per_checkpoint = self
+            .encode_time
+            .checked_div(self.checkpoints as u32)
+            .unwrap_or_default();
+        write!(
+            f,
+            "{} checkpoints ({} messages, {} bytes) encoded in {:?}, {:?} per checkpoint",
+            self.checkpoints, self.messages, self.encoded_bytes, self.encode_time, per_checkpoint
+        )
+    }
+}
+
+impl ShadowPersistence {
+    pub fn new(blob_encoding: BlobEncoding) -> Self {
+        Self {
+            blob_encoding,
+            stats: Mutex::default(),
+        }
+    }
+
+    pub fn stats(&self) -> ShadowStats {
+        *self.stats.lock()
+    }
+
+    /// Encodes the messages as their checkpoint would be and counts them, logging a summary
+    /// every [`SHADOW_SUMMARY_INTERVAL`] checkpoints.
+    pub(crate) fn record(&self, messages: &[Message]) -> anyhow::Result<()> {
+        let start = Instant::now();
+        let blob = encode_blob(&serde_json::to_value(messages)?, self.blob_encoding)?;
+        let encode_time = start.elapsed();
+
+        let mut stats = self.stats.lock();
+        stats.checkpoints += 1;
+        stats.messages += messages.len() as u64;
+        stats.encoded_bytes += blob.len() as u64;
+        stats.encode_time += encode_time;
+        if stats.checkpoints % SHADOW_SUMMARY_INTERVAL == 0 {
+            log::info!("Shadow persistence discarded {}", *stats);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message_handler::ContentValue;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_record_counts_discarded_checkpoints() {
+        let shadow = ShadowPersistence::new(BlobEncoding::Json);
+        let message = Message::Human {
+            content: ContentValue::new("hi".into()),
+            id: "1".into(),
+            name: None,
+            example: false,
+            additional_kwargs: HashMap::new(),
+            response_metadata: HashMap::new(),
+        };
+        shadow.record(&[message.clone(), message.clone()]).unwrap();
+        shadow.record(&[message]).unwrap();
+
+        let stats = shadow.stats();
+        assert_eq!(stats.checkpoints, 2);
+        assert_eq!(stats.messages, 3);
+        assert!(stats.encoded_bytes > 0);
+    }
+}
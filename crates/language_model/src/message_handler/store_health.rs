@@ -0,0 +1,140 @@
+use futures::channel::mpsc;
+use std::fmt;
+
+/// Whether messages reach the store the handler was initialized with, as found by connecting to
+/// it and smoke testing it at startup, and then by whether the handler's writes reach it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum StoreHealth {
+    /// Storage isn't enabled, so nothing is expected to reach a store.
+    #[default]
+    Off,
+    /// Storage is enabled without a store configured, so messages are only cached locally.
+    LocalOnly,
+    /// The store is being connected to and tested.
+    Checking,
+    /// The store was connected to and passed the smoke test.
+    Healthy,
+    /// Messages don't reach the store, and are only cached locally until they can be replicated.
+    Degraded(DegradedStore),
+}
+
+impl StoreHealth {
+    pub fn is_degraded(&self) -> bool {
+        matches!(self, StoreHealth::Degraded(_))
+    }
+}
+
+/// Why persistence is degraded, and what to do about it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DegradedStore {
+    pub cause: DegradedCause,
+    pub error: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DegradedCause {
+    /// The configuration has errors, so the store isn't connected to.
+    Configuration,
+    /// The store couldn't be connected to.
+    Connection,
+    /// The store was connected to, but can't be read from or written to.
+    SmokeTest,
+    /// The store passed its smoke test, but has stopped accepting writes since.
+    Write,
+}
+
+impl DegradedStore {
+    /// What to do so that messages reach the store again.
+    pub fn fix(&self) -> &'static str {
+        match self.cause {
+            DegradedCause::Configuration => {
+                "Fix the message store's configuration, as its diagnostics describe."
+            }
+            DegradedCause::Connection | DegradedCause::Write => {
+                "Check that the configured store is running and reachable."
+            }
+            DegradedCause::SmokeTest => {
+                "Check that the store's user may read and write its tables, and that the store \
+                isn't a read-only replica."
+            }
+        }
+    }
+}
+
+impl fmt::Display for DegradedStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let problem = match self.cause {
+            DegradedCause::Configuration => "isn't connected to",
+            DegradedCause::Connection => "couldn't be connected to",
+            DegradedCause::SmokeTest => "failed its smoke test",
+            DegradedCause::Write => "stopped accepting writes",
+        };
+        write!(
+            f,
+            "The message store {problem}: {}. Conversations are only cached locally. {}",
+            self.error,
+            self.fix()
+        )
+    }
+}
+
+/// Reports whether the handler's writes reach the store, so that its health follows the store
+/// after startup: one that stops accepting writes is degraded, and one that accepts them again,
+/// e.g. as the local cache is replicated to it, is healthy again.
+#[derive(Clone, Default)]
+pub(crate) struct StoreWrites {
+    outcomes: Option<mpsc::UnboundedSender<Result<(), String>>>,
+}
+
+impl StoreWrites {
+    pub(crate) fn new() -> (Self, mpsc::UnboundedReceiver<Result<(), String>>) {
+        let (outcomes, received) = mpsc::unbounded();
+        let writes = Self {
+            outcomes: Some(outcomes),
+        };
+        (writes, received)
+    }
+
+    pub(crate) fn record(&self, result: &anyhow::Result<()>) {
+        if let Some(outcomes) = &self.outcomes {
+            let outcome = match result {
+                Ok(()) => Ok(()),
+                Err(error) => Err(format!("{error:#}")),
+            };
+            outcomes.unbounded_send(outcome).ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_degraded_store_says_what_to_do() {
+        let degraded = DegradedStore {
+            cause: DegradedCause::Connection,
+            error: "connection refused".into(),
+        };
+        assert_eq!(
+            degraded.to_string(),
+            "The message store couldn't be connected to: connection refused. Conversations are \
+            only cached locally. Check that the configured store is running and reachable."
+        );
+        assert!(StoreHealth::Degraded(degraded).is_degraded());
+        assert!(!StoreHealth::LocalOnly.is_degraded());
+    }
+
+    #[test]
+    fn test_store_writes_report_their_outcomes() {
+        let (writes, mut outcomes) = StoreWrites::new();
+        writes.record(&Ok(()));
+        writes.record(&Err(anyhow::anyhow!("connection reset")));
+        assert_eq!(outcomes.try_next().unwrap(), Some(Ok(())));
+        assert_eq!(
+            outcomes.try_next().unwrap(),
+            Some(Err("connection reset".to_string()))
+        );
+        StoreWrites::default().record(&Ok(()));
+    }
+}
@@ -0,0 +1,209 @@
+use collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::{Message, Page, StoredThread, ThreadCursor};
+
+pub(crate) const THREAD_CACHE_CAPACITY: usize = 32;
+/// How many messages the cached threads may hold between them.
+pub(crate) const THREAD_CACHE_MAX_MESSAGES: usize = 20_000;
+/// How long a read is served from the cache. Writes made by other clients don't evict it, so
+/// they show up once it expires.
+pub(crate) const THREAD_CACHE_TTL: Duration = Duration::from_secs(60);
+
+/// The threads most recently read back from the store, so that reopening one doesn't query the
+/// database again. Writes to a thread evict it.
+pub(crate) struct ThreadCache {
+    capacity: usize,
+    max_messages: usize,
+    ttl: Duration,
+    next_use: u64,
+    threads: HashMap<String, CachedThread>,
+    /// How many messages `threads` holds.
+    messages: usize,
+    /// The first page of stored threads, the limit it was fetched with and when, dropped on any
+    /// write.
+    listing: Option<(usize, Instant, Arc<Page<StoredThread, ThreadCursor>>)>,
+    /// Counts writes, so that a read that raced with one isn't cached.
+    generation: u64,
+}
+
+struct CachedThread {
+    last_use: u64,
+    cached_at: Instant,
+    messages: Arc<Vec<Message>>,
+}
+
+impl ThreadCache {
+    pub(crate) fn new(capacity: usize, max_messages: usize, ttl: Duration) -> Self {
+        Self {
+            capacity,
+            max_messages,
+            ttl,
+            next_use: 0,
+            threads: HashMap::default(),
+            messages: 0,
+            listing: None,
+            generation: 0,
+        }
+    }
+
+    pub(crate) fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub(crate) fn get(&mut self, thread_id: &str) -> Option<Arc<Vec<Message>>> {
+        let thread = self.threads.get_mut(thread_id)?;
+        if thread.cached_at.elapsed() >= self.ttl {
+            self.remove(thread_id);
+            return None;
+        }
+        thread.last_use = self.next_use;
+        self.next_use += 1;
+        Some(thread.messages.clone())
+    }
+
+    /// Caches what was read when the cache was at `generation`, unless a write happened since.
+    /// Least recently used threads are evicted to make room, and threads too long to fit aren't
+    /// cached.
+    pub(crate) fn insert(
+        &mut self,
+        generation: u64,
+        thread_id: String,
+        messages: Arc<Vec<Message>>,
+    ) {
+        if self.capacity == 0 || generation != self.generation || messages.len() > self.max_messages
+        {
+            return;
+        }
+        self.remove(&thread_id);
+        while self.threads.len() >= self.capacity
+            || self.messages + messages.len() > self.max_messages
+        {
+            let least_recently_used = self
+                .threads
+                .iter()
+                .min_by_key(|(_, thread)| thread.last_use)
+                .map(|(thread_id, _)| thread_id.clone());
+            let Some(thread_id) = least_recently_used else {
+                break;
+            };
+            self.remove(&thread_id);
+        }
+        self.messages += messages.len();
+        self.threads.insert(
+            thread_id,
+            CachedThread {
+                last_use: self.next_use,
+                cached_at: Instant::now(),
+                messages,
+            },
+        );
+        self.next_use += 1;
+    }
+
+    fn remove(&mut self, thread_id: &str) {
+        if let Some(thread) = self.threads.remove(thread_id) {
+            self.messages -= thread.messages.len();
+        }
+    }
+
+    pub(crate) fn listing(&self, limit: usize) -> Option<Arc<Page<StoredThread, ThreadCursor>>> {
+        self.listing
+            .as_ref()
+            .filter(|(cached_limit, cached_at, _)| {
+                *cached_limit == limit && cached_at.elapsed() < self.ttl
+            })
+            .map(|(_, _, page)| page.clone())
+    }
+
+    pub(crate) fn set_listing(
+        &mut self,
+        generation: u64,
+        limit: usize,
+        listing: Arc<Page<StoredThread, ThreadCursor>>,
+    ) {
+        if generation == self.generation {
+            self.listing = Some((limit, Instant::now(), listing));
+        }
+    }
+
+    pub(crate) fn invalidate(&mut self, thread_id: &str) {
+        self.remove(thread_id);
+        self.listing = None;
+        self.generation += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message_handler::ContentValue;
+
+    const NO_EXPIRY: Duration = Duration::from_secs(3600);
+
+    fn messages(count: usize) -> Arc<Vec<Message>> {
+        let message = Message::System {
+            content: ContentValue::new("system".to_string()),
+            id: "thread".into(),
+            name: None,
+            example: false,
+            additional_kwargs: Default::default(),
+            response_metadata: Default::default(),
+        };
+        Arc::new(vec![message; count])
+    }
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let mut cache = ThreadCache::new(2, 100, NO_EXPIRY);
+        cache.insert(0, "a".into(), Arc::default());
+        cache.insert(0, "b".into(), Arc::default());
+        assert!(cache.get("a").is_some());
+        cache.insert(0, "c".into(), Arc::default());
+        assert!(cache.get("a").is_some());
+        assert!(cache.get("b").is_none());
+        assert!(cache.get("c").is_some());
+
+        cache.set_listing(0, 10, Arc::default());
+        assert!(cache.listing(10).is_some());
+        assert!(cache.listing(20).is_none());
+        cache.invalidate("a");
+        assert!(cache.get("a").is_none());
+        assert!(cache.listing(10).is_none());
+    }
+
+    #[test]
+    fn test_bounds_the_cached_messages() {
+        let mut cache = ThreadCache::new(10, 5, NO_EXPIRY);
+        cache.insert(0, "a".into(), messages(3));
+        cache.insert(0, "b".into(), messages(2));
+        cache.insert(0, "c".into(), messages(2));
+        assert!(cache.get("a").is_none());
+        assert!(cache.get("b").is_some());
+        assert!(cache.get("c").is_some());
+        cache.insert(0, "d".into(), messages(6));
+        assert!(cache.get("d").is_none());
+        assert!(cache.get("b").is_some());
+    }
+
+    #[test]
+    fn test_expires_reads() {
+        let mut cache = ThreadCache::new(2, 100, Duration::ZERO);
+        cache.insert(0, "a".into(), Arc::default());
+        cache.set_listing(0, 10, Arc::default());
+        assert!(cache.get("a").is_none());
+        assert!(cache.listing(10).is_none());
+    }
+
+    #[test]
+    fn test_skips_reads_that_raced_with_writes() {
+        let mut cache = ThreadCache::new(2, 100, NO_EXPIRY);
+        let generation = cache.generation();
+        cache.invalidate("a");
+        cache.insert(generation, "a".into(), Arc::default());
+        cache.set_listing(generation, 10, Arc::default());
+        assert!(cache.get("a").is_none());
+        assert!(cache.listing(10).is_none());
+    }
+}
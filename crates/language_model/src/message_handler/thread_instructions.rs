@@ -0,0 +1,80 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// The instructions a thread's requests were made with: the agent profile that was active, the
+/// tools the requests declared, and the rules their system prompt included. Stored whenever they
+/// change, so that replays and audits know what the model was told.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ThreadInstructions {
+    pub profile_id: String,
+    pub profile_name: String,
+    /// Sorted, so that snapshots only differ when the tools do.
+    pub tools: Vec<String>,
+    pub rules: Vec<RulesSnapshot>,
+}
+
+impl ThreadInstructions {
+    pub fn new(
+        profile_id: impl Into<String>,
+        profile_name: impl Into<String>,
+        tools: impl IntoIterator<Item = String>,
+        rules: Vec<RulesSnapshot>,
+    ) -> Self {
+        let mut tools = tools.into_iter().collect::<Vec<_>>();
+        tools.sort();
+        tools.dedup();
+        Self {
+            profile_id: profile_id.into(),
+            profile_name: profile_name.into(),
+            tools,
+            rules,
+        }
+    }
+}
+
+/// Rules included in a thread's system prompt, as they read when the thread's request was made.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum RulesSnapshot {
+    /// A project's rules file, by its path starting with its worktree's root name.
+    ProjectFile { path: String, text: String },
+    /// A rule of the rules library enabled by default.
+    UserRule {
+        id: String,
+        title: Option<String>,
+        text: String,
+    },
+}
+
+/// Instructions a thread's requests were made with from when they were recorded, until the next
+/// of the thread's records.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordedInstructions {
+    pub recorded_at: DateTime<Utc>,
+    pub instructions: ThreadInstructions,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tools_are_sorted_and_deduplicated() {
+        let instructions = ThreadInstructions::new(
+            "write",
+            "Write",
+            ["terminal", "edit_file", "read_file", "edit_file"].map(String::from),
+            Vec::new(),
+        );
+        assert_eq!(instructions.tools, ["edit_file", "read_file", "terminal"]);
+        assert_eq!(
+            instructions,
+            ThreadInstructions::new(
+                "write",
+                "Write",
+                ["read_file", "terminal", "edit_file"].map(String::from),
+                Vec::new(),
+            )
+        );
+    }
+}
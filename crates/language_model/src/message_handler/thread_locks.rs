@@ -0,0 +1,189 @@
+use chrono::{DateTime, Utc};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use super::MessageAuthor;
+use crate::RequestIds;
+
+/// How long a thread's lock in a shared store is held for after it was last renewed, so that
+/// the locks of editors that quit without releasing them lapse.
+pub const STORE_LOCK_LAPSE_MINUTES: i64 = 15;
+
+/// Returned when a thread is locked by another agent run, whose writes would otherwise be
+/// interleaved with the caller's.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("thread {thread_id} is in use by another agent run (session {session_id})")]
+pub struct ThreadBusy {
+    pub thread_id: String,
+    /// The session holding the lock.
+    pub session_id: String,
+}
+
+struct Holder {
+    session_id: String,
+    locks: usize,
+}
+
+/// Which session each thread is locked by, so that only one agent run writes to a thread at a
+/// time. Locks are held in this process only, and are reentrant within a session, e.g. for the
+/// summaries a run requests while it streams. Threads and sessions are identified as in the
+/// [`RequestIds`] their requests are stored with, so that a lock covers the thread it is checked
+/// against when writing.
+#[derive(Clone, Default)]
+pub struct ThreadLocks {
+    held: Arc<Mutex<HashMap<String, Holder>>>,
+}
+
+/// A thread's lock, released when the last one held by its session is dropped.
+#[must_use]
+pub struct ThreadLock {
+    held: Arc<Mutex<HashMap<String, Holder>>>,
+    thread_id: String,
+}
+
+impl ThreadLocks {
+    pub fn acquire(&self, ids: &RequestIds) -> Result<ThreadLock, ThreadBusy> {
+        let (thread_id, session_id) = (&ids.thread_id, &ids.session_id);
+        let mut held = self.held.lock();
+        let holder = held.entry(thread_id.clone()).or_insert_with(|| Holder {
+            session_id: session_id.clone(),
+            locks: 0,
+        });
+        if holder.session_id != session_id {
+            return Err(ThreadBusy {
+                thread_id: thread_id.clone(),
+                session_id: holder.session_id.clone(),
+            });
+        }
+        holder.locks += 1;
+        Ok(ThreadLock {
+            held: self.held.clone(),
+            thread_id: thread_id.clone(),
+        })
+    }
+
+    /// Whether the session may write to the thread, i.e. it isn't locked by another session.
+    pub fn check(&self, ids: &RequestIds) -> Result<(), ThreadBusy> {
+        match self.held.lock().get(&ids.thread_id) {
+            Some(holder) if holder.session_id != ids.session_id => Err(ThreadBusy {
+                thread_id: ids.thread_id.clone(),
+                session_id: holder.session_id.clone(),
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Drop for ThreadLock {
+    fn drop(&mut self) {
+        let mut held = self.held.lock();
+        if let Some(holder) = held.get_mut(&self.thread_id) {
+            holder.locks -= 1;
+            if holder.locks == 0 {
+                held.remove(&self.thread_id);
+            }
+        }
+    }
+}
+
+/// The editor session holding a thread's lock in a store shared by a team, so that teammates
+/// don't run the agent in the same thread at once. Held while the session's agent runs, and
+/// renewed with each of its requests.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoreLockHolder {
+    pub thread_id: String,
+    pub session_id: String,
+    pub author: Option<MessageAuthor>,
+    pub acquired_at: DateTime<Utc>,
+    pub renewed_at: DateTime<Utc>,
+}
+
+impl StoreLockHolder {
+    /// Whether the lock has lapsed, as its session hasn't renewed it in a while.
+    pub fn is_lapsed(&self, now: DateTime<Utc>) -> bool {
+        now - self.renewed_at > chrono::Duration::minutes(STORE_LOCK_LAPSE_MINUTES)
+    }
+
+    /// The name to show for whoever holds the lock.
+    pub fn display_name(&self) -> &str {
+        self.author
+            .as_ref()
+            .map_or("another editor", |author| author.display_name())
+    }
+}
+
+/// How a thread's lock was taken from the session holding it, recorded with who did it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LockOverride {
+    /// The lock was released so that the thread could be continued elsewhere.
+    TakeOver,
+    /// The lock was released, e.g. because its holder's editor is gone.
+    ForceUnlock,
+}
+
+impl LockOverride {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LockOverride::TakeOver => "take_over",
+            LockOverride::ForceUnlock => "force_unlock",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_thread_lock_is_reentrant_within_a_session() {
+        let ids = |thread_id: &str, session_id: &str| RequestIds {
+            thread_id: thread_id.into(),
+            checkpoint_id: "checkpoint".into(),
+            session_id: session_id.into(),
+            prompt_id: "prompt".into(),
+        };
+        let locks = ThreadLocks::default();
+        let first = locks.acquire(&ids("thread", "a")).unwrap();
+        let second = locks.acquire(&ids("thread", "a")).unwrap();
+        assert_eq!(
+            locks.acquire(&ids("thread", "b")).err(),
+            Some(ThreadBusy {
+                thread_id: "thread".into(),
+                session_id: "a".into(),
+            })
+        );
+        assert!(locks.check(&ids("thread", "b")).is_err());
+        assert!(locks.check(&ids("other", "b")).is_ok());
+
+        drop(first);
+        assert!(locks.check(&ids("thread", "b")).is_err());
+        drop(second);
+        assert!(locks.acquire(&ids("thread", "b")).is_ok());
+    }
+
+    #[test]
+    fn test_store_locks_lapse_unless_renewed() {
+        let now = Utc::now();
+        let holder = StoreLockHolder {
+            thread_id: "thread".into(),
+            session_id: "a".into(),
+            author: None,
+            acquired_at: now - chrono::Duration::hours(1),
+            renewed_at: now - chrono::Duration::minutes(5),
+        };
+        assert!(!holder.is_lapsed(now));
+        assert_eq!(holder.display_name(), "another editor");
+
+        let renewed_at = now - chrono::Duration::minutes(STORE_LOCK_LAPSE_MINUTES + 1);
+        assert!(
+            StoreLockHolder {
+                renewed_at,
+                ..holder
+            }
+            .is_lapsed(now)
+        );
+    }
+}
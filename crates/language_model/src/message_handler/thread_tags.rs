@@ -0,0 +1,54 @@
+/// The longest a tag may be, in characters.
+pub const MAX_TAG_LEN: usize = 64;
+
+/// A tag already on stored threads, offered while the user types one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TagSuggestion {
+    pub tag: String,
+    /// How many threads have it, to offer the most used tags first.
+    pub threads: i64,
+}
+
+/// Trims the tag and collapses the whitespace in it, so that tags typed slightly differently,
+/// e.g. `"ACME  Corp "` and `"ACME Corp"`, are the same tag. Case is kept, since ticket numbers
+/// and customer names are usually written one way.
+pub fn normalize_tag(tag: &str) -> anyhow::Result<String> {
+    let tag = tag.split_whitespace().collect::<Vec<_>>().join(" ");
+    anyhow::ensure!(!tag.is_empty(), "tags can't be empty");
+    anyhow::ensure!(
+        tag.chars().count() <= MAX_TAG_LEN,
+        "tags can't be longer than {MAX_TAG_LEN} characters"
+    );
+    Ok(tag)
+}
+
+/// The `LIKE` pattern matching the tags that start with `prefix`, whatever their case once both
+/// are lowercased, with the wildcards in `prefix` matched literally.
+pub(crate) fn tag_prefix_pattern(prefix: &str) -> String {
+    let mut pattern = String::with_capacity(prefix.len() + 1);
+    for character in prefix.trim_start().to_lowercase().chars() {
+        if matches!(character, '\\' | '%' | '_') {
+            pattern.push('\\');
+        }
+        pattern.push(character);
+    }
+    pattern.push('%');
+    pattern
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tags_are_normalized() {
+        assert_eq!(normalize_tag("  ACME \t Corp ").unwrap(), "ACME Corp");
+        assert_eq!(normalize_tag("JIRA-1234").unwrap(), "JIRA-1234");
+        assert!(normalize_tag(" \n ").is_err());
+        assert!(normalize_tag(&"x".repeat(MAX_TAG_LEN + 1)).is_err());
+
+        assert_eq!(tag_prefix_pattern("Bill"), "bill%");
+        assert_eq!(tag_prefix_pattern("100%_"), "100\\%\\_%");
+        assert_eq!(tag_prefix_pattern(""), "%");
+    }
+}
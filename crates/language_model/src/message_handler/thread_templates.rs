@@ -0,0 +1,102 @@
+use super::idempotency::{CheckpointEvent, stamp_idempotency_key};
+use super::{ContentValue, Message};
+use crate::RequestIds;
+
+/// The `response_metadata` entry of a thread forked as a template, naming the thread it was
+/// forked from.
+const TEMPLATE_PARENT: &str = "template_parent";
+
+/// What the context attached to a user message opens with.
+const CONTEXT_OPENER: &str = "<context>";
+
+/// Whether the message seeds the threads forked from its own: the system prompt, and the user
+/// messages the context was attached to.
+fn is_template_message(message: &Message) -> bool {
+    match message {
+        Message::System { .. } => true,
+        Message::Human { content, .. } => match content {
+            ContentValue::Single(content) => content.contains(CONTEXT_OPENER),
+            ContentValue::Multiple(contents) => contents
+                .iter()
+                .any(|content| content.contains(CONTEXT_OPENER)),
+        },
+        _ => false,
+    }
+}
+
+/// The system prompt and context messages of the parent thread, restamped as the first write of
+/// the thread `ids` start, and linked back to the parent.
+pub fn template_messages(
+    messages: &[Message],
+    parent_thread_id: &str,
+    ids: &RequestIds,
+) -> Vec<Message> {
+    let mut template = messages
+        .iter()
+        .filter(|message| is_template_message(message))
+        .cloned()
+        .collect::<Vec<_>>();
+    for message in &mut template {
+        *message.id_mut() = ids.thread_id.clone();
+        message.response_metadata_mut().insert(
+            TEMPLATE_PARENT.to_string(),
+            serde_json::Value::from(parent_thread_id),
+        );
+    }
+    stamp_idempotency_key(&mut template, ids, CheckpointEvent::Template);
+    template
+}
+
+/// The thread the messages' thread was forked from as a template, if it was.
+pub fn template_parent(messages: &[Message]) -> Option<&str> {
+    messages
+        .iter()
+        .find_map(|message| message.response_metadata().get(TEMPLATE_PARENT)?.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message_handler::idempotency::{idempotency_key, write_idempotency_key};
+
+    #[test]
+    fn test_template_keeps_system_prompt_and_context() {
+        let message = |message_type: &str, content: &str| {
+            serde_json::from_value::<Message>(serde_json::json!({
+                "type": message_type,
+                "content": content,
+                "id": "release-notes",
+                "name": null,
+                "tool_call_id": null,
+                "tool_name": null,
+                "invalid_tool_calls": null,
+                "tool_calls": null,
+            }))
+            .unwrap()
+        };
+        let parent = [
+            message("system", "You write release notes."),
+            message("human", "Draft them\n<context>\nCHANGELOG.md\n</context>"),
+            message("ai", "Here is a draft."),
+            message("human", "Shorter, please."),
+            message("tool", "Read CHANGELOG.md"),
+        ];
+        let ids = RequestIds {
+            thread_id: "next-release-notes".into(),
+            checkpoint_id: "checkpoint".into(),
+            session_id: "session".into(),
+            prompt_id: "prompt".into(),
+        };
+
+        let template = template_messages(&parent, "release-notes", &ids);
+        assert_eq!(template.len(), 2);
+        assert!(matches!(&template[0], Message::System { id, .. } if id == "next-release-notes"));
+        assert!(matches!(&template[1], Message::Human { id, .. } if id == "next-release-notes"));
+        assert_eq!(template_parent(&template), Some("release-notes"));
+        assert_eq!(template_parent(&parent), None);
+        assert_eq!(
+            write_idempotency_key(&template),
+            Some(idempotency_key(&ids, CheckpointEvent::Template).as_str())
+        );
+    }
+}
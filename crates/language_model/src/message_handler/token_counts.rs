@@ -0,0 +1,73 @@
+use std::collections::HashMap;
+
+use super::Message;
+use crate::{TokenCount, TokenUsage};
+
+/// Records a locally counted number of tokens in the message's `response_metadata`.
+pub(crate) fn stamp_token_count(message: &mut Message, count: TokenCount) {
+    message.response_metadata_mut().insert(
+        "token_count".to_string(),
+        serde_json::json!({ "tokens": count.tokens, "tokenizer": count.tokenizer }),
+    );
+}
+
+/// The `additional_kwargs` of a completion's `token_usage` event: what the provider reported if it
+/// reported anything, and otherwise what was counted locally.
+pub(crate) fn token_usage_kwargs(
+    provider_usage: Option<&TokenUsage>,
+    prompt_tokens: Option<usize>,
+    completion: TokenCount,
+) -> HashMap<String, serde_json::Value> {
+    let mut kwargs =
+        HashMap::from_iter([("event".to_string(), serde_json::Value::from("token_usage"))]);
+    match provider_usage {
+        Some(usage) => {
+            kwargs.insert("source".to_string(), "provider".into());
+            kwargs.insert("prompt_tokens".to_string(), usage.input_tokens.into());
+            kwargs.insert("completion_tokens".to_string(), usage.output_tokens.into());
+            kwargs.insert(
+                "cache_read_input_tokens".to_string(),
+                usage.cache_read_input_tokens.into(),
+            );
+            kwargs.insert(
+                "cache_creation_input_tokens".to_string(),
+                usage.cache_creation_input_tokens.into(),
+            );
+        }
+        None => {
+            kwargs.insert("source".to_string(), completion.tokenizer.into());
+            if let Some(prompt_tokens) = prompt_tokens {
+                kwargs.insert("prompt_tokens".to_string(), prompt_tokens.into());
+            }
+            kwargs.insert("completion_tokens".to_string(), completion.tokens.into());
+        }
+    }
+    kwargs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prefers_provider_usage() {
+        let counted = TokenCount {
+            tokens: 40,
+            tokenizer: "tiktoken",
+        };
+        let kwargs = token_usage_kwargs(None, Some(100), counted);
+        assert_eq!(kwargs["source"], "tiktoken");
+        assert_eq!(kwargs["prompt_tokens"], 100);
+        assert_eq!(kwargs["completion_tokens"], 40);
+
+        let usage = TokenUsage {
+            input_tokens: 120,
+            output_tokens: 42,
+            ..Default::default()
+        };
+        let kwargs = token_usage_kwargs(Some(&usage), Some(100), counted);
+        assert_eq!(kwargs["source"], "provider");
+        assert_eq!(kwargs["prompt_tokens"], 120);
+        assert_eq!(kwargs["completion_tokens"], 42);
+    }
+}
@@ -0,0 +1,296 @@
+use std::collections::HashSet;
+
+use super::{ContentValue, Message};
+use crate::MessageContent;
+
+/// The `additional_kwargs` entry of a message whose tool calls or results were repaired on load:
+/// the results re-linked to a call, and the calls or results left without a counterpart.
+pub const TOOL_PAIR_REPAIR: &str = "tool_pair_repair";
+
+/// What the repair of a thread's tool calls and results did.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ToolPairRepair {
+    /// Results whose call id matched no call, linked to an unanswered call of the same tool.
+    pub relinked: usize,
+    /// Results no call could be found for.
+    pub orphan_results: usize,
+    /// Calls no result answered.
+    pub orphan_calls: usize,
+}
+
+impl ToolPairRepair {
+    pub fn is_clean(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+struct ToolCall {
+    id: String,
+    name: String,
+    /// The message it was stored in.
+    index: usize,
+}
+
+/// The content of a request message, stored as the JSON of its parts. The text of completion
+/// events isn't.
+pub(super) fn request_contents(content: &ContentValue) -> Option<Vec<MessageContent>> {
+    match content {
+        ContentValue::Single(text) => serde_json::from_str(text).ok(),
+        _ => None,
+    }
+}
+
+/// The tool calls of the thread, in order, each once: those streamed as completion events, and
+/// those sent back to the model in later requests.
+fn tool_calls(messages: &[Message]) -> Vec<ToolCall> {
+    let mut seen = HashSet::new();
+    let mut calls = Vec::new();
+    for (index, message) in messages.iter().enumerate() {
+        let found = match message {
+            Message::Tool {
+                tool_call_id: Some(id),
+                tool_name,
+                ..
+            } => vec![(id.clone(), tool_name.clone().unwrap_or_default())],
+            Message::Ai { content, .. } => request_contents(content)
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|content| match content {
+                    MessageContent::ToolUse(tool_use) => {
+                        Some((tool_use.id.to_string(), tool_use.name.to_string()))
+                    }
+                    _ => None,
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+        for (id, name) in found {
+            if seen.insert(id.clone()) {
+                calls.push(ToolCall { id, name, index });
+            }
+        }
+    }
+    calls
+}
+
+fn flag(message: &mut Message, key: &str, value: serde_json::Value) {
+    let (Message::Human {
+        additional_kwargs, ..
+    }
+    | Message::Ai {
+        additional_kwargs, ..
+    }
+    | Message::System {
+        additional_kwargs, ..
+    }
+    | Message::Tool {
+        additional_kwargs, ..
+    }
+    | Message::Function {
+        additional_kwargs, ..
+    }) = message;
+    let repair = additional_kwargs
+        .entry(TOOL_PAIR_REPAIR.to_string())
+        .or_insert_with(|| serde_json::json!({}));
+    match repair[key].as_array_mut() {
+        Some(values) => values.push(value),
+        None => repair[key] = serde_json::json!([value]),
+    }
+}
+
+/// Links each tool result to the call it answers. Threads stored before tool calls and results
+/// were paired keep the results inside the user messages that sent them, and the calls as the
+/// completion events that streamed them, so a result's call may be missing, e.g. because its
+/// events weren't stored. A result whose call id matches no call is linked to the latest earlier
+/// call of the same tool that no result answers. Results and calls left without a counterpart are
+/// flagged under [`TOOL_PAIR_REPAIR`], so that they can be dropped when the thread is replayed.
+pub fn repair_tool_pairs(messages: &mut [Message]) -> ToolPairRepair {
+    let calls = tool_calls(messages);
+    let call_ids = calls
+        .iter()
+        .map(|call| call.id.as_str())
+        .collect::<HashSet<_>>();
+    let mut answered = HashSet::new();
+    let mut results = Vec::new();
+    for (index, message) in messages.iter().enumerate() {
+        let Message::Human { content, .. } = message else {
+            continue;
+        };
+        let Some(contents) = request_contents(content) else {
+            continue;
+        };
+        if contents
+            .iter()
+            .any(|content| matches!(content, MessageContent::ToolResult(_)))
+        {
+            for content in &contents {
+                if let MessageContent::ToolResult(result) = content {
+                    if call_ids.contains(result.tool_use_id.as_ref()) {
+                        answered.insert(result.tool_use_id.to_string());
+                    }
+                }
+            }
+            results.push((index, contents));
+        }
+    }
+
+    let mut repair = ToolPairRepair::default();
+    for (index, mut contents) in results {
+        let mut relinked = false;
+        for content in &mut contents {
+            let MessageContent::ToolResult(result) = content else {
+                continue;
+            };
+            if call_ids.contains(result.tool_use_id.as_ref()) {
+                continue;
+            }
+            let call = calls.iter().rev().find(|call| {
+                call.index < index
+                    && call.name == result.tool_name.as_ref()
+                    && !answered.contains(&call.id)
+            });
+            let original_id = result.tool_use_id.to_string();
+            match call {
+                Some(call) => {
+                    answered.insert(call.id.clone());
+                    result.tool_use_id = call.id.clone().into();
+                    flag(
+                        &mut messages[index],
+                        "relinked",
+                        serde_json::json!({ "from": original_id, "to": call.id }),
+                    );
+                    repair.relinked += 1;
+                    relinked = true;
+                }
+                None => {
+                    flag(
+                        &mut messages[index],
+                        "orphan_results",
+                        serde_json::Value::from(original_id),
+                    );
+                    repair.orphan_results += 1;
+                }
+            }
+        }
+        if relinked {
+            if let (Message::Human { content, .. }, Ok(json)) =
+                (&mut messages[index], serde_json::to_string(&contents))
+            {
+                *content = ContentValue::new(json);
+            }
+        }
+    }
+
+    for call in calls.iter().filter(|call| !answered.contains(&call.id)) {
+        flag(
+            &mut messages[call.index],
+            "orphan_calls",
+            serde_json::Value::from(call.id.clone()),
+        );
+        repair.orphan_calls += 1;
+    }
+    repair
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{LanguageModelToolResult, LanguageModelToolResultContent, LanguageModelToolUseId};
+    use std::collections::HashMap;
+
+    fn tool_call(id: &str, name: &str) -> Message {
+        Message::Tool {
+            content: ContentValue::new("{}".into()),
+            id: id.into(),
+            name: Some("ZedIdeAgent".into()),
+            example: false,
+            tool_call_id: Some(id.into()),
+            tool_name: Some(name.into()),
+            additional_kwargs: HashMap::new(),
+            response_metadata: HashMap::new(),
+        }
+    }
+
+    fn tool_results(results: &[(&str, &str)]) -> Message {
+        let contents = results
+            .iter()
+            .map(|(id, name)| {
+                MessageContent::ToolResult(LanguageModelToolResult {
+                    tool_use_id: LanguageModelToolUseId::from(id.to_string()),
+                    tool_name: (*name).into(),
+                    is_error: false,
+                    content: LanguageModelToolResultContent::Text("done".into()),
+                    output: None,
+                })
+            })
+            .collect::<Vec<_>>();
+        Message::Human {
+            content: ContentValue::new(serde_json::to_string(&contents).unwrap()),
+            id: "thread".into(),
+            name: Some("ZedIdeAgent".into()),
+            example: false,
+            additional_kwargs: HashMap::new(),
+            response_metadata: HashMap::new(),
+        }
+    }
+
+    fn repair_entry(message: &Message) -> Option<&serde_json::Value> {
+        match message {
+            Message::Human {
+                additional_kwargs, ..
+            }
+            | Message::Tool {
+                additional_kwargs, ..
+            } => additional_kwargs.get(TOOL_PAIR_REPAIR),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_tool_results_are_relinked_to_their_calls() {
+        let mut messages = vec![
+            tool_call("call-1", "read_file"),
+            tool_call("call-2", "grep"),
+            tool_call("call-3", "terminal"),
+            tool_results(&[
+                ("call-1", "read_file"),
+                ("lost-2", "grep"),
+                ("lost-9", "edit_file"),
+            ]),
+        ];
+
+        let repair = repair_tool_pairs(&mut messages);
+        assert_eq!(
+            repair,
+            ToolPairRepair {
+                relinked: 1,
+                orphan_results: 1,
+                orphan_calls: 1,
+            }
+        );
+        let Message::Human { content, .. } = &messages[3] else {
+            panic!("expected the tool results");
+        };
+        let results = request_contents(content)
+            .unwrap()
+            .into_iter()
+            .filter_map(|content| match content {
+                MessageContent::ToolResult(result) => Some(result.tool_use_id.to_string()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(results, ["call-1", "call-2", "lost-9"]);
+        assert_eq!(
+            repair_entry(&messages[3]),
+            Some(&serde_json::json!({
+                "relinked": [{ "from": "lost-2", "to": "call-2" }],
+                "orphan_results": ["lost-9"],
+            }))
+        );
+        assert_eq!(
+            repair_entry(&messages[2]),
+            Some(&serde_json::json!({ "orphan_calls": ["call-3"] }))
+        );
+        assert_eq!(repair_entry(&messages[0]), None);
+    }
+}
@@ -0,0 +1,391 @@
+use anyhow::{Result, anyhow};
+use chrono::{Days, NaiveDate};
+use collections::HashMap;
+use futures::AsyncReadExt;
+use http_client::{AsyncBody, HttpClient, Method, Request as HttpRequest};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use super::LanguageModelArgs;
+use crate::{ProjectArea, RequestSource, TokenUsage};
+
+/// What requests are broken down by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UsageDimension {
+    /// The language of the buffer each request was made from.
+    Language,
+    /// The [`ProjectArea`] of the buffer each request was made from.
+    ProjectArea,
+    /// The [`RequestSource`] each request was made from.
+    Source,
+}
+
+/// What one completion used, as recorded for [`UsageBreakdown`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequestUsageRecord {
+    pub model_id: String,
+    pub language: Option<String>,
+    pub area: Option<ProjectArea>,
+    pub source: Option<RequestSource>,
+    pub input_tokens: i64,
+    pub output_tokens: i64,
+}
+
+impl RequestUsageRecord {
+    /// The provider's usage if it reported any, or else the locally counted tokens.
+    pub fn new(
+        language_model_args: &LanguageModelArgs,
+        provider_usage: Option<&TokenUsage>,
+        prompt_tokens: Option<usize>,
+        completion_tokens: usize,
+    ) -> Self {
+        let (input_tokens, output_tokens) = match provider_usage {
+            Some(usage) => (
+                i64::from(usage.input_tokens)
+                    + i64::from(usage.cache_read_input_tokens)
+                    + i64::from(usage.cache_creation_input_tokens),
+                i64::from(usage.output_tokens),
+            ),
+            None => (
+                prompt_tokens.unwrap_or_default() as i64,
+                completion_tokens as i64,
+            ),
+        };
+        let buffer = language_model_args.buffer.as_ref();
+        Self {
+            model_id: language_model_args.model_id.0.to_string(),
+            language: buffer.and_then(|buffer| buffer.language.clone()),
+            area: buffer.map(|buffer| buffer.area),
+            source: language_model_args.source,
+            input_tokens,
+            output_tokens,
+        }
+    }
+}
+
+/// What the requests made on one day from the buffers of one language, one project area, or
+/// from one feature, used. `key` is `None` for the requests made from no buffer, one of no
+/// language, or no known feature.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UsageBreakdown {
+    pub day: NaiveDate,
+    pub key: Option<String>,
+    pub requests: i64,
+    pub input_tokens: i64,
+    pub output_tokens: i64,
+}
+
+/// A row of a [`UsageHeatmap`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct UsageHeatmapRow {
+    pub key: Option<String>,
+    pub requests: i64,
+    /// The tokens used on each of the heatmap's days.
+    pub tokens: Vec<i64>,
+}
+
+/// Breakdowns laid out with a row per language or area, busiest first, and a column per day.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UsageHeatmap {
+    pub days: Vec<NaiveDate>,
+    pub rows: Vec<UsageHeatmapRow>,
+    max_tokens: i64,
+}
+
+impl UsageHeatmap {
+    /// Lays out the breakdowns of the `days` days up to and including `last_day`.
+    pub fn new(breakdowns: &[UsageBreakdown], last_day: NaiveDate, days: u64) -> Self {
+        let days = (0..days)
+            .rev()
+            .filter_map(|days_ago| last_day.checked_sub_days(Days::new(days_ago)))
+            .collect::<Vec<_>>();
+        let columns = days
+            .iter()
+            .enumerate()
+            .map(|(column, day)| (*day, column))
+            .collect::<HashMap<_, _>>();
+        let mut rows = HashMap::<Option<&str>, UsageHeatmapRow>::default();
+        for breakdown in breakdowns {
+            let Some(column) = columns.get(&breakdown.day) else {
+                continue;
+            };
+            let row = rows
+                .entry(breakdown.key.as_deref())
+                .or_insert_with(|| UsageHeatmapRow {
+                    key: breakdown.key.clone(),
+                    requests: 0,
+                    tokens: vec![0; days.len()],
+                });
+            row.requests += breakdown.requests;
+            row.tokens[*column] += breakdown.input_tokens + breakdown.output_tokens;
+        }
+        let mut rows = rows.into_values().collect::<Vec<_>>();
+        rows.sort_by(|a, b| {
+            let total = |row: &UsageHeatmapRow| row.tokens.iter().sum::<i64>();
+            total(b).cmp(&total(a)).then_with(|| a.key.cmp(&b.key))
+        });
+        let max_tokens = rows
+            .iter()
+            .flat_map(|row| row.tokens.iter().copied())
+            .max()
+            .unwrap_or(0);
+        Self {
+            days,
+            rows,
+            max_tokens,
+        }
+    }
+
+    /// How busy the cell is relative to the busiest one, from 0 to 1.
+    pub fn intensity(&self, row: usize, column: usize) -> f32 {
+        if self.max_tokens == 0 {
+            return 0.;
+        }
+        self.rows[row].tokens[column] as f32 / self.max_tokens as f32
+    }
+}
+
+/// The lower bounds of the buckets of the tokens each request used, past the first bucket's.
+pub const TOKEN_BUCKET_BOUNDS: [i64; 4] = [1_000, 10_000, 100_000, 1_000_000];
+
+/// Where usage aggregates are exported, and how small a group of requests may be exported.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct UsageExportConfig {
+    /// The analytics endpoint each day's aggregates are posted to, as JSON.
+    pub endpoint: String,
+    /// Sent as a bearer token, if set.
+    pub api_key: Option<String>,
+    /// The fewest requests a model may have made in a day for its aggregate to be exported, so
+    /// that no aggregate describes a handful of requests.
+    pub min_requests: i64,
+}
+
+impl Default for UsageExportConfig {
+    fn default() -> Self {
+        Self {
+            endpoint: String::new(),
+            api_key: None,
+            min_requests: 10,
+        }
+    }
+}
+
+/// How many requests to one model on one day used a bucket's worth of tokens, as the store
+/// counts them. `bucket` indexes the buckets bounded by [`TOKEN_BUCKET_BOUNDS`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct UsageHistogramRow {
+    pub day: NaiveDate,
+    pub model_id: String,
+    pub bucket: usize,
+    pub requests: i64,
+    pub input_tokens: i64,
+    pub output_tokens: i64,
+}
+
+/// What the requests to one model on one day used, with nothing about who made them, from
+/// where, or what they said.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UsageAggregate {
+    pub day: NaiveDate,
+    pub model_id: String,
+    pub requests: i64,
+    pub input_tokens: i64,
+    pub output_tokens: i64,
+    /// How many requests used each bucket's worth of tokens, fewest tokens first.
+    pub token_histogram: Vec<TokenBucket>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TokenBucket {
+    /// The tokens the bucket's requests used, e.g. `1k-10k`.
+    pub tokens: String,
+    pub requests: i64,
+}
+
+fn bucket_label(bucket: usize) -> String {
+    fn tokens(bound: i64) -> String {
+        match bound {
+            bound if bound >= 1_000_000 => format!("{}m", bound / 1_000_000),
+            bound if bound >= 1_000 => format!("{}k", bound / 1_000),
+            bound => bound.to_string(),
+        }
+    }
+    let lower = bucket
+        .checked_sub(1)
+        .and_then(|ix| TOKEN_BUCKET_BOUNDS.get(ix))
+        .copied()
+        .unwrap_or(0);
+    match TOKEN_BUCKET_BOUNDS.get(bucket) {
+        Some(upper) => format!("{}-{}", tokens(lower), tokens(*upper)),
+        None => format!("{}+", tokens(lower)),
+    }
+}
+
+/// Rolls the histogram rows up by day and model, dropping the groups of fewer than
+/// `min_requests` requests.
+pub fn usage_aggregates(rows: &[UsageHistogramRow], min_requests: i64) -> Vec<UsageAggregate> {
+    let mut aggregates = BTreeMap::<(NaiveDate, &str), UsageAggregate>::new();
+    for row in rows {
+        let aggregate = aggregates
+            .entry((row.day, row.model_id.as_str()))
+            .or_insert_with(|| UsageAggregate {
+                day: row.day,
+                model_id: row.model_id.clone(),
+                requests: 0,
+                input_tokens: 0,
+                output_tokens: 0,
+                token_histogram: (0..=TOKEN_BUCKET_BOUNDS.len())
+                    .map(|bucket| TokenBucket {
+                        tokens: bucket_label(bucket),
+                        requests: 0,
+                    })
+                    .collect(),
+            });
+        aggregate.requests += row.requests;
+        aggregate.input_tokens += row.input_tokens;
+        aggregate.output_tokens += row.output_tokens;
+        if let Some(bucket) = aggregate.token_histogram.get_mut(row.bucket) {
+            bucket.requests += row.requests;
+        }
+    }
+    aggregates
+        .into_values()
+        .filter(|aggregate| aggregate.requests >= min_requests)
+        .collect()
+}
+
+/// Posts usage aggregates to the analytics endpoint. Only aggregates are ever sent; the stored
+/// messages stay in the store.
+pub struct UsageExporter {
+    http_client: Arc<dyn HttpClient>,
+    config: UsageExportConfig,
+}
+
+impl UsageExporter {
+    pub fn new(http_client: Arc<dyn HttpClient>, config: UsageExportConfig) -> Self {
+        Self {
+            http_client,
+            config,
+        }
+    }
+
+    pub fn config(&self) -> &UsageExportConfig {
+        &self.config
+    }
+
+    pub async fn send(&self, aggregates: &[UsageAggregate]) -> Result<()> {
+        let mut request = HttpRequest::builder()
+            .method(Method::POST)
+            .uri(&self.config.endpoint)
+            .header("Content-Type", "application/json");
+        if let Some(api_key) = &self.config.api_key {
+            request = request.header("Authorization", format!("Bearer {api_key}"));
+        }
+        let request = request.body(AsyncBody::from(serde_json::to_string(aggregates)?))?;
+        let mut response = self.http_client.send(request).await?;
+        if response.status().is_success() {
+            return Ok(());
+        }
+        let mut body = String::new();
+        response.body_mut().read_to_string(&mut body).await?;
+        Err(anyhow!(
+            "the analytics endpoint responded with {}: {body}",
+            response.status()
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_heatmap_rows_are_sorted_by_usage() {
+        let day = |day| NaiveDate::from_ymd_opt(2025, 6, day).unwrap();
+        let breakdown = |on, key: Option<&str>, input_tokens| UsageBreakdown {
+            day: day(on),
+            key: key.map(Into::into),
+            requests: 1,
+            input_tokens,
+            output_tokens: 0,
+        };
+        let heatmap = UsageHeatmap::new(
+            &[
+                breakdown(1, Some("Rust"), 100),
+                breakdown(2, Some("Python"), 400),
+                breakdown(3, Some("Rust"), 200),
+                breakdown(3, Some("Rust"), 200),
+                breakdown(3, None, 50),
+                // Before the first day shown.
+                breakdown(1, Some("Go"), 1000),
+            ],
+            day(3),
+            2,
+        );
+        assert_eq!(heatmap.days, [day(2), day(3)]);
+        assert_eq!(
+            heatmap.rows,
+            [
+                UsageHeatmapRow {
+                    key: Some("Python".into()),
+                    requests: 1,
+                    tokens: vec![400, 0],
+                },
+                UsageHeatmapRow {
+                    key: Some("Rust".into()),
+                    requests: 2,
+                    tokens: vec![0, 400],
+                },
+                UsageHeatmapRow {
+                    key: None,
+                    requests: 1,
+                    tokens: vec![0, 50],
+                },
+            ]
+        );
+        assert_eq!(heatmap.intensity(1, 1), 1.);
+        assert_eq!(heatmap.intensity(2, 1), 0.125);
+    }
+
+    #[test]
+    fn test_usage_aggregates() {
+        let day = NaiveDate::from_ymd_opt(2025, 6, 2).unwrap();
+        let row = |model_id: &str, bucket, requests| UsageHistogramRow {
+            day,
+            model_id: model_id.to_string(),
+            bucket,
+            requests,
+            input_tokens: requests * 100,
+            output_tokens: requests * 10,
+        };
+        let aggregates = usage_aggregates(
+            &[
+                row("claude-sonnet-4", 0, 8),
+                row("claude-sonnet-4", 2, 4),
+                row("gpt-4o", 4, 3),
+            ],
+            10,
+        );
+        assert_eq!(aggregates.len(), 1);
+        assert_eq!(aggregates[0].model_id, "claude-sonnet-4");
+        assert_eq!(aggregates[0].requests, 12);
+        assert_eq!(aggregates[0].input_tokens, 1200);
+        assert_eq!(
+            aggregates[0]
+                .token_histogram
+                .iter()
+                .map(|bucket| (bucket.tokens.as_str(), bucket.requests))
+                .collect::<Vec<_>>(),
+            [
+                ("0-1k", 8),
+                ("1k-10k", 0),
+                ("10k-100k", 4),
+                ("100k-1m", 0),
+                ("1m+", 0)
+            ]
+        );
+    }
+}
@@ -23,6 +23,7 @@ pub struct LanguageModelRegistry {
     inline_assistant_model: Option<ConfiguredModel>,
     commit_message_model: Option<ConfiguredModel>,
     thread_summary_model: Option<ConfiguredModel>,
+    judge_model: Option<ConfiguredModel>,
     providers: BTreeMap<LanguageModelProviderId, Arc<dyn LanguageModelProvider>>,
     inline_alternatives: Vec<Arc<dyn LanguageModel>>,
 }
@@ -78,6 +79,7 @@ pub enum Event {
     InlineAssistantModelChanged,
     CommitMessageModelChanged,
     ThreadSummaryModelChanged,
+    JudgeModelChanged,
     ProviderStateChanged,
     AddedProvider(LanguageModelProviderId),
     RemovedProvider(LanguageModelProviderId),
@@ -197,6 +199,11 @@ impl LanguageModelRegistry {
         self.set_thread_summary_model(configured_model, cx);
     }
 
+    pub fn select_judge_model(&mut self, model: Option<&SelectedModel>, cx: &mut Context<Self>) {
+        let configured_model = model.and_then(|model| self.select_model(model, cx));
+        self.set_judge_model(configured_model, cx);
+    }
+
     /// Selects and sets the inline alternatives for language models based on
     /// provider name and id.
     pub fn select_inline_alternative_models(
@@ -283,6 +290,15 @@ impl LanguageModelRegistry {
         self.thread_summary_model = model;
     }
 
+    pub fn set_judge_model(&mut self, model: Option<ConfiguredModel>, cx: &mut Context<Self>) {
+        match (self.judge_model.as_ref(), model.as_ref()) {
+            (Some(old), Some(new)) if old.is_same_as(new) => {}
+            (None, None) => {}
+            _ => cx.emit(Event::JudgeModelChanged),
+        }
+        self.judge_model = model;
+    }
+
     pub fn default_model(&self) -> Option<ConfiguredModel> {
         #[cfg(debug_assertions)]
         if std::env::var("ZED_SIMULATE_NO_LLM_PROVIDER").is_ok() {
@@ -327,6 +343,18 @@ impl LanguageModelRegistry {
             .or_else(|| self.default_model.clone())
     }
 
+    /// The model that ranks the responses of the models a prompt is fanned out to.
+    pub fn judge_model(&self) -> Option<ConfiguredModel> {
+        #[cfg(debug_assertions)]
+        if std::env::var("ZED_SIMULATE_NO_LLM_PROVIDER").is_ok() {
+            return None;
+        }
+
+        self.judge_model
+            .clone()
+            .or_else(|| self.default_model.clone())
+    }
+
     /// The models to use for inline assists. Returns the union of the active
     /// model and all inline alternatives. When there are multiple models, the
     /// user will be able to cycle through results.
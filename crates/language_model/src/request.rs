@@ -1,6 +1,8 @@
 use std::io::{Cursor, Write};
+use std::path::Path;
 use std::sync::Arc;
 
+use crate::message_handler::PromptExperimentAssignment;
 use crate::role::Role;
 use crate::{LanguageModelToolUse, LanguageModelToolUseId};
 use anyhow::Result;
@@ -380,6 +382,179 @@ pub enum LanguageModelToolChoice {
     None,
 }
 
+/// The template a request's system prompt was rendered from, so that stored messages can name the
+/// exact version of it that was used.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RequestPromptTemplate {
+    pub id: String,
+    pub source: Arc<str>,
+    /// Set when the template is a variant a prompt experiment assigned to the thread.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub experiment: Option<PromptExperimentAssignment>,
+}
+
+/// Which participant of a shared project made the request, since the host and each guest run
+/// their own completions against the same store.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RequestOrigin {
+    pub peer_id: String,
+    /// Whether the participant is the project's host, rather than a guest.
+    pub is_host: bool,
+}
+
+/// The buffer the request was made from, to break down usage by language and project area.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RequestBuffer {
+    pub language: Option<String>,
+    pub area: ProjectArea,
+}
+
+/// The part of a project a file belongs to, as told by its path.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum ProjectArea {
+    Source,
+    Tests,
+    Docs,
+    Config,
+    Build,
+    Other,
+}
+
+impl ProjectArea {
+    pub fn for_path(path: &Path) -> Self {
+        let file_name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default();
+        let file_stem = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or_default();
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or_default();
+        let in_directory = |names: &[&str]| {
+            path.parent().is_some_and(|parent| {
+                parent.components().any(|component| {
+                    component
+                        .as_os_str()
+                        .to_str()
+                        .is_some_and(|component| names.contains(&component))
+                })
+            })
+        };
+
+        if in_directory(&["test", "tests", "spec", "specs", "__tests__", "testdata"])
+            || file_stem.starts_with("test_")
+            || file_stem.ends_with("_test")
+            || file_stem.ends_with("_tests")
+            || file_stem.ends_with(".test")
+            || file_stem.ends_with(".spec")
+        {
+            Self::Tests
+        } else if in_directory(&["doc", "docs"])
+            || matches!(extension, "md" | "mdx" | "rst" | "adoc" | "txt")
+        {
+            Self::Docs
+        } else if in_directory(&[".github", "ci", "scripts"])
+            || matches!(
+                file_name,
+                "build.rs"
+                    | "Makefile"
+                    | "Dockerfile"
+                    | "CMakeLists.txt"
+                    | "Cargo.toml"
+                    | "package.json"
+                    | "pyproject.toml"
+                    | "build.gradle"
+                    | "pom.xml"
+            )
+        {
+            Self::Build
+        } else if file_name.starts_with('.')
+            || matches!(
+                extension,
+                "toml" | "yaml" | "yml" | "json" | "jsonc" | "ini" | "cfg" | "conf" | "env"
+            )
+        {
+            Self::Config
+        } else if extension.is_empty() {
+            Self::Other
+        } else {
+            Self::Source
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Source => "source",
+            Self::Tests => "tests",
+            Self::Docs => "docs",
+            Self::Config => "config",
+            Self::Build => "build",
+            Self::Other => "other",
+        }
+    }
+}
+
+/// The feature a request was made from, to break down usage by feature.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum RequestSource {
+    /// A thread of the agent panel, including its summaries and the edits of its tools.
+    AgentPanel,
+    InlineAssist,
+    TerminalAssist,
+    /// A text thread, whose context slash commands insert.
+    TextThread,
+    /// The rules library.
+    Rules,
+    /// The commit messages the git panel generates.
+    GitCommit,
+    /// The file summaries of the semantic index.
+    SemanticIndex,
+}
+
+impl RequestSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::AgentPanel => "agent_panel",
+            Self::InlineAssist => "inline_assist",
+            Self::TerminalAssist => "terminal_assist",
+            Self::TextThread => "text_thread",
+            Self::Rules => "rules",
+            Self::GitCommit => "git_commit",
+            Self::SemanticIndex => "semantic_index",
+        }
+    }
+}
+
+/// The toolchain (interpreter, SDK) active where the request originates from.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RequestToolchain {
+    pub language: String,
+    pub name: String,
+    pub path: String,
+    pub version: Option<String>,
+}
+
+impl RequestToolchain {
+    /// Describes the toolchain for the model, so its suggestions target the right interpreter/SDK.
+    pub fn to_prompt(&self) -> String {
+        let mut prompt = format!(
+            "The active {} toolchain is {} at `{}`",
+            self.language, self.name, self.path
+        );
+        if let Some(version) = &self.version {
+            prompt.push_str(&format!(" (version {version})"));
+        }
+        prompt.push('.');
+        prompt
+    }
+}
+
 #[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
 pub struct LanguageModelRequest {
     pub thread_id: Option<String>,
@@ -392,6 +567,39 @@ pub struct LanguageModelRequest {
     pub tool_choice: Option<LanguageModelToolChoice>,
     pub stop: Vec<String>,
     pub temperature: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub toolchain: Option<RequestToolchain>,
+    /// The project the request was made from, recorded with stored messages so that shared
+    /// stores can scope what each reader sees.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub project: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt_template: Option<RequestPromptTemplate>,
+    /// Set when the request was made from a shared project.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<RequestOrigin>,
+    /// The buffer the request was made from, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub buffer: Option<RequestBuffer>,
+    /// The git branch checked out in the project's active repository, so that stored threads
+    /// can be listed by the branch they were started on.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub git_branch: Option<String>,
+    /// The scheduled run that made the request, if one did, so that the threads of each schedule
+    /// can be told apart.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schedule_id: Option<String>,
+    /// Shared by the sibling requests a request was fanned out to, one for each model, so that
+    /// their threads can be compared.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fan_out_group: Option<String>,
+    /// The id, in whatever sent the request, of the message it was sent for, to acknowledge the
+    /// writes of the request and its completion under.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message_id: Option<String>,
+    /// The feature the request was made from, if it was made from one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<RequestSource>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Eq, PartialEq)]
@@ -404,6 +612,24 @@ pub struct LanguageModelResponseMessage {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_project_area_for_path() {
+        let area = |path: &str| ProjectArea::for_path(Path::new(path));
+        assert_eq!(area("crates/editor/src/editor.rs"), ProjectArea::Source);
+        assert_eq!(
+            area("crates/editor/src/editor_tests.rs"),
+            ProjectArea::Tests
+        );
+        assert_eq!(area("web/src/__tests__/app.tsx"), ProjectArea::Tests);
+        assert_eq!(area("web/src/app.test.tsx"), ProjectArea::Tests);
+        assert_eq!(area("docs/src/configuring-zed.md"), ProjectArea::Docs);
+        assert_eq!(area("crates/editor/Cargo.toml"), ProjectArea::Build);
+        assert_eq!(area(".github/workflows/ci.yml"), ProjectArea::Build);
+        assert_eq!(area("assets/settings/default.json"), ProjectArea::Config);
+        assert_eq!(area(".gitignore"), ProjectArea::Config);
+        assert_eq!(area("LICENSE"), ProjectArea::Other);
+    }
+
     #[test]
     fn test_language_model_tool_result_content_deserialization() {
         let json = r#""This is plain text""#;
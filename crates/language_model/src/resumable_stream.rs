@@ -0,0 +1,335 @@
+use anyhow::{Result, anyhow};
+use futures::channel::{mpsc, oneshot};
+use futures::stream::{self, BoxStream};
+use futures::{FutureExt as _, StreamExt as _, future::BoxFuture, select_biased};
+use gpui::AsyncApp;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::message_handler::{AiMessageHandler, MessageHandlerRegistry, get_message_handler_async};
+use crate::{
+    LanguageModel, LanguageModelCompletionError, LanguageModelCompletionEvent,
+    LanguageModelRequest, LanguageModelRequestMessage, MessageContent, Role, TokenUsage,
+};
+
+/// How many times a response is resumed before the error that interrupted it is passed on.
+const MAX_RESUMPTIONS: usize = 2;
+
+/// What models that don't continue their own messages are asked, after the response so far.
+const CONTINUE_INSTRUCTION: &str = "Your previous response was cut off. Continue it exactly \
+    where it stopped, without repeating any of it or commenting on the interruption.";
+
+/// How a model is asked for the rest of a response that was interrupted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Continuation {
+    /// The response so far is sent as the start of the model's message, which it carries on.
+    Prefill,
+    /// The response so far is sent as the model's message, followed by asking it to continue.
+    Instruction,
+}
+
+impl Continuation {
+    pub fn for_model(model: &dyn LanguageModel) -> Self {
+        if model.supports_assistant_prefill() {
+            Self::Prefill
+        } else {
+            Self::Instruction
+        }
+    }
+}
+
+/// Where an interrupted response was spliced with the rest of it, stored in its thread between
+/// the two, so that the thread reads as the one response the user saw.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StreamSplice {
+    /// How many times the response has been resumed, this time included.
+    pub resumption: usize,
+    /// The response's text received before the stream failed, which the rest of it follows.
+    pub partial_text: String,
+    pub error: String,
+    pub continuation: Continuation,
+}
+
+/// Whether the error is the connection to the provider failing, which resuming may get past,
+/// rather than the provider rejecting the request or the response.
+pub fn is_transient(error: &LanguageModelCompletionError) -> bool {
+    match error {
+        LanguageModelCompletionError::BadInputJson { .. } => false,
+        LanguageModelCompletionError::Other(error) => error.chain().any(|cause| {
+            cause.downcast_ref::<std::io::Error>().is_some_and(|error| {
+                matches!(
+                    error.kind(),
+                    std::io::ErrorKind::ConnectionReset
+                        | std::io::ErrorKind::ConnectionAborted
+                        | std::io::ErrorKind::BrokenPipe
+                        | std::io::ErrorKind::TimedOut
+                        | std::io::ErrorKind::UnexpectedEof
+                        | std::io::ErrorKind::Interrupted
+                )
+            })
+        }),
+    }
+}
+
+/// The request for the rest of the response, of which `partial_text` was received.
+pub fn continuation_request(
+    request: &LanguageModelRequest,
+    partial_text: &str,
+    continuation: Continuation,
+) -> LanguageModelRequest {
+    let mut request = request.clone();
+    let text = match continuation {
+        // Providers reject messages the model is to carry on that end in whitespace.
+        Continuation::Prefill => partial_text.trim_end(),
+        Continuation::Instruction => partial_text,
+    };
+    request.messages.push(LanguageModelRequestMessage {
+        role: Role::Assistant,
+        content: vec![MessageContent::Text(text.to_string())],
+        cache: false,
+    });
+    if continuation == Continuation::Instruction {
+        request.messages.push(LanguageModelRequestMessage {
+            role: Role::User,
+            content: vec![MessageContent::Text(CONTINUE_INSTRUCTION.to_string())],
+            cache: false,
+        });
+    }
+    request
+}
+
+/// Requests a completion like [`LanguageModel::stream_completion`], resuming it when the
+/// connection to the provider fails partway through its text. The rest of the response is
+/// requested with the text received as context, and streamed on as though nothing happened,
+/// while the point the two were spliced at is stored in the request's thread.
+///
+/// Responses that had started calling tools aren't resumed, as the calls can't be spliced.
+pub fn stream_resumable_completion(
+    model: Arc<dyn LanguageModel>,
+    request: LanguageModelRequest,
+    cx: &AsyncApp,
+) -> BoxFuture<
+    'static,
+    Result<BoxStream<'static, Result<LanguageModelCompletionEvent, LanguageModelCompletionError>>>,
+> {
+    let handler = cx
+        .update(|cx| {
+            cx.has_global::<MessageHandlerRegistry>()
+                .then(|| get_message_handler_async(cx))
+                .flatten()
+        })
+        .ok()
+        .flatten();
+    let first_attempt = model.stream_completion(request.clone(), cx);
+    let (started_tx, started_rx) = oneshot::channel();
+    let (events_tx, events_rx) = mpsc::unbounded();
+    let (cancel_tx, cancel_rx) = oneshot::channel::<()>();
+    cx.spawn(async move |cx| {
+        let events = match first_attempt.await {
+            Ok(events) => {
+                started_tx.send(Ok(())).ok();
+                events
+            }
+            Err(error) => {
+                started_tx.send(Err(error)).ok();
+                return;
+            }
+        };
+        let resumable = ResumableResponse {
+            continuation: Continuation::for_model(model.as_ref()),
+            model,
+            request,
+            handler,
+            partial_text: String::new(),
+            calls_tools: false,
+            resumptions: 0,
+            usage_before: TokenUsage::default(),
+            usage: TokenUsage::default(),
+        };
+        resumable.forward(events, events_tx, cancel_rx, cx).await;
+    })
+    .detach();
+    async move {
+        started_rx
+            .await
+            .map_err(|_| anyhow!("the completion was dropped before it started"))??;
+        // The caller dropping the stream drops the sender, which stops the provider's stream
+        // too, rather than leaving it to run until its next event.
+        Ok(stream::unfold(
+            (events_rx, cancel_tx),
+            |(mut events_rx, cancel_tx)| async move {
+                let event = events_rx.next().await?;
+                Some((event, (events_rx, cancel_tx)))
+            },
+        )
+        .boxed())
+    }
+    .boxed()
+}
+
+/// The response streamed so far, across the attempts it took.
+struct ResumableResponse {
+    model: Arc<dyn LanguageModel>,
+    request: LanguageModelRequest,
+    handler: Option<Arc<AiMessageHandler>>,
+    continuation: Continuation,
+    partial_text: String,
+    calls_tools: bool,
+    resumptions: usize,
+    /// The usage of the attempts before the current one, which its usage is reported on top of.
+    usage_before: TokenUsage,
+    usage: TokenUsage,
+}
+
+impl ResumableResponse {
+    async fn forward(
+        mut self,
+        mut events: BoxStream<
+            'static,
+            Result<LanguageModelCompletionEvent, LanguageModelCompletionError>,
+        >,
+        events_tx: mpsc::UnboundedSender<
+            Result<LanguageModelCompletionEvent, LanguageModelCompletionError>,
+        >,
+        mut cancelled: oneshot::Receiver<()>,
+        cx: &mut AsyncApp,
+    ) {
+        loop {
+            let event = select_biased! {
+                _ = cancelled => return,
+                event = events.next().fuse() => event,
+            };
+            let Some(event) = event else {
+                return;
+            };
+            let event = match event {
+                Ok(event) => match self.observe(event) {
+                    Some(event) => Ok(event),
+                    None => continue,
+                },
+                Err(error) => match self.resume(&error, cx).await {
+                    Some(resumed) => {
+                        events = resumed;
+                        continue;
+                    }
+                    None => Err(error),
+                },
+            };
+            if events_tx.unbounded_send(event).is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Notes what the event adds to the response, returning it as the caller is to see it.
+    fn observe(
+        &mut self,
+        event: LanguageModelCompletionEvent,
+    ) -> Option<LanguageModelCompletionEvent> {
+        match event {
+            LanguageModelCompletionEvent::Text(text) => {
+                self.partial_text.push_str(&text);
+                Some(LanguageModelCompletionEvent::Text(text))
+            }
+            LanguageModelCompletionEvent::ToolUse(tool_use) => {
+                self.calls_tools = true;
+                Some(LanguageModelCompletionEvent::ToolUse(tool_use))
+            }
+            LanguageModelCompletionEvent::UsageUpdate(usage) => {
+                self.usage = usage;
+                Some(LanguageModelCompletionEvent::UsageUpdate(
+                    self.usage_before + usage,
+                ))
+            }
+            // The rest of the response continues the message the caller already started.
+            LanguageModelCompletionEvent::StartMessage { .. } if self.resumptions > 0 => None,
+            event => Some(event),
+        }
+    }
+
+    fn can_resume(&self, error: &LanguageModelCompletionError) -> bool {
+        self.resumptions < MAX_RESUMPTIONS
+            && !self.partial_text.is_empty()
+            && !self.calls_tools
+            && is_transient(error)
+    }
+
+    /// Requests the rest of the response, if the error can be resumed from.
+    async fn resume(
+        &mut self,
+        error: &LanguageModelCompletionError,
+        cx: &mut AsyncApp,
+    ) -> Option<
+        BoxStream<'static, Result<LanguageModelCompletionEvent, LanguageModelCompletionError>>,
+    > {
+        if !self.can_resume(error) {
+            return None;
+        }
+        self.resumptions += 1;
+        log::warn!(
+            "Resuming a completion from {} after its stream failed: {error:#}",
+            self.model.id().0
+        );
+        let splice = StreamSplice {
+            resumption: self.resumptions,
+            partial_text: self.partial_text.clone(),
+            error: format!("{error:#}"),
+            continuation: self.continuation,
+        };
+        if let Some(handler) = &self.handler {
+            handler.record_stream_splice(&self.request, self.model.id(), &splice);
+        }
+        let request = continuation_request(&self.request, &self.partial_text, self.continuation);
+        match self.model.stream_completion(request, cx).await {
+            Ok(events) => {
+                self.usage_before = self.usage_before + self.usage;
+                self.usage = TokenUsage::default();
+                Some(events)
+            }
+            Err(resume_error) => {
+                log::error!("Failed to resume the completion: {resume_error:#}");
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_continuation_requests() {
+        let request = LanguageModelRequest {
+            messages: vec![LanguageModelRequestMessage {
+                role: Role::User,
+                content: vec![MessageContent::Text("Explain the parser.".into())],
+                cache: false,
+            }],
+            ..Default::default()
+        };
+
+        let prefill = continuation_request(&request, "The parser reads ", Continuation::Prefill);
+        assert_eq!(prefill.messages.len(), 2);
+        assert_eq!(prefill.messages[1].role, Role::Assistant);
+        assert_eq!(prefill.messages[1].string_contents(), "The parser reads");
+
+        let instruction =
+            continuation_request(&request, "The parser reads ", Continuation::Instruction);
+        assert_eq!(instruction.messages.len(), 3);
+        assert_eq!(
+            instruction.messages[1].string_contents(),
+            "The parser reads "
+        );
+        assert_eq!(instruction.messages[2].role, Role::User);
+
+        let reset = LanguageModelCompletionError::Other(anyhow::Error::new(std::io::Error::new(
+            std::io::ErrorKind::ConnectionReset,
+            "connection reset by peer",
+        )));
+        assert!(is_transient(&reset));
+        let rejected = LanguageModelCompletionError::Other(anyhow!("invalid request"));
+        assert!(!is_transient(&rejected));
+    }
+}
@@ -0,0 +1,191 @@
+use futures::Stream;
+use futures::channel::mpsc;
+use std::{
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll, ready},
+};
+
+use crate::{LanguageModelCompletionError, LanguageModelCompletionEvent};
+
+/// Sees each event of a completion stream as whoever consumes the stream reads it.
+pub trait CompletionStreamObserver: Send + Sync {
+    fn on_event(&self, event: &LanguageModelCompletionEvent);
+
+    fn on_error(&self, _error: &LanguageModelCompletionError) {}
+
+    /// Called when the stream is dropped before it ends, as it is when the user cancels the
+    /// generation, before `on_end`.
+    fn on_cancel(&self) {}
+
+    /// Called once, when the stream ends or is dropped before it does.
+    fn on_end(&self) {}
+}
+
+impl<F> CompletionStreamObserver for F
+where
+    F: Fn(&LanguageModelCompletionEvent) + Send + Sync,
+{
+    fn on_event(&self, event: &LanguageModelCompletionEvent) {
+        self(event)
+    }
+}
+
+/// Passes a completion stream through unchanged while showing its events to any number of
+/// observers (e.g. persistence, telemetry, token counting) and channel subscribers (e.g. a live
+/// view), so that each doesn't have to wrap the stream itself.
+pub struct CompletionStreamTee<S> {
+    stream: S,
+    observers: Vec<Arc<dyn CompletionStreamObserver>>,
+    subscribers: Vec<mpsc::UnboundedSender<LanguageModelCompletionEvent>>,
+    ended: bool,
+}
+
+impl<S> CompletionStreamTee<S> {
+    pub fn new(stream: S) -> Self {
+        Self {
+            stream,
+            observers: Vec::new(),
+            subscribers: Vec::new(),
+            ended: false,
+        }
+    }
+
+    pub fn with_observer(mut self, observer: Arc<dyn CompletionStreamObserver>) -> Self {
+        self.observers.push(observer);
+        self
+    }
+
+    /// A channel of the stream's events, closed when the stream ends. Errors aren't sent.
+    pub fn subscribe(&mut self) -> mpsc::UnboundedReceiver<LanguageModelCompletionEvent> {
+        let (tx, rx) = mpsc::unbounded();
+        self.subscribers.push(tx);
+        rx
+    }
+
+    fn end(&mut self) {
+        if !self.ended {
+            self.ended = true;
+            self.subscribers.clear();
+            for observer in &self.observers {
+                observer.on_end();
+            }
+        }
+    }
+}
+
+impl<S> Stream for CompletionStreamTee<S>
+where
+    S: Stream<Item = Result<LanguageModelCompletionEvent, LanguageModelCompletionError>>,
+{
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let this = unsafe { self.get_unchecked_mut() };
+        if this.ended {
+            return Poll::Ready(None);
+        }
+        let item = ready!(unsafe { Pin::new_unchecked(&mut this.stream) }.poll_next(cx));
+        match &item {
+            Some(Ok(event)) => {
+                for observer in &this.observers {
+                    observer.on_event(event);
+                }
+                this.subscribers
+                    .retain(|subscriber| subscriber.unbounded_send(event.clone()).is_ok());
+            }
+            Some(Err(error)) => {
+                for observer in &this.observers {
+                    observer.on_error(error);
+                }
+            }
+            None => this.end(),
+        }
+        Poll::Ready(item)
+    }
+}
+
+impl<S> Drop for CompletionStreamTee<S> {
+    fn drop(&mut self) {
+        if !self.ended {
+            for observer in &self.observers {
+                observer.on_cancel();
+            }
+        }
+        self.end();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt as _;
+    use parking_lot::Mutex;
+
+    #[derive(Default)]
+    struct Recorder {
+        events: Mutex<Vec<LanguageModelCompletionEvent>>,
+        cancels: Mutex<usize>,
+        ends: Mutex<usize>,
+    }
+
+    impl CompletionStreamObserver for Recorder {
+        fn on_event(&self, event: &LanguageModelCompletionEvent) {
+            self.events.lock().push(event.clone());
+        }
+
+        fn on_cancel(&self) {
+            *self.cancels.lock() += 1;
+        }
+
+        fn on_end(&self) {
+            *self.ends.lock() += 1;
+        }
+    }
+
+    #[test]
+    fn test_every_observer_sees_every_event() {
+        let events = vec![
+            LanguageModelCompletionEvent::Text("Hello".into()),
+            LanguageModelCompletionEvent::Text(" world".into()),
+        ];
+        let first = Arc::new(Recorder::default());
+        let second = Arc::new(Recorder::default());
+        let stream = futures::stream::iter(
+            events
+                .clone()
+                .into_iter()
+                .map(Ok::<_, LanguageModelCompletionError>),
+        );
+        let mut tee = CompletionStreamTee::new(stream)
+            .with_observer(first.clone())
+            .with_observer(second.clone());
+        let subscriber = tee.subscribe();
+
+        let read = smol::block_on(tee.map(|event| event.unwrap()).collect::<Vec<_>>());
+        assert_eq!(read, events);
+        assert_eq!(*first.events.lock(), events);
+        assert_eq!(*second.events.lock(), events);
+        assert_eq!(*first.ends.lock(), 1);
+        assert_eq!(*first.cancels.lock(), 0);
+        assert_eq!(smol::block_on(subscriber.collect::<Vec<_>>()), events);
+    }
+
+    #[test]
+    fn test_dropping_the_stream_before_it_ends_cancels_it() {
+        let recorder = Arc::new(Recorder::default());
+        let stream = futures::stream::iter([
+            Ok::<_, LanguageModelCompletionError>(LanguageModelCompletionEvent::Text(
+                "Hello".into(),
+            )),
+            Ok(LanguageModelCompletionEvent::Text(" world".into())),
+        ]);
+        let mut tee = CompletionStreamTee::new(stream).with_observer(recorder.clone());
+
+        assert!(smol::block_on(tee.next()).is_some());
+        drop(tee);
+        assert_eq!(recorder.events.lock().len(), 1);
+        assert_eq!(*recorder.cancels.lock(), 1);
+        assert_eq!(*recorder.ends.lock(), 1);
+    }
+}
@@ -0,0 +1,231 @@
+use anyhow::{Result, anyhow};
+use futures::{FutureExt as _, StreamExt as _, future::BoxFuture};
+use gpui::AsyncApp;
+use schemars::JsonSchema;
+use serde_json::Value;
+use std::sync::Arc;
+use thiserror::Error;
+
+use crate::message_handler::{MessageHandlerRegistry, get_message_handler_async};
+use crate::{
+    LanguageModel, LanguageModelCompletionError, LanguageModelCompletionEvent, LanguageModelRequest,
+};
+
+/// The JSON schema a model's response is expected to match.
+#[derive(Clone)]
+pub struct StructuredOutputSchema {
+    schema: Value,
+    validator: Arc<jsonschema::Validator>,
+}
+
+impl StructuredOutputSchema {
+    pub fn new(schema: Value) -> Result<Self> {
+        let validator = jsonschema::validator_for(&schema)
+            .map_err(|error| anyhow!("invalid schema: {error}"))?;
+        Ok(Self {
+            schema,
+            validator: Arc::new(validator),
+        })
+    }
+
+    /// The schema of a type the response is deserialized into.
+    pub fn of<T: JsonSchema>() -> Result<Self> {
+        Self::new(serde_json::to_value(schemars::schema_for!(T))?)
+    }
+
+    pub fn schema(&self) -> &Value {
+        &self.schema
+    }
+
+    /// Where the value doesn't match the schema, if anywhere.
+    pub fn validate(&self, value: &Value) -> Vec<String> {
+        self.validator
+            .iter_errors(value)
+            .map(|error| format!("{}: {error}", error.instance_path))
+            .collect()
+    }
+}
+
+/// A response that matched its schema.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StructuredOutput {
+    pub value: Value,
+    /// The text the model sent.
+    pub raw: String,
+    /// What the text was repaired to, if it wasn't JSON as sent, e.g. because the response was
+    /// cut off at its token limit.
+    pub repaired: Option<String>,
+}
+
+#[derive(Debug, Error)]
+pub enum StructuredOutputError {
+    #[error(transparent)]
+    Completion(#[from] LanguageModelCompletionError),
+    #[error("the response isn't JSON, even once repaired: {error}")]
+    Unparseable { raw: String, error: String },
+    #[error("the response doesn't match its schema: {}", errors.join("; "))]
+    Invalid {
+        value: Value,
+        raw: String,
+        repaired: Option<String>,
+        errors: Vec<String>,
+    },
+}
+
+/// Collects a streamed JSON response, and parses and validates it once the model stops.
+pub struct StructuredOutputParser {
+    schema: StructuredOutputSchema,
+    raw: String,
+}
+
+impl StructuredOutputParser {
+    pub fn new(schema: StructuredOutputSchema) -> Self {
+        Self {
+            schema,
+            raw: String::new(),
+        }
+    }
+
+    pub fn push(&mut self, text: &str) {
+        self.raw.push_str(text);
+    }
+
+    pub fn raw(&self) -> &str {
+        &self.raw
+    }
+
+    /// The response so far, completed so that it parses, to show it while it streams. It isn't
+    /// validated, since what the schema requires may not have arrived yet.
+    pub fn partial(&self) -> Option<Value> {
+        let json = strip_code_fence(&self.raw);
+        if json.is_empty() {
+            return None;
+        }
+        serde_json::from_str(&partial_json_fixer::fix_json(json)).ok()
+    }
+
+    /// Parses the response, repairing it if it was cut off, and validates it against the schema.
+    pub fn finish(self) -> Result<StructuredOutput, StructuredOutputError> {
+        let json = strip_code_fence(&self.raw);
+        let (value, repaired) = match serde_json::from_str::<Value>(json) {
+            Ok(value) => {
+                let repaired = (json != self.raw).then(|| json.to_string());
+                (value, repaired)
+            }
+            Err(error) => {
+                let repaired = partial_json_fixer::fix_json(json);
+                match serde_json::from_str::<Value>(&repaired) {
+                    Ok(value) => (value, Some(repaired)),
+                    Err(_) => {
+                        return Err(StructuredOutputError::Unparseable {
+                            raw: self.raw,
+                            error: error.to_string(),
+                        });
+                    }
+                }
+            }
+        };
+        let errors = self.schema.validate(&value);
+        if !errors.is_empty() {
+            return Err(StructuredOutputError::Invalid {
+                value,
+                raw: self.raw,
+                repaired,
+                errors,
+            });
+        }
+        Ok(StructuredOutput {
+            value,
+            raw: self.raw,
+            repaired,
+        })
+    }
+}
+
+/// Models often wrap JSON in a Markdown code block even when asked not to.
+fn strip_code_fence(text: &str) -> &str {
+    let text = text.trim();
+    let Some(fenced) = text.strip_prefix("```") else {
+        return text;
+    };
+    let fenced = fenced.strip_prefix("json").unwrap_or(fenced);
+    fenced.strip_suffix("```").unwrap_or(fenced).trim()
+}
+
+/// Requests a completion whose text is expected to be JSON matching the schema, and parses it
+/// once the model stops. The text as sent, and what it was repaired to, are stored in the
+/// request's thread either way, to debug responses that didn't match.
+pub fn stream_structured_output(
+    model: Arc<dyn LanguageModel>,
+    request: LanguageModelRequest,
+    schema: StructuredOutputSchema,
+    cx: &AsyncApp,
+) -> BoxFuture<'static, Result<StructuredOutput, StructuredOutputError>> {
+    let handler = cx
+        .update(|cx| {
+            cx.has_global::<MessageHandlerRegistry>()
+                .then(|| get_message_handler_async(cx))
+                .flatten()
+        })
+        .ok()
+        .flatten();
+    let events = model.stream_completion(request.clone(), cx);
+    async move {
+        let mut events = events.await.map_err(LanguageModelCompletionError::Other)?;
+        let mut parser = StructuredOutputParser::new(schema);
+        while let Some(event) = events.next().await {
+            match event? {
+                LanguageModelCompletionEvent::Text(text) => parser.push(&text),
+                LanguageModelCompletionEvent::Stop(_) => break,
+                _ => {}
+            }
+        }
+        let output = parser.finish();
+        if let Some(handler) = handler {
+            handler.record_structured_output(&request, model.id(), &output);
+        }
+        output
+    }
+    .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_truncated_output_is_repaired_and_validated() {
+        let schema = StructuredOutputSchema::new(json!({
+            "type": "object",
+            "properties": {
+                "title": { "type": "string" },
+                "tags": { "type": "array", "items": { "type": "string" } }
+            },
+            "required": ["title"]
+        }))
+        .unwrap();
+
+        let mut parser = StructuredOutputParser::new(schema.clone());
+        parser.push("```json\n{\"title\": \"Fix the parser\", \"tags\": [\"bug\", \"pars");
+        assert_eq!(parser.partial().unwrap()["title"], "Fix the parser");
+        let output = parser.finish().unwrap();
+        assert_eq!(
+            output.value,
+            json!({ "title": "Fix the parser", "tags": ["bug", "pars"] })
+        );
+        assert!(output.raw.starts_with("```json"));
+        assert!(output.repaired.is_some());
+
+        let mut parser = StructuredOutputParser::new(schema.clone());
+        parser.push("{\"title\": \"Fix the parser\"}");
+        assert_eq!(parser.finish().unwrap().repaired, None);
+
+        let mut parser = StructuredOutputParser::new(schema);
+        parser.push("{\"tags\": []}");
+        assert!(matches!(
+            parser.finish(),
+            Err(StructuredOutputError::Invalid { errors, .. }) if errors.len() == 1
+        ));
+    }
+}
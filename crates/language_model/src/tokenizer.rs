@@ -0,0 +1,134 @@
+use std::future::Future;
+use std::sync::Arc;
+
+use crate::LanguageModelId;
+
+/// Counts tokens locally, for when a provider doesn't report how many a request used.
+pub trait Tokenizer: Send + Sync {
+    /// Names the tokenizer in the counts it makes.
+    fn name(&self) -> &'static str;
+
+    /// `None` if the tokenizer doesn't know how the model tokenizes.
+    fn count_tokens(&self, model_id: &LanguageModelId, text: &str) -> Option<usize>;
+}
+
+/// Estimates counts from the text's length, for models without a tokenizer available locally.
+pub struct EstimatingTokenizer {
+    name: &'static str,
+    chars_per_token: f32,
+    /// Matched against the model id; `None` matches every model.
+    model_prefix: Option<&'static str>,
+}
+
+impl EstimatingTokenizer {
+    /// Anthropic doesn't publish its tokenizer, and suggests about 3.5 characters per token.
+    pub const ANTHROPIC: Self = Self {
+        name: "anthropic-estimate",
+        chars_per_token: 3.5,
+        model_prefix: Some("claude"),
+    };
+
+    /// The usual rule of thumb for English text and code.
+    pub const DEFAULT: Self = Self {
+        name: "estimate",
+        chars_per_token: 4.0,
+        model_prefix: None,
+    };
+}
+
+impl Tokenizer for EstimatingTokenizer {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn count_tokens(&self, model_id: &LanguageModelId, text: &str) -> Option<usize> {
+        if let Some(prefix) = self.model_prefix {
+            if !model_id.0.starts_with(prefix) {
+                return None;
+            }
+        }
+        Some((text.chars().count() as f32 / self.chars_per_token).ceil() as usize)
+    }
+}
+
+/// A token count and the tokenizer it came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TokenCount {
+    pub tokens: usize,
+    pub tokenizer: &'static str,
+}
+
+/// The registered tokenizers, tried in the order they were registered.
+#[derive(Clone, Default)]
+pub struct Tokenizers {
+    tokenizers: Vec<Arc<dyn Tokenizer>>,
+}
+
+impl Tokenizers {
+    pub fn register(&mut self, tokenizer: Arc<dyn Tokenizer>) {
+        self.tokenizers.push(tokenizer);
+    }
+
+    /// Counts with the first tokenizer that knows the model, estimating if none does.
+    pub fn count_tokens(&self, model_id: &LanguageModelId, text: &str) -> TokenCount {
+        self.tokenizers
+            .iter()
+            .find_map(|tokenizer| {
+                Some(TokenCount {
+                    tokens: tokenizer.count_tokens(model_id, text)?,
+                    tokenizer: tokenizer.name(),
+                })
+            })
+            .unwrap_or_else(|| TokenCount {
+                tokens: EstimatingTokenizer::DEFAULT
+                    .count_tokens(model_id, text)
+                    .unwrap_or_default(),
+                tokenizer: EstimatingTokenizer::DEFAULT.name,
+            })
+    }
+
+    /// Counts each of the texts on a background thread, since loading a tokenizer and counting
+    /// long texts with it take a while.
+    pub fn count_tokens_in_background(
+        &self,
+        model_id: &LanguageModelId,
+        texts: Vec<String>,
+    ) -> impl Future<Output = Vec<TokenCount>> + Send + 'static {
+        let tokenizers = self.clone();
+        let model_id = model_id.clone();
+        smol::unblock(move || {
+            texts
+                .iter()
+                .map(|text| tokenizers.count_tokens(&model_id, text))
+                .collect()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_falls_back_to_estimate() {
+        let mut tokenizers = Tokenizers::default();
+        tokenizers.register(Arc::new(EstimatingTokenizer::ANTHROPIC));
+
+        let claude = LanguageModelId::from("claude-sonnet-4".to_string());
+        assert_eq!(
+            tokenizers.count_tokens(&claude, "1234567"),
+            TokenCount {
+                tokens: 2,
+                tokenizer: "anthropic-estimate"
+            }
+        );
+        let llama = LanguageModelId::from("llama3.2".to_string());
+        assert_eq!(
+            tokenizers.count_tokens(&llama, "1234567"),
+            TokenCount {
+                tokens: 2,
+                tokenizer: "estimate"
+            }
+        );
+    }
+}
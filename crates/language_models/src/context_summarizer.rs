@@ -0,0 +1,191 @@
+use anyhow::{Context as _, Result, anyhow};
+use futures::channel::{mpsc, oneshot};
+use futures::{FutureExt, StreamExt, future::BoxFuture};
+use gpui::{App, AsyncApp};
+use language_model::message_handler::{ContentValue, ContextSummarizer, Message};
+use language_model::{
+    ConfiguredModel, EstimatingTokenizer, LanguageModelId, LanguageModelRegistry,
+    LanguageModelRequest, LanguageModelRequestMessage, MessageContent, RequestIds, Role,
+    Tokenizer as _,
+};
+use zed_llm_client::CompletionIntent;
+
+const SUMMARIZE_CONTEXT_PROMPT: &str = "The following is the stored history of a conversation \
+between a user and a coding agent. Write a detailed summary of it that the agent can resume the \
+conversation from without the history: what the user asked for, what was done, the files and \
+symbols involved, decisions made, and what is left to do.";
+
+const CONTINUE_SUMMARY_PROMPT: &str = "The following is a summary of the start of a \
+conversation between a user and a coding agent, followed by the rest of its stored history. \
+Write a detailed summary of the whole conversation that the agent can resume it from without the \
+history: what the user asked for, what was done, the files and symbols involved, decisions made, \
+and what is left to do.";
+
+type SummaryRequest = (RequestIds, Vec<Message>, oneshot::Sender<Result<String>>);
+
+/// Summarizes compacted threads with the thread summary model. Models are used from the main
+/// thread, so summaries are requested from a task running there.
+pub struct ModelContextSummarizer {
+    requests: mpsc::UnboundedSender<SummaryRequest>,
+}
+
+impl ModelContextSummarizer {
+    pub fn new(cx: &mut App) -> Self {
+        let (requests, mut pending) = mpsc::unbounded::<SummaryRequest>();
+        cx.spawn(async move |cx| {
+            while let Some((ids, messages, tx)) = pending.next().await {
+                tx.send(summarize(&ids, messages, cx).await).ok();
+            }
+        })
+        .detach();
+        Self { requests }
+    }
+}
+
+impl ContextSummarizer for ModelContextSummarizer {
+    fn summarize(
+        &self,
+        ids: &RequestIds,
+        messages: Vec<Message>,
+    ) -> BoxFuture<'static, Result<String>> {
+        let (tx, rx) = oneshot::channel();
+        let sent = self.requests.unbounded_send((ids.clone(), messages, tx));
+        async move {
+            sent.map_err(|_| anyhow!("context summarizer stopped"))?;
+            rx.await?
+        }
+        .boxed()
+    }
+}
+
+/// Summarizes the transcript a chunk at a time, each chunk with the summary of those before it,
+/// so that threads longer than the model's context window can be summarized.
+async fn summarize(ids: &RequestIds, messages: Vec<Message>, cx: &mut AsyncApp) -> Result<String> {
+    let model = cx
+        .update(|cx| LanguageModelRegistry::read_global(cx).thread_summary_model())?
+        .context("no thread summary model configured")?;
+    // Half the window is left for the prompt, the summary so far and the summary written.
+    let chunk_tokens = model.model.max_token_count() / 2;
+    let mut summary = None;
+    for chunk in transcript_chunks(&messages, chunk_tokens) {
+        let prompt = match &summary {
+            None => format!("{SUMMARIZE_CONTEXT_PROMPT}\n\n{chunk}"),
+            Some(summary) => format!("{CONTINUE_SUMMARY_PROMPT}\n\n{summary}\n\n{chunk}"),
+        };
+        summary = Some(request_summary(&model, ids, prompt, cx).await?);
+    }
+    summary.context("nothing to summarize")
+}
+
+async fn request_summary(
+    model: &ConfiguredModel,
+    ids: &RequestIds,
+    prompt: String,
+    cx: &mut AsyncApp,
+) -> Result<String> {
+    // Made under the thread's ids, which the message handler stores requests under with theirs
+    // swapped.
+    let request = LanguageModelRequest {
+        thread_id: Some(ids.session_id.clone()),
+        session_id: Some(ids.thread_id.clone()),
+        prompt_id: Some(ids.prompt_id.clone()),
+        intent: Some(CompletionIntent::ThreadContextSummarization),
+        messages: vec![LanguageModelRequestMessage {
+            role: Role::User,
+            content: vec![MessageContent::Text(prompt)],
+            cache: false,
+        }],
+        ..Default::default()
+    };
+
+    let mut text = model.model.stream_completion_text(request, cx).await?;
+    let mut summary = String::new();
+    while let Some(chunk) = text.stream.next().await {
+        summary.push_str(&chunk?);
+    }
+    Ok(summary)
+}
+
+/// The transcript of the messages, split into chunks of about `max_tokens` each. Messages are
+/// kept whole unless one alone is longer than a chunk, in which case its end is cut.
+fn transcript_chunks(messages: &[Message], max_tokens: usize) -> Vec<String> {
+    let model_id = LanguageModelId::from(String::new());
+    let estimate = |text: &str| {
+        EstimatingTokenizer::DEFAULT
+            .count_tokens(&model_id, text)
+            .unwrap_or_default()
+    };
+    let mut chunks = Vec::new();
+    let mut chunk = String::new();
+    let mut chunk_tokens = 0;
+    for message in messages {
+        let (role, content) = match message {
+            Message::Human { content, .. } => ("user", content),
+            Message::Ai { content, .. } => ("assistant", content),
+            Message::System { content, .. } => ("system", content),
+            Message::Tool { content, .. } | Message::Function { content, .. } => ("tool", content),
+        };
+        let content = match content {
+            ContentValue::Single(content) => content.clone(),
+            ContentValue::Multiple(contents) => contents.join("\n"),
+        };
+        let mut entry = format!("{role}: {content}");
+        let mut tokens = estimate(&entry);
+        if tokens > max_tokens {
+            let chars = entry.chars().count() * max_tokens / tokens;
+            entry = entry.chars().take(chars).collect();
+            tokens = max_tokens;
+        }
+        if !chunk.is_empty() && chunk_tokens + tokens > max_tokens {
+            chunks.push(std::mem::take(&mut chunk));
+            chunk_tokens = 0;
+        }
+        if !chunk.is_empty() {
+            chunk.push_str("\n\n");
+        }
+        chunk.push_str(&entry);
+        chunk_tokens += tokens;
+    }
+    if !chunk.is_empty() {
+        chunks.push(chunk);
+    }
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn human(content: &str) -> Message {
+        Message::Human {
+            content: ContentValue::new(content.to_string()),
+            id: "thread".into(),
+            name: None,
+            example: false,
+            additional_kwargs: Default::default(),
+            response_metadata: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_transcript_is_chunked_to_the_window() {
+        // "user: " and 14 characters estimate to 5 tokens.
+        let messages = [
+            "a".repeat(14),
+            "b".repeat(14),
+            "c".repeat(14),
+            "d".repeat(100),
+        ]
+        .map(|content| human(&content));
+        let chunks = transcript_chunks(&messages, 10);
+        assert_eq!(
+            chunks,
+            [
+                format!("user: {}\n\nuser: {}", "a".repeat(14), "b".repeat(14)),
+                format!("user: {}", "c".repeat(14)),
+                format!("user: {}", "d".repeat(33)),
+            ]
+        );
+        assert!(transcript_chunks(&[], 10).is_empty());
+    }
+}
@@ -0,0 +1,84 @@
+use collections::HashMap;
+use language_model::{LanguageModelId, Tokenizer};
+use parking_lot::Mutex;
+use std::path::PathBuf;
+use std::sync::Arc;
+use util::ResultExt as _;
+
+/// Counts tokens for local models, e.g. Ollama's and LM Studio's, with the Hugging Face
+/// `tokenizer.json` saved for them in the tokenizers directory. A model's file is named after
+/// the model without its tag, with `/` replaced by `--`: `llama3.2:latest` is counted with
+/// `llama3.2.json` and `qwen/qwen2.5-coder-7b` with `qwen--qwen2.5-coder-7b.json`.
+pub struct HuggingFaceTokenizer {
+    dir: PathBuf,
+    /// Each model's tokenizer once looked up, `None` if it has no file or it failed to load.
+    loaded: Mutex<HashMap<String, Option<Arc<tokenizers::Tokenizer>>>>,
+}
+
+impl HuggingFaceTokenizer {
+    pub fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            loaded: Mutex::default(),
+        }
+    }
+
+    fn tokenizer(&self, model_id: &LanguageModelId) -> Option<Arc<tokenizers::Tokenizer>> {
+        let name = tokenizer_file_name(&model_id.0)?;
+        if let Some(tokenizer) = self.loaded.lock().get(&name) {
+            return tokenizer.clone();
+        }
+        let path = self.dir.join(&name);
+        let tokenizer = path
+            .exists()
+            .then(|| {
+                tokenizers::Tokenizer::from_file(&path)
+                    .map_err(|error| anyhow::anyhow!("failed to load {}: {error}", path.display()))
+                    .log_err()
+            })
+            .flatten()
+            .map(Arc::new);
+        self.loaded.lock().insert(name, tokenizer.clone());
+        tokenizer
+    }
+}
+
+impl Tokenizer for HuggingFaceTokenizer {
+    fn name(&self) -> &'static str {
+        "huggingface"
+    }
+
+    fn count_tokens(&self, model_id: &LanguageModelId, text: &str) -> Option<usize> {
+        let encoding = self.tokenizer(model_id)?.encode(text, false).log_err()?;
+        Some(encoding.len())
+    }
+}
+
+/// The name of the model's `tokenizer.json` in the tokenizers directory, `None` for ids that
+/// can't name a file in it.
+fn tokenizer_file_name(model_id: &str) -> Option<String> {
+    let name = model_id.split(':').next()?.to_lowercase();
+    if name.is_empty() || name.contains("..") || name.contains('\\') {
+        return None;
+    }
+    Some(format!("{}.json", name.replace('/', "--")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokenizer_file_name() {
+        assert_eq!(
+            tokenizer_file_name("llama3.2:latest").as_deref(),
+            Some("llama3.2.json")
+        );
+        assert_eq!(
+            tokenizer_file_name("Qwen/Qwen2.5-Coder-7B").as_deref(),
+            Some("qwen--qwen2.5-coder-7b.json")
+        );
+        assert_eq!(tokenizer_file_name("../secrets"), None);
+        assert_eq!(tokenizer_file_name(""), None);
+    }
+}
@@ -1,16 +1,37 @@
 use std::sync::Arc;
+use std::time::Duration;
 
 use client::{Client, UserStore};
 use fs::Fs;
 use gpui::{App, Context, Entity};
-use language_model::LanguageModelRegistry;
-use language_model::message_handler::{init_message_handler, MessageHandlerConfig};
+use language_model::message_handler::{
+    AgentIdentity, CompactionPolicy, Guardrails, IssueCommenter, LangSmithExporter,
+    MessageHandlerConfig, Schedule, ShadowPersistence, ThreadNotifier, UsageExporter,
+    init_message_handler, register_request_interceptor, register_tokenizer, set_agent_identity,
+    set_chaos, set_checkpoint_partitioning, set_collaboration_persistence, set_compaction_policy,
+    set_completion_fixtures, set_context_summarizer, set_conversation_retention,
+    set_disabled_response_interceptors, set_issue_commenter, set_job_schedules, set_lint_policy,
+    set_maintenance_policy, set_message_author, set_message_rules, set_persistence_features,
+    set_prompt_experiments, set_raw_exchange_capture, set_response_cache_policy,
+    set_sampling_policy, set_secret_scan, set_shadow_persistence, set_thread_notifier,
+    set_trace_exporter, set_usage_exporter,
+};
+use language_model::{EstimatingTokenizer, LanguageModelProviderId, LanguageModelRegistry};
 use provider::deepseek::DeepSeekLanguageModelProvider;
+use settings::{Settings as _, SettingsStore};
+use util::ResultExt as _;
+use zed_actions::llm_store::RunRegressionTests;
 
+mod context_summarizer;
+mod hugging_face_tokenizer;
 pub mod provider;
+mod regression_tests;
+mod scheduled_runs;
 mod settings;
 pub mod ui;
 
+use crate::context_summarizer::ModelContextSummarizer;
+use crate::hugging_face_tokenizer::HuggingFaceTokenizer;
 use crate::provider::anthropic::AnthropicLanguageModelProvider;
 use crate::provider::bedrock::BedrockLanguageModelProvider;
 use crate::provider::cloud::CloudLanguageModelProvider;
@@ -19,12 +40,19 @@ use crate::provider::google::GoogleLanguageModelProvider;
 use crate::provider::lmstudio::LmStudioLanguageModelProvider;
 use crate::provider::mistral::MistralLanguageModelProvider;
 use crate::provider::ollama::OllamaLanguageModelProvider;
-use crate::provider::open_ai::OpenAiLanguageModelProvider;
+use crate::provider::open_ai::{OpenAiLanguageModelProvider, TiktokenTokenizer};
 use crate::provider::open_router::OpenRouterLanguageModelProvider;
+use crate::regression_tests::run_regression_tests;
+pub use crate::regression_tests::{OutputAssertion, RegressionTest};
+pub use crate::scheduled_runs::ScheduledRun;
+use crate::scheduled_runs::observe_scheduled_runs;
 pub use crate::settings::*;
 
 pub fn init(user_store: Entity<UserStore>, client: Arc<Client>, fs: Arc<dyn Fs>, cx: &mut App) {
     crate::settings::init(fs, cx);
+    cx.on_action(|action: &RunRegressionTests, cx| {
+        run_regression_tests(action.model.clone(), cx);
+    });
     let registry = LanguageModelRegistry::global(cx);
     registry.update(cx, |registry, cx| {
         register_language_model_providers(registry, user_store, client, cx);
@@ -37,8 +65,39 @@ fn register_language_model_providers(
     client: Arc<Client>,
     cx: &mut Context<LanguageModelRegistry>,
 ) {
-    smol::spawn(init_message_handler(MessageHandlerConfig { postgres_connection_string: None, enable_storage: true }, cx))
-        .detach();
+    observe_message_store(cx);
+    observe_trace_exporter_settings(client.clone(), cx);
+    observe_issue_tracker_settings(client.clone(), cx);
+    observe_notifications(client.clone(), cx);
+    observe_usage_export(client.clone(), cx);
+    observe_message_author(user_store.clone(), cx);
+    observe_prompt_experiments(cx);
+    observe_job_schedules(cx);
+    observe_context_compaction(cx);
+    observe_collaboration_persistence(cx);
+    observe_shadow_persistence(cx);
+    observe_sampling_policy(cx);
+    observe_message_rules(cx);
+    observe_raw_exchange_capture(cx);
+    observe_persistence_features(cx);
+    observe_guardrails(cx);
+    observe_secret_scan(cx);
+    observe_disabled_response_interceptors(cx);
+    observe_response_cache_policy(cx);
+    observe_completion_fixtures(cx);
+    observe_chaos(cx);
+    observe_conversation_retention(cx);
+    observe_checkpoint_partitioning(cx);
+    observe_store_maintenance(cx);
+    observe_conversation_lint(cx);
+    observe_agent_identity(cx);
+    observe_scheduled_runs(cx);
+    // Hugging Face tokenizers for local models aren't bundled: models without one saved in the
+    // tokenizers directory have their counts estimated.
+    let tokenizers_dir = paths::data_dir().join("tokenizers");
+    register_tokenizer(Arc::new(HuggingFaceTokenizer::new(tokenizers_dir)), cx);
+    register_tokenizer(Arc::new(TiktokenTokenizer), cx);
+    register_tokenizer(Arc::new(EstimatingTokenizer::ANTHROPIC), cx);
 
     registry.register_provider(
         CloudLanguageModelProvider::new(user_store.clone(), client.clone(), cx),
@@ -83,3 +142,446 @@ fn register_language_model_providers(
     );
     registry.register_provider(CopilotChatLanguageModelProvider::new(cx), cx);
 }
+
+/// Keeps the LangSmith exporter in line with the settings, replacing it only when they change so
+/// that runs still streaming aren't orphaned by unrelated settings edits.
+fn observe_trace_exporter_settings(client: Arc<Client>, cx: &mut App) {
+    let mut config = None;
+    let mut update = move |cx: &mut App| {
+        let new_config = AllLanguageModelSettings::get_global(cx)
+            .langsmith
+            .exporter_config();
+        if new_config == config {
+            return;
+        }
+        config = new_config.clone();
+        let exporter =
+            new_config.map(|config| Arc::new(LangSmithExporter::new(client.http_client(), config)));
+        set_trace_exporter(exporter, cx);
+    };
+    update(cx);
+    cx.observe_global::<SettingsStore>(update).detach();
+}
+
+/// Keeps the issue commenter in line with the settings, replacing it only when they change.
+fn observe_issue_tracker_settings(client: Arc<Client>, cx: &mut App) {
+    let mut config = None;
+    let mut update = move |cx: &mut App| {
+        let new_config = AllLanguageModelSettings::get_global(cx)
+            .issue_trackers
+            .tracker_config();
+        if config.as_ref() == Some(&new_config) {
+            return;
+        }
+        config = Some(new_config.clone());
+        let commenter = IssueCommenter::new(client.http_client(), new_config);
+        set_issue_commenter(Some(Arc::new(commenter)), cx);
+    };
+    update(cx);
+    cx.observe_global::<SettingsStore>(update).detach();
+}
+
+/// Keeps the thread notifier in line with the settings. Replacing it forgets which threads were
+/// notified about, so it is only replaced when they change.
+fn observe_notifications(client: Arc<Client>, cx: &mut App) {
+    let mut policy = None;
+    let mut update = move |cx: &mut App| {
+        let new_policy = AllLanguageModelSettings::get_global(cx)
+            .notifications
+            .clone()
+            .filter(|policy| !policy.webhook_url.is_empty());
+        if policy.as_ref() == Some(&new_policy) {
+            return;
+        }
+        policy = Some(new_policy.clone());
+        let notifier =
+            new_policy.map(|policy| Arc::new(ThreadNotifier::new(client.http_client(), policy)));
+        set_thread_notifier(notifier, cx);
+    };
+    update(cx);
+    cx.observe_global::<SettingsStore>(update).detach();
+}
+
+fn observe_usage_export(client: Arc<Client>, cx: &mut App) {
+    let mut config = None;
+    let mut update = move |cx: &mut App| {
+        let new_config = AllLanguageModelSettings::get_global(cx)
+            .usage_export
+            .clone()
+            .filter(|config| !config.endpoint.is_empty());
+        if config.as_ref() == Some(&new_config) {
+            return;
+        }
+        config = Some(new_config.clone());
+        let exporter =
+            new_config.map(|config| Arc::new(UsageExporter::new(client.http_client(), config)));
+        set_usage_exporter(exporter, cx);
+    };
+    update(cx);
+    cx.observe_global::<SettingsStore>(update).detach();
+}
+
+/// Attributes stored messages to the configured identity, or to the signed-in Zed account.
+fn observe_message_author(user_store: Entity<UserStore>, cx: &mut App) {
+    let update = {
+        let user_store = user_store.clone();
+        move |cx: &mut App| {
+            let user = user_store.read(cx).current_user();
+            let author = AllLanguageModelSettings::get_global(cx)
+                .author
+                .author(user.as_deref());
+            set_message_author(author, cx);
+        }
+    };
+    update(cx);
+    cx.observe_global::<SettingsStore>(update.clone()).detach();
+    cx.observe(&user_store, move |_, cx| update(cx)).detach();
+}
+
+fn observe_prompt_experiments(cx: &mut App) {
+    let update = |cx: &mut App| {
+        let experiments = AllLanguageModelSettings::get_global(cx)
+            .prompt_experiments
+            .clone();
+        set_prompt_experiments(experiments, cx);
+    };
+    update(cx);
+    cx.observe_global::<SettingsStore>(update).detach();
+}
+
+fn observe_context_compaction(cx: &mut App) {
+    let summarizer = ModelContextSummarizer::new(cx);
+    set_context_summarizer(Some(Arc::new(summarizer)), cx);
+    let update = |cx: &mut App| {
+        let policy = AllLanguageModelSettings::get_global(cx)
+            .context_compaction_threshold
+            .map(|max_stored_tokens| CompactionPolicy { max_stored_tokens });
+        set_compaction_policy(policy, cx);
+    };
+    update(cx);
+    cx.observe_global::<SettingsStore>(update).detach();
+}
+
+/// Connects to the configured store, and again whenever its configuration changes.
+fn observe_message_store(cx: &mut App) {
+    let mut connected = None;
+    let mut update = move |cx: &mut App| {
+        let settings = AllLanguageModelSettings::get_global(cx);
+        let config = MessageHandlerConfig {
+            postgres_connection_string: settings.postgres_connection_string.clone(),
+            enable_storage: settings.enable_storage,
+            blob_encoding: settings.blob_encoding,
+            read_only: settings.read_only_store,
+        };
+        if connected.as_ref() == Some(&config) {
+            return;
+        }
+        connected = Some(config.clone());
+        smol::spawn(init_message_handler(config, cx)).detach();
+    };
+    update(cx);
+    cx.observe_global::<SettingsStore>(update).detach();
+}
+
+fn observe_collaboration_persistence(cx: &mut App) {
+    let update = |cx: &mut App| {
+        let persistence = AllLanguageModelSettings::get_global(cx).collaboration_persistence;
+        set_collaboration_persistence(persistence, cx);
+    };
+    update(cx);
+    cx.observe_global::<SettingsStore>(update).detach();
+}
+
+/// Starts a new shadow mode, with fresh counts, only when it is turned on or its encoding changes.
+fn observe_shadow_persistence(cx: &mut App) {
+    let mut config = None;
+    let mut update = move |cx: &mut App| {
+        let settings = AllLanguageModelSettings::get_global(cx);
+        let new_config = settings
+            .shadow_persistence
+            .then_some(settings.blob_encoding);
+        if new_config == config {
+            return;
+        }
+        config = new_config;
+        let shadow =
+            new_config.map(|blob_encoding| Arc::new(ShadowPersistence::new(blob_encoding)));
+        set_shadow_persistence(shadow, cx);
+    };
+    update(cx);
+    cx.observe_global::<SettingsStore>(update).detach();
+}
+
+/// Replaces the sampler only when the policy changes, since it holds the threads it has decided on.
+fn observe_sampling_policy(cx: &mut App) {
+    let mut policy = None;
+    let mut update = move |cx: &mut App| {
+        let new_policy = AllLanguageModelSettings::get_global(cx).sampling;
+        if new_policy == policy {
+            return;
+        }
+        policy = new_policy;
+        set_sampling_policy(policy, cx);
+    };
+    update(cx);
+    cx.observe_global::<SettingsStore>(update).detach();
+}
+
+/// Replaces the response cache only when its policy changes, since it holds the responses.
+fn observe_response_cache_policy(cx: &mut App) {
+    let mut policy = None;
+    let mut update = move |cx: &mut App| {
+        let new_policy = AllLanguageModelSettings::get_global(cx)
+            .response_cache
+            .clone();
+        if policy.as_ref() == Some(&new_policy) {
+            return;
+        }
+        policy = Some(new_policy.clone());
+        set_response_cache_policy(new_policy, cx);
+    };
+    update(cx);
+    cx.observe_global::<SettingsStore>(update).detach();
+}
+
+/// Replaces the fixtures only when their policy changes, since they hold the responses expected
+/// to be recorded.
+fn observe_completion_fixtures(cx: &mut App) {
+    let mut policy = None;
+    let mut update = move |cx: &mut App| {
+        let new_policy = AllLanguageModelSettings::get_global(cx)
+            .completion_fixtures
+            .clone();
+        if policy.as_ref() == Some(&new_policy) {
+            return;
+        }
+        policy = Some(new_policy.clone());
+        set_completion_fixtures(new_policy, cx);
+    };
+    update(cx);
+    cx.observe_global::<SettingsStore>(update).detach();
+}
+
+/// Replaces the injected faults only when their config changes, so that seeded faults don't start
+/// over on unrelated settings changes.
+fn observe_chaos(cx: &mut App) {
+    let mut config = None;
+    let mut update = move |cx: &mut App| {
+        let new_config = AllLanguageModelSettings::get_global(cx).chaos.clone();
+        if config.as_ref() == Some(&new_config) {
+            return;
+        }
+        config = Some(new_config.clone());
+        set_chaos(new_config, cx);
+    };
+    update(cx);
+    cx.observe_global::<SettingsStore>(update).detach();
+}
+
+/// Reschedules garbage collection only when the retention period changes.
+fn observe_conversation_retention(cx: &mut App) {
+    let mut retention_days = None;
+    let mut update = move |cx: &mut App| {
+        let new_retention_days =
+            AllLanguageModelSettings::get_global(cx).conversation_retention_days;
+        if retention_days == Some(new_retention_days) {
+            return;
+        }
+        retention_days = Some(new_retention_days);
+        set_conversation_retention(
+            new_retention_days.map(|days| Duration::from_secs(days * 24 * 60 * 60)),
+            cx,
+        );
+    };
+    update(cx);
+    cx.observe_global::<SettingsStore>(update).detach();
+}
+
+/// Reschedules partition maintenance only when the partitioning changes.
+fn observe_checkpoint_partitioning(cx: &mut App) {
+    let mut partitioning = None;
+    let mut update = move |cx: &mut App| {
+        let new_partitioning = AllLanguageModelSettings::get_global(cx).checkpoint_partitioning;
+        if partitioning == Some(new_partitioning) {
+            return;
+        }
+        partitioning = Some(new_partitioning);
+        set_checkpoint_partitioning(new_partitioning, cx);
+    };
+    update(cx);
+    cx.observe_global::<SettingsStore>(update).detach();
+}
+
+/// Rebuilds the handler only when the maintenance policy changes.
+fn observe_store_maintenance(cx: &mut App) {
+    let mut policy = None;
+    let mut update = move |cx: &mut App| {
+        let new_policy = AllLanguageModelSettings::get_global(cx)
+            .store_maintenance
+            .clone();
+        if policy.as_ref() == Some(&new_policy) {
+            return;
+        }
+        policy = Some(new_policy.clone());
+        set_maintenance_policy(new_policy, cx);
+    };
+    update(cx);
+    cx.observe_global::<SettingsStore>(update).detach();
+}
+
+fn observe_conversation_lint(cx: &mut App) {
+    let mut policy = None;
+    let mut update = move |cx: &mut App| {
+        let new_policy = AllLanguageModelSettings::get_global(cx)
+            .conversation_lint
+            .clone();
+        if policy.as_ref() == Some(&new_policy) {
+            return;
+        }
+        policy = Some(new_policy.clone());
+        set_lint_policy(new_policy, cx);
+    };
+    update(cx);
+    cx.observe_global::<SettingsStore>(update).detach();
+}
+
+fn observe_agent_identity(cx: &mut App) {
+    let mut identity = None;
+    let mut update = move |cx: &mut App| {
+        let new_identity = AllLanguageModelSettings::get_global(cx)
+            .agent_identity
+            .clone();
+        if identity.as_ref() == Some(&new_identity) {
+            return;
+        }
+        identity = Some(new_identity.clone());
+        set_agent_identity(new_identity, cx);
+    };
+    update(cx);
+    cx.observe_global::<SettingsStore>(update).detach();
+}
+
+/// Rebuilds the handler only when capturing is turned on or off.
+fn observe_raw_exchange_capture(cx: &mut App) {
+    let mut capture = None;
+    let mut update = move |cx: &mut App| {
+        let new_capture = AllLanguageModelSettings::get_global(cx).capture_raw_exchanges;
+        if capture == Some(new_capture) {
+            return;
+        }
+        capture = Some(new_capture);
+        set_raw_exchange_capture(new_capture, cx);
+    };
+    update(cx);
+    cx.observe_global::<SettingsStore>(update).detach();
+}
+
+/// Rebuilds the handler only when the parts of completions that are stored change.
+fn observe_persistence_features(cx: &mut App) {
+    let mut features = None;
+    let mut update = move |cx: &mut App| {
+        let new_features = AllLanguageModelSettings::get_global(cx).persistence_features;
+        if features == Some(new_features) {
+            return;
+        }
+        features = Some(new_features);
+        set_persistence_features(new_features, cx);
+    };
+    update(cx);
+    cx.observe_global::<SettingsStore>(update).detach();
+}
+
+/// Registers the guardrails once, and recompiles their rules only when they change. Invalid rules
+/// leave the previous ones in place.
+fn observe_guardrails(cx: &mut App) {
+    let guardrails = Arc::new(Guardrails::default());
+    register_request_interceptor(guardrails.clone(), cx);
+    let mut rules = None;
+    let mut update = move |cx: &mut App| {
+        let new_rules = AllLanguageModelSettings::get_global(cx).guardrails.clone();
+        if rules.as_ref() == Some(&new_rules) {
+            return;
+        }
+        rules = Some(new_rules.clone());
+        guardrails.set_rules(new_rules).log_err();
+    };
+    update(cx);
+    cx.observe_global::<SettingsStore>(update).detach();
+}
+
+/// Replaces the secret scanner only when its policy changes.
+fn observe_secret_scan(cx: &mut App) {
+    let mut policy = None;
+    let mut update = move |cx: &mut App| {
+        let new_policy = AllLanguageModelSettings::get_global(cx).secret_scan.clone();
+        if policy.as_ref() == Some(&new_policy) {
+            return;
+        }
+        policy = Some(new_policy.clone());
+        set_secret_scan(new_policy, cx);
+    };
+    update(cx);
+    cx.observe_global::<SettingsStore>(update).detach();
+}
+
+/// Updates which response interceptors providers skip only when that changes.
+fn observe_disabled_response_interceptors(cx: &mut App) {
+    let mut disabled = None;
+    let mut update = move |cx: &mut App| {
+        let new_disabled = AllLanguageModelSettings::get_global(cx)
+            .disabled_response_interceptors
+            .clone();
+        if disabled.as_ref() == Some(&new_disabled) {
+            return;
+        }
+        disabled = Some(new_disabled.clone());
+        let disabled = new_disabled
+            .into_iter()
+            .map(|(provider_id, names)| (LanguageModelProviderId(provider_id.into()), names))
+            .collect();
+        set_disabled_response_interceptors(disabled, cx);
+    };
+    update(cx);
+    cx.observe_global::<SettingsStore>(update).detach();
+}
+
+/// Recompiles the message rules only when they change.
+fn observe_message_rules(cx: &mut App) {
+    let mut rules = None;
+    let mut update = move |cx: &mut App| {
+        let new_rules = AllLanguageModelSettings::get_global(cx)
+            .message_rules
+            .clone();
+        if rules.as_ref() == Some(&new_rules) {
+            return;
+        }
+        rules = Some(new_rules.clone());
+        set_message_rules(new_rules, cx);
+    };
+    update(cx);
+    cx.observe_global::<SettingsStore>(update).detach();
+}
+
+fn observe_job_schedules(cx: &mut App) {
+    let update = |cx: &mut App| {
+        let schedules = AllLanguageModelSettings::get_global(cx)
+            .scheduled_jobs
+            .iter()
+            .filter_map(|(name, schedule)| {
+                if schedule == "off" {
+                    return Some((name.clone(), None));
+                }
+                match schedule.parse::<Schedule>() {
+                    Ok(schedule) => Some((name.clone(), Some(schedule))),
+                    Err(error) => {
+                        log::error!("Invalid schedule for job {name}: {error:#}");
+                        None
+                    }
+                }
+            })
+            .collect();
+        set_job_schedules(schedules, cx);
+    };
+    update(cx);
+    cx.observe_global::<SettingsStore>(update).detach();
+}
@@ -15,7 +15,10 @@ use gpui::{
     AnyView, App, AsyncApp, Context, Entity, FontStyle, Subscription, Task, TextStyle, WhiteSpace,
 };
 use http_client::HttpClient;
-use language_model::message_handler::{AiMessageHandler, LanguageModelArgs, peek_db};
+use language_model::message_handler::{
+    AiMessageHandler, LanguageModelArgs, ResponseInterceptors, cached_completion,
+    intercept_request, peek_db, raw_exchange_client,
+};
 use language_model::{
     _retrieve_ids, AuthenticateError, LanguageModel, LanguageModelCacheConfiguration,
     LanguageModelCompletionError, LanguageModelId, LanguageModelKnownError, LanguageModelName,
@@ -390,11 +393,10 @@ impl AnthropicModel {
     fn stream_completion(
         &self,
         request: anthropic::Request,
+        http_client: Arc<dyn HttpClient>,
         cx: &AsyncApp,
     ) -> BoxFuture<'static, Result<BoxStream<'static, Result<anthropic::Event, AnthropicError>>>>
     {
-        let http_client = self.http_client.clone();
-
         let Ok((api_key, api_url)) = cx.read_entity(&self.state, |state, cx| {
             let settings = &AllLanguageModelSettings::get_global(cx).anthropic;
             (state.api_key.clone(), settings.api_url.clone())
@@ -445,6 +447,10 @@ impl LanguageModel for AnthropicModel {
         }
     }
 
+    fn supports_assistant_prefill(&self) -> bool {
+        true
+    }
+
     fn telemetry_id(&self) -> String {
         format!("anthropic/{}", self.model.id())
     }
@@ -479,6 +485,14 @@ impl LanguageModel for AnthropicModel {
             BoxStream<'static, Result<LanguageModelCompletionEvent, LanguageModelCompletionError>>,
         >,
     > {
+        let request = match intercept_request(request, self, cx) {
+            Ok(request) => request,
+            Err(blocked) => return futures::future::ready(Err(blocked.into())).boxed(),
+        };
+        let response_interceptors = ResponseInterceptors::for_model(self, cx);
+        if let Some(cached) = cached_completion(&request, self, cx) {
+            return cached;
+        }
         let ids = _retrieve_ids(&request);
 
         // Get message handler for saving messages
@@ -493,8 +507,11 @@ impl LanguageModel for AnthropicModel {
             self.model.max_output_tokens(),
             self.model.mode(),
         );
-        let request = self.stream_completion(request, cx);
+        let http_client =
+            raw_exchange_client(message_handler.as_ref(), self.http_client.clone(), &ids);
+        let request = self.stream_completion(request, http_client, cx);
         let id = self.id.clone();
+        let max_tokens = self.max_token_count();
         let future = self.request_limiter.stream(async move {
             // Save request messages if handler is available
             if let Some(handler) = &message_handler {
@@ -502,7 +519,9 @@ impl LanguageModel for AnthropicModel {
                     .save_completion_req(
                         &request_to_save,
                         &ids,
-                        LanguageModelArgs::from_request(id.clone(), &request_to_save),
+                        LanguageModelArgs::from_request(id.clone(), &request_to_save)
+                            .with_provider(PROVIDER_ID)
+                            .with_max_tokens(max_tokens),
                     )
                     .await;
             }
@@ -517,13 +536,15 @@ impl LanguageModel for AnthropicModel {
             let mapper = AnthropicEventMapper::new();
             let stream = mapper.map_stream(response);
 
-            Ok(peek_db(
-                stream,
-                message_handler,
-                ids,
-                LanguageModelArgs::from_request(id, &request_to_save),
-            )
-            .boxed())
+            Ok(response_interceptors
+                .apply(peek_db(
+                    stream,
+                    message_handler,
+                    ids,
+                    LanguageModelArgs::from_request(id, &request_to_save)
+                        .with_provider(PROVIDER_ID),
+                ))
+                .boxed())
         });
         async move { Ok(future.await?.boxed()) }.boxed()
     }
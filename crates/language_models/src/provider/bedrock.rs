@@ -31,7 +31,10 @@ use gpui::{
 };
 use gpui_tokio::Tokio;
 use http_client::HttpClient;
-use language_model::message_handler::{AiMessageHandler, LanguageModelArgs, peek_db};
+use language_model::message_handler::{
+    AiMessageHandler, LanguageModelArgs, ResponseInterceptors, cached_completion,
+    intercept_request, peek_db,
+};
 use language_model::{
     _retrieve_ids, AuthenticateError, LanguageModel, LanguageModelCacheConfiguration,
     LanguageModelCompletionError, LanguageModelCompletionEvent, LanguageModelId, LanguageModelName,
@@ -530,6 +533,14 @@ impl LanguageModel for BedrockModel {
             BoxStream<'static, Result<LanguageModelCompletionEvent, LanguageModelCompletionError>>,
         >,
     > {
+        let request = match intercept_request(request, self, cx) {
+            Ok(request) => request,
+            Err(blocked) => return futures::future::ready(Err(blocked.into())).boxed(),
+        };
+        let response_interceptors = ResponseInterceptors::for_model(self, cx);
+        if let Some(cached) = cached_completion(&request, self, cx) {
+            return cached;
+        }
         let Ok(region) = cx.read_entity(&self.state, |state, _cx| {
             // Get region - from credentials or directly from settings
             let credentials_region = state.credentials.as_ref().map(|s| s.region.clone());
@@ -571,6 +582,7 @@ impl LanguageModel for BedrockModel {
         let request_future = self.stream_completion(request, cx);
         let message_handler = cx.update(|cx| get_message_handler_async(cx)).ok().flatten();
         let id = self.id.clone();
+        let max_tokens = self.max_token_count();
         let future = self.request_limiter.stream(async move {
             let ids = _retrieve_ids(&original_request);
 
@@ -580,7 +592,9 @@ impl LanguageModel for BedrockModel {
                     .save_completion_req(
                         &original_request,
                         &ids,
-                        LanguageModelArgs::from_request(id.clone(), &original_request),
+                        LanguageModelArgs::from_request(id.clone(), &original_request)
+                            .with_provider(PROVIDER_ID)
+                            .with_max_tokens(max_tokens),
                     )
                     .await;
             }
@@ -588,13 +602,15 @@ impl LanguageModel for BedrockModel {
             let response = request_future.map_err(|err| anyhow!(err))?.await;
             let mapped_stream = map_to_language_model_completion_events(response, owned_handle);
 
-            Ok(peek_db(
-                mapped_stream,
-                message_handler.clone(),
-                ids,
-                LanguageModelArgs::from_request(id, &original_request),
-            )
-            .boxed())
+            Ok(response_interceptors
+                .apply(peek_db(
+                    mapped_stream,
+                    message_handler.clone(),
+                    ids,
+                    LanguageModelArgs::from_request(id, &original_request)
+                        .with_provider(PROVIDER_ID),
+                ))
+                .boxed())
         });
         async move { Ok(future.await?.boxed()) }.boxed()
     }
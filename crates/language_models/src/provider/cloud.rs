@@ -48,7 +48,10 @@ use crate::AllLanguageModelSettings;
 use crate::provider::anthropic::{AnthropicEventMapper, count_anthropic_tokens, into_anthropic};
 use crate::provider::google::{GoogleEventMapper, into_google};
 use crate::provider::open_ai::{OpenAiEventMapper, count_open_ai_tokens, into_open_ai};
-use language_model::message_handler::{AiMessageHandler, LanguageModelArgs, peek_db};
+use language_model::message_handler::{
+    AiMessageHandler, LanguageModelArgs, ResponseInterceptors, cached_completion,
+    intercept_request, peek_db,
+};
 
 pub const PROVIDER_NAME: &str = "Zed";
 
@@ -702,6 +705,13 @@ impl LanguageModel for CloudLanguageModel {
         self.model.supports_max_mode
     }
 
+    fn supports_assistant_prefill(&self) -> bool {
+        matches!(
+            self.model.provider,
+            zed_llm_client::LanguageModelProvider::Anthropic
+        )
+    }
+
     fn telemetry_id(&self) -> String {
         format!("zed.dev/{}", self.model.id)
     }
@@ -812,6 +822,14 @@ impl LanguageModel for CloudLanguageModel {
             BoxStream<'static, Result<LanguageModelCompletionEvent, LanguageModelCompletionError>>,
         >,
     > {
+        let request = match intercept_request(request, self, cx) {
+            Ok(request) => request,
+            Err(blocked) => return futures::future::ready(Err(blocked.into())).boxed(),
+        };
+        let response_interceptors = ResponseInterceptors::for_model(self, cx);
+        if let Some(cached) = cached_completion(&request, self, cx) {
+            return cached;
+        }
         let prompt_id = request.session_id.clone();
         let intent = request.intent;
         let mode = request.mode;
@@ -820,6 +838,7 @@ impl LanguageModel for CloudLanguageModel {
         let original_request = request.clone();
         let ids = _retrieve_ids(&original_request);
         let id = self.id.clone();
+        let max_tokens = self.max_token_count();
 
         match self.model.provider {
             zed_llm_client::LanguageModelProvider::Anthropic => {
@@ -844,7 +863,9 @@ impl LanguageModel for CloudLanguageModel {
                             .save_completion_req(
                                 &original_request,
                                 &ids,
-                                LanguageModelArgs::from_request(id.clone(), &original_request),
+                                LanguageModelArgs::from_request(id.clone(), &original_request)
+                                    .with_provider(ZED_CLOUD_PROVIDER_ID)
+                                    .with_max_tokens(max_tokens),
                             )
                             .await;
                     }
@@ -886,7 +907,7 @@ impl LanguageModel for CloudLanguageModel {
                     })?;
 
                     let mut mapper = AnthropicEventMapper::new();
-                    Ok(peek_db(
+                    Ok(response_interceptors.apply(peek_db(
                         map_cloud_completion_events(
                             Box::pin(
                                 response_lines(response, includes_status_messages)
@@ -897,8 +918,9 @@ impl LanguageModel for CloudLanguageModel {
                         ),
                         message_handler,
                         ids,
-                        LanguageModelArgs::from_request(id, &original_request),
-                    ))
+                        LanguageModelArgs::from_request(id, &original_request)
+                            .with_provider(ZED_CLOUD_PROVIDER_ID),
+                    )))
                 });
                 async move { Ok(future.await?.boxed()) }.boxed()
             }
@@ -918,7 +940,9 @@ impl LanguageModel for CloudLanguageModel {
                             .save_completion_req(
                                 &original_request,
                                 &ids,
-                                LanguageModelArgs::from_request(id.clone(), &original_request),
+                                LanguageModelArgs::from_request(id.clone(), &original_request)
+                                    .with_provider(ZED_CLOUD_PROVIDER_ID)
+                                    .with_max_tokens(max_tokens),
                             )
                             .await;
                     }
@@ -945,7 +969,7 @@ impl LanguageModel for CloudLanguageModel {
                     .await?;
 
                     let mut mapper = OpenAiEventMapper::new();
-                    Ok(peek_db(
+                    Ok(response_interceptors.apply(peek_db(
                         map_cloud_completion_events(
                             Box::pin(
                                 response_lines(response, includes_status_messages)
@@ -956,8 +980,9 @@ impl LanguageModel for CloudLanguageModel {
                         ),
                         message_handler,
                         ids,
-                        LanguageModelArgs::from_request(id, &original_request),
-                    ))
+                        LanguageModelArgs::from_request(id, &original_request)
+                            .with_provider(ZED_CLOUD_PROVIDER_ID),
+                    )))
                 });
                 async move { Ok(future.await?.boxed()) }.boxed()
             }
@@ -995,7 +1020,7 @@ impl LanguageModel for CloudLanguageModel {
                     .await?;
 
                     let mut mapper = GoogleEventMapper::new();
-                    Ok(peek_db(
+                    Ok(response_interceptors.apply(peek_db(
                         map_cloud_completion_events(
                             Box::pin(
                                 response_lines(response, includes_status_messages)
@@ -1006,8 +1031,9 @@ impl LanguageModel for CloudLanguageModel {
                         ),
                         message_handler,
                         ids,
-                        LanguageModelArgs::from_request(id, &original_request),
-                    ))
+                        LanguageModelArgs::from_request(id, &original_request)
+                            .with_provider(ZED_CLOUD_PROVIDER_ID),
+                    )))
                 });
                 async move { Ok(future.await?.boxed()) }.boxed()
             }
@@ -17,7 +17,10 @@ use gpui::{
     Action, Animation, AnimationExt, AnyView, App, AsyncApp, Entity, Render, Subscription, Task,
     Transformation, percentage, svg,
 };
-use language_model::message_handler::{AiMessageHandler, LanguageModelArgs, peek_db};
+use language_model::message_handler::{
+    AiMessageHandler, LanguageModelArgs, ResponseInterceptors, cached_completion,
+    intercept_request, peek_db,
+};
 use language_model::{
     _retrieve_ids, AuthenticateError, LanguageModel, LanguageModelCompletionError,
     LanguageModelCompletionEvent, LanguageModelId, LanguageModelName, LanguageModelProvider,
@@ -258,6 +261,14 @@ impl LanguageModel for CopilotChatLanguageModel {
             BoxStream<'static, Result<LanguageModelCompletionEvent, LanguageModelCompletionError>>,
         >,
     > {
+        let request = match intercept_request(request, self, cx) {
+            Ok(request) => request,
+            Err(blocked) => return futures::future::ready(Err(blocked.into())).boxed(),
+        };
+        let response_interceptors = ResponseInterceptors::for_model(self, cx);
+        if let Some(cached) = cached_completion(&request, self, cx) {
+            return cached;
+        }
         if let Some(message) = request.messages.last() {
             if message.contents_empty() {
                 const EMPTY_PROMPT_MSG: &str =
@@ -281,6 +292,7 @@ impl LanguageModel for CopilotChatLanguageModel {
         };
         let is_streaming = copilot_request.stream;
         let id = self.model.id().to_string();
+        let max_tokens = self.max_token_count();
 
         let request_limiter = self.request_limiter.clone();
         let future = cx.spawn(async move |cx| {
@@ -296,7 +308,9 @@ impl LanguageModel for CopilotChatLanguageModel {
                         LanguageModelArgs::from_request(
                             LanguageModelId::from(id.clone()),
                             &original_request,
-                        ),
+                        )
+                        .with_provider(PROVIDER_ID)
+                        .with_max_tokens(max_tokens),
                     )
                     .await;
             }
@@ -307,16 +321,18 @@ impl LanguageModel for CopilotChatLanguageModel {
                     let response = request.await?;
                     let mapped_stream =
                         map_to_language_model_completion_events(response, is_streaming);
-                    Ok(peek_db(
-                        mapped_stream,
-                        message_handler,
-                        ids,
-                        LanguageModelArgs::from_request(
-                            LanguageModelId::from(id),
-                            &original_request,
-                        ),
-                    )
-                    .boxed())
+                    Ok(response_interceptors
+                        .apply(peek_db(
+                            mapped_stream,
+                            message_handler,
+                            ids,
+                            LanguageModelArgs::from_request(
+                                LanguageModelId::from(id),
+                                &original_request,
+                            )
+                            .with_provider(PROVIDER_ID),
+                        ))
+                        .boxed())
                 })
                 .await
         });
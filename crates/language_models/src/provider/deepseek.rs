@@ -9,7 +9,10 @@ use gpui::{
     WhiteSpace,
 };
 use http_client::HttpClient;
-use language_model::message_handler::{AiMessageHandler, LanguageModelArgs, peek_db};
+use language_model::message_handler::{
+    AiMessageHandler, LanguageModelArgs, ResponseInterceptors, cached_completion,
+    intercept_request, peek_db, raw_exchange_client,
+};
 use language_model::{
     _retrieve_ids, AuthenticateError, LanguageModel, LanguageModelCompletionError,
     LanguageModelCompletionEvent, LanguageModelId, LanguageModelName, LanguageModelProvider,
@@ -256,9 +259,9 @@ impl DeepSeekLanguageModel {
     fn stream_completion(
         &self,
         request: deepseek::Request,
+        http_client: Arc<dyn HttpClient>,
         cx: &AsyncApp,
     ) -> BoxFuture<'static, Result<BoxStream<'static, Result<deepseek::StreamResponse>>>> {
-        let http_client = self.http_client.clone();
         let Ok((api_key, api_url)) = cx.read_entity(&self.state, |state, cx| {
             let settings = &AllLanguageModelSettings::get_global(cx).deepseek;
             (state.api_key.clone(), settings.api_url.clone())
@@ -355,33 +358,45 @@ impl LanguageModel for DeepSeekLanguageModel {
             BoxStream<'static, Result<LanguageModelCompletionEvent, LanguageModelCompletionError>>,
         >,
     > {
+        let request = match intercept_request(request, self, cx) {
+            Ok(request) => request,
+            Err(blocked) => return futures::future::ready(Err(blocked.into())).boxed(),
+        };
+        let response_interceptors = ResponseInterceptors::for_model(self, cx);
+        if let Some(cached) = cached_completion(&request, self, cx) {
+            return cached;
+        }
         let original_request = request.clone();
+        let ids = _retrieve_ids(&original_request);
+        let message_handler = cx.update(|cx| get_message_handler_async(cx)).ok().flatten();
         let request = into_deepseek(request, &self.model, self.max_output_tokens());
-        let stream = self.stream_completion(request, cx);
+        let http_client =
+            raw_exchange_client(message_handler.as_ref(), self.http_client.clone(), &ids);
+        let stream = self.stream_completion(request, http_client, cx);
 
-        let message_handler = cx.update(|cx| get_message_handler_async(cx)).ok().flatten();
         let id = self.id.clone();
+        let max_tokens = self.max_token_count();
         async move {
-            let ids = _retrieve_ids(&original_request);
-
             // Save request messages if handler is available
             if let Some(handler) = &message_handler {
                 handler
                     .save_completion_req(
                         &original_request,
                         &ids,
-                        LanguageModelArgs::from_request(id.clone(), &original_request),
+                        LanguageModelArgs::from_request(id.clone(), &original_request)
+                            .with_provider(PROVIDER_ID)
+                            .with_max_tokens(max_tokens),
                     )
                     .await;
             }
 
             let mapper = DeepSeekEventMapper::new();
-            Ok(peek_db(
+            Ok(response_interceptors.apply(peek_db(
                 mapper.map_stream(stream.await?).boxed(),
                 message_handler,
                 ids,
-                LanguageModelArgs::from_request(id, &original_request),
-            ))
+                LanguageModelArgs::from_request(id, &original_request).with_provider(PROVIDER_ID),
+            )))
         }
         .boxed()
     }
@@ -11,7 +11,10 @@ use gpui::{
     AnyView, App, AsyncApp, Context, Entity, FontStyle, Subscription, Task, TextStyle, WhiteSpace,
 };
 use http_client::HttpClient;
-use language_model::message_handler::{LanguageModelArgs, peek_db};
+use language_model::message_handler::{
+    LanguageModelArgs, ResponseInterceptors, cached_completion, intercept_request, peek_db,
+    raw_exchange_client,
+};
 use language_model::{
     _retrieve_ids, AuthenticateError, LanguageModelCompletionError, LanguageModelCompletionEvent,
     LanguageModelToolChoice, LanguageModelToolSchemaFormat, LanguageModelToolUse,
@@ -297,13 +300,12 @@ impl GoogleLanguageModel {
     fn stream_completion(
         &self,
         request: google_ai::GenerateContentRequest,
+        http_client: Arc<dyn HttpClient>,
         cx: &AsyncApp,
     ) -> BoxFuture<
         'static,
         Result<futures::stream::BoxStream<'static, Result<GenerateContentResponse>>>,
     > {
-        let http_client = self.http_client.clone();
-
         let Ok((api_key, api_url)) = cx.read_entity(&self.state, |state, cx| {
             let settings = &AllLanguageModelSettings::get_global(cx).google;
             (state.api_key.clone(), settings.api_url.clone())
@@ -409,24 +411,36 @@ impl LanguageModel for GoogleLanguageModel {
             BoxStream<'static, Result<LanguageModelCompletionEvent, LanguageModelCompletionError>>,
         >,
     > {
+        let request = match intercept_request(request, self, cx) {
+            Ok(request) => request,
+            Err(blocked) => return futures::future::ready(Err(blocked.into())).boxed(),
+        };
+        let response_interceptors = ResponseInterceptors::for_model(self, cx);
+        if let Some(cached) = cached_completion(&request, self, cx) {
+            return cached;
+        }
         // Get message handler for saving messages
         let message_handler = cx.update(|cx| get_message_handler_async(cx)).ok().flatten();
 
         // Save request messages if handler is available
         let prev_request = request.clone();
+        let ids = _retrieve_ids(&prev_request);
 
         let request = into_google(request, self.model.id().to_string(), self.model.mode());
-        let request = self.stream_completion(request, cx);
+        let http_client =
+            raw_exchange_client(message_handler.as_ref(), self.http_client.clone(), &ids);
+        let request = self.stream_completion(request, http_client, cx);
         let id = self.id.clone();
+        let max_tokens = self.max_token_count();
         let future = self.request_limiter.stream(async move {
-            let ids = _retrieve_ids(&prev_request);
-
             if let Some(handler) = &message_handler {
                 handler
                     .save_completion_req(
                         &prev_request,
                         &ids,
-                        LanguageModelArgs::from_request(id.clone(), &prev_request),
+                        LanguageModelArgs::from_request(id.clone(), &prev_request)
+                            .with_provider(PROVIDER_ID)
+                            .with_max_tokens(max_tokens),
                     )
                     .await;
             }
@@ -435,12 +449,12 @@ impl LanguageModel for GoogleLanguageModel {
                 .map_err(|err| LanguageModelCompletionError::Other(anyhow!(err)))?;
 
             let stream = GoogleEventMapper::new().map_stream(response);
-            let s = peek_db(
+            let s = response_interceptors.apply(peek_db(
                 stream,
                 message_handler,
                 ids,
-                LanguageModelArgs::from_request(id, &prev_request),
-            );
+                LanguageModelArgs::from_request(id, &prev_request).with_provider(PROVIDER_ID),
+            ));
             Ok(s)
         });
         async move { Ok(future.await?.boxed()) }.boxed()
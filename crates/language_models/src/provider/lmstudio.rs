@@ -11,7 +11,10 @@ use language_model::{
     StopReason,
 };
 
-use language_model::message_handler::{AiMessageHandler, LanguageModelArgs, peek_db};
+use language_model::message_handler::{
+    AiMessageHandler, LanguageModelArgs, ResponseInterceptors, cached_completion,
+    intercept_request, peek_db, raw_exchange_client,
+};
 use language_model::{
     LanguageModel, LanguageModelId, LanguageModelName, LanguageModelProvider,
     LanguageModelProviderId, LanguageModelProviderName, LanguageModelProviderState,
@@ -344,10 +347,10 @@ impl LmStudioLanguageModel {
     fn stream_completion(
         &self,
         request: ChatCompletionRequest,
+        http_client: Arc<dyn HttpClient>,
         cx: &AsyncApp,
     ) -> BoxFuture<'static, Result<futures::stream::BoxStream<'static, Result<ResponseStreamEvent>>>>
     {
-        let http_client = self.http_client.clone();
         let Ok(api_url) = cx.update(|cx| {
             let settings = &AllLanguageModelSettings::get_global(cx).lmstudio;
             settings.api_url.clone()
@@ -433,29 +436,42 @@ impl LanguageModel for LmStudioLanguageModel {
             BoxStream<'static, Result<LanguageModelCompletionEvent, LanguageModelCompletionError>>,
         >,
     > {
+        let request = match intercept_request(request, self, cx) {
+            Ok(request) => request,
+            Err(blocked) => return futures::future::ready(Err(blocked.into())).boxed(),
+        };
+        let response_interceptors = ResponseInterceptors::for_model(self, cx);
+        if let Some(cached) = cached_completion(&request, self, cx) {
+            return cached;
+        }
         let original_request = request.clone();
         let ids = _retrieve_ids(&original_request);
         let request = self.to_lmstudio_request(request);
-        let completions = self.stream_completion(request, cx);
         let message_handler = cx.update(|cx| get_message_handler_async(cx)).ok().flatten();
+        let http_client =
+            raw_exchange_client(message_handler.as_ref(), self.http_client.clone(), &ids);
+        let completions = self.stream_completion(request, http_client, cx);
         let id = self.id.clone();
+        let max_tokens = self.max_token_count();
         async move {
             if let Some(handler) = &message_handler {
                 handler
                     .save_completion_req(
                         &original_request,
                         &ids,
-                        LanguageModelArgs::from_request(id.clone(), &original_request),
+                        LanguageModelArgs::from_request(id.clone(), &original_request)
+                            .with_provider(PROVIDER_ID)
+                            .with_max_tokens(max_tokens),
                     )
                     .await;
             }
             let mapper = LmStudioEventMapper::new();
-            Ok(peek_db(
+            Ok(response_interceptors.apply(peek_db(
                 mapper.map_stream(completions.await?).boxed(),
                 message_handler,
                 ids,
-                LanguageModelArgs::from_request(id, &original_request),
-            ))
+                LanguageModelArgs::from_request(id, &original_request).with_provider(PROVIDER_ID),
+            )))
         }
         .boxed()
     }
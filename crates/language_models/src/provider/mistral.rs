@@ -8,7 +8,10 @@ use gpui::{
     AnyView, App, AsyncApp, Context, Entity, FontStyle, Subscription, Task, TextStyle, WhiteSpace,
 };
 use http_client::HttpClient;
-use language_model::message_handler::{AiMessageHandler, LanguageModelArgs, peek_db};
+use language_model::message_handler::{
+    AiMessageHandler, LanguageModelArgs, ResponseInterceptors, cached_completion,
+    intercept_request, peek_db, raw_exchange_client,
+};
 use language_model::{
     _retrieve_ids, AuthenticateError, LanguageModel, LanguageModelCompletionError,
     LanguageModelCompletionEvent, LanguageModelId, LanguageModelName, LanguageModelProvider,
@@ -265,12 +268,12 @@ impl MistralLanguageModel {
     fn stream_completion(
         &self,
         request: mistral::Request,
+        http_client: Arc<dyn HttpClient>,
         cx: &AsyncApp,
     ) -> BoxFuture<
         'static,
         Result<futures::stream::BoxStream<'static, Result<mistral::StreamResponse>>>,
     > {
-        let http_client = self.http_client.clone();
         let Ok((api_key, api_url)) = cx.read_entity(&self.state, |state, cx| {
             let settings = &AllLanguageModelSettings::get_global(cx).mistral;
             (state.api_key.clone(), settings.api_url.clone())
@@ -367,6 +370,14 @@ impl LanguageModel for MistralLanguageModel {
             BoxStream<'static, Result<LanguageModelCompletionEvent, LanguageModelCompletionError>>,
         >,
     > {
+        let request = match intercept_request(request, self, cx) {
+            Ok(request) => request,
+            Err(blocked) => return futures::future::ready(Err(blocked.into())).boxed(),
+        };
+        let response_interceptors = ResponseInterceptors::for_model(self, cx);
+        if let Some(cached) = cached_completion(&request, self, cx) {
+            return cached;
+        }
         // Get message handler for saving messages
         let message_handler = cx.update(|cx| get_message_handler_async(cx)).ok().flatten();
 
@@ -378,27 +389,32 @@ impl LanguageModel for MistralLanguageModel {
             self.model.id().to_string(),
             self.max_output_tokens(),
         );
-        let stream = self.stream_completion(request, cx);
+        let http_client =
+            raw_exchange_client(message_handler.as_ref(), self.http_client.clone(), &ids);
+        let stream = self.stream_completion(request, http_client, cx);
 
         let id = self.id.clone();
+        let max_tokens = self.max_token_count();
         async move {
             if let Some(handler) = &message_handler {
                 handler
                     .save_completion_req(
                         &prev_request,
                         &ids,
-                        LanguageModelArgs::from_request(id.clone(), &prev_request),
+                        LanguageModelArgs::from_request(id.clone(), &prev_request)
+                            .with_provider(PROVIDER_ID)
+                            .with_max_tokens(max_tokens),
                     )
                     .await;
             }
             let stream = stream.await?;
             let mapper = MistralEventMapper::new();
-            Ok(peek_db(
+            Ok(response_interceptors.apply(peek_db(
                 mapper.map_stream(stream).boxed(),
                 message_handler,
                 ids,
-                LanguageModelArgs::from_request(id, &prev_request),
-            ))
+                LanguageModelArgs::from_request(id, &prev_request).with_provider(PROVIDER_ID),
+            )))
         }
         .boxed()
     }
@@ -843,6 +859,16 @@ mod tests {
             tools: Vec::new(),
             tool_choice: None,
             stop: Vec::new(),
+            toolchain: None,
+            project: None,
+            prompt_template: None,
+            origin: None,
+            buffer: None,
+            git_branch: None,
+            schedule_id: None,
+            fan_out_group: None,
+            message_id: None,
+            source: None,
         };
 
         let model_name = "mistral-medium-latest".to_string();
@@ -3,7 +3,10 @@ use futures::{FutureExt, StreamExt, future::BoxFuture, stream::BoxStream};
 use futures::{Stream, TryFutureExt, stream};
 use gpui::{AnyView, App, AsyncApp, Context, Subscription, Task};
 use http_client::HttpClient;
-use language_model::message_handler::{AiMessageHandler, LanguageModelArgs, peek_db};
+use language_model::message_handler::{
+    AiMessageHandler, LanguageModelArgs, ResponseInterceptors, cached_completion,
+    intercept_request, peek_db, raw_exchange_client,
+};
 use language_model::{
     _retrieve_ids, AuthenticateError, LanguageModelCompletionError, LanguageModelCompletionEvent,
     LanguageModelRequestTool, LanguageModelToolChoice, LanguageModelToolUse,
@@ -423,6 +426,14 @@ impl LanguageModel for OllamaLanguageModel {
             BoxStream<'static, Result<LanguageModelCompletionEvent, LanguageModelCompletionError>>,
         >,
     > {
+        let request = match intercept_request(request, self, cx) {
+            Ok(request) => request,
+            Err(blocked) => return futures::future::ready(Err(blocked.into())).boxed(),
+        };
+        let response_interceptors = ResponseInterceptors::for_model(self, cx);
+        if let Some(cached) = cached_completion(&request, self, cx) {
+            return cached;
+        }
         // Get message handler for saving messages
 
         let request_copy = request.clone();
@@ -430,7 +441,9 @@ impl LanguageModel for OllamaLanguageModel {
 
         let request = self.to_ollama_request(request);
 
-        let http_client = self.http_client.clone();
+        let message_handler = cx.update(|cx| get_message_handler_async(cx)).ok().flatten();
+        let http_client =
+            raw_exchange_client(message_handler.as_ref(), self.http_client.clone(), &ids);
         let Ok(api_url) = cx.update(|cx| {
             let settings = &AllLanguageModelSettings::get_global(cx).ollama;
             settings.api_url.clone()
@@ -438,9 +451,8 @@ impl LanguageModel for OllamaLanguageModel {
             return futures::future::ready(Err(anyhow!("App state dropped"))).boxed();
         };
 
-        let message_handler = cx.update(|cx| get_message_handler_async(cx)).ok().flatten();
-
         let id = self.id.clone();
+        let max_tokens = self.max_token_count();
         let future = self.request_limiter.stream(async move {
             // Save request messages if handler is available
             if let Some(handler) = &message_handler {
@@ -448,7 +460,9 @@ impl LanguageModel for OllamaLanguageModel {
                     .save_completion_req(
                         &request_copy,
                         &ids,
-                        LanguageModelArgs::from_request(id.clone(), &request_copy),
+                        LanguageModelArgs::from_request(id.clone(), &request_copy)
+                            .with_provider(PROVIDER_ID)
+                            .with_max_tokens(max_tokens),
                     )
                     .await;
             }
@@ -456,13 +470,14 @@ impl LanguageModel for OllamaLanguageModel {
             let stream = stream_chat_completion(http_client.as_ref(), &api_url, request).await?;
             let stream = map_to_language_model_completion_events(stream);
 
-            Ok(peek_db(
-                stream,
-                message_handler,
-                ids,
-                LanguageModelArgs::from_request(id, &request_copy),
-            )
-            .boxed())
+            Ok(response_interceptors
+                .apply(peek_db(
+                    stream,
+                    message_handler,
+                    ids,
+                    LanguageModelArgs::from_request(id, &request_copy).with_provider(PROVIDER_ID),
+                ))
+                .boxed())
         });
 
         future.map_ok(|f| f.boxed()).boxed()
@@ -9,14 +9,17 @@ use gpui::{
     AnyView, App, AsyncApp, Context, Entity, FontStyle, Subscription, Task, TextStyle, WhiteSpace,
 };
 use http_client::HttpClient;
-use language_model::message_handler::{AiMessageHandler, LanguageModelArgs, peek_db};
+use language_model::message_handler::{
+    AiMessageHandler, LanguageModelArgs, ResponseInterceptors, cached_completion,
+    intercept_request, peek_db, raw_exchange_client,
+};
 use language_model::{
     _retrieve_ids, AuthenticateError, LanguageModel, LanguageModelCompletionError,
     LanguageModelCompletionEvent, LanguageModelId, LanguageModelName, LanguageModelProvider,
     LanguageModelProviderId, LanguageModelProviderName, LanguageModelProviderState,
     LanguageModelRequest, LanguageModelToolChoice, LanguageModelToolResultContent,
     LanguageModelToolSchemaFormat, LanguageModelToolUse, MessageContent, RateLimiter, RequestIds,
-    Role, StopReason, TokenUsage, get_message_handler_async,
+    Role, StopReason, TokenUsage, Tokenizer, get_message_handler_async,
 };
 use open_ai::{ImageUrl, Model, ResponseStreamEvent, stream_completion};
 use schemars::JsonSchema;
@@ -24,9 +27,10 @@ use serde::{Deserialize, Serialize};
 use settings::{Settings, SettingsStore};
 use std::pin::Pin;
 use std::str::FromStr as _;
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
 use strum::IntoEnumIterator;
 use theme::ThemeSettings;
+use tiktoken_rs::CoreBPE;
 use ui::{Icon, IconName, List, Tooltip, prelude::*};
 use util::ResultExt;
 use uuid::uuid;
@@ -255,10 +259,10 @@ impl OpenAiLanguageModel {
     fn stream_completion(
         &self,
         request: open_ai::Request,
+        http_client: Arc<dyn HttpClient>,
         cx: &AsyncApp,
     ) -> BoxFuture<'static, Result<futures::stream::BoxStream<'static, Result<ResponseStreamEvent>>>>
     {
-        let http_client = self.http_client.clone();
         let Ok((api_key, api_url)) = cx.read_entity(&self.state, |state, cx| {
             let settings = &AllLanguageModelSettings::get_global(cx).openai;
             (state.api_key.clone(), settings.api_url.clone())
@@ -343,6 +347,14 @@ impl LanguageModel for OpenAiLanguageModel {
             >,
         >,
     > {
+        let request = match intercept_request(request, self, cx) {
+            Ok(request) => request,
+            Err(blocked) => return futures::future::ready(Err(blocked.into())).boxed(),
+        };
+        let response_interceptors = ResponseInterceptors::for_model(self, cx);
+        if let Some(cached) = cached_completion(&request, self, cx) {
+            return cached;
+        }
         let original_request = request.clone();
         let ids = _retrieve_ids(&original_request);
 
@@ -353,14 +365,19 @@ impl LanguageModel for OpenAiLanguageModel {
 
         let request = into_open_ai(request, &self.model, self.max_output_tokens());
         let id = self.id.clone();
-        let completions = self.stream_completion(request, cx);
+        let max_tokens = self.max_token_count();
+        let http_client =
+            raw_exchange_client(message_handler.as_ref(), self.http_client.clone(), &ids);
+        let completions = self.stream_completion(request, http_client, cx);
         async move {
             if let Some(handler) = &message_handler {
                 handler
                     .save_completion_req(
                         &original_request,
                         &ids,
-                        LanguageModelArgs::from_request(id.clone(), &original_request),
+                        LanguageModelArgs::from_request(id.clone(), &original_request)
+                            .with_provider(PROVIDER_ID)
+                            .with_max_tokens(max_tokens),
                     )
                     .await;
             }
@@ -368,13 +385,15 @@ impl LanguageModel for OpenAiLanguageModel {
             let mapper = OpenAiEventMapper::new();
             let stream = mapper.map_stream(completions.await?);
 
-            Ok(peek_db(
-                stream,
-                message_handler,
-                ids,
-                LanguageModelArgs::from_request(id, &original_request),
-            )
-            .boxed())
+            Ok(response_interceptors
+                .apply(peek_db(
+                    stream,
+                    message_handler,
+                    ids,
+                    LanguageModelArgs::from_request(id, &original_request)
+                        .with_provider(PROVIDER_ID),
+                ))
+                .boxed())
         }
         .boxed()
     }
@@ -686,6 +705,45 @@ pub fn count_open_ai_tokens(
     .boxed()
 }
 
+/// Counts tokens for the models tiktoken knows the encoding of, i.e. OpenAI's.
+pub struct TiktokenTokenizer;
+
+impl Tokenizer for TiktokenTokenizer {
+    fn name(&self) -> &'static str {
+        "tiktoken"
+    }
+
+    fn count_tokens(&self, model_id: &LanguageModelId, text: &str) -> Option<usize> {
+        let encoding = tiktoken_rs::tokenizer::get_tokenizer(&model_id.0)?;
+        let bpe = tiktoken_bpe(encoding)?;
+        Some(bpe.encode_with_special_tokens(text).len())
+    }
+}
+
+/// The encoding's BPE, loaded the first time a model using it is counted. Loading one parses its
+/// whole vocabulary.
+fn tiktoken_bpe(encoding: tiktoken_rs::tokenizer::Tokenizer) -> Option<&'static CoreBPE> {
+    use tiktoken_rs::tokenizer::Tokenizer as Encoding;
+
+    static O200K_BASE: OnceLock<Option<CoreBPE>> = OnceLock::new();
+    static CL100K_BASE: OnceLock<Option<CoreBPE>> = OnceLock::new();
+    static P50K_BASE: OnceLock<Option<CoreBPE>> = OnceLock::new();
+    static P50K_EDIT: OnceLock<Option<CoreBPE>> = OnceLock::new();
+    static R50K_BASE: OnceLock<Option<CoreBPE>> = OnceLock::new();
+    static GPT2: OnceLock<Option<CoreBPE>> = OnceLock::new();
+
+    let bpe = match encoding {
+        Encoding::O200kBase => &O200K_BASE,
+        Encoding::Cl100kBase => &CL100K_BASE,
+        Encoding::P50kBase => &P50K_BASE,
+        Encoding::P50kEdit => &P50K_EDIT,
+        Encoding::R50kBase => &R50K_BASE,
+        Encoding::Gpt2 => &GPT2,
+    };
+    bpe.get_or_init(|| tiktoken_rs::get_bpe_from_tokenizer(encoding).log_err())
+        .as_ref()
+}
+
 struct ConfigurationView {
     api_key_editor: Entity<Editor>,
     state: gpui::Entity<State>,
@@ -901,6 +959,16 @@ mod tests {
             tool_choice: None,
             stop: vec![],
             temperature: None,
+            toolchain: None,
+            project: None,
+            prompt_template: None,
+            origin: None,
+            buffer: None,
+            git_branch: None,
+            schedule_id: None,
+            fan_out_group: None,
+            message_id: None,
+            source: None,
         };
 
         // Validate that all models are supported by tiktoken-rs
@@ -7,7 +7,10 @@ use gpui::{
     AnyView, App, AsyncApp, Context, Entity, FontStyle, Subscription, Task, TextStyle, WhiteSpace,
 };
 use http_client::HttpClient;
-use language_model::message_handler::{AiMessageHandler, LanguageModelArgs, peek_db};
+use language_model::message_handler::{
+    AiMessageHandler, LanguageModelArgs, ResponseInterceptors, cached_completion,
+    intercept_request, peek_db, raw_exchange_client,
+};
 use language_model::{
     _retrieve_ids, AuthenticateError, LanguageModel, LanguageModelCompletionError,
     LanguageModelCompletionEvent, LanguageModelId, LanguageModelName, LanguageModelProvider,
@@ -281,10 +284,10 @@ impl OpenRouterLanguageModel {
     fn stream_completion(
         &self,
         request: open_router::Request,
+        http_client: Arc<dyn HttpClient>,
         cx: &AsyncApp,
     ) -> BoxFuture<'static, Result<futures::stream::BoxStream<'static, Result<ResponseStreamEvent>>>>
     {
-        let http_client = self.http_client.clone();
         let Ok((api_key, api_url)) = cx.read_entity(&self.state, |state, cx| {
             let settings = &AllLanguageModelSettings::get_global(cx).open_router;
             (state.api_key.clone(), settings.api_url.clone())
@@ -372,6 +375,14 @@ impl LanguageModel for OpenRouterLanguageModel {
             >,
         >,
     > {
+        let request = match intercept_request(request, self, cx) {
+            Ok(request) => request,
+            Err(blocked) => return futures::future::ready(Err(blocked.into())).boxed(),
+        };
+        let response_interceptors = ResponseInterceptors::for_model(self, cx);
+        if let Some(cached) = cached_completion(&request, self, cx) {
+            return cached;
+        }
         let original_request = request.clone();
         let ids = _retrieve_ids(&original_request);
 
@@ -379,8 +390,11 @@ impl LanguageModel for OpenRouterLanguageModel {
         let message_handler = cx.update(|cx| get_message_handler_async(cx)).ok().flatten();
 
         let request = into_open_router(request, &self.model, self.max_output_tokens());
-        let completions = self.stream_completion(request, cx);
+        let http_client =
+            raw_exchange_client(message_handler.as_ref(), self.http_client.clone(), &ids);
+        let completions = self.stream_completion(request, http_client, cx);
         let id = self.id.clone();
+        let max_tokens = self.max_token_count();
         async move {
             // Save request messages if handler is available
             if let Some(handler) = &message_handler {
@@ -388,7 +402,9 @@ impl LanguageModel for OpenRouterLanguageModel {
                     .save_completion_req(
                         &original_request,
                         &ids,
-                        LanguageModelArgs::from_request(id.clone(), &original_request),
+                        LanguageModelArgs::from_request(id.clone(), &original_request)
+                            .with_provider(PROVIDER_ID)
+                            .with_max_tokens(max_tokens),
                     )
                     .await;
             }
@@ -396,13 +412,15 @@ impl LanguageModel for OpenRouterLanguageModel {
             let mapper = OpenRouterEventMapper::new();
             let stream = mapper.map_stream(completions.await?);
 
-            Ok(peek_db(
-                stream,
-                message_handler,
-                ids,
-                LanguageModelArgs::from_request(id, &original_request),
-            )
-            .boxed())
+            Ok(response_interceptors
+                .apply(peek_db(
+                    stream,
+                    message_handler,
+                    ids,
+                    LanguageModelArgs::from_request(id, &original_request)
+                        .with_provider(PROVIDER_ID),
+                ))
+                .boxed())
         }
         .boxed()
     }
@@ -0,0 +1,232 @@
+//! Replays stored threads against a model as regression tests of the prompts that made them,
+//! checking what the model answers against each test's assertions. Each replay is stored as a
+//! new thread, and its outcome as a run result tagged with the test's id.
+
+use anyhow::{Result, anyhow};
+use chrono::Utc;
+use futures::StreamExt;
+use gpui::{App, AsyncApp};
+use language_model::message_handler::{
+    AiMessageHandler, RunResult, RunStatus, create_conversation_id, get_message_handler,
+    record_run_result,
+};
+use language_model::{LanguageModelCompletionEvent, LanguageModelRequest};
+use regex::Regex;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use settings::Settings as _;
+use std::sync::Arc;
+use zed_llm_client::CompletionIntent;
+
+use crate::AllLanguageModelSettings;
+use crate::scheduled_runs::configured_model;
+
+/// A stored thread whose latest request is replayed, and what the model must answer to it.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct RegressionTest {
+    /// Tags the run results of the test's replays.
+    pub id: String,
+    /// The stored thread to replay.
+    pub thread_id: String,
+    /// Checked against the text of the model's answer. A replay passes if all of them hold.
+    pub assertions: Vec<OutputAssertion>,
+}
+
+/// What the text of a replayed answer must hold.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum OutputAssertion {
+    /// The answer contains the text.
+    Contains { text: String },
+    /// The answer matches the regular expression.
+    Regex { pattern: String },
+    /// The answer, or the first fenced code block in it, is JSON with a value at the path, e.g.
+    /// `$.findings[0].severity`, equal to `equals` if it is given.
+    JsonPath {
+        path: String,
+        #[serde(default)]
+        equals: Option<serde_json::Value>,
+    },
+}
+
+impl OutputAssertion {
+    /// Why the output fails the assertion, if it does.
+    pub fn check(&self, output: &str) -> Result<(), String> {
+        match self {
+            OutputAssertion::Contains { text } => {
+                if output.contains(text.as_str()) {
+                    Ok(())
+                } else {
+                    Err(format!("the output doesn't contain {text:?}"))
+                }
+            }
+            OutputAssertion::Regex { pattern } => {
+                let regex = Regex::new(pattern)
+                    .map_err(|error| format!("invalid pattern {pattern:?}: {error}"))?;
+                if regex.is_match(output) {
+                    Ok(())
+                } else {
+                    Err(format!("the output doesn't match {pattern:?}"))
+                }
+            }
+            OutputAssertion::JsonPath { path, equals } => {
+                let json = output_json(output).ok_or("the output isn't JSON")?;
+                let value = json_path(&json, path)
+                    .ok_or_else(|| format!("the output has no value at {path}"))?;
+                match equals {
+                    Some(expected) if value != expected => {
+                        Err(format!("{path} is {value}, not {expected}"))
+                    }
+                    _ => Ok(()),
+                }
+            }
+        }
+    }
+}
+
+/// The output parsed as JSON, or else the first fenced code block in it.
+fn output_json(output: &str) -> Option<serde_json::Value> {
+    if let Ok(json) = serde_json::from_str(output.trim()) {
+        return Some(json);
+    }
+    let (_, fenced) = output.split_once("```")?;
+    let (_, code) = fenced.split_once('\n')?;
+    let (code, _) = code.split_once("```")?;
+    serde_json::from_str(code.trim()).ok()
+}
+
+/// The value at a path of `.key` and `[index]` steps from the root, `$`.
+fn json_path<'a>(json: &'a serde_json::Value, path: &str) -> Option<&'a serde_json::Value> {
+    let mut value = json;
+    let mut rest = path.strip_prefix('$').unwrap_or(path);
+    while !rest.is_empty() {
+        if let Some(after_dot) = rest.strip_prefix('.') {
+            let end = after_dot.find(['.', '[']).unwrap_or(after_dot.len());
+            value = value.get(&after_dot[..end])?;
+            rest = &after_dot[end..];
+        } else if let Some(after_bracket) = rest.strip_prefix('[') {
+            let (index, after) = after_bracket.split_once(']')?;
+            value = value.get(index.trim().parse::<usize>().ok()?)?;
+            rest = after;
+        } else {
+            return None;
+        }
+    }
+    Some(value)
+}
+
+/// Replays every configured regression test against the model given as `provider_id/model_id`,
+/// or the agent's default model, recording each outcome as a run result.
+pub(crate) fn run_regression_tests(model: Option<String>, cx: &mut App) {
+    let tests = AllLanguageModelSettings::get_global(cx)
+        .regression_tests
+        .clone();
+    let Some(handler) = get_message_handler(cx) else {
+        log::error!("Regression tests replay stored threads, but messages aren't being stored");
+        return;
+    };
+    for test in tests {
+        let handler = handler.clone();
+        let model = model.clone();
+        cx.spawn(async move |cx| execute(&test, model.as_deref(), &handler, cx).await)
+            .detach();
+    }
+}
+
+async fn execute(
+    test: &RegressionTest,
+    model: Option<&str>,
+    handler: &Arc<AiMessageHandler>,
+    cx: &mut AsyncApp,
+) {
+    let thread_id = create_conversation_id();
+    let (status, error) = match replay(test, model, handler, &thread_id, cx).await {
+        Ok(failures) if failures.is_empty() => (RunStatus::Success, None),
+        Ok(failures) => (RunStatus::Failed, Some(failures.join("; "))),
+        Err(error) => {
+            log::error!("Regression test {} failed to run: {error:#}", test.id);
+            (RunStatus::Error, Some(format!("{error:#}")))
+        }
+    };
+    let result = RunResult {
+        thread_id,
+        schedule_id: test.id.clone(),
+        status,
+        error,
+        finished_at: Utc::now(),
+    };
+    cx.update(|cx| record_run_result(result, cx)).ok();
+}
+
+/// Sends the stored thread's latest request again in a new thread, returning the assertions its
+/// answer fails.
+async fn replay(
+    test: &RegressionTest,
+    model: Option<&str>,
+    handler: &Arc<AiMessageHandler>,
+    thread_id: &str,
+    cx: &mut AsyncApp,
+) -> Result<Vec<String>> {
+    let messages = handler.replay_request_messages(&test.thread_id).await?;
+    if messages.is_empty() {
+        return Err(anyhow!("thread {} has no stored request", test.thread_id));
+    }
+    // Offered as the stored request declared them, even if the tools changed since.
+    let tools = handler.replay_request_tools(&test.thread_id).await?;
+    let model = cx.update(|cx| configured_model(model, cx))??;
+    let request = LanguageModelRequest {
+        thread_id: Some(thread_id.to_string()),
+        intent: Some(CompletionIntent::UserPrompt),
+        messages,
+        tools,
+        schedule_id: Some(test.id.clone()),
+        ..Default::default()
+    };
+    let mut events = model.model.stream_completion(request, cx).await?;
+    let mut output = String::new();
+    while let Some(event) = events.next().await {
+        if let LanguageModelCompletionEvent::Text(text) = event? {
+            output.push_str(&text);
+        }
+    }
+    Ok(test
+        .assertions
+        .iter()
+        .filter_map(|assertion| assertion.check(&output).err())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_output_assertions() {
+        let output = "Found one issue:\n```json\n{\"findings\": [{\"severity\": \"high\"}]}\n```";
+        let contains = OutputAssertion::Contains {
+            text: "one issue".into(),
+        };
+        let regex = OutputAssertion::Regex {
+            pattern: r"^Found \d+ issues?".into(),
+        };
+        let severity = |equals: &str| OutputAssertion::JsonPath {
+            path: "$.findings[0].severity".into(),
+            equals: Some(equals.into()),
+        };
+        assert_eq!(contains.check(output), Ok(()));
+        assert_eq!(regex.check(output), Ok(()));
+        assert_eq!(severity("high").check(output), Ok(()));
+        assert_eq!(
+            severity("low").check(output),
+            Err("$.findings[0].severity is \"high\", not \"low\"".to_string())
+        );
+        assert!(
+            OutputAssertion::JsonPath {
+                path: "$.findings[1]".into(),
+                equals: None,
+            }
+            .check(output)
+            .is_err()
+        );
+    }
+}
@@ -0,0 +1,246 @@
+//! Runs saved prompts headlessly, on a schedule or after each commit in an open project, storing
+//! each run as a new thread tagged with the id of the run.
+
+use anyhow::{Context as _, Result, anyhow};
+use chrono::Utc;
+use collections::HashMap;
+use futures::StreamExt;
+use gpui::{App, AppContext as _, AsyncApp, Context, Global, SharedString, Task, Window};
+use language_model::message_handler::{
+    RunResult, RunStatus, Schedule, create_conversation_id, record_run_result,
+};
+use language_model::{
+    ConfiguredModel, LanguageModelCompletionEvent, LanguageModelRegistry, LanguageModelRequest,
+    LanguageModelRequestMessage, MessageContent, Role, SelectedModel, StopReason,
+};
+use project::Project;
+use project::git_store::{GitStoreEvent, Repository, RepositoryId};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use settings::{Settings as _, SettingsStore};
+use zed_llm_client::CompletionIntent;
+
+use crate::AllLanguageModelSettings;
+
+/// A prompt to run without a user, whose result is stored as a new thread.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct ScheduledRun {
+    /// Tags the threads the run stores.
+    pub id: String,
+    /// Sent as the first, and only, user message of each thread.
+    pub prompt: String,
+    /// The model to run the prompt with, as `provider_id/model_id`.
+    ///
+    /// Default: the agent's default model
+    #[serde(default)]
+    pub model: Option<String>,
+    /// When to run the prompt, as a cron expression in UTC (e.g. "0 9 * * 1-5").
+    #[serde(default)]
+    pub schedule: Option<String>,
+    /// Whether to run the prompt after each commit to the branch checked out in an open project.
+    #[serde(default)]
+    pub on_commit: bool,
+}
+
+struct ScheduledRuns {
+    runs: Vec<ScheduledRun>,
+    _timers: Vec<Task<()>>,
+}
+
+impl Global for ScheduledRuns {}
+
+/// Where a repository's head was when it was last updated.
+#[derive(Clone, Debug, PartialEq)]
+struct Head {
+    branch: Option<SharedString>,
+    sha: SharedString,
+    commit_timestamp: i64,
+}
+
+impl Head {
+    fn of(repository: &Repository) -> Option<Self> {
+        let commit = repository.head_commit.as_ref()?;
+        Some(Self {
+            branch: repository
+                .branch
+                .as_ref()
+                .map(|branch| branch.ref_name.clone()),
+            sha: commit.sha.clone(),
+            commit_timestamp: commit.commit_timestamp,
+        })
+    }
+
+    /// Whether the head moved from `previous` to a later commit on the same branch, as it does
+    /// when a commit is made or pulled, and not when another branch is checked out or the
+    /// branch is reset.
+    fn follows(&self, previous: &Head) -> bool {
+        self.branch == previous.branch
+            && self.sha != previous.sha
+            && self.commit_timestamp >= previous.commit_timestamp
+    }
+}
+
+pub(crate) fn observe_scheduled_runs(cx: &mut App) {
+    let update = |cx: &mut App| {
+        let runs = AllLanguageModelSettings::get_global(cx)
+            .scheduled_runs
+            .clone();
+        if cx
+            .try_global::<ScheduledRuns>()
+            .is_some_and(|scheduled| scheduled.runs == runs)
+        {
+            return;
+        }
+        let timers = runs
+            .iter()
+            .filter_map(|run| {
+                let schedule = run.schedule.as_ref()?;
+                match schedule.parse::<Schedule>() {
+                    Ok(schedule) => Some(run_on_schedule(run.clone(), schedule, cx)),
+                    Err(error) => {
+                        log::error!("Invalid schedule for scheduled run {}: {error:#}", run.id);
+                        None
+                    }
+                }
+            })
+            .collect();
+        cx.set_global(ScheduledRuns {
+            runs,
+            _timers: timers,
+        });
+    };
+    update(cx);
+    cx.observe_global::<SettingsStore>(update).detach();
+    cx.observe_new(watch_commits).detach();
+}
+
+fn run_on_schedule(run: ScheduledRun, schedule: Schedule, cx: &mut App) -> Task<()> {
+    cx.spawn(async move |cx| {
+        while let Some(next) = schedule.next_after(Utc::now()) {
+            let wait = (next - Utc::now()).to_std().unwrap_or_default();
+            cx.background_executor().timer(wait).await;
+            execute(&run, cx).await;
+        }
+    })
+}
+
+fn watch_commits(project: &mut Project, _: Option<&mut Window>, cx: &mut Context<Project>) {
+    if !project.is_local() {
+        return;
+    }
+    let mut heads = HashMap::<RepositoryId, Head>::default();
+    cx.subscribe(project.git_store(), move |_, git_store, event, cx| {
+        let GitStoreEvent::RepositoryUpdated(id, _, _) = event else {
+            return;
+        };
+        let Some(repository) = git_store.read(cx).repositories().get(id) else {
+            return;
+        };
+        let Some(head) = Head::of(repository.read(cx)) else {
+            return;
+        };
+        if heads
+            .insert(*id, head.clone())
+            .is_some_and(|previous| head.follows(&previous))
+        {
+            run_on_commit(cx);
+        }
+    })
+    .detach();
+}
+
+fn run_on_commit(cx: &mut App) {
+    let Some(scheduled) = cx.try_global::<ScheduledRuns>() else {
+        return;
+    };
+    for run in scheduled.runs.iter().filter(|run| run.on_commit).cloned() {
+        cx.spawn(async move |cx| {
+            execute(&run, cx).await;
+        })
+        .detach();
+    }
+}
+
+/// Runs the prompt, and records how the run ended for the inbox and notifications.
+async fn execute(run: &ScheduledRun, cx: &mut AsyncApp) {
+    let thread_id = create_conversation_id();
+    let (status, error) = match start_run(run, &thread_id, cx).await {
+        Ok(status) => (status, None),
+        Err(error) => {
+            log::error!("Scheduled run {} failed: {error:#}", run.id);
+            (RunStatus::Error, Some(format!("{error:#}")))
+        }
+    };
+    let result = RunResult {
+        thread_id,
+        schedule_id: run.id.clone(),
+        status,
+        error,
+        finished_at: Utc::now(),
+    };
+    cx.update(|cx| record_run_result(result, cx)).ok();
+}
+
+/// The model given as `provider_id/model_id`, or the agent's default model.
+pub(crate) fn configured_model(model: Option<&str>, cx: &mut App) -> Result<ConfiguredModel> {
+    let registry = LanguageModelRegistry::global(cx);
+    match model {
+        Some(model) => {
+            let selected = model
+                .parse::<SelectedModel>()
+                .map_err(|error| anyhow!(error))?;
+            registry
+                .update(cx, |registry, cx| registry.select_model(&selected, cx))
+                .with_context(|| format!("model {model} isn't available"))
+        }
+        None => registry
+            .read(cx)
+            .default_model()
+            .context("no default model configured"),
+    }
+}
+
+/// Runs the prompt in a new thread, which the model's provider stores as it streams.
+async fn start_run(run: &ScheduledRun, thread_id: &str, cx: &mut AsyncApp) -> Result<RunStatus> {
+    let model = cx.update(|cx| configured_model(run.model.as_deref(), cx))??;
+    let request = LanguageModelRequest {
+        thread_id: Some(thread_id.to_string()),
+        intent: Some(CompletionIntent::UserPrompt),
+        messages: vec![LanguageModelRequestMessage {
+            role: Role::User,
+            content: vec![MessageContent::Text(run.prompt.clone())],
+            cache: false,
+        }],
+        schedule_id: Some(run.id.clone()),
+        ..Default::default()
+    };
+
+    let mut events = model.model.stream_completion(request, cx).await?;
+    let mut status = RunStatus::Success;
+    while let Some(event) = events.next().await {
+        if let LanguageModelCompletionEvent::Stop(StopReason::MaxTokens) = event? {
+            status = RunStatus::Truncated;
+        }
+    }
+    Ok(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_head_follows_commits_on_its_branch() {
+        let head = |branch: &str, sha: &str, commit_timestamp: i64| Head {
+            branch: Some(branch.to_string().into()),
+            sha: sha.to_string().into(),
+            commit_timestamp,
+        };
+        let main = head("refs/heads/main", "a1", 100);
+
+        assert!(head("refs/heads/main", "b2", 200).follows(&main));
+        assert!(!main.follows(&main));
+        assert!(!head("refs/heads/main", "z0", 50).follows(&main));
+        assert!(!head("refs/heads/feature", "c3", 300).follows(&main));
+    }
+}
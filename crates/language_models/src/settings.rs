@@ -1,8 +1,17 @@
 use std::sync::Arc;
 
 use anyhow::Result;
+use client::User;
+use collections::HashMap;
 use gpui::App;
 use language_model::LanguageModelCacheConfiguration;
+use language_model::message_handler::{
+    AgentIdentity, BlobEncoding, ChaosConfig, CheckpointPartitioning, CollaborationPersistence,
+    FixturePolicy, GITHUB_API_URL, GitHubIssuesConfig, GuardrailRule, IssueTrackerConfig,
+    JiraConfig, LANGSMITH_API_URL, LangSmithConfig, LintPolicy, MaintenancePolicy, MessageAuthor,
+    MessageRule, NotificationPolicy, PersistenceFeatures, PersistenceFeaturesContent,
+    PromptExperiment, ResponseCachePolicy, SamplingPolicy, SecretScanPolicy, UsageExportConfig,
+};
 use project::Fs;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
@@ -22,6 +31,7 @@ use crate::provider::{
     open_ai::OpenAiSettings,
     open_router::OpenRouterSettings,
 };
+use crate::{RegressionTest, ScheduledRun};
 
 /// Initializes the language model settings.
 pub fn init(fs: Arc<dyn Fs>, cx: &mut App) {
@@ -69,6 +79,149 @@ pub struct AllLanguageModelSettings {
     pub lmstudio: LmStudioSettings,
     pub deepseek: DeepSeekSettings,
     pub mistral: MistralSettings,
+    pub langsmith: LangSmithSettings,
+    pub issue_trackers: IssueTrackerSettings,
+    pub author: AuthorSettings,
+    pub prompt_experiments: Vec<PromptExperiment>,
+    /// Cron expressions overriding when background jobs run, by job name; "off" disables a job.
+    pub scheduled_jobs: HashMap<String, String>,
+    /// Whether conversations are stored at all. Off unless explicitly turned on.
+    pub enable_storage: bool,
+    /// The conversation store to connect to. Without one, nor one in the environment,
+    /// conversations are only cached locally.
+    pub postgres_connection_string: Option<String>,
+    /// How the conversation store encodes new checkpoints. Read when the store connects.
+    pub blob_encoding: BlobEncoding,
+    /// Whether the conversation store is only browsed, writing nothing to it. Read when the store
+    /// connects.
+    pub read_only_store: bool,
+    /// Tokens of stored history past which a thread is summarized and compacted.
+    pub context_compaction_threshold: Option<u64>,
+    /// Which participants of shared projects store their completions.
+    pub collaboration_persistence: CollaborationPersistence,
+    /// Whether messages are only encoded and counted, and not stored.
+    pub shadow_persistence: bool,
+    /// Which threads are stored, if not every thread.
+    pub sampling: Option<SamplingPolicy>,
+    /// Rules that drop, redact or route messages before they are stored.
+    pub message_rules: Vec<MessageRule>,
+    /// Whether the raw HTTP exchanges with providers are stored next to their messages.
+    pub capture_raw_exchanges: bool,
+    /// Which parts of completions are stored.
+    pub persistence_features: PersistenceFeatures,
+    /// Patterns that block, or warn about, requests before they are sent.
+    pub guardrails: Vec<GuardrailRule>,
+    /// How the files, diagnostics and other worktree context in requests are scanned for secrets
+    /// before they are sent. Not scanned when unset.
+    pub secret_scan: Option<SecretScanPolicy>,
+    /// For each provider, the names of the response interceptors its completions skip.
+    pub disabled_response_interceptors: HashMap<String, Vec<String>>,
+    /// Which requests are answered with the response to an identical recent request.
+    pub response_cache: Option<ResponseCachePolicy>,
+    /// Whether completions are recorded as fixtures, or replayed from them instead of being sent.
+    pub completion_fixtures: Option<FixturePolicy>,
+    /// Faults injected into the message store and the providers, to test how failures are handled.
+    pub chaos: Option<ChaosConfig>,
+    /// How many days stored conversations are kept once they stop being written to, unless
+    /// referenced. Kept indefinitely when unset.
+    pub conversation_retention_days: Option<u64>,
+    /// How the stored checkpoints are partitioned by month. Left unpartitioned when unset.
+    pub checkpoint_partitioning: Option<CheckpointPartitioning>,
+    /// What the store's diagnostics recommend maintaining, and the statements they run first.
+    pub store_maintenance: MaintenancePolicy,
+    /// What the stored threads are linted against for prompt anti-patterns.
+    pub conversation_lint: LintPolicy,
+    /// The names stored messages are saved under, by the intent and project of their request.
+    pub agent_identity: AgentIdentity,
+    /// Prompts run without a user on a schedule, or after commits, each stored as a new thread.
+    pub scheduled_runs: Vec<ScheduledRun>,
+    /// Stored threads replayed by `llm_store: run regression tests`, with what their answers
+    /// must hold.
+    pub regression_tests: Vec<RegressionTest>,
+    /// The webhook told about stored threads that match its rules. Nothing is sent when unset.
+    pub notifications: Option<NotificationPolicy>,
+    /// Where anonymized daily usage aggregates are exported. Nothing is exported when unset.
+    pub usage_export: Option<UsageExportConfig>,
+}
+
+/// Where saved completions are mirrored as LangSmith traces. Nothing is sent without an API key.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LangSmithSettings {
+    pub api_url: String,
+    pub api_key: Option<String>,
+    pub project: String,
+}
+
+impl Default for LangSmithSettings {
+    fn default() -> Self {
+        Self {
+            api_url: LANGSMITH_API_URL.to_string(),
+            api_key: None,
+            project: "zed".to_string(),
+        }
+    }
+}
+
+impl LangSmithSettings {
+    pub fn exporter_config(&self) -> Option<LangSmithConfig> {
+        let api_key = self.api_key.as_ref().filter(|key| !key.is_empty())?;
+        Some(LangSmithConfig {
+            api_url: self.api_url.clone(),
+            api_key: api_key.clone(),
+            project: self.project.clone(),
+        })
+    }
+}
+
+/// The issue trackers threads are linked to. Summaries of resolved threads are only posted to
+/// trackers with credentials.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IssueTrackerSettings {
+    pub github_api_url: String,
+    pub github_token: Option<String>,
+    pub jira_base_url: Option<String>,
+    pub jira_email: Option<String>,
+    pub jira_api_token: Option<String>,
+    pub comment_on_resolve: bool,
+}
+
+impl Default for IssueTrackerSettings {
+    fn default() -> Self {
+        Self {
+            github_api_url: GITHUB_API_URL.to_string(),
+            github_token: None,
+            jira_base_url: None,
+            jira_email: None,
+            jira_api_token: None,
+            comment_on_resolve: false,
+        }
+    }
+}
+
+impl IssueTrackerSettings {
+    pub fn tracker_config(&self) -> IssueTrackerConfig {
+        let non_empty = |value: &Option<String>| value.clone().filter(|value| !value.is_empty());
+        let github = non_empty(&self.github_token).map(|token| GitHubIssuesConfig {
+            api_url: self.github_api_url.clone(),
+            token,
+        });
+        let jira_base_url = non_empty(&self.jira_base_url);
+        let jira = jira_base_url
+            .clone()
+            .zip(non_empty(&self.jira_email))
+            .zip(non_empty(&self.jira_api_token))
+            .map(|((base_url, email), api_token)| JiraConfig {
+                base_url,
+                email,
+                api_token,
+            });
+        IssueTrackerConfig {
+            github,
+            jira,
+            jira_base_url,
+            comment_on_resolve: self.comment_on_resolve,
+        }
+    }
 }
 
 #[derive(Default, Clone, Debug, Serialize, Deserialize, PartialEq, JsonSchema)]
@@ -85,6 +238,37 @@ pub struct AllLanguageModelSettingsContent {
     pub deepseek: Option<DeepseekSettingsContent>,
     pub copilot_chat: Option<CopilotChatSettingsContent>,
     pub mistral: Option<MistralSettingsContent>,
+    pub langsmith: Option<LangSmithSettingsContent>,
+    pub issue_trackers: Option<IssueTrackerSettingsContent>,
+    pub author: Option<AuthorSettingsContent>,
+    pub prompt_experiments: Option<Vec<PromptExperimentSettingsContent>>,
+    pub scheduled_jobs: Option<HashMap<String, String>>,
+    pub enable_storage: Option<bool>,
+    pub postgres_connection_string: Option<String>,
+    pub blob_encoding: Option<BlobEncoding>,
+    pub read_only_store: Option<bool>,
+    pub context_compaction_threshold: Option<u64>,
+    pub collaboration_persistence: Option<CollaborationPersistence>,
+    pub shadow_persistence: Option<bool>,
+    pub sampling: Option<SamplingPolicy>,
+    pub message_rules: Option<Vec<MessageRule>>,
+    pub capture_raw_exchanges: Option<bool>,
+    pub persistence_features: Option<PersistenceFeaturesContent>,
+    pub guardrails: Option<Vec<GuardrailRule>>,
+    pub secret_scan: Option<SecretScanPolicy>,
+    pub disabled_response_interceptors: Option<HashMap<String, Vec<String>>>,
+    pub response_cache: Option<ResponseCachePolicy>,
+    pub completion_fixtures: Option<FixturePolicy>,
+    pub chaos: Option<ChaosConfig>,
+    pub conversation_retention_days: Option<u64>,
+    pub checkpoint_partitioning: Option<CheckpointPartitioning>,
+    pub store_maintenance: Option<MaintenancePolicy>,
+    pub conversation_lint: Option<LintPolicy>,
+    pub agent_identity: Option<AgentIdentity>,
+    pub scheduled_runs: Option<Vec<ScheduledRun>>,
+    pub regression_tests: Option<Vec<RegressionTest>>,
+    pub notifications: Option<NotificationPolicy>,
+    pub usage_export: Option<UsageExportConfig>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, JsonSchema)]
@@ -280,6 +464,103 @@ pub struct OpenRouterSettingsContent {
     pub available_models: Option<Vec<provider::open_router::AvailableModel>>,
 }
 
+/// Who stored messages are attributed to, so that a team can share one conversation store.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AuthorSettings {
+    pub name: Option<String>,
+    pub email: Option<String>,
+    pub use_zed_account: bool,
+}
+
+impl Default for AuthorSettings {
+    fn default() -> Self {
+        Self {
+            name: None,
+            email: None,
+            use_zed_account: true,
+        }
+    }
+}
+
+impl AuthorSettings {
+    /// The configured identity, falling back to the signed-in Zed account.
+    pub fn author(&self, user: Option<&User>) -> Option<MessageAuthor> {
+        let user = user.filter(|_| self.use_zed_account);
+        let name = self.name.clone().or_else(|| {
+            user.map(|user| {
+                user.name
+                    .clone()
+                    .unwrap_or_else(|| user.github_login.clone())
+            })
+        });
+        let id = self
+            .email
+            .clone()
+            .or_else(|| user.map(|user| format!("zed:{}", user.id)))
+            .or_else(|| name.clone())?;
+        Some(MessageAuthor {
+            id,
+            name,
+            email: self.email.clone(),
+        })
+    }
+}
+
+#[derive(Default, Clone, Debug, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct AuthorSettingsContent {
+    /// The name shown next to your threads.
+    pub name: Option<String>,
+    /// Identifies you across machines; used instead of your Zed account when set.
+    pub email: Option<String>,
+    /// Whether to attribute messages to the signed-in Zed account when no email is set.
+    pub use_zed_account: Option<bool>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct PromptExperimentSettingsContent {
+    /// Names the experiment in stored messages and results.
+    pub id: String,
+    /// The prompt template the variants stand in for.
+    ///
+    /// Default: "assistant_system_prompt"
+    pub template: Option<String>,
+    /// The prompt templates to choose between, by name. Add them to the prompt overrides
+    /// directory as `<name>.hbs`; list the original template to keep it in the running.
+    pub variants: Vec<String>,
+}
+
+#[derive(Default, Clone, Debug, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct LangSmithSettingsContent {
+    pub api_url: Option<String>,
+    pub api_key: Option<String>,
+    pub project: Option<String>,
+}
+
+#[derive(Default, Clone, Debug, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct IssueTrackerSettingsContent {
+    pub github: Option<GitHubIssueTrackerSettingsContent>,
+    pub jira: Option<JiraIssueTrackerSettingsContent>,
+    /// Whether marking a thread resolved posts its summary as a comment on its issue.
+    ///
+    /// Default: false
+    pub comment_on_resolve: Option<bool>,
+}
+
+#[derive(Default, Clone, Debug, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct GitHubIssueTrackerSettingsContent {
+    pub api_url: Option<String>,
+    /// A token allowed to comment on the repositories' issues.
+    pub token: Option<String>,
+}
+
+#[derive(Default, Clone, Debug, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct JiraIssueTrackerSettingsContent {
+    /// The Jira site, e.g. "https://acme.atlassian.net". Bare keys like "PROJ-123" link to it.
+    pub base_url: Option<String>,
+    pub email: Option<String>,
+    pub api_token: Option<String>,
+}
+
 impl settings::Settings for AllLanguageModelSettings {
     const KEY: Option<&'static str> = Some("language_models");
 
@@ -431,6 +712,142 @@ impl settings::Settings for AllLanguageModelSettings {
                     .as_ref()
                     .and_then(|s| s.available_models.clone()),
             );
+
+            // LangSmith
+            let langsmith = value.langsmith.clone();
+            merge(
+                &mut settings.langsmith.api_url,
+                langsmith.as_ref().and_then(|s| s.api_url.clone()),
+            );
+            if let Some(api_key) = langsmith.as_ref().and_then(|s| s.api_key.clone()) {
+                settings.langsmith.api_key = Some(api_key);
+            }
+            merge(
+                &mut settings.langsmith.project,
+                langsmith.as_ref().and_then(|s| s.project.clone()),
+            );
+
+            // Issue trackers
+            let issue_trackers = value.issue_trackers.clone().unwrap_or_default();
+            let github = issue_trackers.github.unwrap_or_default();
+            merge(&mut settings.issue_trackers.github_api_url, github.api_url);
+            if let Some(token) = github.token {
+                settings.issue_trackers.github_token = Some(token);
+            }
+            let jira = issue_trackers.jira.unwrap_or_default();
+            if let Some(base_url) = jira.base_url {
+                settings.issue_trackers.jira_base_url = Some(base_url);
+            }
+            if let Some(email) = jira.email {
+                settings.issue_trackers.jira_email = Some(email);
+            }
+            if let Some(api_token) = jira.api_token {
+                settings.issue_trackers.jira_api_token = Some(api_token);
+            }
+            merge(
+                &mut settings.issue_trackers.comment_on_resolve,
+                issue_trackers.comment_on_resolve,
+            );
+
+            // Author
+            let author = value.author.clone();
+            if let Some(name) = author.as_ref().and_then(|s| s.name.clone()) {
+                settings.author.name = Some(name);
+            }
+            if let Some(email) = author.as_ref().and_then(|s| s.email.clone()) {
+                settings.author.email = Some(email);
+            }
+            merge(
+                &mut settings.author.use_zed_account,
+                author.as_ref().and_then(|s| s.use_zed_account),
+            );
+
+            // Prompt experiments
+            if let Some(experiments) = value.prompt_experiments.clone() {
+                settings.prompt_experiments = experiments
+                    .into_iter()
+                    .map(|experiment| PromptExperiment {
+                        id: experiment.id,
+                        template_id: experiment
+                            .template
+                            .unwrap_or_else(|| "assistant_system_prompt".to_string()),
+                        variants: experiment.variants,
+                    })
+                    .collect();
+            }
+
+            // Scheduled jobs
+            if let Some(scheduled_jobs) = value.scheduled_jobs.clone() {
+                settings.scheduled_jobs.extend(scheduled_jobs);
+            }
+
+            merge(&mut settings.enable_storage, value.enable_storage);
+            if let Some(connection_string) = value.postgres_connection_string.clone() {
+                settings.postgres_connection_string = Some(connection_string);
+            }
+            merge(&mut settings.blob_encoding, value.blob_encoding);
+            merge(&mut settings.read_only_store, value.read_only_store);
+            if let Some(threshold) = value.context_compaction_threshold {
+                settings.context_compaction_threshold = Some(threshold);
+            }
+            merge(
+                &mut settings.collaboration_persistence,
+                value.collaboration_persistence,
+            );
+            merge(&mut settings.shadow_persistence, value.shadow_persistence);
+            if let Some(sampling) = value.sampling {
+                settings.sampling = Some(sampling);
+            }
+            merge(&mut settings.message_rules, value.message_rules);
+            merge(
+                &mut settings.capture_raw_exchanges,
+                value.capture_raw_exchanges,
+            );
+            if let Some(persistence_features) = value.persistence_features {
+                settings.persistence_features = persistence_features.into();
+            }
+            merge(&mut settings.guardrails, value.guardrails);
+            if let Some(secret_scan) = value.secret_scan.clone() {
+                settings.secret_scan = Some(secret_scan);
+            }
+            if let Some(disabled) = value.disabled_response_interceptors.clone() {
+                settings.disabled_response_interceptors.extend(disabled);
+            }
+            if let Some(response_cache) = value.response_cache.clone() {
+                settings.response_cache = Some(response_cache);
+            }
+            if let Some(completion_fixtures) = value.completion_fixtures.clone() {
+                settings.completion_fixtures = Some(completion_fixtures);
+            }
+            if let Some(chaos) = value.chaos.clone() {
+                settings.chaos = Some(chaos);
+            }
+            if let Some(days) = value.conversation_retention_days {
+                settings.conversation_retention_days = Some(days);
+            }
+            if let Some(partitioning) = value.checkpoint_partitioning {
+                settings.checkpoint_partitioning = Some(partitioning);
+            }
+            if let Some(store_maintenance) = value.store_maintenance.clone() {
+                settings.store_maintenance = store_maintenance;
+            }
+            if let Some(conversation_lint) = value.conversation_lint.clone() {
+                settings.conversation_lint = conversation_lint;
+            }
+            if let Some(agent_identity) = value.agent_identity.clone() {
+                settings.agent_identity = agent_identity;
+            }
+            merge(&mut settings.scheduled_runs, value.scheduled_runs.clone());
+            merge(
+                &mut settings.regression_tests,
+                value.regression_tests.clone(),
+            );
+            if let Some(notifications) = value.notifications.clone() {
+                settings.notifications = Some(notifications);
+            }
+            if let Some(usage_export) = value.usage_export.clone() {
+                settings.usage_export = Some(usage_export);
+            }
         }
 
         Ok(settings)
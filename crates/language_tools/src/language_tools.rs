@@ -1,5 +1,7 @@
 mod key_context_view;
+mod llm_traffic;
 mod lsp_log;
+mod model_usage;
 mod syntax_tree_view;
 
 #[cfg(test)]
@@ -7,11 +9,15 @@ mod lsp_log_tests;
 
 use gpui::App;
 
+pub use llm_traffic::LlmTrafficView;
 pub use lsp_log::{LogStore, LspLogToolbarItemView, LspLogView};
+pub use model_usage::ModelUsageView;
 pub use syntax_tree_view::{SyntaxTreeToolbarItemView, SyntaxTreeView};
 
 pub fn init(cx: &mut App) {
     lsp_log::init(cx);
     syntax_tree_view::init(cx);
     key_context_view::init(cx);
+    llm_traffic::init(cx);
+    model_usage::init(cx);
 }
@@ -0,0 +1,256 @@
+//! Mirrors every request sent to a model, and its streamed response, across all threads as it
+//! happens, to debug what providers are actually sent and send back.
+
+use editor::{Editor, EditorEvent, scroll::Autoscroll};
+use futures::StreamExt;
+use gpui::{
+    App, Context, Entity, EventEmitter, FocusHandle, Focusable, IntoElement, ParentElement, Render,
+    Styled, Subscription, Task, Window, actions,
+};
+use language_model::{
+    LanguageModelCompletionEvent,
+    message_handler::{TrafficEvent, TrafficKind, subscribe_llm_traffic},
+};
+use ui::{Label, prelude::*};
+use workspace::{SplitDirection, Workspace, item::Item};
+
+use crate::lsp_log::initialize_new_editor;
+
+actions!(dev, [OpenLlmTraffic]);
+
+pub fn init(cx: &mut App) {
+    cx.observe_new(|workspace: &mut Workspace, _, _| {
+        workspace.register_action(|workspace, _: &OpenLlmTraffic, window, cx| {
+            let traffic_view = cx.new(|cx| LlmTrafficView::new(window, cx));
+            workspace.split_item(SplitDirection::Right, Box::new(traffic_view), window, cx)
+        });
+    })
+    .detach();
+}
+
+#[derive(Default)]
+struct TrafficTotals {
+    requests: usize,
+    /// Locally counted, so only an estimate.
+    prompt_tokens: usize,
+    input_tokens: u64,
+    output_tokens: u64,
+    cache_read_tokens: u64,
+    errors: usize,
+}
+
+pub struct LlmTrafficView {
+    editor: Entity<Editor>,
+    focus_handle: FocusHandle,
+    totals: TrafficTotals,
+    /// The checkpoint whose streamed text the log ends in, and whether it is thinking, so that
+    /// its next chunk is appended in place.
+    streaming: Option<(String, bool)>,
+    _subscriptions: [Subscription; 2],
+    _receive_traffic: Task<()>,
+}
+
+impl LlmTrafficView {
+    pub fn new(window: &mut Window, cx: &mut Context<Self>) -> Self {
+        let editor = initialize_new_editor(String::new(), false, window, cx);
+        let editor_subscription = cx.subscribe(&editor, |_, _, event: &EditorEvent, cx| {
+            cx.emit(event.clone())
+        });
+        let focus_handle = cx.focus_handle();
+        let focus_subscription = cx.on_focus(&focus_handle, window, |this, window, cx| {
+            window.focus(&this.editor.focus_handle(cx));
+        });
+        let mut traffic = subscribe_llm_traffic(cx);
+        let receive_traffic = cx.spawn_in(window, async move |this, cx| {
+            while let Some(event) = traffic.next().await {
+                if this.update(cx, |this, cx| this.push(event, cx)).is_err() {
+                    break;
+                }
+            }
+        });
+        Self {
+            editor,
+            focus_handle,
+            totals: TrafficTotals::default(),
+            streaming: None,
+            _subscriptions: [editor_subscription, focus_subscription],
+            _receive_traffic: receive_traffic,
+        }
+    }
+
+    fn push(&mut self, event: TrafficEvent, cx: &mut Context<Self>) {
+        let entry = self.entry(&event);
+        if entry.is_empty() {
+            return;
+        }
+        self.editor.update(cx, |editor, cx| {
+            editor.set_read_only(false);
+            let last_point = editor.buffer().read(cx).len(cx);
+            let newest_cursor_is_at_end = editor.selections.newest::<usize>(cx).start >= last_point;
+            editor.edit(vec![(last_point..last_point, entry.as_str())], cx);
+            if newest_cursor_is_at_end {
+                editor.request_autoscroll(Autoscroll::bottom(), cx);
+            }
+            editor.set_read_only(true);
+        });
+        cx.notify();
+    }
+
+    /// The text the event adds to the log, updating the totals.
+    fn entry(&mut self, event: &TrafficEvent) -> String {
+        let TrafficEvent {
+            ids,
+            model_id,
+            kind,
+        } = event;
+        let origin = format!(
+            "{} · thread {} · checkpoint {}",
+            model_id.0, ids.thread_id, ids.checkpoint_id
+        );
+        match kind {
+            TrafficKind::Request {
+                messages,
+                prompt_tokens,
+            } => {
+                self.totals.requests += 1;
+                self.totals.prompt_tokens += prompt_tokens;
+                let mut entry = self.header(format!(
+                    "Request · {origin} · ~{prompt_tokens} prompt tokens"
+                ));
+                for (role, text) in messages {
+                    entry.push_str(&format!("[{role}] {text}\n"));
+                }
+                entry
+            }
+            TrafficKind::Event(LanguageModelCompletionEvent::Text(text)) => {
+                self.stream(&ids.checkpoint_id, false, &origin, text)
+            }
+            TrafficKind::Event(LanguageModelCompletionEvent::Thinking { text, .. }) => {
+                self.stream(&ids.checkpoint_id, true, &origin, text)
+            }
+            TrafficKind::Event(LanguageModelCompletionEvent::ToolUse(tool_use))
+                if tool_use.is_input_complete =>
+            {
+                let mut entry = self.header(format!("Tool use · {origin} · {}", tool_use.name));
+                entry.push_str(&tool_use.raw_input);
+                entry.push('\n');
+                entry
+            }
+            TrafficKind::Event(LanguageModelCompletionEvent::Stop(reason)) => {
+                self.header(format!("Stop · {origin} · {reason:?}"))
+            }
+            TrafficKind::Event(_) => String::new(),
+            TrafficKind::Error(error) => {
+                self.totals.errors += 1;
+                let mut entry = self.header(format!("Error · {origin}"));
+                entry.push_str(error);
+                entry.push('\n');
+                entry
+            }
+            TrafficKind::PersistFailed { error } => {
+                self.totals.errors += 1;
+                let mut entry = self.header(format!("Persist failed · {origin}"));
+                entry.push_str(error);
+                entry.push('\n');
+                entry
+            }
+            TrafficKind::End { usage } => {
+                let Some(usage) = usage else {
+                    return self.header(format!("End · {origin}"));
+                };
+                self.totals.input_tokens += u64::from(usage.input_tokens);
+                self.totals.output_tokens += u64::from(usage.output_tokens);
+                self.totals.cache_read_tokens += u64::from(usage.cache_read_input_tokens);
+                self.header(format!(
+                    "End · {origin} · {} input, {} output, {} cache read tokens",
+                    usage.input_tokens, usage.output_tokens, usage.cache_read_input_tokens
+                ))
+            }
+        }
+    }
+
+    /// Starts a new entry, on a line of its own after any streamed text.
+    fn header(&mut self, header: String) -> String {
+        let separator = if self.streaming.take().is_some() {
+            "\n"
+        } else {
+            ""
+        };
+        format!("{separator}// {header}\n")
+    }
+
+    fn stream(&mut self, checkpoint_id: &str, thinking: bool, origin: &str, text: &str) -> String {
+        let continues = self
+            .streaming
+            .as_ref()
+            .is_some_and(|(streaming, was_thinking)| {
+                streaming == checkpoint_id && *was_thinking == thinking
+            });
+        let mut entry = if continues {
+            String::new()
+        } else {
+            self.header(format!(
+                "{} · {origin}",
+                if thinking { "Thinking" } else { "Response" }
+            ))
+        };
+        entry.push_str(text);
+        self.streaming = Some((checkpoint_id.to_string(), thinking));
+        entry
+    }
+}
+
+impl Render for LlmTrafficView {
+    fn render(&mut self, _window: &mut Window, cx: &mut Context<Self>) -> impl IntoElement {
+        let totals = &self.totals;
+        v_flex()
+            .size_full()
+            .child(
+                h_flex()
+                    .p_1()
+                    .gap_3()
+                    .border_b_1()
+                    .border_color(cx.theme().colors().border)
+                    .child(Label::new(format!("Requests: {}", totals.requests)))
+                    .child(Label::new(format!(
+                        "Prompt tokens: ~{}",
+                        totals.prompt_tokens
+                    )))
+                    .child(Label::new(format!("Input tokens: {}", totals.input_tokens)))
+                    .child(Label::new(format!(
+                        "Output tokens: {}",
+                        totals.output_tokens
+                    )))
+                    .child(Label::new(format!(
+                        "Cache read tokens: {}",
+                        totals.cache_read_tokens
+                    )))
+                    .child(Label::new(format!("Errors: {}", totals.errors))),
+            )
+            .child(div().flex_1().child(self.editor.clone()))
+    }
+}
+
+impl Focusable for LlmTrafficView {
+    fn focus_handle(&self, _: &App) -> FocusHandle {
+        self.focus_handle.clone()
+    }
+}
+
+impl Item for LlmTrafficView {
+    type Event = EditorEvent;
+
+    fn to_item_events(event: &Self::Event, f: impl FnMut(workspace::item::ItemEvent)) {
+        Editor::to_item_events(event, f)
+    }
+
+    fn tab_content_text(&self, _detail: usize, _cx: &App) -> SharedString {
+        "Live LLM Traffic".into()
+    }
+
+    fn telemetry_event_text(&self) -> Option<&'static str> {
+        None
+    }
+}
+
+impl EventEmitter<EditorEvent> for LlmTrafficView {}
@@ -1528,7 +1528,7 @@ impl Render for LspLogToolbarItemView {
     }
 }
 
-fn initialize_new_editor(
+pub(crate) fn initialize_new_editor(
     content: String,
     move_to_end: bool,
     window: &mut Window,
@@ -0,0 +1,241 @@
+//! Shows how many tokens the requests made from each language, or each area of the project,
+//! used on each of the last weeks' days, to see where models are relied on most.
+
+use chrono::{Days, Utc};
+use gpui::{
+    App, Context, EventEmitter, FocusHandle, Focusable, IntoElement, ParentElement, Render, Styled,
+    Task, Window, actions,
+};
+use language_model::message_handler::{
+    MessageHandlerRegistry, UsageDimension, UsageHeatmap, get_message_handler,
+};
+use ui::{Button, ButtonStyle, Label, LabelSize, Tooltip, prelude::*};
+use workspace::{SplitDirection, Workspace, item::Item};
+use zed_actions::llm_store::ShowUsage;
+
+actions!(dev, [OpenModelUsage]);
+
+/// How many days back the heatmap goes, including today.
+const HEATMAP_DAYS: u64 = 28;
+
+pub fn init(cx: &mut App) {
+    cx.observe_new(|workspace: &mut Workspace, _, _| {
+        workspace
+            .register_action(|workspace, _: &OpenModelUsage, window, cx| {
+                open_usage(workspace, window, cx)
+            })
+            .register_action(|workspace, _: &ShowUsage, window, cx| {
+                open_usage(workspace, window, cx)
+            });
+    })
+    .detach();
+}
+
+fn open_usage(workspace: &mut Workspace, window: &mut Window, cx: &mut Context<Workspace>) {
+    let usage_view = cx.new(|cx| ModelUsageView::new(window, cx));
+    workspace.split_item(SplitDirection::Right, Box::new(usage_view), window, cx)
+}
+
+pub struct ModelUsageView {
+    focus_handle: FocusHandle,
+    dimension: UsageDimension,
+    heatmap: Option<UsageHeatmap>,
+    error: Option<SharedString>,
+    _load_usage: Task<()>,
+}
+
+impl ModelUsageView {
+    pub fn new(window: &mut Window, cx: &mut Context<Self>) -> Self {
+        let mut this = Self {
+            focus_handle: cx.focus_handle(),
+            dimension: UsageDimension::Language,
+            heatmap: None,
+            error: None,
+            _load_usage: Task::ready(()),
+        };
+        this.load_usage(window, cx);
+        this
+    }
+
+    fn set_dimension(
+        &mut self,
+        dimension: UsageDimension,
+        window: &mut Window,
+        cx: &mut Context<Self>,
+    ) {
+        if self.dimension != dimension {
+            self.dimension = dimension;
+            self.load_usage(window, cx);
+        }
+    }
+
+    fn load_usage(&mut self, window: &mut Window, cx: &mut Context<Self>) {
+        let handler = cx
+            .has_global::<MessageHandlerRegistry>()
+            .then(|| get_message_handler(cx))
+            .flatten();
+        let Some(handler) = handler else {
+            self.heatmap = None;
+            self.error = Some("Usage is only recorded while messages are stored.".into());
+            cx.notify();
+            return;
+        };
+        let dimension = self.dimension;
+        let today = Utc::now().date_naive();
+        let since = today
+            .checked_sub_days(Days::new(HEATMAP_DAYS - 1))
+            .unwrap_or(today);
+        self._load_usage = cx.spawn_in(window, async move |this, cx| {
+            let breakdowns = handler.usage_breakdown(dimension, since).await;
+            this.update(cx, |this, cx| {
+                if this.dimension != dimension {
+                    return;
+                }
+                match breakdowns {
+                    Ok(breakdowns) => {
+                        this.heatmap = Some(UsageHeatmap::new(&breakdowns, today, HEATMAP_DAYS));
+                        this.error = None;
+                    }
+                    Err(error) => {
+                        this.heatmap = None;
+                        this.error = Some(format!("Failed to load usage: {error:#}").into());
+                    }
+                }
+                cx.notify();
+            })
+            .ok();
+        });
+    }
+
+    fn render_dimension_button(
+        &self,
+        id: &'static str,
+        label: &'static str,
+        dimension: UsageDimension,
+        cx: &mut Context<Self>,
+    ) -> impl IntoElement {
+        Button::new(id, label)
+            .style(ButtonStyle::Subtle)
+            .toggle_state(self.dimension == dimension)
+            .on_click(
+                cx.listener(move |this, _, window, cx| this.set_dimension(dimension, window, cx)),
+            )
+    }
+
+    fn render_heatmap(&self, heatmap: &UsageHeatmap, cx: &mut Context<Self>) -> impl IntoElement {
+        let colors = cx.theme().colors();
+        let empty = colors.element_background;
+        let busy = colors.text_accent;
+        let unknown = match self.dimension {
+            UsageDimension::Language => "No language",
+            UsageDimension::ProjectArea => "No buffer",
+            UsageDimension::Source => "Unknown source",
+        };
+        v_flex()
+            .gap_1()
+            .children(heatmap.rows.iter().enumerate().map(|(row_ix, row)| {
+                let key = row.key.clone().unwrap_or_else(|| unknown.to_string());
+                h_flex()
+                    .gap_1()
+                    .child(
+                        div()
+                            .w_32()
+                            .child(Label::new(key.clone()).size(LabelSize::Small).truncate()),
+                    )
+                    .children(heatmap.days.iter().enumerate().map(|(column, day)| {
+                        let tokens = row.tokens[column];
+                        let intensity = heatmap.intensity(row_ix, column);
+                        let day = day.format("%b %-d");
+                        let tooltip = format!("{key} · {day} · {tokens} tokens");
+                        div()
+                            .id(SharedString::from(format!("usage-{row_ix}-{column}")))
+                            .size_4()
+                            .rounded_xs()
+                            .map(|cell| {
+                                if tokens == 0 {
+                                    cell.bg(empty)
+                                } else {
+                                    cell.bg(busy.opacity(0.2 + 0.8 * intensity))
+                                }
+                            })
+                            .tooltip(Tooltip::text(tooltip))
+                    }))
+                    .child(
+                        Label::new(format!("{} requests", row.requests))
+                            .size(LabelSize::Small)
+                            .color(Color::Muted),
+                    )
+            }))
+    }
+}
+
+impl Render for ModelUsageView {
+    fn render(&mut self, _window: &mut Window, cx: &mut Context<Self>) -> impl IntoElement {
+        v_flex()
+            .id("model-usage")
+            .size_full()
+            .p_4()
+            .gap_4()
+            .overflow_y_scroll()
+            .track_focus(&self.focus_handle)
+            .child(
+                h_flex()
+                    .gap_2()
+                    .child(Label::new("Model Usage").size(LabelSize::Large))
+                    .child(self.render_dimension_button(
+                        "by-language",
+                        "By Language",
+                        UsageDimension::Language,
+                        cx,
+                    ))
+                    .child(self.render_dimension_button(
+                        "by-project-area",
+                        "By Project Area",
+                        UsageDimension::ProjectArea,
+                        cx,
+                    ))
+                    .child(self.render_dimension_button(
+                        "by-source",
+                        "By Source",
+                        UsageDimension::Source,
+                        cx,
+                    )),
+            )
+            .child(
+                Label::new("Tokens used each day, by where each request was made from.")
+                    .color(Color::Muted),
+            )
+            .when_some(self.error.clone(), |this, error| {
+                this.child(Label::new(error).color(Color::Error))
+            })
+            .when_some(self.heatmap.as_ref(), |this, heatmap| {
+                if heatmap.rows.is_empty() {
+                    this.child(Label::new("No requests recorded yet.").color(Color::Muted))
+                } else {
+                    this.child(self.render_heatmap(heatmap, cx))
+                }
+            })
+    }
+}
+
+impl Focusable for ModelUsageView {
+    fn focus_handle(&self, _: &App) -> FocusHandle {
+        self.focus_handle.clone()
+    }
+}
+
+impl Item for ModelUsageView {
+    type Event = ();
+
+    fn to_item_events(_: &Self::Event, _: impl FnMut(workspace::item::ItemEvent)) {}
+
+    fn tab_content_text(&self, _detail: usize, _cx: &App) -> SharedString {
+        "Model Usage".into()
+    }
+
+    fn telemetry_event_text(&self) -> Option<&'static str> {
+        None
+    }
+}
+
+impl EventEmitter<()> for ModelUsageView {}
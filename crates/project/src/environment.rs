@@ -137,6 +137,13 @@ impl ProjectEnvironment {
             .or_insert_with(|| get_directory_env_impl(abs_path.clone(), cx).shared())
             .clone()
     }
+
+    /// Drops the cached environments at or below `abs_path`, so that they are resolved again
+    /// on next use, e.g. after the active toolchain changed.
+    pub fn invalidate_environments(&mut self, abs_path: &Path) {
+        self.environments
+            .retain(|path, _| !path.starts_with(abs_path));
+    }
 }
 
 fn set_origin_marker(env: &mut HashMap<String, String>, origin: EnvironmentOrigin) {
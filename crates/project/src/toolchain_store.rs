@@ -296,7 +296,10 @@ struct RemoteStore(WeakEntity<RemoteToolchainStore>);
 
 #[derive(Clone)]
 pub enum ToolchainStoreEvent {
-    ToolchainActivated,
+    ToolchainActivated {
+        path: ProjectPath,
+        toolchain: Toolchain,
+    },
 }
 
 impl EventEmitter<ToolchainStoreEvent> for LocalToolchainStore {}
@@ -313,8 +316,8 @@ impl LocalToolchainStore {
                 this.active_toolchains
                     .entry((path.worktree_id, toolchain.language_name.clone()))
                     .or_default()
-                    .insert(path.path, toolchain.clone());
-                cx.emit(ToolchainStoreEvent::ToolchainActivated);
+                    .insert(path.path.clone(), toolchain.clone());
+                cx.emit(ToolchainStoreEvent::ToolchainActivated { path, toolchain });
             })
             .ok();
             Some(())
@@ -1,5 +1,6 @@
 use anyhow::Result;
 use assets::Assets;
+use collections::HashMap;
 use fs::Fs;
 use futures::StreamExt;
 use gpui::{App, AppContext as _, AssetSource};
@@ -18,6 +19,9 @@ use util::{ResultExt, get_system_shell};
 
 use crate::UserPromptId;
 
+/// The template the agent's system prompt is rendered from.
+pub const ASSISTANT_SYSTEM_PROMPT_TEMPLATE: &str = "assistant_system_prompt";
+
 #[derive(Debug, Clone, Serialize)]
 pub struct ProjectContext {
     pub worktrees: Vec<WorktreeContext>,
@@ -124,6 +128,9 @@ pub struct PromptLoadingParams<'a> {
 
 pub struct PromptBuilder {
     handlebars: Arc<Mutex<Handlebars<'static>>>,
+    /// The source of each registered template, so that the prompts they render can be traced back
+    /// to the exact template that produced them.
+    template_sources: Arc<Mutex<HashMap<String, Arc<str>>>>,
 }
 
 impl PromptBuilder {
@@ -172,16 +179,25 @@ impl PromptBuilder {
 
     pub fn new(loading_params: Option<PromptLoadingParams>) -> Result<Self> {
         let mut handlebars = Handlebars::new();
-        Self::register_built_in_templates(&mut handlebars)?;
+        let mut template_sources = HashMap::default();
+        Self::register_built_in_templates(&mut handlebars, &mut template_sources)?;
         handlebars.register_helper("has_tool", Box::new(Self::has_tool_helper));
 
         let handlebars = Arc::new(Mutex::new(handlebars));
+        let template_sources = Arc::new(Mutex::new(template_sources));
 
         if let Some(params) = loading_params {
-            Self::watch_fs_for_template_overrides(params, handlebars.clone());
+            Self::watch_fs_for_template_overrides(
+                params,
+                handlebars.clone(),
+                template_sources.clone(),
+            );
         }
 
-        Ok(Self { handlebars })
+        Ok(Self {
+            handlebars,
+            template_sources,
+        })
     }
 
     /// Watches the filesystem for changes to prompt template overrides.
@@ -200,9 +216,11 @@ impl PromptBuilder {
     /// * `params` - A `PromptLoadingParams` struct containing the filesystem, repository path,
     ///   and application context.
     /// * `handlebars` - An `Arc<Mutex<Handlebars>>` for registering and updating templates.
+    /// * `template_sources` - The sources of the registered templates, kept in sync with `handlebars`.
     fn watch_fs_for_template_overrides(
         params: PromptLoadingParams,
         handlebars: Arc<Mutex<Handlebars<'static>>>,
+        template_sources: Arc<Mutex<HashMap<String, Arc<str>>>>,
     ) {
         let templates_dir = paths::prompt_overrides_dir(params.repo_path.as_deref());
         params.cx.background_spawn(async move {
@@ -264,7 +282,9 @@ impl PromptBuilder {
                             if let Ok(content) = params.fs.load(&file_path).await {
                                 let file_name = file_path.file_stem().unwrap().to_string_lossy();
                                 log::debug!("Registering prompt template override: {}", file_name);
-                                handlebars.lock().register_template_string(&file_name, content).log_err();
+                                if handlebars.lock().register_template_string(&file_name, &content).log_err().is_some() {
+                                    template_sources.lock().insert(file_name.to_string(), content.into());
+                                }
                             }
                         }
                     }
@@ -282,7 +302,7 @@ impl PromptBuilder {
                     if changed_paths.iter().any(|p| &p.path == &templates_dir) {
                         if !params.fs.is_dir(&templates_dir).await {
                             log::info!("Prompt template overrides directory removed. Restoring built-in prompt templates.");
-                            Self::register_built_in_templates(&mut handlebars.lock()).log_err();
+                            Self::register_built_in_templates(&mut handlebars.lock(), &mut template_sources.lock()).log_err();
                             break;
                         }
                     }
@@ -291,7 +311,9 @@ impl PromptBuilder {
                             log::info!("Reloading prompt template override: {}", event.path.display());
                             if let Some(content) = params.fs.load(&event.path).await.log_err() {
                                 let file_name = event.path.file_stem().unwrap().to_string_lossy();
-                                handlebars.lock().register_template_string(&file_name, content).log_err();
+                                if handlebars.lock().register_template_string(&file_name, &content).log_err().is_some() {
+                                    template_sources.lock().insert(file_name.to_string(), content.into());
+                                }
                             }
                         }
                     }
@@ -304,7 +326,10 @@ impl PromptBuilder {
             .detach();
     }
 
-    fn register_built_in_templates(handlebars: &mut Handlebars) -> Result<()> {
+    fn register_built_in_templates(
+        handlebars: &mut Handlebars,
+        template_sources: &mut HashMap<String, Arc<str>>,
+    ) -> Result<()> {
         for path in Assets.list("prompts")? {
             if let Some(id) = path
                 .split('/')
@@ -314,7 +339,9 @@ impl PromptBuilder {
                 if let Some(prompt) = Assets.load(path.as_ref()).log_err().flatten() {
                     log::debug!("Registering built-in prompt template: {}", id);
                     let prompt = String::from_utf8_lossy(prompt.as_ref());
-                    handlebars.register_template_string(id, LineEnding::normalize_cow(prompt))?
+                    let prompt = LineEnding::normalize_cow(prompt);
+                    handlebars.register_template_string(id, prompt.as_ref())?;
+                    template_sources.insert(id.to_string(), prompt.into());
                 }
             }
         }
@@ -322,10 +349,29 @@ impl PromptBuilder {
         Ok(())
     }
 
+    /// The source of the template registered under `id`, including any user override.
+    pub fn template_source(&self, id: &str) -> Option<Arc<str>> {
+        self.template_sources.lock().get(id).cloned()
+    }
+
     pub fn generate_assistant_system_prompt(
         &self,
         context: &ProjectContext,
         model_context: &ModelContext,
+    ) -> Result<String, RenderError> {
+        self.generate_assistant_system_prompt_from(
+            ASSISTANT_SYSTEM_PROMPT_TEMPLATE,
+            context,
+            model_context,
+        )
+    }
+
+    /// Renders the system prompt from another template, e.g. a variant being experimented with.
+    pub fn generate_assistant_system_prompt_from(
+        &self,
+        template_id: &str,
+        context: &ProjectContext,
+        model_context: &ModelContext,
     ) -> Result<String, RenderError> {
         let template_context = PromptTemplateContext {
             project: context.clone(),
@@ -335,7 +381,7 @@ impl PromptBuilder {
 
         self.handlebars
             .lock()
-            .render("assistant_system_prompt", &template_context)
+            .render(template_id, &template_context)
     }
 
     pub fn generate_inline_transformation_prompt(
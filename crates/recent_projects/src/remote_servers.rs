@@ -1004,6 +1004,7 @@ impl RemoteServerProjects {
                     nickname: None,
                     args: connection_options.args.unwrap_or_default(),
                     upload_binary_over_ssh: None,
+                    persist_messages_via_host: None,
                     port_forwards: connection_options.port_forwards,
                 })
         });
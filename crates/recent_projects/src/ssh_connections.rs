@@ -52,6 +52,7 @@ impl SshSettings {
                 return SshConnectionOptions {
                     nickname: conn.nickname,
                     upload_binary_over_ssh: conn.upload_binary_over_ssh.unwrap_or_default(),
+                    persist_messages_via_host: conn.persist_messages_via_host.unwrap_or_default(),
                     args: Some(conn.args),
                     host,
                     port,
@@ -91,6 +92,11 @@ pub struct SshConnection {
     // limited outbound internet access.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub upload_binary_over_ssh: Option<bool>,
+    // By default Zed stores agent conversations from this machine.
+    // If this is set to true, they are stored by the server instead. Useful if
+    // the team's message store can only be reached from the server.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub persist_messages_via_host: Option<bool>,
 
     #[serde(skip_serializing_if = "Option::is_none")]
     pub port_forwards: Option<Vec<SshPortForwardOption>>,
@@ -106,6 +112,7 @@ impl From<SshConnection> for SshConnectionOptions {
             args: Some(val.args),
             nickname: val.nickname,
             upload_binary_over_ssh: val.upload_binary_over_ssh.unwrap_or_default(),
+            persist_messages_via_host: val.persist_messages_via_host.unwrap_or_default(),
             port_forwards: val.port_forwards,
         }
     }
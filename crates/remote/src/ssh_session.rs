@@ -83,6 +83,7 @@ pub struct SshConnectionOptions {
 
     pub nickname: Option<String>,
     pub upload_binary_over_ssh: bool,
+    pub persist_messages_via_host: bool,
 }
 
 #[macro_export]
@@ -232,6 +233,7 @@ impl SshConnectionOptions {
             password: None,
             nickname: None,
             upload_binary_over_ssh: false,
+            persist_messages_via_host: false,
         })
     }
 
@@ -7,6 +7,10 @@ use fs::Fs;
 use gpui::{App, AppContext as _, AsyncApp, Context, Entity, PromptLevel};
 use http_client::HttpClient;
 use language::{Buffer, BufferEvent, LanguageRegistry, proto::serialize_operation};
+use language_model::message_handler::{
+    MessageHandlerRegistry, forwarded_messages, get_message_handler, init_message_handler,
+    set_message_rules,
+};
 use node_runtime::NodeRuntime;
 use project::{
     LspStore, LspStoreEvent, ManifestTree, PrettierStore, ProjectEnvironment, ProjectPath,
@@ -25,7 +29,7 @@ use rpc::{
     proto::{self, SSH_PEER_ID, SSH_PROJECT_ID},
 };
 
-use settings::initial_server_settings_content;
+use settings::{Settings as _, SettingsStore, initial_server_settings_content};
 use smol::stream::StreamExt;
 use std::{
     path::{Path, PathBuf},
@@ -34,6 +38,8 @@ use std::{
 use util::ResultExt;
 use worktree::Worktree;
 
+use crate::message_store_settings::MessageStoreSettings;
+
 pub struct HeadlessProject {
     pub fs: Arc<dyn Fs>,
     pub session: AnyProtoClient,
@@ -63,6 +69,8 @@ impl HeadlessProject {
         settings::init(cx);
         language::init(cx);
         project::Project::init_settings(cx);
+        MessageStoreSettings::register(cx);
+        observe_message_store_settings(cx);
     }
 
     pub fn new(
@@ -222,6 +230,7 @@ impl HeadlessProject {
         client.add_request_handler(cx.weak_entity(), Self::handle_get_path_metadata);
         client.add_request_handler(cx.weak_entity(), Self::handle_shutdown_remote_server);
         client.add_request_handler(cx.weak_entity(), Self::handle_ping);
+        client.add_request_handler(cx.weak_entity(), Self::handle_persist_completion_messages);
 
         client.add_entity_request_handler(Self::handle_add_worktree);
         client.add_request_handler(cx.weak_entity(), Self::handle_remove_worktree);
@@ -663,6 +672,59 @@ impl HeadlessProject {
         log::debug!("Received ping from client");
         Ok(proto::Ack {})
     }
+
+    /// Stores the agent messages of a client that can't reach the message store itself.
+    pub async fn handle_persist_completion_messages(
+        _this: Entity<Self>,
+        envelope: TypedEnvelope<proto::PersistCompletionMessages>,
+        cx: AsyncApp,
+    ) -> Result<proto::Ack> {
+        let (messages, ids, author) = forwarded_messages(envelope.payload)?;
+        let init = cx.update(|cx| {
+            (!cx.has_global::<MessageHandlerRegistry>()).then(|| {
+                let settings = MessageStoreSettings::get_global(cx);
+                let rules = settings.message_rules.clone();
+                let init = init_message_handler(settings.config(), cx);
+                set_message_rules(rules, cx);
+                init
+            })
+        })?;
+        if let Some(init) = init {
+            // Without a connection, messages are kept in the server's local cache until one is made.
+            init.await.log_err();
+        }
+        let handler = cx
+            .update(|cx| get_message_handler(cx))?
+            .context("no message handler")?;
+        handler
+            .save_forwarded_messages(messages, &ids, author)
+            .await?;
+        Ok(proto::Ack {})
+    }
+}
+
+/// Applies changes to the server's message store settings once it stores forwarded messages.
+fn observe_message_store_settings(cx: &mut App) {
+    let mut previous = MessageStoreSettings::get_global(cx).clone();
+    cx.observe_global::<SettingsStore>(move |cx| {
+        let settings = MessageStoreSettings::get_global(cx).clone();
+        if !cx.has_global::<MessageHandlerRegistry>() {
+            previous = settings;
+            return;
+        }
+        if settings.message_rules != previous.message_rules {
+            set_message_rules(settings.message_rules.clone(), cx);
+        }
+        if settings.enable_storage != previous.enable_storage
+            || settings.postgres_connection_string != previous.postgres_connection_string
+            || settings.blob_encoding != previous.blob_encoding
+            || settings.read_only_store != previous.read_only_store
+        {
+            init_message_handler(settings.config(), cx).detach_and_log_err(cx);
+        }
+        previous = settings;
+    })
+    .detach();
 }
 
 fn prompt_to_proto(
@@ -0,0 +1,41 @@
+use anyhow::Result;
+use gpui::App;
+use language_model::message_handler::{BlobEncoding, MessageHandlerConfig, MessageRule};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use settings::{Settings, SettingsSources};
+
+/// The server's own `language_models` settings for storing the messages that clients forward to
+/// it, read from its settings file like the client reads them from its own.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct MessageStoreSettings {
+    pub enable_storage: bool,
+    pub postgres_connection_string: Option<String>,
+    pub blob_encoding: BlobEncoding,
+    pub read_only_store: bool,
+    pub message_rules: Vec<MessageRule>,
+}
+
+impl MessageStoreSettings {
+    pub fn config(&self) -> MessageHandlerConfig {
+        MessageHandlerConfig {
+            postgres_connection_string: self.postgres_connection_string.clone(),
+            enable_storage: self.enable_storage,
+            blob_encoding: self.blob_encoding,
+            read_only: self.read_only_store,
+        }
+    }
+}
+
+impl Settings for MessageStoreSettings {
+    const KEY: Option<&'static str> = Some("language_models");
+
+    type FileContent = Self;
+
+    fn load(sources: SettingsSources<Self::FileContent>, _: &mut App) -> Result<Self> {
+        sources.json_merge()
+    }
+
+    fn import_from_vscode(_vscode: &settings::VsCodeSettings, _current: &mut Self::FileContent) {}
+}
@@ -1,4 +1,5 @@
 mod headless_project;
+mod message_store_settings;
 
 #[cfg(not(windows))]
 pub mod unix;
@@ -9,7 +9,8 @@ use gpui::{
 };
 use language::{Buffer, LanguageRegistry, language_settings::SoftWrap};
 use language_model::{
-    ConfiguredModel, LanguageModelRegistry, LanguageModelRequest, LanguageModelRequestMessage, Role,
+    ConfiguredModel, LanguageModelRegistry, LanguageModelRequest, LanguageModelRequestMessage,
+    RequestSource, Role,
 };
 use picker::{Picker, PickerDelegate};
 use release_channel::ReleaseChannel;
@@ -949,6 +950,16 @@ impl RulesLibrary {
                                     tool_choice: None,
                                     stop: Vec::new(),
                                     temperature: None,
+                                    toolchain: None,
+                                    project: None,
+                                    prompt_template: None,
+                                    origin: None,
+                                    buffer: None,
+                                    git_branch: None,
+                                    schedule_id: None,
+                                    fan_out_group: None,
+                                    message_id: None,
+                                    source: Some(RequestSource::Rules),
                                 },
                                 cx,
                             )
@@ -10,7 +10,7 @@ use heed::{
 };
 use language_model::{
     LanguageModelCompletionEvent, LanguageModelId, LanguageModelRegistry, LanguageModelRequest,
-    LanguageModelRequestMessage, Role,
+    LanguageModelRequestMessage, RequestSource, Role,
 };
 use log;
 use parking_lot::Mutex;
@@ -571,6 +571,16 @@ impl SummaryIndex {
             tool_choice: None,
             stop: Vec::new(),
             temperature: None,
+            toolchain: None,
+            project: None,
+            prompt_template: None,
+            origin: None,
+            buffer: None,
+            git_branch: None,
+            schedule_id: None,
+            fan_out_group: None,
+            message_id: None,
+            source: Some(RequestSource::SemanticIndex),
         };
 
         let code_len = code.len();
@@ -899,6 +899,14 @@ impl TerminalPanel {
         self.assistant_enabled
     }
 
+    pub fn terminals(&self, cx: &App) -> Vec<Entity<TerminalView>> {
+        self.center
+            .panes()
+            .into_iter()
+            .flat_map(|pane| pane.read(cx).items_of_type::<TerminalView>())
+            .collect()
+    }
+
     fn is_enabled(&self, cx: &App) -> bool {
         self.workspace.upgrade().map_or(false, |workspace| {
             is_enabled_in_workspace(workspace.read(cx), cx)
@@ -1,23 +1,64 @@
 use std::{path::Path, sync::Arc};
 
+use collections::HashSet;
 use editor::Editor;
 use gpui::{
-    AsyncWindowContext, Context, Entity, IntoElement, ParentElement, Render, Subscription, Task,
-    WeakEntity, Window, div,
+    App, AsyncWindowContext, Context, Corner, Entity, Focusable, IntoElement, ParentElement,
+    Render, Subscription, Task, WeakEntity, Window,
 };
 use language::{Buffer, BufferEvent, LanguageName, Toolchain};
 use project::{Project, ProjectPath, WorktreeId, toolchain_store::ToolchainStoreEvent};
-use ui::{Button, ButtonCommon, Clickable, FluentBuilder, LabelSize, SharedString, Tooltip};
+use project_panel::ProjectPanel;
+use terminal::Terminal;
+use terminal_view::TerminalView;
+use ui::{
+    Button, ButtonCommon, Clickable, Color, ContextMenu, FluentBuilder, IconButton, IconName,
+    IconSize, LabelSize, PopoverMenu, SharedString, Tooltip, h_flex,
+};
 use workspace::{StatusItemView, Workspace, item::ItemHandle};
 
-use crate::ToolchainSelector;
+use crate::{
+    ToolchainSelector,
+    toolchain_detection::detect_toolchain,
+    toolchain_health::{ToolchainHealth, check_toolchain_health},
+};
 
 pub struct ActiveToolchain {
     active_toolchain: Option<Toolchain>,
+    health: ToolchainHealth,
     term: SharedString,
     workspace: WeakEntity<Workspace>,
     active_buffer: Option<(WorktreeId, WeakEntity<Buffer>, Subscription)>,
+    active_path: Option<(ActivePath, Option<Subscription>)>,
+    last_language: Option<LanguageName>,
+    /// The location the displayed toolchain was resolved for.
+    active_location: Option<(WorktreeId, Arc<Path>, LanguageName)>,
+    /// Languages resolved so far, used to list toolchains for every worktree.
+    known_languages: Vec<LanguageName>,
+    worktree_toolchains: Vec<WorktreeToolchain>,
+    _project_panel_subscription: Option<Subscription>,
     _update_toolchain_task: Task<Option<()>>,
+    _update_worktree_toolchains_task: Task<Option<()>>,
+    _health_check_task: Task<Option<()>>,
+}
+
+/// The toolchain resolved at the root of a worktree for one of the known languages.
+#[derive(Clone)]
+struct WorktreeToolchain {
+    worktree_id: WorktreeId,
+    worktree_name: SharedString,
+    toolchain: Toolchain,
+}
+
+/// A location without a backing buffer (a terminal's working directory or a project panel
+/// selection) that the toolchain should be resolved for.
+#[derive(Clone, PartialEq)]
+struct ActivePath {
+    worktree_id: WorktreeId,
+    /// Directory relative to the worktree root that toolchains are looked up for.
+    path: Arc<Path>,
+    /// File used to infer the language; when absent, the last resolved language is reused.
+    language_path: Option<Arc<Path>>,
 }
 
 impl ActiveToolchain {
@@ -39,16 +80,29 @@ impl ActiveToolchain {
                     if let Some(editor) = editor {
                         this.active_toolchain.take();
                         this.update_lister(editor, window, cx);
+                    } else if this.active_path.is_some() {
+                        this.refresh_toolchain(window, cx);
                     }
+                    this._update_worktree_toolchains_task =
+                        Self::spawn_worktree_toolchains_task(window, cx);
                 },
             )
             .detach();
         }
         Self {
             active_toolchain: None,
+            health: ToolchainHealth::Unknown,
             active_buffer: None,
+            active_path: None,
+            last_language: None,
+            active_location: None,
+            known_languages: Vec::new(),
+            worktree_toolchains: Vec::new(),
             term: SharedString::new_static("Toolchain"),
             workspace: workspace.weak_handle(),
+            _project_panel_subscription: None,
+            _update_worktree_toolchains_task: Task::ready(None),
+            _health_check_task: Task::ready(None),
 
             _update_toolchain_task: Self::spawn_tracker_task(window, cx),
         }
@@ -62,12 +116,42 @@ impl ActiveToolchain {
                         .map(|(_, buffer, _)| buffer.clone())
                 })
                 .ok()
-                .flatten()?;
+                .flatten();
             let workspace = this.read_with(cx, |this, _| this.workspace.clone()).ok()?;
-            let language_name = active_file
-                .read_with(cx, |this, _| Some(this.language()?.name()))
-                .ok()
-                .flatten()?;
+            let (worktree_id, path, language_name) = if let Some(active_file) = active_file {
+                let language_name = active_file
+                    .read_with(cx, |this, _| Some(this.language()?.name()))
+                    .ok()
+                    .flatten()?;
+                let (worktree_id, path) = active_file
+                    .update(cx, |this, cx| {
+                        this.file().and_then(|file| {
+                            Some((
+                                file.worktree_id(cx),
+                                Arc::<Path>::from(file.path().parent()?),
+                            ))
+                        })
+                    })
+                    .ok()
+                    .flatten()?;
+                (worktree_id, path, language_name)
+            } else {
+                let active_path = this
+                    .read_with(cx, |this, _| {
+                        this.active_path.as_ref().map(|(path, _)| path.clone())
+                    })
+                    .ok()
+                    .flatten()?;
+                let language_name = Self::language_for_path(&workspace, &active_path, cx).await;
+                let language_name = match language_name {
+                    Some(language_name) => language_name,
+                    None => this
+                        .read_with(cx, |this, _| this.last_language.clone())
+                        .ok()
+                        .flatten()?,
+                };
+                (active_path.worktree_id, active_path.path, language_name)
+            };
             let term = workspace
                 .update(cx, |workspace, cx| {
                     let languages = workspace.project().read(cx).languages();
@@ -77,23 +161,21 @@ impl ActiveToolchain {
                 .await?;
             let _ = this.update(cx, |this, cx| {
                 this.term = term;
+                this.last_language = Some(language_name.clone());
+                this.active_location = Some((worktree_id, path.clone(), language_name.clone()));
+                if !this.known_languages.contains(&language_name) {
+                    this.known_languages.push(language_name.clone());
+                }
                 cx.notify();
             });
-            let (worktree_id, path) = active_file
-                .update(cx, |this, cx| {
-                    this.file().and_then(|file| {
-                        Some((
-                            file.worktree_id(cx),
-                            Arc::<Path>::from(file.path().parent()?),
-                        ))
-                    })
-                })
-                .ok()
-                .flatten()?;
             let toolchain =
                 Self::active_toolchain(workspace, worktree_id, path, language_name, cx).await?;
-            let _ = this.update(cx, |this, cx| {
+            let _ = this.update_in(cx, |this, window, cx| {
+                this.health = ToolchainHealth::Unknown;
+                this._health_check_task = this.spawn_health_check_task(toolchain.clone(), cx);
                 this.active_toolchain = Some(toolchain);
+                this._update_worktree_toolchains_task =
+                    Self::spawn_worktree_toolchains_task(window, cx);
 
                 cx.notify();
             });
@@ -101,6 +183,333 @@ impl ActiveToolchain {
         })
     }
 
+    fn spawn_worktree_toolchains_task(
+        window: &mut Window,
+        cx: &mut Context<Self>,
+    ) -> Task<Option<()>> {
+        cx.spawn_in(window, async move |this, cx| {
+            let (workspace, languages) = this
+                .read_with(cx, |this, _| {
+                    (this.workspace.clone(), this.known_languages.clone())
+                })
+                .ok()?;
+            let worktrees = workspace
+                .read_with(cx, |workspace, cx| {
+                    workspace
+                        .project()
+                        .read(cx)
+                        .visible_worktrees(cx)
+                        .map(|worktree| {
+                            let worktree = worktree.read(cx);
+                            (
+                                worktree.id(),
+                                SharedString::from(worktree.root_name().to_string()),
+                            )
+                        })
+                        .collect::<Vec<_>>()
+                })
+                .ok()?;
+            let mut worktree_toolchains = Vec::new();
+            for (worktree_id, worktree_name) in worktrees {
+                for language_name in &languages {
+                    let toolchain = workspace
+                        .update(cx, |workspace, cx| {
+                            workspace.project().read(cx).active_toolchain(
+                                ProjectPath {
+                                    worktree_id,
+                                    path: Arc::from(Path::new("")),
+                                },
+                                language_name.clone(),
+                                cx,
+                            )
+                        })
+                        .ok()?
+                        .await;
+                    if let Some(toolchain) = toolchain {
+                        worktree_toolchains.push(WorktreeToolchain {
+                            worktree_id,
+                            worktree_name: worktree_name.clone(),
+                            toolchain,
+                        });
+                    }
+                }
+            }
+            this.update(cx, |this, cx| {
+                this.worktree_toolchains = worktree_toolchains;
+                cx.notify();
+            })
+            .ok()
+        })
+    }
+
+    fn spawn_health_check_task(
+        &self,
+        toolchain: Toolchain,
+        cx: &mut Context<Self>,
+    ) -> Task<Option<()>> {
+        let project = self
+            .workspace
+            .upgrade()
+            .map(|workspace| workspace.read(cx).project().clone());
+        cx.spawn(async move |this, cx| {
+            let project = project?;
+            // Remote toolchains live on the host; probing them locally would report them as missing.
+            let fs = project
+                .read_with(cx, |project, _| {
+                    project.is_local().then(|| project.fs().clone())
+                })
+                .ok()??;
+            let executor = cx.background_executor().clone();
+            let health = check_toolchain_health(fs, &toolchain, executor).await;
+            this.update(cx, |this, cx| {
+                if this.active_toolchain.as_ref() == Some(&toolchain) {
+                    this.health = health;
+                    cx.notify();
+                }
+            })
+            .ok()
+        })
+    }
+
+    /// The dropdown is only worth showing once toolchains resolve in more than one worktree.
+    fn has_multiple_worktrees(&self) -> bool {
+        self.worktree_toolchains
+            .iter()
+            .map(|entry| entry.worktree_id)
+            .collect::<HashSet<_>>()
+            .len()
+            > 1
+    }
+
+    fn toggle_selector(&self, window: &mut Window, cx: &mut App) {
+        let Some(workspace) = self.workspace.upgrade() else {
+            return;
+        };
+        workspace.update(cx, |workspace, cx| {
+            if let Some((worktree_id, path, language_name)) = self.active_location.clone() {
+                ToolchainSelector::toggle_for_path(
+                    workspace,
+                    worktree_id,
+                    path,
+                    language_name,
+                    window,
+                    cx,
+                );
+            } else {
+                ToolchainSelector::toggle(workspace, window, cx);
+            }
+        });
+    }
+
+    fn build_worktree_menu(&self, window: &mut Window, cx: &mut App) -> Entity<ContextMenu> {
+        let workspace = self.workspace.clone();
+        let term = self.term.clone();
+        let entries = self.worktree_toolchains.clone();
+        let active_location = self.active_location.clone();
+        ContextMenu::build(window, cx, move |mut menu, _, _| {
+            if let Some((worktree_id, path, language_name)) = active_location {
+                let workspace = workspace.clone();
+                menu = menu
+                    .entry(
+                        format!("Select {term} for Current Location"),
+                        None,
+                        move |window, cx| {
+                            Self::open_selector(
+                                &workspace,
+                                worktree_id,
+                                path.clone(),
+                                language_name.clone(),
+                                window,
+                                cx,
+                            )
+                        },
+                    )
+                    .separator();
+            }
+            let mut current_worktree = None;
+            for entry in entries {
+                if current_worktree != Some(entry.worktree_id) {
+                    current_worktree = Some(entry.worktree_id);
+                    menu = menu.header(entry.worktree_name.clone());
+                }
+                let label = format!(
+                    "{}: {}",
+                    entry.toolchain.language_name, entry.toolchain.name
+                );
+                let workspace = workspace.clone();
+                menu = menu.entry(label, None, move |window, cx| {
+                    Self::open_selector(
+                        &workspace,
+                        entry.worktree_id,
+                        Arc::from(Path::new("")),
+                        entry.toolchain.language_name.clone(),
+                        window,
+                        cx,
+                    )
+                });
+            }
+            menu
+        })
+    }
+
+    fn open_selector(
+        workspace: &WeakEntity<Workspace>,
+        worktree_id: WorktreeId,
+        path: Arc<Path>,
+        language_name: LanguageName,
+        window: &mut Window,
+        cx: &mut App,
+    ) {
+        workspace
+            .update(cx, |workspace, cx| {
+                ToolchainSelector::toggle_for_path(
+                    workspace,
+                    worktree_id,
+                    path,
+                    language_name,
+                    window,
+                    cx,
+                );
+            })
+            .ok();
+    }
+
+    async fn language_for_path(
+        workspace: &WeakEntity<Workspace>,
+        active_path: &ActivePath,
+        cx: &mut AsyncWindowContext,
+    ) -> Option<LanguageName> {
+        let language_path = active_path.language_path.clone()?;
+        let languages = workspace
+            .read_with(cx, |workspace, cx| {
+                workspace.project().read(cx).languages().clone()
+            })
+            .ok()?;
+        let language = languages
+            .language_for_file_path(&language_path)
+            .await
+            .ok()?;
+        Some(language.name())
+    }
+
+    /// Tracks the project panel selection, so that the toolchain follows it while the panel is focused.
+    pub fn observe_project_panel(
+        &mut self,
+        project_panel: &Entity<ProjectPanel>,
+        window: &mut Window,
+        cx: &mut Context<Self>,
+    ) {
+        self._project_panel_subscription =
+            Some(
+                cx.observe_in(project_panel, window, |this, project_panel, window, cx| {
+                    if !project_panel.focus_handle(cx).contains_focused(window, cx) {
+                        return;
+                    }
+                    let Some((worktree, entry)) = project_panel.read(cx).selected_entry(cx) else {
+                        return;
+                    };
+                    let (path, language_path) = if entry.is_dir() {
+                        (entry.path.clone(), None)
+                    } else {
+                        let Some(parent) = entry.path.parent() else {
+                            return;
+                        };
+                        (Arc::<Path>::from(parent), Some(entry.path.clone()))
+                    };
+                    let active_path = ActivePath {
+                        worktree_id: worktree.id(),
+                        path,
+                        language_path,
+                    };
+                    this.set_active_path(active_path, None, window, cx);
+                }),
+            );
+    }
+
+    fn update_terminal(
+        &mut self,
+        terminal_view: Entity<TerminalView>,
+        window: &mut Window,
+        cx: &mut Context<Self>,
+    ) {
+        let terminal = terminal_view.read(cx).terminal().clone();
+        let Some(active_path) = self.terminal_path(&terminal, cx) else {
+            return;
+        };
+        let subscription = cx.subscribe_in(
+            &terminal,
+            window,
+            |this, terminal, event: &terminal::Event, window, cx| {
+                // The shell's working directory is reflected in the terminal title.
+                if matches!(event, terminal::Event::TitleChanged) {
+                    if let Some(active_path) = this.terminal_path(terminal, cx) {
+                        this.retarget_active_path(active_path, window, cx);
+                    }
+                }
+            },
+        );
+        self.set_active_path(active_path, Some(subscription), window, cx);
+    }
+
+    fn terminal_path(&self, terminal: &Entity<Terminal>, cx: &App) -> Option<ActivePath> {
+        let working_directory = terminal.read(cx).working_directory()?;
+        let (worktree, relative_path) = self
+            .workspace
+            .upgrade()?
+            .read(cx)
+            .project()
+            .read(cx)
+            .find_worktree(&working_directory, cx)?;
+        Some(ActivePath {
+            worktree_id: worktree.read(cx).id(),
+            path: Arc::from(relative_path),
+            language_path: None,
+        })
+    }
+
+    fn set_active_path(
+        &mut self,
+        active_path: ActivePath,
+        subscription: Option<Subscription>,
+        window: &mut Window,
+        cx: &mut Context<Self>,
+    ) {
+        let unchanged = self.active_buffer.is_none()
+            && self
+                .active_path
+                .as_ref()
+                .is_some_and(|(current, _)| *current == active_path);
+        if unchanged {
+            return;
+        }
+        self.active_buffer = None;
+        self.active_path = Some((active_path, subscription));
+        self.refresh_toolchain(window, cx);
+    }
+
+    /// Points the tracked location elsewhere while keeping its subscription alive,
+    /// e.g. when a terminal's shell changes its working directory.
+    fn retarget_active_path(
+        &mut self,
+        active_path: ActivePath,
+        window: &mut Window,
+        cx: &mut Context<Self>,
+    ) {
+        let Some((current, _)) = self.active_path.as_mut() else {
+            return;
+        };
+        if *current != active_path {
+            *current = active_path;
+            self.refresh_toolchain(window, cx);
+        }
+    }
+
+    fn refresh_toolchain(&mut self, window: &mut Window, cx: &mut Context<Self>) {
+        self.active_toolchain.take();
+        self._update_toolchain_task = Self::spawn_tracker_task(window, cx);
+        cx.notify();
+    }
+
     fn update_lister(
         &mut self,
         editor: Entity<Editor>,
@@ -120,6 +529,7 @@ impl ActiveToolchain {
                     },
                 );
                 self.active_buffer = Some((worktree_id, buffer.downgrade(), subscription));
+                self.active_path = None;
                 self._update_toolchain_task = Self::spawn_tracker_task(window, cx);
             }
         }
@@ -171,7 +581,23 @@ impl ActiveToolchain {
                     })
                     .ok()?
                     .await?;
-                if let Some(toolchain) = toolchains.toolchains.first() {
+                let (fs, worktree_root) = project
+                    .read_with(cx, |this, cx| {
+                        let worktree_root = this
+                            .worktree_for_id(worktree_id, cx)
+                            .map(|worktree| worktree.read(cx).abs_path());
+                        (this.fs().clone(), worktree_root)
+                    })
+                    .ok()?;
+                let detected = match worktree_root {
+                    Some(worktree_root) => {
+                        detect_toolchain(fs.as_ref(), &worktree_root, &relative_path, &toolchains)
+                            .await
+                    }
+                    None => None,
+                };
+                let picked = detected.or_else(|| toolchains.toolchains.first().cloned());
+                if let Some(toolchain) = picked.as_ref() {
                     // Since we don't have a selected toolchain, pick one for user here.
                     workspace::WORKSPACE_DB
                         .set_toolchain(
@@ -197,7 +623,7 @@ impl ActiveToolchain {
                         .await;
                 }
 
-                toolchains.toolchains.first().cloned()
+                picked
             }
         })
     }
@@ -205,20 +631,47 @@ impl ActiveToolchain {
 
 impl Render for ActiveToolchain {
     fn render(&mut self, _window: &mut Window, cx: &mut Context<Self>) -> impl IntoElement {
-        div().when_some(self.active_toolchain.as_ref(), |el, active_toolchain| {
+        let has_multiple_worktrees = self.has_multiple_worktrees();
+        let problem = self
+            .active_toolchain
+            .as_ref()
+            .and_then(|toolchain| self.health.problem(toolchain));
+        h_flex().when_some(self.active_toolchain.as_ref(), |el, active_toolchain| {
             let term = self.term.clone();
-            el.child(
-                Button::new("change-toolchain", active_toolchain.name.clone())
-                    .label_size(LabelSize::Small)
-                    .on_click(cx.listener(|this, _, window, cx| {
-                        if let Some(workspace) = this.workspace.upgrade() {
-                            workspace.update(cx, |workspace, cx| {
-                                ToolchainSelector::toggle(workspace, window, cx)
-                            });
-                        }
-                    }))
-                    .tooltip(Tooltip::text(format!("Select {}", &term))),
-            )
+            let el = el.when_some(problem, |el, problem| {
+                el.child(
+                    IconButton::new("toolchain-health", IconName::Warning)
+                        .icon_size(IconSize::Small)
+                        .icon_color(Color::Warning)
+                        .tooltip(Tooltip::text(format!(
+                            "{problem}. Click to select another."
+                        )))
+                        .on_click(cx.listener(|this, _, window, cx| {
+                            this.toggle_selector(window, cx);
+                        })),
+                )
+            });
+            let button = Button::new("change-toolchain", active_toolchain.name.clone())
+                .label_size(LabelSize::Small);
+            if has_multiple_worktrees {
+                let this = cx.entity();
+                el.child(
+                    PopoverMenu::new("toolchain-menu")
+                        .menu(move |window, cx| {
+                            Some(this.update(cx, |this, cx| this.build_worktree_menu(window, cx)))
+                        })
+                        .anchor(Corner::BottomLeft)
+                        .trigger_with_tooltip(button, Tooltip::text(format!("Select {}", &term))),
+                )
+            } else {
+                el.child(
+                    button
+                        .on_click(cx.listener(|this, _, window, cx| {
+                            this.toggle_selector(window, cx);
+                        }))
+                        .tooltip(Tooltip::text(format!("Select {}", &term))),
+                )
+            }
         })
     }
 }
@@ -233,6 +686,10 @@ impl StatusItemView for ActiveToolchain {
         if let Some(editor) = active_pane_item.and_then(|item| item.downcast::<Editor>()) {
             self.active_toolchain.take();
             self.update_lister(editor, window, cx);
+        } else if let Some(terminal_view) =
+            active_pane_item.and_then(|item| item.downcast::<TerminalView>())
+        {
+            self.update_terminal(terminal_view, window, cx);
         }
         cx.notify();
     }
@@ -0,0 +1,225 @@
+//! Picks a toolchain out of the scanned candidates based on version pins and environment markers
+//! found in the project (`.python-version`, `rust-toolchain.toml`, `.nvmrc`, virtualenv directories),
+//! instead of blindly activating the first candidate.
+
+use std::path::{Path, PathBuf};
+
+use fs::Fs;
+use language::{Toolchain, ToolchainList};
+use serde::Deserialize;
+
+/// Key under which the detection provenance is recorded in [`Toolchain::as_json`].
+pub(crate) const DETECTION_SOURCE_KEY: &str = "detection_source";
+
+const VIRTUALENV_DIRS: &[&str] = &[".venv", "venv", "env", ".env"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum DetectionSource {
+    PythonVersion { file: PathBuf, version: String },
+    RustToolchain { file: PathBuf, channel: String },
+    Nvmrc { file: PathBuf, version: String },
+    Virtualenv { dir: PathBuf },
+}
+
+impl DetectionSource {
+    fn kind(&self) -> &'static str {
+        match self {
+            DetectionSource::PythonVersion { .. } => "python-version",
+            DetectionSource::RustToolchain { .. } => "rust-toolchain",
+            DetectionSource::Nvmrc { .. } => "nvmrc",
+            DetectionSource::Virtualenv { .. } => "virtualenv",
+        }
+    }
+
+    fn path(&self) -> &Path {
+        match self {
+            DetectionSource::PythonVersion { file, .. }
+            | DetectionSource::RustToolchain { file, .. }
+            | DetectionSource::Nvmrc { file, .. } => file,
+            DetectionSource::Virtualenv { dir } => dir,
+        }
+    }
+
+    fn matches(&self, toolchain: &Toolchain) -> bool {
+        match self {
+            DetectionSource::PythonVersion { version, .. }
+            | DetectionSource::RustToolchain {
+                channel: version, ..
+            }
+            | DetectionSource::Nvmrc { version, .. } => {
+                mentions_version(&toolchain.name, version)
+                    || mentions_version(&toolchain.path, version)
+            }
+            DetectionSource::Virtualenv { dir } => {
+                Path::new(toolchain.path.as_ref()).starts_with(dir)
+            }
+        }
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "kind": self.kind(),
+            "path": self.path().to_string_lossy(),
+        })
+    }
+}
+
+/// Walks from `relative_path` up to the worktree root, returning the first candidate matching a
+/// pin or marker. The returned toolchain carries the provenance under [`DETECTION_SOURCE_KEY`].
+pub(crate) async fn detect_toolchain(
+    fs: &dyn Fs,
+    worktree_root: &Path,
+    relative_path: &Path,
+    toolchains: &ToolchainList,
+) -> Option<Toolchain> {
+    if toolchains.toolchains.is_empty() {
+        return None;
+    }
+    for ancestor in relative_path.ancestors() {
+        let dir = worktree_root.join(ancestor);
+        for source in detection_sources(fs, &dir).await {
+            if let Some(toolchain) = toolchains
+                .toolchains
+                .iter()
+                .find(|toolchain| source.matches(toolchain))
+            {
+                return Some(with_provenance(toolchain.clone(), &source));
+            }
+        }
+    }
+    None
+}
+
+async fn detection_sources(fs: &dyn Fs, dir: &Path) -> Vec<DetectionSource> {
+    let mut sources = Vec::new();
+
+    let file = dir.join(".python-version");
+    if let Some(version) = fs
+        .load(&file)
+        .await
+        .ok()
+        .and_then(|c| parse_version_file(&c))
+    {
+        sources.push(DetectionSource::PythonVersion { file, version });
+    }
+
+    for name in ["rust-toolchain.toml", "rust-toolchain"] {
+        let file = dir.join(name);
+        if let Some(channel) = fs
+            .load(&file)
+            .await
+            .ok()
+            .and_then(|c| parse_rust_toolchain(&c))
+        {
+            sources.push(DetectionSource::RustToolchain { file, channel });
+            break;
+        }
+    }
+
+    let file = dir.join(".nvmrc");
+    if let Some(version) = fs
+        .load(&file)
+        .await
+        .ok()
+        .and_then(|c| parse_version_file(&c))
+    {
+        sources.push(DetectionSource::Nvmrc { file, version });
+    }
+
+    for name in VIRTUALENV_DIRS {
+        let venv = dir.join(name);
+        if fs.is_file(&venv.join("pyvenv.cfg")).await {
+            sources.push(DetectionSource::Virtualenv { dir: venv });
+        }
+    }
+
+    sources
+}
+
+fn with_provenance(mut toolchain: Toolchain, source: &DetectionSource) -> Toolchain {
+    if let serde_json::Value::Object(map) = &mut toolchain.as_json {
+        map.insert(DETECTION_SOURCE_KEY.to_string(), source.to_json());
+    } else {
+        toolchain.as_json = serde_json::json!({
+            "toolchain": toolchain.as_json,
+            DETECTION_SOURCE_KEY: source.to_json(),
+        });
+    }
+    toolchain
+}
+
+/// Reads the first meaningful line of a `.python-version` / `.nvmrc` style file.
+fn parse_version_file(contents: &str) -> Option<String> {
+    let line = contents
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty() && !line.starts_with('#'))?;
+    let version = line.strip_prefix('v').unwrap_or(line);
+    // `lts/*` and similar aliases can't be matched against a scanned toolchain.
+    if version.contains('/') {
+        return None;
+    }
+    Some(version.to_string())
+}
+
+fn parse_rust_toolchain(contents: &str) -> Option<String> {
+    #[derive(Deserialize)]
+    struct RustToolchainFile {
+        toolchain: RustToolchainSection,
+    }
+
+    #[derive(Deserialize)]
+    struct RustToolchainSection {
+        channel: Option<String>,
+    }
+
+    match toml::from_str::<RustToolchainFile>(contents) {
+        Ok(file) => file.toolchain.channel,
+        // The legacy `rust-toolchain` file contains just the channel name.
+        Err(_) => parse_version_file(contents),
+    }
+}
+
+/// Whether `haystack` mentions `version` on component boundaries: `3.11` matches `3.11.4`,
+/// but `3.1` does not match `3.11`.
+fn mentions_version(haystack: &str, version: &str) -> bool {
+    haystack.match_indices(version).any(|(ix, _)| {
+        let before = haystack[..ix].chars().next_back();
+        let after = haystack[ix + version.len()..].chars().next();
+        before.map_or(true, |c| !c.is_ascii_digit() && c != '.')
+            && after.map_or(true, |c| !c.is_ascii_digit())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_version_file() {
+        assert_eq!(parse_version_file("3.11.4\n"), Some("3.11.4".into()));
+        assert_eq!(
+            parse_version_file("# pinned\nv18.16.0"),
+            Some("18.16.0".into())
+        );
+        assert_eq!(parse_version_file("lts/*"), None);
+        assert_eq!(parse_version_file("\n\n"), None);
+    }
+
+    #[test]
+    fn test_parse_rust_toolchain() {
+        assert_eq!(
+            parse_rust_toolchain("[toolchain]\nchannel = \"1.87\"\n"),
+            Some("1.87".into())
+        );
+        assert_eq!(parse_rust_toolchain("nightly\n"), Some("nightly".into()));
+    }
+
+    #[test]
+    fn test_mentions_version() {
+        assert!(mentions_version("Python 3.11.4 (venv)", "3.11"));
+        assert!(mentions_version("/usr/bin/python3.11", "3.11"));
+        assert!(!mentions_version("Python 3.11.4", "3.1"));
+        assert!(!mentions_version("node-v18.16.0", "8.16.0"));
+    }
+}
@@ -0,0 +1,77 @@
+//! Quick sanity checks for the active toolchain, so that a stale selection (e.g. a deleted
+//! virtualenv) is surfaced in the status bar instead of failing later at runtime.
+
+use std::{path::Path, sync::Arc, time::Duration};
+
+use fs::Fs;
+use gpui::BackgroundExecutor;
+use language::Toolchain;
+use util::command::new_smol_command;
+
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) enum ToolchainHealth {
+    /// Not checked yet, or can't be checked from here (e.g. the toolchain lives on a remote host).
+    #[default]
+    Unknown,
+    Healthy,
+    Missing,
+    ProbeFailed(String),
+}
+
+impl ToolchainHealth {
+    /// Describes the problem, if there is one.
+    pub(crate) fn problem(&self, toolchain: &Toolchain) -> Option<String> {
+        match self {
+            ToolchainHealth::Unknown | ToolchainHealth::Healthy => None,
+            ToolchainHealth::Missing => Some(format!(
+                "{} no longer exists at {}",
+                toolchain.name, toolchain.path
+            )),
+            ToolchainHealth::ProbeFailed(reason) => {
+                Some(format!("{} failed to run: {reason}", toolchain.name))
+            }
+        }
+    }
+}
+
+/// Checks that the toolchain path still exists and, for executables, that `--version` runs.
+pub(crate) async fn check_toolchain_health(
+    fs: Arc<dyn Fs>,
+    toolchain: &Toolchain,
+    executor: BackgroundExecutor,
+) -> ToolchainHealth {
+    let path = Path::new(toolchain.path.as_ref());
+    let metadata = match fs.metadata(path).await {
+        Ok(Some(metadata)) => metadata,
+        Ok(None) => return ToolchainHealth::Missing,
+        Err(error) => return ToolchainHealth::ProbeFailed(error.to_string()),
+    };
+    // SDK directories have no single binary to probe.
+    if metadata.is_dir {
+        return ToolchainHealth::Healthy;
+    }
+
+    let probe = async {
+        match new_smol_command(path).arg("--version").output().await {
+            Ok(output) if output.status.success() => ToolchainHealth::Healthy,
+            Ok(output) => {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                let reason = stderr
+                    .lines()
+                    .next()
+                    .map(|line| line.trim().to_string())
+                    .filter(|line| !line.is_empty())
+                    .unwrap_or_else(|| output.status.to_string());
+                ToolchainHealth::ProbeFailed(reason)
+            }
+            Err(error) => ToolchainHealth::ProbeFailed(error.to_string()),
+        }
+    };
+    let timeout = async {
+        executor.timer(PROBE_TIMEOUT).await;
+        ToolchainHealth::ProbeFailed("timed out".to_string())
+    };
+    smol::future::or(probe, timeout).await
+}
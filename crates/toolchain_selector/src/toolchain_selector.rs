@@ -1,26 +1,43 @@
 mod active_toolchain;
+mod toolchain_detection;
+mod toolchain_health;
+mod toolchain_settings;
 
 pub use active_toolchain::ActiveToolchain;
 use editor::Editor;
+use fs::Fs;
 use fuzzy::{StringMatch, StringMatchCandidate, match_strings};
 use gpui::{
-    App, Context, DismissEvent, Entity, EventEmitter, FocusHandle, Focusable, ParentElement,
-    Render, Styled, Task, WeakEntity, Window, actions,
+    App, AsyncApp, Context, DismissEvent, Entity, EventEmitter, FocusHandle, Focusable,
+    ParentElement, PathPromptOptions, Render, Styled, Task, WeakEntity, Window, actions,
 };
 use language::{LanguageName, Toolchain, ToolchainList};
 use picker::{Picker, PickerDelegate};
-use project::{Project, ProjectPath, WorktreeId};
-use std::{borrow::Cow, path::Path, sync::Arc};
+use project::{
+    DirectoryLister, Project, ProjectPath, WorktreeId, toolchain_store::ToolchainStoreEvent,
+};
+use settings::Settings;
+use std::{
+    borrow::Cow,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use terminal_view::{TerminalView, terminal_panel::TerminalPanel};
+pub use toolchain_settings::{ToolchainChangeHooks, ToolchainSettings};
 use ui::{HighlightedLabel, ListItem, ListItemSpacing, prelude::*};
 use util::ResultExt;
-use workspace::{ModalView, Workspace};
+use workspace::{ModalView, Toast, Workspace, notifications::NotificationId};
 
 actions!(toolchain, [Select]);
 
 pub fn init(cx: &mut App) {
+    ToolchainSettings::register(cx);
     cx.observe_new(ToolchainSelector::register).detach();
 }
 
+struct TerminalsUseStaleToolchain;
+struct InvalidCustomToolchain;
+
 pub struct ToolchainSelector {
     picker: Entity<Picker<ToolchainSelectorDelegate>>,
 }
@@ -29,11 +46,85 @@ impl ToolchainSelector {
     fn register(
         workspace: &mut Workspace,
         _window: Option<&mut Window>,
-        _: &mut Context<Workspace>,
+        cx: &mut Context<Workspace>,
     ) {
         workspace.register_action(move |workspace, _: &Select, window, cx| {
             Self::toggle(workspace, window, cx);
         });
+        if let Some(toolchain_store) = workspace.project().read(cx).toolchain_store() {
+            cx.subscribe(&toolchain_store, |workspace, _, event, cx| match event {
+                ToolchainStoreEvent::ToolchainActivated { path, toolchain } => {
+                    Self::on_toolchain_activated(workspace, path, toolchain, cx);
+                }
+            })
+            .detach();
+        }
+    }
+
+    fn on_toolchain_activated(
+        workspace: &mut Workspace,
+        path: &ProjectPath,
+        toolchain: &Toolchain,
+        cx: &mut Context<Workspace>,
+    ) {
+        cx.emit(workspace::Event::ToolchainChanged {
+            path: path.clone(),
+            toolchain: toolchain.clone(),
+        });
+
+        let hooks = ToolchainSettings::get_global(cx).on_change;
+        let project = workspace.project().clone();
+
+        if hooks.restart_language_servers {
+            let buffers = project
+                .read(cx)
+                .opened_buffers(cx)
+                .into_iter()
+                .filter(|buffer| {
+                    let buffer = buffer.read(cx);
+                    buffer.file().map_or(false, |file| {
+                        file.worktree_id(cx) == path.worktree_id
+                            && file.path().starts_with(&path.path)
+                    }) && buffer
+                        .language()
+                        .map_or(false, |language| language.name() == toolchain.language_name)
+                })
+                .collect::<Vec<_>>();
+            if !buffers.is_empty() {
+                project.update(cx, |project, cx| {
+                    project.restart_language_servers_for_buffers(buffers, cx)
+                });
+            }
+        }
+
+        if hooks.reload_environment {
+            if let Some(worktree) = project.read(cx).worktree_for_id(path.worktree_id, cx) {
+                let abs_path = worktree.read(cx).abs_path().join(&path.path);
+                project.read(cx).environment().update(cx, |environment, _| {
+                    environment.invalidate_environments(&abs_path)
+                });
+            }
+        }
+
+        if hooks.notify_terminals {
+            let has_terminals = workspace.items_of_type::<TerminalView>(cx).next().is_some()
+                || workspace
+                    .panel::<TerminalPanel>(cx)
+                    .map_or(false, |panel| !panel.read(cx).terminals(cx).is_empty());
+            if has_terminals {
+                workspace.show_toast(
+                    Toast::new(
+                        NotificationId::unique::<TerminalsUseStaleToolchain>(),
+                        format!(
+                            "Switched to {}. Open terminals keep the previous environment until reopened.",
+                            toolchain.name
+                        ),
+                    )
+                    .autohide(),
+                    cx,
+                );
+            }
+        }
     }
 
     fn toggle(
@@ -46,11 +137,29 @@ impl ToolchainSelector {
             .act_as::<Editor>(cx)?
             .read(cx)
             .active_excerpt(cx)?;
-        let project = workspace.project().clone();
-
         let language_name = buffer.read(cx).language()?.name();
         let worktree_id = buffer.read(cx).file()?.worktree_id(cx);
         let relative_path: Arc<Path> = Arc::from(buffer.read(cx).file()?.path().parent()?);
+        Self::toggle_for_path(
+            workspace,
+            worktree_id,
+            relative_path,
+            language_name,
+            window,
+            cx,
+        )
+    }
+
+    /// Opens the selector for an explicit location rather than the active editor's buffer.
+    pub fn toggle_for_path(
+        workspace: &mut Workspace,
+        worktree_id: WorktreeId,
+        relative_path: Arc<Path>,
+        language_name: LanguageName,
+        window: &mut Window,
+        cx: &mut Context<Workspace>,
+    ) -> Option<()> {
+        let project = workspace.project().clone();
         let worktree_root_path = project
             .read(cx)
             .worktree_for_id(worktree_id, cx)?
@@ -134,15 +243,35 @@ impl Focusable for ToolchainSelector {
 impl EventEmitter<DismissEvent> for ToolchainSelector {}
 impl ModalView for ToolchainSelector {}
 
+/// Rows listed after the scanned candidates, for toolchains the scan did not find.
+#[derive(Debug, Clone, PartialEq)]
+enum CustomEntry {
+    /// The query itself looks like a path to an interpreter or SDK.
+    TypedPath(PathBuf),
+    Browse,
+}
+
+/// Where a selection is recorded. Lookups fall back to the nearest ancestor with a recorded
+/// toolchain, so a worktree-wide selection applies to every package without its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ToolchainScope {
+    Package,
+    Worktree,
+}
+
 pub struct ToolchainSelectorDelegate {
     toolchain_selector: WeakEntity<ToolchainSelector>,
     candidates: ToolchainList,
     matches: Vec<StringMatch>,
+    custom_entries: Vec<CustomEntry>,
     selected_index: usize,
     workspace: WeakEntity<Workspace>,
+    project: Entity<Project>,
     worktree_id: WorktreeId,
     worktree_abs_path_root: Arc<Path>,
     relative_path: Arc<Path>,
+    scope: ToolchainScope,
+    language_name: LanguageName,
     placeholder_text: Arc<str>,
     _fetch_candidates_task: Task<Option<()>>,
 }
@@ -162,6 +291,7 @@ impl ToolchainSelectorDelegate {
     ) -> Self {
         let _fetch_candidates_task = cx.spawn_in(window, {
             let project = project.clone();
+            let language_name = language_name.clone();
             async move |this, cx| {
                 let term = project
                     .read_with(cx, |this, _| {
@@ -206,6 +336,18 @@ impl ToolchainSelectorDelegate {
                     this.delegate.candidates = available_toolchains;
 
                     if let Some(active_toolchain) = active_toolchain {
+                        // Custom toolchains are never found by scanning, keep them selectable.
+                        if !this
+                            .delegate
+                            .candidates
+                            .toolchains
+                            .contains(&active_toolchain)
+                        {
+                            this.delegate
+                                .candidates
+                                .toolchains
+                                .insert(0, active_toolchain.clone());
+                        }
                         if let Some(position) = this
                             .delegate
                             .candidates
@@ -227,15 +369,37 @@ impl ToolchainSelectorDelegate {
             toolchain_selector,
             candidates: Default::default(),
             matches: vec![],
+            custom_entries: vec![CustomEntry::Browse],
             selected_index: 0,
             workspace,
+            project,
             worktree_id,
             worktree_abs_path_root,
             placeholder_text,
             relative_path,
+            scope: ToolchainScope::Package,
+            language_name,
             _fetch_candidates_task,
         }
     }
+
+    /// Interprets the query as a toolchain path, if it looks like one. Relative paths are
+    /// resolved against the worktree root.
+    fn custom_toolchain_path(query: &str, worktree_root: &Path) -> Option<PathBuf> {
+        let query = query.trim();
+        if let Some(rest) = query.strip_prefix("~/") {
+            return Some(util::paths::home_dir().join(rest));
+        }
+        let path = Path::new(query);
+        if path.is_absolute() {
+            Some(path.to_path_buf())
+        } else if query.starts_with('.') || query.contains(std::path::MAIN_SEPARATOR) {
+            Some(worktree_root.join(path))
+        } else {
+            None
+        }
+    }
+
     fn relativize_path(path: SharedString, worktree_root: &Path) -> SharedString {
         Path::new(&path.as_ref())
             .strip_prefix(&worktree_root)
@@ -244,6 +408,131 @@ impl ToolchainSelectorDelegate {
             .and_then(|path| path.to_str().map(String::from).map(SharedString::from))
             .unwrap_or(path)
     }
+
+    fn activation_target(&self, cx: &App) -> ActivationTarget {
+        // Remote projects resolve the path on the host, where we can't check it from here.
+        let fs = self
+            .project
+            .read(cx)
+            .is_local()
+            .then(|| self.project.read(cx).fs().clone());
+        let relative_path = match self.scope {
+            ToolchainScope::Package => self.relative_path.clone(),
+            ToolchainScope::Worktree => Arc::from(Path::new("")),
+        };
+        ActivationTarget {
+            workspace: self.workspace.clone(),
+            worktree_id: self.worktree_id,
+            relative_path,
+            language_name: self.language_name.clone(),
+            fs,
+        }
+    }
+
+    fn browse_for_custom(&self, window: &mut Window, cx: &mut Context<Picker<Self>>) {
+        let target = self.activation_target(cx);
+        let lister = DirectoryLister::Project(self.project.clone());
+        // Deferred so that the path prompt isn't closed along with this modal.
+        window.defer(cx, move |window, cx| {
+            let Some(paths) = target
+                .workspace
+                .update(cx, |workspace, cx| {
+                    workspace.prompt_for_open_path(
+                        PathPromptOptions {
+                            files: true,
+                            directories: true,
+                            multiple: false,
+                        },
+                        lister,
+                        window,
+                        cx,
+                    )
+                })
+                .ok()
+            else {
+                return;
+            };
+            cx.spawn(async move |cx| {
+                let path = paths.await.ok()??.into_iter().next()?;
+                target.activate_custom(path, cx).await
+            })
+            .detach();
+        });
+    }
+}
+
+/// Everything needed to persist and activate a toolchain once the picker is gone.
+struct ActivationTarget {
+    workspace: WeakEntity<Workspace>,
+    worktree_id: WorktreeId,
+    relative_path: Arc<Path>,
+    language_name: LanguageName,
+    fs: Option<Arc<dyn Fs>>,
+}
+
+impl ActivationTarget {
+    async fn activate(self, toolchain: Toolchain, cx: &mut AsyncApp) -> Option<()> {
+        let workspace_id = self
+            .workspace
+            .read_with(cx, |this, _| this.database_id())
+            .ok()??;
+        let worktree_id = self.worktree_id;
+        workspace::WORKSPACE_DB
+            .set_toolchain(
+                workspace_id,
+                worktree_id,
+                self.relative_path.to_string_lossy().into_owned(),
+                toolchain.clone(),
+            )
+            .await
+            .log_err();
+        let path = self.relative_path;
+        self.workspace
+            .update(cx, |this, cx| {
+                this.project().update(cx, |this, cx| {
+                    this.activate_toolchain(ProjectPath { worktree_id, path }, toolchain, cx)
+                })
+            })
+            .ok()?
+            .await
+    }
+
+    /// Validates a user-provided toolchain path before activating it, reporting failures as a toast.
+    async fn activate_custom(self, path: PathBuf, cx: &mut AsyncApp) -> Option<()> {
+        if let Some(fs) = &self.fs {
+            let error = match fs.metadata(&path).await {
+                Ok(Some(_)) => None,
+                Ok(None) => Some(format!("No toolchain found at {}", path.display())),
+                Err(error) => Some(format!("Failed to read {}: {error}", path.display())),
+            };
+            if let Some(error) = error {
+                self.workspace
+                    .update(cx, |workspace, cx| {
+                        workspace.show_toast(
+                            Toast::new(NotificationId::unique::<InvalidCustomToolchain>(), error),
+                            cx,
+                        )
+                    })
+                    .ok();
+                return None;
+            }
+        }
+        let toolchain = self.custom_toolchain(&path);
+        self.activate(toolchain, cx).await
+    }
+
+    fn custom_toolchain(&self, path: &Path) -> Toolchain {
+        let file_name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| path.to_string_lossy().into_owned());
+        Toolchain {
+            name: format!("{file_name} (custom)").into(),
+            path: path.to_string_lossy().into_owned().into(),
+            language_name: self.language_name.clone(),
+            as_json: serde_json::json!({ "custom": true }),
+        }
+    }
 }
 
 impl PickerDelegate for ToolchainSelectorDelegate {
@@ -254,42 +543,28 @@ impl PickerDelegate for ToolchainSelectorDelegate {
     }
 
     fn match_count(&self) -> usize {
-        self.matches.len()
+        self.matches.len() + self.custom_entries.len()
     }
 
     fn confirm(&mut self, _: bool, window: &mut Window, cx: &mut Context<Picker<Self>>) {
         if let Some(string_match) = self.matches.get(self.selected_index) {
             let toolchain = self.candidates.toolchains[string_match.candidate_id].clone();
-            if let Some(workspace_id) = self
-                .workspace
-                .read_with(cx, |this, _| this.database_id())
-                .ok()
-                .flatten()
-            {
-                let workspace = self.workspace.clone();
-                let worktree_id = self.worktree_id;
-                let path = self.relative_path.clone();
-                let relative_path = self.relative_path.to_string_lossy().into_owned();
-                cx.spawn_in(window, async move |_, cx| {
-                    workspace::WORKSPACE_DB
-                        .set_toolchain(workspace_id, worktree_id, relative_path, toolchain.clone())
-                        .await
-                        .log_err();
-                    workspace
-                        .update(cx, |this, cx| {
-                            this.project().update(cx, |this, cx| {
-                                this.activate_toolchain(
-                                    ProjectPath { worktree_id, path },
-                                    toolchain,
-                                    cx,
-                                )
-                            })
-                        })
-                        .ok()?
-                        .await;
-                    Some(())
-                })
+            let target = self.activation_target(cx);
+            cx.spawn(async move |_, cx| target.activate(toolchain, cx).await)
                 .detach();
+        } else {
+            match self
+                .custom_entries
+                .get(self.selected_index - self.matches.len())
+                .cloned()
+            {
+                Some(CustomEntry::TypedPath(path)) => {
+                    let target = self.activation_target(cx);
+                    cx.spawn(async move |_, cx| target.activate_custom(path, cx).await)
+                        .detach();
+                }
+                Some(CustomEntry::Browse) => self.browse_for_custom(window, cx),
+                None => {}
             }
         }
         self.dismissed(window, cx);
@@ -323,6 +598,11 @@ impl PickerDelegate for ToolchainSelectorDelegate {
         let background = cx.background_executor().clone();
         let candidates = self.candidates.clone();
         let worktree_root_path = self.worktree_abs_path_root.clone();
+        let mut custom_entries = Vec::new();
+        if let Some(path) = Self::custom_toolchain_path(&query, &worktree_root_path) {
+            custom_entries.push(CustomEntry::TypedPath(path));
+        }
+        custom_entries.push(CustomEntry::Browse);
         cx.spawn_in(window, async move |this, cx| {
             let matches = if query.is_empty() {
                 candidates
@@ -365,9 +645,10 @@ impl PickerDelegate for ToolchainSelectorDelegate {
             this.update(cx, |this, cx| {
                 let delegate = &mut this.delegate;
                 delegate.matches = matches;
+                delegate.custom_entries = custom_entries;
                 delegate.selected_index = delegate
                     .selected_index
-                    .min(delegate.matches.len().saturating_sub(1));
+                    .min(delegate.match_count().saturating_sub(1));
                 cx.notify();
             })
             .log_err();
@@ -381,7 +662,23 @@ impl PickerDelegate for ToolchainSelectorDelegate {
         _window: &mut Window,
         _: &mut Context<Picker<Self>>,
     ) -> Option<Self::ListItem> {
-        let mat = &self.matches[ix];
+        let Some(mat) = self.matches.get(ix) else {
+            let (icon, label) = match self.custom_entries.get(ix - self.matches.len())? {
+                CustomEntry::TypedPath(path) => (
+                    IconName::Plus,
+                    format!("Use custom toolchain at {}", path.display()),
+                ),
+                CustomEntry::Browse => (IconName::FolderOpen, "Add custom toolchain…".to_string()),
+            };
+            return Some(
+                ListItem::new(ix)
+                    .inset(true)
+                    .spacing(ListItemSpacing::Sparse)
+                    .toggle_state(selected)
+                    .start_slot(Icon::new(icon).color(Color::Muted))
+                    .child(Label::new(label)),
+            );
+        };
         let toolchain = &self.candidates.toolchains[mat.candidate_id];
 
         let label = toolchain.name.clone();
@@ -407,4 +704,69 @@ impl PickerDelegate for ToolchainSelectorDelegate {
                 ),
         )
     }
+    fn render_footer(
+        &self,
+        _: &mut Window,
+        cx: &mut Context<Picker<Self>>,
+    ) -> Option<gpui::AnyElement> {
+        // At the worktree root both scopes are the same.
+        if self.relative_path.as_os_str().is_empty() {
+            return None;
+        }
+        let scope_button = |id: &'static str, label: String, scope: ToolchainScope| {
+            Button::new(id, label)
+                .label_size(LabelSize::Small)
+                .toggle_state(self.scope == scope)
+                .on_click(cx.listener(move |this, _, _, cx| {
+                    this.delegate.scope = scope;
+                    cx.notify();
+                }))
+        };
+        Some(
+            h_flex()
+                .p_2()
+                .w_full()
+                .gap_1()
+                .border_t_1()
+                .border_color(cx.theme().colors().border_variant)
+                .child(
+                    Label::new("Apply to:")
+                        .size(LabelSize::Small)
+                        .color(Color::Muted),
+                )
+                .child(scope_button(
+                    "scope-package",
+                    format!("`{}`", self.relative_path.display()),
+                    ToolchainScope::Package,
+                ))
+                .child(scope_button(
+                    "scope-worktree",
+                    "Whole Worktree".to_string(),
+                    ToolchainScope::Worktree,
+                ))
+                .into_any_element(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_custom_toolchain_path() {
+        let root = Path::new("/project");
+        assert_eq!(
+            ToolchainSelectorDelegate::custom_toolchain_path("/usr/bin/python3", root),
+            Some(PathBuf::from("/usr/bin/python3"))
+        );
+        assert_eq!(
+            ToolchainSelectorDelegate::custom_toolchain_path("./.venv/bin/python", root),
+            Some(PathBuf::from("/project/./.venv/bin/python"))
+        );
+        assert_eq!(
+            ToolchainSelectorDelegate::custom_toolchain_path("python 3.11", root),
+            None
+        );
+    }
 }
@@ -0,0 +1,56 @@
+use gpui::App;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use settings::{Settings, SettingsSources};
+
+/// The settings for toolchain selection.
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema, Default)]
+pub struct ToolchainSettings {
+    /// What to do once the active toolchain changes, be it from a user selection or an automatic pick.
+    #[serde(default)]
+    pub on_change: ToolchainChangeHooks,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct ToolchainChangeHooks {
+    /// Whether to restart the language servers of open buffers affected by the change.
+    ///
+    /// Default: false
+    pub restart_language_servers: bool,
+    /// Whether to resolve the project environment of the affected worktree again.
+    ///
+    /// Default: true
+    pub reload_environment: bool,
+    /// Whether to notify about open terminals, which keep the environment they were spawned with.
+    ///
+    /// Default: true
+    pub notify_terminals: bool,
+}
+
+impl Default for ToolchainChangeHooks {
+    fn default() -> Self {
+        Self {
+            restart_language_servers: false,
+            reload_environment: true,
+            notify_terminals: true,
+        }
+    }
+}
+
+impl Settings for ToolchainSettings {
+    const KEY: Option<&'static str> = Some("toolchain");
+
+    type FileContent = Self;
+
+    fn load(sources: SettingsSources<Self::FileContent>, _: &mut App) -> anyhow::Result<Self> {
+        SettingsSources::<Self::FileContent>::json_merge_with(
+            [sources.default]
+                .into_iter()
+                .chain(sources.user)
+                .chain(sources.server),
+        )
+    }
+
+    fn import_from_vscode(_vscode: &settings::VsCodeSettings, _current: &mut Self::FileContent) {}
+}
@@ -542,6 +542,22 @@ define_connection! {
         ALTER TABLE breakpoints ADD COLUMN condition TEXT;
         ALTER TABLE breakpoints ADD COLUMN hit_condition TEXT;
     ),
+    sql!(
+        CREATE TABLE toolchains2 (
+            workspace_id INTEGER,
+            worktree_id INTEGER,
+            relative_worktree_path TEXT NOT NULL DEFAULT "",
+            language_name TEXT NOT NULL,
+            name TEXT NOT NULL,
+            path TEXT NOT NULL,
+            raw_json TEXT DEFAULT "{}",
+            PRIMARY KEY (workspace_id, worktree_id, relative_worktree_path, language_name)
+        );
+        INSERT INTO toolchains2 (workspace_id, worktree_id, relative_worktree_path, language_name, name, path, raw_json)
+            SELECT workspace_id, worktree_id, relative_worktree_path, language_name, name, path, raw_json FROM toolchains;
+        DROP TABLE toolchains;
+        ALTER TABLE toolchains2 RENAME TO toolchains;
+    ),
     ];
 }
 
@@ -1336,6 +1352,8 @@ impl WorkspaceDb {
         }
     }
 
+    /// Returns the toolchain recorded for `relative_path` or, failing that, for its nearest
+    /// ancestor, so that a selection made for a package or the worktree root is inherited.
     pub async fn toolchain(
         &self,
         workspace_id: WorkspaceId,
@@ -1346,18 +1364,23 @@ impl WorkspaceDb {
         self.write(move |this| {
             let mut select = this
                 .select_bound(sql!(
-                    SELECT name, path, raw_json FROM toolchains WHERE workspace_id = ? AND language_name = ? AND worktree_id = ? AND relative_path = ?
+                    SELECT relative_worktree_path, name, path, raw_json FROM toolchains WHERE workspace_id = ? AND language_name = ? AND worktree_id = ?
                 ))
                 .context("Preparing insertion")?;
 
-            let toolchain: Vec<(String, String, String)> =
-                select((workspace_id, language_name.as_ref().to_string(), worktree_id.to_usize(), relative_path))?;
+            let toolchains: Vec<(String, String, String, String)> =
+                select((workspace_id, language_name.as_ref().to_string(), worktree_id.to_usize()))?;
 
-            Ok(toolchain.into_iter().next().and_then(|(name, path, raw_json)| Some(Toolchain {
-                name: name.into(),
-                path: path.into(),
+            let nearest = Path::new(&relative_path).ancestors().find_map(|ancestor| {
+                toolchains
+                    .iter()
+                    .find(|(recorded_path, ..)| Path::new(recorded_path) == ancestor)
+            });
+            Ok(nearest.and_then(|(_, name, path, raw_json)| Some(Toolchain {
+                name: name.clone().into(),
+                path: path.clone().into(),
                 language_name,
-                as_json: serde_json::Value::from_str(&raw_json).ok()?
+                as_json: serde_json::Value::from_str(raw_json).ok()?
             })))
         })
         .await
@@ -1400,11 +1423,12 @@ impl WorkspaceDb {
         self.write(move |conn| {
             let mut insert = conn
                 .exec_bound(sql!(
-                    INSERT INTO toolchains(workspace_id, worktree_id, relative_worktree_path, language_name, name, path) VALUES (?, ?, ?, ?, ?,  ?)
+                    INSERT INTO toolchains(workspace_id, worktree_id, relative_worktree_path, language_name, name, path, raw_json) VALUES (?, ?, ?, ?, ?, ?, ?)
                     ON CONFLICT DO
                     UPDATE SET
                         name = ?5,
-                        path = ?6
+                        path = ?6,
+                        raw_json = ?7
 
                 ))
                 .context("Preparing insertion")?;
@@ -1416,6 +1440,7 @@ impl WorkspaceDb {
                 toolchain.language_name.as_ref(),
                 toolchain.name.as_ref(),
                 toolchain.path.as_ref(),
+                toolchain.as_json.to_string(),
             ))?;
 
             Ok(())
@@ -1736,6 +1761,41 @@ mod tests {
         assert!(empty_breakpoints.is_none());
     }
 
+    #[gpui::test]
+    async fn test_toolchain_inherited_from_nearest_ancestor() {
+        zlog::init_test();
+
+        let db = WorkspaceDb::open_test_db("test_toolchain_inherited_from_nearest_ancestor").await;
+        let id = db.next_id().await.unwrap();
+        let worktree_id = WorktreeId::from_usize(1);
+        let language_name = LanguageName::new("Python");
+        let toolchain = |name: &str| Toolchain {
+            name: name.to_string().into(),
+            path: format!("/envs/{name}/bin/python").into(),
+            language_name: language_name.clone(),
+            as_json: serde_json::json!({ "name": name }),
+        };
+
+        db.set_toolchain(id, worktree_id, "".into(), toolchain("root"))
+            .await
+            .unwrap();
+        db.set_toolchain(id, worktree_id, "packages/a".into(), toolchain("a"))
+            .await
+            .unwrap();
+
+        let resolve =
+            |path: &str| db.toolchain(id, worktree_id, path.into(), language_name.clone());
+        assert_eq!(
+            resolve("packages/a/src").await.unwrap(),
+            Some(toolchain("a"))
+        );
+        assert_eq!(
+            resolve("packages/b").await.unwrap(),
+            Some(toolchain("root"))
+        );
+        assert_eq!(resolve("").await.unwrap(), Some(toolchain("root")));
+    }
+
     #[gpui::test]
     async fn test_next_id_stability() {
         zlog::init_test();
@@ -48,7 +48,7 @@ pub use item::{
     ProjectItem, SerializableItem, SerializableItemHandle, WeakItemHandle,
 };
 use itertools::Itertools;
-use language::{Buffer, LanguageRegistry, Rope};
+use language::{Buffer, LanguageRegistry, Rope, Toolchain};
 pub use modal_layer::*;
 use node_runtime::NodeRuntime;
 use notifications::{
@@ -886,6 +886,10 @@ pub enum Event {
     ZoomChanged,
     ModalOpened,
     ClearActivityIndicator,
+    ToolchainChanged {
+        path: ProjectPath,
+        toolchain: Toolchain,
+    },
 }
 
 #[derive(Debug)]
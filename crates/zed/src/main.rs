@@ -52,7 +52,7 @@ use zed::{
     OpenListener, OpenRequest, app_menus, build_window_options, derive_paths_with_position,
     handle_cli_connection, handle_keymap_file_changes, handle_settings_changed,
     handle_settings_file_changes, initialize_workspace, inline_completion_registry,
-    open_paths_with_positions,
+    open_paths_with_positions, show_message_handler_config_diagnostics,
 };
 
 #[cfg(feature = "mimalloc")]
@@ -523,6 +523,7 @@ Error: Running Zed as root or via sudo is unsupported.
             app_state.fs.clone(),
             cx,
         );
+        show_message_handler_config_diagnostics(cx);
         web_search::init(cx);
         web_search_providers::init(app_state.client.clone(), cx);
         snippet_provider::init(cx);
@@ -579,6 +580,7 @@ Error: Running Zed as root or via sudo is unsupported.
         journal::init(app_state.clone(), cx);
         language_selector::init(cx);
         toolchain_selector::init(cx);
+        cdc_agents::init(cx);
         theme_selector::init(cx);
         language_tools::init(cx);
         call::init(app_state.client.clone(), app_state.user_store.clone(), cx);
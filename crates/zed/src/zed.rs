@@ -230,6 +230,7 @@ pub fn initialize_workspace(
             window,
             cx,
         );
+        let store_health_indicator = cx.new(agent::StoreHealthIndicator::new);
         let active_buffer_language =
             cx.new(|_| language_selector::ActiveBufferLanguage::new(workspace));
         let active_toolchain_language =
@@ -242,6 +243,7 @@ pub fn initialize_workspace(
             status_bar.add_left_item(search_button, window, cx);
             status_bar.add_left_item(diagnostic_summary, window, cx);
             status_bar.add_left_item(activity_indicator, window, cx);
+            status_bar.add_left_item(store_health_indicator, window, cx);
             status_bar.add_right_item(inline_completion_button, window, cx);
             status_bar.add_right_item(active_buffer_language, window, cx);
             status_bar.add_right_item(active_toolchain_language, window, cx);
@@ -406,6 +408,15 @@ fn initialize_panels(
         )?;
 
         workspace_handle.update_in(cx, |workspace, window, cx| {
+            if let Some(active_toolchain) = workspace
+                .status_bar()
+                .read(cx)
+                .item_of_type::<toolchain_selector::ActiveToolchain>()
+            {
+                active_toolchain.update(cx, |active_toolchain, cx| {
+                    active_toolchain.observe_project_panel(&project_panel, window, cx)
+                });
+            }
             workspace.add_panel(project_panel, window, cx);
             workspace.add_panel(outline_panel, window, cx);
             workspace.add_panel(terminal_panel, window, cx);
@@ -1451,6 +1462,31 @@ pub fn handle_settings_changed(error: Option<anyhow::Error>, cx: &mut App) {
     }
 }
 
+/// Shows what was wrong with the message handler's configuration, since otherwise conversations
+/// silently go unstored.
+pub fn show_message_handler_config_diagnostics(cx: &mut App) {
+    struct MessageHandlerConfigNotification;
+    let diagnostics = language_model::message_handler::config_diagnostics(cx);
+    if diagnostics.is_empty() {
+        return;
+    }
+    let message = diagnostics
+        .iter()
+        .map(|diagnostic| diagnostic.to_string())
+        .collect::<Vec<_>>()
+        .join("\n");
+    show_app_notification(
+        NotificationId::unique::<MessageHandlerConfigNotification>(),
+        cx,
+        move |cx| {
+            cx.new(|cx| {
+                MessageNotification::new(message.clone(), cx)
+                    .with_title("Conversations aren't stored as configured")
+            })
+        },
+    );
+}
+
 pub fn open_new_ssh_project_from_project(
     workspace: &mut Workspace,
     paths: Vec<PathBuf>,
@@ -562,6 +562,7 @@ mod tests {
                 port_forwards: None,
                 nickname: None,
                 upload_binary_over_ssh: false,
+                persist_messages_via_host: false,
             }
         );
         assert_eq!(request.open_paths, vec!["/"]);
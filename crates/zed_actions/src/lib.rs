@@ -197,11 +197,46 @@ pub mod icon_theme_selector {
 }
 
 pub mod agent {
-    use gpui::actions;
+    use gpui::{actions, impl_actions};
+    use schemars::JsonSchema;
+    use serde::Deserialize;
 
     actions!(
         agent,
-        [OpenConfiguration, OpenOnboardingModal, ResetOnboarding]
+        [
+            CompareModels,
+            OpenConfiguration,
+            OpenOnboardingModal,
+            OpenRunInbox,
+            ResetOnboarding
+        ]
+    );
+
+    /// Opens the agent thread whose session the commit was made during.
+    #[derive(PartialEq, Clone, Default, Debug, Deserialize, JsonSchema)]
+    #[serde(deny_unknown_fields)]
+    pub struct OpenCommitThread {
+        pub sha: String,
+    }
+
+    impl_actions!(agent, [OpenCommitThread]);
+}
+
+pub mod llm_store {
+    use gpui::actions;
+
+    actions!(
+        llm_store,
+        [
+            ExportCurrentThread,
+            LinkCurrentThreadToIssue,
+            LintStoredThreads,
+            OpenHistory,
+            ResolveCurrentThread,
+            ShowStoreDiagnostics,
+            ShowUsage,
+            TogglePersistence
+        ]
     );
 }
 
@@ -0,0 +1,312 @@
+//! Multi-container agent stacks (an agent plus the services it needs, e.g. Postgres and a message
+//! broker) declared in a TOML manifest and brought up in dependency order.
+
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{Context as _, Result, anyhow};
+use collections::{BTreeMap, HashMap, HashSet};
+use fs::Fs;
+use serde::Deserialize;
+
+use crate::{ContainerSpec, HealthCheck, Lifecycle, Mount};
+
+/// Location of a workspace-specific manifest, relative to a worktree root.
+pub const WORKSPACE_STACK_MANIFEST: &str = ".zed/agent_stack.toml";
+
+/// The stack used when the workspace doesn't declare its own.
+pub const DEFAULT_STACK_MANIFEST: &str = include_str!("../agent_stack.toml");
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct AgentStack {
+    pub name: String,
+    #[serde(default)]
+    pub services: BTreeMap<String, ServiceManifest>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct ServiceManifest {
+    pub image: String,
+    /// Pins the image to a digest, e.g. `sha256:...`, verified when it is pulled.
+    #[serde(default)]
+    pub digest: Option<String>,
+    #[serde(default)]
+    pub command: Option<Vec<String>>,
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    #[serde(default)]
+    pub ports: Vec<PortManifest>,
+    #[serde(default)]
+    pub mounts: Vec<MountManifest>,
+    #[serde(default)]
+    pub depends_on: Vec<String>,
+    /// Readiness probe run by Docker inside the container, e.g. `["CMD-SHELL", "pg_isready"]`.
+    #[serde(default)]
+    pub health_check: Option<Vec<String>>,
+    /// Seconds to wait for the service to become ready.
+    #[serde(default)]
+    pub startup_timeout: Option<u64>,
+    /// Memory limit in MiB.
+    #[serde(default)]
+    pub memory_limit_mb: Option<u64>,
+    /// CPU limit, in cores.
+    #[serde(default)]
+    pub cpus: Option<f64>,
+    /// Marks the Postgres service the editor stores conversations in once the stack is up.
+    #[serde(default)]
+    pub message_store: bool,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+pub struct PortManifest {
+    pub container: u16,
+    pub host: u16,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct MountManifest {
+    /// Relative paths are resolved against the worktree the stack is loaded for.
+    pub host: PathBuf,
+    pub container: String,
+    #[serde(default)]
+    pub read_write: bool,
+}
+
+impl MountManifest {
+    fn to_mount(&self) -> Mount {
+        if self.read_write {
+            Mount::read_write(&self.host, &self.container)
+        } else {
+            Mount::read_only(&self.host, &self.container)
+        }
+    }
+}
+
+impl AgentStack {
+    pub fn parse(manifest: &str) -> Result<Self> {
+        let stack: Self = toml::from_str(manifest).context("invalid agent stack manifest")?;
+        stack.start_order()?;
+        Ok(stack)
+    }
+
+    fn resolve_mounts(&mut self, worktree_root: Option<&Path>) -> Result<()> {
+        for (name, service) in &mut self.services {
+            for mount in &mut service.mounts {
+                if mount.host.is_relative() {
+                    let root = worktree_root.with_context(|| {
+                        format!("service `{name}` mounts a relative path, but there is no worktree")
+                    })?;
+                    mount.host = root.join(&mount.host);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Everything the stack's services mount, for the user to approve before starting it.
+    pub fn mounts(&self) -> Vec<Mount> {
+        self.services
+            .values()
+            .flat_map(|service| service.mounts.iter().map(MountManifest::to_mount))
+            .collect()
+    }
+
+    /// The container of the service marked as the message store, if any.
+    pub fn message_store_container(&self) -> Option<String> {
+        self.services
+            .iter()
+            .find(|(_, service)| service.message_store)
+            .map(|(name, _)| self.container_name(name))
+    }
+
+    pub fn lifecycle(&self) -> Lifecycle {
+        Lifecycle::named(format!("stack-{}", self.name))
+    }
+
+    pub fn container_name(&self, service: &str) -> String {
+        format!("{}-{service}", self.name)
+    }
+
+    /// The network shared by the stack's containers, on which services reach each other by name.
+    pub fn network_name(&self) -> String {
+        format!("{}-network", self.name)
+    }
+
+    /// Services ordered so that each comes after everything it depends on.
+    pub fn start_order(&self) -> Result<Vec<String>> {
+        // A service listing a dependency twice still waits for it once.
+        let mut dependencies = BTreeMap::default();
+        for (name, service) in &self.services {
+            for dependency in &service.depends_on {
+                if !self.services.contains_key(dependency) {
+                    return Err(anyhow!(
+                        "service `{name}` depends on unknown service `{dependency}`"
+                    ));
+                }
+            }
+            let service_dependencies = service
+                .depends_on
+                .iter()
+                .map(String::as_str)
+                .collect::<HashSet<_>>();
+            dependencies.insert(name.as_str(), service_dependencies);
+        }
+        let mut remaining_dependencies = dependencies
+            .iter()
+            .map(|(name, dependencies)| (*name, dependencies.len()))
+            .collect::<BTreeMap<_, _>>();
+
+        let mut order = Vec::with_capacity(self.services.len());
+        let mut ready = remaining_dependencies
+            .iter()
+            .filter(|(_, count)| **count == 0)
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>();
+        while let Some(name) = ready.pop() {
+            order.push(name.to_string());
+            for (dependent, dependencies) in &dependencies {
+                if dependencies.contains(name) {
+                    if let Some(count) = remaining_dependencies.get_mut(dependent) {
+                        *count -= 1;
+                        if *count == 0 {
+                            ready.push(*dependent);
+                        }
+                    }
+                }
+            }
+        }
+
+        if order.len() != self.services.len() {
+            let cyclic = self
+                .services
+                .keys()
+                .filter(|name| !order.contains(*name))
+                .cloned()
+                .collect::<Vec<_>>();
+            return Err(anyhow!(
+                "dependency cycle between services: {}",
+                cyclic.join(", ")
+            ));
+        }
+        Ok(order)
+    }
+
+    pub fn container_spec(&self, service_name: &str) -> Option<ContainerSpec> {
+        let service = self.services.get(service_name)?;
+        let mut spec = ContainerSpec::new(self.container_name(service_name), &service.image);
+        spec.image_digest = service.digest.clone();
+        spec.cmd = service.command.clone();
+        spec.env = service
+            .env
+            .iter()
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        spec.ports = service
+            .ports
+            .iter()
+            .map(|port| (port.container, port.host))
+            .collect();
+        spec.mounts = service.mounts.iter().map(MountManifest::to_mount).collect();
+        spec.health_check = service.health_check.clone().map(HealthCheck::command);
+        spec.network = Some(self.network_name());
+        spec.network_aliases = vec![service_name.to_string()];
+        if let Some(timeout) = service.startup_timeout {
+            spec.startup_timeout = Duration::from_secs(timeout);
+        }
+        spec.memory_limit = service.memory_limit_mb.map(|mb| mb * 1024 * 1024);
+        spec.cpu_limit = service.cpus;
+        Some(spec)
+    }
+}
+
+/// Loads the stack declared by the first worktree that has one, falling back to the default.
+pub async fn load_stack(fs: &dyn Fs, worktree_roots: &[impl AsRef<Path>]) -> Result<AgentStack> {
+    for root in worktree_roots {
+        let path = root.as_ref().join(WORKSPACE_STACK_MANIFEST);
+        if fs.is_file(&path).await {
+            let manifest = fs.load(&path).await?;
+            let mut stack = AgentStack::parse(&manifest)
+                .with_context(|| format!("failed to load {}", path.display()))?;
+            stack.resolve_mounts(Some(root.as_ref()))?;
+            return Ok(stack);
+        }
+    }
+    let mut stack = AgentStack::parse(DEFAULT_STACK_MANIFEST)?;
+    stack.resolve_mounts(worktree_roots.first().map(AsRef::as_ref))?;
+    Ok(stack)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_stack_parses() {
+        let stack = AgentStack::parse(DEFAULT_STACK_MANIFEST).unwrap();
+        let order = stack.start_order().unwrap();
+        let position = |name: &str| order.iter().position(|service| service == name).unwrap();
+        assert!(position("postgres") < position("agent"));
+        assert!(position("broker") < position("agent"));
+    }
+
+    #[test]
+    fn test_relative_mounts_resolve_against_the_worktree() {
+        let mut stack = AgentStack::parse(DEFAULT_STACK_MANIFEST).unwrap();
+        stack
+            .resolve_mounts(Some(Path::new("/work/project")))
+            .unwrap();
+        assert_eq!(
+            stack.mounts(),
+            vec![Mount::read_only("/work/project", "/workspace")]
+        );
+
+        let mut stack = AgentStack::parse(DEFAULT_STACK_MANIFEST).unwrap();
+        assert!(stack.resolve_mounts(None).is_err());
+    }
+
+    #[test]
+    fn test_dependency_cycles_are_rejected() {
+        let manifest = r#"
+            name = "cyclic"
+
+            [services.a]
+            image = "a"
+            depends_on = ["b"]
+
+            [services.b]
+            image = "b"
+            depends_on = ["a"]
+        "#;
+        assert!(AgentStack::parse(manifest).is_err());
+    }
+
+    #[test]
+    fn test_repeated_dependencies_are_not_cycles() {
+        let manifest = r#"
+            name = "repeated"
+
+            [services.postgres]
+            image = "postgres"
+
+            [services.agent]
+            image = "agent"
+            depends_on = ["postgres", "postgres"]
+        "#;
+        let stack = AgentStack::parse(manifest).unwrap();
+        assert_eq!(stack.start_order().unwrap(), ["postgres", "agent"]);
+    }
+
+    #[test]
+    fn test_unknown_dependencies_are_rejected() {
+        let manifest = r#"
+            name = "dangling"
+
+            [services.agent]
+            image = "agent"
+            depends_on = ["postgres"]
+        "#;
+        assert!(AgentStack::parse(manifest).is_err());
+    }
+}
@@ -0,0 +1,157 @@
+//! Just enough of an ANSI escape sequence parser to color container logs: SGR foreground colors
+//! are kept, everything else (cursor movement, erasing, ...) is dropped.
+
+use std::ops::Range;
+
+const ESCAPE: char = '\u{1b}';
+
+/// One of the 16 standard terminal colors, 8..16 being the bright variants.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct AnsiColor(pub u8);
+
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct StyledText {
+    pub text: String,
+    pub colors: Vec<(Range<usize>, AnsiColor)>,
+}
+
+/// Parses a stream of chunks, carrying the current style and incomplete escape sequences from
+/// one chunk to the next.
+#[derive(Debug, Default)]
+pub(crate) struct AnsiParser {
+    foreground: Option<u8>,
+    bold: bool,
+    pending: String,
+}
+
+impl AnsiParser {
+    pub fn parse(&mut self, chunk: &str) -> StyledText {
+        let input = if self.pending.is_empty() {
+            chunk.to_string()
+        } else {
+            let mut input = std::mem::take(&mut self.pending);
+            input.push_str(chunk);
+            input
+        };
+
+        let mut output = StyledText::default();
+        let mut run_start = 0;
+        let mut chars = input.char_indices().peekable();
+        while let Some((ix, char)) = chars.next() {
+            match char {
+                ESCAPE => {
+                    if chars.peek().is_none() {
+                        self.pending = input[ix..].to_string();
+                        break;
+                    }
+                    if chars.next_if(|(_, char)| *char == '[').is_none() {
+                        // Not a CSI sequence, drop the escaped character as well.
+                        chars.next();
+                        continue;
+                    }
+                    let parameters_start = ix + 2;
+                    let Some((final_ix, final_char)) =
+                        chars.find(|(_, char)| ('\u{40}'..='\u{7e}').contains(char))
+                    else {
+                        self.pending = input[ix..].to_string();
+                        break;
+                    };
+                    if final_char == 'm' {
+                        let color = self.color();
+                        self.push_run(&mut output, run_start, color);
+                        self.apply_sgr(&input[parameters_start..final_ix]);
+                        run_start = output.text.len();
+                    }
+                }
+                '\r' => {}
+                _ => output.text.push(char),
+            }
+        }
+        let color = self.color();
+        self.push_run(&mut output, run_start, color);
+        output
+    }
+
+    fn color(&self) -> Option<AnsiColor> {
+        // Like most terminals, render bold text in the bright variant of its color.
+        self.foreground.map(|color| match color {
+            0..8 if self.bold => AnsiColor(color + 8),
+            _ => AnsiColor(color),
+        })
+    }
+
+    fn push_run(&self, output: &mut StyledText, start: usize, color: Option<AnsiColor>) {
+        if let Some(color) = color {
+            if start < output.text.len() {
+                output.colors.push((start..output.text.len(), color));
+            }
+        }
+    }
+
+    fn apply_sgr(&mut self, parameters: &str) {
+        let mut parameters = parameters
+            .split(';')
+            .map(|parameter| parameter.parse::<u16>().unwrap_or(0));
+        while let Some(parameter) = parameters.next() {
+            match parameter {
+                0 => {
+                    self.foreground = None;
+                    self.bold = false;
+                }
+                1 => self.bold = true,
+                22 => self.bold = false,
+                30..=37 => self.foreground = Some((parameter - 30) as u8),
+                39 => self.foreground = None,
+                90..=97 => self.foreground = Some((parameter - 90 + 8) as u8),
+                38 | 48 => match parameters.next() {
+                    Some(5) => {
+                        let color = parameters.next().unwrap_or(0);
+                        if parameter == 38 {
+                            // Colors outside the standard 16 have no theme counterpart.
+                            self.foreground = (color < 16).then_some(color as u8);
+                        }
+                    }
+                    Some(2) => {
+                        parameters.nth(2);
+                        if parameter == 38 {
+                            self.foreground = None;
+                        }
+                    }
+                    _ => {}
+                },
+                _ => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_colors_are_extracted() {
+        let mut parser = AnsiParser::default();
+        let output = parser.parse("plain \u{1b}[31merror\u{1b}[0m and \u{1b}[1;32mok\u{1b}[m\r\n");
+        assert_eq!(output.text, "plain error and ok\n");
+        assert_eq!(
+            output.colors,
+            vec![(6..11, AnsiColor(1)), (16..18, AnsiColor(10))]
+        );
+    }
+
+    #[test]
+    fn test_sequences_split_across_chunks() {
+        let mut parser = AnsiParser::default();
+        let first = parser.parse("a\u{1b}[3");
+        assert_eq!(first.text, "a");
+        let second = parser.parse("4mb");
+        assert_eq!(second.text, "b");
+        assert_eq!(second.colors, vec![(0..1, AnsiColor(4))]);
+
+        // The style carries over into the next chunk.
+        let third = parser.parse("c\u{1b}[2Kd");
+        assert_eq!(third.text, "cd");
+        assert_eq!(third.colors, vec![(0..2, AnsiColor(4))]);
+    }
+}
@@ -0,0 +1,262 @@
+//! Runs the containers backing CDC agents (agent runtimes and the services they depend on) next
+//! to the editor, through the local Docker daemon.
+
+mod agent_stack;
+mod ansi;
+mod container_exec_tool;
+mod container_logs;
+mod container_manager;
+mod container_panel;
+mod image_provisioner;
+mod message_store;
+mod mount_policy;
+mod port_allocator;
+
+use anyhow::{Context as _, Result, anyhow};
+use assistant_tool::ToolRegistry;
+use bollard::Docker;
+use gpui::{App, AppContext as _, Context, Task, Window, actions};
+use workspace::{Toast, Workspace, notifications::NotificationId};
+
+pub use agent_stack::{AgentStack, MountManifest, PortManifest, ServiceManifest, load_stack};
+pub use container_exec_tool::ContainerExecTool;
+pub use container_logs::ContainerLogView;
+pub use container_manager::{
+    ContainerManager, ContainerManagerEvent, ContainerSpec, ContainerStatus, HealthCheck,
+    Lifecycle, ManagedContainer,
+};
+pub use container_panel::{ContainerPanel, ToggleContainerPanel};
+pub use image_provisioner::{ImageProvisioner, ImagePullIndicator, PullProgress};
+pub use message_store::{
+    connect_message_store, connection_string, conversation_store_spec, start_conversation_store,
+};
+pub use mount_policy::{Mount, MountAccess, MountPolicy, approve_mounts};
+
+actions!(
+    cdc_agents,
+    [
+        OpenContainerLogs,
+        StartAgentStack,
+        StartConversationStore,
+        StopAgentStack
+    ]
+);
+
+pub fn init(cx: &mut App) {
+    let docker = match Docker::connect_with_local_defaults() {
+        Ok(docker) => docker,
+        Err(error) => {
+            log::info!("Docker is unavailable, agent containers are disabled: {error}");
+            return;
+        }
+    };
+    let manager = ContainerManager::init_global(docker, cx);
+    ToolRegistry::global(cx).register_tool(ContainerExecTool);
+
+    cx.observe_new(move |workspace: &mut Workspace, window, cx| {
+        if let Some(window) = window {
+            let images = manager.read(cx).images().clone();
+            let indicator = cx.new(|cx| ImagePullIndicator::new(images, cx));
+            workspace.status_bar().update(cx, |status_bar, cx| {
+                status_bar.add_left_item(indicator, window, cx)
+            });
+
+            let workspace_handle = cx.entity().downgrade();
+            let panel = cx.new(|cx| ContainerPanel::new(manager.clone(), workspace_handle, cx));
+            workspace.add_panel(panel, window, cx);
+        }
+
+        workspace.register_action(|workspace, _: &StartAgentStack, window, cx| {
+            run_stack_action(workspace, StackAction::Start, window, cx)
+        });
+        workspace.register_action(|workspace, _: &StopAgentStack, window, cx| {
+            run_stack_action(workspace, StackAction::Stop, window, cx)
+        });
+        workspace.register_action(|workspace, _: &StartConversationStore, _, cx| {
+            run_start_conversation_store(workspace, cx)
+        });
+        workspace.register_action(|workspace, _: &OpenContainerLogs, window, cx| {
+            show_container_logs(workspace, None, window, cx)
+        });
+        workspace.register_action(|workspace, _: &ToggleContainerPanel, window, cx| {
+            workspace.toggle_panel_focus::<ContainerPanel>(window, cx);
+        });
+
+        let lifecycle = Lifecycle::for_workspace(cx.entity_id());
+        let manager = manager.clone();
+        cx.on_release(move |_, cx| {
+            manager.update(cx, |manager, cx| {
+                manager.teardown(&lifecycle, cx).detach_and_log_err(cx)
+            })
+        })
+        .detach();
+    })
+    .detach();
+}
+
+/// Opens the container log view, or activates the existing one, optionally switching to the
+/// given container's tab.
+pub fn show_container_logs(
+    workspace: &mut Workspace,
+    container: Option<&str>,
+    window: &mut Window,
+    cx: &mut Context<Workspace>,
+) {
+    let view = if let Some(existing) = workspace.items_of_type::<ContainerLogView>(cx).next() {
+        workspace.activate_item(&existing, true, true, window, cx);
+        existing
+    } else {
+        let Some(manager) = ContainerManager::global(cx) else {
+            return;
+        };
+        let view = cx.new(|cx| ContainerLogView::new(manager, window, cx));
+        workspace.add_item_to_active_pane(Box::new(view.clone()), None, true, window, cx);
+        view
+    };
+    if let Some(container) = container {
+        view.update(cx, |view, cx| {
+            view.activate_container(container, window, cx)
+        });
+    }
+}
+
+struct AgentStackNotification;
+
+#[derive(Clone, Copy)]
+enum StackAction {
+    Start,
+    Stop,
+}
+
+fn run_stack_action(
+    workspace: &mut Workspace,
+    action: StackAction,
+    window: &mut Window,
+    cx: &mut Context<Workspace>,
+) {
+    let Some(manager) = ContainerManager::global(cx) else {
+        workspace.show_toast(
+            Toast::new(
+                NotificationId::unique::<AgentStackNotification>(),
+                "Docker is unavailable, agent stacks can't be managed.",
+            ),
+            cx,
+        );
+        return;
+    };
+    let project = workspace.project().read(cx);
+    let fs = project.fs().clone();
+    let worktree_roots = project
+        .visible_worktrees(cx)
+        .map(|worktree| worktree.read(cx).abs_path())
+        .collect::<Vec<_>>();
+    cx.spawn_in(window, async move |workspace, cx| {
+        let result = async {
+            let stack = load_stack(fs.as_ref(), &worktree_roots).await?;
+            if let StackAction::Start = action {
+                let worktree_roots = worktree_roots
+                    .iter()
+                    .map(|root| root.to_path_buf())
+                    .collect();
+                cx.update(|window, cx| {
+                    approve_mounts(
+                        manager.clone(),
+                        stack.lifecycle(),
+                        worktree_roots,
+                        stack.mounts(),
+                        window,
+                        cx,
+                    )
+                })?
+                .await?;
+            }
+            manager
+                .update(cx, |manager, cx| match action {
+                    StackAction::Start => manager.start_stack(stack.clone(), cx),
+                    StackAction::Stop => manager.stop_stack(&stack, cx),
+                })?
+                .await?;
+            if let (StackAction::Start, Some(name)) = (action, stack.message_store_container()) {
+                cx.update(|_, cx| {
+                    let container = manager.read(cx).container(&name).cloned();
+                    match container {
+                        Some(container) => connect_message_store(&container, cx),
+                        None => Task::ready(Err(anyhow!("{name} is not running"))),
+                    }
+                })?
+                .await
+                .context("failed to connect the conversation store")?;
+            }
+            anyhow::Ok(stack.name)
+        }
+        .await;
+        let message = match (action, result) {
+            (StackAction::Start, Ok(name)) => format!("Agent stack `{name}` is up."),
+            (StackAction::Stop, Ok(name)) => format!("Agent stack `{name}` was stopped."),
+            (_, Err(error)) => format!("Agent stack failed: {error:#}"),
+        };
+        workspace
+            .update(cx, |workspace, cx| {
+                workspace.show_toast(
+                    Toast::new(NotificationId::unique::<AgentStackNotification>(), message)
+                        .autohide(),
+                    cx,
+                )
+            })
+            .ok();
+    })
+    .detach();
+}
+
+struct ConversationStoreNotification;
+
+fn run_start_conversation_store(workspace: &mut Workspace, cx: &mut Context<Workspace>) {
+    let Some(manager) = ContainerManager::global(cx) else {
+        workspace.show_toast(
+            Toast::new(
+                NotificationId::unique::<ConversationStoreNotification>(),
+                "Docker is unavailable, the conversation store can't be started.",
+            ),
+            cx,
+        );
+        return;
+    };
+    let start = start_conversation_store(manager, cx);
+    cx.spawn(async move |workspace, cx| {
+        let message = match start.await {
+            Ok(()) => "Conversation store is up.".to_string(),
+            Err(error) => format!("Conversation store failed: {error:#}"),
+        };
+        workspace
+            .update(cx, |workspace, cx| {
+                workspace.show_toast(
+                    Toast::new(
+                        NotificationId::unique::<ConversationStoreNotification>(),
+                        message,
+                    )
+                    .autohide(),
+                    cx,
+                )
+            })
+            .ok();
+    })
+    .detach();
+}
+
+pub struct CdcAgentsExtension {
+    docker: Docker,
+}
+
+impl CdcAgentsExtension {
+    /// Connects to the local Docker daemon, failing if it doesn't respond.
+    pub async fn new() -> Result<Self> {
+        let docker =
+            Docker::connect_with_local_defaults().context("failed to connect to Docker")?;
+        docker.ping().await.context("Docker did not respond")?;
+        Ok(Self { docker })
+    }
+
+    pub fn docker(&self) -> &Docker {
+        &self.docker
+    }
+}
@@ -0,0 +1,351 @@
+//! An agent tool that runs commands inside a managed container, so that models can execute code
+//! against the project without it running on the user's machine.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{Context as _, Result, anyhow};
+use assistant_tool::{ActionLog, CommandOutput, Tool, ToolResult};
+use bollard::{
+    Docker,
+    container::LogOutput,
+    exec::{CreateExecOptions, StartExecResults},
+};
+use futures::StreamExt as _;
+use gpui::{AnyWindowHandle, App, Entity, Task};
+use gpui_tokio::Tokio;
+use language_model::{LanguageModel, LanguageModelRequest, LanguageModelToolSchemaFormat};
+use project::Project;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use ui::IconName;
+use util::markdown::MarkdownInlineCode;
+
+use crate::{ContainerManager, Mount};
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+const MAX_TIMEOUT: Duration = Duration::from_secs(600);
+
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ContainerExecToolInput {
+    /// Name of the agent container to run the command in.
+    container: String,
+    /// The one-liner command to execute.
+    command: String,
+    /// Working directory for the command, relative to one of the root directories of the
+    /// project. The directory must be mounted into the container. Defaults to the container's
+    /// own working directory.
+    #[serde(default)]
+    cd: Option<String>,
+    /// Seconds to wait for the command to finish. Defaults to 60, at most 600.
+    #[serde(default)]
+    timeout_secs: Option<u64>,
+}
+
+pub struct ContainerExecTool;
+
+impl ContainerExecTool {
+    pub const NAME: &str = "container_exec";
+}
+
+impl Tool for ContainerExecTool {
+    fn name(&self) -> String {
+        Self::NAME.to_string()
+    }
+
+    fn needs_confirmation(&self, _: &serde_json::Value, _: &App) -> bool {
+        true
+    }
+
+    // Worktrees may be mounted read-write.
+    fn may_perform_edits(&self) -> bool {
+        true
+    }
+
+    fn description(&self) -> String {
+        include_str!("./container_exec_tool/description.md").to_string()
+    }
+
+    fn icon(&self) -> IconName {
+        IconName::Terminal
+    }
+
+    fn input_schema(&self, format: LanguageModelToolSchemaFormat) -> Result<serde_json::Value> {
+        let mut schema = serde_json::to_value(schemars::schema_for!(ContainerExecToolInput))?;
+        assistant_tool::adapt_schema_to_format(&mut schema, format)?;
+        Ok(schema)
+    }
+
+    fn ui_text(&self, input: &serde_json::Value) -> String {
+        match serde_json::from_value::<ContainerExecToolInput>(input.clone()) {
+            Ok(input) => format!(
+                "Run {} in {}",
+                MarkdownInlineCode(&input.command),
+                MarkdownInlineCode(&input.container)
+            ),
+            Err(_) => "Run command in container".to_string(),
+        }
+    }
+
+    fn run(
+        self: Arc<Self>,
+        input: serde_json::Value,
+        _request: Arc<LanguageModelRequest>,
+        project: Entity<Project>,
+        _action_log: Entity<ActionLog>,
+        _model: Arc<dyn LanguageModel>,
+        _window: Option<AnyWindowHandle>,
+        cx: &mut App,
+    ) -> ToolResult {
+        let input: ContainerExecToolInput = match serde_json::from_value(input) {
+            Ok(input) => input,
+            Err(err) => return Task::ready(Err(anyhow!(err))).into(),
+        };
+        let Some(manager) = ContainerManager::global(cx) else {
+            return Task::ready(Err(anyhow!("Docker is unavailable"))).into();
+        };
+        let manager = manager.read(cx);
+        let Some(container) = manager
+            .container(&input.container)
+            .filter(|container| container.status.is_live())
+        else {
+            let running = manager
+                .containers()
+                .filter(|container| container.status.is_live())
+                .map(|container| container.spec.name.as_str())
+                .collect::<Vec<_>>();
+            return Task::ready(Err(anyhow!(
+                "container `{}` is not running. Running containers: {}",
+                input.container,
+                if running.is_empty() {
+                    "none".to_string()
+                } else {
+                    running.join(", ")
+                }
+            )))
+            .into();
+        };
+
+        let working_dir = match &input.cd {
+            Some(cd) => {
+                let host_dir = match resolve_host_dir(&project, cd, cx) {
+                    Ok(host_dir) => host_dir,
+                    Err(error) => return Task::ready(Err(error)).into(),
+                };
+                match container_dir(&container.spec.mounts, &host_dir) {
+                    Some(dir) => Some(dir),
+                    None => {
+                        return Task::ready(Err(anyhow!(
+                            "{} is not mounted into container `{}`",
+                            host_dir.display(),
+                            input.container
+                        )))
+                        .into();
+                    }
+                }
+            }
+            None => None,
+        };
+        let timeout = input
+            .timeout_secs
+            .map_or(DEFAULT_TIMEOUT, Duration::from_secs)
+            .min(MAX_TIMEOUT);
+        let docker = manager.docker().clone();
+        let exec = Tokio::spawn(cx, {
+            let container = input.container.clone();
+            let command = input.command.clone();
+            async move { exec(docker, container, command, working_dir, timeout).await }
+        });
+        cx.background_spawn(async move {
+            let output = exec
+                .await
+                .map_err(anyhow::Error::from)
+                .and_then(|result| result)?;
+            Ok(exec_message(&output, &input.command, timeout).into())
+        })
+        .into()
+    }
+}
+
+/// Resolves a directory given relative to one of the project's roots, e.g. `zed/crates`, to its
+/// path on the host.
+fn resolve_host_dir(project: &Entity<Project>, cd: &str, cx: &App) -> Result<PathBuf> {
+    let cd = Path::new(cd);
+    if cd.is_absolute() {
+        return Ok(cd.to_path_buf());
+    }
+    let worktrees = project
+        .read(cx)
+        .visible_worktrees(cx)
+        .map(|worktree| {
+            let worktree = worktree.read(cx);
+            (worktree.root_name().to_string(), worktree.abs_path())
+        })
+        .collect::<Vec<_>>();
+    for (root_name, abs_path) in &worktrees {
+        if let Ok(relative) = cd.strip_prefix(root_name) {
+            return Ok(abs_path.join(relative));
+        }
+    }
+    match worktrees.as_slice() {
+        [(_, abs_path)] => Ok(abs_path.join(cd)),
+        _ => Err(anyhow!(
+            "`cd` must start with one of the project's root directories"
+        )),
+    }
+}
+
+/// Where the host directory appears inside a container with the given mounts.
+fn container_dir(mounts: &[Mount], host_dir: &Path) -> Option<String> {
+    mounts
+        .iter()
+        .filter_map(|mount| {
+            let relative = host_dir.strip_prefix(&mount.host_path).ok()?;
+            Some((mount.host_path.components().count(), mount, relative))
+        })
+        .max_by_key(|(depth, _, _)| *depth)
+        .map(|(_, mount, relative)| {
+            let mut dir = mount.container_path.trim_end_matches('/').to_string();
+            for component in relative.components() {
+                dir.push('/');
+                dir.push_str(&component.as_os_str().to_string_lossy());
+            }
+            if dir.is_empty() { "/".to_string() } else { dir }
+        })
+}
+
+/// What the model is told about the command's run.
+fn exec_message(output: &CommandOutput, command: &str, timeout: Duration) -> String {
+    let content = output.output.trim();
+    let mut message = if content.is_empty() {
+        String::new()
+    } else if output.truncated {
+        format!(
+            "Command output too long. The first {} bytes:\n\n```\n{content}\n```",
+            content.len()
+        )
+    } else {
+        format!("```\n{content}\n```")
+    };
+    let status = if output.timed_out {
+        Some(format!(
+            "Command \"{command}\" did not finish within {} seconds and may still be running.",
+            timeout.as_secs()
+        ))
+    } else {
+        match output.exit_code {
+            Some(0) if content.is_empty() => Some("Command executed successfully.".into()),
+            Some(0) => None,
+            Some(code) => Some(format!(
+                "Command \"{command}\" failed with exit code {code}."
+            )),
+            None => Some(format!("Command \"{command}\" exited without a status.")),
+        }
+    };
+    if let Some(status) = status {
+        if message.is_empty() {
+            message = status;
+        } else {
+            message = format!("{status}\n\n{message}");
+        }
+    }
+    message
+}
+
+async fn exec(
+    docker: Docker,
+    container: String,
+    command: String,
+    working_dir: Option<String>,
+    timeout: Duration,
+) -> Result<CommandOutput> {
+    let exec = docker
+        .create_exec(
+            &container,
+            CreateExecOptions {
+                cmd: Some(vec!["sh".to_string(), "-c".to_string(), command]),
+                attach_stdout: Some(true),
+                attach_stderr: Some(true),
+                working_dir,
+                ..Default::default()
+            },
+        )
+        .await
+        .with_context(|| format!("failed to run command in container {container}"))?;
+    let StartExecResults::Attached { mut output, .. } = docker.start_exec(&exec.id, None).await?
+    else {
+        return Err(anyhow!("command was started detached"));
+    };
+
+    let mut result = CommandOutput::default();
+    let collect = async {
+        while let Some(chunk) = output.next().await {
+            let message = match chunk? {
+                LogOutput::StdOut { message }
+                | LogOutput::StdErr { message }
+                | LogOutput::Console { message }
+                | LogOutput::StdIn { message } => message,
+            };
+            result.append(&String::from_utf8_lossy(&message));
+        }
+        anyhow::Ok(())
+    };
+    match tokio::time::timeout(timeout, collect).await {
+        Ok(collected) => collected?,
+        Err(_) => result.timed_out = true,
+    }
+    if !result.timed_out {
+        result.exit_code = docker.inspect_exec(&exec.id).await?.exit_code;
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_container_dir_maps_through_mounts() {
+        let mounts = vec![
+            Mount::read_only("/work/project", "/workspace"),
+            Mount::read_write("/work/project/target", "/out/"),
+        ];
+        assert_eq!(
+            container_dir(&mounts, Path::new("/work/project")).as_deref(),
+            Some("/workspace")
+        );
+        assert_eq!(
+            container_dir(&mounts, Path::new("/work/project/crates/gpui")).as_deref(),
+            Some("/workspace/crates/gpui")
+        );
+        assert_eq!(
+            container_dir(&mounts, Path::new("/work/project/target/debug")).as_deref(),
+            Some("/out/debug")
+        );
+        assert_eq!(container_dir(&mounts, Path::new("/work/other")), None);
+    }
+
+    #[test]
+    fn test_exec_message_reports_failures() {
+        let output = CommandOutput {
+            output: "boom\n".into(),
+            exit_code: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(
+            exec_message(&output, "make", DEFAULT_TIMEOUT),
+            "Command \"make\" failed with exit code 2.\n\n```\nboom\n```"
+        );
+        let output = CommandOutput {
+            exit_code: Some(0),
+            ..Default::default()
+        };
+        assert_eq!(
+            exec_message(&output, "true", DEFAULT_TIMEOUT),
+            "Command executed successfully."
+        );
+    }
+}
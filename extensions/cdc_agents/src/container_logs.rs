@@ -0,0 +1,397 @@
+//! Follows the stdout/stderr of agent containers in a read-only, ANSI-colored view with one tab
+//! per container.
+
+use std::{
+    ops::Range,
+    pin::pin,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bollard::{
+    Docker,
+    container::{LogOutput, LogsOptions},
+};
+use editor::{Anchor, Editor};
+use futures::{StreamExt as _, channel::mpsc};
+use gpui::{
+    Entity, EventEmitter, FocusHandle, Focusable, HighlightStyle, Hsla, Subscription, Task,
+};
+use gpui_tokio::Tokio;
+use ui::{Tab, TabBar, TabPosition, prelude::*};
+use workspace::item::{Item, ItemEvent};
+
+use crate::{
+    ContainerManager, ContainerManagerEvent,
+    ansi::{AnsiColor, AnsiParser},
+};
+
+/// Lines fetched from before the view was opened.
+const INITIAL_TAIL: &str = "500";
+
+pub struct ContainerLogView {
+    tabs: Vec<LogTab>,
+    active_tab: usize,
+    focus_handle: FocusHandle,
+    _subscription: Subscription,
+}
+
+struct LogTab {
+    container: String,
+    editor: Entity<Editor>,
+    parser: AnsiParser,
+    colored_ranges: [Vec<Range<Anchor>>; 16],
+    /// Unix timestamp of the last time output was received, to resume following after a restart
+    /// without repeating what is already shown.
+    last_output_at: Option<i64>,
+    follow_task: Option<Task<()>>,
+}
+
+impl ContainerLogView {
+    pub fn new(
+        manager: Entity<ContainerManager>,
+        window: &mut Window,
+        cx: &mut Context<Self>,
+    ) -> Self {
+        let subscription = cx.subscribe_in(&manager, window, |this, manager, event, window, cx| {
+            if let ContainerManagerEvent::StatusChanged { name } = event {
+                this.container_status_changed(manager, name, window, cx);
+            }
+        });
+
+        let mut this = Self {
+            tabs: Vec::new(),
+            active_tab: 0,
+            focus_handle: cx.focus_handle(),
+            _subscription: subscription,
+        };
+        let live_containers = manager
+            .read(cx)
+            .containers()
+            .filter(|container| container.status.is_live())
+            .map(|container| container.spec.name.clone())
+            .collect::<Vec<_>>();
+        for container in live_containers {
+            this.follow(&manager, container, window, cx);
+        }
+        this
+    }
+
+    fn container_status_changed(
+        &mut self,
+        manager: &Entity<ContainerManager>,
+        name: &str,
+        window: &mut Window,
+        cx: &mut Context<Self>,
+    ) {
+        let Some(container) = manager.read(cx).container(name) else {
+            return;
+        };
+        if !container.status.is_live() {
+            return;
+        }
+        let is_followed = self
+            .tabs
+            .iter()
+            .any(|tab| tab.container == name && tab.follow_task.is_some());
+        if !is_followed {
+            self.follow(manager, name.to_string(), window, cx);
+        }
+    }
+
+    /// Starts following a container's output, in its existing tab if it has one.
+    fn follow(
+        &mut self,
+        manager: &Entity<ContainerManager>,
+        container: String,
+        window: &mut Window,
+        cx: &mut Context<Self>,
+    ) {
+        let tab_ix = match self.tabs.iter().position(|tab| tab.container == container) {
+            Some(ix) => ix,
+            None => {
+                self.tabs.push(LogTab {
+                    container: container.clone(),
+                    editor: log_editor(window, cx),
+                    parser: AnsiParser::default(),
+                    colored_ranges: Default::default(),
+                    last_output_at: None,
+                    follow_task: None,
+                });
+                self.tabs.len() - 1
+            }
+        };
+
+        let docker = manager.read(cx).docker().clone();
+        let since = self.tabs[tab_ix].last_output_at;
+        let (tx, mut rx) = mpsc::unbounded();
+        let stream_logs = Tokio::spawn(cx, stream_logs(docker, container.clone(), since, tx));
+        let follow_task = cx.spawn_in(window, async move |this, cx| {
+            while let Some(chunk) = rx.next().await {
+                let appended = this.update_in(cx, |this, window, cx| {
+                    this.append(&container, &chunk, window, cx)
+                });
+                if appended.is_err() {
+                    return;
+                }
+            }
+            let message = match stream_logs.await {
+                Ok(Err(error)) => format!("\n[failed to follow logs: {error}]\n"),
+                _ => "\n[container stopped]\n".to_string(),
+            };
+            this.update_in(cx, |this, window, cx| {
+                this.append(&container, &message, window, cx);
+                if let Some(tab) = this.tab_mut(&container) {
+                    tab.follow_task = None;
+                }
+            })
+            .ok();
+        });
+        self.tabs[tab_ix].follow_task = Some(follow_task);
+        cx.notify();
+    }
+
+    fn tab_mut(&mut self, container: &str) -> Option<&mut LogTab> {
+        self.tabs.iter_mut().find(|tab| tab.container == container)
+    }
+
+    fn append(
+        &mut self,
+        container: &str,
+        chunk: &str,
+        window: &mut Window,
+        cx: &mut Context<Self>,
+    ) {
+        let Some(tab) = self.tab_mut(container) else {
+            return;
+        };
+        tab.last_output_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .ok()
+            .map(|now| now.as_secs() as i64);
+        let styled = tab.parser.parse(chunk);
+        if styled.text.is_empty() {
+            return;
+        }
+
+        let colored_ranges = &mut tab.colored_ranges;
+        tab.editor.update(cx, |editor, cx| {
+            let end = editor.buffer().read(cx).len(cx);
+            let is_following = editor.selections.newest::<usize>(cx).head() == end;
+
+            editor.set_read_only(false);
+            editor.edit([(end..end, styled.text.as_str())], cx);
+            editor.set_read_only(true);
+
+            let snapshot = editor.buffer().read(cx).snapshot(cx);
+            let mut changed_colors = Vec::new();
+            for (range, color) in styled.colors {
+                colored_ranges[color.0 as usize].push(
+                    snapshot.anchor_after(end + range.start)
+                        ..snapshot.anchor_before(end + range.end),
+                );
+                if !changed_colors.contains(&color) {
+                    changed_colors.push(color);
+                }
+            }
+            for color in changed_colors {
+                highlight_color(
+                    editor,
+                    color,
+                    colored_ranges[color.0 as usize].clone(),
+                    ansi_color(color, cx),
+                    cx,
+                );
+            }
+
+            if is_following {
+                editor.move_to_end(&editor::actions::MoveToEnd, window, cx);
+            }
+        });
+    }
+
+    pub fn activate_container(
+        &mut self,
+        container: &str,
+        window: &mut Window,
+        cx: &mut Context<Self>,
+    ) {
+        if let Some(ix) = self.tabs.iter().position(|tab| tab.container == container) {
+            self.activate_tab(ix, window, cx);
+        }
+    }
+
+    fn activate_tab(&mut self, ix: usize, window: &mut Window, cx: &mut Context<Self>) {
+        self.active_tab = ix;
+        if let Some(tab) = self.tabs.get(ix) {
+            window.focus(&tab.editor.focus_handle(cx));
+        }
+        cx.notify();
+    }
+
+    fn render_tab_bar(&self, cx: &mut Context<Self>) -> impl IntoElement {
+        let last_ix = self.tabs.len().saturating_sub(1);
+        TabBar::new("container-log-tabs").children(self.tabs.iter().enumerate().map(|(ix, tab)| {
+            Tab::new(ix)
+                .position(if ix == 0 {
+                    TabPosition::First
+                } else if ix == last_ix {
+                    TabPosition::Last
+                } else {
+                    TabPosition::Middle(ix.cmp(&self.active_tab))
+                })
+                .toggle_state(ix == self.active_tab)
+                .on_click(cx.listener(move |this, _, window, cx| this.activate_tab(ix, window, cx)))
+                .child(
+                    Label::new(tab.container.clone()).color(if tab.follow_task.is_some() {
+                        Color::Default
+                    } else {
+                        Color::Muted
+                    }),
+                )
+        }))
+    }
+}
+
+async fn stream_logs(
+    docker: Docker,
+    container: String,
+    since: Option<i64>,
+    tx: mpsc::UnboundedSender<String>,
+) -> anyhow::Result<()> {
+    let options = LogsOptions::<String> {
+        follow: true,
+        stdout: true,
+        stderr: true,
+        since: since.unwrap_or(0),
+        tail: if since.is_some() {
+            "all".to_string()
+        } else {
+            INITIAL_TAIL.to_string()
+        },
+        ..Default::default()
+    };
+    let mut logs = pin!(docker.logs(&container, Some(options)));
+    while let Some(output) = logs.next().await {
+        let message = match output? {
+            LogOutput::StdOut { message }
+            | LogOutput::StdErr { message }
+            | LogOutput::Console { message }
+            | LogOutput::StdIn { message } => message,
+        };
+        if tx
+            .unbounded_send(String::from_utf8_lossy(&message).into_owned())
+            .is_err()
+        {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn log_editor(window: &mut Window, cx: &mut App) -> Entity<Editor> {
+    cx.new(|cx| {
+        let mut editor = Editor::multi_line(window, cx);
+        editor.set_show_code_actions(false, cx);
+        editor.set_show_breakpoints(false, cx);
+        editor.set_show_git_diff_gutter(false, cx);
+        editor.set_show_runnables(false, cx);
+        editor.set_input_enabled(false);
+        editor.set_use_autoclose(false);
+        editor.set_read_only(true);
+        editor.set_show_edit_predictions(Some(false), window, cx);
+        editor
+    })
+}
+
+/// Text highlights are keyed by type, so each color gets its own marker type.
+struct AnsiHighlight<const COLOR: u8>;
+
+fn highlight_color(
+    editor: &mut Editor,
+    color: AnsiColor,
+    ranges: Vec<Range<Anchor>>,
+    style: HighlightStyle,
+    cx: &mut Context<Editor>,
+) {
+    macro_rules! highlight {
+        ($($color:literal),*) => {
+            match color.0 {
+                $($color => editor.highlight_text::<AnsiHighlight<$color>>(ranges, style, cx),)*
+                _ => {}
+            }
+        };
+    }
+    highlight!(0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15);
+}
+
+fn ansi_color(color: AnsiColor, cx: &App) -> HighlightStyle {
+    let colors = cx.theme().colors();
+    let color: Hsla = match color.0 {
+        0 => colors.terminal_ansi_black,
+        1 => colors.terminal_ansi_red,
+        2 => colors.terminal_ansi_green,
+        3 => colors.terminal_ansi_yellow,
+        4 => colors.terminal_ansi_blue,
+        5 => colors.terminal_ansi_magenta,
+        6 => colors.terminal_ansi_cyan,
+        7 => colors.terminal_ansi_white,
+        8 => colors.terminal_ansi_bright_black,
+        9 => colors.terminal_ansi_bright_red,
+        10 => colors.terminal_ansi_bright_green,
+        11 => colors.terminal_ansi_bright_yellow,
+        12 => colors.terminal_ansi_bright_blue,
+        13 => colors.terminal_ansi_bright_magenta,
+        14 => colors.terminal_ansi_bright_cyan,
+        _ => colors.terminal_ansi_bright_white,
+    };
+    HighlightStyle {
+        color: Some(color),
+        ..Default::default()
+    }
+}
+
+impl Render for ContainerLogView {
+    fn render(&mut self, _window: &mut Window, cx: &mut Context<Self>) -> impl IntoElement {
+        let content = match self.tabs.get(self.active_tab) {
+            Some(tab) => div().flex_1().size_full().child(tab.editor.clone()),
+            None => div()
+                .flex_1()
+                .size_full()
+                .flex()
+                .items_center()
+                .justify_center()
+                .child(Label::new("No agent containers are running.").color(Color::Muted)),
+        };
+        v_flex()
+            .key_context("ContainerLogView")
+            .track_focus(&self.focus_handle)
+            .size_full()
+            .child(self.render_tab_bar(cx))
+            .child(content)
+    }
+}
+
+impl Focusable for ContainerLogView {
+    fn focus_handle(&self, cx: &App) -> FocusHandle {
+        match self.tabs.get(self.active_tab) {
+            Some(tab) => tab.editor.focus_handle(cx),
+            None => self.focus_handle.clone(),
+        }
+    }
+}
+
+impl EventEmitter<()> for ContainerLogView {}
+
+impl Item for ContainerLogView {
+    type Event = ();
+
+    fn to_item_events(_: &Self::Event, _: impl FnMut(ItemEvent)) {}
+
+    fn tab_content_text(&self, _detail: usize, _cx: &App) -> SharedString {
+        "Container Logs".into()
+    }
+
+    fn telemetry_event_text(&self) -> Option<&'static str> {
+        None
+    }
+}
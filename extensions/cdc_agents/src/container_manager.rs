@@ -0,0 +1,840 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context as _, Result, anyhow};
+use bollard::{
+    Docker,
+    container::{
+        Config, CreateContainerOptions, InspectContainerOptions, ListContainersOptions,
+        NetworkingConfig, RemoveContainerOptions, RestartContainerOptions, StartContainerOptions,
+        StopContainerOptions,
+    },
+    errors::Error as DockerError,
+    models::{
+        ContainerInspectResponse, ContainerStateStatusEnum, EndpointSettings, HealthConfig,
+        HealthStatusEnum, HostConfig, PortBinding,
+    },
+    network::CreateNetworkOptions,
+};
+use collections::{BTreeMap, HashSet};
+use gpui::{
+    App, AppContext as _, Context, Entity, EntityId, EventEmitter, Global, SharedString, Task,
+};
+use gpui_tokio::Tokio;
+use util::ResultExt as _;
+
+use crate::{
+    AgentStack, ImageProvisioner, Mount, MountPolicy, port_allocator::allocate_host_ports,
+};
+
+/// Marks containers created by the manager, so that leftovers can be found after a crash.
+const MANAGED_LABEL: &str = "dev.zed.cdc_agents.managed";
+const LIFECYCLE_LABEL: &str = "dev.zed.cdc_agents.lifecycle";
+const WORKSPACE_LIFECYCLE_PREFIX: &str = "workspace-";
+
+const STOP_TIMEOUT_SECS: i64 = 10;
+const READINESS_POLL_INTERVAL: Duration = Duration::from_millis(500);
+const DEFAULT_STARTUP_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Groups containers that are started and torn down together. Workspace lifecycles end when the
+/// workspace closes; named lifecycles last until they are torn down explicitly or Zed quits.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Lifecycle(SharedString);
+
+impl Lifecycle {
+    pub fn named(name: impl Into<SharedString>) -> Self {
+        Self(name.into())
+    }
+
+    pub fn for_workspace(workspace: EntityId) -> Self {
+        Self(format!("{WORKSPACE_LIFECYCLE_PREFIX}{workspace}").into())
+    }
+
+    pub fn name(&self) -> &SharedString {
+        &self.0
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ContainerSpec {
+    pub name: String,
+    pub image: String,
+    /// Digest the image must match, e.g. `sha256:...`. Tags are used as-is when unset.
+    pub image_digest: Option<String>,
+    pub cmd: Option<Vec<String>>,
+    pub env: Vec<(String, String)>,
+    /// Host paths to mount, each of which the lifecycle's [`MountPolicy`] must allow.
+    pub mounts: Vec<Mount>,
+    /// Named volumes to mount, as `(volume, container_path)`. Docker creates them on first use
+    /// and keeps them when the container is removed.
+    pub volumes: Vec<(String, String)>,
+    /// Ports published on the host, as `(container_port, host_port)`. The host port is only a
+    /// preference (0 for any): if it is taken, another one is picked when the container starts.
+    pub ports: Vec<(u16, u16)>,
+    pub health_check: Option<HealthCheck>,
+    pub startup_timeout: Duration,
+    /// Memory the container may use, in bytes.
+    pub memory_limit: Option<u64>,
+    /// CPUs the container may use, e.g. `1.5`.
+    pub cpu_limit: Option<f64>,
+    /// User-defined network to attach to, created if missing.
+    pub network: Option<String>,
+    /// Names under which other containers on the network reach this one.
+    pub network_aliases: Vec<String>,
+}
+
+impl ContainerSpec {
+    pub fn new(name: impl Into<String>, image: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            image: image.into(),
+            image_digest: None,
+            cmd: None,
+            env: Vec::new(),
+            mounts: Vec::new(),
+            volumes: Vec::new(),
+            ports: Vec::new(),
+            health_check: None,
+            startup_timeout: DEFAULT_STARTUP_TIMEOUT,
+            memory_limit: None,
+            cpu_limit: None,
+            network: None,
+            network_aliases: Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct HealthCheck {
+    /// Command run by Docker inside the container, e.g. `["CMD-SHELL", "pg_isready"]`.
+    pub test: Vec<String>,
+    pub interval: Duration,
+    pub timeout: Duration,
+    pub retries: u32,
+}
+
+impl HealthCheck {
+    pub fn command(test: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        Self {
+            test: test.into_iter().map(Into::into).collect(),
+            interval: Duration::from_secs(2),
+            timeout: Duration::from_secs(5),
+            retries: 10,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ContainerStatus {
+    Starting,
+    Running,
+    Healthy,
+    Unhealthy,
+    Exited { code: i64 },
+    Stopped,
+    Missing,
+}
+
+impl ContainerStatus {
+    /// Whether the container's process is (or is about to be) running.
+    pub fn is_live(&self) -> bool {
+        matches!(
+            self,
+            ContainerStatus::Starting
+                | ContainerStatus::Running
+                | ContainerStatus::Healthy
+                | ContainerStatus::Unhealthy
+        )
+    }
+
+    fn is_ready(&self, has_health_check: bool) -> bool {
+        match self {
+            ContainerStatus::Healthy => true,
+            ContainerStatus::Running => !has_health_check,
+            _ => false,
+        }
+    }
+
+    fn is_failed(&self) -> bool {
+        matches!(
+            self,
+            ContainerStatus::Unhealthy | ContainerStatus::Exited { .. } | ContainerStatus::Missing
+        )
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ManagedContainer {
+    /// The spec the container was created from, with host ports resolved.
+    pub spec: ContainerSpec,
+    pub lifecycle: Lifecycle,
+    pub id: Option<String>,
+    pub status: ContainerStatus,
+    pub started_at: Option<Instant>,
+}
+
+impl ManagedContainer {
+    /// The host port the container port is published on.
+    pub fn host_port(&self, container_port: u16) -> Option<u16> {
+        self.spec
+            .ports
+            .iter()
+            .find(|(port, _)| *port == container_port)
+            .map(|(_, host_port)| *host_port)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum ContainerManagerEvent {
+    StatusChanged { name: String },
+    Removed { name: String },
+}
+
+/// Creates, starts, health-checks, restarts and tears down agent containers.
+pub struct ContainerManager {
+    docker: Docker,
+    images: Entity<ImageProvisioner>,
+    containers: BTreeMap<String, ManagedContainer>,
+    mount_policies: HashMap<Lifecycle, MountPolicy>,
+}
+
+struct GlobalContainerManager(Entity<ContainerManager>);
+
+impl Global for GlobalContainerManager {}
+
+impl EventEmitter<ContainerManagerEvent> for ContainerManager {}
+
+impl ContainerManager {
+    pub fn global(cx: &App) -> Option<Entity<Self>> {
+        cx.try_global::<GlobalContainerManager>()
+            .map(|global| global.0.clone())
+    }
+
+    pub(crate) fn init_global(docker: Docker, cx: &mut App) -> Entity<Self> {
+        let manager = cx.new(|cx| Self::new(docker, cx));
+        cx.set_global(GlobalContainerManager(manager.clone()));
+        manager
+    }
+
+    fn new(docker: Docker, cx: &mut Context<Self>) -> Self {
+        cx.on_app_quit(|this, cx| {
+            let teardown = this.teardown_all(cx);
+            async move {
+                teardown.await.log_err();
+            }
+        })
+        .detach();
+
+        // Workspaces of a previous session are gone, so are the reasons to keep their containers.
+        let remove_orphans = Tokio::spawn(cx, remove_orphaned_containers(docker.clone()));
+        cx.background_spawn(async move {
+            join(remove_orphans.await).log_err();
+        })
+        .detach();
+
+        Self {
+            images: cx.new(|_| ImageProvisioner::new(docker.clone())),
+            docker,
+            containers: BTreeMap::default(),
+            mount_policies: HashMap::default(),
+        }
+    }
+
+    pub fn docker(&self) -> &Docker {
+        &self.docker
+    }
+
+    pub fn images(&self) -> &Entity<ImageProvisioner> {
+        &self.images
+    }
+
+    pub fn mount_policy(&self, lifecycle: &Lifecycle) -> Option<&MountPolicy> {
+        self.mount_policies.get(lifecycle)
+    }
+
+    pub fn set_mount_policy(&mut self, lifecycle: Lifecycle, policy: MountPolicy) {
+        self.mount_policies.insert(lifecycle, policy);
+    }
+
+    pub fn containers(&self) -> impl Iterator<Item = &ManagedContainer> {
+        self.containers.values()
+    }
+
+    pub fn container(&self, name: &str) -> Option<&ManagedContainer> {
+        self.containers.get(name)
+    }
+
+    /// Creates and starts a container, replacing any previous one with the same name, and
+    /// resolves once it is ready: healthy if the spec has a health check, running otherwise.
+    pub fn start(
+        &mut self,
+        lifecycle: Lifecycle,
+        mut spec: ContainerSpec,
+        cx: &mut Context<Self>,
+    ) -> Task<Result<()>> {
+        let policy = self.mount_policies.get(&lifecycle);
+        let ungranted = spec
+            .mounts
+            .iter()
+            .filter(|mount| !policy.is_some_and(|policy| policy.is_allowed(mount)))
+            .map(|mount| mount.host_path.display().to_string())
+            .collect::<Vec<_>>();
+        if !ungranted.is_empty() {
+            return Task::ready(Err(anyhow!(
+                "container {} mounts paths that weren't granted: {}",
+                spec.name,
+                ungranted.join(", ")
+            )));
+        }
+
+        let mut reserved = HashSet::default();
+        let mut owned = HashSet::default();
+        for container in self.containers.values() {
+            let ports = if container.spec.name == spec.name {
+                &mut owned
+            } else {
+                &mut reserved
+            };
+            ports.extend(container.spec.ports.iter().map(|(_, host_port)| *host_port));
+        }
+        spec.ports = match allocate_host_ports(&spec.ports, &reserved, &owned) {
+            Ok(ports) => ports,
+            Err(error) => return Task::ready(Err(error)),
+        };
+
+        let name = spec.name.clone();
+        self.containers.insert(
+            name.clone(),
+            ManagedContainer {
+                spec: spec.clone(),
+                lifecycle: lifecycle.clone(),
+                id: None,
+                status: ContainerStatus::Starting,
+                started_at: None,
+            },
+        );
+        self.status_changed(&name, cx);
+
+        let provision = self.images.update(cx, |images, cx| {
+            images.provision(spec.image.clone(), spec.image_digest.clone(), cx)
+        });
+        let docker = self.docker.clone();
+        cx.spawn(async move |this, cx| {
+            let id = async {
+                let mut spec = spec;
+                spec.image = provision.await?;
+                let create = Tokio::spawn(cx, async move {
+                    create_and_start(&docker, &lifecycle, &spec).await
+                })?;
+                join(create.await)
+            }
+            .await;
+            this.update(cx, |this, cx| {
+                if let Some(container) = this.containers.get_mut(&name) {
+                    match &id {
+                        Ok(id) => {
+                            container.id = Some(id.clone());
+                            container.started_at = Some(Instant::now());
+                        }
+                        Err(_) => container.status = ContainerStatus::Missing,
+                    }
+                    this.status_changed(&name, cx);
+                }
+            })?;
+            id?;
+            this.update(cx, |this, cx| this.wait_until_ready(&name, cx))?
+                .await
+        })
+    }
+
+    pub fn stop(&mut self, name: &str, cx: &mut Context<Self>) -> Task<Result<()>> {
+        let docker = self.docker.clone();
+        let container_name = name.to_string();
+        let stop = Tokio::spawn(cx, async move {
+            docker
+                .stop_container(
+                    &container_name,
+                    Some(StopContainerOptions {
+                        t: STOP_TIMEOUT_SECS,
+                    }),
+                )
+                .await
+                .or_else(ignore_not_found)
+        });
+        let name = name.to_string();
+        cx.spawn(async move |this, cx| {
+            join(stop.await)?;
+            this.update(cx, |this, cx| {
+                this.set_status(&name, ContainerStatus::Stopped, cx)
+            })
+        })
+    }
+
+    pub fn restart(&mut self, name: &str, cx: &mut Context<Self>) -> Task<Result<()>> {
+        let docker = self.docker.clone();
+        let container_name = name.to_string();
+        let restart = Tokio::spawn(cx, async move {
+            docker
+                .restart_container(
+                    &container_name,
+                    Some(RestartContainerOptions {
+                        t: STOP_TIMEOUT_SECS as isize,
+                    }),
+                )
+                .await
+                .with_context(|| format!("failed to restart container {container_name}"))
+        });
+        self.set_status(name, ContainerStatus::Starting, cx);
+        let name = name.to_string();
+        cx.spawn(async move |this, cx| {
+            join(restart.await)?;
+            this.update(cx, |this, cx| {
+                if let Some(container) = this.containers.get_mut(&name) {
+                    container.started_at = Some(Instant::now());
+                }
+                this.wait_until_ready(&name, cx)
+            })?
+            .await
+        })
+    }
+
+    /// Inspects the container and records its current status.
+    pub fn refresh_status(
+        &mut self,
+        name: &str,
+        cx: &mut Context<Self>,
+    ) -> Task<Result<ContainerStatus>> {
+        let docker = self.docker.clone();
+        let container_name = name.to_string();
+        let inspect = Tokio::spawn(
+            cx,
+            async move { inspect_status(&docker, &container_name).await },
+        );
+        let name = name.to_string();
+        cx.spawn(async move |this, cx| {
+            let status = join(inspect.await)?;
+            this.update(cx, |this, cx| this.set_status(&name, status, cx))?;
+            Ok(status)
+        })
+    }
+
+    /// Starts the stack's services one at a time, each once everything it depends on is ready.
+    pub fn start_stack(&mut self, stack: AgentStack, cx: &mut Context<Self>) -> Task<Result<()>> {
+        let order = match stack.start_order() {
+            Ok(order) => order,
+            Err(error) => return Task::ready(Err(error)),
+        };
+        let lifecycle = stack.lifecycle();
+        cx.spawn(async move |this, cx| {
+            for service in order {
+                let spec = stack
+                    .container_spec(&service)
+                    .with_context(|| format!("unknown service {service}"))?;
+                this.update(cx, |this, cx| this.start(lifecycle.clone(), spec, cx))?
+                    .await
+                    .with_context(|| format!("failed to start service {service}"))?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Removes the stack's containers, dependents before their dependencies.
+    pub fn stop_stack(&mut self, stack: &AgentStack, cx: &mut Context<Self>) -> Task<Result<()>> {
+        let mut names = match stack.start_order() {
+            Ok(order) => order
+                .iter()
+                .map(|service| stack.container_name(service))
+                .filter(|name| self.containers.contains_key(name))
+                .collect::<Vec<_>>(),
+            Err(error) => return Task::ready(Err(error)),
+        };
+        names.reverse();
+        self.remove_containers(names, cx)
+    }
+
+    /// Stops and removes every container of the lifecycle.
+    pub fn teardown(&mut self, lifecycle: &Lifecycle, cx: &mut Context<Self>) -> Task<Result<()>> {
+        self.mount_policies.remove(lifecycle);
+        let names = self
+            .containers
+            .values()
+            .filter(|container| &container.lifecycle == lifecycle)
+            .map(|container| container.spec.name.clone())
+            .collect::<Vec<_>>();
+        self.remove_containers(names, cx)
+    }
+
+    pub fn teardown_all(&mut self, cx: &mut Context<Self>) -> Task<Result<()>> {
+        let names = self.containers.keys().cloned().collect();
+        self.remove_containers(names, cx)
+    }
+
+    fn remove_containers(
+        &mut self,
+        names: Vec<String>,
+        cx: &mut Context<Self>,
+    ) -> Task<Result<()>> {
+        if names.is_empty() {
+            return Task::ready(Ok(()));
+        }
+        for name in &names {
+            self.containers.remove(name);
+            cx.emit(ContainerManagerEvent::Removed { name: name.clone() });
+        }
+        cx.notify();
+
+        let docker = self.docker.clone();
+        let remove = Tokio::spawn(cx, async move {
+            let mut errors = Vec::new();
+            for name in names {
+                if let Err(error) = remove_container(&docker, &name).await {
+                    errors.push(format!("{name}: {error:#}"));
+                }
+            }
+            if errors.is_empty() {
+                Ok(())
+            } else {
+                Err(anyhow!(
+                    "failed to remove containers: {}",
+                    errors.join(", ")
+                ))
+            }
+        });
+        cx.background_spawn(async move { join(remove.await) })
+    }
+
+    fn wait_until_ready(&mut self, name: &str, cx: &mut Context<Self>) -> Task<Result<()>> {
+        let Some(container) = self.containers.get(name) else {
+            return Task::ready(Err(anyhow!("container {name} is not managed")));
+        };
+        let docker = self.docker.clone();
+        let container_name = name.to_string();
+        let has_health_check = container.spec.health_check.is_some();
+        let startup_timeout = container.spec.startup_timeout;
+        let wait = Tokio::spawn(cx, async move {
+            let deadline = Instant::now() + startup_timeout;
+            loop {
+                let status = inspect_status(&docker, &container_name).await?;
+                if status.is_ready(has_health_check) || status.is_failed() {
+                    return Ok(status);
+                }
+                if Instant::now() >= deadline {
+                    return Err(anyhow!(
+                        "container {container_name} was not ready after {startup_timeout:?}"
+                    ));
+                }
+                tokio::time::sleep(READINESS_POLL_INTERVAL).await;
+            }
+        });
+        let name = name.to_string();
+        cx.spawn(async move |this, cx| {
+            let status = join(wait.await);
+            let status = this.update(cx, |this, cx| {
+                let recorded = *status.as_ref().unwrap_or(&ContainerStatus::Unhealthy);
+                this.set_status(&name, recorded, cx);
+                status
+            })??;
+            if status.is_failed() {
+                Err(anyhow!("container {name} failed to start: {status:?}"))
+            } else {
+                Ok(())
+            }
+        })
+    }
+
+    fn set_status(&mut self, name: &str, status: ContainerStatus, cx: &mut Context<Self>) {
+        if let Some(container) = self.containers.get_mut(name) {
+            if container.status != status {
+                container.status = status;
+                self.status_changed(name, cx);
+            }
+        }
+    }
+
+    fn status_changed(&self, name: &str, cx: &mut Context<Self>) {
+        cx.emit(ContainerManagerEvent::StatusChanged {
+            name: name.to_string(),
+        });
+        cx.notify();
+    }
+}
+
+fn join<T>(result: Result<Result<T>, tokio::task::JoinError>) -> Result<T> {
+    result
+        .map_err(anyhow::Error::from)
+        .and_then(|result| result)
+}
+
+fn ignore_not_found(error: DockerError) -> Result<()> {
+    match error {
+        DockerError::DockerResponseServerError {
+            status_code: 404 | 304,
+            ..
+        } => Ok(()),
+        error => Err(error.into()),
+    }
+}
+
+async fn ensure_network(docker: &Docker, network: &str) -> Result<()> {
+    match docker
+        .create_network(CreateNetworkOptions {
+            name: network,
+            check_duplicate: true,
+            ..Default::default()
+        })
+        .await
+    {
+        Ok(_)
+        | Err(DockerError::DockerResponseServerError {
+            status_code: 409, ..
+        }) => Ok(()),
+        Err(error) => Err(error).with_context(|| format!("failed to create network {network}")),
+    }
+}
+
+async fn create_and_start(
+    docker: &Docker,
+    lifecycle: &Lifecycle,
+    spec: &ContainerSpec,
+) -> Result<String> {
+    if let Some(network) = &spec.network {
+        ensure_network(docker, network).await?;
+    }
+    remove_container(docker, &spec.name).await?;
+    let response = docker
+        .create_container(
+            Some(CreateContainerOptions {
+                name: spec.name.clone(),
+                platform: None,
+            }),
+            container_config(lifecycle, spec),
+        )
+        .await
+        .with_context(|| format!("failed to create container {}", spec.name))?;
+    docker
+        .start_container(&response.id, None::<StartContainerOptions<String>>)
+        .await
+        .with_context(|| format!("failed to start container {}", spec.name))?;
+    Ok(response.id)
+}
+
+async fn remove_container(docker: &Docker, name: &str) -> Result<()> {
+    docker
+        .remove_container(
+            name,
+            Some(RemoveContainerOptions {
+                force: true,
+                ..Default::default()
+            }),
+        )
+        .await
+        .or_else(ignore_not_found)
+        .with_context(|| format!("failed to remove container {name}"))
+}
+
+async fn inspect_status(docker: &Docker, name: &str) -> Result<ContainerStatus> {
+    match docker
+        .inspect_container(name, None::<InspectContainerOptions>)
+        .await
+    {
+        Ok(response) => Ok(status_from_inspect(&response)),
+        Err(DockerError::DockerResponseServerError {
+            status_code: 404, ..
+        }) => Ok(ContainerStatus::Missing),
+        Err(error) => Err(error.into()),
+    }
+}
+
+async fn remove_orphaned_containers(docker: Docker) -> Result<()> {
+    let containers = docker
+        .list_containers(Some(ListContainersOptions {
+            all: true,
+            filters: HashMap::from_iter([("label", vec![MANAGED_LABEL])]),
+            ..Default::default()
+        }))
+        .await?;
+    for container in containers {
+        let is_workspace_scoped = container
+            .labels
+            .as_ref()
+            .and_then(|labels| labels.get(LIFECYCLE_LABEL))
+            .is_some_and(|lifecycle| lifecycle.starts_with(WORKSPACE_LIFECYCLE_PREFIX));
+        if let (true, Some(id)) = (is_workspace_scoped, container.id) {
+            remove_container(&docker, &id).await.log_err();
+        }
+    }
+    Ok(())
+}
+
+fn container_config(lifecycle: &Lifecycle, spec: &ContainerSpec) -> Config<String> {
+    let labels = HashMap::from_iter([
+        (MANAGED_LABEL.to_string(), "true".to_string()),
+        (LIFECYCLE_LABEL.to_string(), lifecycle.name().to_string()),
+    ]);
+    let exposed_ports = spec
+        .ports
+        .iter()
+        .map(|(container_port, _)| (format!("{container_port}/tcp"), HashMap::new()))
+        .collect::<HashMap<_, _>>();
+    let port_bindings = spec
+        .ports
+        .iter()
+        .map(|(container_port, host_port)| {
+            (
+                format!("{container_port}/tcp"),
+                Some(vec![PortBinding {
+                    host_ip: Some("127.0.0.1".to_string()),
+                    host_port: Some(host_port.to_string()),
+                }]),
+            )
+        })
+        .collect::<HashMap<_, _>>();
+    let healthcheck = spec.health_check.as_ref().map(|check| HealthConfig {
+        test: Some(check.test.clone()),
+        interval: Some(check.interval.as_nanos() as i64),
+        timeout: Some(check.timeout.as_nanos() as i64),
+        retries: Some(check.retries as i64),
+        ..Default::default()
+    });
+
+    let binds = spec
+        .mounts
+        .iter()
+        .map(Mount::to_bind)
+        .chain(
+            spec.volumes
+                .iter()
+                .map(|(volume, container_path)| format!("{volume}:{container_path}")),
+        )
+        .collect();
+
+    let networking_config = spec.network.as_ref().map(|network| NetworkingConfig {
+        endpoints_config: HashMap::from_iter([(
+            network.clone(),
+            EndpointSettings {
+                aliases: Some(spec.network_aliases.clone()),
+                ..Default::default()
+            },
+        )]),
+    });
+
+    Config {
+        image: Some(spec.image.clone()),
+        cmd: spec.cmd.clone(),
+        env: Some(
+            spec.env
+                .iter()
+                .map(|(key, value)| format!("{key}={value}"))
+                .collect(),
+        ),
+        labels: Some(labels),
+        exposed_ports: Some(exposed_ports),
+        healthcheck,
+        host_config: Some(HostConfig {
+            binds: Some(binds),
+            port_bindings: Some(port_bindings),
+            memory: spec.memory_limit.map(|bytes| bytes as i64),
+            nano_cpus: spec.cpu_limit.map(|cpus| (cpus * 1e9) as i64),
+            network_mode: spec.network.clone(),
+            ..Default::default()
+        }),
+        networking_config,
+        ..Default::default()
+    }
+}
+
+fn status_from_inspect(response: &ContainerInspectResponse) -> ContainerStatus {
+    let Some(state) = response.state.as_ref() else {
+        return ContainerStatus::Missing;
+    };
+    match state.health.as_ref().and_then(|health| health.status) {
+        Some(HealthStatusEnum::HEALTHY) => return ContainerStatus::Healthy,
+        Some(HealthStatusEnum::UNHEALTHY) => return ContainerStatus::Unhealthy,
+        Some(HealthStatusEnum::STARTING) => return ContainerStatus::Starting,
+        _ => {}
+    }
+    match state.status {
+        Some(ContainerStateStatusEnum::RUNNING) => ContainerStatus::Running,
+        Some(ContainerStateStatusEnum::CREATED | ContainerStateStatusEnum::RESTARTING) => {
+            ContainerStatus::Starting
+        }
+        Some(ContainerStateStatusEnum::EXITED | ContainerStateStatusEnum::DEAD) => {
+            ContainerStatus::Exited {
+                code: state.exit_code.unwrap_or_default(),
+            }
+        }
+        _ => ContainerStatus::Stopped,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bollard::models::{ContainerState, Health};
+
+    #[test]
+    fn test_status_prefers_health() {
+        let response = |status, health| ContainerInspectResponse {
+            state: Some(ContainerState {
+                status: Some(status),
+                health: health.map(|status| Health {
+                    status: Some(status),
+                    ..Default::default()
+                }),
+                exit_code: Some(3),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert_eq!(
+            status_from_inspect(&response(ContainerStateStatusEnum::RUNNING, None)),
+            ContainerStatus::Running
+        );
+        assert_eq!(
+            status_from_inspect(&response(
+                ContainerStateStatusEnum::RUNNING,
+                Some(HealthStatusEnum::STARTING)
+            )),
+            ContainerStatus::Starting
+        );
+        assert_eq!(
+            status_from_inspect(&response(
+                ContainerStateStatusEnum::RUNNING,
+                Some(HealthStatusEnum::HEALTHY)
+            )),
+            ContainerStatus::Healthy
+        );
+        assert_eq!(
+            status_from_inspect(&response(ContainerStateStatusEnum::EXITED, None)),
+            ContainerStatus::Exited { code: 3 }
+        );
+    }
+
+    #[test]
+    fn test_container_config_labels_and_ports() {
+        let mut spec = ContainerSpec::new("agent", "agent:latest");
+        spec.ports = vec![(5432, 5488)];
+        spec.env = vec![("KEY".into(), "value".into())];
+        spec.volumes = vec![("agent-data".into(), "/data".into())];
+        spec.cpu_limit = Some(1.5);
+        let config = container_config(&Lifecycle::named("conversation-store"), &spec);
+
+        let labels = config.labels.unwrap();
+        assert_eq!(labels[LIFECYCLE_LABEL], "conversation-store");
+        assert_eq!(config.env, Some(vec!["KEY=value".to_string()]));
+        let host_config = config.host_config.unwrap();
+        assert_eq!(
+            host_config.binds,
+            Some(vec!["agent-data:/data".to_string()])
+        );
+        let bindings = host_config.port_bindings.unwrap();
+        assert_eq!(
+            bindings["5432/tcp"].as_ref().unwrap()[0]
+                .host_port
+                .as_deref(),
+            Some("5488")
+        );
+    }
+}
@@ -0,0 +1,405 @@
+//! A dock panel listing the managed containers with their state, uptime and resource usage.
+
+use std::{pin::pin, time::Duration};
+
+use bollard::container::{Stats, StatsOptions};
+use collections::HashMap;
+use futures::{StreamExt as _, channel::mpsc};
+use gpui::{
+    Action, Entity, EventEmitter, FocusHandle, Focusable, Pixels, Subscription, Task, WeakEntity,
+    actions,
+};
+use gpui_tokio::Tokio;
+use ui::{Indicator, Tooltip, prelude::*};
+use workspace::{
+    Workspace,
+    dock::{DockPosition, Panel, PanelEvent},
+};
+
+use crate::{ContainerManager, ContainerManagerEvent, ContainerStatus, ManagedContainer};
+
+actions!(cdc_agents, [ToggleContainerPanel]);
+
+const DEFAULT_WIDTH: f32 = 320.;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct ResourceUsage {
+    cpu_percent: Option<f64>,
+    memory_used: Option<u64>,
+    memory_limit: Option<u64>,
+}
+
+impl ResourceUsage {
+    fn from_stats(stats: &Stats) -> Self {
+        let cpu_delta = stats
+            .cpu_stats
+            .cpu_usage
+            .total_usage
+            .saturating_sub(stats.precpu_stats.cpu_usage.total_usage);
+        let system_delta = stats
+            .cpu_stats
+            .system_cpu_usage
+            .zip(stats.precpu_stats.system_cpu_usage)
+            .map(|(now, before)| now.saturating_sub(before));
+        let cpus = stats
+            .cpu_stats
+            .online_cpus
+            .or_else(|| {
+                stats
+                    .cpu_stats
+                    .cpu_usage
+                    .percpu_usage
+                    .as_ref()
+                    .map(|usage| usage.len() as u64)
+            })
+            .unwrap_or(1);
+        Self {
+            cpu_percent: system_delta
+                .and_then(|system_delta| cpu_percent(cpu_delta, system_delta, cpus)),
+            memory_used: stats.memory_stats.usage,
+            memory_limit: stats.memory_stats.limit,
+        }
+    }
+}
+
+/// CPU usage the way `docker stats` reports it, where 100% is one full core.
+fn cpu_percent(cpu_delta: u64, system_delta: u64, cpus: u64) -> Option<f64> {
+    (system_delta > 0).then(|| cpu_delta as f64 / system_delta as f64 * cpus as f64 * 100.)
+}
+
+pub struct ContainerPanel {
+    manager: Entity<ContainerManager>,
+    workspace: WeakEntity<Workspace>,
+    usage: HashMap<String, ResourceUsage>,
+    stats_tasks: HashMap<String, Task<()>>,
+    position: DockPosition,
+    width: Option<Pixels>,
+    focus_handle: FocusHandle,
+    _subscription: Subscription,
+}
+
+impl ContainerPanel {
+    pub fn new(
+        manager: Entity<ContainerManager>,
+        workspace: WeakEntity<Workspace>,
+        cx: &mut Context<Self>,
+    ) -> Self {
+        let subscription = cx.subscribe(&manager, |this, _, event, cx| {
+            let name = match event {
+                ContainerManagerEvent::StatusChanged { name } => name,
+                ContainerManagerEvent::Removed { name } => name,
+            };
+            this.update_stats_subscription(name, cx);
+            cx.notify();
+        });
+        let mut this = Self {
+            manager,
+            workspace,
+            usage: HashMap::default(),
+            stats_tasks: HashMap::default(),
+            position: DockPosition::Right,
+            width: None,
+            focus_handle: cx.focus_handle(),
+            _subscription: subscription,
+        };
+        let names = this
+            .manager
+            .read(cx)
+            .containers()
+            .map(|container| container.spec.name.clone())
+            .collect::<Vec<_>>();
+        for name in names {
+            this.update_stats_subscription(&name, cx);
+        }
+        this
+    }
+
+    /// Streams stats for the container while it runs.
+    fn update_stats_subscription(&mut self, name: &str, cx: &mut Context<Self>) {
+        let is_live = self
+            .manager
+            .read(cx)
+            .container(name)
+            .is_some_and(|container| container.status.is_live());
+        if !is_live {
+            self.stats_tasks.remove(name);
+            self.usage.remove(name);
+            return;
+        }
+        if self.stats_tasks.contains_key(name) {
+            return;
+        }
+
+        let docker = self.manager.read(cx).docker().clone();
+        let (tx, mut rx) = mpsc::unbounded();
+        let stream = Tokio::spawn(cx, {
+            let name = name.to_string();
+            async move {
+                let mut stats = pin!(docker.stats(
+                    &name,
+                    Some(StatsOptions {
+                        stream: true,
+                        one_shot: false,
+                    }),
+                ));
+                while let Some(Ok(stats)) = stats.next().await {
+                    if tx
+                        .unbounded_send(ResourceUsage::from_stats(&stats))
+                        .is_err()
+                    {
+                        break;
+                    }
+                }
+            }
+        });
+        let name = name.to_string();
+        let task = cx.spawn({
+            let name = name.clone();
+            async move |this, cx| {
+                while let Some(usage) = rx.next().await {
+                    let updated = this.update(cx, |this, cx| {
+                        this.usage.insert(name.clone(), usage);
+                        cx.notify();
+                    });
+                    if updated.is_err() {
+                        break;
+                    }
+                }
+                stream.await.ok();
+            }
+        });
+        self.stats_tasks.insert(name, task);
+    }
+
+    fn open_logs(&mut self, name: String, window: &mut Window, cx: &mut Context<Self>) {
+        self.workspace
+            .update(cx, |workspace, cx| {
+                crate::show_container_logs(workspace, Some(&name), window, cx)
+            })
+            .ok();
+    }
+
+    fn render_container(
+        &self,
+        container: &ManagedContainer,
+        cx: &mut Context<Self>,
+    ) -> impl IntoElement {
+        let name = container.spec.name.clone();
+        let (status, color) = status_label(container.status);
+        let mut details = vec![status.to_string()];
+        if let Some(started_at) = container.started_at.filter(|_| container.status.is_live()) {
+            details.push(format!("up {}", format_uptime(started_at.elapsed())));
+        }
+        if let Some(usage) = self.usage.get(&name) {
+            if let Some(cpu_percent) = usage.cpu_percent {
+                details.push(format!("{cpu_percent:.1}% CPU"));
+            }
+            match (usage.memory_used, usage.memory_limit) {
+                (Some(used), Some(limit)) => {
+                    details.push(format!("{} / {}", format_bytes(used), format_bytes(limit)))
+                }
+                (Some(used), None) => details.push(format_bytes(used)),
+                _ => {}
+            }
+        }
+
+        h_flex()
+            .id(SharedString::from(format!("container-{name}")))
+            .w_full()
+            .px_2()
+            .py_1()
+            .gap_2()
+            .justify_between()
+            .child(
+                v_flex()
+                    .min_w_0()
+                    .child(
+                        h_flex()
+                            .gap_1p5()
+                            .child(Indicator::dot().color(color))
+                            .child(Label::new(name.clone()).truncate()),
+                    )
+                    .child(
+                        Label::new(details.join(" · "))
+                            .size(LabelSize::Small)
+                            .color(Color::Muted)
+                            .truncate(),
+                    ),
+            )
+            .child(
+                h_flex()
+                    .gap_0p5()
+                    .child(
+                        IconButton::new(
+                            SharedString::from(format!("restart-{name}")),
+                            IconName::RotateCw,
+                        )
+                        .icon_size(IconSize::Small)
+                        .tooltip(Tooltip::text("Restart"))
+                        .on_click(cx.listener({
+                            let name = name.clone();
+                            move |this, _, _, cx| {
+                                this.manager
+                                    .update(cx, |manager, cx| manager.restart(&name, cx))
+                                    .detach_and_log_err(cx)
+                            }
+                        })),
+                    )
+                    .child(
+                        IconButton::new(SharedString::from(format!("stop-{name}")), IconName::Stop)
+                            .icon_size(IconSize::Small)
+                            .tooltip(Tooltip::text("Stop"))
+                            .disabled(!container.status.is_live())
+                            .on_click(cx.listener({
+                                let name = name.clone();
+                                move |this, _, _, cx| {
+                                    this.manager
+                                        .update(cx, |manager, cx| manager.stop(&name, cx))
+                                        .detach_and_log_err(cx)
+                                }
+                            })),
+                    )
+                    .child(
+                        IconButton::new(
+                            SharedString::from(format!("logs-{name}")),
+                            IconName::FileText,
+                        )
+                        .icon_size(IconSize::Small)
+                        .tooltip(Tooltip::text("Open Logs"))
+                        .on_click(cx.listener(
+                            move |this, _, window, cx| this.open_logs(name.clone(), window, cx),
+                        )),
+                    ),
+            )
+    }
+}
+
+fn status_label(status: ContainerStatus) -> (&'static str, Color) {
+    match status {
+        ContainerStatus::Starting => ("Starting", Color::Warning),
+        ContainerStatus::Running => ("Running", Color::Success),
+        ContainerStatus::Healthy => ("Healthy", Color::Success),
+        ContainerStatus::Unhealthy => ("Unhealthy", Color::Error),
+        ContainerStatus::Exited { .. } => ("Exited", Color::Error),
+        ContainerStatus::Stopped => ("Stopped", Color::Muted),
+        ContainerStatus::Missing => ("Missing", Color::Error),
+    }
+}
+
+fn format_uptime(uptime: Duration) -> String {
+    let seconds = uptime.as_secs();
+    match seconds {
+        0..60 => format!("{seconds}s"),
+        60..3600 => format!("{}m", seconds / 60),
+        _ => format!("{}h {}m", seconds / 3600, seconds % 3600 / 60),
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const MIB: f64 = 1024. * 1024.;
+    const GIB: f64 = MIB * 1024.;
+    let bytes = bytes as f64;
+    if bytes >= GIB {
+        format!("{:.1} GiB", bytes / GIB)
+    } else {
+        format!("{:.0} MiB", bytes / MIB)
+    }
+}
+
+impl Render for ContainerPanel {
+    fn render(&mut self, _window: &mut Window, cx: &mut Context<Self>) -> impl IntoElement {
+        let containers = self
+            .manager
+            .read(cx)
+            .containers()
+            .cloned()
+            .collect::<Vec<_>>();
+        v_flex()
+            .key_context("ContainerPanel")
+            .track_focus(&self.focus_handle)
+            .size_full()
+            .py_1()
+            .when(containers.is_empty(), |this| {
+                this.items_center()
+                    .justify_center()
+                    .child(Label::new("No agent containers are running.").color(Color::Muted))
+            })
+            .children(
+                containers
+                    .iter()
+                    .map(|container| self.render_container(container, cx)),
+            )
+    }
+}
+
+impl Focusable for ContainerPanel {
+    fn focus_handle(&self, _: &App) -> FocusHandle {
+        self.focus_handle.clone()
+    }
+}
+
+impl EventEmitter<PanelEvent> for ContainerPanel {}
+
+impl Panel for ContainerPanel {
+    fn persistent_name() -> &'static str {
+        "ContainerPanel"
+    }
+
+    fn position(&self, _: &Window, _: &App) -> DockPosition {
+        self.position
+    }
+
+    fn position_is_valid(&self, _: DockPosition) -> bool {
+        true
+    }
+
+    fn set_position(&mut self, position: DockPosition, _: &mut Window, cx: &mut Context<Self>) {
+        self.position = position;
+        cx.notify();
+    }
+
+    fn size(&self, _: &Window, _: &App) -> Pixels {
+        self.width.unwrap_or(px(DEFAULT_WIDTH))
+    }
+
+    fn set_size(&mut self, size: Option<Pixels>, _: &mut Window, cx: &mut Context<Self>) {
+        self.width = size;
+        cx.notify();
+    }
+
+    fn icon(&self, _: &Window, _: &App) -> Option<IconName> {
+        Some(IconName::Server)
+    }
+
+    fn icon_tooltip(&self, _: &Window, _: &App) -> Option<&'static str> {
+        Some("Agent Containers")
+    }
+
+    fn toggle_action(&self) -> Box<dyn Action> {
+        Box::new(ToggleContainerPanel)
+    }
+
+    fn activation_priority(&self) -> u32 {
+        10
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cpu_percent() {
+        assert_eq!(cpu_percent(50, 100, 4), Some(200.));
+        assert_eq!(cpu_percent(50, 0, 4), None);
+    }
+
+    #[test]
+    fn test_formatting() {
+        assert_eq!(format_uptime(Duration::from_secs(42)), "42s");
+        assert_eq!(format_uptime(Duration::from_secs(125)), "2m");
+        assert_eq!(format_uptime(Duration::from_secs(3 * 3600 + 120)), "3h 2m");
+        assert_eq!(format_bytes(128 * 1024 * 1024), "128 MiB");
+        assert_eq!(format_bytes(3 * 1024 * 1024 * 1024 / 2), "1.5 GiB");
+    }
+}
@@ -0,0 +1,329 @@
+//! Makes the images agent containers run available locally: pulls them with progress reporting,
+//! verifies pinned digests, and remembers past pulls so startup doesn't go to the registry every
+//! time.
+
+use std::{
+    pin::pin,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context as _, Result, anyhow};
+use bollard::{
+    Docker, errors::Error as DockerError, image::CreateImageOptions, models::CreateImageInfo,
+};
+use collections::{BTreeMap, HashMap};
+use db::kvp::KEY_VALUE_STORE;
+use futures::{StreamExt as _, channel::mpsc};
+use gpui::{Context, Entity, Subscription, Task, Window};
+use gpui_tokio::Tokio;
+use serde::{Deserialize, Serialize};
+use ui::prelude::*;
+use util::ResultExt as _;
+use workspace::{ItemHandle, StatusItemView};
+
+const PULL_CACHE_KEY: &str = "cdc_agents_image_pulls";
+
+/// How long a pulled tag is trusted before asking the registry whether it moved.
+const TAG_REFRESH_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PullProgress {
+    pub status: Option<String>,
+    /// Bytes downloaded and total bytes, per layer.
+    layers: HashMap<String, (i64, i64)>,
+}
+
+impl PullProgress {
+    fn update(&mut self, info: &CreateImageInfo) {
+        self.status = info.status.clone();
+        if let (Some(layer), Some(detail)) = (&info.id, &info.progress_detail) {
+            if let (Some(current), Some(total)) = (detail.current, detail.total) {
+                self.layers.insert(layer.clone(), (current, total));
+            }
+        }
+    }
+
+    pub fn fraction(&self) -> Option<f32> {
+        let (current, total) = self
+            .layers
+            .values()
+            .fold((0, 0), |(current, total), layer| {
+                (current + layer.0, total + layer.1)
+            });
+        (total > 0).then(|| current as f32 / total as f32)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct CachedPull {
+    digest: String,
+    /// Seconds since the Unix epoch.
+    pulled_at: u64,
+}
+
+pub struct ImageProvisioner {
+    docker: Docker,
+    pulls: BTreeMap<String, PullProgress>,
+    cache: HashMap<String, CachedPull>,
+}
+
+impl ImageProvisioner {
+    pub(crate) fn new(docker: Docker) -> Self {
+        let cache = KEY_VALUE_STORE
+            .read_kvp(PULL_CACHE_KEY)
+            .log_err()
+            .flatten()
+            .and_then(|cache| serde_json::from_str(&cache).log_err())
+            .unwrap_or_default();
+        Self {
+            docker,
+            pulls: BTreeMap::default(),
+            cache,
+        }
+    }
+
+    /// Images being pulled right now.
+    pub fn pulls(&self) -> impl Iterator<Item = (&String, &PullProgress)> {
+        self.pulls.iter()
+    }
+
+    /// Ensures the image is available locally, pulling it if needed, and resolves to the
+    /// reference containers should be created from.
+    pub fn provision(
+        &mut self,
+        image: String,
+        pinned_digest: Option<String>,
+        cx: &mut Context<Self>,
+    ) -> Task<Result<String>> {
+        let reference = match &pinned_digest {
+            Some(digest) => format!("{}@{digest}", repository(&image)),
+            None => image.clone(),
+        };
+        let cached = self.cache.get(&image).cloned();
+        let docker = self.docker.clone();
+        let (progress_tx, mut progress_rx) = mpsc::unbounded();
+        let provision = Tokio::spawn(cx, {
+            let reference = reference.clone();
+            async move { provision_image(docker, reference, pinned_digest, cached, progress_tx).await }
+        });
+        cx.spawn(async move |this, cx| {
+            while let Some(info) = progress_rx.next().await {
+                this.update(cx, |this, cx| {
+                    this.pulls.entry(image.clone()).or_default().update(&info);
+                    cx.notify();
+                })?;
+            }
+            let pulled = provision
+                .await
+                .map_err(anyhow::Error::from)
+                .and_then(|result| result)
+                .with_context(|| format!("failed to provision image {image}"));
+            this.update(cx, |this, cx| {
+                this.pulls.remove(&image);
+                if let Ok(Some(pull)) = &pulled {
+                    this.cache.insert(image, pull.clone());
+                    this.persist_cache(cx);
+                }
+                cx.notify();
+            })?;
+            pulled.map(|_| reference)
+        })
+    }
+
+    fn persist_cache(&self, cx: &mut Context<Self>) {
+        let Some(cache) = serde_json::to_string(&self.cache).log_err() else {
+            return;
+        };
+        db::write_and_log(cx, move || {
+            KEY_VALUE_STORE.write_kvp(PULL_CACHE_KEY.to_string(), cache)
+        });
+    }
+}
+
+/// Pulls the image unless a usable copy is already present, returning what was pulled.
+async fn provision_image(
+    docker: Docker,
+    reference: String,
+    pinned_digest: Option<String>,
+    cached: Option<CachedPull>,
+    progress_tx: mpsc::UnboundedSender<CreateImageInfo>,
+) -> Result<Option<CachedPull>> {
+    let is_present = match docker.inspect_image(&reference).await {
+        Ok(_) => true,
+        Err(DockerError::DockerResponseServerError {
+            status_code: 404, ..
+        }) => false,
+        Err(error) => return Err(error.into()),
+    };
+    let now = unix_now();
+    if is_present && !needs_refresh(cached.as_ref(), pinned_digest.is_some(), now) {
+        return Ok(None);
+    }
+
+    let mut pull = pin!(docker.create_image(
+        Some(CreateImageOptions {
+            from_image: reference.as_str(),
+            ..Default::default()
+        }),
+        None,
+        None,
+    ));
+    while let Some(info) = pull.next().await {
+        match info {
+            Ok(info) => {
+                progress_tx.unbounded_send(info).ok();
+            }
+            // Locally built images have no registry to refresh from.
+            Err(error) if is_present => {
+                log::warn!("failed to refresh image {reference}, using the local copy: {error}");
+                return Ok(None);
+            }
+            Err(error) => return Err(error.into()),
+        }
+    }
+
+    let inspect = docker.inspect_image(&reference).await?;
+    let digest = verify_digest(
+        inspect.repo_digests.as_deref().unwrap_or_default(),
+        pinned_digest.as_deref(),
+    )?;
+    Ok(Some(CachedPull {
+        digest,
+        pulled_at: now,
+    }))
+}
+
+/// Pinned images are immutable, so only tags are ever refreshed.
+fn needs_refresh(cached: Option<&CachedPull>, is_pinned: bool, now: u64) -> bool {
+    if is_pinned {
+        return false;
+    }
+    cached
+        .is_none_or(|cached| now.saturating_sub(cached.pulled_at) >= TAG_REFRESH_INTERVAL.as_secs())
+}
+
+/// Checks the pulled image against its pin, returning its digest.
+fn verify_digest(repo_digests: &[String], pinned_digest: Option<&str>) -> Result<String> {
+    let mut digests = repo_digests
+        .iter()
+        .filter_map(|repo_digest| repo_digest.split_once('@').map(|(_, digest)| digest));
+    let digest = match pinned_digest {
+        Some(pinned) => digests.find(|digest| *digest == pinned).ok_or_else(|| {
+            anyhow!(
+                "digest mismatch: expected {pinned}, got {}",
+                repo_digests.join(", ")
+            )
+        })?,
+        None => digests
+            .next()
+            .ok_or_else(|| anyhow!("pulled image has no registry digest"))?,
+    };
+    Ok(digest.to_string())
+}
+
+/// The image name without its tag, e.g. `postgres` for `postgres:16`.
+fn repository(image: &str) -> &str {
+    let name_start = image.rfind('/').map_or(0, |ix| ix + 1);
+    match image[name_start..].find(':') {
+        Some(ix) => &image[..name_start + ix],
+        None => image,
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs())
+}
+
+/// Shows image pulls in progress in the status bar.
+pub struct ImagePullIndicator {
+    provisioner: Entity<ImageProvisioner>,
+    _observe_provisioner: Subscription,
+}
+
+impl ImagePullIndicator {
+    pub fn new(provisioner: Entity<ImageProvisioner>, cx: &mut Context<Self>) -> Self {
+        Self {
+            _observe_provisioner: cx.observe(&provisioner, |_, _, cx| cx.notify()),
+            provisioner,
+        }
+    }
+}
+
+impl Render for ImagePullIndicator {
+    fn render(&mut self, _window: &mut Window, cx: &mut Context<Self>) -> impl IntoElement {
+        let provisioner = self.provisioner.read(cx);
+        let mut pulls = provisioner.pulls();
+        let Some((image, progress)) = pulls.next() else {
+            return div().into_any_element();
+        };
+        let mut message = format!("Pulling {image}");
+        if let Some(fraction) = progress.fraction() {
+            message.push_str(&format!(" ({:.0}%)", fraction * 100.));
+        }
+        let others = pulls.count();
+        if others > 0 {
+            message.push_str(&format!(" and {others} more"));
+        }
+        h_flex()
+            .gap_1()
+            .child(
+                Icon::new(IconName::Download)
+                    .size(IconSize::Small)
+                    .color(Color::Muted),
+            )
+            .child(Label::new(message).size(LabelSize::Small))
+            .into_any_element()
+    }
+}
+
+impl StatusItemView for ImagePullIndicator {
+    fn set_active_pane_item(
+        &mut self,
+        _: Option<&dyn ItemHandle>,
+        _window: &mut Window,
+        _: &mut Context<Self>,
+    ) {
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repository() {
+        assert_eq!(repository("postgres:16-alpine"), "postgres");
+        assert_eq!(repository("ghcr.io/org/agent"), "ghcr.io/org/agent");
+        assert_eq!(
+            repository("localhost:5000/agent:1.2"),
+            "localhost:5000/agent"
+        );
+    }
+
+    #[test]
+    fn test_verify_digest() {
+        let repo_digests = vec!["postgres@sha256:abc".to_string()];
+        assert_eq!(
+            verify_digest(&repo_digests, Some("sha256:abc")).unwrap(),
+            "sha256:abc"
+        );
+        assert_eq!(verify_digest(&repo_digests, None).unwrap(), "sha256:abc");
+        assert!(verify_digest(&repo_digests, Some("sha256:def")).is_err());
+        assert!(verify_digest(&[], None).is_err());
+    }
+
+    #[test]
+    fn test_tags_are_refreshed_daily() {
+        let day = TAG_REFRESH_INTERVAL.as_secs();
+        let cached = CachedPull {
+            digest: "sha256:abc".to_string(),
+            pulled_at: 1_000,
+        };
+        assert!(needs_refresh(None, false, 1_000));
+        assert!(!needs_refresh(Some(&cached), false, 1_000 + day - 1));
+        assert!(needs_refresh(Some(&cached), false, 1_000 + day));
+        assert!(!needs_refresh(None, true, 1_000));
+    }
+}
@@ -0,0 +1,206 @@
+//! Decides which host paths agent containers may see. Worktrees can be mounted read-only without
+//! asking; writing to them, or mounting anything else, needs a grant the user approved.
+
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{Result, anyhow};
+use gpui::{App, Entity, PromptLevel, Task, Window};
+use itertools::Itertools as _;
+
+use crate::{ContainerManager, Lifecycle};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MountAccess {
+    ReadOnly,
+    ReadWrite,
+}
+
+impl MountAccess {
+    fn label(&self) -> &'static str {
+        match self {
+            MountAccess::ReadOnly => "read-only",
+            MountAccess::ReadWrite => "read-write",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mount {
+    pub host_path: PathBuf,
+    pub container_path: String,
+    pub access: MountAccess,
+}
+
+impl Mount {
+    pub fn read_only(host_path: impl Into<PathBuf>, container_path: impl Into<String>) -> Self {
+        Self {
+            host_path: host_path.into(),
+            container_path: container_path.into(),
+            access: MountAccess::ReadOnly,
+        }
+    }
+
+    pub fn read_write(host_path: impl Into<PathBuf>, container_path: impl Into<String>) -> Self {
+        Self {
+            access: MountAccess::ReadWrite,
+            ..Self::read_only(host_path, container_path)
+        }
+    }
+
+    /// The mount in Docker's `host_path:container_path[:ro]` bind form.
+    pub fn to_bind(&self) -> String {
+        let bind = format!("{}:{}", self.host_path.display(), self.container_path);
+        match self.access {
+            MountAccess::ReadOnly => format!("{bind}:ro"),
+            MountAccess::ReadWrite => bind,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MountPolicy {
+    worktree_roots: Vec<PathBuf>,
+    grants: Vec<(PathBuf, MountAccess)>,
+}
+
+impl MountPolicy {
+    pub fn new(worktree_roots: Vec<PathBuf>) -> Self {
+        Self {
+            worktree_roots,
+            grants: Vec::new(),
+        }
+    }
+
+    pub fn set_worktree_roots(&mut self, worktree_roots: Vec<PathBuf>) {
+        self.worktree_roots = worktree_roots;
+    }
+
+    /// Allows mounting the path and everything below it with up to the given access.
+    pub fn grant(&mut self, path: PathBuf, access: MountAccess) {
+        self.grants.push((path, access));
+    }
+
+    /// Paths are compared lexically, so a symlink inside a worktree is trusted like the worktree.
+    pub fn is_allowed(&self, mount: &Mount) -> bool {
+        let Some(path) = normalize(&mount.host_path) else {
+            return false;
+        };
+        let in_worktree = mount.access == MountAccess::ReadOnly
+            && self
+                .worktree_roots
+                .iter()
+                .any(|root| path.starts_with(root));
+        in_worktree
+            || self
+                .grants
+                .iter()
+                .any(|(granted, access)| path.starts_with(granted) && mount.access <= *access)
+    }
+}
+
+/// Resolves `.` and `..` in an absolute path, rejecting relative paths and paths that climb
+/// above the root.
+fn normalize(path: &Path) -> Option<PathBuf> {
+    if !path.is_absolute() {
+        return None;
+    }
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if normalized.parent().is_none() {
+                    return None;
+                }
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    Some(normalized)
+}
+
+/// Asks the user to grant the mounts the lifecycle's policy doesn't already allow, then makes
+/// the extended policy the lifecycle's.
+pub fn approve_mounts(
+    manager: Entity<ContainerManager>,
+    lifecycle: Lifecycle,
+    worktree_roots: Vec<PathBuf>,
+    mounts: Vec<Mount>,
+    window: &mut Window,
+    cx: &mut App,
+) -> Task<Result<()>> {
+    let mut policy = manager
+        .read(cx)
+        .mount_policy(&lifecycle)
+        .cloned()
+        .unwrap_or_default();
+    policy.set_worktree_roots(worktree_roots);
+    let ungranted = mounts
+        .into_iter()
+        .filter(|mount| !policy.is_allowed(mount))
+        .collect::<Vec<_>>();
+    if ungranted.is_empty() {
+        manager.update(cx, |manager, _| manager.set_mount_policy(lifecycle, policy));
+        return Task::ready(Ok(()));
+    }
+
+    let detail = ungranted
+        .iter()
+        .map(|mount| {
+            format!(
+                "{} → {} ({})",
+                mount.host_path.display(),
+                mount.container_path,
+                mount.access.label()
+            )
+        })
+        .join("\n");
+    let answer = window.prompt(
+        PromptLevel::Warning,
+        "Allow agent containers to access these paths?",
+        Some(&detail),
+        &["Allow", "Deny"],
+        cx,
+    );
+    cx.spawn(async move |cx| {
+        if answer.await? != 0 {
+            return Err(anyhow!("access to {detail} was denied"));
+        }
+        for mount in ungranted {
+            policy.grant(mount.host_path, mount.access);
+        }
+        manager.update(cx, |manager, _| manager.set_mount_policy(lifecycle, policy))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_worktrees_are_read_only_by_default() {
+        let policy = MountPolicy::new(vec![PathBuf::from("/work/project")]);
+        assert!(policy.is_allowed(&Mount::read_only("/work/project", "/workspace")));
+        assert!(policy.is_allowed(&Mount::read_only("/work/project/src", "/src")));
+        assert!(!policy.is_allowed(&Mount::read_write("/work/project", "/workspace")));
+        assert!(!policy.is_allowed(&Mount::read_only("/work/project-other", "/other")));
+        assert!(!policy.is_allowed(&Mount::read_only("/work/project/../..", "/root")));
+        assert!(!policy.is_allowed(&Mount::read_only("work/project", "/workspace")));
+    }
+
+    #[test]
+    fn test_grants() {
+        let mut policy = MountPolicy::new(vec![PathBuf::from("/work/project")]);
+        policy.grant(
+            PathBuf::from("/work/project/target"),
+            MountAccess::ReadWrite,
+        );
+        policy.grant(PathBuf::from("/home/me/.cache"), MountAccess::ReadOnly);
+        assert!(policy.is_allowed(&Mount::read_write("/work/project/target/debug", "/out")));
+        assert!(!policy.is_allowed(&Mount::read_write("/work/project/src", "/src")));
+        assert!(policy.is_allowed(&Mount::read_only("/home/me/.cache", "/cache")));
+        assert!(!policy.is_allowed(&Mount::read_write("/home/me/.cache", "/cache")));
+        assert!(!policy.is_allowed(&Mount::read_only("/home/me", "/home")));
+    }
+}
@@ -0,0 +1,81 @@
+//! Picks host ports for published container ports, moving off preferred ports that are already
+//! taken so that a stray local Postgres doesn't keep an agent stack from starting.
+
+use std::net::{Ipv4Addr, TcpListener};
+
+use anyhow::{Context as _, Result};
+use collections::HashSet;
+
+/// Resolves `(container_port, preferred_host_port)` pairs to the host ports to publish on, where
+/// a preferred port of 0 means any. `reserved` ports are promised to other containers; `owned`
+/// ports are held by the container being replaced and become free once it is removed.
+pub(crate) fn allocate_host_ports(
+    requested: &[(u16, u16)],
+    reserved: &HashSet<u16>,
+    owned: &HashSet<u16>,
+) -> Result<Vec<(u16, u16)>> {
+    let mut taken = reserved.clone();
+    let mut allocated = Vec::with_capacity(requested.len());
+    for &(container_port, preferred) in requested {
+        let is_available =
+            |port: u16| !taken.contains(&port) && (owned.contains(&port) || is_port_free(port));
+        let host_port = if preferred != 0 && is_available(preferred) {
+            preferred
+        } else {
+            let port = loop {
+                let port = ephemeral_port()?;
+                if !taken.contains(&port) {
+                    break port;
+                }
+            };
+            if preferred != 0 {
+                log::info!(
+                    "host port {preferred} is in use, publishing container port {container_port} on {port}"
+                );
+            }
+            port
+        };
+        taken.insert(host_port);
+        allocated.push((container_port, host_port));
+    }
+    Ok(allocated)
+}
+
+/// Containers publish their ports on the loopback address, so that's where a port must be free.
+fn is_port_free(port: u16) -> bool {
+    TcpListener::bind((Ipv4Addr::LOCALHOST, port)).is_ok()
+}
+
+fn ephemeral_port() -> Result<u16> {
+    let listener =
+        TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).context("failed to find a free host port")?;
+    Ok(listener.local_addr()?.port())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conflicting_ports_are_reallocated() {
+        let listener = TcpListener::bind((Ipv4Addr::UNSPECIFIED, 0)).unwrap();
+        let busy = listener.local_addr().unwrap().port();
+        let free = ephemeral_port().unwrap();
+        let reserved = HashSet::from_iter([free]);
+
+        let allocated = allocate_host_ports(
+            &[(5432, busy), (5672, free)],
+            &reserved,
+            &HashSet::default(),
+        )
+        .unwrap();
+        assert_ne!(allocated[0].1, busy);
+        assert_ne!(allocated[1].1, free);
+        assert_ne!(allocated[0].1, allocated[1].1);
+
+        // The container being replaced keeps its port.
+        let owned = HashSet::from_iter([busy]);
+        let allocated = allocate_host_ports(&[(5432, busy)], &HashSet::default(), &owned).unwrap();
+        assert_eq!(allocated, vec![(5432, busy)]);
+    }
+}